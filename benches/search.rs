@@ -0,0 +1,43 @@
+//! `search_files` over a generated tree of 100k files, walked on one
+//! thread and on several: `cargo bench --features bench --bench search`
+
+use std::fs;
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use openclaw_enforce::benching::search_tree;
+use tempfile::TempDir;
+
+const PACKAGES: usize = 1_000;
+const FILES_PER_PACKAGE: usize = 100;
+
+/// A monorepo of packages whose first module holds the needle
+fn tree() -> TempDir {
+    let dir = TempDir::new().expect("temporary directory");
+    for i in 0..PACKAGES {
+        let package = dir.path().join(format!("packages/pkg-{}/src", i));
+        fs::create_dir_all(&package).expect("package directory");
+        for j in 0..FILES_PER_PACKAGE {
+            let body = if j == 0 { "// NEEDLE\n" } else { "fn f() {}\n" };
+            fs::write(package.join(format!("m{}.rs", j)), body).expect("module");
+        }
+    }
+    dir
+}
+
+fn search(c: &mut Criterion) {
+    let dir = tree();
+    let expected = ((PACKAGES * FILES_PER_PACKAGE) as u64, PACKAGES as u64);
+    let mut group = c.benchmark_group("search_100k_files");
+    group.sample_size(10);
+    for threads in [1, 8] {
+        assert_eq!(search_tree(dir.path(), "NEEDLE", threads), expected);
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter(|| search_tree(black_box(dir.path()), "NEEDLE", threads))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
@@ -0,0 +1,115 @@
+//! Path validation, glob matching and policy loading, for small and large
+//! policies: `cargo bench --features bench`
+
+use std::hint::black_box;
+
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use glob::Pattern;
+use openclaw_enforce::benching::{oversized_paths, policy_toml, request_paths, Globs, Validator};
+
+/// Entries per list in the small and the large policy
+const SIZES: [usize; 2] = [10, 4_000];
+const REQUESTS: usize = 1_000;
+
+fn validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("can_read");
+    for entries in SIZES {
+        let validator = Validator::load(&policy_toml(entries));
+        let paths = request_paths(entries, REQUESTS);
+        group.bench_with_input(BenchmarkId::from_parameter(entries), &paths, |b, paths| {
+            b.iter(|| validator.count_readable(black_box(paths)))
+        });
+    }
+    group.finish();
+}
+
+/// Paths of a megabyte or 100k components, refused by the `[enforcement]`
+/// bounds before any lookup, so this stays close to `can_read` however
+/// large the policy
+fn oversized(c: &mut Criterion) {
+    let mut group = c.benchmark_group("can_read_oversized");
+    for entries in SIZES {
+        let validator = Validator::load(&policy_toml(entries));
+        let paths = oversized_paths(entries, 16);
+        assert_eq!(validator.count_readable(&paths), 0);
+        group.bench_with_input(BenchmarkId::from_parameter(entries), &paths, |b, paths| {
+            b.iter(|| validator.count_readable(black_box(paths)))
+        });
+    }
+    group.finish();
+}
+
+/// 10k allowed prefixes, through the compiled index and through the
+/// linear scan it replaced
+fn prefixes(c: &mut Criterion) {
+    let allowed: Vec<String> = (0..10_000).map(|i| format!("/srv/projects/team-{}/workspace", i)).collect();
+    let lookups: Vec<PathBuf> = (0..REQUESTS)
+        .map(|i| PathBuf::from(format!("/srv/projects/team-{}/workspace/src/main.rs", i * 7 + 3)))
+        .collect();
+    let linear = |paths: &[PathBuf]| {
+        paths
+            .iter()
+            .filter(|path| {
+                let path = path.to_string_lossy();
+                allowed.iter().any(|entry| {
+                    path.starts_with(entry.as_str()) || Pattern::new(entry).is_ok_and(|p| p.matches(&path))
+                })
+            })
+            .count()
+    };
+    let validator = Validator::with_lists(allowed.clone(), vec!["*.key".to_string()], 0);
+    assert_eq!(validator.count_readable(&lookups), linear(&lookups));
+
+    let mut group = c.benchmark_group("can_read_10k_prefixes");
+    group.bench_function("indexed", |b| b.iter(|| validator.count_readable(black_box(&lookups))));
+    group.bench_function("linear", |b| b.iter(|| linear(black_box(&lookups))));
+    group.finish();
+}
+
+/// The same 100 paths checked over and over against a policy heavy in
+/// globs, with and without the decision cache
+fn repeated_checks(c: &mut Criterion) {
+    let allowed: Vec<String> = (0..200).map(|i| format!("/srv/*/team-{}/**", i)).collect();
+    let denied: Vec<String> = (0..200).map(|i| format!("*/secret-{}/*", i)).collect();
+    let paths: Vec<PathBuf> = (0..REQUESTS)
+        .map(|i| PathBuf::from(format!("/srv/projects/team-{}/src/main.rs", (i % 100) * 3)))
+        .collect();
+
+    let mut group = c.benchmark_group("can_read_repeated");
+    for (name, cache_size) in [("uncached", 0), ("cached", 1024)] {
+        let validator = Validator::with_lists(allowed.clone(), denied.clone(), cache_size);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &paths, |b, paths| {
+            b.iter(|| validator.count_readable(black_box(paths)))
+        });
+    }
+    group.finish();
+}
+
+fn glob_matching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deny_globs");
+    for entries in SIZES {
+        let patterns: Vec<String> = (0..entries).map(|i| format!("*/secret-{}/*", i)).collect();
+        let globs = Globs::new(&patterns);
+        let paths = request_paths(entries, REQUESTS);
+        group.bench_with_input(BenchmarkId::from_parameter(entries), &paths, |b, paths| {
+            b.iter(|| globs.count_matches(black_box(paths)))
+        });
+    }
+    group.finish();
+}
+
+fn policy_loading(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load_policy");
+    for entries in SIZES {
+        let toml = policy_toml(entries);
+        group.bench_with_input(BenchmarkId::from_parameter(entries), &toml, |b, toml| {
+            b.iter(|| Validator::load(black_box(toml)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, validation, oversized, prefixes, repeated_checks, glob_matching, policy_loading);
+criterion_main!(benches);
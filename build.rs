@@ -1,9 +1,57 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure()
+    let mut config = tonic_build::configure()
         .build_server(true)
-        .build_client(false)
-        .compile_protos(&["proto/enforce.proto"], &["proto"])?;
+        // Clients call out to the embedder's ApprovalService, and back the
+        // `client` feature's EnforceClient
+        .build_client(true);
+    // The `reflection` feature serves the descriptors of the compiled protos
+    if std::env::var_os("CARGO_FEATURE_REFLECTION").is_some() {
+        let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+        config = config.file_descriptor_set_path(out_dir.join("enforce_descriptor.bin"));
+    }
+    config.compile_protos(&["proto/enforce.proto"], &["proto"])?;
     
     println!("cargo:rerun-if-changed=proto/enforce.proto");
+    emit_build_info()?;
+    Ok(())
+}
+
+/// What `GetStatus` reports the daemon was built from. Outside a git
+/// checkout, such as from a source tarball, the commit is "unknown" unless
+/// OPENCLAW_GIT_SHA names it.
+fn emit_build_info() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-env-changed=OPENCLAW_GIT_SHA");
+    let git_sha = std::env::var("OPENCLAW_GIT_SHA").ok().or_else(|| {
+        let output = std::process::Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+    });
+    println!("cargo:rustc-env=OPENCLAW_GIT_SHA={}", git_sha.unwrap_or_else(|| "unknown".to_string()));
+    // A commit moves the branch HEAD points at, not HEAD itself. Paths that
+    // do not exist would rerun this on every build, so only those that do
+    // are watched.
+    let head = std::path::Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(branch) = std::fs::read_to_string(head)?.trim().strip_prefix("ref: ") {
+            let branch = std::path::Path::new(".git").join(branch);
+            if branch.exists() {
+                println!("cargo:rerun-if-changed={}", branch.display());
+            }
+        }
+    }
+
+    println!("cargo:rustc-env=OPENCLAW_TARGET={}", std::env::var("TARGET")?);
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_ascii_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=OPENCLAW_FEATURES={}", features.join(","));
     Ok(())
 }
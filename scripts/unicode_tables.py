@@ -0,0 +1,86 @@
+#!/usr/bin/env python3
+"""Generates src/fs/unicode_tables.rs from Python's unicodedata.
+
+    python3 scripts/unicode_tables.py > src/fs/unicode_tables.rs
+
+Only the canonical data NFC and NFD need is emitted. Hangul syllables
+are composed and decomposed algorithmically, so they are left out.
+"""
+
+import sys
+import unicodedata
+
+HANGUL = range(0xAC00, 0xD7A4)
+
+
+def canonical(c):
+    """The single-level canonical decomposition of c, if it has one"""
+    d = unicodedata.decomposition(c)
+    if not d or d.startswith("<"):
+        return None
+    return [chr(int(x, 16)) for x in d.split()]
+
+
+def full(c):
+    parts = canonical(c)
+    if parts is None:
+        return [c]
+    return [p for part in parts for p in full(part)]
+
+
+def rust_char(c):
+    return "'\\u{%X}'" % ord(c)
+
+
+def rows(items, per_line):
+    for i in range(0, len(items), per_line):
+        yield "    " + " ".join(items[i:i + per_line])
+
+
+def main():
+    chars = [chr(cp) for cp in range(sys.maxunicode + 1)
+             if not 0xD800 <= cp <= 0xDFFF and cp not in HANGUL]
+
+    decompositions = []
+    compositions = []
+    for c in chars:
+        parts = canonical(c)
+        if parts is None:
+            continue
+        expanded = "".join(full(c))
+        decompositions.append("(%s, \"%s\")," % (
+            rust_char(c), "".join("\\u{%X}" % ord(p) for p in expanded)))
+        # Primary composites: pairs that NFC recombines into c
+        if len(parts) == 2 and unicodedata.normalize("NFC", c) == c:
+            compositions.append((parts[0], parts[1], c))
+
+    classes = []
+    start = None
+    for cp in range(sys.maxunicode + 2):
+        ccc = unicodedata.combining(chr(cp)) if cp <= sys.maxunicode else 0
+        if start is not None and (ccc != start[1] or cp != prev + 1):
+            classes.append("(%s, %s, %d)," % (rust_char(chr(start[0])), rust_char(chr(prev)), start[1]))
+            start = None
+        if ccc and start is None:
+            start = (cp, ccc)
+        prev = cp
+
+    compositions.sort(key=lambda t: (ord(t[0]), ord(t[1])))
+    pairs = ["((%s, %s), %s)," % (rust_char(a), rust_char(b), rust_char(c)) for a, b, c in compositions]
+
+    out = sys.stdout
+    out.write("// Generated by scripts/unicode_tables.py from Unicode %s; do not edit.\n\n"
+              % unicodedata.unidata_version)
+    out.write("/// Full canonical decompositions, by code point\n")
+    out.write("pub const DECOMPOSITIONS: &[(char, &str)] = &[\n")
+    out.write("\n".join(rows(decompositions, 3)) + "\n];\n\n")
+    out.write("/// Non-zero canonical combining classes, as inclusive ranges\n")
+    out.write("pub const COMBINING_CLASSES: &[(char, char, u8)] = &[\n")
+    out.write("\n".join(rows(classes, 3)) + "\n];\n\n")
+    out.write("/// Primary composites, by the pair they compose from\n")
+    out.write("pub const COMPOSITIONS: &[((char, char), char)] = &[\n")
+    out.write("\n".join(rows(pairs, 2)) + "\n];\n")
+
+
+if __name__ == "__main__":
+    main()
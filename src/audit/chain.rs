@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use super::logger::AuditEntry;
+use super::sha256;
+
+/// `prev_hash` of the first entry in a log
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// `event_type` of the entry retention puts at the head of a compacted log
+pub const TRUNCATED_EVENT: &str = "audit_truncated";
+/// Metadata on that entry: the hash the first kept entry chains onto
+pub const CONTINUES_FROM: &str = "continues_from";
+
+/// Where a log stops verifying
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ChainBreak {
+    #[error("line {line}: not an audit entry: {error}")]
+    Malformed { line: u64, error: String },
+    #[error("line {line}: prev_hash does not match the entry before it; entries were removed or reordered")]
+    Unlinked { line: u64 },
+    #[error("line {line}: entry_hash does not match the entry; it was altered")]
+    Altered { line: u64 },
+}
+
+/// The fields an entry hash covers, in a fixed order with sorted metadata
+#[derive(Serialize)]
+struct Hashed<'a> {
+    prev_hash: &'a str,
+    timestamp: i64,
+    event_type: &'a str,
+    session_id: &'a str,
+    request_id: &'a str,
+    operation: &'a str,
+    resource: &'a str,
+    allowed: bool,
+    reason: &'a str,
+    metadata: BTreeMap<&'a str, &'a str>,
+}
+
+/// SHA-256 over the entry, including its `prev_hash`
+pub fn entry_hash(entry: &AuditEntry) -> String {
+    let hashed = Hashed {
+        prev_hash: &entry.prev_hash,
+        timestamp: entry.timestamp,
+        event_type: &entry.event_type,
+        session_id: &entry.session_id,
+        request_id: &entry.request_id,
+        operation: &entry.operation,
+        resource: &entry.resource,
+        allowed: entry.allowed,
+        reason: &entry.reason,
+        metadata: entry.metadata.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(),
+    };
+    let json = serde_json::to_vec(&hashed).unwrap_or_default();
+    sha256::hex_digest(&json)
+}
+
+/// Chains `entry` onto the entry whose hash is `prev_hash`
+pub fn link(entry: &mut AuditEntry, prev_hash: &str) {
+    entry.prev_hash = prev_hash.to_string();
+    entry.entry_hash = entry_hash(entry);
+}
+
+/// Checks a JSON-lines audit log written with `[audit] file`, returning
+/// the number of entries. An error carrying a [`ChainBreak`] names the
+/// first entry that was altered, or that follows removed entries.
+///
+/// A log compacted by `[audit]` retention starts with a
+/// [`TRUNCATED_EVENT`] entry chained onto [`GENESIS_HASH`], and the entry
+/// after it onto the hash in its [`CONTINUES_FROM`] metadata.
+///
+/// This makes tampering detectable, not impossible: whoever can rewrite
+/// the file can rebuild the chain, and dropping entries from the end
+/// leaves a valid chain. Keep the log on write-once or remote storage
+/// when that matters.
+pub fn verify_chain(path: &Path) -> Result<u64> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    verify(BufReader::new(file))
+}
+
+fn verify(reader: impl BufRead) -> Result<u64> {
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut count = 0;
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i as u64 + 1;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry = serde_json::from_str(&line).map_err(|e| ChainBreak::Malformed {
+            line: line_no,
+            error: e.to_string(),
+        })?;
+        if entry.prev_hash != prev_hash {
+            return Err(ChainBreak::Unlinked { line: line_no }.into());
+        }
+        if entry_hash(&entry) != entry.entry_hash {
+            return Err(ChainBreak::Altered { line: line_no }.into());
+        }
+        prev_hash = match entry.metadata.get(CONTINUES_FROM) {
+            Some(continues_from) if count == 0 && entry.event_type == TRUNCATED_EVENT => continues_from.clone(),
+            _ => entry.entry_hash,
+        };
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(n: usize) -> Vec<String> {
+        let mut prev_hash = GENESIS_HASH.to_string();
+        (0..n)
+            .map(|i| {
+                let mut entry = AuditEntry::decision("read_file", &format!("/tmp/{}", i), true, "ok")
+                    .with_metadata("a", "1")
+                    .with_metadata("b", "2");
+                link(&mut entry, &prev_hash);
+                prev_hash = entry.entry_hash.clone();
+                serde_json::to_string(&entry).unwrap()
+            })
+            .collect()
+    }
+
+    fn check(lines: &[String]) -> std::result::Result<u64, ChainBreak> {
+        verify(lines.join("\n").as_bytes()).map_err(|e| e.downcast::<ChainBreak>().unwrap())
+    }
+
+    #[test]
+    fn test_intact_chain_verifies() {
+        assert_eq!(check(&log(3)), Ok(3));
+        assert_eq!(check(&[]), Ok(0));
+    }
+
+    #[test]
+    fn test_removed_and_altered_entries_break_the_chain() {
+        let mut removed = log(3);
+        removed.remove(1);
+        assert_eq!(check(&removed), Err(ChainBreak::Unlinked { line: 2 }));
+
+        let mut head_removed = log(3);
+        head_removed.remove(0);
+        assert_eq!(check(&head_removed), Err(ChainBreak::Unlinked { line: 1 }));
+
+        let mut altered = log(3);
+        altered[2] = altered[2].replace("/tmp/2", "/etc/shadow");
+        assert_eq!(check(&altered), Err(ChainBreak::Altered { line: 3 }));
+
+        // Only at the head of a log
+        let mut truncated = log(4);
+        let mut marker = AuditEntry::note(TRUNCATED_EVENT, "audit_retention", "audit.jsonl", "evicted")
+            .with_metadata(CONTINUES_FROM, serde_json::from_str::<AuditEntry>(&truncated[1]).unwrap().entry_hash);
+        link(&mut marker, GENESIS_HASH);
+        truncated.splice(0..2, [serde_json::to_string(&marker).unwrap()]);
+        assert_eq!(check(&truncated), Ok(3));
+        truncated.insert(2, truncated[0].clone());
+        assert_eq!(check(&truncated), Err(ChainBreak::Unlinked { line: 3 }));
+
+        let mut garbage = log(2);
+        garbage.push("not json".to_string());
+        assert!(matches!(check(&garbage), Err(ChainBreak::Malformed { line: 3, .. })));
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use super::logger::AuditEntry;
+
+/// Distinct denials tracked at once; the least recently seen is evicted
+pub const MAX_TRACKED_DENIALS: usize = 1024;
+
+/// Identifies "the same denial": one actor hitting one target with one operation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DenialKey {
+    operation: String,
+    resource: String,
+    session_id: String,
+}
+
+impl DenialKey {
+    fn of(entry: &AuditEntry) -> Self {
+        Self {
+            operation: entry.operation.clone(),
+            resource: entry.resource.clone(),
+            session_id: entry.session_id.clone(),
+        }
+    }
+}
+
+/// A run of identical denials; the first one was emitted
+#[derive(Debug)]
+struct Window {
+    first: AuditEntry,
+    last_seen: i64,
+    count: u64,
+}
+
+/// Folds repeats of the same denial within a window into one summary.
+///
+/// The first denial of a run is passed through unchanged. Later ones inside
+/// `window_secs` of it are only counted, and once the window has passed a
+/// summary entry carrying the exact count and the first and last timestamps
+/// is produced. Allowed decisions are never folded.
+pub struct DenialDeduper {
+    window_secs: i64,
+    capacity: usize,
+    windows: HashMap<DenialKey, Window>,
+}
+
+impl DenialDeduper {
+    pub fn new(window_secs: u64, capacity: usize) -> Self {
+        Self {
+            window_secs: window_secs as i64,
+            capacity,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Returns whether `entry` should be emitted, plus summaries of any
+    /// runs that were closed along the way to place before it
+    pub fn observe(&mut self, entry: &AuditEntry) -> (bool, Vec<AuditEntry>) {
+        let mut summaries = self.expire(entry.timestamp);
+        if entry.allowed {
+            return (true, summaries);
+        }
+
+        let key = DenialKey::of(entry);
+        if let Some(window) = self.windows.get_mut(&key) {
+            window.count += 1;
+            window.last_seen = entry.timestamp;
+            return (false, summaries);
+        }
+
+        if self.windows.len() >= self.capacity {
+            summaries.extend(self.evict_oldest());
+        }
+        self.windows.insert(
+            key,
+            Window {
+                first: entry.clone(),
+                last_seen: entry.timestamp,
+                count: 1,
+            },
+        );
+        (true, summaries)
+    }
+
+    /// Closes every run whose window has passed at `now`
+    pub fn expire(&mut self, now: i64) -> Vec<AuditEntry> {
+        let window_secs = self.window_secs;
+        let expired: Vec<DenialKey> = self
+            .windows
+            .iter()
+            .filter(|(_, w)| now >= w.first.timestamp + window_secs)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut summaries: Vec<AuditEntry> = expired
+            .into_iter()
+            .filter_map(|key| self.windows.remove(&key))
+            .filter_map(|w| summarize(w, window_secs))
+            .collect();
+        summaries.sort_by_key(|s| s.timestamp);
+        summaries
+    }
+
+    fn evict_oldest(&mut self) -> Option<AuditEntry> {
+        let key = self
+            .windows
+            .iter()
+            .min_by_key(|(_, w)| w.last_seen)
+            .map(|(key, _)| key.clone())?;
+        let window = self.windows.remove(&key)?;
+        summarize(window, self.window_secs)
+    }
+}
+
+/// A run that never repeated needs no summary
+fn summarize(window: Window, window_secs: i64) -> Option<AuditEntry> {
+    if window.count < 2 {
+        return None;
+    }
+
+    let first = window.first;
+    let reason = format!(
+        "denied {} times in last {}s: {}",
+        window.count, window_secs, first.reason
+    );
+    let mut summary = AuditEntry {
+        timestamp: window.last_seen,
+        reason,
+        ..first.clone()
+    }
+    .with_metadata("repeat_count", window.count.to_string())
+    .with_metadata("suppressed", (window.count - 1).to_string())
+    .with_metadata("first_seen", first.timestamp.to_string())
+    .with_metadata("last_seen", window.last_seen.to_string());
+    summary.event_type = "decision_summary".to_string();
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn denial(resource: &str, timestamp: i64) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            ..AuditEntry::decision("read_file", resource, false, "Path not in allowed read list")
+        }
+    }
+
+    #[test]
+    fn test_repeats_fold_into_one_summary() {
+        let mut deduper = DenialDeduper::new(60, 16);
+
+        assert_eq!(deduper.observe(&denial("/etc/shadow", 100)), (true, vec![]));
+        for t in 101..=111 {
+            assert_eq!(deduper.observe(&denial("/etc/shadow", t)), (false, vec![]));
+        }
+
+        assert!(deduper.expire(159).is_empty());
+        let summaries = deduper.expire(160);
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.event_type, "decision_summary");
+        assert!(summary.reason.starts_with("denied 12 times in last 60s"));
+        assert_eq!(summary.metadata["repeat_count"], "12");
+        assert_eq!(summary.metadata["first_seen"], "100");
+        assert_eq!(summary.metadata["last_seen"], "111");
+        assert_eq!(summary.timestamp, 111);
+    }
+
+    #[test]
+    fn test_new_window_after_expiry_is_emitted() {
+        let mut deduper = DenialDeduper::new(60, 16);
+        deduper.observe(&denial("/etc/shadow", 100));
+        deduper.observe(&denial("/etc/shadow", 120));
+
+        let (emit, summaries) = deduper.observe(&denial("/etc/shadow", 200));
+        assert!(emit);
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].metadata["repeat_count"], "2");
+    }
+
+    #[test]
+    fn test_distinct_keys_and_allowed_entries_pass_through() {
+        let mut deduper = DenialDeduper::new(60, 16);
+        assert!(deduper.observe(&denial("/a", 100)).0);
+        assert!(deduper.observe(&denial("/b", 100)).0);
+        assert!(deduper.observe(&denial("/a", 100).with_session("other")).0);
+
+        let allowed = AuditEntry::decision("read_file", "/a", true, "Access granted");
+        assert!(deduper.observe(&allowed).0);
+        assert!(deduper.observe(&allowed).0);
+    }
+
+    #[test]
+    fn test_eviction_keeps_counts() {
+        let mut deduper = DenialDeduper::new(60, 1);
+        deduper.observe(&denial("/a", 100));
+        deduper.observe(&denial("/a", 101));
+
+        let (emit, summaries) = deduper.observe(&denial("/b", 102));
+        assert!(emit);
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].resource, "/a");
+        assert_eq!(summaries[0].metadata["repeat_count"], "2");
+    }
+}
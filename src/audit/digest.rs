@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+/// Width of the buckets denials are counted in by operation, so a window
+/// is exact to within this many seconds
+const BUCKET_SECS: i64 = 10;
+/// An hour of buckets
+const BUCKETS: i64 = 360;
+
+/// Denied targets are tallied in ten-minute buckets, the last six of which
+/// make up the hour the top targets are taken from
+const TARGET_BUCKET_SECS: i64 = 600;
+const TARGET_BUCKETS: i64 = 6;
+/// Targets tallied per bucket; past this the least denied gives way
+const TARGETS_PER_BUCKET: usize = 32;
+/// Longer targets are cut to this many bytes
+const MAX_TARGET_BYTES: usize = 256;
+
+/// Operations counted by name; denials of any others are counted together
+const MAX_OPERATIONS: usize = 32;
+const OTHER_OPERATIONS: &str = "other";
+
+/// Targets listed by [`DenialDigest::snapshot`]
+pub const TOP_TARGETS: usize = 5;
+
+/// Denials over the last hour, for `get_status`: counts by operation over
+/// sliding windows, the most denied targets and when the last denial was.
+///
+/// Memory is fixed by the bucket and tally sizes however many denials
+/// there are; a denial costs a bucket lookup and a scan of at most
+/// [`TARGETS_PER_BUCKET`] tallies. The most denied targets are kept with
+/// the space-saving algorithm, so with more distinct targets than fit, a
+/// count may be over by what the target it replaced had.
+#[derive(Debug)]
+pub struct DenialDigest {
+    operations: Vec<String>,
+    /// Slot `n % BUCKETS` holds bucket `n`: denials by operation index
+    buckets: Vec<(i64, Vec<u32>)>,
+    targets: Vec<(i64, Vec<(String, u64)>)>,
+    last_denied_at: i64,
+}
+
+impl Default for DenialDigest {
+    fn default() -> Self {
+        Self {
+            operations: Vec::new(),
+            buckets: vec![(i64::MIN, Vec::new()); BUCKETS as usize],
+            targets: vec![(i64::MIN, Vec::new()); TARGET_BUCKETS as usize],
+            last_denied_at: 0,
+        }
+    }
+}
+
+/// Denials of one operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationDenials {
+    pub operation: String,
+    pub last_1m: u64,
+    pub last_5m: u64,
+    pub last_60m: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DigestSnapshot {
+    /// Operations denied in the last hour, by name
+    pub operations: Vec<OperationDenials>,
+    /// `(target, denials)`, most denied first
+    pub top_targets: Vec<(String, u64)>,
+    /// Unix seconds; 0 before the first denial
+    pub last_denied_at: i64,
+}
+
+impl DenialDigest {
+    /// Counts a denial of `operation` on `target` at `at`, in Unix seconds
+    pub fn record(&mut self, operation: &str, target: &str, at: i64) {
+        self.last_denied_at = self.last_denied_at.max(at);
+
+        let index = self.operation_index(operation);
+        let bucket = at.div_euclid(BUCKET_SECS);
+        // Left out when older than what its slot now holds
+        if let Some(counts) = current(&mut self.buckets, bucket, BUCKETS) {
+            if counts.len() <= index {
+                counts.resize(index + 1, 0);
+            }
+            counts[index] += 1;
+        }
+
+        let Some(tallies) = current(&mut self.targets, at.div_euclid(TARGET_BUCKET_SECS), TARGET_BUCKETS) else {
+            return;
+        };
+        let target = truncate(target);
+        if let Some((_, count)) = tallies.iter_mut().find(|(t, _)| t == target) {
+            *count += 1;
+        } else if tallies.len() < TARGETS_PER_BUCKET {
+            tallies.push((target.to_string(), 1));
+        } else if let Some(least) = tallies.iter_mut().min_by_key(|(_, count)| *count) {
+            *least = (target.to_string(), least.1 + 1);
+        }
+    }
+
+    fn operation_index(&mut self, operation: &str) -> usize {
+        if let Some(index) = self.operations.iter().position(|o| o == operation) {
+            return index;
+        }
+        let name = if self.operations.len() < MAX_OPERATIONS - 1 { operation } else { OTHER_OPERATIONS };
+        if let Some(index) = self.operations.iter().position(|o| o == name) {
+            return index;
+        }
+        self.operations.push(name.to_string());
+        self.operations.len() - 1
+    }
+
+    /// The digest as of `now`, in Unix seconds
+    pub fn snapshot(&self, now: i64) -> DigestSnapshot {
+        let bucket = now.div_euclid(BUCKET_SECS);
+        let mut operations: Vec<OperationDenials> = self
+            .operations
+            .iter()
+            .map(|operation| OperationDenials {
+                operation: operation.clone(),
+                last_1m: 0,
+                last_5m: 0,
+                last_60m: 0,
+            })
+            .collect();
+        for (start, counts) in &self.buckets {
+            let age = bucket.saturating_sub(*start);
+            if !(0..BUCKETS).contains(&age) {
+                continue;
+            }
+            for (denials, count) in operations.iter_mut().zip(counts) {
+                let count = u64::from(*count);
+                denials.last_60m += count;
+                if age < 30 {
+                    denials.last_5m += count;
+                }
+                if age < 6 {
+                    denials.last_1m += count;
+                }
+            }
+        }
+        operations.retain(|denials| denials.last_60m > 0);
+        operations.sort_by(|a, b| a.operation.cmp(&b.operation));
+
+        let bucket = now.div_euclid(TARGET_BUCKET_SECS);
+        let mut targets: HashMap<&str, u64> = HashMap::new();
+        for (start, tallies) in &self.targets {
+            if (0..TARGET_BUCKETS).contains(&bucket.saturating_sub(*start)) {
+                for (target, count) in tallies {
+                    *targets.entry(target).or_default() += count;
+                }
+            }
+        }
+        let mut top_targets: Vec<(String, u64)> =
+            targets.into_iter().map(|(target, count)| (target.to_string(), count)).collect();
+        top_targets.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_targets.truncate(TOP_TARGETS);
+
+        DigestSnapshot {
+            operations,
+            top_targets,
+            last_denied_at: self.last_denied_at,
+        }
+    }
+}
+
+/// The contents of the slot for bucket `n`, emptied first if it held an
+/// older bucket; None if `n` is older than the one it holds
+fn current<T: Default>(slots: &mut [(i64, T)], n: i64, len: i64) -> Option<&mut T> {
+    let slot = &mut slots[n.rem_euclid(len) as usize];
+    if slot.0 > n {
+        return None;
+    }
+    if slot.0 < n {
+        *slot = (n, T::default());
+    }
+    Some(&mut slot.1)
+}
+
+fn truncate(target: &str) -> &str {
+    if target.len() <= MAX_TARGET_BYTES {
+        return target;
+    }
+    let mut end = MAX_TARGET_BYTES;
+    while !target.is_char_boundary(end) {
+        end -= 1;
+    }
+    &target[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn counts(snapshot: &DigestSnapshot, operation: &str) -> (u64, u64, u64) {
+        let denials = snapshot.operations.iter().find(|d| d.operation == operation).unwrap();
+        (denials.last_1m, denials.last_5m, denials.last_60m)
+    }
+
+    #[test]
+    fn test_windows_slide_by_operation() {
+        let mut digest = DenialDigest::default();
+        assert_eq!(digest.snapshot(NOW), DigestSnapshot::default());
+
+        digest.record("read_file", "/etc/shadow", NOW - 50 * 60);
+        digest.record("read_file", "/etc/shadow", NOW - 4 * 60);
+        digest.record("read_file", "/etc/passwd", NOW - 10);
+        digest.record("execute_command", "rm", NOW);
+        let snapshot = digest.snapshot(NOW);
+        assert_eq!(counts(&snapshot, "read_file"), (1, 2, 3));
+        assert_eq!(counts(&snapshot, "execute_command"), (1, 1, 1));
+        assert_eq!(snapshot.last_denied_at, NOW);
+        assert_eq!(snapshot.top_targets[0], ("/etc/shadow".to_string(), 2));
+        assert_eq!(snapshot.top_targets.len(), 3);
+
+        // An hour on, everything has left the windows but the last denial
+        let later = digest.snapshot(NOW + 3600 + TARGET_BUCKET_SECS);
+        assert!(later.operations.is_empty() && later.top_targets.is_empty());
+        assert_eq!(later.last_denied_at, NOW);
+        // And the slots are reused for new denials
+        digest.record("read_file", "/etc/shadow", NOW + 3600);
+        assert_eq!(counts(&digest.snapshot(NOW + 3600), "read_file"), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_memory_is_bounded() {
+        let mut digest = DenialDigest::default();
+        for i in 0..10_000 {
+            digest.record(&format!("op{}", i % 100), &format!("/srv/{}{}", i, "x".repeat(1000)), NOW);
+        }
+        for _ in 0..500 {
+            digest.record("op1", "/srv/hot", NOW);
+        }
+        let snapshot = digest.snapshot(NOW);
+        assert_eq!(digest.operations.len(), MAX_OPERATIONS);
+        assert_eq!(snapshot.operations.iter().map(|d| d.last_60m).sum::<u64>(), 10_500);
+        assert!(counts(&snapshot, OTHER_OPERATIONS).2 > 0);
+        assert!(digest.targets.iter().all(|(_, tallies)| tallies.len() <= TARGETS_PER_BUCKET));
+        assert!(digest.targets.iter().flat_map(|(_, t)| t).all(|(target, _)| target.len() <= MAX_TARGET_BYTES));
+        // The hot target comes out on top, however many others there were
+        assert_eq!(snapshot.top_targets[0].0, "/srv/hot");
+        assert!(snapshot.top_targets[0].1 >= 500);
+        assert_eq!(snapshot.top_targets.len(), TOP_TARGETS);
+    }
+}
@@ -0,0 +1,635 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use super::chain::{self, CONTINUES_FROM, GENESIS_HASH, TRUNCATED_EVENT};
+use super::dedupe::{DenialDeduper, MAX_TRACKED_DENIALS};
+use super::digest::{DenialDigest, DigestSnapshot};
+use super::sample::AllowedSampler;
+use crate::daemon::health::{Condition, HealthRegistry, Subsystem};
+
+/// Entries kept in memory for `get_audit_logs` replay
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Buffered entries per live subscriber before it starts lagging
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+/// A single audit record, mirroring the proto `AuditLogEntry`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: i64,
+    pub event_type: String,
+    pub session_id: String,
+    /// Correlation ID of the RPC that produced the entry
+    #[serde(default)]
+    pub request_id: String,
+    pub operation: String,
+    pub resource: String,
+    pub allowed: bool,
+    pub reason: String,
+    pub metadata: HashMap<String, String>,
+    /// `entry_hash` of the entry recorded before this one
+    #[serde(default)]
+    pub prev_hash: String,
+    /// SHA-256 over this entry and `prev_hash`; see [`chain::verify_chain`]
+    #[serde(default)]
+    pub entry_hash: String,
+}
+
+impl AuditEntry {
+    /// An enforcement decision for `operation` on `resource`
+    pub fn decision(operation: &str, resource: &str, allowed: bool, reason: &str) -> Self {
+        Self {
+            timestamp: unix_now(),
+            event_type: "decision".to_string(),
+            session_id: String::new(),
+            request_id: String::new(),
+            operation: operation.to_string(),
+            resource: resource.to_string(),
+            allowed,
+            reason: reason.to_string(),
+            metadata: HashMap::new(),
+            prev_hash: String::new(),
+            entry_hash: String::new(),
+        }
+    }
+
+    /// An informational event that is not tied to a caller's request
+    pub fn note(event_type: &str, operation: &str, resource: &str, reason: &str) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            ..Self::decision(operation, resource, true, reason)
+        }
+    }
+
+    pub fn with_session(mut self, session_id: &str) -> Self {
+        self.session_id = session_id.to_string();
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.request_id = request_id.to_string();
+        self
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+/// Selects entries for replay and live streaming
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub since_timestamp: i64,
+    pub session_id: Option<String>,
+    pub event_types: Vec<String>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        if entry.timestamp < self.since_timestamp {
+            return false;
+        }
+        if let Some(session_id) = &self.session_id {
+            if entry.session_id != *session_id {
+                return false;
+            }
+        }
+        self.event_types.is_empty() || self.event_types.contains(&entry.event_type)
+    }
+}
+
+/// In-memory audit trail with live subscribers.
+///
+/// Recording never blocks on subscribers: each one gets a bounded buffer and
+/// a subscriber that falls behind loses the oldest entries instead of
+/// delaying the enforcement decision.
+pub struct AuditLogger {
+    records: Mutex<Records>,
+    capacity: usize,
+    /// Entries the file may hold; `None` lets it grow
+    file_capacity: Option<u64>,
+    max_age_secs: Option<i64>,
+    sender: broadcast::Sender<AuditEntry>,
+    deduper: Option<Mutex<DenialDeduper>>,
+    sampler: Option<(f64, Mutex<AllowedSampler>)>,
+    /// Told when appends to the file start or stop failing
+    health: Option<Arc<HealthRegistry>>,
+    /// Every denial, whether or not it is folded into a summary
+    denials: Mutex<DenialDigest>,
+}
+
+/// Entries are hash-chained in the order they are pushed, so the ring, the
+/// file and the chain head change together under one lock
+struct Records {
+    entries: VecDeque<AuditEntry>,
+    last_hash: String,
+    file: Option<LogFile>,
+}
+
+struct LogFile {
+    file: File,
+    path: PathBuf,
+    /// Not counting a [`TRUNCATED_EVENT`] entry at the head
+    entries: u64,
+    /// Timestamp of the first entry after that
+    oldest: Option<i64>,
+    /// The last append failed
+    failing: bool,
+}
+
+impl AuditLogger {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BUFFER);
+        Self {
+            records: Mutex::new(Records {
+                entries: VecDeque::new(),
+                last_hash: GENESIS_HASH.to_string(),
+                file: None,
+            }),
+            capacity,
+            file_capacity: None,
+            max_age_secs: None,
+            sender,
+            deduper: None,
+            sampler: None,
+            health: None,
+            denials: Mutex::default(),
+        }
+    }
+
+    /// Folds repeated identical denials within `window_secs` into a summary
+    /// entry. Zero disables deduplication.
+    pub fn with_dedupe_window(mut self, window_secs: u64) -> Self {
+        self.deduper = (window_secs > 0)
+            .then(|| Mutex::new(DenialDeduper::new(window_secs, MAX_TRACKED_DENIALS)));
+        self
+    }
+
+    /// Records only `rate` (0.0 to 1.0) of allowed decisions; denials are
+    /// always recorded. 1.0 records everything.
+    pub fn with_sample_rate(mut self, rate: f64) -> Self {
+        self.sampler = (rate < 1.0).then(|| (rate, Mutex::new(AllowedSampler::new(rate))));
+        self
+    }
+
+    /// Keeps at most `max_entries` entries, in memory and in the file, and
+    /// none older than `max_age_secs`, evicting the oldest first. Zero
+    /// lifts either limit, except that memory still holds `capacity`.
+    pub fn with_retention(mut self, max_entries: usize, max_age_secs: u64) -> Self {
+        if max_entries > 0 {
+            self.capacity = max_entries;
+            self.file_capacity = Some(max_entries as u64);
+        }
+        self.max_age_secs = (max_age_secs > 0).then_some(max_age_secs as i64);
+        self
+    }
+
+    /// Also appends every entry to `path` as a JSON line. An existing log
+    /// is continued, so its chain stays verifiable across restarts.
+    pub fn with_file(self, path: &Path) -> io::Result<Self> {
+        let existing = match File::open(path) {
+            Ok(existing) => summarize(existing)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Summary::default(),
+            Err(e) => return Err(e),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        {
+            let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(last_hash) = existing.last_hash {
+                records.last_hash = last_hash;
+            }
+            records.file = Some(LogFile {
+                file,
+                path: path.to_path_buf(),
+                entries: existing.entries,
+                oldest: existing.oldest,
+                failing: false,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Reports [`Subsystem::AuditSink`] failed while appends to the file
+    /// fail, and ok again once one succeeds
+    pub fn with_health(mut self, health: Arc<HealthRegistry>) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Returns false when the entry repeats a recent denial and was only
+    /// counted towards its summary, or was an allowed decision left out by
+    /// sampling; callers use this to skip their own log line. The decision
+    /// itself is never affected.
+    pub fn record(&self, mut entry: AuditEntry) -> bool {
+        if !entry.allowed && entry.event_type == "decision" {
+            let mut denials = self.denials.lock().unwrap_or_else(|e| e.into_inner());
+            denials.record(&entry.operation, &entry.resource, entry.timestamp);
+        }
+        if let Some((rate, sampler)) = &self.sampler {
+            if !sampler.lock().unwrap_or_else(|e| e.into_inner()).keep(&entry) {
+                return false;
+            }
+            if entry.allowed && entry.event_type == "decision" {
+                entry = entry.with_metadata("sample_rate", rate.to_string());
+            }
+        }
+        let Some(deduper) = &self.deduper else {
+            self.push(entry);
+            return true;
+        };
+
+        let mut deduper = deduper.lock().unwrap_or_else(|e| e.into_inner());
+        let (emit, summaries) = deduper.observe(&entry);
+        self.push_summaries(summaries);
+        if emit {
+            self.push(entry);
+        }
+        emit
+    }
+
+    /// Denials in the last hour, as of now
+    pub fn denial_digest(&self) -> DigestSnapshot {
+        self.denials.lock().unwrap_or_else(|e| e.into_inner()).snapshot(unix_now())
+    }
+
+    /// Emits summaries for runs whose window has passed without a new
+    /// occurrence. Called periodically so quiet runs are not held forever.
+    pub fn flush_expired(&self) {
+        if let Some(deduper) = &self.deduper {
+            let mut deduper = deduper.lock().unwrap_or_else(|e| e.into_inner());
+            self.push_summaries(deduper.expire(unix_now()));
+        }
+    }
+
+    fn push_summaries(&self, summaries: Vec<AuditEntry>) {
+        for summary in summaries {
+            warn!("❌ {} {}: {}", summary.operation, summary.resource, summary.reason);
+            self.push(summary);
+        }
+    }
+
+    fn push(&self, mut entry: AuditEntry) {
+        {
+            let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+            chain::link(&mut entry, &records.last_hash);
+            records.last_hash = entry.entry_hash.clone();
+            if let Some(log) = &mut records.file {
+                let mut line = serde_json::to_vec(&entry).unwrap_or_default();
+                line.push(b'\n');
+                let written = log.file.write_all(&line);
+                match &written {
+                    Ok(()) => {
+                        log.entries += 1;
+                        log.oldest.get_or_insert(entry.timestamp);
+                    }
+                    Err(e) => warn!("Failed to append to the audit log file: {}", e),
+                }
+                if log.failing != written.is_err() {
+                    log.failing = written.is_err();
+                    if let Some(health) = &self.health {
+                        let written = written.map_err(|e| format!("{}: {}", log.path.display(), e));
+                        health.report_result(Subsystem::AuditSink, &written, Condition::Failed);
+                    }
+                }
+            }
+            if records.entries.len() == self.capacity {
+                records.entries.pop_front();
+            }
+            records.entries.push_back(entry.clone());
+            self.evict_expired(&mut records.entries, entry.timestamp);
+        }
+
+        // An error only means nobody is subscribed
+        let _ = self.sender.send(entry);
+    }
+
+    fn evict_expired(&self, entries: &mut VecDeque<AuditEntry>, now: i64) {
+        if let Some(max_age) = self.max_age_secs {
+            while entries.front().is_some_and(|e| e.timestamp < now - max_age) {
+                entries.pop_front();
+            }
+        }
+    }
+
+    /// Applies `with_retention` limits that recording alone does not: age
+    /// in memory, and count and age in the file. Called periodically.
+    ///
+    /// The file is rewritten without its oldest entries behind a
+    /// [`TRUNCATED_EVENT`] entry, so it still verifies. Only the final
+    /// rename holds the lock `record` takes; returns the entries evicted
+    /// from the file.
+    pub fn enforce_retention(&self) -> io::Result<u64> {
+        let now = unix_now();
+        let snapshot = {
+            let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+            self.evict_expired(&mut records.entries, now);
+            let Some(log) = &records.file else {
+                return Ok(0);
+            };
+            let over_count = self.file_capacity.is_some_and(|capacity| log.entries > capacity);
+            let over_age = self
+                .max_age_secs
+                .is_some_and(|max_age| log.oldest.is_some_and(|oldest| oldest < now - max_age));
+            if !(over_count || over_age) {
+                return Ok(0);
+            }
+            (log.path.clone(), log.file.metadata()?.len())
+        };
+        let (path, len) = snapshot;
+
+        // Everything up to `len` is whole lines `record` will not touch again
+        let mut text = String::new();
+        File::open(&path)?.take(len).read_to_string(&mut text)?;
+        let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+        let mut kept: Vec<AuditEntry> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line))
+            .collect::<Result<_, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if kept.first().is_some_and(|first| first.event_type == TRUNCATED_EVENT) {
+            kept.remove(0);
+        }
+        let mut evicted = kept.len().saturating_sub(self.file_capacity.unwrap_or(u64::MAX) as usize);
+        if let Some(max_age) = self.max_age_secs {
+            evicted = evicted.max(kept.iter().take_while(|e| e.timestamp < now - max_age).count());
+        }
+        if evicted == 0 {
+            return Ok(0);
+        }
+        let continues_from = kept[evicted - 1].entry_hash.clone();
+        let kept = &kept[evicted..];
+
+        let mut marker = AuditEntry::note(
+            TRUNCATED_EVENT,
+            "audit_retention",
+            &path.display().to_string(),
+            &format!("{} older entries evicted by retention", evicted),
+        )
+        .with_metadata(CONTINUES_FROM, continues_from)
+        .with_metadata("evicted", evicted.to_string());
+        chain::link(&mut marker, GENESIS_HASH);
+        let mut tmp_name = path.clone().into_os_string();
+        tmp_name.push(".compact");
+        let tmp = PathBuf::from(tmp_name);
+        let mut out = File::create(&tmp)?;
+        for entry in std::iter::once(&marker).chain(kept) {
+            let mut line = serde_json::to_vec(entry).unwrap_or_default();
+            line.push(b'\n');
+            out.write_all(&line)?;
+        }
+
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(log) = &mut records.file else {
+            return Ok(0);
+        };
+        // Entries recorded since the snapshot
+        let mut recent = Vec::new();
+        let mut current = File::open(&path)?;
+        current.seek(SeekFrom::Start(len))?;
+        current.read_to_end(&mut recent)?;
+        out.write_all(&recent)?;
+        out.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        log.file = OpenOptions::new().append(true).open(&path)?;
+        log.entries -= evicted as u64;
+        log.oldest = kept.first().map(|e| e.timestamp).or((!recent.is_empty()).then_some(now));
+        Ok(evicted as u64)
+    }
+
+    pub fn query(&self, filter: &AuditFilter) -> Vec<AuditEntry> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.entries.iter().filter(|e| filter.matches(e)).cloned().collect()
+    }
+
+    /// Receives every entry recorded from now on. Dropping the receiver
+    /// detaches the subscriber.
+    pub fn subscribe(&self) -> broadcast::Receiver<AuditEntry> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for AuditLogger {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+/// What recording and retention need to know about an existing log
+#[derive(Default)]
+struct Summary {
+    /// The hash the log ends on, to chain new entries onto
+    last_hash: Option<String>,
+    /// As in [`LogFile`]
+    entries: u64,
+    oldest: Option<i64>,
+}
+
+fn summarize(file: File) -> io::Result<Summary> {
+    let parse = |line: &str, which: &str| {
+        serde_json::from_str::<AuditEntry>(line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{} line is not an audit entry: {}", which, e))
+        })
+    };
+    let mut summary = Summary::default();
+    let mut last = None;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if summary.oldest.is_none() {
+            let entry = parse(&line, "first")?;
+            // The marker at the head of a compacted log is not one of its entries
+            if !(last.is_none() && entry.event_type == TRUNCATED_EVENT) {
+                summary.oldest = Some(entry.timestamp);
+            }
+        }
+        if summary.oldest.is_some() {
+            summary.entries += 1;
+        }
+        last = Some(line);
+    }
+    if let Some(line) = last {
+        summary.last_hash = Some(parse(&line, "last")?.entry_hash);
+    }
+    Ok(summary)
+}
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_filters_entries() {
+        let logger = AuditLogger::new(10);
+        logger.record(AuditEntry::decision("read_file", "/tmp/a", true, "ok").with_session("s1"));
+        logger.record(AuditEntry::decision("read_file", "/etc/shadow", false, "denied"));
+
+        assert_eq!(logger.query(&AuditFilter::default()).len(), 2);
+
+        let filter = AuditFilter {
+            session_id: Some("s1".to_string()),
+            ..Default::default()
+        };
+        let entries = logger.query(&filter);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].resource, "/tmp/a");
+
+        let filter = AuditFilter {
+            event_types: vec!["policy_load".to_string()],
+            ..Default::default()
+        };
+        assert!(logger.query(&filter).is_empty());
+    }
+
+    #[test]
+    fn test_capacity_drops_oldest() {
+        let logger = AuditLogger::new(2);
+        for path in ["/a", "/b", "/c"] {
+            logger.record(AuditEntry::decision("read_file", path, true, "ok"));
+        }
+        let resources: Vec<_> = logger
+            .query(&AuditFilter::default())
+            .into_iter()
+            .map(|e| e.resource)
+            .collect();
+        assert_eq!(resources, vec!["/b", "/c"]);
+    }
+
+    #[test]
+    fn test_retention_evicts_in_fifo_order() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let logger = AuditLogger::new(100).with_retention(3, 0).with_file(&path).unwrap();
+        for i in 0..5 {
+            logger.record(AuditEntry::decision("read_file", &format!("/{}", i), true, "ok"));
+        }
+        let resources = |entries: Vec<AuditEntry>| -> Vec<String> {
+            entries.into_iter().map(|e| e.resource).collect()
+        };
+        assert_eq!(resources(logger.query(&AuditFilter::default())), ["/2", "/3", "/4"]);
+
+        // The file is trimmed by the sweep, and still verifies
+        assert_eq!(logger.enforce_retention().unwrap(), 2);
+        logger.record(AuditEntry::decision("read_file", "/5", true, "ok"));
+        let text = std::fs::read_to_string(&path).unwrap();
+        let file: Vec<AuditEntry> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(file[0].event_type, TRUNCATED_EVENT);
+        assert_eq!(resources(file[1..].to_vec()), ["/2", "/3", "/4", "/5"]);
+        assert_eq!(chain::verify_chain(&path).unwrap(), 5);
+
+        assert_eq!(logger.enforce_retention().unwrap(), 1);
+        assert_eq!(chain::verify_chain(&path).unwrap(), 4);
+        assert_eq!(logger.enforce_retention().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_retention_evicts_by_age() {
+        let logger = AuditLogger::new(100).with_retention(0, 60);
+        let mut old = AuditEntry::decision("read_file", "/old", true, "ok");
+        old.timestamp -= 120;
+        logger.record(old);
+        assert_eq!(logger.query(&AuditFilter::default()).len(), 1);
+
+        logger.record(AuditEntry::decision("read_file", "/new", true, "ok"));
+        let entries = logger.query(&AuditFilter::default());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].resource, "/new");
+    }
+
+    #[test]
+    fn test_subscribers_receive_and_detach() {
+        let logger = AuditLogger::new(10);
+        let mut receiver = logger.subscribe();
+
+        logger.record(AuditEntry::decision("read_file", "/tmp/a", true, "ok"));
+        assert_eq!(receiver.try_recv().unwrap().resource, "/tmp/a");
+
+        drop(receiver);
+        // Recording with no subscribers must not fail or block
+        logger.record(AuditEntry::decision("read_file", "/tmp/b", true, "ok"));
+    }
+
+    #[test]
+    fn test_repeated_denials_are_summarized() {
+        let logger = AuditLogger::new(100).with_dedupe_window(60);
+        let denial = || AuditEntry::decision("read_file", "/etc/shadow", false, "denied");
+
+        assert!(logger.record(denial()));
+        for _ in 0..5 {
+            assert!(!logger.record(denial()));
+        }
+        assert!(logger.record(AuditEntry::decision("read_file", "/tmp/a", true, "ok")));
+        assert_eq!(logger.query(&AuditFilter::default()).len(), 2);
+
+        // Nothing has expired yet
+        logger.flush_expired();
+        assert_eq!(logger.query(&AuditFilter::default()).len(), 2);
+    }
+
+    #[test]
+    fn test_sampling_drops_allowed_decisions_only() {
+        let logger = AuditLogger::new(100).with_sample_rate(0.5);
+        let kept: Vec<bool> = (0..4)
+            .map(|i| logger.record(AuditEntry::decision("read_file", &format!("/tmp/{}", i), true, "ok")))
+            .collect();
+        assert_eq!(kept, [true, false, true, false]);
+        for _ in 0..3 {
+            assert!(logger.record(AuditEntry::decision("read_file", "/etc/shadow", false, "denied")));
+        }
+
+        let entries = logger.query(&AuditFilter::default());
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].metadata["sample_rate"], "0.5");
+        assert!(!entries[4].metadata.contains_key("sample_rate"));
+    }
+
+    #[test]
+    fn test_slow_subscriber_does_not_block_recording() {
+        let logger = AuditLogger::new(10);
+        let mut receiver = logger.subscribe();
+
+        for i in 0..(SUBSCRIBER_BUFFER + 10) {
+            logger.record(AuditEntry::decision("read_file", &format!("/tmp/{}", i), true, "ok"));
+        }
+
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_log_stays_chained_across_restarts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        for run in 0..2 {
+            let logger = AuditLogger::new(10).with_file(&path).unwrap();
+            logger.record(AuditEntry::decision("read_file", &format!("/tmp/{}", run), true, "ok"));
+            logger.record(AuditEntry::decision("write_file", "/tmp/out", false, "denied"));
+        }
+        assert_eq!(chain::verify_chain(&path).unwrap(), 4);
+
+        let logger = AuditLogger::new(10);
+        logger.record(AuditEntry::decision("read_file", "/tmp/a", true, "ok"));
+        logger.record(AuditEntry::decision("read_file", "/tmp/b", true, "ok"));
+        let entries = logger.query(&AuditFilter::default());
+        assert_eq!(entries[0].prev_hash, GENESIS_HASH);
+        assert_eq!(entries[1].prev_hash, entries[0].entry_hash);
+    }
+}
@@ -1 +1,7 @@
 // Security audit logging
+pub mod chain;
+pub mod dedupe;
+pub mod digest;
+pub mod logger;
+pub mod sample;
+pub mod sha256;
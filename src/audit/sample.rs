@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use super::logger::AuditEntry;
+
+/// Credit is kept in millionths, so that adding up the rate is exact
+const ONE: u64 = 1_000_000;
+
+/// Keeps a fraction of allowed decisions, per operation.
+///
+/// Each operation carries credit that grows by the rate with every allowed
+/// decision and is spent on each one kept. Which decisions are kept
+/// depends only on their order, not on chance, so replaying the same
+/// calls records the same entries, and the first allowed decision of
+/// each operation is always recorded. Denials and every other event are
+/// never sampled.
+pub struct AllowedSampler {
+    rate: u64,
+    credit: HashMap<String, u64>,
+}
+
+impl AllowedSampler {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate: (rate.clamp(0.0, 1.0) * ONE as f64).round() as u64,
+            credit: HashMap::new(),
+        }
+    }
+
+    pub fn keep(&mut self, entry: &AuditEntry) -> bool {
+        if !entry.allowed || entry.event_type != "decision" {
+            return true;
+        }
+        let credit = self.credit.entry(entry.operation.clone()).or_insert(ONE);
+        let keep = *credit >= ONE;
+        if keep {
+            *credit -= ONE;
+        }
+        *credit += self.rate;
+        keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(operation: &str) -> AuditEntry {
+        AuditEntry::decision(operation, "/srv/a", true, "Access granted")
+    }
+
+    #[test]
+    fn test_keeps_the_rate_of_allowed_decisions_per_operation() {
+        let mut sampler = AllowedSampler::new(0.25);
+        let kept: Vec<bool> = (0..8).map(|_| sampler.keep(&allowed("read_file"))).collect();
+        assert_eq!(kept, [true, false, false, false, true, false, false, false]);
+        // Each operation has its own share
+        assert!(sampler.keep(&allowed("write_file")));
+
+        let mut tenth = AllowedSampler::new(0.1);
+        let kept_at: Vec<usize> = (0..30).filter(|_| tenth.keep(&allowed("read_file"))).collect();
+        assert_eq!(kept_at, [0, 10, 20]);
+
+        let mut again = AllowedSampler::new(0.25);
+        let replayed: Vec<bool> = (0..8).map(|_| again.keep(&allowed("read_file"))).collect();
+        assert_eq!(replayed, kept);
+    }
+
+    #[test]
+    fn test_denials_and_events_are_always_kept() {
+        let mut sampler = AllowedSampler::new(0.0);
+        assert!(sampler.keep(&allowed("read_file")));
+        assert!(!sampler.keep(&allowed("read_file")));
+        for _ in 0..100 {
+            assert!(sampler.keep(&AuditEntry::decision("read_file", "/etc/shadow", false, "denied")));
+            assert!(sampler.keep(&AuditEntry::note("policy_load", "load", "policy", "loaded")));
+        }
+    }
+}
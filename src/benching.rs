@@ -0,0 +1,142 @@
+//! Entry points for the benches in `benches/`, built into the library
+//! with the `bench` feature, so path checks, glob matching, policy loading
+//! and searches can be timed without the gRPC layer.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use crate::fs::matcher::GlobSet;
+use crate::fs::redact::Redactor;
+use crate::fs::search::{search, SearchOptions};
+use crate::fs::validator::{Access, PathValidator};
+use crate::policy::parser::PolicySource;
+use crate::policy::{FilesystemPolicy, Policy};
+
+/// A policy allowing `entries` project prefixes and as many globs, and
+/// denying as many patterns
+pub fn policy_toml(entries: usize) -> String {
+    let list = |format: &dyn Fn(usize) -> String| {
+        (0..entries).map(|i| format!("{:?}", format(i))).collect::<Vec<_>>().join(", ")
+    };
+    format!(
+        r#"
+[filesystem]
+allowed_read = [{}, {}]
+allowed_write = []
+denied_patterns = [{}]
+
+[network]
+allowed_domains = []
+blocked_ips = []
+max_connections = 10
+
+[process]
+allowed_commands = []
+max_cpu_percent = 50
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = 100
+max_open_files = 1000
+"#,
+        list(&|i| format!("/srv/projects/team-{}/workspace", i)),
+        list(&|i| format!("/srv/shared/*/team-{}/**", i)),
+        list(&|i| format!("*/secret-{}/*", i)),
+    )
+}
+
+/// `count` request paths against [`policy_toml`] of `entries`: allowed,
+/// allowed by a glob, denied by a pattern and outside the policy in turn
+pub fn request_paths(entries: usize, count: usize) -> Vec<PathBuf> {
+    (0..count)
+        .map(|i| {
+            let team = (i * 7) % entries.max(1);
+            PathBuf::from(match i % 4 {
+                0 => format!("/srv/projects/team-{}/workspace/src/main.rs", team),
+                1 => format!("/srv/shared/docs/team-{}/guide.md", team),
+                2 => format!("/srv/projects/team-{}/workspace/secret-{}/token", team, team),
+                _ => format!("/home/user/team-{}/notes.txt", team),
+            })
+        })
+        .collect()
+}
+
+/// `count` paths far over the `[enforcement]` defaults, half by length
+/// and half by component count, each against [`policy_toml`] of `entries`
+pub fn oversized_paths(entries: usize, count: usize) -> Vec<PathBuf> {
+    (0..count)
+        .map(|i| {
+            let team = (i * 7) % entries.max(1);
+            let base = format!("/srv/projects/team-{}/workspace", team);
+            PathBuf::from(match i % 2 {
+                0 => format!("{}/{}", base, "x".repeat(1 << 20)),
+                _ => format!("{}{}", base, "/a".repeat(100_000)),
+            })
+        })
+        .collect()
+}
+
+/// A policy loaded and compiled as the daemon does at startup
+pub struct Validator(PathValidator);
+
+impl Validator {
+    /// Panics on a policy that does not load
+    pub fn load(toml: &str) -> Self {
+        let policy = Policy::load(&PolicySource::Inline(toml.to_string()), None).expect("bench policy loads");
+        Self(PathValidator::new(policy.filesystem).with_input_limits(&policy.enforcement))
+    }
+
+    /// A validator allowing reads under `allowed_read` except for
+    /// `denied_patterns`, remembering up to `cache_size` decisions
+    pub fn with_lists(allowed_read: Vec<String>, denied_patterns: Vec<String>, cache_size: usize) -> Self {
+        let policy = FilesystemPolicy {
+            allowed_read,
+            denied_patterns,
+            ..Default::default()
+        };
+        Self(PathValidator::new(policy).with_decision_cache(cache_size, Duration::ZERO))
+    }
+
+    /// How many of `paths` may be read
+    pub fn count_readable(&self, paths: &[PathBuf]) -> usize {
+        paths.iter().filter(|path| self.0.can_access(path, Access::Read).unwrap_or(false)).count()
+    }
+}
+
+/// Deny patterns compiled as the validator compiles them
+pub struct Globs(GlobSet);
+
+impl Globs {
+    pub fn new(patterns: &[String]) -> Self {
+        Self(GlobSet::new(patterns))
+    }
+
+    pub fn count_matches(&self, paths: &[PathBuf]) -> usize {
+        paths.iter().filter(|path| self.0.matches(&path.to_string_lossy())).count()
+    }
+}
+
+/// Searches every file under `root`, which the policy allows reading, for
+/// `pattern` on `threads` walker threads as `search_files` does. Returns
+/// the files searched and the matches.
+pub fn search_tree(root: &Path, pattern: &str, threads: usize) -> (u64, u64) {
+    let validator = PathValidator::new(FilesystemPolicy {
+        allowed_read: vec![root.to_string_lossy().into_owned()],
+        ..Default::default()
+    });
+    let options = SearchOptions {
+        pattern: pattern.to_string(),
+        literal: true,
+        include: vec![],
+        exclude: vec![],
+        context_lines: 0,
+        max_results: u64::MAX,
+        max_file_bytes: 1024 * 1024,
+        time_budget: Duration::from_secs(600),
+        threads,
+    };
+    let summary = search(root, &options, &validator, &Redactor::default(), &AtomicBool::new(false), &|_| true)
+        .expect("bench pattern compiles");
+    (summary.files_searched, summary.matches)
+}
@@ -0,0 +1,235 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::daemon::clock::SharedClock;
+use crate::daemon::state;
+
+/// A temporary permission for one session to repeat one operation on one
+/// resource, e.g. from an `allow_session` approval
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    pub approver: String,
+    pub expires: Instant,
+}
+
+type GrantKey = (String, String, String);
+
+/// A grant as `[state] dir` keeps it
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedGrant {
+    session: String,
+    operation: String,
+    resource: String,
+    approver: String,
+    /// Unix timestamp
+    expires_at: i64,
+}
+
+/// Temporary grants, checked before a denial is escalated again
+#[derive(Default)]
+pub struct GrantStore {
+    grants: Mutex<HashMap<GrantKey, Grant>>,
+    state_file: Option<PathBuf>,
+    changed: AtomicBool,
+    clock: SharedClock,
+}
+
+impl GrantStore {
+    /// Expires grants by `clock`; given before
+    /// [`with_state_file`](Self::with_state_file), which reads saved expiries
+    /// by it
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Saves grants to `path` on [`flush`](Self::flush), starting from the
+    /// unexpired ones already there
+    pub fn with_state_file(mut self, path: Option<PathBuf>) -> Self {
+        let saved: Vec<SavedGrant> = path.as_deref().and_then(|path| state::load_json(path, "grants")).unwrap_or_default();
+        let grants = self.grants.get_mut().unwrap_or_else(|e| e.into_inner());
+        for grant in saved {
+            if let Some(expires) = state::from_unix(grant.expires_at, &self.clock) {
+                let key = (grant.session, grant.operation, grant.resource);
+                grants.insert(key, Grant { approver: grant.approver, expires });
+            }
+        }
+        self.state_file = path;
+        self
+    }
+
+    /// Inserts the grant and revokes the session's other grants for an
+    /// operation `conflicts` pairs with `operation`, returning those as
+    /// `(operation, resource, grant)` so their end can be audited
+    pub fn insert(
+        &self,
+        session: &str,
+        operation: &str,
+        resource: &str,
+        ttl: Duration,
+        approver: &str,
+        conflicts: &[[String; 2]],
+    ) -> Vec<(String, String, Grant)> {
+        let conflicting = |other: &str| {
+            conflicts.iter().any(|[a, b]| (a == operation && b == other) || (b == operation && a == other))
+        };
+        let mut grants = self.lock();
+        let mut revoked: Vec<_> = grants
+            .extract_if(|(s, o, r), _| s == session && conflicting(o) && (o != operation || r != resource))
+            .map(|((_, operation, resource), grant)| (operation, resource, grant))
+            .collect();
+        revoked.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        grants.insert(
+            (session.to_string(), operation.to_string(), resource.to_string()),
+            Grant {
+                approver: approver.to_string(),
+                expires: self.clock.now() + ttl,
+            },
+        );
+        self.changed.store(true, Ordering::Relaxed);
+        revoked
+    }
+
+    /// The unexpired grant for exactly this session, operation and resource
+    pub fn find(&self, session: &str, operation: &str, resource: &str) -> Option<Grant> {
+        let now = self.clock.now();
+        self.lock()
+            .get(&(session.to_string(), operation.to_string(), resource.to_string()))
+            .filter(|grant| grant.expires > now)
+            .cloned()
+    }
+
+    /// Drops the grants that have expired, returning them as `(session,
+    /// operation, resource, grant)` so their end can be audited
+    pub fn expire(&self) -> Vec<(String, String, String, Grant)> {
+        let now = self.clock.now();
+        let mut grants = self.lock();
+        let mut expired: Vec<_> = grants
+            .extract_if(|_, grant| grant.expires <= now)
+            .map(|((session, operation, resource), grant)| (session, operation, resource, grant))
+            .collect();
+        expired.sort_by(|a, b| (&a.0, &a.1, &a.2).cmp(&(&b.0, &b.1, &b.2)));
+        expired
+    }
+
+    /// Saves the grants if any was added since the last save. Expired ones
+    /// are dropped again on load, so they need no save of their own.
+    pub fn flush(&self) -> Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let saved: Vec<SavedGrant> = self
+            .lock()
+            .iter()
+            .map(|((session, operation, resource), grant)| SavedGrant {
+                session: session.clone(),
+                operation: operation.clone(),
+                resource: resource.clone(),
+                approver: grant.approver.clone(),
+                expires_at: state::to_unix(grant.expires, &self.clock),
+            })
+            .collect();
+        let saved = state::save_json(path, &saved);
+        if saved.is_err() {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        saved.with_context(|| format!("Failed to save grants to {}", path.display()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<GrantKey, Grant>> {
+        self.grants.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::daemon::clock::MockClock;
+
+    #[test]
+    fn test_grants_are_scoped_and_expire() {
+        let clock = MockClock::new(1_700_000_000);
+        let store = GrantStore::default().with_clock(SharedClock::new(clock.clone()));
+        store.insert("session-a", "write_file", "/srv/out.txt", Duration::from_secs(60), "alice", &[]);
+        store.insert("session-a", "read_file", "/srv/in.txt", Duration::ZERO, "alice", &[]);
+
+        let grant = store.find("session-a", "write_file", "/srv/out.txt").unwrap();
+        assert_eq!(grant.approver, "alice");
+        assert!(store.find("session-b", "write_file", "/srv/out.txt").is_none());
+        assert!(store.find("session-a", "delete_file", "/srv/out.txt").is_none());
+        assert!(store.find("session-a", "write_file", "/srv/other.txt").is_none());
+        assert!(store.find("session-a", "read_file", "/srv/in.txt").is_none());
+
+        clock.advance(Duration::from_secs(59));
+        assert!(store.find("session-a", "write_file", "/srv/out.txt").is_some());
+        clock.advance(Duration::from_secs(1));
+        assert!(store.find("session-a", "write_file", "/srv/out.txt").is_none());
+
+        let expired = store.expire();
+        let keys: Vec<(&str, &str)> =
+            expired.iter().map(|(_, operation, resource, _)| (operation.as_str(), resource.as_str())).collect();
+        assert_eq!(keys, [("read_file", "/srv/in.txt"), ("write_file", "/srv/out.txt")]);
+        assert!(store.expire().is_empty());
+    }
+
+    #[test]
+    fn test_a_grant_revokes_the_grants_it_conflicts_with() {
+        let store = GrantStore::default();
+        let conflicts = [
+            ["read_file".to_string(), "write_file".to_string()],
+            ["delete_file".to_string(), "delete_file".to_string()],
+        ];
+        let ttl = Duration::from_secs(60);
+        let grant = |session, operation, resource| {
+            store.insert(session, operation, resource, ttl, "alice", &conflicts)
+        };
+        assert!(grant("session-a", "read_file", "/srv/in.txt").is_empty());
+        assert!(grant("session-a", "read_file", "/srv/other.txt").is_empty());
+        assert!(grant("session-b", "read_file", "/srv/in.txt").is_empty());
+        assert!(grant("session-a", "execute_command", "make").is_empty());
+
+        // Granting write revokes the session's reads, and only its own
+        let revoked = grant("session-a", "write_file", "/srv/out.txt");
+        let keys: Vec<(&str, &str)> = revoked.iter().map(|(o, r, _)| (o.as_str(), r.as_str())).collect();
+        assert_eq!(keys, [("read_file", "/srv/in.txt"), ("read_file", "/srv/other.txt")]);
+        assert!(store.find("session-a", "read_file", "/srv/in.txt").is_none());
+        assert!(store.find("session-a", "write_file", "/srv/out.txt").is_some());
+        assert!(store.find("session-a", "execute_command", "make").is_some());
+        assert!(store.find("session-b", "read_file", "/srv/in.txt").is_some());
+        // And reading again revokes the write
+        assert_eq!(grant("session-a", "read_file", "/srv/in.txt").len(), 1);
+        assert!(store.find("session-a", "write_file", "/srv/out.txt").is_none());
+
+        // An operation that conflicts with itself is granted one resource at a time
+        assert!(grant("session-a", "delete_file", "/srv/a").is_empty());
+        assert!(grant("session-a", "delete_file", "/srv/a").is_empty());
+        assert_eq!(grant("session-a", "delete_file", "/srv/b")[0].1, "/srv/a");
+        assert!(store.find("session-a", "delete_file", "/srv/b").is_some());
+    }
+
+    #[test]
+    fn test_grants_survive_a_restart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("grants.json");
+        let store = GrantStore::default().with_state_file(Some(path.clone()));
+        store.insert("session-a", "write_file", "/srv/out.txt", Duration::from_secs(60), "alice", &[]);
+        store.insert("session-a", "read_file", "/srv/in.txt", Duration::ZERO, "alice", &[]);
+        store.flush().unwrap();
+
+        let restarted = GrantStore::default().with_state_file(Some(path.clone()));
+        assert_eq!(restarted.find("session-a", "write_file", "/srv/out.txt").unwrap().approver, "alice");
+        assert!(restarted.find("session-a", "read_file", "/srv/in.txt").is_none());
+
+        std::fs::write(&path, "not json").unwrap();
+        let fresh = GrantStore::default().with_state_file(Some(path));
+        assert!(fresh.find("session-a", "write_file", "/srv/out.txt").is_none());
+    }
+}
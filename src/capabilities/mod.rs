@@ -1 +1,3 @@
 // Capability-based access control
+pub mod grants;
+pub mod sessions;
@@ -0,0 +1,473 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::audit::sha256;
+use crate::daemon::clock::SharedClock;
+use crate::daemon::state;
+use crate::network::Denial;
+use crate::policy::SessionsPolicy;
+
+/// Files a snapshot session records the first read of; reads of others are
+/// neither recorded nor flagged
+pub const MAX_SNAPSHOT_PATHS: usize = 10_000;
+
+/// How much of a file its fingerprint hashes
+const SNAPSHOT_PREFIX_BYTES: u64 = 64 * 1024;
+
+/// What a session spends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    BytesRead,
+    FilesWritten,
+    CommandsRun,
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Budget::BytesRead => "bytes_read",
+            Budget::FilesWritten => "files_written",
+            Budget::CommandsRun => "commands_run",
+        })
+    }
+}
+
+/// An amount per budget; as limits, 0 means unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Budgets {
+    pub bytes_read: u64,
+    pub files_written: u64,
+    pub commands_run: u64,
+}
+
+impl Budgets {
+    /// The `[sessions]` defaults
+    pub fn from_policy(policy: &SessionsPolicy) -> Self {
+        Self {
+            bytes_read: policy.max_read_mb.saturating_mul(1024 * 1024),
+            files_written: policy.max_files_written,
+            commands_run: policy.max_commands_run,
+        }
+    }
+
+    /// Each budget of `self` that is unset (0) taken from `defaults`
+    pub fn or(self, defaults: Budgets) -> Self {
+        let pick = |own: u64, default: u64| if own == 0 { default } else { own };
+        Self {
+            bytes_read: pick(self.bytes_read, defaults.bytes_read),
+            files_written: pick(self.files_written, defaults.files_written),
+            commands_run: pick(self.commands_run, defaults.commands_run),
+        }
+    }
+
+    fn get_mut(&mut self, budget: Budget) -> &mut u64 {
+        match budget {
+            Budget::BytesRead => &mut self.bytes_read,
+            Budget::FilesWritten => &mut self.files_written,
+            Budget::CommandsRun => &mut self.commands_run,
+        }
+    }
+
+    pub fn get(&self, budget: Budget) -> u64 {
+        match budget {
+            Budget::BytesRead => self.bytes_read,
+            Budget::FilesWritten => self.files_written,
+            Budget::CommandsRun => self.commands_run,
+        }
+    }
+}
+
+/// Whether a session checks that the files it reads stay as they were
+/// when it first read them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Snapshot {
+    #[default]
+    Off,
+    /// Reads of a changed file are flagged
+    Flag,
+    /// And denied with `snapshot_violation`
+    Strict,
+}
+
+/// What a snapshot session compares a file by: its size, mtime and a hash
+/// of its first [`SNAPSHOT_PREFIX_BYTES`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    size: u64,
+    mtime_ns: i128,
+    prefix: [u8; 32],
+}
+
+impl Fingerprint {
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let mut file = std::fs::File::open(path)?;
+        let metadata = file.metadata()?;
+        let mtime_ns = match metadata.modified()?.duration_since(std::time::UNIX_EPOCH) {
+            Ok(since) => since.as_nanos() as i128,
+            Err(e) => -(e.duration().as_nanos() as i128),
+        };
+        let mut prefix = Vec::new();
+        (&mut file).take(SNAPSHOT_PREFIX_BYTES).read_to_end(&mut prefix)?;
+        Ok(Self {
+            size: metadata.len(),
+            mtime_ns,
+            prefix: sha256::digest(&prefix),
+        })
+    }
+}
+
+/// A session's limits and what it has spent so far
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    /// Unix timestamp
+    pub expires_at: i64,
+    pub limits: Budgets,
+    pub used: Budgets,
+    #[serde(default)]
+    pub snapshot: Snapshot,
+}
+
+/// One debit against a session, for the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Debit {
+    pub budget: Budget,
+    pub used: u64,
+    /// 0 when unlimited
+    pub limit: u64,
+}
+
+impl fmt::Display for Debit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.limit == 0 {
+            write!(f, "{}", self.used)
+        } else {
+            write!(f, "{}/{}", self.used, self.limit)
+        }
+    }
+}
+
+struct Entry {
+    session: Session,
+    expires: Instant,
+    /// First reads, for [`Snapshot`] sessions; gone with the session, and
+    /// not saved, so a restart starts them again
+    first_reads: HashMap<PathBuf, Fingerprint>,
+}
+
+/// Sessions created through `CreateSession`, held in memory until their
+/// TTL runs out. Requests naming any other session id are not budgeted.
+pub struct SessionStore {
+    defaults: Budgets,
+    ttl: Duration,
+    max_sessions: usize,
+    sessions: Mutex<HashMap<String, Entry>>,
+    state_file: Option<PathBuf>,
+    changed: AtomicBool,
+    clock: SharedClock,
+}
+
+impl SessionStore {
+    pub fn new(policy: &SessionsPolicy) -> Self {
+        Self {
+            defaults: Budgets::from_policy(policy),
+            ttl: Duration::from_secs(policy.ttl_secs),
+            max_sessions: policy.max_sessions as usize,
+            sessions: Mutex::new(HashMap::new()),
+            state_file: None,
+            changed: AtomicBool::new(false),
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Expires sessions by `clock`; given before
+    /// [`with_state_file`](Self::with_state_file), which reads saved expiries
+    /// by it
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Saves sessions and what they spent to `path` on
+    /// [`flush`](Self::flush), starting from the live ones already there
+    pub fn with_state_file(mut self, path: Option<PathBuf>) -> Self {
+        let saved: Vec<Session> = path.as_deref().and_then(|path| state::load_json(path, "sessions")).unwrap_or_default();
+        let sessions = self.sessions.get_mut().unwrap_or_else(|e| e.into_inner());
+        for session in saved {
+            if let Some(expires) = state::from_unix(session.expires_at, &self.clock) {
+                let first_reads = HashMap::new();
+                sessions.insert(session.id.clone(), Entry { session, expires, first_reads });
+            }
+        }
+        self.state_file = path;
+        self
+    }
+
+    /// Starts a session with `limits`, unset budgets taking the policy
+    /// defaults, that lives for `ttl` or the policy's `ttl_secs`
+    pub fn create(&self, limits: Budgets, ttl: Option<Duration>, snapshot: Snapshot) -> Result<Session, Denial> {
+        let mut sessions = self.lock();
+        let now = self.clock.now();
+        sessions.retain(|_, entry| entry.expires > now);
+        if sessions.len() >= self.max_sessions {
+            return Err(Denial::new(
+                "session_limit_reached",
+                format!("{} sessions already active", self.max_sessions),
+            ));
+        }
+
+        let ttl = ttl.unwrap_or(self.ttl);
+        let session = Session {
+            id: new_id(),
+            expires_at: self.clock.unix_now().saturating_add(ttl.as_secs() as i64),
+            limits: limits.or(self.defaults),
+            used: Budgets::default(),
+            snapshot,
+        };
+        sessions.insert(
+            session.id.clone(),
+            Entry {
+                session: session.clone(),
+                expires: now + ttl,
+                first_reads: HashMap::new(),
+            },
+        );
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(session)
+    }
+
+    /// The session, unless it is unknown or has expired
+    pub fn get(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.lock();
+        self.live(&mut sessions, id).map(|entry| entry.session.clone())
+    }
+
+    /// Spends `amount` of `budget`. `Ok(None)` when `id` is not a live
+    /// session; a debit that would overspend is refused whole and leaves
+    /// the budget as it was.
+    pub fn debit(&self, id: &str, budget: Budget, amount: u64) -> Result<Option<Debit>, Denial> {
+        let mut sessions = self.lock();
+        let Some(entry) = self.live(&mut sessions, id) else {
+            return Ok(None);
+        };
+        let session = &mut entry.session;
+        let limit = session.limits.get(budget);
+        let used = session.used.get_mut(budget);
+        let after = used.saturating_add(amount);
+        if limit > 0 && after > limit {
+            return Err(Denial::new(
+                "budget_exhausted",
+                format!("Session {} budget exhausted: {} of {} used", budget, used, limit),
+            ));
+        }
+        *used = after;
+        self.changed.store(true, Ordering::Relaxed);
+        Ok(Some(Debit {
+            budget,
+            used: after,
+            limit,
+        }))
+    }
+
+    /// The snapshot mode of session `id`; off when it is not a live session
+    pub fn snapshot(&self, id: &str) -> Snapshot {
+        let mut sessions = self.lock();
+        self.live(&mut sessions, id).map_or(Snapshot::Off, |entry| entry.session.snapshot)
+    }
+
+    /// Files session `id` has recorded the first read of
+    pub fn snapshot_paths(&self, id: &str) -> usize {
+        let mut sessions = self.lock();
+        self.live(&mut sessions, id).map_or(0, |entry| entry.first_reads.len())
+    }
+
+    /// Compares a read of `path` with the session's first read of it,
+    /// recording this one if it is the first. `Ok(true)` when the file has
+    /// changed since, which a strict session refuses with
+    /// `snapshot_violation` instead.
+    pub fn check_read(&self, id: &str, path: &Path, fingerprint: Fingerprint) -> Result<bool, Denial> {
+        let mut sessions = self.lock();
+        let Some(entry) = self.live(&mut sessions, id) else {
+            return Ok(false);
+        };
+        let changed = match entry.first_reads.get(path) {
+            Some(first) => *first != fingerprint,
+            None => {
+                if entry.first_reads.len() < MAX_SNAPSHOT_PATHS {
+                    entry.first_reads.insert(path.to_path_buf(), fingerprint);
+                }
+                false
+            }
+        };
+        if changed && entry.session.snapshot == Snapshot::Strict {
+            return Err(Denial::new(
+                "snapshot_violation",
+                format!("{} has changed since the session first read it", path.display()),
+            ));
+        }
+        Ok(changed)
+    }
+
+    /// Gives back a debit for an operation that then failed
+    pub fn refund(&self, id: &str, budget: Budget, amount: u64) {
+        let mut sessions = self.lock();
+        if let Some(entry) = self.live(&mut sessions, id) {
+            let used = entry.session.used.get_mut(budget);
+            *used = used.saturating_sub(amount);
+            self.changed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Saves the sessions if one was created or spent since the last save
+    pub fn flush(&self) -> Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let saved: Vec<Session> = self.lock().values().map(|entry| entry.session.clone()).collect();
+        let saved = state::save_json(path, &saved);
+        if saved.is_err() {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        saved.with_context(|| format!("Failed to save sessions to {}", path.display()))
+    }
+
+    pub fn active(&self) -> usize {
+        let mut sessions = self.lock();
+        let now = self.clock.now();
+        sessions.retain(|_, entry| entry.expires > now);
+        sessions.len()
+    }
+
+    fn live<'a>(&self, sessions: &'a mut HashMap<String, Entry>, id: &str) -> Option<&'a mut Entry> {
+        if sessions.get(id)?.expires <= self.clock.now() {
+            sessions.remove(id);
+            return None;
+        }
+        sessions.get_mut(id)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn new_id() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sess-{}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> SessionStore {
+        SessionStore::new(&SessionsPolicy {
+            max_files_written: 2,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_budgets_are_debited_per_session() {
+        let store = store();
+        let limits = Budgets {
+            bytes_read: 100,
+            ..Default::default()
+        };
+        let a = store.create(limits, None, Snapshot::Off).unwrap();
+        let b = store.create(limits, None, Snapshot::Off).unwrap();
+        assert_eq!(a.limits.files_written, 2, "unset budgets take the policy default");
+        assert_eq!(a.limits.commands_run, 0);
+
+        let debit = store.debit(&a.id, Budget::BytesRead, 60).unwrap().unwrap();
+        assert_eq!(debit.to_string(), "60/100");
+        let denial = store.debit(&a.id, Budget::BytesRead, 50).unwrap_err();
+        assert_eq!(denial.violation, "budget_exhausted");
+        assert_eq!(store.get(&a.id).unwrap().used.bytes_read, 60);
+
+        // Exhausting one session leaves the other alone
+        assert!(store.debit(&b.id, Budget::BytesRead, 100).unwrap().is_some());
+        // Unlimited budgets only count
+        assert_eq!(store.debit(&a.id, Budget::CommandsRun, 1000).unwrap().unwrap().to_string(), "1000");
+        // Unknown sessions are not budgeted
+        assert_eq!(store.debit("free-form-id", Budget::BytesRead, 1 << 40).unwrap(), None);
+    }
+
+    #[test]
+    fn test_refund_and_expiry() {
+        let store = store();
+        let session = store.create(Budgets::default(), None, Snapshot::Off).unwrap();
+        store.debit(&session.id, Budget::FilesWritten, 1).unwrap();
+        store.debit(&session.id, Budget::FilesWritten, 1).unwrap();
+        assert!(store.debit(&session.id, Budget::FilesWritten, 1).is_err());
+        store.refund(&session.id, Budget::FilesWritten, 1);
+        assert!(store.debit(&session.id, Budget::FilesWritten, 1).is_ok());
+
+        let expired = store.create(Budgets::default(), Some(Duration::ZERO), Snapshot::Off).unwrap();
+        assert!(store.get(&expired.id).is_none());
+        assert_eq!(store.debit(&expired.id, Budget::FilesWritten, 5).unwrap(), None);
+        assert_eq!(store.active(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_sessions_notice_changed_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, "first").unwrap();
+        let store = store();
+        let flag = store.create(Budgets::default(), None, Snapshot::Flag).unwrap();
+        let strict = store.create(Budgets::default(), None, Snapshot::Strict).unwrap();
+        let off = store.create(Budgets::default(), None, Snapshot::Off).unwrap();
+        let read = |id: &str| store.check_read(id, &path, Fingerprint::read(&path).unwrap());
+
+        assert_eq!(read(&flag.id), Ok(false));
+        assert_eq!(read(&strict.id), Ok(false));
+        assert_eq!(read(&flag.id), Ok(false));
+        assert_eq!(store.snapshot_paths(&flag.id), 1);
+
+        // Same size, so only the content tells
+        std::fs::write(&path, "again").unwrap();
+        assert_eq!(read(&flag.id), Ok(true));
+        assert_eq!(read(&strict.id).unwrap_err().violation, "snapshot_violation");
+        assert_eq!(read(&off.id), Ok(false));
+        assert_eq!(read("free-form-id"), Ok(false));
+
+        // Reads past the bound are not recorded
+        let fingerprint = Fingerprint::read(&path).unwrap();
+        for i in 1..MAX_SNAPSHOT_PATHS + 10 {
+            let other = Path::new(&format!("/srv/{}", i)).to_path_buf();
+            assert_eq!(store.check_read(&flag.id, &other, fingerprint.clone()), Ok(false));
+        }
+        assert_eq!(store.snapshot_paths(&flag.id), MAX_SNAPSHOT_PATHS);
+    }
+
+    #[test]
+    fn test_spent_budgets_survive_a_restart() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sessions.json");
+        let before = store().with_state_file(Some(path.clone()));
+        let session = before.create(Budgets::default(), None, Snapshot::Off).unwrap();
+        before.debit(&session.id, Budget::FilesWritten, 2).unwrap();
+        before.flush().unwrap();
+
+        let restarted = store().with_state_file(Some(path.clone()));
+        assert_eq!(restarted.get(&session.id).unwrap().used.files_written, 2);
+        assert_eq!(restarted.debit(&session.id, Budget::FilesWritten, 1).unwrap_err().violation, "budget_exhausted");
+
+        std::fs::write(&path, "[{").unwrap();
+        assert_eq!(store().with_state_file(Some(path)).active(), 0);
+    }
+}
@@ -0,0 +1,201 @@
+use tonic::transport::{Channel, Endpoint};
+
+use crate::proto::openclaw::enforce::enforcement_service_client::EnforcementServiceClient;
+use crate::proto::openclaw::enforce::*;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("failed to connect: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    /// Boxed, as a `Status` is large
+    #[error("request failed: {}", .0.message())]
+    Status(Box<tonic::Status>),
+    /// The policy refused the request
+    #[error("denied: {reason} ({})", .violations.join(", "))]
+    Denied {
+        code: ReasonCode,
+        reason: String,
+        violations: Vec<String>,
+    },
+}
+
+impl From<tonic::Status> for ClientError {
+    fn from(status: tonic::Status) -> Self {
+        Self::Status(Box::new(status))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// Async client for the enforcement daemon.
+///
+/// Policy denials come back as [`ClientError::Denied`] rather than as a
+/// status to inspect; [`EnforceClient::inner`] gives the generated stub for
+/// RPCs without a method here.
+#[derive(Debug, Clone)]
+pub struct EnforceClient {
+    inner: EnforcementServiceClient<Channel>,
+    session_id: String,
+}
+
+impl EnforceClient {
+    /// Connects to `address`, e.g. `http://127.0.0.1:50051`
+    pub async fn connect(address: impl Into<String>) -> Result<Self> {
+        let channel = Endpoint::from_shared(address.into())?.connect().await?;
+        Ok(Self::new(channel))
+    }
+
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            inner: EnforcementServiceClient::new(channel),
+            session_id: String::new(),
+        }
+    }
+
+    /// Sends `session_id` with each request, for audit entries, approvals
+    /// and session budgets
+    pub fn with_session(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = session_id.into();
+        self
+    }
+
+    pub fn inner(&mut self) -> &mut EnforcementServiceClient<Channel> {
+        &mut self.inner
+    }
+
+    pub async fn read_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let response = self
+            .inner
+            .read_file(ReadFileRequest {
+                path: path.to_string(),
+                capability: self.capability(),
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        allowed(response.status)?;
+        Ok(response.data)
+    }
+
+    /// `length` bytes of `path` from `offset`, or the rest of it when
+    /// `length` is 0, and whether they end the file. A large file can be
+    /// read in parts by moving `offset` on until they do.
+    pub async fn read_file_range(&mut self, path: &str, offset: u64, length: u64) -> Result<(Vec<u8>, bool)> {
+        let response = self
+            .inner
+            .read_file(ReadFileRequest {
+                path: path.to_string(),
+                capability: self.capability(),
+                offset,
+                length,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        allowed(response.status)?;
+        Ok((response.data, response.eof))
+    }
+
+    /// Whether the policy lets this session read `path`. There is no
+    /// check-only RPC, so the file is read and its contents dropped.
+    pub async fn can_read(&mut self, path: &str) -> Result<bool> {
+        match self.read_file(path).await {
+            Ok(_) => Ok(true),
+            Err(ClientError::Denied { .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn write_file(&mut self, path: &str, data: impl Into<Vec<u8>>, mode: WriteFileMode) -> Result<()> {
+        let response = self
+            .inner
+            .write_file(WriteFileRequest {
+                path: path.to_string(),
+                data: data.into(),
+                capability: self.capability(),
+                mode: mode as i32,
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        allowed(response.status)
+    }
+
+    /// Replaces `path` with `chunks` in turn, over one WriteFileStream call,
+    /// so the whole file need not be in memory at once
+    pub async fn write_file_chunks<I>(&mut self, path: &str, chunks: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        let first = WriteFileChunk {
+            path: path.to_string(),
+            capability: self.capability(),
+            ..Default::default()
+        };
+        let rest = chunks.into_iter().map(|data| WriteFileChunk {
+            data,
+            ..Default::default()
+        });
+        let last = WriteFileChunk {
+            done: true,
+            ..Default::default()
+        };
+        let stream = tokio_stream::iter(std::iter::once(first).chain(rest).chain([last]));
+        let response = self.inner.write_file_stream(stream).await?.into_inner();
+        allowed(response.status)
+    }
+
+    /// Deletes `path`; returns the trash id when the file can be restored
+    pub async fn delete_file(&mut self, path: &str) -> Result<Option<String>> {
+        let response = self
+            .inner
+            .delete_file(DeleteFileRequest {
+                path: path.to_string(),
+                capability: self.capability(),
+            })
+            .await?
+            .into_inner();
+        allowed(response.status)?;
+        Ok((!response.trash_id.is_empty()).then_some(response.trash_id))
+    }
+
+    /// Starts a budgeted session; pass its id to [`EnforceClient::with_session`]
+    pub async fn create_session(&mut self, budgets: SessionBudgets, ttl_secs: u64) -> Result<SessionInfo> {
+        let request = CreateSessionRequest {
+            budgets: Some(budgets),
+            ttl_secs,
+            ..Default::default()
+        };
+        Ok(self.inner.create_session(request).await?.into_inner())
+    }
+
+    pub async fn get_session(&mut self, session_id: &str) -> Result<SessionInfo> {
+        let request = GetSessionRequest {
+            session_id: session_id.to_string(),
+        };
+        Ok(self.inner.get_session(request).await?.into_inner())
+    }
+
+    pub async fn status(&mut self) -> Result<StatusResponse> {
+        Ok(self.inner.get_status(StatusRequest {}).await?.into_inner())
+    }
+
+    fn capability(&self) -> Option<Capability> {
+        Some(Capability {
+            session_id: self.session_id.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+fn allowed(status: Option<SecurityStatus>) -> Result<()> {
+    match status {
+        Some(status) if !status.allowed => Err(ClientError::Denied {
+            code: status.reason_code(),
+            reason: status.reason,
+            violations: status.violations,
+        }),
+        _ => Ok(()),
+    }
+}
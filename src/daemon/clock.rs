@@ -0,0 +1,107 @@
+//! The time that expiries, quotas and `[schedule]` windows are decided by
+//!
+//! Stores that expire what they hold read the time from a [`SharedClock`]
+//! rather than the system, so tests can hand them a `MockClock` and move
+//! it past an expiry instead of sleeping. Everything defaults to the system
+//! clock.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+#[cfg(test)]
+use std::{sync::Mutex, time::Duration};
+
+/// A source of the current time, in both forms the daemon keeps it
+pub trait Clock: Send + Sync {
+    /// For expiries held in memory
+    fn now(&self) -> Instant;
+
+    /// Unix seconds, for saved expiries, quota periods and times of day
+    fn unix_now(&self) -> i64;
+}
+
+/// The system's clocks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_now(&self) -> i64 {
+        crate::audit::logger::unix_now()
+    }
+}
+
+/// A clock that stands still until [`advance`](Self::advance)d. Clones
+/// share the time, so a test keeps one to move the one it handed out.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    start: Instant,
+    unix_start: i64,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    /// Starting at `unix` seconds
+    pub fn new(unix: i64) -> Self {
+        Self {
+            start: Instant::now(),
+            unix_start: unix,
+            elapsed: Arc::default(),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn unix_now(&self) -> i64 {
+        self.unix_start + self.elapsed().as_secs() as i64
+    }
+}
+
+/// A [`Clock`] the stores and the service share; the system clock by
+/// default
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    pub fn unix_now(&self) -> i64 {
+        self.0.unix_now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedClock").field(&self.unix_now()).finish()
+    }
+}
@@ -0,0 +1,223 @@
+//! What the daemon's subsystems say about themselves, behind the gRPC
+//! health service and `get_status`
+//!
+//! Each subsystem reports whether it is working, degraded or failed. The
+//! daemon is serving unless a critical one has failed: a failed reload
+//! leaves the last good policy in force, and requests go on while state
+//! snapshots cannot be written, but decisions that cannot be audited
+//! should not happen at all.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::audit::logger::unix_now;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Subsystem {
+    /// Reloads from the policy source
+    PolicyLoader,
+    /// Appends to `[audit] file`
+    AuditSink,
+    /// Snapshots in `[state] dir`
+    StateStore,
+    /// `watch_file` streams polling their files
+    FileWatches,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 4] = [
+        Subsystem::PolicyLoader,
+        Subsystem::AuditSink,
+        Subsystem::StateStore,
+        Subsystem::FileWatches,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::PolicyLoader => "policy_loader",
+            Subsystem::AuditSink => "audit_sink",
+            Subsystem::StateStore => "state_store",
+            Subsystem::FileWatches => "file_watches",
+        }
+    }
+
+    /// Whether the daemon stops serving while this has failed
+    pub fn is_critical(self) -> bool {
+        matches!(self, Subsystem::AuditSink)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Condition {
+    #[default]
+    Ok,
+    Degraded,
+    Failed,
+}
+
+impl Condition {
+    pub fn name(self) -> &'static str {
+        match self {
+            Condition::Ok => "ok",
+            Condition::Degraded => "degraded",
+            Condition::Failed => "failed",
+        }
+    }
+}
+
+/// The last report from one subsystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubsystemHealth {
+    pub subsystem: Subsystem,
+    pub condition: Condition,
+    /// Why it is not ok; empty when it is
+    pub detail: String,
+    /// Unix timestamp of the last change of condition, 0 if it never changed
+    pub since: i64,
+    /// Changes of condition since startup
+    pub transitions: u64,
+}
+
+pub struct HealthRegistry {
+    subsystems: Mutex<BTreeMap<Subsystem, SubsystemHealth>>,
+    serving: watch::Sender<bool>,
+}
+
+impl Default for HealthRegistry {
+    fn default() -> Self {
+        let subsystems = Subsystem::ALL
+            .into_iter()
+            .map(|subsystem| {
+                let health = SubsystemHealth {
+                    subsystem,
+                    condition: Condition::Ok,
+                    detail: String::new(),
+                    since: 0,
+                    transitions: 0,
+                };
+                (subsystem, health)
+            })
+            .collect();
+        Self {
+            subsystems: Mutex::new(subsystems),
+            serving: watch::Sender::new(true),
+        }
+    }
+}
+
+impl HealthRegistry {
+    /// Records `condition` for `subsystem`, logging it if the condition
+    /// changed. Returns whether it did; a new `detail` alone is no change.
+    pub fn report(&self, subsystem: Subsystem, condition: Condition, detail: impl Into<String>) -> bool {
+        let detail = if condition == Condition::Ok { String::new() } else { detail.into() };
+        let mut subsystems = self.lock();
+        let health = subsystems.get_mut(&subsystem).expect("every subsystem is registered");
+        health.detail = detail;
+        if health.condition == condition {
+            return false;
+        }
+        let previous = std::mem::replace(&mut health.condition, condition);
+        health.since = unix_now();
+        health.transitions += 1;
+        match condition {
+            Condition::Ok => info!("✅ {} recovered (was {})", subsystem.name(), previous.name()),
+            _ => warn!("⚠️ {} is {}: {}", subsystem.name(), condition.name(), health.detail),
+        }
+        let serving = is_serving(subsystems.values());
+        self.serving.send_if_modified(|current| std::mem::replace(current, serving) != serving);
+        true
+    }
+
+    /// [`report`](Self::report) of [`Condition::Ok`] on success, else of
+    /// `on_error` with the error as the detail
+    pub fn report_result<E: std::fmt::Display>(
+        &self,
+        subsystem: Subsystem,
+        result: &Result<(), E>,
+        on_error: Condition,
+    ) -> bool {
+        match result {
+            Ok(()) => self.report(subsystem, Condition::Ok, ""),
+            Err(e) => self.report(subsystem, on_error, format!("{:#}", e)),
+        }
+    }
+
+    /// False while a critical subsystem has failed
+    pub fn is_serving(&self) -> bool {
+        *self.serving.borrow()
+    }
+
+    /// Sees every change of [`is_serving`](Self::is_serving)
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.serving.subscribe()
+    }
+
+    /// Every subsystem, in [`Subsystem::ALL`] order
+    pub fn snapshot(&self) -> Vec<SubsystemHealth> {
+        self.lock().values().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<Subsystem, SubsystemHealth>> {
+        self.subsystems.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn is_serving<'a>(mut subsystems: impl Iterator<Item = &'a SubsystemHealth>) -> bool {
+    !subsystems.any(|health| health.subsystem.is_critical() && health.condition == Condition::Failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health_of(registry: &HealthRegistry, subsystem: Subsystem) -> SubsystemHealth {
+        registry.snapshot().into_iter().find(|health| health.subsystem == subsystem).unwrap()
+    }
+
+    #[test]
+    fn test_only_critical_failures_stop_serving() {
+        let registry = HealthRegistry::default();
+        let mut serving = registry.subscribe();
+        assert!(registry.is_serving());
+
+        // A failed reload keeps the old policy, and unsaved state is retried
+        assert!(registry.report(Subsystem::PolicyLoader, Condition::Degraded, "bad toml"));
+        assert!(registry.report(Subsystem::StateStore, Condition::Failed, "read-only file system"));
+        assert!(registry.is_serving());
+        assert!(!serving.has_changed().unwrap());
+
+        assert!(registry.report(Subsystem::AuditSink, Condition::Failed, "disk full"));
+        assert!(!registry.is_serving());
+        assert!(serving.has_changed().unwrap());
+        assert!(!*serving.borrow_and_update());
+
+        // Still failed, for a new reason: not a transition
+        assert!(!registry.report(Subsystem::AuditSink, Condition::Failed, "quota exceeded"));
+        assert_eq!(health_of(&registry, Subsystem::AuditSink).detail, "quota exceeded");
+        assert!(!serving.has_changed().unwrap());
+
+        // Degraded is not failed, even for a critical subsystem
+        assert!(registry.report(Subsystem::AuditSink, Condition::Degraded, "slow"));
+        assert!(registry.is_serving());
+        assert!(*serving.borrow_and_update());
+    }
+
+    #[test]
+    fn test_transitions_are_counted_per_subsystem() {
+        let registry = HealthRegistry::default();
+        let failed: Result<(), &str> = Err("disk full");
+        assert!(registry.report_result(Subsystem::AuditSink, &failed, Condition::Failed));
+        assert!(!registry.report_result(Subsystem::AuditSink, &failed, Condition::Failed));
+        assert!(registry.report_result::<&str>(Subsystem::AuditSink, &Ok(()), Condition::Failed));
+        assert!(!registry.report(Subsystem::StateStore, Condition::Ok, "ignored"));
+
+        let audit = health_of(&registry, Subsystem::AuditSink);
+        assert_eq!((audit.condition, audit.transitions, audit.detail.as_str()), (Condition::Ok, 2, ""));
+        assert!(audit.since > 0);
+        let state = health_of(&registry, Subsystem::StateStore);
+        assert_eq!((state.transitions, state.since), (0, 0));
+        assert!(registry.is_serving());
+    }
+}
@@ -0,0 +1,7 @@
+// Running as a service: PID file, systemd notifications, saved state,
+// subsystem health and the clock
+pub mod clock;
+pub mod health;
+pub mod notify;
+pub mod pid_file;
+pub mod state;
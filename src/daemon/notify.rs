@@ -0,0 +1,100 @@
+//! systemd service notifications (`sd_notify`), for `Type=notify` units.
+//!
+//! Sent only when built with the `systemd` feature on Linux and started
+//! with `NOTIFY_SOCKET` set; otherwise every call does nothing.
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The gRPC server is accepting connections
+    Ready,
+    /// Shutdown has begun and in-flight requests are draining
+    Stopping,
+    /// Keep-alive for `WatchdogSec`
+    Watchdog,
+}
+
+impl State {
+    pub fn message(self) -> &'static str {
+        match self {
+            State::Ready => "READY=1",
+            State::Stopping => "STOPPING=1",
+            State::Watchdog => "WATCHDOG=1",
+        }
+    }
+}
+
+pub fn notify(state: State) {
+    tracing::debug!("Service state: {}", state.message());
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = linux::send(&socket, state.message()) {
+            tracing::warn!("sd_notify {} failed: {}", state.message(), e);
+        }
+    }
+}
+
+/// How often to send [`State::Watchdog`]: half of `WATCHDOG_USEC`, when
+/// the watchdog is enabled for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    #[cfg(all(feature = "systemd", target_os = "linux"))]
+    {
+        let pid = std::env::var("WATCHDOG_PID").ok();
+        linux::watchdog_interval(std::env::var("WATCHDOG_USEC").ok()?.as_str(), pid.as_deref())
+    }
+    #[cfg(not(all(feature = "systemd", target_os = "linux")))]
+    None
+}
+
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+mod linux {
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// `socket` is a path, or an abstract name when it starts with `@`
+    pub fn send(socket: &OsStr, message: &str) -> io::Result<()> {
+        let datagram = UnixDatagram::unbound()?;
+        match socket.as_bytes().strip_prefix(b"@") {
+            Some(name) => datagram.send_to_addr(message.as_bytes(), &SocketAddr::from_abstract_name(name)?)?,
+            None => datagram.send_to(message.as_bytes(), Path::new(socket))?,
+        };
+        Ok(())
+    }
+
+    /// A `WATCHDOG_PID` naming another process means the watchdog is not ours
+    pub fn watchdog_interval(usec: &str, pid: Option<&str>) -> Option<Duration> {
+        if pid.is_some_and(|pid| pid != std::process::id().to_string()) {
+            return None;
+        }
+        let usec: u64 = usec.parse().ok().filter(|&usec| usec > 0)?;
+        Some(Duration::from_micros(usec / 2))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_send_and_watchdog_interval() {
+            let dir = tempfile::TempDir::new().unwrap();
+            let path = dir.path().join("notify");
+            let receiver = UnixDatagram::bind(&path).unwrap();
+            send(path.as_os_str(), "READY=1").unwrap();
+            let mut buf = [0; 64];
+            let len = receiver.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"READY=1");
+
+            let ours = std::process::id().to_string();
+            assert_eq!(watchdog_interval("30000000", None), Some(Duration::from_secs(15)));
+            assert_eq!(watchdog_interval("30000000", Some(&ours)), Some(Duration::from_secs(15)));
+            assert_eq!(watchdog_interval("30000000", Some("1")), None);
+            assert_eq!(watchdog_interval("0", None), None);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// `--pid-file`: holds the daemon's pid while it runs and is removed on a
+/// clean shutdown, when this is dropped.
+///
+/// A file left behind by a process that is gone is replaced. One naming a
+/// live process means another daemon owns it, so startup fails.
+pub struct PidFile {
+    path: PathBuf,
+    pid: u32,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        let pid = std::process::id();
+        // A second attempt only follows removing a stale file
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", pid)
+                        .and_then(|()| file.sync_all())
+                        .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+                    return Ok(Self {
+                        path: path.to_path_buf(),
+                        pid,
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to create PID file {}", path.display()))
+                }
+            }
+
+            let contents = fs::read_to_string(path).unwrap_or_default();
+            match contents.trim().parse::<u32>() {
+                Ok(other) if is_running(other) => anyhow::bail!(
+                    "PID file {} belongs to running process {}; is another daemon up?",
+                    path.display(),
+                    other
+                ),
+                _ => {
+                    warn!("Replacing stale PID file {} ({:?})", path.display(), contents.trim());
+                    match fs::remove_file(path) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                            return Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        anyhow::bail!("PID file {} keeps reappearing", path.display())
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Left alone if something else has taken it over since
+        let ours = fs::read_to_string(&self.path).is_ok_and(|c| c.trim() == self.pid.to_string());
+        if ours {
+            match fs::remove_file(&self.path) {
+                Ok(()) => info!("Removed PID file {}", self.path.display()),
+                Err(e) => warn!("Failed to remove PID file {}: {}", self.path.display(), e),
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    use nix::errno::Errno;
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    // EPERM: it exists, but belongs to someone else
+    pid > 0 && matches!(kill(Pid::from_raw(pid), None), Ok(()) | Err(Errno::EPERM))
+}
+
+/// Without a way to probe the pid, a leftover file is taken to be stale
+#[cfg(not(target_os = "linux"))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pid_file_lifecycle() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("openclaw-enforce.pid");
+
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        // Our own process is alive
+        let err = PidFile::create(&path).err().unwrap();
+        assert!(err.to_string().contains("running process"), "{}", err);
+        drop(pid_file);
+        assert!(!path.exists());
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead = child.id();
+        child.wait().unwrap();
+        for stale in [dead.to_string(), "garbage".to_string()] {
+            fs::write(&path, &stale).unwrap();
+            let pid_file = PidFile::create(&path).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap().trim(), std::process::id().to_string());
+            drop(pid_file);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Runtime state kept across restarts (`[state] dir`)
+//!
+//! Temporary grants, session budgets and quota usage are each saved as a
+//! JSON snapshot, written beside its file and renamed over it, so a crash
+//! leaves the old snapshot or the new one. The stores only mark themselves
+//! changed on the hot path; the server saves them every few seconds and on
+//! shutdown. A snapshot that cannot be read is logged and its store starts
+//! empty.
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use super::clock::SharedClock;
+use crate::policy::Policy;
+
+const GRANTS_FILE: &str = "grants.json";
+const SESSIONS_FILE: &str = "sessions.json";
+const QUOTAS_FILE: &str = "quotas.json";
+
+/// Where each store is saved; `None` keeps it in memory only
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatePaths {
+    pub grants: Option<PathBuf>,
+    pub sessions: Option<PathBuf>,
+    /// `[network] quota_state_file` when set, else in the state dir
+    pub quotas: Option<PathBuf>,
+}
+
+impl StatePaths {
+    pub fn of(policy: &Policy) -> Self {
+        let dir = policy.state.dir.as_deref().map(Path::new);
+        let in_dir = |name: &str| dir.map(|dir| dir.join(name));
+        Self {
+            grants: in_dir(GRANTS_FILE),
+            sessions: in_dir(SESSIONS_FILE),
+            quotas: policy.network.quota_state_file.as_ref().map(PathBuf::from).or_else(|| in_dir(QUOTAS_FILE)),
+        }
+    }
+
+    /// `--reset-state`: removes every snapshot, returning those that existed
+    pub fn reset(&self) -> Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for path in [&self.grants, &self.sessions, &self.quotas].into_iter().flatten() {
+            match fs::remove_file(path) {
+                Ok(()) => removed.push(path.clone()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", path.display())),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// The snapshot at `path`, or `None` when there is none or it is unreadable
+pub fn load_json<T: DeserializeOwned>(path: &Path, what: &str) -> Option<T> {
+    let text = match fs::read(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Failed to read {} from {}; starting fresh: {}", what, path.display(), e);
+            return None;
+        }
+    };
+    serde_json::from_slice(&text)
+        .map_err(|e| warn!("Ignoring {} in {}; starting fresh: {}", what, path.display(), e))
+        .ok()
+}
+
+/// Saves `value` to `path`, creating its directory for the daemon's user
+/// only
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, serde_json::to_vec(value)?)?;
+    fs::rename(&tmp, path)
+}
+
+/// `at` as a Unix timestamp, for saving an expiry
+pub fn to_unix(at: Instant, clock: &SharedClock) -> i64 {
+    let (now, unix_now) = (clock.now(), clock.unix_now());
+    match at.checked_duration_since(now) {
+        Some(ahead) => unix_now.saturating_add(ahead.as_secs() as i64),
+        None => unix_now.saturating_sub(now.duration_since(at).as_secs() as i64),
+    }
+}
+
+/// A saved expiry as an `Instant`; `None` once it has passed
+pub fn from_unix(at: i64, clock: &SharedClock) -> Option<Instant> {
+    let ahead = at.checked_sub(clock.unix_now()).filter(|ahead| *ahead > 0)?;
+    clock.now().checked_add(Duration::from_secs(ahead as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_snapshots_round_trip_and_tolerate_corruption() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("nested/counts.json");
+        let counts = BTreeMap::from([("a".to_string(), 1u64)]);
+        save_json(&path, &counts).unwrap();
+        assert_eq!(load_json::<BTreeMap<String, u64>>(&path, "counts"), Some(counts));
+
+        fs::write(&path, "{\"a\": ").unwrap();
+        assert_eq!(load_json::<BTreeMap<String, u64>>(&path, "counts"), None);
+        assert_eq!(load_json::<BTreeMap<String, u64>>(&dir.path().join("missing.json"), "counts"), None);
+    }
+
+    #[test]
+    fn test_reset_removes_what_exists() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let paths = StatePaths {
+            grants: Some(dir.path().join(GRANTS_FILE)),
+            sessions: Some(dir.path().join(SESSIONS_FILE)),
+            quotas: None,
+        };
+        fs::write(dir.path().join(GRANTS_FILE), "[]").unwrap();
+        assert_eq!(paths.reset().unwrap(), vec![dir.path().join(GRANTS_FILE)]);
+        assert!(paths.reset().unwrap().is_empty());
+    }
+}
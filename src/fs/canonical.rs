@@ -0,0 +1,164 @@
+//! Spellings of a path that name the same file, reduced to one before
+//! request paths are matched against policy entries: the Unicode form set
+//! by `unicode_normalization`, macOS's `/tmp`, `/var` and `/etc`, which are
+//! symlinks into `/private`, and the case of names on volumes that ignore
+//! it. Entries and request paths go through the same [`PathForm`], so a
+//! policy allowing `/tmp` also allows the `/private/tmp/...` a resolved
+//! path comes back as.
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+use super::matcher::has_glob_chars;
+use super::unicode::normalize;
+use crate::policy::{FilesystemPolicy, UnicodeNormalization};
+
+/// Top-level directories macOS links into `/private`
+const PRIVATE_ALIASES: &[&str] = &["/tmp", "/var", "/etc"];
+
+/// How paths are spelled before they are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathForm {
+    pub unicode: UnicodeNormalization,
+    /// Spell [`PRIVATE_ALIASES`] as the `/private` paths they resolve to
+    pub private_aliases: bool,
+    /// Lowercase names
+    pub fold_case: bool,
+}
+
+impl PathForm {
+    /// The form for this platform and the policy's volumes: `/private`
+    /// aliasing on macOS, and case folding as `case_insensitive_fs` says or,
+    /// when unset, if any allow list entry is on a volume that ignores case
+    pub fn of(policy: &FilesystemPolicy) -> Self {
+        Self {
+            unicode: policy.unicode_normalization,
+            private_aliases: cfg!(target_os = "macos"),
+            fold_case: policy.case_insensitive_fs.unwrap_or_else(|| {
+                policy
+                    .allowed_read
+                    .iter()
+                    .chain(&policy.allowed_write)
+                    .filter(|entry| !has_glob_chars(entry))
+                    .any(|entry| ignores_case(Path::new(entry)))
+            }),
+        }
+    }
+
+    pub fn apply<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        let mut path = normalize(path, self.unicode);
+        if self.private_aliases {
+            path = then(path, private_alias);
+        }
+        if self.fold_case {
+            path = then(path, fold_case);
+        }
+        path
+    }
+}
+
+/// `path` changed by `f`, keeping the borrow when `f` changes nothing
+fn then<'a>(path: Cow<'a, str>, f: fn(&str) -> Cow<'_, str>) -> Cow<'a, str> {
+    let changed = match f(&path) {
+        Cow::Owned(changed) => Some(changed),
+        Cow::Borrowed(_) => None,
+    };
+    changed.map_or(path, Cow::Owned)
+}
+
+/// `path` under `/private` when it starts with one of [`PRIVATE_ALIASES`]
+pub fn private_alias(path: &str) -> Cow<'_, str> {
+    let aliased = PRIVATE_ALIASES.iter().any(|alias| {
+        path.strip_prefix(alias)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    if aliased {
+        Cow::Owned(format!("/private{}", path))
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+pub fn fold_case(path: &str) -> Cow<'_, str> {
+    if path.chars().any(char::is_uppercase) {
+        Cow::Owned(path.to_lowercase())
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Whether the volume `path` is on ignores case: spelled with the case of
+/// its letters swapped, it names the same file
+pub fn ignores_case(path: &Path) -> bool {
+    let Some(name) = path.to_str() else {
+        return false;
+    };
+    let swapped: String = name
+        .chars()
+        .map(|c| if c.is_ascii_lowercase() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+        .collect();
+    if swapped == name {
+        return false;
+    }
+    match (fs::metadata(path), fs::metadata(&swapped)) {
+        (Ok(original), Ok(swapped)) => same_file(&original, &swapped),
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// Both spellings exist, and a volume that keeps case apart rarely holds
+/// two files whose names differ only in case
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_private_aliases() {
+        assert_eq!(private_alias("/tmp"), "/private/tmp");
+        assert_eq!(private_alias("/tmp/openclaw/a.txt"), "/private/tmp/openclaw/a.txt");
+        assert_eq!(private_alias("/var/folders/xy/T"), "/private/var/folders/xy/T");
+        assert_eq!(private_alias("/etc/sudoers.d/*"), "/private/etc/sudoers.d/*");
+        for unchanged in ["/private/tmp/a", "/tmpfiles", "/usr/tmp", "*/tmp/*", "/Users/me"] {
+            assert!(matches!(private_alias(unchanged), Cow::Borrowed(_)), "{}", unchanged);
+        }
+    }
+
+    #[test]
+    fn test_forms_apply_in_order() {
+        let form = PathForm {
+            unicode: UnicodeNormalization::Nfc,
+            private_aliases: true,
+            fold_case: true,
+        };
+        assert_eq!(form.apply("/tmp/Cafe\u{301}/NOTES.md"), "/private/tmp/caf\u{e9}/notes.md");
+        assert_eq!(form.apply("/Users/Me/Documents"), "/users/me/documents");
+        assert!(matches!(form.apply("/private/var/log"), Cow::Borrowed(_)));
+
+        let plain = PathForm {
+            unicode: UnicodeNormalization::None,
+            private_aliases: false,
+            fold_case: false,
+        };
+        assert_eq!(plain.apply("/tmp/Cafe\u{301}"), "/tmp/Cafe\u{301}");
+        assert_eq!(fold_case("/home/ÅSA"), "/home/åsa");
+    }
+
+    #[test]
+    fn test_missing_paths_do_not_ignore_case() {
+        assert!(!ignores_case(Path::new("/nonexistent-openclaw-probe/Dir")));
+        // Nothing to swap
+        assert!(!ignores_case(Path::new("/")));
+    }
+}
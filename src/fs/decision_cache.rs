@@ -0,0 +1,192 @@
+//! Bounded LRU of path decisions (`[performance] decision_cache_size`)
+//!
+//! A decision depends on the policy and on the path as spelled after
+//! [`PathForm`](super::canonical::PathForm), never on what is on disk:
+//! symlinks are followed by the open that comes after the check, and the
+//! path it actually opened is checked again on its own. So an entry cannot
+//! go stale while its validator stands, and a policy reload builds a new
+//! validator with an empty cache. The TTL bounds how long any decision is
+//! reused regardless.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::validator::Access;
+
+type Key = (PathBuf, Access);
+
+pub struct DecisionCache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<Key, Cached>,
+    /// Keys by their last use, oldest first
+    by_use: BTreeMap<u64, Key>,
+    clock: u64,
+}
+
+struct Cached {
+    allowed: bool,
+    stored: Instant,
+    used: u64,
+}
+
+/// Counters since the cache was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+    pub capacity: u64,
+}
+
+impl DecisionCache {
+    /// Holds up to `capacity` decisions, each for at most `ttl`; a zero
+    /// `ttl` keeps them until they are evicted
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl: (!ttl.is_zero()).then_some(ttl),
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached decision for `path`, counted as a hit or a miss
+    pub fn get(&self, path: &Path, access: Access) -> Option<bool> {
+        let mut entries = self.lock();
+        let key = (path.to_path_buf(), access);
+        let found = match entries.by_key.get(&key) {
+            Some(cached) if self.ttl.is_some_and(|ttl| cached.stored.elapsed() >= ttl) => {
+                entries.remove(&key);
+                None
+            }
+            Some(_) => Some(entries.touch(&key)),
+            None => None,
+        };
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    pub fn insert(&self, path: PathBuf, access: Access, allowed: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.lock();
+        let key = (path, access);
+        entries.remove(&key);
+        while entries.by_key.len() >= self.capacity {
+            let Some((_, oldest)) = entries.by_use.pop_first() else {
+                break;
+            };
+            entries.by_key.remove(&oldest);
+        }
+        entries.clock += 1;
+        let used = entries.clock;
+        entries.by_use.insert(used, key.clone());
+        entries.by_key.insert(
+            key,
+            Cached {
+                allowed,
+                stored: Instant::now(),
+                used,
+            },
+        );
+    }
+
+    /// Drops every decision, keeping the counters
+    pub fn clear(&self) {
+        *self.lock() = Entries::default();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lock().by_key.len() as u64,
+            capacity: self.capacity as u64,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Entries {
+    /// Marks `key` as just used and returns its decision
+    fn touch(&mut self, key: &Key) -> bool {
+        self.clock += 1;
+        let clock = self.clock;
+        let cached = self.by_key.get_mut(key).expect("touched key is cached");
+        let previous = std::mem::replace(&mut cached.used, clock);
+        let allowed = cached.allowed;
+        if let Some(key) = self.by_use.remove(&previous) {
+            self.by_use.insert(clock, key);
+        }
+        allowed
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(cached) = self.by_key.remove(key) {
+            self.by_use.remove(&cached.used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hits_misses_and_eviction() {
+        let cache = DecisionCache::new(2, Duration::ZERO);
+        assert_eq!(cache.get(Path::new("/a"), Access::Read), None);
+        cache.insert("/a".into(), Access::Read, true);
+        cache.insert("/b".into(), Access::Read, false);
+        assert_eq!(cache.get(Path::new("/a"), Access::Read), Some(true));
+        // Read and write are decided apart
+        assert_eq!(cache.get(Path::new("/a"), Access::Write), None);
+
+        // /b is the least recently used, so /c evicts it
+        cache.insert("/c".into(), Access::Write, true);
+        assert_eq!(cache.get(Path::new("/b"), Access::Read), None);
+        assert_eq!(cache.get(Path::new("/a"), Access::Read), Some(true));
+        assert_eq!(cache.get(Path::new("/c"), Access::Write), Some(true));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 3,
+                entries: 2,
+                capacity: 2,
+            }
+        );
+
+        cache.clear();
+        assert_eq!(cache.get(Path::new("/a"), Access::Read), None);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().misses, 4);
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = DecisionCache::new(8, Duration::from_millis(20));
+        cache.insert("/a".into(), Access::Read, true);
+        assert_eq!(cache.get(Path::new("/a"), Access::Read), Some(true));
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get(Path::new("/a"), Access::Read), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use tokio::sync::OwnedMutexGuard;
+
+use super::verified::{self, PathCheck};
+use crate::audit::sha256;
+use crate::network::Denial;
+
+/// One search-and-replace step of an edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replacement {
+    pub old_text: String,
+    pub new_text: String,
+    /// Replace every occurrence; otherwise `old_text` must occur once
+    pub replace_all: bool,
+}
+
+/// A file as read at the start of an edit
+#[derive(Debug)]
+pub struct Current {
+    pub data: Vec<u8>,
+    pub version: String,
+    pub mtime_ns: u64,
+}
+
+impl Current {
+    /// Reads `path` through [`verified::open_verified`]
+    pub fn read(path: &Path, allowed: &PathCheck<'_>) -> io::Result<Self> {
+        let mut file = verified::open_verified(path, std::fs::OpenOptions::new().read(true), allowed)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Self {
+            version: version(&data),
+            mtime_ns: mtime_ns(&file.metadata()?),
+            data,
+        })
+    }
+
+    /// Whether the file is still the one the caller last saw. An empty
+    /// version or a zero mtime is not checked.
+    pub fn check_expected(&self, path: &str, expected_version: &str, expected_mtime_ns: u64) -> Result<(), Denial> {
+        if !expected_version.is_empty() && expected_version != self.version {
+            return Err(Denial::new(
+                "conflict",
+                format!("{} changed since version {}", path, expected_version),
+            ));
+        }
+        if expected_mtime_ns != 0 && expected_mtime_ns != self.mtime_ns {
+            return Err(Denial::new(
+                "conflict",
+                format!("{} was modified after {}", path, expected_mtime_ns),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The content once `replacements` are applied in order
+    pub fn apply(&self, path: &str, replacements: &[Replacement]) -> Result<Vec<u8>, Denial> {
+        let not_applied = |reason: String| Denial::new("edit_not_applied", reason);
+        let mut text = std::str::from_utf8(&self.data)
+            .map_err(|_| not_applied(format!("{} is not UTF-8 text", path)))?
+            .to_string();
+        for (i, replacement) in replacements.iter().enumerate() {
+            if replacement.old_text.is_empty() {
+                return Err(not_applied(format!("Replacement {} has an empty old_text", i)));
+            }
+            let found = text.matches(replacement.old_text.as_str()).count();
+            match found {
+                0 => return Err(not_applied(format!("Replacement {}: old_text not found in {}", i, path))),
+                1 => {}
+                _ if replacement.replace_all => {}
+                n => {
+                    return Err(not_applied(format!(
+                        "Replacement {}: old_text occurs {} times in {}; set replace_all",
+                        i, n, path
+                    )))
+                }
+            }
+            text = text.replace(&replacement.old_text, &replacement.new_text);
+        }
+        Ok(text.into_bytes())
+    }
+}
+
+/// Version of a file's content, as reported by `ReadFile` and `EditFile`
+pub fn version(data: &[u8]) -> String {
+    sha256::hex_digest(data)
+}
+
+/// Modification time in nanoseconds since the epoch, or 0 if unknown
+pub fn mtime_ns(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos().min(u64::MAX as u128) as u64)
+}
+
+/// Advisory per-path locks serializing `EditFile` calls.
+///
+/// Only edits take them; a concurrent `WriteFile` is caught by the version
+/// check instead when the next edit reads the file. Entries exist while a
+/// lock is held or waited for.
+#[derive(Default)]
+pub struct EditLocks {
+    held: Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl EditLocks {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+
+    /// Waits for the lock on `path`; dropping the guard releases it, on
+    /// whichever path the edit returns by
+    pub async fn lock(self: &Arc<Self>, path: &Path) -> EditLock {
+        let mutex = {
+            let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+            held.entry(path.to_path_buf()).or_default().clone()
+        };
+        EditLock {
+            guard: Some(mutex.lock_owned().await),
+            locks: Arc::clone(self),
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Paths locked or waited for
+    #[cfg(test)]
+    pub fn active(&self) -> usize {
+        self.held.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+pub struct EditLock {
+    guard: Option<OwnedMutexGuard<()>>,
+    locks: Arc<EditLocks>,
+    path: PathBuf,
+}
+
+impl Drop for EditLock {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        let mut held = self.locks.held.lock().unwrap_or_else(|e| e.into_inner());
+        // Nobody else is waiting once the map holds the only reference
+        if held.get(&self.path).is_some_and(|mutex| Arc::strong_count(mutex) == 1) {
+            held.remove(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn current(text: &str) -> Current {
+        Current {
+            data: text.as_bytes().to_vec(),
+            version: version(text.as_bytes()),
+            mtime_ns: 42,
+        }
+    }
+
+    fn replace(old_text: &str, new_text: &str, replace_all: bool) -> Replacement {
+        Replacement {
+            old_text: old_text.to_string(),
+            new_text: new_text.to_string(),
+            replace_all,
+        }
+    }
+
+    #[test]
+    fn test_replacements_apply_in_order() {
+        let file = current("port = 80\nhost = \"a\"\nport_alt = 80\n");
+        let edited = file
+            .apply("cfg", &[replace("port = 80", "port = 8080", false), replace("80", "81", true)])
+            .unwrap();
+        assert_eq!(String::from_utf8(edited).unwrap(), "port = 8181\nhost = \"a\"\nport_alt = 81\n");
+
+        for replacements in [
+            vec![replace("missing", "x", false)],
+            vec![replace("80", "81", false)],
+            vec![replace("", "x", true)],
+        ] {
+            let denial = file.apply("cfg", &replacements).unwrap_err();
+            assert_eq!(denial.violation, "edit_not_applied", "{:?}", replacements);
+        }
+        let binary = Current {
+            data: vec![0xff, 0xfe],
+            ..current("")
+        };
+        assert!(binary.apply("bin", &[replace("a", "b", false)]).is_err());
+    }
+
+    #[test]
+    fn test_expected_version_and_mtime() {
+        let file = current("v1");
+        assert!(file.check_expected("cfg", "", 0).is_ok());
+        assert!(file.check_expected("cfg", &version(b"v1"), 42).is_ok());
+        assert_eq!(file.check_expected("cfg", &version(b"v0"), 0).unwrap_err().violation, "conflict");
+        assert_eq!(file.check_expected("cfg", "", 41).unwrap_err().violation, "conflict");
+    }
+
+    #[tokio::test]
+    async fn test_locks_serialize_and_clean_up() {
+        let locks = EditLocks::new();
+        let path = Path::new("/srv/app/config.toml");
+        let first = locks.lock(path).await;
+        // A different path is not held up
+        drop(locks.lock(Path::new("/srv/app/other.toml")).await);
+
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move { drop(locks.lock(Path::new("/srv/app/config.toml")).await) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+        assert_eq!(locks.active(), 1);
+
+        drop(first);
+        tokio::time::timeout(Duration::from_secs(5), waiter).await.unwrap().unwrap();
+        assert_eq!(locks.active(), 0);
+    }
+}
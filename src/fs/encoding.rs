@@ -0,0 +1,54 @@
+//! A guess at how the bytes of a file are encoded, returned with reads so
+//! a client can tell text from binary without a second pass
+
+/// How much of the data is looked at
+const PREFIX: usize = 8192;
+
+/// Reads larger than this, and parts of files, are only guessed at when
+/// the request asks
+pub const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+
+/// `utf-8`, `utf-16le`, `utf-16be` or `binary`, from a byte order mark or
+/// the start of `data`. Empty data is `utf-8`.
+pub fn guess(data: &[u8]) -> &'static str {
+    match data {
+        [0xEF, 0xBB, 0xBF, ..] => return "utf-8",
+        [0xFF, 0xFE, ..] => return "utf-16le",
+        [0xFE, 0xFF, ..] => return "utf-16be",
+        _ => {}
+    }
+    let prefix = &data[..data.len().min(PREFIX)];
+    // Text has no NULs, and no bytes that are not UTF-8 other than a
+    // character cut short by the end of the prefix or of a part
+    let utf8 = match std::str::from_utf8(prefix) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    if utf8 && !prefix.contains(&0) {
+        "utf-8"
+    } else {
+        "binary"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_and_binary() {
+        assert_eq!(guess(b""), "utf-8");
+        assert_eq!(guess("naïve café\n".as_bytes()), "utf-8");
+        assert_eq!(guess(b"\xEF\xBB\xBFwith a BOM"), "utf-8");
+        assert_eq!(guess(b"\xFF\xFEh\0i\0"), "utf-16le");
+        assert_eq!(guess(b"\xFE\xFF\0h\0i"), "utf-16be");
+        assert_eq!(guess(b"\x7FELF\x02\x01\x01\0\0\0"), "binary");
+        assert_eq!(guess(b"latin-1 caf\xE9 au lait"), "binary");
+
+        // A character split where the prefix ends is still text
+        let mut long = "a".repeat(PREFIX - 1).into_bytes();
+        long.extend("é and more".as_bytes());
+        assert_eq!(guess(&long), "utf-8");
+        assert_eq!(guess(&"é".as_bytes()[..1]), "utf-8");
+    }
+}
@@ -0,0 +1,92 @@
+//! The files the deny list matches on disk, by device and inode, for
+//! `[filesystem] check_inodes`. A hardlink is the same file under another
+//! name, so one made inside an allowed directory would pass the path
+//! checks; its inode gives it away.
+//!
+//! The files are found when the policy loads, by expanding each deny
+//! pattern that names a place: absolute ones, and ones starting with `*/`
+//! beneath each home directory. Patterns like `*.key` name no place and
+//! are left to the path checks. Files created after the load are not in
+//! the set until the next reload, but new links to the files in it are
+//! caught.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files gathered at most; patterns matching more than this many are
+/// better served by a narrower pattern than by a scan
+pub const MAX_INODES: usize = 100_000;
+
+/// Where a pattern starting with `*/` is expanded, besides `$HOME`
+const HOME_DIRS: &[&str] = &["/root", "/home/*", "/Users/*"];
+
+/// `(device, inode)` of each denied file, with the path it was found at
+#[derive(Debug, Default)]
+pub struct DeniedInodes {
+    inodes: HashMap<(u64, u64), PathBuf>,
+}
+
+impl DeniedInodes {
+    /// Expands `patterns` on disk, keeping the regular files `denied` says
+    /// the deny list rules out, so that exceptions stay excepted
+    pub fn gather<S: AsRef<str>>(patterns: &[S], denied: impl Fn(&Path) -> bool) -> Self {
+        let mut homes: Vec<String> = HOME_DIRS
+            .iter()
+            .flat_map(|dir| expand(dir))
+            .filter(|home| home.is_dir())
+            .map(|home| home.display().to_string())
+            .collect();
+        homes.extend(std::env::var("HOME").ok().filter(|home| home.starts_with('/')));
+        homes.sort();
+        homes.dedup();
+
+        let mut set = Self::default();
+        for pattern in patterns.iter().map(AsRef::as_ref) {
+            let expanded: Vec<String> = match pattern.strip_prefix("*/") {
+                Some(rest) => homes.iter().map(|home| format!("{}/{}", home.trim_end_matches('/'), rest)).collect(),
+                None if pattern.starts_with('/') => vec![pattern.to_string()],
+                None => continue,
+            };
+            for path in expanded.iter().flat_map(|pattern| expand(pattern)) {
+                if set.inodes.len() >= MAX_INODES {
+                    return set;
+                }
+                let Some(key) = fs::metadata(&path).ok().filter(|m| m.is_file()).and_then(|m| key(&m)) else {
+                    continue;
+                };
+                if denied(&path) {
+                    set.inodes.entry(key).or_insert(path);
+                }
+            }
+        }
+        set
+    }
+
+    /// The denied file `path` is another name for, if any. Costs a `stat`.
+    pub fn matching(&self, path: &Path) -> Option<&Path> {
+        let key = key(&fs::metadata(path).ok()?)?;
+        self.inodes.get(&key).map(PathBuf::as_path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inodes.len()
+    }
+}
+
+fn expand(pattern: &str) -> Vec<PathBuf> {
+    glob::glob(pattern).map(|paths| paths.filter_map(Result::ok).collect()).unwrap_or_default()
+}
+
+/// `(device, inode)` of a file, where the platform has them
+#[cfg(unix)]
+pub fn key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Only Unix reports inodes; the parser refuses `check_inodes` elsewhere
+#[cfg(not(unix))]
+pub fn key(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
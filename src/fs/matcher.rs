@@ -0,0 +1,312 @@
+use glob::Pattern;
+use regex::{Regex, RegexSet, RegexSetBuilder};
+use std::collections::HashMap;
+
+use crate::policy::PathMatchMode;
+
+/// Byte-level trie over literal strings.
+///
+/// A lookup walks the input once and reports whether any inserted entry is a
+/// prefix of it, so the cost depends on the input length rather than on the
+/// number of entries.
+#[derive(Debug, Default)]
+struct PrefixTrie {
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: HashMap<u8, usize>,
+    terminal: bool,
+}
+
+impl PrefixTrie {
+    fn new() -> Self {
+        Self {
+            nodes: vec![TrieNode::default()],
+        }
+    }
+
+    fn insert<I: IntoIterator<Item = u8>>(&mut self, bytes: I) {
+        let mut node = 0;
+        for byte in bytes {
+            node = match self.nodes[node].children.get(&byte) {
+                Some(&next) => next,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let next = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(byte, next);
+                    next
+                }
+            };
+        }
+        self.nodes[node].terminal = true;
+    }
+
+    fn matches_prefix<I: IntoIterator<Item = u8>>(&self, bytes: I) -> bool {
+        let mut node = 0;
+        if self.nodes[node].terminal {
+            return true;
+        }
+        for byte in bytes {
+            match self.nodes[node].children.get(&byte) {
+                Some(&next) => node = next,
+                None => return false,
+            }
+            if self.nodes[node].terminal {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Like [`matches_prefix`](Self::matches_prefix), but an entry only
+    /// counts where it ends a component of `path`: `/srv/app` is a prefix
+    /// of `/srv/app/x` and not of `/srv/appx`
+    fn matches_path_prefix(&self, path: &[u8]) -> bool {
+        let mut node = 0;
+        if self.nodes[node].terminal {
+            return true;
+        }
+        for (i, byte) in path.iter().enumerate() {
+            match self.nodes[node].children.get(byte) {
+                Some(&next) => node = next,
+                None => return false,
+            }
+            let ends_component = *byte == b'/' || matches!(path.get(i + 1), None | Some(b'/'));
+            if self.nodes[node].terminal && ends_component {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn is_empty(&self) -> bool {
+        self.nodes.len() == 1 && !self.nodes[0].terminal
+    }
+}
+
+/// Compiled set of glob patterns.
+///
+/// Patterns of the shape `lit*` and `*lit` are reduced to prefix and suffix
+/// tries; everything else is compiled once and matched in order.
+#[derive(Debug, Default)]
+pub struct GlobSet {
+    prefixes: PrefixTrie,
+    suffixes: PrefixTrie,
+    patterns: Vec<Pattern>,
+}
+
+impl GlobSet {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let mut set = Self {
+            prefixes: PrefixTrie::new(),
+            suffixes: PrefixTrie::new(),
+            patterns: Vec::new(),
+        };
+
+        for pattern in patterns {
+            set.insert(pattern.as_ref());
+        }
+
+        set
+    }
+
+    fn insert(&mut self, pattern: &str) {
+        // `*` matches path separators under the default match options, so a
+        // single leading or trailing star is a plain suffix or prefix test.
+        if let Some(literal) = pattern.strip_prefix('*') {
+            if !literal.is_empty() && !has_glob_chars(literal) {
+                self.suffixes.insert(literal.bytes().rev());
+                return;
+            }
+        }
+        if let Some(literal) = pattern.strip_suffix('*') {
+            if !literal.is_empty() && !has_glob_chars(literal) {
+                self.prefixes.insert(literal.bytes());
+                return;
+            }
+        }
+
+        // Invalid patterns are skipped; policy loading rejects them, since a
+        // skipped deny pattern would fail open
+        if let Ok(compiled) = Pattern::new(pattern) {
+            self.patterns.push(compiled);
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        if !self.prefixes.is_empty() && self.prefixes.matches_prefix(path.bytes()) {
+            return true;
+        }
+        if !self.suffixes.is_empty() && self.suffixes.matches_prefix(path.bytes().rev()) {
+            return true;
+        }
+        self.patterns.iter().any(|pattern| pattern.matches(path))
+    }
+}
+
+/// Compiled form of an allow list.
+///
+/// By default every entry is treated as a literal prefix of whole path
+/// components, and entries containing glob metacharacters are additionally
+/// compiled as patterns; [`with_mode`](Self::with_mode) picks one way of
+/// matching instead.
+#[derive(Debug, Default)]
+pub struct PathIndex {
+    prefixes: PrefixTrie,
+    globs: GlobSet,
+    regexes: Option<RegexSet>,
+}
+
+impl PathIndex {
+    pub fn new<S: AsRef<str>>(entries: &[S]) -> Self {
+        Self::with_mode(entries, PathMatchMode::Hybrid, false)
+    }
+
+    /// Matches `entries` as `mode` says. Regexes cannot be lowercased like
+    /// the other entries, so `ignore_case` compiles them to ignore case
+    /// instead, for paths that have been.
+    pub fn with_mode<S: AsRef<str>>(entries: &[S], mode: PathMatchMode, ignore_case: bool) -> Self {
+        let mut prefixes = PrefixTrie::new();
+        let mut globs = Vec::new();
+        let mut regexes = Vec::new();
+
+        for entry in entries {
+            let entry = entry.as_ref();
+            match mode {
+                PathMatchMode::Hybrid => {
+                    prefixes.insert(entry.bytes());
+                    if has_glob_chars(entry) {
+                        globs.push(entry);
+                    }
+                }
+                PathMatchMode::Prefix => prefixes.insert(entry.bytes()),
+                PathMatchMode::Glob => globs.push(entry),
+                PathMatchMode::Regex => regexes.push(format!("^(?:{})", entry)),
+            }
+        }
+
+        // Invalid regexes are skipped like invalid globs; policy loading
+        // rejects both
+        let valid = regexes.into_iter().filter(|regex| Regex::new(regex).is_ok());
+        let regexes = RegexSetBuilder::new(valid).case_insensitive(ignore_case).build().ok();
+        Self {
+            prefixes,
+            globs: GlobSet::new(&globs),
+            regexes: regexes.filter(|set| !set.is_empty()),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.prefixes.matches_path_prefix(path.as_bytes())
+            || self.globs.matches(path)
+            || self.regexes.as_ref().is_some_and(|set| set.is_match(path))
+    }
+}
+
+pub fn has_glob_chars(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_index_matches_string_prefixes() {
+        let index = PathIndex::new(&["/tmp", "/home/user/documents"]);
+        assert!(index.matches("/tmp/file.txt"));
+        assert!(index.matches("/home/user/documents/a/b.txt"));
+        assert!(!index.matches("/home/user/other"));
+        assert!(!index.matches("/"));
+        // A sibling sharing the name's start is not under the entry
+        assert!(!index.matches("/tmpfile"));
+        assert!(!index.matches("/home/user/documents-private/a.txt"));
+        assert!(PathIndex::new(&["/"]).matches("/etc/passwd"));
+        assert!(PathIndex::new(&["/srv/"]).matches("/srv/a"));
+    }
+
+    #[test]
+    fn test_empty_index_matches_nothing() {
+        let index = PathIndex::new::<&str>(&[]);
+        assert!(!index.matches("/tmp"));
+    }
+
+    #[test]
+    fn test_match_modes_on_the_same_inputs() {
+        use PathMatchMode::*;
+
+        let entries = ["/srv/app", "/data/*.csv", r"/logs/[0-9]+\.log\z"];
+        // (path, hybrid, prefix, glob, regex)
+        let cases = [
+            ("/srv/app", true, true, true, true),
+            ("/srv/app/src/main.rs", true, true, false, true),
+            ("/srv/application", false, false, false, true),
+            ("/data/q1.csv", true, false, true, false),
+            ("/data/*.csv", true, true, true, false),
+            ("/data/old/q1.csv", true, false, true, false),
+            ("/logs/42.log", false, false, false, true),
+            ("/logs/42.log.1", false, false, false, false),
+            ("/tmp/srv/app", false, false, false, false),
+        ];
+        for mode in [Hybrid, Prefix, Glob, Regex] {
+            let index = PathIndex::with_mode(&entries, mode, false);
+            for (path, hybrid, prefix, glob, regex) in cases {
+                let expected = match mode {
+                    Hybrid => hybrid,
+                    Prefix => prefix,
+                    Glob => glob,
+                    Regex => regex,
+                };
+                assert_eq!(index.matches(path), expected, "{:?} {}", mode, path);
+            }
+        }
+
+        let regex = |ignore_case| PathIndex::with_mode(&["/Users/Alice"], Regex, ignore_case);
+        assert!(!regex(false).matches("/users/alice/notes"));
+        assert!(regex(true).matches("/users/alice/notes"));
+        // Skipped, as policy loading rejects it
+        assert!(!PathIndex::with_mode(&["/srv/("], Regex, false).matches("/srv/("));
+    }
+
+    #[test]
+    fn test_glob_set_shapes() {
+        let set = GlobSet::new(&["*.key", "/etc/*", "/home/*/.ssh/*"]);
+        assert!(set.matches("/tmp/secret.key"));
+        assert!(set.matches("/etc/passwd"));
+        assert!(set.matches("/home/alice/.ssh/id_rsa"));
+        assert!(!set.matches("/home/alice/notes.txt"));
+    }
+
+    #[test]
+    fn test_glob_set_agrees_with_pattern() {
+        let patterns = ["*.key", "*.pem", "/etc/*", "/home/*/.ssh/*", "*.env", "/var/?og/*"];
+        let paths = [
+            "/tmp/a.key",
+            "/tmp/a.keys",
+            "/etc",
+            "/etc/x",
+            "/home/u/.ssh/k",
+            "/home/u/ssh/k",
+            "/app/.env",
+            "/var/log/syslog",
+            "/var/blog/post",
+        ];
+        let set = GlobSet::new(&patterns);
+
+        for path in paths {
+            let expected = patterns
+                .iter()
+                .any(|p| Pattern::new(p).unwrap().matches(path));
+            assert_eq!(set.matches(path), expected, "mismatch for {}", path);
+        }
+    }
+
+    #[test]
+    fn test_glob_entries_in_allow_list() {
+        let index = PathIndex::new(&["/data/*/public"]);
+        assert!(index.matches("/data/team/public"));
+        assert!(!index.matches("/data/team/private"));
+    }
+}
@@ -1 +1,52 @@
+pub mod canonical;
+pub mod decision_cache;
+pub mod edit;
+pub mod encoding;
+pub mod inodes;
+pub mod matcher;
+pub mod protected;
+pub mod redact;
+pub mod scratch;
+pub mod search;
+pub mod self_check;
+pub mod trash;
+pub mod unicode;
+mod unicode_tables;
 pub mod validator;
+pub mod verified;
+pub mod walk;
+pub mod watch;
+pub mod writer;
+
+#[cfg(test)]
+mod properties;
+
+/// Sensitive locations merged into `denied_patterns` when
+/// `use_default_denylist` is enabled. Prefix an entry with `!` in
+/// `denied_patterns` to exempt a path from it.
+pub const DEFAULT_DENYLIST: &[&str] = &[
+    // SSH and GPG keys
+    "*/.ssh/*",
+    "*/.gnupg/*",
+    // System credential stores
+    "/etc/shadow",
+    "/etc/gshadow",
+    "/etc/sudoers",
+    "/etc/sudoers.d/*",
+    // Cloud and tooling credentials
+    "*/.aws/credentials",
+    "*/.aws/config",
+    "*/.config/gcloud/*",
+    "*/.azure/*",
+    "*/.kube/config",
+    "*/.docker/config.json",
+    "*/.netrc",
+    "*/.git-credentials",
+    "*/.npmrc",
+    // Browser cookie and password stores
+    "*/Cookies",
+    "*/Login Data",
+    "*/cookies.sqlite",
+    "*/logins.json",
+    "*/key4.db",
+];
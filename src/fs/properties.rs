@@ -0,0 +1,138 @@
+//! Randomized checks of the invariants the path validator rests on, run
+//! over paths built from the components that keep surprising us: `..`,
+//! `.`, empty ones from doubled and trailing slashes, non-ASCII names and
+//! names that share the start of an allowed one. Each property runs a
+//! fixed number of cases from a fixed seed, so a failure reproduces.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::path::{Component, Path, PathBuf};
+
+use super::validator::{Access, PathValidator};
+use crate::policy::FilesystemPolicy;
+
+const CASES: usize = 2000;
+
+const READ_ROOTS: &[&str] = &["/srv/app", "/data/shared"];
+const WRITE_ROOTS: &[&str] = &["/srv/app/out"];
+
+/// Components paths are built from
+const COMPONENTS: &[&str] = &[
+    "srv", "app", "out", "data", "shared", "apps", "app2", "etc", "passwd", "..", ".", "", "café", "naïve.txt",
+    "a b", "notes.txt", "id.key", ".ssh", "x",
+];
+
+fn validator() -> PathValidator {
+    PathValidator::new(FilesystemPolicy {
+        allowed_read: READ_ROOTS.iter().map(|r| r.to_string()).collect(),
+        allowed_write: WRITE_ROOTS.iter().map(|r| r.to_string()).collect(),
+        denied_patterns: vec!["*.key".to_string()],
+        ..Default::default()
+    })
+}
+
+/// An absolute path of up to 8 random components, some starting from an
+/// allowed root so that allowed paths come up often
+fn random_path(rng: &mut StdRng) -> String {
+    let mut path = if rng.gen_bool(0.5) {
+        READ_ROOTS.choose(rng).unwrap().to_string()
+    } else {
+        String::new()
+    };
+    for _ in 0..rng.gen_range(0..8) {
+        path.push('/');
+        path.push_str(COMPONENTS.choose(rng).unwrap());
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+/// `path` with `.` and empty components dropped and each `..` taking off
+/// the component before it, which is what a path names before symlinks
+fn normalize(path: &str) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in Path::new(path).components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+fn under_any(path: &Path, roots: &[&str]) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+fn allowed(validator: &PathValidator, path: &str, access: Access) -> bool {
+    validator.can_access(Path::new(path), access).unwrap()
+}
+
+#[test]
+fn test_paths_outside_every_root_are_denied() {
+    let (validator, mut rng) = (validator(), StdRng::seed_from_u64(1));
+    for _ in 0..CASES {
+        let path = random_path(&mut rng);
+        let normalized = normalize(&path);
+        for (access, roots) in [(Access::Read, READ_ROOTS), (Access::Write, WRITE_ROOTS)] {
+            if !under_any(&normalized, roots) {
+                assert!(!allowed(&validator, &path, access), "{:?} {} names {:?}", access, path, normalized);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parent_components_never_widen_access() {
+    let (validator, mut rng) = (validator(), StdRng::seed_from_u64(2));
+    for _ in 0..CASES {
+        let base = random_path(&mut rng);
+        let escaped = format!("{}/../{}", base, random_path(&mut rng).trim_start_matches('/'));
+        let target = normalize(&escaped).display().to_string();
+        for access in [Access::Read, Access::Write] {
+            // Decided as the path it names, not as the one it starts with
+            assert_eq!(allowed(&validator, &escaped, access), allowed(&validator, &target, access), "{}", escaped);
+        }
+    }
+}
+
+#[test]
+fn test_decisions_ignore_redundant_components() {
+    let (validator, mut rng) = (validator(), StdRng::seed_from_u64(3));
+    for _ in 0..CASES {
+        let path = random_path(&mut rng);
+        let padded: String = path
+            .split('/')
+            .map(|component| match rng.gen_range(0..4) {
+                0 => format!("{}/.", component),
+                1 => format!("{}/", component),
+                _ => component.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        for access in [Access::Read, Access::Write] {
+            let (plain, redundant) = (allowed(&validator, &path, access), allowed(&validator, &padded, access));
+            assert_eq!(plain, redundant, "{} as {}", path, padded);
+        }
+    }
+}
+
+#[test]
+fn test_deny_patterns_win_over_allows() {
+    let (validator, mut rng) = (validator(), StdRng::seed_from_u64(4));
+    for _ in 0..CASES {
+        let path = random_path(&mut rng);
+        let denied = [format!("{}/id.key", path), format!("{}/.ssh/config", path), format!("{}/.ssh/id", path)];
+        for path in denied.iter().map(|p| p.replace("//", "/")) {
+            assert!(!allowed(&validator, &path, Access::Read), "{}", path);
+            assert!(!allowed(&validator, &path, Access::Write), "{}", path);
+            assert!(validator.is_denied(Path::new(&path)), "{}", path);
+        }
+    }
+}
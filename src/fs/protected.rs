@@ -0,0 +1,207 @@
+//! The daemon's own files: the policy it loaded with the files it applies
+//! on top, the audit log and what it keeps in `[state] dir`. No policy
+//! makes them writable, so an agent allowed to write beside them cannot
+//! rewrite its rules or its record.
+
+use path_absolutize::Absolutize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use super::verified::resolved_target;
+use crate::daemon::state::StatePaths;
+use crate::network::Denial;
+use crate::policy::signature::signature_path;
+use crate::policy::Policy;
+
+/// One protected path, as it resolves on disk
+#[derive(Clone)]
+struct Protected {
+    path: PathBuf,
+    /// What it is, for the denial: "policy file", "audit log"
+    kind: &'static str,
+    /// Everything below the path is protected too
+    dir: bool,
+}
+
+/// The paths writes, deletes and restores are refused for, whatever the
+/// allow lists and rules say. The server builds the set again each time
+/// it swaps in a policy, keeping the files it opened at startup.
+#[derive(Default)]
+pub struct SelfProtection {
+    protected: Vec<Protected>,
+}
+
+impl SelfProtection {
+    /// The audit log and state of `policy`, the files it is read with on
+    /// each load, and `policy_file` with its signature when the policy was
+    /// read from one
+    pub fn of(policy: &Policy, policy_file: Option<&Path>) -> Self {
+        let mut protection = Self::default();
+        if let Some(file) = policy_file {
+            protection.add(file, "policy file", false);
+            protection.add(&signature_path(file), "policy signature", false);
+        }
+        if let Some(file) = &policy.server.policy_edits_file {
+            protection.add(Path::new(file), "policy edits file", false);
+        }
+        for file in &policy.network.blocked_ip_lists {
+            protection.add(Path::new(file), "blocked IP list", false);
+        }
+        protection.and(&Self::opened(policy))
+    }
+
+    /// The audit log and state of `policy`, which the daemon opens at
+    /// startup and writes until it exits, whatever a reload says
+    pub fn opened(policy: &Policy) -> Self {
+        let mut protection = Self::default();
+        if let Some(file) = &policy.audit.file {
+            protection.add(Path::new(file), "audit log", false);
+        }
+        if let Some(dir) = &policy.state.dir {
+            protection.add(Path::new(dir), "state dir", true);
+        }
+        let saved = StatePaths::of(policy);
+        for file in [saved.grants, saved.sessions, saved.quotas].into_iter().flatten() {
+            protection.add(&file, "state file", false);
+        }
+        protection
+    }
+
+    /// `self` with the paths of `other` as well
+    pub fn and(mut self, other: &SelfProtection) -> Self {
+        self.protected.extend(other.protected.iter().cloned());
+        self
+    }
+
+    fn add(&mut self, path: &Path, kind: &'static str, dir: bool) {
+        self.protected.push(Protected {
+            path: resolve(path),
+            kind,
+            dir,
+        });
+    }
+
+    /// The denial for writing `path`, which is resolved through any
+    /// symlinks first, so a link to a protected file is refused as well
+    pub fn check(&self, path: &Path) -> Result<(), Denial> {
+        let resolved = resolve(path);
+        match self.protecting(&resolved) {
+            Some(kind) => Err(Denial::new(
+                "self_protection",
+                format!("{} is the daemon's {} and cannot be changed", path.display(), kind),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn protecting(&self, resolved: &Path) -> Option<&'static str> {
+        let covers = |p: &Protected| resolved == p.path || (p.dir && resolved.starts_with(&p.path));
+        self.protected.iter().find(|p| covers(p)).map(|p| p.kind)
+    }
+
+    /// `(path, kind)` for each protected path; under a protected dir only
+    /// the files the daemon keeps there are listed
+    pub fn files(&self) -> impl Iterator<Item = (&Path, &'static str)> {
+        self.protected.iter().filter(|p| !p.dir).map(|p| (p.path.as_path(), p.kind))
+    }
+}
+
+/// The [`SelfProtection`] in force, shared by the server and the writers
+/// of each policy it swaps in, and replaced whole along with the policy
+#[derive(Clone, Default)]
+pub struct SharedProtection(Arc<RwLock<Arc<SelfProtection>>>);
+
+impl SharedProtection {
+    pub fn new(protection: SelfProtection) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(protection))))
+    }
+
+    pub fn replace(&self, protection: SelfProtection) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(protection);
+    }
+
+    /// [`SelfProtection::check`] against the set in force
+    pub fn check(&self, path: &Path) -> Result<(), Denial> {
+        let current = self.0.read().unwrap_or_else(|e| e.into_inner()).clone();
+        current.check(path)
+    }
+}
+
+/// `path` made absolute and resolved through symlinks, the last component
+/// included, as far as it exists
+fn resolve(path: &Path) -> PathBuf {
+    let Ok(absolute) = path.absolutize() else {
+        return path.to_path_buf();
+    };
+    fs::canonicalize(&absolute)
+        .ok()
+        .or_else(|| resolved_target(&absolute))
+        .unwrap_or_else(|| absolute.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::parser::PolicySource;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_protected_paths_are_found_through_symlinks() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let policy_file = root.join("policy.toml");
+        fs::write(&policy_file, "").unwrap();
+        let toml = format!(
+            "[filesystem]\nallowed_read = [{root:?}]\nallowed_write = [{root:?}]\ndenied_patterns = []\n\
+             [network]\nallowed_domains = []\nblocked_ips = []\nmax_connections = 10\nblocked_ip_lists = [{ips:?}]\n\
+             [process]\nallowed_commands = []\nmax_cpu_percent = 50\nmax_memory_mb = 1024\n\
+             [resources]\nmax_file_size_mb = 100\nmax_open_files = 1000\n\
+             [audit]\nfile = {audit:?}\n[state]\ndir = {state:?}\n[server]\npolicy_edits_file = {edits:?}\n",
+            root = root.display().to_string(),
+            audit = root.join("logs/audit.jsonl").display().to_string(),
+            state = root.join("state").display().to_string(),
+            edits = root.join("edits.toml").display().to_string(),
+            ips = root.join("blocked.txt").display().to_string(),
+        );
+        fs::write(root.join("blocked.txt"), "").unwrap();
+        let policy = Policy::load(&PolicySource::Inline(toml), None).unwrap();
+        let protection = SelfProtection::of(&policy, Some(&policy_file));
+
+        let violation = |path: PathBuf| protection.check(&path).err().map(|denial| denial.violation);
+        assert_eq!(violation(policy_file.clone()), Some("self_protection"));
+        assert_eq!(violation(root.join("policy.toml.sig")), Some("self_protection"));
+        // Neither exists yet
+        assert_eq!(violation(root.join("logs/audit.jsonl")), Some("self_protection"));
+        assert_eq!(violation(root.join("state/anything/at/all")), Some("self_protection"));
+        assert_eq!(violation(root.join("logs/../policy.toml")), Some("self_protection"));
+        assert_eq!(violation(root.join("edits.toml")), Some("self_protection"));
+        assert_eq!(violation(root.join("blocked.txt")), Some("self_protection"));
+        assert_eq!(violation(root.join("notes.txt")), None);
+        assert_eq!(violation(root.join("stateful")), None);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&policy_file, root.join("link.toml")).unwrap();
+            std::os::unix::fs::symlink(&root, root.join("alias")).unwrap();
+            assert_eq!(violation(root.join("link.toml")), Some("self_protection"));
+            assert_eq!(violation(root.join("alias/policy.toml")), Some("self_protection"));
+            assert_eq!(violation(root.join("alias/state/grants.json")), Some("self_protection"));
+            assert_eq!(violation(root.join("alias/notes.txt")), None);
+        }
+        let listed: Vec<&str> = protection.files().map(|(_, kind)| kind).collect();
+        assert_eq!(
+            listed,
+            [
+                "policy file",
+                "policy signature",
+                "policy edits file",
+                "blocked IP list",
+                "audit log",
+                "state file",
+                "state file",
+                "state file"
+            ]
+        );
+    }
+}
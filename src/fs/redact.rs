@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use glob::Pattern;
+use path_absolutize::Absolutize;
+use regex::Regex;
+use std::path::Path;
+
+use crate::network::Denial;
+use crate::policy::RedactionRule;
+
+struct CompiledRedaction {
+    pattern: Pattern,
+    rules: Vec<(Regex, String)>,
+}
+
+/// File contents after the matching `[[filesystem.redaction]]` rules ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redacted {
+    pub data: Vec<u8>,
+    pub replacements: u64,
+}
+
+/// Masks secrets in files covered by `[[filesystem.redaction]]`
+#[derive(Default)]
+pub struct Redactor {
+    redactions: Vec<CompiledRedaction>,
+}
+
+impl Redactor {
+    /// Fails on an invalid glob or regex, so a bad rule stops the policy
+    /// from loading
+    pub fn new(rules: &[RedactionRule]) -> Result<Self> {
+        let redactions = rules
+            .iter()
+            .map(|rule| {
+                let pattern = Pattern::new(&rule.pattern)
+                    .with_context(|| format!("Invalid redaction pattern {:?}", rule.pattern))?;
+                let rules = rule
+                    .rules
+                    .iter()
+                    .map(|r| {
+                        let regex = Regex::new(&r.regex)
+                            .with_context(|| format!("Invalid redaction regex {:?}", r.regex))?;
+                        Ok((regex, r.replacement.clone()))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(CompiledRedaction { pattern, rules })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { redactions })
+    }
+
+    /// Whether a rule covers `path`, as requested or with symlinks resolved
+    pub fn covers(&self, path: &Path) -> bool {
+        !self.redactions.is_empty() && self.matching(path).next().is_some()
+    }
+
+    /// Applies every rule covering `path` to `data`, or `None` when no
+    /// rule covers it. Binary content is refused rather than rewritten.
+    pub fn redact(&self, path: &Path, data: &[u8]) -> Result<Option<Redacted>, Denial> {
+        if !self.covers(path) {
+            return Ok(None);
+        }
+        let binary = || {
+            Denial::new(
+                "binary_not_redactable",
+                format!("{} is covered by a redaction rule but is not text", path.display()),
+            )
+        };
+        if data.contains(&0) {
+            return Err(binary());
+        }
+        let mut text = std::str::from_utf8(data).map_err(|_| binary())?.to_string();
+
+        let mut replacements = 0;
+        for redaction in self.matching(path) {
+            for (regex, replacement) in &redaction.rules {
+                let found = regex.find_iter(&text).count() as u64;
+                if found > 0 {
+                    replacements += found;
+                    text = regex.replace_all(&text, replacement.as_str()).into_owned();
+                }
+            }
+        }
+        Ok(Some(Redacted {
+            data: text.into_bytes(),
+            replacements,
+        }))
+    }
+
+    fn matching<'a>(&'a self, path: &Path) -> impl Iterator<Item = &'a CompiledRedaction> {
+        let mut forms: Vec<String> = Vec::new();
+        if let Ok(absolute) = path.absolutize() {
+            forms.push(absolute.to_string_lossy().into_owned());
+        }
+        if let Ok(canonical) = path.canonicalize() {
+            forms.push(canonical.to_string_lossy().into_owned());
+        }
+        self.redactions
+            .iter()
+            .filter(move |r| forms.iter().any(|form| r.pattern.matches(form)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::RedactionPattern;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&[RedactionRule {
+            pattern: "*.env".to_string(),
+            rules: vec![RedactionPattern {
+                regex: r"(?m)^((?:API_KEY|SECRET)\w*)=.*$".to_string(),
+                replacement: "$1=***".to_string(),
+            }],
+        }])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_masks_values_of_covered_files() {
+        let redactor = redactor();
+        let data = b"PORT=8080\nAPI_KEY=abc123\nSECRET_TOKEN=xyz\n";
+        let redacted = redactor.redact(Path::new("/srv/app/.env"), data).unwrap().unwrap();
+        assert_eq!(redacted.data, b"PORT=8080\nAPI_KEY=***\nSECRET_TOKEN=***\n");
+        assert_eq!(redacted.replacements, 2);
+        assert_eq!(redactor.redact(Path::new("/srv/app/config.toml"), data).unwrap(), None);
+    }
+
+    #[test]
+    fn test_refuses_binary_and_invalid_rules() {
+        let redactor = redactor();
+        let denial = redactor.redact(Path::new("/srv/app/.env"), b"API_KEY\0=x").unwrap_err();
+        assert_eq!(denial.violation, "binary_not_redactable");
+        assert!(redactor.redact(Path::new("/srv/app/.env"), &[0xff, 0xfe]).is_err());
+
+        let invalid = RedactionRule {
+            pattern: "*.env".to_string(),
+            rules: vec![RedactionPattern {
+                regex: "(unclosed".to_string(),
+                replacement: String::new(),
+            }],
+        };
+        assert!(Redactor::new(&[invalid]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_to_covered_files_are_covered() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("prod.env"), "SECRET=1").unwrap();
+        let link = dir.path().join("settings.txt");
+        std::os::unix::fs::symlink(dir.path().join("prod.env"), &link).unwrap();
+        assert!(redactor().covers(&link));
+    }
+}
@@ -0,0 +1,219 @@
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+use super::writer::create_dirs;
+use crate::network::Denial;
+
+/// Temp files that may exist at once, across sessions
+pub const MAX_TEMP_FILES: usize = 1024;
+/// Longest prefix or suffix `create` accepts, in bytes
+const MAX_AFFIX_LEN: usize = 64;
+
+/// A file made by [`ScratchArea::create`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TempFile {
+    pub handle_id: String,
+    pub path: PathBuf,
+}
+
+struct Owned {
+    session: String,
+    path: PathBuf,
+}
+
+#[derive(Default)]
+struct Files {
+    /// The daemon's subdirectory, resolved, once something was created in it
+    dir: Option<PathBuf>,
+    by_handle: HashMap<String, Owned>,
+}
+
+/// Temp files made by `create_temp_file` in `[filesystem] scratch_dir`.
+///
+/// Files go in `<scratch_dir>/openclaw-<random>/`, created with 0o700 on
+/// first use so other processes sharing the scratch dir cannot collide
+/// with them, and removed with everything in it when the area is dropped.
+/// Each file may be written by the session that created it until that
+/// session cleans up.
+pub struct ScratchArea {
+    dir: PathBuf,
+    files: Mutex<Files>,
+}
+
+impl ScratchArea {
+    pub fn new(scratch_dir: &Path) -> Self {
+        Self {
+            dir: scratch_dir.join(format!("openclaw-{}", random_hex(8))),
+            files: Mutex::new(Files::default()),
+        }
+    }
+
+    /// Creates `<prefix><random><suffix>` with 0o600, failing rather than
+    /// opening a file that already exists
+    pub fn create(&self, session: &str, prefix: &str, suffix: &str) -> io::Result<Result<TempFile, Denial>> {
+        if let Err(denial) = check_affix("prefix", prefix).and_then(|_| check_affix("suffix", suffix)) {
+            return Ok(Err(denial));
+        }
+
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        if files.by_handle.len() >= MAX_TEMP_FILES {
+            return Ok(Err(Denial::new(
+                "temp_file_limit_reached",
+                format!("{} temp files already exist; clean some up first", MAX_TEMP_FILES),
+            )));
+        }
+        let dir = match &files.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                create_dirs(&self.dir)?;
+                let dir = fs::canonicalize(&self.dir)?;
+                files.dir = Some(dir.clone());
+                dir
+            }
+        };
+
+        let path = dir.join(format!("{}{}{}", prefix, random_hex(8), suffix));
+        create_new(&path)?;
+        let handle_id = format!("tmp-{}", random_hex(16));
+        files.by_handle.insert(
+            handle_id.clone(),
+            Owned {
+                session: session.to_string(),
+                path: path.clone(),
+            },
+        );
+        Ok(Ok(TempFile { handle_id, path }))
+    }
+
+    /// Whether `resolved` is a temp file `session` created
+    pub fn owns(&self, session: &str, resolved: &Path) -> bool {
+        let files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files
+            .by_handle
+            .values()
+            .any(|owned| owned.session == session && owned.path == resolved)
+    }
+
+    /// Deletes the temp files `session` created and drops their grants;
+    /// returns how many there were
+    pub fn cleanup(&self, session: &str) -> usize {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        let before = files.by_handle.len();
+        files.by_handle.retain(|_, owned| {
+            if owned.session != session {
+                return true;
+            }
+            if let Err(e) = fs::remove_file(&owned.path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Failed to remove temp file {}: {}", owned.path.display(), e);
+                }
+            }
+            false
+        });
+        before - files.by_handle.len()
+    }
+}
+
+impl Drop for ScratchArea {
+    fn drop(&mut self) {
+        let files = self.files.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some(dir) = &files.dir {
+            if let Err(e) = fs::remove_dir_all(dir) {
+                warn!("Failed to remove scratch directory {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+/// A prefix or suffix must stay part of one file name
+fn check_affix(what: &str, affix: &str) -> Result<(), Denial> {
+    if affix.len() > MAX_AFFIX_LEN {
+        return Err(Denial::new(
+            "malformed_path",
+            format!("Temp file {} is longer than {} bytes", what, MAX_AFFIX_LEN),
+        ));
+    }
+    if affix.chars().any(|c| c == '/' || c == '\\' || c.is_control()) {
+        return Err(Denial::new(
+            "malformed_path",
+            format!("Temp file {} {:?} must not contain a separator or control character", what, affix),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_new(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path).map(drop)
+}
+
+#[cfg(not(unix))]
+fn create_new(path: &Path) -> io::Result<()> {
+    OpenOptions::new().write(true).create_new(true).open(path).map(drop)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len).map(|_| format!("{:02x}", rng.gen::<u8>())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_create_owns_and_cleanup() {
+        let dir = TempDir::new().unwrap();
+        let area = ScratchArea::new(dir.path());
+
+        let file = area.create("alice", "build-", ".log").unwrap().unwrap();
+        let name = file.path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("build-") && name.ends_with(".log"));
+        assert!(file.path.starts_with(fs::canonicalize(dir.path()).unwrap()));
+        assert!(file.path.is_file());
+        assert!(area.owns("alice", &file.path));
+        assert!(!area.owns("bob", &file.path));
+
+        let other = area.create("alice", "build-", ".log").unwrap().unwrap();
+        assert_ne!(other.path, file.path);
+        assert_ne!(other.handle_id, file.handle_id);
+
+        assert_eq!(area.cleanup("bob"), 0);
+        assert_eq!(area.cleanup("alice"), 2);
+        assert!(!file.path.exists());
+        assert!(!area.owns("alice", &file.path));
+    }
+
+    #[test]
+    fn test_affix_cannot_leave_the_area() {
+        let dir = TempDir::new().unwrap();
+        let area = ScratchArea::new(dir.path());
+        for (prefix, suffix) in [("../", ""), ("", "/../../x"), ("a\\b", ""), ("", "\n")] {
+            let denial = area.create("s", prefix, suffix).unwrap().unwrap_err();
+            assert_eq!(denial.violation, "malformed_path", "{:?} {:?}", prefix, suffix);
+        }
+        assert!(area.create("s", &"a".repeat(MAX_AFFIX_LEN + 1), "").unwrap().is_err());
+        // Dots alone are part of the name, not a path component
+        assert!(area.create("s", "..", "..").unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_drop_removes_the_subdirectory() {
+        let dir = TempDir::new().unwrap();
+        let area = ScratchArea::new(dir.path());
+        let file = area.create("s", "", "").unwrap().unwrap();
+        let subdir = file.path.parent().unwrap().to_path_buf();
+        fs::write(subdir.join("left-behind"), "x").unwrap();
+
+        drop(area);
+        assert!(!subdir.exists());
+        assert!(dir.path().exists());
+    }
+}
@@ -0,0 +1,386 @@
+use anyhow::{Context, Result};
+use glob::Pattern;
+use regex::{Regex, RegexBuilder};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use super::redact::Redactor;
+use super::validator::PathValidator;
+use super::verified;
+use super::walk::{self, WalkOptions};
+
+/// Upper bound on `context_lines` a client may ask for
+pub const MAX_CONTEXT_LINES: usize = 10;
+/// Matched lines longer than this are cut in snippets and context
+const MAX_SNIPPET_BYTES: usize = 512;
+/// Files with a NUL byte in this prefix are treated as binary
+const BINARY_PROBE_BYTES: usize = 8 * 1024;
+/// Compiled size limit, so a pathological pattern cannot exhaust memory
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub pattern: String,
+    /// Match `pattern` as a literal string rather than a regex
+    pub literal: bool,
+    /// Globs a file must match to be searched. A glob without `/` is
+    /// matched against the file name, otherwise against the path relative
+    /// to the root.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub context_lines: usize,
+    pub max_results: u64,
+    pub max_file_bytes: u64,
+    pub time_budget: Duration,
+    pub threads: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub path: PathBuf,
+    /// 1-based line number
+    pub line: u64,
+    /// 1-based byte column of the first match on the line
+    pub column: u64,
+    pub snippet: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchSummary {
+    pub files_searched: u64,
+    pub matches: u64,
+    /// Stopped at `max_results`
+    pub truncated: bool,
+    /// Stopped at `time_budget`
+    pub timed_out: bool,
+}
+
+/// Compiled include/exclude globs
+struct FileFilter {
+    include: Vec<(Pattern, bool)>,
+    exclude: Vec<(Pattern, bool)>,
+}
+
+impl FileFilter {
+    fn new(options: &SearchOptions) -> Result<Self> {
+        let compile = |globs: &[String]| -> Result<Vec<(Pattern, bool)>> {
+            globs
+                .iter()
+                .map(|glob| {
+                    let pattern = Pattern::new(glob).with_context(|| format!("Invalid glob {:?}", glob))?;
+                    Ok((pattern, glob.contains('/')))
+                })
+                .collect()
+        };
+        Ok(Self {
+            include: compile(&options.include)?,
+            exclude: compile(&options.exclude)?,
+        })
+    }
+
+    fn accepts(&self, relative: &Path) -> bool {
+        let name = relative.file_name().map(Path::new).unwrap_or(relative);
+        let matches = |(pattern, whole): &(Pattern, bool)| {
+            pattern.matches_path(if *whole { relative } else { name })
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Greps the tree under `root` on several threads, calling `on_match`
+/// for each hit until it returns false.
+///
+/// The tree is walked as [`walk`] describes, so files the policy denies
+/// are silently skipped and symlinked directories searched once, where
+/// they lead. Binary or oversized files are not read. Files covered by a redaction rule are searched as
+/// `read_file` would return them. The walk stops at `max_results`,
+/// `time_budget`, or when `cancel` is set.
+pub fn search(
+    root: &Path,
+    options: &SearchOptions,
+    validator: &PathValidator,
+    redactor: &Redactor,
+    cancel: &AtomicBool,
+    on_match: &(dyn Fn(SearchMatch) -> bool + Sync),
+) -> Result<SearchSummary> {
+    let pattern = if options.literal {
+        regex::escape(&options.pattern)
+    } else {
+        options.pattern.clone()
+    };
+    let regex = RegexBuilder::new(&pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .with_context(|| format!("Invalid pattern {:?}", options.pattern))?;
+    let filter = FileFilter::new(options)?;
+
+    let deadline = Instant::now() + options.time_budget;
+    let timed_out = AtomicBool::new(false);
+    let truncated = AtomicBool::new(false);
+    let files_searched = AtomicU64::new(0);
+    let matches = AtomicU64::new(0);
+    let stop = || {
+        if Instant::now() >= deadline {
+            timed_out.store(true, Ordering::Relaxed);
+        }
+        cancel.load(Ordering::Relaxed)
+            || timed_out.load(Ordering::Relaxed)
+            || truncated.load(Ordering::Relaxed)
+    };
+
+    let searcher = FileSearcher {
+        regex: &regex,
+        context_lines: options.context_lines.min(MAX_CONTEXT_LINES),
+        max_file_bytes: options.max_file_bytes,
+        redactor,
+        validator,
+    };
+    let walk_options = WalkOptions {
+        threads: options.threads,
+        max_depth: usize::MAX,
+    };
+    walk::walk(root, walk_options, validator, &stop, &|path, meta| {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        if !meta.is_file() || !(path == root || filter.accepts(relative)) {
+            return;
+        }
+        files_searched.fetch_add(1, Ordering::Relaxed);
+        for hit in searcher.search_file(path, meta.len()) {
+            if stop() {
+                break;
+            }
+            if matches.fetch_add(1, Ordering::Relaxed) >= options.max_results {
+                matches.fetch_sub(1, Ordering::Relaxed);
+                truncated.store(true, Ordering::Relaxed);
+                break;
+            }
+            if !on_match(hit) {
+                cancel.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    });
+
+    Ok(SearchSummary {
+        files_searched: files_searched.into_inner(),
+        matches: matches.into_inner(),
+        truncated: truncated.into_inner(),
+        timed_out: timed_out.into_inner(),
+    })
+}
+
+struct FileSearcher<'a> {
+    regex: &'a Regex,
+    context_lines: usize,
+    max_file_bytes: u64,
+    redactor: &'a Redactor,
+    validator: &'a PathValidator,
+}
+
+impl FileSearcher<'_> {
+    /// Unreadable, binary and oversized files yield nothing, and so does
+    /// one swapped for a path the policy denies after it was checked
+    fn search_file(&self, path: &Path, len: u64) -> Vec<SearchMatch> {
+        if len > self.max_file_bytes {
+            return Vec::new();
+        }
+        let mut content = Vec::new();
+        let can_read = |resolved: &Path| self.validator.can_read(resolved).unwrap_or(false);
+        let read = verified::open_verified(path, fs::OpenOptions::new().read(true), &can_read)
+            .and_then(|file| file.take(self.max_file_bytes).read_to_end(&mut content));
+        if read.is_err() || is_binary(&content) {
+            return Vec::new();
+        }
+        match self.redactor.redact(path, &content) {
+            Ok(Some(redacted)) => content = redacted.data,
+            Ok(None) => {}
+            Err(_) => return Vec::new(),
+        }
+
+        let text = String::from_utf8_lossy(&content);
+        let lines: Vec<&str> = text.lines().collect();
+        let mut hits = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let Some(found) = self.regex.find(line) else {
+                continue;
+            };
+            let context = |range: &[&str]| range.iter().map(|l| snippet(l)).collect();
+            hits.push(SearchMatch {
+                path: path.to_path_buf(),
+                line: i as u64 + 1,
+                column: found.start() as u64 + 1,
+                snippet: snippet(line),
+                before: context(&lines[i.saturating_sub(self.context_lines)..i]),
+                after: context(&lines[i + 1..(i + 1 + self.context_lines).min(lines.len())]),
+            });
+        }
+        hits
+    }
+}
+
+fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_PROBE_BYTES)].contains(&0)
+}
+
+fn snippet(line: &str) -> String {
+    if line.len() <= MAX_SNIPPET_BYTES {
+        return line.to_string();
+    }
+    let mut end = MAX_SNIPPET_BYTES;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    line[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::FilesystemPolicy;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    fn options(pattern: &str) -> SearchOptions {
+        SearchOptions {
+            pattern: pattern.to_string(),
+            literal: false,
+            include: vec![],
+            exclude: vec![],
+            context_lines: 0,
+            max_results: 100,
+            max_file_bytes: 1024 * 1024,
+            time_budget: Duration::from_secs(10),
+            threads: 4,
+        }
+    }
+
+    fn workspace() -> (TempDir, PathValidator) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {\n    // TODO: flags\n}\n").unwrap();
+        fs::write(root.join("src/nested/lib.rs"), "// TODO: docs\n").unwrap();
+        fs::write(root.join("notes.md"), "TODO (a.b)\n").unwrap();
+        fs::write(root.join("secret.key"), "TODO leak\n").unwrap();
+        fs::write(root.join("blob.bin"), b"TODO\0\x01").unwrap();
+
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec![root.to_string_lossy().into_owned()],
+            denied_patterns: vec!["*.key".to_string()],
+            ..Default::default()
+        });
+        (dir, validator)
+    }
+
+    fn run(root: &Path, options: &SearchOptions, validator: &PathValidator) -> (Vec<SearchMatch>, SearchSummary) {
+        let found = Mutex::new(Vec::new());
+        let summary = search(root, options, validator, &Redactor::default(), &AtomicBool::new(false), &|hit| {
+            found.lock().unwrap().push(hit);
+            true
+        })
+        .unwrap();
+        let mut found = found.into_inner().unwrap();
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+        (found, summary)
+    }
+
+    #[test]
+    fn test_skips_denied_and_binary_files() {
+        let (dir, validator) = workspace();
+        let (found, summary) = run(dir.path(), &options("TODO"), &validator);
+
+        let names: Vec<_> = found
+            .iter()
+            .map(|m| m.path.strip_prefix(dir.path()).unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["notes.md", "src/main.rs", "src/nested/lib.rs"]);
+        assert_eq!(found[1].line, 2);
+        assert_eq!(found[1].column, 8);
+        assert!(!summary.truncated && !summary.timed_out);
+    }
+
+    #[test]
+    fn test_literal_globs_and_context() {
+        let (dir, validator) = workspace();
+        let mut literal = options("(a.b)");
+        literal.literal = true;
+        let (found, _) = run(dir.path(), &literal, &validator);
+        assert_eq!(found.len(), 1);
+
+        let mut rust_only = options("TODO");
+        rust_only.include = vec!["*.rs".to_string()];
+        rust_only.exclude = vec!["src/nested/*".to_string()];
+        rust_only.context_lines = 1;
+        let (found, _) = run(dir.path(), &rust_only, &validator);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].before, vec!["fn main() {"]);
+        assert_eq!(found[0].after, vec!["}"]);
+    }
+
+    #[test]
+    fn test_result_cap_and_invalid_pattern() {
+        let (dir, validator) = workspace();
+        let mut capped = options("TODO");
+        capped.max_results = 2;
+        let (found, summary) = run(dir.path(), &capped, &validator);
+        assert_eq!(found.len(), 2);
+        assert_eq!(summary.matches, 2);
+        assert!(summary.truncated);
+
+        let cancel = AtomicBool::new(false);
+        assert!(search(dir.path(), &options("("), &validator, &Redactor::default(), &cancel, &|_| true).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_are_not_followed() {
+        let (dir, validator) = workspace();
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("other.txt"), "TODO outside\n").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("link")).unwrap();
+
+        let (found, _) = run(dir.path(), &options("outside"), &validator);
+        assert!(found.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_directories_are_searched_once() {
+        let (dir, validator) = workspace();
+        std::os::unix::fs::symlink(dir.path().join("src"), dir.path().join("src/nested/loop")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("root")).unwrap();
+
+        let (found, summary) = run(dir.path(), &options("TODO"), &validator);
+        assert_eq!(found.len(), 3);
+        assert_eq!(summary.files_searched, 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_swapped_after_check_is_skipped() {
+        let (dir, validator) = workspace();
+        let path = dir.path().join("notes.md");
+        assert!(validator.can_read(&path).unwrap());
+        let regex = Regex::new("TODO").unwrap();
+        let redactor = Redactor::default();
+        let searcher = FileSearcher {
+            regex: &regex,
+            context_lines: 0,
+            max_file_bytes: 1024,
+            redactor: &redactor,
+            validator: &validator,
+        };
+
+        // Between the walk's check and the read, the file becomes a link
+        // to a denied one
+        fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret.key"), &path).unwrap();
+        assert!(searcher.search_file(&path, 10).is_empty());
+    }
+}
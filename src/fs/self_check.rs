@@ -0,0 +1,300 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::matcher::{has_glob_chars, PathIndex};
+use super::validator::Access;
+use crate::policy::{FilesystemPolicy, PathMatchMode};
+
+/// Upper bound on paths visited when counting glob matches
+pub const MAX_GLOB_SCAN: usize = 10_000;
+
+/// What the daemon found for one allow entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryReport {
+    pub access: Access,
+    pub entry: String,
+    pub exists: bool,
+    /// Target after resolving symlinks, when it differs from the entry
+    pub resolved: Option<PathBuf>,
+    /// Whether the daemon's own user can read (or write) the path
+    pub accessible: bool,
+    /// A symlink whose target is not covered by the same allow list
+    pub escapes: bool,
+    /// For glob entries: existing paths matched, up to [`MAX_GLOB_SCAN`]
+    pub glob_matches: Option<usize>,
+}
+
+impl EntryReport {
+    pub fn is_glob(&self) -> bool {
+        self.glob_matches.is_some()
+    }
+
+    pub fn problem(&self) -> Option<String> {
+        if let Some(matches) = self.glob_matches {
+            return (matches == 0).then(|| "glob matches no existing paths".to_string());
+        }
+        if !self.exists {
+            return Some("path does not exist".to_string());
+        }
+        if self.escapes {
+            let target = self.resolved.as_deref().unwrap_or(Path::new(""));
+            return Some(format!(
+                "symlink resolves to {} outside {}",
+                target.display(),
+                self.access.list_name()
+            ));
+        }
+        if !self.accessible {
+            let verb = match self.access {
+                Access::Read => "read",
+                Access::Write => "write",
+            };
+            return Some(format!("daemon user cannot {} this path", verb));
+        }
+        None
+    }
+
+    fn status(&self) -> &'static str {
+        match self.problem() {
+            None => "ok",
+            Some(_) if self.is_glob() => "no matches",
+            Some(_) if !self.exists => "missing",
+            Some(_) if self.escapes => "escapes",
+            Some(_) => "no access",
+        }
+    }
+
+    fn detail(&self) -> String {
+        if let Some(problem) = self.problem() {
+            return problem;
+        }
+        match (self.glob_matches, &self.resolved) {
+            (Some(n), _) if n >= MAX_GLOB_SCAN => format!("{}+ matches", MAX_GLOB_SCAN),
+            (Some(n), _) => format!("{} matches", n),
+            (None, Some(resolved)) => format!("-> {}", resolved.display()),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+/// Checks that the allow lists of a policy point at usable paths.
+///
+/// Run by `--self-check` and at server start, where problems are logged as
+/// warnings; the result is also reported by `get_status`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfCheckReport {
+    pub entries: Vec<EntryReport>,
+    pub checked_at: i64,
+}
+
+impl SelfCheckReport {
+    pub fn run(policy: &FilesystemPolicy) -> Self {
+        let mode = policy.path_match_mode;
+        // A regex names no path to look at
+        let (allowed_read, allowed_write) = match mode {
+            PathMatchMode::Regex => (&[][..], &[][..]),
+            _ => (&policy.allowed_read[..], &policy.allowed_write[..]),
+        };
+        let read_index = PathIndex::with_mode(allowed_read, mode, false);
+        let write_index = PathIndex::with_mode(allowed_write, mode, false);
+
+        let reads = allowed_read
+            .iter()
+            .map(|entry| check_entry(entry, Access::Read, mode, &read_index));
+        let writes = allowed_write
+            .iter()
+            .map(|entry| check_entry(entry, Access::Write, mode, &write_index));
+
+        Self {
+            entries: reads.chain(writes).collect(),
+            checked_at: crate::audit::logger::unix_now(),
+        }
+    }
+
+    pub fn problems(&self) -> impl Iterator<Item = (&EntryReport, String)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.problem().map(|problem| (entry, problem)))
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.problems().next().is_none()
+    }
+
+    pub fn render_table(&self) -> String {
+        let headers = ["LIST", "ENTRY", "STATUS", "DETAIL"];
+        let rows: Vec<[String; 4]> = self
+            .entries
+            .iter()
+            .map(|e| {
+                [
+                    e.access.list_name().to_string(),
+                    e.entry.clone(),
+                    e.status().to_string(),
+                    e.detail(),
+                ]
+            })
+            .collect();
+
+        let mut widths = headers.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let mut out = String::new();
+        let mut line = |cells: [&str; 4]| {
+            let [list, entry, status, detail] = cells;
+            let _ = writeln!(
+                out,
+                "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+                list,
+                entry,
+                status,
+                detail,
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            );
+        };
+        line(headers);
+        for row in &rows {
+            line([&row[0], &row[1], &row[2], &row[3]]);
+        }
+        out.lines().map(str::trim_end).collect::<Vec<_>>().join("\n") + "\n"
+    }
+}
+
+fn check_entry(entry: &str, access: Access, mode: PathMatchMode, index: &PathIndex) -> EntryReport {
+    let mut report = EntryReport {
+        access,
+        entry: entry.to_string(),
+        exists: false,
+        resolved: None,
+        accessible: false,
+        escapes: false,
+        glob_matches: None,
+    };
+
+    if mode != PathMatchMode::Prefix && has_glob_chars(entry) {
+        let matches = glob::glob(entry)
+            .map(|paths| paths.filter_map(Result::ok).take(MAX_GLOB_SCAN).count())
+            .unwrap_or(0);
+        report.exists = matches > 0;
+        report.accessible = report.exists;
+        report.glob_matches = Some(matches);
+        return report;
+    }
+
+    let path = Path::new(entry);
+    let Ok(canonical) = fs::canonicalize(path) else {
+        return report;
+    };
+    report.exists = true;
+    report.accessible = can_access(&canonical, access);
+    if canonical != path {
+        report.escapes = !index.matches(&canonical.to_string_lossy());
+        report.resolved = Some(canonical);
+    }
+    report
+}
+
+fn can_access(path: &Path, access: Access) -> bool {
+    match access {
+        Access::Read if path.is_dir() => fs::read_dir(path).is_ok(),
+        Access::Read => fs::File::open(path).is_ok(),
+        Access::Write => can_write(path),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn can_write(path: &Path) -> bool {
+    nix::unistd::access(path, nix::unistd::AccessFlags::W_OK).is_ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn can_write(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| !m.permissions().readonly())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn policy(read: Vec<String>, write: Vec<String>) -> FilesystemPolicy {
+        FilesystemPolicy {
+            allowed_read: read,
+            allowed_write: write,
+            ..Default::default()
+        }
+    }
+
+    fn path_str(path: &Path) -> String {
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_reports_missing_and_existing_entries() {
+        let dir = TempDir::new().unwrap();
+        let workspace = dir.path().canonicalize().unwrap();
+        let missing = workspace.join("typo");
+
+        let report = SelfCheckReport::run(&policy(
+            vec![path_str(&workspace), path_str(&missing)],
+            vec![path_str(&workspace)],
+        ));
+
+        let problems: Vec<_> = report.problems().map(|(e, _)| e.entry.clone()).collect();
+        assert_eq!(problems, vec![path_str(&missing)]);
+        assert!(!report.is_ok());
+
+        let table = report.render_table();
+        assert!(table.starts_with("LIST"));
+        assert!(table.contains("missing"));
+    }
+
+    #[test]
+    fn test_symlink_outside_allow_set_is_flagged() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("outside")).unwrap();
+        fs::create_dir(root.join("allowed")).unwrap();
+        std::os::unix::fs::symlink(root.join("outside"), root.join("allowed/link")).unwrap();
+        std::os::unix::fs::symlink(root.join("allowed"), root.join("alias")).unwrap();
+
+        let report = SelfCheckReport::run(&policy(
+            vec![path_str(&root.join("allowed")), path_str(&root.join("allowed/link"))],
+            vec![],
+        ));
+        assert!(!report.entries[0].escapes);
+        assert!(report.entries[1].escapes);
+        assert_eq!(report.entries[1].resolved, Some(root.join("outside")));
+
+        // A link into the same allow set is fine
+        let report = SelfCheckReport::run(&policy(
+            vec![path_str(&root.join("allowed")), path_str(&root.join("alias"))],
+            vec![],
+        ));
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_glob_entries_count_matches() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::write(root.join("a.txt"), "").unwrap();
+        fs::write(root.join("b.txt"), "").unwrap();
+
+        let report = SelfCheckReport::run(&policy(
+            vec![format!("{}/*.txt", root.display()), format!("{}/*.md", root.display())],
+            vec![],
+        ));
+        assert_eq!(report.entries[0].glob_matches, Some(2));
+        assert!(report.entries[0].problem().is_none());
+        assert_eq!(report.entries[1].glob_matches, Some(0));
+        assert!(report.entries[1].problem().is_some());
+    }
+}
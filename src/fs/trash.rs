@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::verified;
+use super::writer::{create_dirs, DIR_MODE};
+use crate::audit::logger::unix_now;
+
+/// Name of the moved file inside its trash slot
+const DATA_FILE: &str = "data";
+/// Sidecar recording where the file came from
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// The sidecar manifest of a trashed file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub id: String,
+    pub original_path: PathBuf,
+    pub deleted_at: i64,
+}
+
+/// Holds files removed by `delete_file` so they can be restored.
+///
+/// Each deletion gets its own slot `<trash_dir>/<deleted_at>-<n>/` holding
+/// the file as `data` next to a `manifest.json` with the original path.
+pub struct Trash {
+    dir: PathBuf,
+    retention_secs: Option<i64>,
+}
+
+impl Trash {
+    /// `retention_days` of 0 keeps trashed files until restored
+    pub fn new(dir: PathBuf, retention_days: u64) -> Self {
+        Self {
+            dir,
+            retention_secs: (retention_days > 0).then_some(retention_days as i64 * 86_400),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Moves `path` into a fresh slot and returns its manifest. A symlink
+    /// is moved as the link, never as what it points to.
+    pub fn move_to_trash(&self, path: &Path) -> io::Result<TrashItem> {
+        create_dirs(&self.dir)?;
+        let deleted_at = unix_now();
+        let (id, slot) = self.create_slot(deleted_at)?;
+
+        let item = TrashItem {
+            id,
+            original_path: path.to_path_buf(),
+            deleted_at,
+        };
+        let result = write_manifest(&slot, &item).and_then(|_| move_file(path, &slot.join(DATA_FILE)));
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&slot);
+            return Err(e);
+        }
+        Ok(item)
+    }
+
+    pub fn lookup(&self, id: &str) -> io::Result<TrashItem> {
+        let slot = self.slot_path(id)?;
+        let manifest = fs::read(slot.join(MANIFEST_FILE))?;
+        serde_json::from_slice(&manifest).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Moves a trashed file back to `to`, its original path as it resolves
+    /// now. The caller is responsible for checking that `to` is still
+    /// writable by policy.
+    pub fn restore(&self, item: &TrashItem, to: &Path) -> io::Result<()> {
+        let slot = self.slot_path(&item.id)?;
+        // A dangling symlink is taken too
+        if fs::symlink_metadata(to).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        if let Some(parent) = to.parent() {
+            create_dirs(parent)?;
+        }
+        move_file(&slot.join(DATA_FILE), to)?;
+        fs::remove_dir_all(slot)
+    }
+
+    /// Deletes slots older than the retention period, returning how many
+    pub fn purge_expired(&self, now: i64) -> io::Result<usize> {
+        let Some(retention) = self.retention_secs else {
+            return Ok(0);
+        };
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let mut purged = 0;
+        for entry in entries.flatten() {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            // Slots whose manifest is unreadable are dated by their name
+            let deleted_at = match self.lookup(&id) {
+                Ok(item) => item.deleted_at,
+                Err(_) => match id.split('-').next().and_then(|t| t.parse().ok()) {
+                    Some(t) => t,
+                    None => continue,
+                },
+            };
+            if now - deleted_at >= retention {
+                fs::remove_dir_all(entry.path())?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Creates an unused slot directory; `create_dir` failing on an existing
+    /// name is what makes concurrent deletions collision-safe
+    fn create_slot(&self, deleted_at: i64) -> io::Result<(String, PathBuf)> {
+        for n in 0.. {
+            let id = format!("{}-{}", deleted_at, n);
+            let slot = self.dir.join(&id);
+            match fs::create_dir(&slot) {
+                Ok(()) => {
+                    set_dir_mode(&slot)?;
+                    return Ok((id, slot));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        unreachable!()
+    }
+
+    fn slot_path(&self, id: &str) -> io::Result<PathBuf> {
+        let valid = !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit() || b == b'-');
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid trash id: {:?}", id),
+            ));
+        }
+        Ok(self.dir.join(id))
+    }
+}
+
+fn write_manifest(slot: &Path, item: &TrashItem) -> io::Result<()> {
+    let manifest = serde_json::to_vec_pretty(item).map_err(io::Error::other)?;
+    fs::write(slot.join(MANIFEST_FILE), manifest)
+}
+
+/// Renames `from` to `to`, copying and removing when they are on
+/// different filesystems
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_and_remove(from, to),
+        result => result,
+    }
+}
+
+/// The copy half of [`move_file`]. A symlink is recreated rather than
+/// followed, and a file is opened without following one, so no content
+/// the link points to is copied when `from` is swapped in between.
+fn copy_and_remove(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        copy_symlink(from, to)?;
+    } else {
+        let mut options = fs::OpenOptions::new();
+        verified::no_follow(options.read(true));
+        let mut source = options.open(from)?;
+        let mut copy = fs::OpenOptions::new().write(true).create_new(true).open(to)?;
+        io::copy(&mut source, &mut copy)?;
+        copy.set_permissions(source.metadata()?.permissions())?;
+    }
+    fs::remove_file(from)
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is a symlink and cannot be moved across filesystems", from.display()),
+    ))
+}
+
+#[cfg(unix)]
+fn set_dir_mode(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(dir, fs::Permissions::from_mode(DIR_MODE))
+}
+
+#[cfg(not(unix))]
+fn set_dir_mode(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_trash_and_restore_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let trash = Trash::new(dir.path().join("trash"), 30);
+        let file = dir.path().join("work/report.txt");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, "draft").unwrap();
+
+        let item = trash.move_to_trash(&file).unwrap();
+        assert!(!file.exists());
+        assert_eq!(trash.lookup(&item.id).unwrap(), item);
+
+        trash.restore(&item, &file).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "draft");
+        assert!(trash.lookup(&item.id).is_err());
+    }
+
+    #[test]
+    fn test_same_second_deletions_get_distinct_slots() {
+        let dir = TempDir::new().unwrap();
+        let trash = Trash::new(dir.path().join("trash"), 30);
+        let file = dir.path().join("a.txt");
+
+        fs::write(&file, "one").unwrap();
+        let first = trash.move_to_trash(&file).unwrap();
+        fs::write(&file, "two").unwrap();
+        let second = trash.move_to_trash(&file).unwrap();
+        assert_ne!(first.id, second.id);
+
+        // The original location is taken again, so the restore must not clobber it
+        fs::write(&file, "three").unwrap();
+        let err = trash.restore(&first, &file).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "three");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copies_move_symlinks_as_links() {
+        let dir = TempDir::new().unwrap();
+        let (secret, link) = (dir.path().join("secret.txt"), dir.path().join("link.txt"));
+        fs::write(&secret, "secret").unwrap();
+        std::os::unix::fs::symlink(&secret, &link).unwrap();
+
+        copy_and_remove(&link, &dir.path().join("moved.txt")).unwrap();
+        assert_eq!(fs::read_link(dir.path().join("moved.txt")).unwrap(), secret);
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert_eq!(fs::read_to_string(&secret).unwrap(), "secret");
+
+        copy_and_remove(&secret, &dir.path().join("copy.txt")).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("copy.txt")).unwrap(), "secret");
+        assert!(!secret.exists());
+    }
+
+    #[test]
+    fn test_purge_respects_retention() {
+        let dir = TempDir::new().unwrap();
+        let trash = Trash::new(dir.path().join("trash"), 1);
+        let file = dir.path().join("old.txt");
+        fs::write(&file, "").unwrap();
+        let item = trash.move_to_trash(&file).unwrap();
+
+        assert_eq!(trash.purge_expired(item.deleted_at + 60).unwrap(), 0);
+        assert_eq!(trash.purge_expired(item.deleted_at + 86_400).unwrap(), 1);
+        assert!(trash.lookup(&item.id).is_err());
+
+        let keep_forever = Trash::new(dir.path().join("trash"), 0);
+        assert_eq!(keep_forever.purge_expired(i64::MAX).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_ids_cannot_escape_the_trash_dir() {
+        let dir = TempDir::new().unwrap();
+        let trash = Trash::new(dir.path().join("trash"), 30);
+        let err = trash.lookup("../../etc").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,174 @@
+//! Canonical Unicode normalization (NFC and NFD) for path matching, per
+//! UAX #15. Compatibility forms are not needed: they fold characters that
+//! a filesystem keeps distinct.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use super::unicode_tables::{COMBINING_CLASSES, COMPOSITIONS, DECOMPOSITIONS};
+use crate::policy::UnicodeNormalization;
+
+// Hangul syllables are composed arithmetically rather than from tables
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+/// `s` in `form`; ASCII, and everything under `none`, is returned as is
+pub fn normalize(s: &str, form: UnicodeNormalization) -> Cow<'_, str> {
+    if s.is_ascii() {
+        return Cow::Borrowed(s);
+    }
+    match form {
+        UnicodeNormalization::None => Cow::Borrowed(s),
+        UnicodeNormalization::Nfd => Cow::Owned(nfd(s)),
+        UnicodeNormalization::Nfc => Cow::Owned(nfc(s)),
+    }
+}
+
+pub fn nfd(s: &str) -> String {
+    decomposed(s).into_iter().collect()
+}
+
+pub fn nfc(s: &str) -> String {
+    let mut out: Vec<char> = Vec::with_capacity(s.len());
+    // Index in `out` of the starter marks may still compose with, and the
+    // class of the last character kept after it
+    let mut starter: Option<usize> = None;
+    let mut last_class: Option<u8> = None;
+    for c in decomposed(s) {
+        let class = combining_class(c);
+        if let Some(index) = starter {
+            let blocked = last_class.is_some_and(|last| last == 0 || last >= class);
+            if !blocked {
+                if let Some(composite) = compose(out[index], c) {
+                    out[index] = composite;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(out.len());
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+        out.push(c);
+    }
+    out.into_iter().collect()
+}
+
+/// Full canonical decomposition in canonical order
+fn decomposed(s: &str) -> Vec<char> {
+    let mut out = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        decompose(c, &mut out);
+    }
+    // Each run of non-starters is put in combining class order, keeping
+    // the order of marks that share a class
+    let mut i = 0;
+    while i < out.len() {
+        if combining_class(out[i]) == 0 {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < out.len() && combining_class(out[i]) != 0 {
+            i += 1;
+        }
+        out[start..i].sort_by_key(|&c| combining_class(c));
+    }
+    out
+}
+
+fn decompose(c: char, out: &mut Vec<char>) {
+    let code = c as u32;
+    if (S_BASE..S_BASE + S_COUNT).contains(&code) {
+        let index = code - S_BASE;
+        let jamo = [
+            L_BASE + index / N_COUNT,
+            V_BASE + (index % N_COUNT) / T_COUNT,
+            T_BASE + index % T_COUNT,
+        ];
+        let len = if jamo[2] == T_BASE { 2 } else { 3 };
+        out.extend(jamo[..len].iter().filter_map(|&j| char::from_u32(j)));
+        return;
+    }
+    match DECOMPOSITIONS.binary_search_by_key(&c, |&(from, _)| from) {
+        Ok(i) => out.extend(DECOMPOSITIONS[i].1.chars()),
+        Err(_) => out.push(c),
+    }
+}
+
+fn compose(a: char, b: char) -> Option<char> {
+    let (first, second) = (a as u32, b as u32);
+    if (L_BASE..L_BASE + L_COUNT).contains(&first) && (V_BASE..V_BASE + V_COUNT).contains(&second) {
+        let syllable = S_BASE + ((first - L_BASE) * V_COUNT + (second - V_BASE)) * T_COUNT;
+        return char::from_u32(syllable);
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&first)
+        && (first - S_BASE).is_multiple_of(T_COUNT)
+        && (T_BASE + 1..T_BASE + T_COUNT).contains(&second)
+    {
+        return char::from_u32(first + second - T_BASE);
+    }
+    COMPOSITIONS
+        .binary_search_by_key(&(a, b), |&(pair, _)| pair)
+        .ok()
+        .map(|i| COMPOSITIONS[i].1)
+}
+
+fn combining_class(c: char) -> u8 {
+    COMBINING_CLASSES
+        .binary_search_by(|&(low, high, _)| {
+            if high < c {
+                Ordering::Less
+            } else if low > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .map_or(0, |i| COMBINING_CLASSES[i].2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_composed_and_decomposed_forms() {
+        let cases = [
+            // (composed, decomposed)
+            ("caf\u{e9}", "cafe\u{301}"),
+            ("\u{1e0d}\u{307}", "d\u{323}\u{307}"),
+            ("\u{d55c}\u{ae00}", "\u{1112}\u{1161}\u{11ab}\u{1100}\u{1173}\u{11af}"),
+            ("\u{1f80}", "\u{3b1}\u{313}\u{345}"),
+        ];
+        for (composed, decomposed) in cases {
+            assert_eq!(nfd(composed), decomposed);
+            assert_eq!(nfc(decomposed), composed);
+            assert_eq!(nfc(composed), composed);
+            assert_eq!(nfd(decomposed), decomposed);
+        }
+    }
+
+    #[test]
+    fn test_reordering_and_exclusions() {
+        // Marks are put in class order before composing
+        assert_eq!(nfc("d\u{307}\u{323}"), "\u{1e0d}\u{307}");
+        assert_eq!(nfd("\u{1e0b}\u{323}"), "d\u{323}\u{307}");
+        // Excluded composites stay decomposed; singletons map away
+        assert_eq!(nfc("\u{958}"), "\u{915}\u{93c}");
+        assert_eq!(nfc("\u{212b}"), "\u{c5}");
+        // A mark blocked by one of the same class is left alone
+        assert_eq!(nfc("a\u{301}\u{301}"), "\u{e1}\u{301}");
+        assert_eq!(normalize("/srv/plain.txt", UnicodeNormalization::Nfc), "/srv/plain.txt");
+        assert_eq!(normalize("cafe\u{301}", UnicodeNormalization::None), "cafe\u{301}");
+    }
+}
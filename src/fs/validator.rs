@@ -1,90 +1,667 @@
 use anyhow::Result;
 use path_absolutize::Absolutize;
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
-use glob::Pattern;
+use std::time::Duration;
+use tracing::{debug, trace, Level};
 
-use crate::policy::FilesystemPolicy;
+use super::canonical::PathForm;
+use super::decision_cache::{CacheStats, DecisionCache};
+use super::inodes::DeniedInodes;
+use super::matcher::{GlobSet, PathIndex};
+use super::DEFAULT_DENYLIST;
+use crate::network::Denial;
+use crate::policy::rules::{self, Candidate, Decision, Effect, Specificity};
+use crate::policy::{
+    EmptyAllowMeans, EnforcementPolicy, FilesystemPolicy, MatchStrategy, PathMatchMode, PathRule, RuleAccess,
+    RuleAction,
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl Access {
+    pub fn list_name(self) -> &'static str {
+        match self {
+            Access::Read => "allowed_read",
+            Access::Write => "allowed_write",
+        }
+    }
+}
+
+/// A `[[filesystem.rules]]` entry with its path compiled
+struct CompiledRule {
+    path: String,
+    matcher: PathIndex,
+    action: RuleAction,
+    access: RuleAccess,
+}
+
+impl CompiledRule {
+    fn new(rule: &PathRule, form: PathForm) -> Self {
+        let path = form.apply(&rule.path).into_owned();
+        Self {
+            matcher: PathIndex::new(&[&path]),
+            path,
+            action: rule.action,
+            access: rule.access,
+        }
+    }
+
+    fn candidate(&self) -> Candidate {
+        let effect = match self.action {
+            RuleAction::Allow => Effect::Allow,
+            RuleAction::Deny => Effect::Deny,
+        };
+        Candidate::new("filesystem.rules", self.path.as_str(), effect, Specificity::path(&self.path))
+    }
+
+    fn applies(&self, path: &str, access: Access) -> bool {
+        let access_matches = matches!(
+            (self.access, access),
+            (RuleAccess::Any, _)
+                | (RuleAccess::Read, Access::Read)
+                | (RuleAccess::Write, Access::Write)
+        );
+        access_matches && self.matcher.matches(path)
+    }
+}
+
+/// Validates paths against the filesystem policy.
+///
+/// The allow and deny lists are compiled into matcher indexes once at
+/// construction, so a decision costs roughly the length of the path rather
+/// than the number of rules. Ordered rules are inherently evaluated in
+/// sequence.
+///
+/// Decisions follow [`RuleSet`](crate::policy::rules::RuleSet)'s
+/// precedence: `denied_patterns`, the trash and state dirs, hardlinks to
+/// denied files under `check_inodes` and, for writes,
+/// `denied_write_extensions` deny outright; among `rules`,
+/// `match_strategy` picks the one that counts, which then ranks as an
+/// explicit deny or an allow beside the allow list entries.
+/// [`explain`](Self::explain) lists the entries behind a decision.
 pub struct PathValidator {
-    policy: FilesystemPolicy,
+    rules: Vec<CompiledRule>,
+    match_strategy: MatchStrategy,
+    path_match_mode: PathMatchMode,
+    allowed_read: PathIndex,
+    allowed_write: PathIndex,
+    allowed_stat: PathIndex,
+    empty_allow_means: EmptyAllowMeans,
+    denied_patterns: GlobSet,
+    deny_exceptions: GlobSet,
+    allow_relative_paths: bool,
+    require_exists: bool,
+    denied_write_extensions: Vec<String>,
+    max_path_length: usize,
+    max_path_components: usize,
+    trash_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    /// With `check_inodes`
+    denied_inodes: Option<DeniedInodes>,
+    form: PathForm,
+    cache: Option<DecisionCache>,
+    /// The entries behind the indexes, for the `trace` decision log
+    entries: ListEntries,
+}
+
+/// Each list as spelled after [`PathForm`], the default denylist included
+#[derive(Default)]
+struct ListEntries {
+    denied: Vec<String>,
+    /// How many of `denied` are from the default denylist, which come first
+    from_default: usize,
+    exceptions: Vec<String>,
+    allowed_read: Vec<String>,
+    allowed_write: Vec<String>,
 }
 
 impl PathValidator {
     pub fn new(policy: FilesystemPolicy) -> Self {
-        Self { policy }
+        let form = PathForm::of(&policy);
+        let bounds = EnforcementPolicy::default();
+        let mut denied = Vec::new();
+        let mut exceptions = Vec::new();
+
+        if policy.use_default_denylist {
+            denied.extend(DEFAULT_DENYLIST.iter().map(|p| form.apply(p)));
+        }
+        for pattern in &policy.denied_patterns {
+            match pattern.strip_prefix('!') {
+                Some(negated) => exceptions.push(form.apply(negated)),
+                None => denied.push(form.apply(pattern)),
+            }
+        }
+        // Lowercasing a regex would change what it means, `\S` to `\s`, so
+        // regex entries are compiled to ignore case instead
+        let allow_form = PathForm {
+            fold_case: form.fold_case && policy.path_match_mode != PathMatchMode::Regex,
+            ..form
+        };
+        let normalized = |entries: &[String]| -> Vec<String> {
+            entries.iter().map(|e| allow_form.apply(e).into_owned()).collect()
+        };
+        let index = |entries: &[String]| PathIndex::with_mode(entries, policy.path_match_mode, form.fold_case);
+        let entries = ListEntries {
+            denied: denied.iter().map(|p| p.to_string()).collect(),
+            from_default: if policy.use_default_denylist { DEFAULT_DENYLIST.len() } else { 0 },
+            exceptions: exceptions.iter().map(|p| p.to_string()).collect(),
+            allowed_read: normalized(&policy.allowed_read),
+            allowed_write: normalized(&policy.allowed_write),
+        };
+
+        let mut validator = Self {
+            rules: policy.rules.iter().map(|rule| CompiledRule::new(rule, form)).collect(),
+            match_strategy: policy.match_strategy,
+            path_match_mode: policy.path_match_mode,
+            allowed_read: index(&entries.allowed_read),
+            allowed_write: index(&entries.allowed_write),
+            allowed_stat: index(&normalized(&policy.allowed_stat)),
+            empty_allow_means: policy.empty_allow_means,
+            denied_patterns: GlobSet::new(&entries.denied),
+            deny_exceptions: GlobSet::new(&entries.exceptions),
+            allow_relative_paths: policy.allow_relative_paths,
+            require_exists: policy.require_exists,
+            denied_write_extensions: policy.denied_write_extensions,
+            max_path_length: bounds.max_path_length,
+            max_path_components: bounds.max_path_components,
+            trash_dir: policy.trash_dir.as_deref().map(|dir| PathBuf::from(form.apply(dir).as_ref())),
+            state_dir: None,
+            denied_inodes: None,
+            form,
+            cache: None,
+            entries,
+        };
+        if policy.check_inodes {
+            let mut patterns: Vec<&str> = policy.denied_patterns.iter().map(String::as_str).collect();
+            if policy.use_default_denylist {
+                patterns.extend(DEFAULT_DENYLIST);
+            }
+            let inodes = DeniedInodes::gather(&patterns, |path| validator.is_denied(path));
+            debug!("check_inodes: {} denied files found", inodes.len());
+            validator.denied_inodes = Some(inodes);
+        }
+        validator
+    }
+
+    /// Remembers up to `size` read and write decisions for `ttl` (zero for
+    /// no expiry); a `size` of 0 leaves every check uncached
+    pub fn with_decision_cache(mut self, size: usize, ttl: Duration) -> Self {
+        self.cache = (size > 0).then(|| DecisionCache::new(size, ttl));
+        self
+    }
+
+    /// Denies paths over `[enforcement] max_path_length` or
+    /// `max_path_components` in [`precheck`](Self::precheck), instead of
+    /// the defaults
+    pub fn with_input_limits(mut self, enforcement: &EnforcementPolicy) -> Self {
+        self.max_path_length = enforcement.max_path_length;
+        self.max_path_components = enforcement.max_path_components;
+        self
+    }
+
+    /// Keeps `[state] dir` out of reach like the trash
+    pub fn with_state_dir(mut self, dir: Option<&str>) -> Self {
+        self.state_dir = dir.map(|dir| PathBuf::from(self.form.apply(dir).as_ref()));
+        self
+    }
+
+    /// Forgets every cached decision. Temporary grants are checked before
+    /// the validator is asked, so none is cached here; the server clears
+    /// the cache when they change all the same, so that no decision taken
+    /// before a change is reused after it.
+    pub fn clear_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+    }
+
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(DecisionCache::stats)
+    }
+
+    /// Rejects request paths that are refused before any allow-list lookup,
+    /// with the violation to report
+    pub fn precheck(&self, path: &Path) -> Result<(), Denial> {
+        // First, so that nothing below walks an oversized path
+        let length = path.as_os_str().len();
+        if length > self.max_path_length {
+            return Err(Denial::new(
+                "input_too_large",
+                format!("Path is {} bytes, over [enforcement] max_path_length of {}", length, self.max_path_length),
+            ));
+        }
+        if path.components().nth(self.max_path_components).is_some() {
+            return Err(Denial::new(
+                "input_too_large",
+                format!(
+                    "Path has more than {} components, the [enforcement] max_path_components",
+                    self.max_path_components
+                ),
+            ));
+        }
+        // Checked on the raw string: a NUL truncates the path at the OS
+        // boundary, so what was matched would not be what gets opened
+        let text = path.to_string_lossy();
+        if let Some(c) = text.chars().find(|c| c.is_control()) {
+            return Err(Denial::new(
+                "malformed_path",
+                format!("Path contains control character {:?}: {:?}", c, path),
+            ));
+        }
+        // Absolutizing only undoes a literal `..`. The OS would open these
+        // as names, but an agent sending one is probing for a layer that
+        // decodes them.
+        if let Some(component) = hidden_traversal(&text) {
+            return Err(Denial::new(
+                "suspicious_path",
+                format!("Path component {:?} is an encoded \"..\": {:?}", component, path),
+            ));
+        }
+        if path.is_relative() && !self.allow_relative_paths {
+            return Err(Denial::new(
+                "relative_path_not_allowed",
+                format!("Relative paths are not allowed: {}", path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// With `require_exists`, the denial for a path that is not there.
+    /// Only asked once `path` is known to be readable, so whether a path
+    /// outside the policy exists is never given away.
+    pub fn check_exists(&self, path: &Path) -> Result<(), Denial> {
+        if self.require_exists && matches!(path.try_exists(), Ok(false)) {
+            return Err(Denial::new("path_not_found", format!("Path does not exist: {}", path.display())));
+        }
+        Ok(())
+    }
+
+    /// The denial for writing a file with one of `denied_write_extensions`,
+    /// which no allow entry or rule lifts
+    pub fn check_write_extension(&self, path: &Path) -> Result<(), Denial> {
+        if let Some(extension) = self.denied_write_extension(path) {
+            return Err(Denial::new(
+                "write_extension_denied",
+                format!("Writing .{} files is not allowed: {}", extension, path.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn can_access(&self, path: &Path, access: Access) -> Result<bool> {
+        match access {
+            Access::Read => self.can_read(path),
+            Access::Write => self.can_write(path),
+        }
     }
 
     pub fn can_read(&self, path: &Path) -> Result<bool> {
+        self.decide(path, Access::Read)
+    }
+
+    /// Check if a path can be written to
+    pub fn can_write(&self, path: &Path) -> Result<bool> {
+        self.decide(path, Access::Write)
+    }
+
+    /// Whether the metadata of `path` may be returned: it is readable, or
+    /// in `allowed_stat` and not ruled out by the deny list or the trash
+    pub fn can_stat(&self, path: &Path) -> Result<bool> {
+        if self.can_read(path)? {
+            return Ok(true);
+        }
+        if self.precheck(path).is_err() {
+            return Ok(false);
+        }
         let canonical = self.canonicalize_path(path)?;
-        
-        // Check deny patterns first
-        if self.matches_deny_pattern(&canonical) {
+        if self.matches_deny_pattern(&canonical) || self.is_reserved(&canonical) || self.is_denied_inode(&canonical) {
             return Ok(false);
         }
-        
-        // Check against allowed read paths
-        Ok(self.matches_allowed_paths(&canonical, &self.policy.allowed_read))
+        Ok(self.allowed_stat.matches(&canonical.to_string_lossy()))
     }
 
-    /// Check if a path can be written to
-    #[allow(dead_code)] // Will be used when WriteFile is implemented
-    pub fn can_write(&self, path: &Path) -> Result<bool> {
+    /// Whether the deny list or the trash rules `path` out for all access,
+    /// regardless of the allow lists
+    pub fn is_denied(&self, path: &Path) -> bool {
+        match self.canonicalize_path(path) {
+            Ok(canonical) => {
+                self.matches_deny_pattern(&canonical)
+                    || self.is_reserved(&canonical)
+                    || self.is_denied_inode(&canonical)
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Every entry that matches `path` for `access`, the deciding one
+    /// first, where a check only says whether the path may be accessed.
+    /// Matches entries one by one, so it is much slower than a check.
+    pub fn explain(&self, path: &Path, access: Access) -> Result<Decision> {
+        if self.precheck(path).is_err() {
+            return Ok(Decision::default());
+        }
+        Ok(self.explain_canonical(&self.canonicalize_path(path)?, access))
+    }
+
+    fn explain_canonical(&self, canonical: &Path, access: Access) -> Decision {
+        let path_str = canonical.to_string_lossy();
+        let glob = |entry: &str| GlobSet::new(&[entry]).matches(&path_str);
+        let allows = |entry: &str| self.allow_index(entry).matches(&path_str);
+        let mut candidates = Vec::new();
+
+        // One exception lifts every deny pattern
+        if !self.entries.exceptions.iter().any(|entry| glob(entry)) {
+            for (i, entry) in self.entries.denied.iter().enumerate().filter(|(_, entry)| glob(entry)) {
+                let list = if i < self.entries.from_default {
+                    "filesystem.use_default_denylist"
+                } else {
+                    "filesystem.denied_patterns"
+                };
+                candidates.push(Candidate::new(list, entry.as_str(), Effect::Deny, Specificity::path(entry)));
+            }
+        }
+        let reserved = [("filesystem.trash_dir", &self.trash_dir), ("state.dir", &self.state_dir)];
+        for (list, dir) in reserved {
+            if let Some(dir) = dir.as_ref().filter(|dir| canonical.starts_with(dir)) {
+                let dir = dir.to_string_lossy();
+                candidates.push(Candidate::new(list, dir.as_ref(), Effect::Deny, Specificity::path(&dir)));
+            }
+        }
+        if let Some(denied) = self.denied_inodes.as_ref().and_then(|inodes| inodes.matching(canonical)) {
+            let denied = denied.to_string_lossy();
+            let list = "filesystem.check_inodes";
+            candidates.push(Candidate::new(list, denied.as_ref(), Effect::Deny, Specificity::path(&denied)));
+        }
+        if let Some(extension) = self.denied_write_extension(canonical).filter(|_| access == Access::Write) {
+            let list = "filesystem.denied_write_extensions";
+            candidates.push(Candidate::new(list, extension, Effect::Deny, Specificity::default()));
+        }
+        let matching_rules: Vec<&CompiledRule> =
+            self.rules.iter().filter(|rule| rule.applies(&path_str, access)).collect();
+        let deciding = match self.match_strategy {
+            MatchStrategy::FirstMatch => matching_rules.first(),
+            MatchStrategy::LastMatch => matching_rules.last(),
+        };
+        candidates.extend(deciding.map(|rule| rule.candidate()));
+        let (list, allowed) = match access {
+            Access::Read => ("filesystem.allowed_read", &self.entries.allowed_read),
+            Access::Write => ("filesystem.allowed_write", &self.entries.allowed_write),
+        };
+        for entry in allowed.iter().filter(|entry| allows(entry)) {
+            candidates.push(Candidate::new(list, entry.as_str(), Effect::Allow, Specificity::path(entry)));
+        }
+        if allowed.is_empty() && self.empty_allow_means == EmptyAllowMeans::All {
+            let list = "filesystem.empty_allow_means";
+            candidates.push(Candidate::new(list, "all", Effect::Allow, Specificity::default()));
+        }
+
+        let mut decision = rules::decide(candidates);
+        // Rules match_strategy passed over lose to the one it picked
+        let passed_over = matching_rules.iter().filter(|rule| !deciding.is_some_and(|d| std::ptr::eq(*d, **rule)));
+        decision.losers.extend(passed_over.map(|rule| rule.candidate()));
+        decision
+    }
+
+    fn decide(&self, path: &Path, access: Access) -> Result<bool> {
+        // One check of a cached flag while `trace` is off
+        let tracing = tracing::enabled!(Level::TRACE);
+        if let Err(denial) = self.precheck(path) {
+            if tracing {
+                trace!("{:?} {:?}: denied before lookup: {}", access, path, denial.reason);
+            }
+            return Ok(false);
+        }
         let canonical = self.canonicalize_path(path)?;
-        
-        // Check deny patterns first
-        if self.matches_deny_pattern(&canonical) {
+        if tracing {
+            self.trace_steps(path, &canonical, access);
+        }
+        // Not cached: a name can be linked to a denied file at any time
+        if self.is_denied_inode(&canonical) {
+            if tracing {
+                trace!("{:?} {:?}: denied, the same file as a denied one", access, path);
+            }
             return Ok(false);
         }
-        
-        // Check against allowed write paths
-        Ok(self.matches_allowed_paths(&canonical, &self.policy.allowed_write))
+        let Some(cache) = &self.cache else {
+            let allowed = self.decide_canonical(&canonical, access);
+            if tracing {
+                trace!("{:?} {:?}: {}", access, path, verdict(allowed));
+            }
+            return Ok(allowed);
+        };
+        if let Some(allowed) = cache.get(&canonical, access) {
+            if tracing {
+                trace!("{:?} {:?}: {} (cached)", access, path, verdict(allowed));
+            }
+            return Ok(allowed);
+        }
+        let allowed = self.decide_canonical(&canonical, access);
+        cache.insert(canonical, access, allowed);
+        if tracing {
+            trace!("{:?} {:?}: {}", access, path, verdict(allowed));
+        }
+        Ok(allowed)
+    }
+
+    /// Logs each check [`decide_canonical`](Self::decide_canonical) makes,
+    /// matching entries one by one where the indexes only say whether any
+    /// matched. Slow, and only done at `trace`.
+    fn trace_steps(&self, path: &Path, canonical: &Path, access: Access) {
+        let path_str = canonical.to_string_lossy();
+        let matching = |entries: &[String], matches: &dyn Fn(&str, &str) -> bool| -> Vec<String> {
+            entries.iter().filter(|entry| matches(entry, &path_str)).cloned().collect()
+        };
+        let glob = |entry: &str, path: &str| GlobSet::new(&[entry]).matches(path);
+        let allows = |entry: &str, path: &str| self.allow_index(entry).matches(path);
+
+        trace!("{:?} {:?}: canonical path {:?}", access, path, canonical);
+        trace!(
+            "{:?} {:?}: tried {} deny patterns, matched {:?}; {} exceptions, matched {:?}",
+            access,
+            path,
+            self.entries.denied.len(),
+            matching(&self.entries.denied, &glob),
+            self.entries.exceptions.len(),
+            matching(&self.entries.exceptions, &glob),
+        );
+        if self.is_reserved(canonical) {
+            trace!("{:?} {:?}: in the trash or state dir", access, path);
+        }
+        if let Some(extension) = self.denied_write_extension(canonical).filter(|_| access == Access::Write) {
+            trace!("{:?} {:?}: extension {:?} is never writable", access, path, extension);
+        }
+        if let Some(action) = self.matching_rule(&path_str, access) {
+            trace!("{:?} {:?}: {} rules, deciding rule says {:?}", access, path, self.rules.len(), action);
+        }
+        let allowed = match access {
+            Access::Read => &self.entries.allowed_read,
+            Access::Write => &self.entries.allowed_write,
+        };
+        trace!(
+            "{:?} {:?}: tried {} {} entries, matched {:?}",
+            access,
+            path,
+            allowed.len(),
+            access.list_name(),
+            matching(allowed, &allows),
+        );
+        let decision = self.explain_canonical(canonical, access);
+        let losers: Vec<String> = decision.losers.iter().map(Candidate::to_string).collect();
+        trace!("{:?} {:?}: decided by {}, over {:?}", access, path, decision.describe(), losers);
+    }
+
+    fn decide_canonical(&self, canonical: &Path, access: Access) -> bool {
+        // Check deny patterns first
+        if self.matches_deny_pattern(canonical) || self.is_reserved(canonical) {
+            return false;
+        }
+        if access == Access::Write && self.denied_write_extension(canonical).is_some() {
+            return false;
+        }
+
+        let path_str = canonical.to_string_lossy();
+        if let Some(action) = self.matching_rule(&path_str, access) {
+            return action == RuleAction::Allow;
+        }
+
+        // Check against the allow list for this access
+        let (allowed, entries) = match access {
+            Access::Read => (&self.allowed_read, &self.entries.allowed_read),
+            Access::Write => (&self.allowed_write, &self.entries.allowed_write),
+        };
+        if entries.is_empty() {
+            return self.empty_allow_means == EmptyAllowMeans::All;
+        }
+        allowed.matches(&path_str)
+    }
+
+    /// One allow list entry on its own, matched as `path_match_mode` says
+    fn allow_index(&self, entry: &str) -> PathIndex {
+        PathIndex::with_mode(&[entry], self.path_match_mode, self.form.fold_case)
+    }
+
+    /// The action of the deciding rule under `match_strategy`, if any matches
+    fn matching_rule(&self, path: &str, access: Access) -> Option<RuleAction> {
+        let mut matching = self.rules.iter().filter(|rule| rule.applies(path, access));
+        let rule = match self.match_strategy {
+            MatchStrategy::FirstMatch => matching.next(),
+            MatchStrategy::LastMatch => matching.next_back(),
+        };
+        rule.map(|rule| rule.action)
     }
 
     fn canonicalize_path(&self, path: &Path) -> Result<PathBuf> {
+        #[cfg(test)]
+        hooks::check_canonicalize()?;
+
         // Absolutize and normalize the path to prevent directory traversal
         let abs_path = path.absolutize()?;
+        // Names that are not UTF-8 are not Unicode, and match byte for byte
+        if let Some(Cow::Owned(normalized)) = abs_path.to_str().map(|s| self.form.apply(s)) {
+            return Ok(PathBuf::from(normalized));
+        }
         Ok(abs_path.to_path_buf())
     }
 
-    fn matches_deny_pattern(&self, path: &PathBuf) -> bool {
+    /// The trash is only reachable through `delete_file` and `restore_file`,
+    /// and the state dir not at all
+    fn is_reserved(&self, path: &Path) -> bool {
+        [&self.trash_dir, &self.state_dir].into_iter().flatten().any(|dir| path.starts_with(dir))
+    }
+
+    fn is_denied_inode(&self, path: &Path) -> bool {
+        self.denied_inodes.as_ref().is_some_and(|inodes| inodes.matching(path).is_some())
+    }
+
+    /// The entry of `denied_write_extensions` that `path` ends in, if any
+    fn denied_write_extension(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_str()?;
+        self.denied_write_extensions.iter().map(String::as_str).find(|e| e.eq_ignore_ascii_case(extension))
+    }
+
+    fn matches_deny_pattern(&self, path: &Path) -> bool {
         let path_str = path.to_string_lossy();
-        
-        for pattern_str in &self.policy.denied_patterns {
-            if let Ok(pattern) = Pattern::new(pattern_str) {
-                if pattern.matches(&path_str) {
-                    return true;
-                }
+        self.denied_patterns.matches(&path_str) && !self.deny_exceptions.matches(&path_str)
+    }
+}
+
+/// Rounds of percent-decoding undone, so `%252e` is caught as well
+const MAX_DECODE_ROUNDS: usize = 3;
+
+/// The component of `path` that spells `..` some other way: percent-encoded
+/// (`%2e%2e`, `%252e%252e`, `%u002e`), as an overlong UTF-8 dot
+/// (`%c0%ae`) or beside a backslash where backslashes are not separators
+fn hidden_traversal(path: &str) -> Option<&str> {
+    path.split(|c| c == '/' || (cfg!(windows) && c == '\\')).find(|component| {
+        let mut decoded = component.to_string();
+        for _ in 0..MAX_DECODE_ROUNDS {
+            match percent_decode(&decoded) {
+                Some(next) => decoded = next,
+                None => break,
             }
         }
-        
-        false
-    }
+        *component != ".." && decoded.split(['/', '\\']).any(|part| part == "..")
+    })
+}
 
-    fn matches_allowed_paths(&self, path: &PathBuf, allowed_paths: &[String]) -> bool {
-        let path_str = path.to_string_lossy();
-        
-        for allowed in allowed_paths {
-            // Check if path starts with an allowed prefix
-            if path_str.starts_with(allowed) {
-                return true;
+/// `text` with its `%XX` and `%uXXXX` escapes decoded, the overlong forms of
+/// `.` and `/` as those characters; `None` when it has none
+fn percent_decode(text: &str) -> Option<String> {
+    let hex = |digits: &[u8]| {
+        let digits = std::str::from_utf8(digits).ok().filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()))?;
+        u32::from_str_radix(digits, 16).ok()
+    };
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let wide = matches!(bytes.get(i + 1), Some(b'u' | b'U'));
+            if let Some(code) = bytes.get(i + 2..i + 6).filter(|_| wide).and_then(hex) {
+                let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                i += 6;
+                continue;
             }
-            
-            // Also check glob patterns
-            if let Ok(pattern) = Pattern::new(allowed) {
-                if pattern.matches(&path_str) {
-                    return true;
-                }
+            if let Some(byte) = bytes.get(i + 1..i + 3).and_then(hex) {
+                out.push(byte as u8);
+                i += 3;
+                continue;
             }
         }
-        
-        false
+        out.push(bytes[i]);
+        i += 1;
+    }
+    if out == bytes {
+        return None;
+    }
+    for (overlong, plain) in [(&[0xe0, 0x80, 0xae][..], "."), (&[0xc0, 0xae], "."), (&[0xc0, 0xaf], "/")] {
+        while let Some(at) = out.windows(overlong.len()).position(|window| window == overlong) {
+            out.splice(at..at + overlong.len(), plain.bytes());
+        }
+    }
+    Some(String::from_utf8_lossy(&out).into_owned())
+}
+
+fn verdict(allowed: bool) -> &'static str {
+    if allowed {
+        "allowed"
+    } else {
+        "denied"
+    }
+}
+
+/// Lets tests make path resolution fail
+#[cfg(test)]
+pub mod hooks {
+    use std::cell::Cell;
+
+    thread_local! {
+        static FAIL_CANONICALIZE: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// The next path resolved on this thread fails
+    pub fn fail_next_canonicalize() {
+        FAIL_CANONICALIZE.with(|f| f.set(true));
+    }
+
+    pub(super) fn check_canonicalize() -> anyhow::Result<()> {
+        if FAIL_CANONICALIZE.with(|f| f.replace(false)) {
+            anyhow::bail!("injected canonicalization failure");
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::policy::FilesystemPolicy;
+    use crate::policy::{FilesystemPolicy, UnicodeNormalization};
 
     fn create_test_policy() -> FilesystemPolicy {
         FilesystemPolicy {
@@ -99,6 +676,7 @@ mod tests {
                 "*.key".to_string(),
                 "*.pem".to_string(),
             ],
+            ..Default::default()
         }
     }
 
@@ -133,4 +711,569 @@ mod tests {
         assert!(result.is_ok());
         assert!(!result.unwrap());
     }
+
+    #[test]
+    fn test_default_denylist_denies_ssh_keys() {
+        let policy = FilesystemPolicy {
+            allowed_read: vec!["/home/user".to_string()],
+            ..Default::default()
+        };
+        let validator = PathValidator::new(policy.clone());
+        assert!(!validator.can_read(Path::new("/home/user/.ssh/id_rsa")).unwrap());
+        assert!(validator.can_read(Path::new("/home/user/notes.txt")).unwrap());
+
+        let validator = PathValidator::new(FilesystemPolicy {
+            use_default_denylist: false,
+            ..policy
+        });
+        assert!(validator.can_read(Path::new("/home/user/.ssh/id_rsa")).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_inodes_denies_hardlinks_to_denied_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("secret")).unwrap();
+        std::fs::create_dir_all(root.join("work")).unwrap();
+        for name in ["id_rsa", "public.txt", "later"] {
+            std::fs::write(root.join("secret").join(name), name).unwrap();
+        }
+        std::fs::hard_link(root.join("secret/id_rsa"), root.join("work/id_rsa.txt")).unwrap();
+        std::fs::hard_link(root.join("secret/public.txt"), root.join("work/public.txt")).unwrap();
+        let policy = FilesystemPolicy {
+            allowed_read: vec![root.display().to_string()],
+            allowed_write: vec![root.join("work").display().to_string()],
+            denied_patterns: vec![
+                format!("{}/secret/*", root.display()),
+                format!("!{}/secret/public.txt", root.display()),
+            ],
+            use_default_denylist: false,
+            ..Default::default()
+        };
+        let link = root.join("work/id_rsa.txt");
+        assert!(PathValidator::new(policy.clone()).can_read(&link).unwrap());
+
+        let validator = PathValidator::new(FilesystemPolicy {
+            check_inodes: true,
+            ..policy
+        })
+        .with_decision_cache(16, Duration::ZERO);
+        assert!(!validator.can_read(&link).unwrap());
+        assert!(!validator.can_write(&link).unwrap());
+        assert!(validator.is_denied(&link));
+        let decision = validator.explain(&link, Access::Read).unwrap();
+        let winner = decision.winner.unwrap();
+        assert_eq!(winner.list, "filesystem.check_inodes");
+        assert_eq!(winner.entry, root.join("secret/id_rsa").display().to_string());
+        // Exceptions stay excepted, and other files are untouched
+        assert!(validator.can_read(&root.join("work/public.txt")).unwrap());
+        assert!(validator.can_write(&root.join("work/notes.txt")).unwrap());
+        // A link made after the load, to a name already checked
+        let later = root.join("work/later.txt");
+        assert!(validator.can_read(&later).unwrap());
+        std::fs::hard_link(root.join("secret/later"), &later).unwrap();
+        assert!(!validator.can_read(&later).unwrap());
+    }
+
+    #[test]
+    fn test_empty_allow_lists_mean_none_or_all() {
+        let policy = FilesystemPolicy {
+            allowed_write: vec!["/srv/out".to_string()],
+            denied_patterns: vec!["*.key".to_string()],
+            ..Default::default()
+        };
+        let none = PathValidator::new(policy.clone());
+        assert!(!none.can_read(Path::new("/srv/notes.txt")).unwrap());
+        assert!(none.can_write(Path::new("/srv/out/log.txt")).unwrap());
+
+        let all = PathValidator::new(FilesystemPolicy {
+            empty_allow_means: EmptyAllowMeans::All,
+            ..policy
+        });
+        assert!(all.can_read(Path::new("/srv/notes.txt")).unwrap());
+        let decision = all.explain(Path::new("/srv/notes.txt"), Access::Read).unwrap();
+        assert_eq!(decision.describe(), "allow \"all\" (filesystem.empty_allow_means, specificity 0)");
+        // The deny lists still apply, and a list with entries is unchanged
+        assert!(!all.can_read(Path::new("/srv/server.key")).unwrap());
+        assert!(!all.can_read(Path::new("/home/user/.ssh/id_rsa")).unwrap());
+        assert!(!all.can_write(Path::new("/srv/notes.txt")).unwrap());
+    }
+
+    #[test]
+    fn test_negated_pattern_overrides_denylist() {
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec!["/home/user".to_string()],
+            denied_patterns: vec!["!*/.ssh/known_hosts".to_string()],
+            ..Default::default()
+        });
+        assert!(validator.can_read(Path::new("/home/user/.ssh/known_hosts")).unwrap());
+        assert!(!validator.can_read(Path::new("/home/user/.ssh/id_ed25519")).unwrap());
+    }
+
+    #[test]
+    fn test_relative_paths_rejected_by_default() {
+        let validator = PathValidator::new(create_test_policy());
+        let denial = validator.precheck(Path::new("notes.txt")).unwrap_err();
+        assert_eq!(denial.violation, "relative_path_not_allowed");
+        assert!(!validator.can_read(Path::new("notes.txt")).unwrap());
+        assert!(validator.precheck(Path::new("/tmp/notes.txt")).is_ok());
+    }
+
+    #[test]
+    fn test_null_bytes_and_control_chars_rejected() {
+        let validator = PathValidator::new(create_test_policy());
+        for path in ["/tmp/notes.txt\0.key", "/tmp/a\nb.txt", "/tmp/\x1b[2Jx", "/tmp/\u{85}x"] {
+            let denial = validator.precheck(Path::new(path)).unwrap_err();
+            assert_eq!(denial.violation, "malformed_path", "{:?}", path);
+            assert!(!validator.can_read(Path::new(path)).unwrap());
+            assert!(!validator.can_write(Path::new(path)).unwrap());
+        }
+        // Still malformed when relative paths are allowed
+        let denial = PathValidator::new(FilesystemPolicy {
+            allow_relative_paths: true,
+            ..create_test_policy()
+        })
+        .precheck(Path::new("notes\0.txt"))
+        .unwrap_err();
+        assert_eq!(denial.violation, "malformed_path");
+    }
+
+    #[test]
+    fn test_encoded_traversal_rejected() {
+        let validator = PathValidator::new(create_test_policy());
+        for path in [
+            "/tmp/%2e%2e/etc/passwd",
+            "/tmp/%2E%2e/etc/passwd",
+            "/tmp/.%2e/etc/passwd",
+            "/tmp/a%2f..%2f..%2fetc/passwd",
+            "/tmp/%252e%252e/etc/passwd",
+            "/tmp/%25252e%25252e/etc/passwd",
+            "/tmp/%u002e%u002e/etc/passwd",
+            "/tmp/%c0%ae%c0%ae/etc/passwd",
+            "/tmp/..%c0%afetc/passwd",
+            "/tmp/..%5cetc/passwd",
+            #[cfg(not(windows))]
+            "/tmp/..\\..\\etc/passwd",
+        ] {
+            let denial = validator.precheck(Path::new(path)).unwrap_err();
+            assert_eq!(denial.violation, "suspicious_path", "{}", path);
+            assert!(!validator.can_read(Path::new(path)).unwrap());
+        }
+        // Literal dots are absolutized, and escapes of anything else are
+        // only names
+        for path in ["/tmp/a/../b", "/tmp/100%25.txt", "/tmp/%2e.hidden", "/tmp/...", "/tmp/50%off"] {
+            assert!(validator.precheck(Path::new(path)).is_ok(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_oversized_paths_rejected_before_lookup() {
+        let validator = PathValidator::new(create_test_policy()).with_input_limits(&EnforcementPolicy {
+            max_path_length: 64,
+            max_path_components: 4,
+            ..Default::default()
+        });
+        assert!(validator.can_read(Path::new("/tmp/a/b")).unwrap());
+        for path in [format!("/tmp/{}", "x".repeat(60)), "/tmp/a/b/c".to_string()] {
+            let denial = validator.precheck(Path::new(&path)).unwrap_err();
+            assert_eq!(denial.violation, "input_too_large", "{}", path);
+            assert!(!validator.can_read(Path::new(&path)).unwrap());
+        }
+        // The defaults still allow any path the OS would open
+        let deep = format!("/tmp{}", "/a".repeat(1000));
+        assert!(PathValidator::new(create_test_policy()).can_read(Path::new(&deep)).unwrap());
+        assert!(!PathValidator::new(create_test_policy()).can_read(Path::new(&"/a".repeat(5000))).unwrap());
+    }
+
+    #[test]
+    fn test_relative_paths_resolved_when_allowed() {
+        let cwd = std::env::current_dir().unwrap();
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec![cwd.to_string_lossy().into_owned()],
+            allow_relative_paths: true,
+            ..Default::default()
+        });
+        assert!(validator.precheck(Path::new("notes.txt")).is_ok());
+        assert!(validator.can_read(Path::new("notes.txt")).unwrap());
+        assert!(!validator.can_read(Path::new("../outside.txt")).unwrap());
+    }
+
+    #[test]
+    fn test_trash_and_state_dirs_are_excluded() {
+        let validator = PathValidator::new(FilesystemPolicy {
+            trash_dir: Some("/tmp/.trash".to_string()),
+            ..create_test_policy()
+        });
+        assert!(!validator.can_read(Path::new("/tmp/.trash/1700000000-0/data")).unwrap());
+        assert!(!validator.can_write(Path::new("/tmp/.trash/new.txt")).unwrap());
+        assert!(validator.can_write(Path::new("/tmp/.trashy.txt")).unwrap());
+
+        let validator = PathValidator::new(create_test_policy()).with_state_dir(Some("/tmp/openclaw-state"));
+        assert!(!validator.can_read(Path::new("/tmp/openclaw-state/grants.json")).unwrap());
+        assert!(!validator.can_stat(Path::new("/tmp/openclaw-state")).unwrap());
+        assert!(validator.can_read(Path::new("/tmp/openclaw-stateless.txt")).unwrap());
+    }
+
+    #[test]
+    fn test_match_strategy_decides_overlapping_rules() {
+        let rules = vec![
+            PathRule {
+                path: "/srv/project".to_string(),
+                action: RuleAction::Allow,
+                access: RuleAccess::Any,
+            },
+            PathRule {
+                path: "/srv/project/*.log".to_string(),
+                action: RuleAction::Deny,
+                access: RuleAccess::Any,
+            },
+        ];
+        let log = Path::new("/srv/project/build.log");
+        let source = Path::new("/srv/project/main.rs");
+
+        let first = PathValidator::new(FilesystemPolicy {
+            rules: rules.clone(),
+            ..Default::default()
+        });
+        assert!(first.can_read(log).unwrap());
+        assert!(first.can_read(source).unwrap());
+
+        let last = PathValidator::new(FilesystemPolicy {
+            rules,
+            match_strategy: MatchStrategy::LastMatch,
+            ..Default::default()
+        });
+        assert!(!last.can_read(log).unwrap());
+        assert!(last.can_read(source).unwrap());
+    }
+
+    /// Locks in [`RuleSet`](crate::policy::rules::RuleSet)'s precedence
+    /// for paths
+    #[test]
+    fn test_precedence_golden() {
+        let rule = |path: &str, action| PathRule {
+            path: path.to_string(),
+            action,
+            access: RuleAccess::Any,
+        };
+        let policy = FilesystemPolicy {
+            allowed_read: vec!["/srv".to_string(), "/srv/app".to_string()],
+            allowed_write: vec!["/srv/app".to_string()],
+            denied_patterns: vec!["*.key".to_string(), "!/srv/app/public.key".to_string()],
+            use_default_denylist: false,
+            rules: vec![rule("/srv/app/logs", RuleAction::Deny), rule("/srv/app/logs/keep", RuleAction::Allow)],
+            denied_write_extensions: vec!["sh".to_string()],
+            trash_dir: Some("/srv/.trash".to_string()),
+            ..Default::default()
+        };
+        let first_match = PathValidator::new(policy.clone());
+        let last_match = PathValidator::new(FilesystemPolicy {
+            match_strategy: MatchStrategy::LastMatch,
+            ..policy
+        });
+        type Case<'a> = (&'a PathValidator, Access, &'a str, Option<&'a str>, &'a [&'a str]);
+        let cases: [Case; 10] = [
+            (&first_match, Access::Read, "/srv/app/src/main.rs", Some("/srv/app"), &["/srv"]),
+            (&first_match, Access::Read, "/srv/docs/a.md", Some("/srv"), &[]),
+            (&first_match, Access::Read, "/srv/app/secret.key", Some("*.key"), &["/srv/app", "/srv"]),
+            (&first_match, Access::Read, "/srv/app/public.key", Some("/srv/app"), &["/srv"]),
+            (&first_match, Access::Write, "/srv/app/run.sh", Some("sh"), &["/srv/app"]),
+            (&first_match, Access::Read, "/srv/app/logs/a.log", Some("/srv/app/logs"), &["/srv/app", "/srv"]),
+            (
+                &first_match,
+                Access::Read,
+                "/srv/app/logs/keep/b.log",
+                Some("/srv/app/logs"),
+                &["/srv/app", "/srv", "/srv/app/logs/keep"],
+            ),
+            (
+                &last_match,
+                Access::Read,
+                "/srv/app/logs/keep/b.log",
+                Some("/srv/app/logs/keep"),
+                &["/srv/app", "/srv", "/srv/app/logs"],
+            ),
+            (&first_match, Access::Read, "/srv/.trash/1/a.txt", Some("/srv/.trash"), &["/srv"]),
+            (&first_match, Access::Read, "/etc/hosts", None, &[]),
+        ];
+        for (validator, access, path, winner, losers) in cases {
+            let decision = validator.explain(Path::new(path), access).unwrap();
+            assert_eq!(decision.winner.as_ref().map(|c| c.entry.as_str()), winner, "{}", path);
+            let lost: Vec<&str> = decision.losers.iter().map(|c| c.entry.as_str()).collect();
+            assert_eq!(lost, losers, "{}", path);
+            assert_eq!(decision.allowed(), validator.can_access(Path::new(path), access).unwrap(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_rules_respect_access_and_deny_list() {
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_write: vec!["/srv/project".to_string()],
+            denied_patterns: vec!["*.key".to_string()],
+            rules: vec![
+                PathRule {
+                    path: "/srv/project".to_string(),
+                    action: RuleAction::Allow,
+                    access: RuleAccess::Read,
+                },
+                PathRule {
+                    path: "/srv/project/vendor".to_string(),
+                    action: RuleAction::Deny,
+                    access: RuleAccess::Write,
+                },
+            ],
+            ..Default::default()
+        });
+        assert!(validator.can_read(Path::new("/srv/project/vendor/lib.rs")).unwrap());
+        assert!(!validator.can_write(Path::new("/srv/project/vendor/lib.rs")).unwrap());
+        // No write rule matches, so allowed_write decides
+        assert!(validator.can_write(Path::new("/srv/project/src/lib.rs")).unwrap());
+        // The deny list still wins over an allow rule
+        assert!(!validator.can_read(Path::new("/srv/project/server.key")).unwrap());
+    }
+
+    #[test]
+    fn test_composed_and_decomposed_names_match_alike() {
+        let composed = "/srv/caf\u{e9}";
+        let decomposed = "/srv/cafe\u{301}";
+        let validator = |form| {
+            PathValidator::new(FilesystemPolicy {
+                allowed_read: vec![decomposed.to_string()],
+                denied_patterns: vec![format!("{}/*.key", composed)],
+                unicode_normalization: form,
+                ..Default::default()
+            })
+        };
+        let can_read = |validator: &PathValidator, root: &str, name: &str| {
+            validator.can_read(Path::new(&format!("{}/{}", root, name))).unwrap()
+        };
+
+        for form in [UnicodeNormalization::Nfc, UnicodeNormalization::Nfd] {
+            let validator = validator(form);
+            for root in [composed, decomposed] {
+                assert!(can_read(&validator, root, "menu.txt"), "{:?} {:?}", form, root);
+                assert!(!can_read(&validator, root, "server.key"), "{:?} {:?}", form, root);
+            }
+        }
+
+        // Byte for byte, each spelling only matches entries spelled alike,
+        // so the decomposed name slips past the deny pattern
+        let validator = validator(UnicodeNormalization::None);
+        assert!(!can_read(&validator, composed, "menu.txt"));
+        assert!(can_read(&validator, decomposed, "server.key"));
+    }
+
+    #[test]
+    fn test_require_exists() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let present = dir.path().join("present.txt");
+        std::fs::write(&present, "x").unwrap();
+        let missing = dir.path().join("missing.txt");
+        let validator = |require_exists| {
+            PathValidator::new(FilesystemPolicy {
+                allowed_read: vec![dir.path().display().to_string()],
+                require_exists,
+                ..Default::default()
+            })
+        };
+
+        assert!(validator(false).check_exists(&missing).is_ok());
+        let strict = validator(true);
+        assert!(strict.check_exists(&present).is_ok());
+        assert_eq!(strict.check_exists(&missing).unwrap_err().violation, "path_not_found");
+        // Whether the path is readable is decided as before
+        assert!(strict.can_read(&missing).unwrap());
+    }
+
+    #[test]
+    fn test_denied_write_extensions() {
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec!["/workspace".to_string()],
+            allowed_write: vec!["/workspace".to_string()],
+            rules: vec![PathRule {
+                path: "/workspace/bin".to_string(),
+                action: RuleAction::Allow,
+                access: RuleAccess::Any,
+            }],
+            denied_write_extensions: vec!["sh".to_string(), "exe".to_string()],
+            ..Default::default()
+        });
+
+        assert!(validator.can_write(Path::new("/workspace/notes.txt")).unwrap());
+        assert!(validator.check_write_extension(Path::new("/workspace/notes.txt")).is_ok());
+        for path in ["/workspace/run.sh", "/workspace/RUN.SH", "/workspace/bin/setup.exe"] {
+            assert!(!validator.can_write(Path::new(path)).unwrap(), "{}", path);
+            let denial = validator.check_write_extension(Path::new(path)).unwrap_err();
+            assert_eq!(denial.violation, "write_extension_denied");
+        }
+        // Only writes are restricted, and only by the last extension
+        assert!(validator.can_read(Path::new("/workspace/run.sh")).unwrap());
+        assert!(validator.can_write(Path::new("/workspace/run.sh.txt")).unwrap());
+        assert!(validator.can_write(Path::new("/workspace/sh")).unwrap());
+    }
+
+    #[test]
+    fn test_stat_is_broader_than_read() {
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec!["/srv/project/src".to_string()],
+            allowed_stat: vec!["/srv/project".to_string()],
+            denied_patterns: vec!["*.key".to_string()],
+            ..Default::default()
+        });
+        assert!(validator.can_stat(Path::new("/srv/project/src/main.rs")).unwrap());
+        assert!(validator.can_stat(Path::new("/srv/project/secrets.toml")).unwrap());
+        assert!(!validator.can_read(Path::new("/srv/project/secrets.toml")).unwrap());
+        // The deny list covers metadata too
+        assert!(!validator.can_stat(Path::new("/srv/project/server.key")).unwrap());
+        assert!(!validator.can_stat(Path::new("/srv/other")).unwrap());
+        assert!(!validator.can_stat(Path::new("relative")).unwrap());
+    }
+
+    #[test]
+    fn test_case_insensitive_fs_folds_both_sides() {
+        let validator = |case_insensitive_fs| {
+            PathValidator::new(FilesystemPolicy {
+                allowed_read: vec!["/Users/me/Documents".to_string()],
+                denied_patterns: vec!["*.pem".to_string()],
+                case_insensitive_fs: Some(case_insensitive_fs),
+                ..Default::default()
+            })
+        };
+        let folded = validator(true);
+        assert!(folded.can_read(Path::new("/users/me/documents/notes.txt")).unwrap());
+        assert!(folded.can_read(Path::new("/Users/me/Documents/Notes.txt")).unwrap());
+        assert!(!folded.can_read(Path::new("/Users/me/Documents/KEY.PEM")).unwrap());
+
+        let exact = validator(false);
+        assert!(!exact.can_read(Path::new("/users/me/documents/notes.txt")).unwrap());
+        assert!(exact.can_read(Path::new("/Users/me/Documents/KEY.PEM")).unwrap());
+    }
+
+    #[test]
+    fn test_regex_match_mode_keeps_its_escapes_when_folding_case() {
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec![r"/Users/me/\S+\.md\z".to_string()],
+            path_match_mode: PathMatchMode::Regex,
+            case_insensitive_fs: Some(true),
+            ..Default::default()
+        });
+        assert!(validator.can_read(Path::new("/users/me/README.MD")).unwrap());
+        assert!(!validator.can_read(Path::new("/Users/me/read me.md")).unwrap());
+        assert!(!validator.can_read(Path::new("/Users/me/notes.txt")).unwrap());
+
+        let decision = validator.explain(Path::new("/Users/Me/Notes.md"), Access::Read).unwrap();
+        assert_eq!(decision.winner.unwrap().entry, r"/Users/me/\S+\.md\z");
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_private_aliases_match_on_macos() {
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec!["/tmp".to_string()],
+            denied_patterns: vec!["/private/tmp/*.key".to_string()],
+            ..Default::default()
+        });
+        // /tmp resolves to /private/tmp, and either spelling matches either
+        let resolved = std::fs::canonicalize("/tmp").unwrap();
+        assert_eq!(resolved, Path::new("/private/tmp"));
+        assert!(validator.can_read(&resolved.join("notes.txt")).unwrap());
+        assert!(validator.can_read(Path::new("/tmp/notes.txt")).unwrap());
+        assert!(!validator.can_read(Path::new("/tmp/server.key")).unwrap());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_case_insensitivity_is_detected_on_macos() {
+        // The default APFS volume ignores case
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = std::fs::canonicalize(dir.path()).unwrap();
+        assert!(crate::fs::canonical::ignores_case(&root));
+        let validator = PathValidator::new(FilesystemPolicy {
+            allowed_read: vec![root.display().to_string()],
+            denied_patterns: vec!["*.pem".to_string()],
+            ..Default::default()
+        });
+        let shouted = root.display().to_string().to_uppercase();
+        assert!(validator.can_read(Path::new(&format!("{}/NOTES.TXT", shouted))).unwrap());
+        assert!(!validator.can_read(&root.join("KEY.PEM")).unwrap());
+    }
+
+    #[test]
+    fn test_decision_cache() {
+        let policy = create_test_policy();
+        let validator = PathValidator::new(policy.clone()).with_decision_cache(16, Duration::ZERO);
+        let uncached = PathValidator::new(policy);
+        for path in ["/tmp/a.txt", "/tmp/../etc/passwd", "/tmp/secret.key", "/tmp/a.txt"] {
+            for access in [Access::Read, Access::Write] {
+                let path = Path::new(path);
+                assert_eq!(validator.can_access(path, access).unwrap(), uncached.can_access(path, access).unwrap());
+            }
+        }
+        // Keyed by the resolved path, so the second /tmp/a.txt hits
+        let stats = validator.cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (2, 6, 6));
+        // Refused before the cache is asked
+        assert!(!validator.can_read(Path::new("relative.txt")).unwrap());
+        assert_eq!(validator.cache_stats().unwrap().misses, 6);
+
+        validator.clear_cache();
+        assert_eq!(validator.cache_stats().unwrap().entries, 0);
+        assert!(uncached.cache_stats().is_none());
+    }
+
+    /// Collects what a subscriber writes
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    fn captured_at(level: Level) -> (Captured, impl tracing::Subscriber) {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        (captured, subscriber)
+    }
+
+    #[test]
+    fn test_trace_logs_each_step() {
+        let validator = PathValidator::new(create_test_policy()).with_decision_cache(16, Duration::ZERO);
+        let (captured, subscriber) = captured_at(Level::TRACE);
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(!validator.can_read(Path::new("/tmp/../tmp/secret.key")).unwrap());
+            assert!(validator.can_write(Path::new("/tmp/a.txt")).unwrap());
+            assert!(validator.can_write(Path::new("/tmp/a.txt")).unwrap());
+        });
+        let log = captured.text();
+        assert!(log.contains(r#"Read "/tmp/../tmp/secret.key": canonical path "/tmp/secret.key""#), "{}", log);
+        assert!(log.contains(r#"deny patterns, matched ["*.key"]; 0 exceptions"#), "{}", log);
+        assert!(log.contains(r#"Read "/tmp/../tmp/secret.key": denied"#), "{}", log);
+        assert!(log.contains(r#"decided by deny "*.key" (filesystem.denied_patterns, specificity 0)"#), "{}", log);
+        assert!(log.contains(r#"tried 1 allowed_write entries, matched ["/tmp"]"#), "{}", log);
+        assert!(log.contains(r#"Write "/tmp/a.txt": allowed (cached)"#), "{}", log);
+
+        // Nothing is logged at info
+        let (captured, subscriber) = captured_at(Level::INFO);
+        tracing::subscriber::with_default(subscriber, || validator.can_read(Path::new("/tmp/b.txt")).unwrap());
+        assert!(captured.text().is_empty());
+    }
 }
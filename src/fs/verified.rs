@@ -0,0 +1,334 @@
+//! Opening a file first and checking the policy against what was opened.
+//!
+//! A check on the requested path followed by a plain open leaves a window
+//! in which a component can be swapped for a symlink. Here the open comes
+//! first, with `O_NOFOLLOW` on Linux and macOS, and the path the
+//! descriptor refers to is checked afterwards. Linux reads that path from
+//! `/proc/self/fd` and macOS from `fcntl(F_GETPATH)`. Elsewhere, or with
+//! `/proc` unmounted, the requested path is canonicalized again, which
+//! narrows the window without closing it. A symlink in the last component
+//! is resolved and its target checked before it is opened the same way.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::network::Denial;
+
+/// Policy check applied to the path a file was actually opened at
+pub type PathCheck<'a> = dyn Fn(&Path) -> bool + Send + Sync + 'a;
+
+/// Raised when the file that was opened is not one the policy allows,
+/// e.g. because a path component was swapped for a symlink after the
+/// request was checked.
+#[derive(Debug, thiserror::Error)]
+#[error("{} resolved to {} after it was checked", .path.display(), .resolved.display())]
+pub struct PathSwapped {
+    pub path: PathBuf,
+    pub resolved: PathBuf,
+}
+
+impl PathSwapped {
+    pub fn denial(&self) -> Denial {
+        Denial::new("toctou_detected", self.to_string())
+    }
+}
+
+/// The [`PathSwapped`] carried by an error from this module, if any
+pub fn swapped(err: &io::Error) -> Option<&PathSwapped> {
+    err.get_ref()?.downcast_ref()
+}
+
+/// Opens `path` without following a symlink in its last component, then
+/// checks the path the descriptor really refers to with `allowed`. A
+/// symlink there is resolved and its target opened the same way, once
+/// `allowed` accepts it.
+pub fn open_verified(path: &Path, options: &mut OpenOptions, allowed: &PathCheck<'_>) -> io::Result<File> {
+    #[cfg(test)]
+    hooks::before_open();
+
+    no_follow(options);
+    let file = match options.open(path) {
+        Err(e) if is_symlink_error(&e) => {
+            // The request was checked through the link, so a target that
+            // fails now was changed since
+            let target = fs::canonicalize(path)?;
+            if !allowed(&target) {
+                return Err(swap_error(path, target));
+            }
+            match options.open(&target) {
+                Err(e) if is_symlink_error(&e) => return Err(swap_error(path, target)),
+                result => result?,
+            }
+        }
+        result => result?,
+    };
+    verify_opened(&file, path, allowed)?;
+    Ok(file)
+}
+
+/// Reads a whole file through [`open_verified`]
+pub fn read(path: &Path, allowed: &PathCheck<'_>) -> io::Result<Vec<u8>> {
+    let mut file = open_verified(path, OpenOptions::new().read(true), allowed)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Reads `length` bytes from `offset` through [`open_verified`], or to the
+/// end when `length` is 0, into one buffer of the size read. Also returns
+/// whether the read reached the end of the file.
+pub fn read_range(path: &Path, offset: u64, length: u64, allowed: &PathCheck<'_>) -> io::Result<(Vec<u8>, bool)> {
+    let mut file = open_verified(path, OpenOptions::new().read(true), allowed)?;
+    let size = file.metadata()?.len();
+    let wanted = match length {
+        0 => size.saturating_sub(offset),
+        length => length.min(size.saturating_sub(offset)),
+    };
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = Vec::with_capacity(wanted as usize);
+    file.take(wanted).read_to_end(&mut data)?;
+    let eof = offset.saturating_add(data.len() as u64) >= size;
+    Ok((data, eof))
+}
+
+/// [`read_range`] of content already in memory, such as a redacted file
+pub fn slice_range(mut data: Vec<u8>, offset: u64, length: u64) -> (Vec<u8>, bool) {
+    let start = data.len().min(usize::try_from(offset).unwrap_or(usize::MAX));
+    let end = match length {
+        0 => data.len(),
+        length => data.len().min(start.saturating_add(usize::try_from(length).unwrap_or(usize::MAX))),
+    };
+    let eof = end == data.len();
+    data.truncate(end);
+    data.drain(..start);
+    (data, eof)
+}
+
+/// Where a read or write of `path` lands if nothing changes on disk: the
+/// nearest existing ancestor with symlinks resolved, joined with the rest.
+/// Compared with the path a file was really opened at.
+pub fn resolved_target(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    let mut existing = path.parent()?;
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(resolved) = fs::canonicalize(existing) {
+            return Some(missing.iter().rev().fold(resolved, |dir, part| dir.join(part)).join(name));
+        }
+        missing.push(existing.file_name()?);
+        existing = existing.parent()?;
+    }
+}
+
+/// Fails with [`PathSwapped`] unless the [`resolved_target`] of `path` is
+/// one `allowed` accepts, to check where a write lands before any of its
+/// missing parent directories are created
+pub fn verify_target(path: &Path, allowed: &PathCheck<'_>) -> io::Result<()> {
+    match resolved_target(path) {
+        Some(resolved) if !allowed(&resolved) => Err(swap_error(path, resolved)),
+        _ => Ok(()),
+    }
+}
+
+/// Fails with [`PathSwapped`] unless `file`, opened from `path`, resolves
+/// to a path `allowed` accepts. Returns that resolved path.
+pub fn verify_opened(file: &File, path: &Path, allowed: &PathCheck<'_>) -> io::Result<PathBuf> {
+    let resolved = opened_path(file).or_else(|_| fs::canonicalize(path))?;
+    if allowed(&resolved) {
+        Ok(resolved)
+    } else {
+        Err(swap_error(path, resolved))
+    }
+}
+
+fn swap_error(path: &Path, resolved: PathBuf) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        PathSwapped {
+            path: path.to_path_buf(),
+            resolved,
+        },
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn opened_path(file: &File) -> io::Result<PathBuf> {
+    use std::os::unix::io::AsRawFd;
+    fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))
+}
+
+#[cfg(target_os = "macos")]
+fn opened_path(file: &File) -> io::Result<PathBuf> {
+    use std::ffi::CStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+
+    let mut buf = [0 as libc::c_char; libc::PATH_MAX as usize];
+    // SAFETY: F_GETPATH writes a NUL-terminated path of at most PATH_MAX bytes
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETPATH, buf.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let path = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(path.to_bytes())))
+}
+
+/// Without a way to ask the descriptor, the caller re-canonicalizes
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn opened_path(_file: &File) -> io::Result<PathBuf> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(target_os = "linux")]
+pub(super) fn no_follow(options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.custom_flags(nix::fcntl::OFlag::O_NOFOLLOW.bits());
+}
+
+#[cfg(target_os = "macos")]
+pub(super) fn no_follow(options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.custom_flags(libc::O_NOFOLLOW);
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(super) fn no_follow(_options: &mut OpenOptions) {}
+
+#[cfg(target_os = "linux")]
+fn is_symlink_error(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(nix::errno::Errno::ELOOP as i32)
+}
+
+#[cfg(target_os = "macos")]
+fn is_symlink_error(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ELOOP)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn is_symlink_error(_err: &io::Error) -> bool {
+    false
+}
+
+/// Lets tests change the filesystem between a policy check and the open
+#[cfg(test)]
+pub mod hooks {
+    use std::cell::RefCell;
+
+    thread_local! {
+        static BEFORE_OPEN: RefCell<Option<Box<dyn FnOnce()>>> = RefCell::new(None);
+    }
+
+    pub fn set_before_open(hook: impl FnOnce() + 'static) {
+        BEFORE_OPEN.with(|h| *h.borrow_mut() = Some(Box::new(hook)));
+    }
+
+    pub(super) fn before_open() {
+        if let Some(hook) = BEFORE_OPEN.with(|h| h.borrow_mut().take()) {
+            hook();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::fs::validator::PathValidator;
+    use crate::policy::FilesystemPolicy;
+    use std::os::unix::fs::symlink;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn setup() -> (TempDir, PathBuf, Arc<PathValidator>) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("allowed")).unwrap();
+        fs::create_dir(root.join("forbidden")).unwrap();
+        fs::write(root.join("allowed/notes.txt"), "notes").unwrap();
+        fs::write(root.join("forbidden/secret.txt"), "secret").unwrap();
+        let validator = Arc::new(PathValidator::new(FilesystemPolicy {
+            allowed_read: vec![root.join("allowed").to_string_lossy().into_owned()],
+            ..Default::default()
+        }));
+        (dir, root, validator)
+    }
+
+    fn can_read(validator: &Arc<PathValidator>) -> Box<PathCheck<'static>> {
+        let validator = validator.clone();
+        Box::new(move |path| validator.can_read(path).unwrap_or(false))
+    }
+
+    #[test]
+    fn test_reads_allowed_file() {
+        let (_dir, root, validator) = setup();
+        let data = read(&root.join("allowed/notes.txt"), &*can_read(&validator)).unwrap();
+        assert_eq!(data, b"notes");
+    }
+
+    #[test]
+    fn test_reads_through_a_symlink_to_an_allowed_file() {
+        let (_dir, root, validator) = setup();
+        fs::create_dir(root.join("allowed/conf")).unwrap();
+        fs::write(root.join("allowed/conf/real.toml"), "real").unwrap();
+        symlink(root.join("allowed/conf/real.toml"), root.join("allowed/config.toml")).unwrap();
+        symlink("conf/real.toml", root.join("allowed/relative.toml")).unwrap();
+        for link in ["allowed/config.toml", "allowed/relative.toml"] {
+            assert_eq!(read(&root.join(link), &*can_read(&validator)).unwrap(), b"real", "{}", link);
+        }
+
+        symlink(root.join("forbidden/secret.txt"), root.join("allowed/secret.txt")).unwrap();
+        let err = read(&root.join("allowed/secret.txt"), &*can_read(&validator)).unwrap_err();
+        assert_eq!(swapped(&err).unwrap().resolved, root.join("forbidden/secret.txt"));
+    }
+
+    #[test]
+    fn test_ranges_stop_at_the_end() {
+        let (_dir, root, validator) = setup();
+        let path = root.join("allowed/notes.txt");
+        let read = |offset, length| read_range(&path, offset, length, &*can_read(&validator)).unwrap();
+        assert_eq!(read(1, 2), (b"ot".to_vec(), false));
+        assert_eq!(read(3, 2), (b"es".to_vec(), true));
+        assert_eq!(read(3, 100), (b"es".to_vec(), true));
+        assert_eq!(read(2, 0), (b"tes".to_vec(), true));
+        assert_eq!(read(9, 1), (Vec::new(), true));
+        for (offset, length) in [(1, 2), (3, 2), (3, 100), (2, 0), (9, 1)] {
+            assert_eq!(slice_range(b"notes".to_vec(), offset, length), read(offset, length));
+        }
+        assert!(read_range(&root.join("forbidden/secret.txt"), 0, 1, &*can_read(&validator)).is_err());
+    }
+
+    #[test]
+    fn test_file_swapped_for_symlink_after_check() {
+        let (_dir, root, validator) = setup();
+        let path = root.join("allowed/notes.txt");
+        assert!(validator.can_read(&path).unwrap());
+
+        let (victim, target) = (path.clone(), root.join("forbidden/secret.txt"));
+        hooks::set_before_open(move || {
+            fs::remove_file(&victim).unwrap();
+            symlink(&target, &victim).unwrap();
+        });
+        let err = read(&path, &*can_read(&validator)).unwrap_err();
+        let swapped = swapped(&err).expect("swap detected");
+        assert_eq!(swapped.denial().violation, "toctou_detected");
+        assert_eq!(swapped.resolved, root.join("forbidden/secret.txt"));
+    }
+
+    #[test]
+    fn test_directory_swapped_for_symlink_after_check() {
+        let (_dir, root, validator) = setup();
+        fs::create_dir(root.join("allowed/sub")).unwrap();
+        fs::write(root.join("allowed/sub/secret.txt"), "decoy").unwrap();
+        let path = root.join("allowed/sub/secret.txt");
+        assert!(validator.can_read(&path).unwrap());
+
+        // O_NOFOLLOW only covers the last component; the descriptor check
+        // catches a swapped parent directory
+        let (sub, forbidden) = (root.join("allowed/sub"), root.join("forbidden"));
+        hooks::set_before_open(move || {
+            fs::remove_dir_all(&sub).unwrap();
+            symlink(&forbidden, &sub).unwrap();
+        });
+        let err = read(&path, &*can_read(&validator)).unwrap_err();
+        assert_eq!(swapped(&err).unwrap().resolved, root.join("forbidden/secret.txt"));
+    }
+}
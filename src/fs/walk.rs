@@ -0,0 +1,216 @@
+//! Walking a directory tree on several threads, for `search_files` and
+//! recursive `list_directory`.
+//!
+//! Workers share a stack of paths to visit and check each entry against
+//! the policy where they find it: a directory the deny list matches is
+//! neither reported nor entered, and a file is only reported when it may
+//! be read. A symlink to a directory that may be read is reported where it
+//! is and entered where it leads, so what lies below it is checked at its
+//! real path. Every directory is entered at most once by device and inode,
+//! so links cannot make the walk loop; without inodes, off Unix, links are
+//! not followed. Symlinks to files and special files are skipped.
+
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use super::inodes;
+use super::validator::PathValidator;
+
+/// How often idle workers re-check for cancellation
+const IDLE_WAIT: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy)]
+pub struct WalkOptions {
+    pub threads: usize,
+    /// Levels below the root entered; 1 reports only its own entries
+    pub max_depth: usize,
+}
+
+/// Paths waiting to be visited with their depth, shared by the worker
+/// threads. A worker only gives up once the stack is empty and nobody is
+/// still expanding a directory that could refill it.
+struct WorkQueue {
+    state: Mutex<(Vec<(PathBuf, usize)>, usize)>,
+    ready: Condvar,
+}
+
+impl WorkQueue {
+    fn new(root: PathBuf) -> Self {
+        Self {
+            state: Mutex::new((vec![(root, 0)], 0)),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn pop(&self, stop: &dyn Fn() -> bool) -> Option<(PathBuf, usize)> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if stop() {
+                return None;
+            }
+            if let Some(item) = state.0.pop() {
+                state.1 += 1;
+                return Some(item);
+            }
+            if state.1 == 0 {
+                return None;
+            }
+            state = self
+                .ready
+                .wait_timeout(state, IDLE_WAIT)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    fn finish(&self, children: Vec<(PathBuf, usize)>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.0.extend(children);
+        state.1 -= 1;
+        self.ready.notify_all();
+    }
+}
+
+/// Calls `on_entry` from the worker threads with each file and directory
+/// under `root` the policy lets through, with its metadata (a linked
+/// directory's own), until `stop` returns true. `root` is entered
+/// without a check, as the caller authorized it, and only reported when it
+/// is a readable file.
+pub fn walk(
+    root: &Path,
+    options: WalkOptions,
+    validator: &PathValidator,
+    stop: &(dyn Fn() -> bool + Sync),
+    on_entry: &(dyn Fn(&Path, &Metadata) + Sync),
+) {
+    let visited = Mutex::new(HashSet::new());
+    // The children of `dir`, unless it was entered before or is as deep as
+    // the walk goes
+    let enter = |dir: &Path, metadata: &Metadata, depth: usize| -> Vec<(PathBuf, usize)> {
+        if depth >= options.max_depth {
+            return Vec::new();
+        }
+        if let Some(key) = inodes::key(metadata) {
+            if !visited.lock().unwrap_or_else(|e| e.into_inner()).insert(key) {
+                return Vec::new();
+            }
+        }
+        match fs::read_dir(dir) {
+            Ok(entries) => entries.flatten().map(|entry| (entry.path(), depth + 1)).collect(),
+            Err(_) => Vec::new(),
+        }
+    };
+
+    let queue = WorkQueue::new(root.to_path_buf());
+    std::thread::scope(|scope| {
+        for _ in 0..options.threads.max(1) {
+            scope.spawn(|| {
+                while let Some((path, depth)) = queue.pop(stop) {
+                    let mut children = Vec::new();
+                    match fs::symlink_metadata(&path) {
+                        Ok(meta) if meta.is_dir() && (depth == 0 || !validator.is_denied(&path)) => {
+                            if depth > 0 {
+                                on_entry(&path, &meta);
+                            }
+                            children = enter(&path, &meta, depth);
+                        }
+                        Ok(meta) if meta.is_file() && validator.can_read(&path).unwrap_or(false) => {
+                            on_entry(&path, &meta);
+                        }
+                        Ok(meta) if meta.is_symlink() => {
+                            if let Some((target, meta)) = linked_dir(&path, validator) {
+                                on_entry(&path, &meta);
+                                children = enter(&target, &meta, depth);
+                            }
+                        }
+                        // Special files are skipped
+                        _ => {}
+                    }
+                    queue.finish(children);
+                }
+            });
+        }
+    });
+}
+
+/// Where the symlink `path` leads, and its metadata, if that is a
+/// directory that may be read and can be told apart by inode
+fn linked_dir(path: &Path, validator: &PathValidator) -> Option<(PathBuf, Metadata)> {
+    let target = fs::canonicalize(path).ok()?;
+    let metadata = fs::metadata(&target).ok().filter(Metadata::is_dir)?;
+    inodes::key(&metadata)?;
+    let allowed = !validator.is_denied(&target) && validator.can_read(&target).unwrap_or(false);
+    allowed.then_some((target, metadata))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::policy::FilesystemPolicy;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    fn paths(root: &Path, options: WalkOptions, validator: &PathValidator) -> Vec<String> {
+        let found = Mutex::new(Vec::new());
+        walk(root, options, validator, &|| false, &|path, _| {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            found.lock().unwrap().push(relative.to_string_lossy().into_owned());
+        });
+        let mut found = found.into_inner().unwrap();
+        found.sort();
+        found
+    }
+
+    fn validator(allowed: &Path) -> PathValidator {
+        PathValidator::new(FilesystemPolicy {
+            allowed_read: vec![allowed.to_string_lossy().into_owned()],
+            denied_patterns: vec!["*/private".to_string()],
+            ..Default::default()
+        })
+    }
+
+    const RECURSIVE: WalkOptions = WalkOptions {
+        threads: 4,
+        max_depth: usize::MAX,
+    };
+
+    #[test]
+    fn test_symlink_loops_are_entered_once() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/file.txt"), "x").unwrap();
+        symlink(&root, root.join("a/b/up")).unwrap();
+        symlink(root.join("a"), root.join("a/b/again")).unwrap();
+
+        let found = paths(&root, RECURSIVE, &validator(&root));
+        assert_eq!(found, ["a", "a/b", "a/b/again", "a/b/file.txt", "a/b/up"]);
+    }
+
+    #[test]
+    fn test_links_are_followed_only_to_readable_directories() {
+        let dir = TempDir::new().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let root = base.join("work");
+        fs::create_dir_all(root.join("private")).unwrap();
+        fs::create_dir_all(root.join("shared")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::write(root.join("private/key"), "x").unwrap();
+        fs::write(root.join("shared/notes.md"), "x").unwrap();
+        fs::write(base.join("outside/other.txt"), "x").unwrap();
+        symlink(base.join("outside"), root.join("out")).unwrap();
+        symlink(root.join("private"), root.join("hidden")).unwrap();
+        symlink(root.join("shared"), root.join("linked")).unwrap();
+        symlink(root.join("shared/notes.md"), root.join("file-link")).unwrap();
+
+        // What lies below a link is reported at its real path, once
+        let found = paths(&root, RECURSIVE, &validator(&root));
+        assert_eq!(found, ["linked", "shared", "shared/notes.md"]);
+
+        let top = WalkOptions { max_depth: 1, ..RECURSIVE };
+        assert_eq!(paths(&root, top, &validator(&root)), ["linked", "shared"]);
+    }
+}
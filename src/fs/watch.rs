@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::verified::{self, PathCheck};
+use crate::network::Denial;
+
+/// Largest chunk of appended data sent in one event
+pub const MAX_CHUNK_BYTES: usize = 64 * 1024;
+/// Appended data read per poll; the rest is picked up by the next poll
+const MAX_BYTES_PER_POLL: usize = 1024 * 1024;
+
+/// What changed in a watched file since the previous poll
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Appended { offset: u64, data: Vec<u8> },
+    /// The file shrank; reading restarts from the beginning
+    Truncated,
+    /// Another file now lives at the path, e.g. after a rename or log
+    /// rotation; reading restarts at the beginning of the new file
+    Replaced,
+    Deleted,
+}
+
+/// Follows a single file by polling its metadata.
+///
+/// Replacement is detected through the device and inode numbers, so it is
+/// only reported on Unix. The file is opened, and reopened after a
+/// replacement, through [`verified::open_verified`] with `allowed`, so a
+/// symlink swapped in fails the poll with a [`verified::PathSwapped`].
+pub struct FileWatcher {
+    path: PathBuf,
+    file: File,
+    offset: u64,
+    identity: Option<(u64, u64)>,
+    allowed: Box<PathCheck<'static>>,
+}
+
+impl FileWatcher {
+    /// Starts at the end of the file unless `from_start` is set
+    pub fn open(path: &Path, from_start: bool, allowed: Box<PathCheck<'static>>) -> io::Result<Self> {
+        let file = verified::open_verified(path, OpenOptions::new().read(true), &*allowed)?;
+        let metadata = file.metadata()?;
+        if metadata.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a directory", path.display()),
+            ));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            offset: if from_start { 0 } else { metadata.len() },
+            identity: identity(&metadata),
+            file,
+            allowed,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn poll(&mut self) -> io::Result<Vec<Change>> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![Change::Deleted]),
+            Err(e) => return Err(e),
+        };
+
+        let mut changes = Vec::new();
+        if identity(&metadata) != self.identity {
+            self.file = verified::open_verified(&self.path, OpenOptions::new().read(true), &*self.allowed)?;
+            self.identity = identity(&self.file.metadata()?);
+            self.offset = 0;
+            changes.push(Change::Replaced);
+        } else if metadata.len() < self.offset {
+            self.offset = 0;
+            changes.push(Change::Truncated);
+        }
+
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        let mut remaining = MAX_BYTES_PER_POLL;
+        while remaining > 0 {
+            let mut data = vec![0; MAX_CHUNK_BYTES.min(remaining)];
+            let n = self.file.read(&mut data)?;
+            if n == 0 {
+                break;
+            }
+            data.truncate(n);
+            changes.push(Change::Appended {
+                offset: self.offset,
+                data,
+            });
+            self.offset += n as u64;
+            remaining -= n;
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Counts open watches per client against `max_watches_per_client`
+pub struct WatchRegistry {
+    max_per_client: usize,
+    counts: Mutex<HashMap<String, usize>>,
+}
+
+impl WatchRegistry {
+    pub fn new(max_per_client: u32) -> Arc<Self> {
+        Arc::new(Self {
+            max_per_client: max_per_client as usize,
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Reserves a watch for `client`; dropping the slot releases it
+    pub fn acquire(self: &Arc<Self>, client: &str) -> Result<WatchSlot, Denial> {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        let count = counts.entry(client.to_string()).or_insert(0);
+        if *count >= self.max_per_client {
+            return Err(Denial::new(
+                "watch_limit_reached",
+                format!("{} watches already open for this client", self.max_per_client),
+            ));
+        }
+        *count += 1;
+        Ok(WatchSlot {
+            registry: Arc::clone(self),
+            client: client.to_string(),
+        })
+    }
+
+    pub fn active(&self) -> usize {
+        self.counts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .sum()
+    }
+}
+
+pub struct WatchSlot {
+    registry: Arc<WatchRegistry>,
+    client: String,
+}
+
+impl Drop for WatchSlot {
+    fn drop(&mut self) {
+        let mut counts = self.registry.counts.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = counts.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.client);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn anywhere() -> Box<PathCheck<'static>> {
+        Box::new(|_| true)
+    }
+
+    fn appended(changes: &[Change]) -> Vec<u8> {
+        changes
+            .iter()
+            .filter_map(|c| match c {
+                Change::Appended { data, .. } => Some(data.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    #[test]
+    fn test_follows_appends_and_truncation() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("build.log");
+        fs::write(&path, "old line\n").unwrap();
+
+        let mut watcher = FileWatcher::open(&path, false, anywhere()).unwrap();
+        assert!(watcher.poll().unwrap().is_empty());
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"step 1\n").unwrap();
+        let changes = watcher.poll().unwrap();
+        assert_eq!(
+            changes,
+            vec![Change::Appended {
+                offset: 9,
+                data: b"step 1\n".to_vec()
+            }]
+        );
+
+        fs::write(&path, "new\n").unwrap();
+        let changes = watcher.poll().unwrap();
+        assert_eq!(changes[0], Change::Truncated);
+        assert_eq!(appended(&changes), b"new\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_detects_replacement_and_deletion() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("app.log");
+        fs::write(&path, "before rotation\n").unwrap();
+        let mut watcher = FileWatcher::open(&path, true, anywhere()).unwrap();
+        assert_eq!(appended(&watcher.poll().unwrap()), b"before rotation\n");
+
+        let rotated = dir.path().join("app.log.new");
+        fs::write(&rotated, "after\n").unwrap();
+        fs::rename(&rotated, &path).unwrap();
+        let changes = watcher.poll().unwrap();
+        assert_eq!(changes[0], Change::Replaced);
+        assert_eq!(appended(&changes), b"after\n");
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll().unwrap(), vec![Change::Deleted]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_swapped_in_is_not_followed() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let (path, secret) = (root.join("app.log"), root.join("secret.txt"));
+        fs::write(&path, "log\n").unwrap();
+        fs::write(&secret, "secret\n").unwrap();
+        let log = path.clone();
+        let mut watcher = FileWatcher::open(&path, true, Box::new(move |resolved| resolved == log)).unwrap();
+        assert_eq!(appended(&watcher.poll().unwrap()), b"log\n");
+
+        // Rotated into a symlink to a file the watch may not read
+        fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink(&secret, &path).unwrap();
+        let err = watcher.poll().unwrap_err();
+        assert_eq!(verified::swapped(&err).unwrap().denial().violation, "toctou_detected");
+
+        // A swapped parent directory is caught by the descriptor check
+        fs::create_dir(root.join("logs")).unwrap();
+        let nested = root.join("logs/app.log");
+        fs::write(&nested, "log\n").unwrap();
+        let allowed = nested.clone();
+        let mut watcher = FileWatcher::open(&nested, true, Box::new(move |resolved| resolved == allowed)).unwrap();
+        fs::remove_dir_all(root.join("logs")).unwrap();
+        fs::create_dir(root.join("elsewhere")).unwrap();
+        fs::write(root.join("elsewhere/app.log"), "other\n").unwrap();
+        std::os::unix::fs::symlink(root.join("elsewhere"), root.join("logs")).unwrap();
+        let err = watcher.poll().unwrap_err();
+        assert_eq!(verified::swapped(&err).unwrap().resolved, root.join("elsewhere/app.log"));
+    }
+
+    #[test]
+    fn test_registry_limits_and_releases() {
+        let registry = WatchRegistry::new(2);
+        let first = registry.acquire("session-a").unwrap();
+        let _second = registry.acquire("session-a").unwrap();
+        let denial = registry.acquire("session-a").err().unwrap();
+        assert_eq!(denial.violation, "watch_limit_reached");
+        let _other = registry.acquire("session-b").unwrap();
+        assert_eq!(registry.active(), 3);
+
+        drop(first);
+        assert_eq!(registry.active(), 2);
+        assert!(registry.acquire("session-a").is_ok());
+    }
+}
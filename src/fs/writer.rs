@@ -0,0 +1,586 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::verified::{self, PathCheck};
+use crate::policy::FilesystemPolicy;
+
+/// Mode for directories created on the way to a written file
+pub const DIR_MODE: u32 = 0o700;
+
+/// What a write does with a file that already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Atomically replace the file
+    #[default]
+    Overwrite,
+    /// Add to the end of the file
+    Append,
+    /// Fail with `AlreadyExists` instead of touching an existing file
+    CreateNew,
+}
+
+/// How `write_file` applies its data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    pub mode: WriteMode,
+    /// fsync the file and its directory before returning
+    pub sync: bool,
+}
+
+/// Writes files on behalf of the agent.
+///
+/// Replacing writes go to a temporary file in the destination directory
+/// that is renamed over the target, so a crash leaves either the old or the
+/// new content, never a mix. Appends use `O_APPEND` on the file itself, and
+/// `create_new` writes use `O_EXCL` so an existing file is never clobbered.
+///
+/// Newly created files get the policy's `write_mode` and any missing parent
+/// directories get [`DIR_MODE`], independently of the process umask. Existing
+/// files keep their permissions. Modes are only applied on Unix.
+///
+/// With a check set, the file actually opened is verified against it
+/// before any data is written; see [`verified`]. Missing parents are only
+/// created once the check accepts where the file will land. A guard is
+/// verified the same way, but an approval does not lift it.
+pub struct FileWriter {
+    file_mode: u32,
+    next_temp: AtomicU64,
+    check: Option<Arc<PathCheck<'static>>>,
+    guard: Option<Arc<PathCheck<'static>>>,
+}
+
+impl FileWriter {
+    pub fn new(policy: &FilesystemPolicy) -> Self {
+        Self {
+            file_mode: policy.write_mode,
+            next_temp: AtomicU64::new(0),
+            check: None,
+            guard: None,
+        }
+    }
+
+    pub fn with_check(mut self, check: Arc<PathCheck<'static>>) -> Self {
+        self.check = Some(check);
+        self
+    }
+
+    /// A check no approval lifts, such as the daemon's protection of its
+    /// own files
+    pub fn with_guard(mut self, guard: Arc<PathCheck<'static>>) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    pub fn write(&self, path: &Path, data: &[u8], options: WriteOptions) -> io::Result<()> {
+        self.write_as(path, data, options, None)
+    }
+
+    /// Like [`write`](Self::write), but the check also accepts `approved`,
+    /// the resolved path of a write a human allowed despite the policy
+    pub fn write_approved(&self, path: &Path, data: &[u8], options: WriteOptions, approved: &Path) -> io::Result<()> {
+        self.write_as(path, data, options, Some(approved))
+    }
+
+    fn write_as(&self, path: &Path, data: &[u8], options: WriteOptions, approved: Option<&Path>) -> io::Result<()> {
+        let allows = |resolved: &Path| self.allows(resolved, approved);
+        self.write_checked(path, data, options, self.checked(&allows))
+    }
+
+    /// Starts a write that replaces `path` with data given in pieces, for
+    /// a write too large to hold at once. `approved` is as for
+    /// [`write_approved`](Self::write_approved).
+    pub fn begin_replace(&self, path: &Path, approved: Option<&Path>) -> io::Result<PendingReplace> {
+        let allows = |resolved: &Path| self.allows(resolved, approved);
+        let check = self.checked(&allows);
+        create_parents(path, check)?;
+        self.start_replace(path, check)
+    }
+
+    /// `allows` as the check to verify opened files with, unless neither a
+    /// check nor a guard is set
+    fn checked<'a>(&self, allows: &'a PathCheck<'a>) -> Option<&'a PathCheck<'a>> {
+        (self.check.is_some() || self.guard.is_some()).then_some(allows)
+    }
+
+    /// Whether the file opened at `resolved` may be written: the check or
+    /// the approval, and the guard
+    fn allows(&self, resolved: &Path, approved: Option<&Path>) -> bool {
+        let checked = approved == Some(resolved) || self.check.as_ref().is_none_or(|check| check(resolved));
+        checked && self.guard.as_ref().is_none_or(|guard| guard(resolved))
+    }
+
+    fn write_checked(
+        &self,
+        path: &Path,
+        data: &[u8],
+        options: WriteOptions,
+        check: Option<&PathCheck<'_>>,
+    ) -> io::Result<()> {
+        create_parents(path, check)?;
+
+        match options.mode {
+            WriteMode::Overwrite => self.replace_with(path, options.sync, check, |file| file.write_all(data)),
+            WriteMode::Append => self.append(path, data, options.sync, check),
+            WriteMode::CreateNew => {
+                let mut file = self.create_checked(path, false, check)?;
+                file.write_all(data)?;
+                if options.sync {
+                    file.sync_all()?;
+                    sync_dir(path.parent().unwrap_or(Path::new(".")))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn append(&self, path: &Path, data: &[u8], sync: bool, check: Option<&PathCheck<'_>>) -> io::Result<()> {
+        let mut file = match self.create_checked(path, true, check) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match check {
+                Some(check) => verified::open_verified(path, OpenOptions::new().append(true), check)?,
+                None => OpenOptions::new().append(true).open(path)?,
+            },
+            Err(e) => return Err(e),
+        };
+        file.write_all(data)?;
+        if sync {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Fills a temporary sibling of `path` and renames it into place. The
+    /// temporary file is removed if any step fails.
+    fn replace_with<F>(&self, path: &Path, sync: bool, check: Option<&PathCheck<'_>>, fill: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut File) -> io::Result<()>,
+    {
+        let mut pending = self.start_replace(path, check)?;
+        fill(&mut pending.file)?;
+        pending.commit(sync)
+    }
+
+    /// Creates the temporary sibling of `path` a replacing write fills
+    fn start_replace(&self, path: &Path, check: Option<&PathCheck<'_>>) -> io::Result<PendingReplace> {
+        let mode = existing_mode(path)?.unwrap_or(self.file_mode);
+        let temp = self.temp_path(path)?;
+        let file = self.create_new(&temp, mode, false)?;
+        let mut pending = PendingReplace {
+            file,
+            temp,
+            target: path.to_path_buf(),
+            done: false,
+        };
+        // Rename within the directory the temporary file really landed in,
+        // once that directory is known to be allowed
+        if let Some(check) = check {
+            let name = pending.target.file_name().map(PathBuf::from).unwrap_or_default();
+            let check_target = |resolved: &Path| check(&resolved.with_file_name(&name));
+            let resolved =
+                verified::verify_opened(&pending.file, &pending.temp, &check_target).map_err(|e| retarget(e, path))?;
+            pending.target = resolved.with_file_name(&name);
+            pending.temp = resolved;
+        }
+        Ok(pending)
+    }
+
+    /// A hidden name next to `path`; being in the same directory it is
+    /// covered by the same allow entry and stays on the same filesystem
+    fn temp_path(&self, path: &Path) -> io::Result<PathBuf> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no file name", path.display()),
+            )
+        })?;
+        let n = self.next_temp.fetch_add(1, Ordering::Relaxed);
+        let temp = format!(".{}.tmp-{}-{}", name.to_string_lossy(), std::process::id(), n);
+        Ok(path.with_file_name(temp))
+    }
+
+    /// Creates `path` with the policy's mode and verifies it against the check
+    fn create_checked(&self, path: &Path, append: bool, check: Option<&PathCheck<'_>>) -> io::Result<File> {
+        let file = self.create_new(path, self.file_mode, append)?;
+        if let Some(check) = check {
+            // An empty file is left where it landed: removing `path` again
+            // could remove whatever it names by now instead
+            verified::verify_opened(&file, path, check)?;
+        }
+        Ok(file)
+    }
+
+    fn create_new(&self, path: &Path, mode: u32, append: bool) -> io::Result<File> {
+        let mut options = OpenOptions::new();
+        options.create_new(true);
+        if append {
+            options.append(true);
+        } else {
+            options.write(true);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+
+        let file = options.open(path)?;
+        // The umask may have cleared bits the policy asked for
+        set_file_mode(&file, mode)?;
+        Ok(file)
+    }
+}
+
+/// A replacing write under way: data goes to a temporary sibling of the
+/// target, renamed over it by [`commit`](Self::commit). Dropped before
+/// then, as when a stream of data is cut off, the temporary file is removed
+/// and the target left as it was.
+pub struct PendingReplace {
+    file: File,
+    temp: PathBuf,
+    target: PathBuf,
+    done: bool,
+}
+
+impl PendingReplace {
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)
+    }
+
+    pub fn commit(mut self, sync: bool) -> io::Result<()> {
+        if sync {
+            self.file.sync_all()?;
+        }
+        fs::rename(&self.temp, &self.target)?;
+        self.done = true;
+        if sync {
+            sync_dir(self.target.parent().unwrap_or(Path::new(".")))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PendingReplace {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Reports a swap detected on the temporary file against the requested path
+fn retarget(err: io::Error, path: &Path) -> io::Error {
+    match verified::swapped(&err) {
+        Some(swapped) => {
+            let resolved = swapped.resolved.with_file_name(path.file_name().unwrap_or_default());
+            io::Error::new(
+                err.kind(),
+                verified::PathSwapped {
+                    path: path.to_path_buf(),
+                    resolved,
+                },
+            )
+        }
+        None => err,
+    }
+}
+
+/// Permission bits of an existing file, to carry over to its replacement
+#[cfg(unix)]
+fn existing_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata.permissions().mode() & 0o7777)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn existing_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+/// Persists the rename itself
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Creates the missing parent directories of `path`, once the check
+/// accepts where `path` lands under the nearest one that exists
+fn create_parents(path: &Path, check: Option<&PathCheck<'_>>) -> io::Result<()> {
+    if let Some(check) = check {
+        verified::verify_target(path, check)?;
+    }
+    match path.parent() {
+        Some(parent) => create_dirs(parent),
+        None => Ok(()),
+    }
+}
+
+/// Creates every missing ancestor of `dir`, outermost first
+pub fn create_dirs(dir: &Path) -> io::Result<()> {
+    let missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+        .map(Path::to_path_buf)
+        .collect();
+
+    for dir in missing.iter().rev() {
+        match fs::create_dir(dir) {
+            Ok(()) => set_mode(dir, DIR_MODE)?,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Like [`set_mode`], but on the file opened rather than a path
+#[cfg(unix)]
+fn set_file_mode(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_file: &File, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn mode_of(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn test_new_files_and_dirs_get_restricted_modes() {
+        let dir = TempDir::new().unwrap();
+        let writer = FileWriter::new(&FilesystemPolicy::default());
+        let path = dir.path().join("a/b/secret.txt");
+
+        writer.write(&path, b"token", WriteOptions::default()).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"token");
+        assert_eq!(mode_of(&path), 0o600);
+        assert_eq!(mode_of(&dir.path().join("a")), DIR_MODE);
+        assert_eq!(mode_of(&dir.path().join("a/b")), DIR_MODE);
+    }
+
+    #[test]
+    fn test_configured_mode_and_existing_files() {
+        let dir = TempDir::new().unwrap();
+        let writer = FileWriter::new(&FilesystemPolicy {
+            write_mode: 0o640,
+            ..Default::default()
+        });
+        let path = dir.path().join("report.txt");
+
+        writer.write(&path, b"first", WriteOptions::default()).unwrap();
+        assert_eq!(mode_of(&path), 0o640);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        writer.write(&path, b"second", WriteOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(mode_of(&path), 0o644);
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_failed_write_leaves_original_intact() {
+        let dir = TempDir::new().unwrap();
+        let writer = FileWriter::new(&FilesystemPolicy::default());
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "original = true\n").unwrap();
+
+        let data = b"replacement = true\n";
+        let err = writer
+            .replace_with(&path, true, None, |file| {
+                file.write_all(&data[..8])?;
+                Err(io::Error::other("injected failure"))
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "injected failure");
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original = true\n");
+        // The temporary file was cleaned up
+        assert_eq!(entries(dir.path()), vec!["config.toml"]);
+    }
+
+    #[test]
+    fn test_pending_replace_commits_or_leaves_nothing() {
+        let dir = TempDir::new().unwrap();
+        let writer = FileWriter::new(&FilesystemPolicy::default());
+        let path = dir.path().join("upload.bin");
+        fs::write(&path, "original").unwrap();
+
+        let mut pending = writer.begin_replace(&path, None).unwrap();
+        pending.write(b"first ").unwrap();
+        assert_eq!(entries(dir.path()).len(), 2);
+        drop(pending);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(entries(dir.path()), vec!["upload.bin"]);
+
+        let mut pending = writer.begin_replace(&path, None).unwrap();
+        pending.write(b"first ").unwrap();
+        pending.write(b"second").unwrap();
+        pending.commit(false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first second");
+        assert_eq!(entries(dir.path()), vec!["upload.bin"]);
+    }
+
+    #[test]
+    fn test_replace_is_complete_with_sync() {
+        let dir = TempDir::new().unwrap();
+        let writer = FileWriter::new(&FilesystemPolicy::default());
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "original").unwrap();
+
+        let options = WriteOptions {
+            sync: true,
+            ..Default::default()
+        };
+        writer.write(&path, b"new", options).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(entries(dir.path()), vec!["config.toml"]);
+    }
+
+    #[test]
+    fn test_append_creates_then_extends() {
+        let dir = TempDir::new().unwrap();
+        let writer = FileWriter::new(&FilesystemPolicy::default());
+        let path = dir.path().join("log.txt");
+        let options = WriteOptions {
+            mode: WriteMode::Append,
+            ..Default::default()
+        };
+
+        writer.write(&path, b"one\n", options).unwrap();
+        writer.write(&path, b"two\n", options).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        assert_eq!(mode_of(&path), 0o600);
+    }
+
+    #[test]
+    fn test_checked_writer_refuses_swapped_directory() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("allowed")).unwrap();
+        fs::create_dir_all(root.join("forbidden")).unwrap();
+        std::os::unix::fs::symlink(root.join("forbidden"), root.join("allowed/sub")).unwrap();
+
+        let allowed = root.join("allowed");
+        let writer = FileWriter::new(&FilesystemPolicy::default())
+            .with_check(Arc::new(move |path| path.starts_with(&allowed)));
+        let path = root.join("allowed/sub/config.toml");
+
+        let err = writer.write(&path, b"x", WriteOptions::default()).unwrap_err();
+        let swapped = verified::swapped(&err).expect("swap detected");
+        assert_eq!(swapped.path, path);
+        assert_eq!(swapped.resolved, root.join("forbidden/config.toml"));
+        assert!(entries(&root.join("forbidden")).is_empty());
+
+        let options = WriteOptions {
+            mode: WriteMode::Append,
+            ..Default::default()
+        };
+        assert!(writer.write(&path, b"x", options).is_err());
+        assert!(entries(&root.join("forbidden")).is_empty());
+        assert!(writer.write(&root.join("allowed/ok.txt"), b"x", options).is_ok());
+    }
+
+    #[test]
+    fn test_checked_writer_creates_no_dirs_through_symlinks() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("allowed")).unwrap();
+        fs::create_dir_all(root.join("forbidden")).unwrap();
+        std::os::unix::fs::symlink(root.join("forbidden"), root.join("allowed/sub")).unwrap();
+
+        let allowed = root.join("allowed");
+        let writer = FileWriter::new(&FilesystemPolicy::default())
+            .with_check(Arc::new(move |path| path.starts_with(&allowed)));
+        let path = root.join("allowed/sub/new/deeper/config.toml");
+
+        let err = writer.write(&path, b"x", WriteOptions::default()).unwrap_err();
+        assert_eq!(verified::swapped(&err).unwrap().resolved, root.join("forbidden/new/deeper/config.toml"));
+        assert!(writer.begin_replace(&path, None).is_err());
+        assert!(entries(&root.join("forbidden")).is_empty());
+
+        writer.write(&root.join("allowed/new/config.toml"), b"x", WriteOptions::default()).unwrap();
+        assert_eq!(mode_of(&root.join("allowed/new")), DIR_MODE);
+    }
+
+    #[test]
+    fn test_guard_holds_for_approved_and_swapped_paths() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let guarded = root.join("policy.toml");
+        fs::write(&guarded, "original").unwrap();
+        fs::create_dir(root.join("allowed")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("allowed/sub")).unwrap();
+
+        let refused = guarded.clone();
+        let writer = FileWriter::new(&FilesystemPolicy::default())
+            .with_check(Arc::new(|_: &Path| true))
+            .with_guard(Arc::new(move |path| path != refused));
+        let via_link = root.join("allowed/sub/policy.toml");
+        let err = writer.write(&via_link, b"x", WriteOptions::default()).unwrap_err();
+        assert_eq!(verified::swapped(&err).unwrap().resolved, guarded);
+        assert!(writer.write_approved(&guarded, b"x", WriteOptions::default(), &guarded).is_err());
+        assert!(writer.begin_replace(&guarded, Some(&guarded)).is_err());
+        assert_eq!(fs::read_to_string(&guarded).unwrap(), "original");
+        assert!(writer.write(&root.join("allowed/ok.txt"), b"x", WriteOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_create_new_refuses_existing_file() {
+        let dir = TempDir::new().unwrap();
+        let writer = FileWriter::new(&FilesystemPolicy::default());
+        let path = dir.path().join("lock.json");
+        let options = WriteOptions {
+            mode: WriteMode::CreateNew,
+            sync: true,
+        };
+
+        writer.write(&path, b"{}", options).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        assert_eq!(mode_of(&path), 0o600);
+
+        let err = writer.write(&path, b"clobbered", options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+    }
+}
@@ -0,0 +1,126 @@
+//! Entry point shared by the `fuzz/` targets and the corpus test below:
+//! whatever the bytes, loading a policy either fails with an error or
+//! yields one the daemon can start, or hot-reload, with.
+
+use std::sync::OnceLock;
+
+use crate::grpc::server::EnforcementServiceImpl;
+use crate::policy::parser::{parse_policy, PolicyFormat, PolicySource};
+use crate::resources::monitor::ResourceMonitor;
+
+/// Parses `data` as TOML and as JSON, and builds the daemon's state from
+/// any policy that loads. Panics only on a bug.
+pub fn check(data: &[u8]) {
+    let Ok(content) = std::str::from_utf8(data) else {
+        return;
+    };
+    for format in [PolicyFormat::Toml, PolicyFormat::Json] {
+        let source = PolicySource::Inline(content.to_string());
+        if let Ok(policy) = parse_policy(content, format, &source) {
+            let _guard = runtime().enter();
+            ResourceMonitor::new(&policy);
+            let _ = EnforcementServiceImpl::new(policy);
+        }
+    }
+}
+
+/// Variations of a valid policy: each `key = value` line in turn given a
+/// hostile value, and each list grown past any sane size
+pub fn mutations(policy: &str) -> Vec<String> {
+    const VALUES: &[&str] = &[
+        "0",
+        "-1",
+        "18446744073709551615",
+        "9223372036854775807",
+        "4294967296",
+        "1e308",
+        "\"\"",
+        "\"[\"",
+        "\"**/**/**/**/**/**/**/**\"",
+        "\"/..\"",
+        "\"!\"",
+        "\"\\u0000\"",
+        "[[[[[[[[[[]]]]]]]]]]",
+        "{}",
+        "true",
+    ];
+    let lines: Vec<&str> = policy.lines().collect();
+    let mut out = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        // Commented-out settings are mutated as if they were set
+        let Some((key, value)) = line.trim_start_matches("# ").split_once(" = ") else {
+            continue;
+        };
+        if key.contains(['#', ' ']) {
+            continue;
+        }
+        let mut values: Vec<String> = VALUES.iter().map(|v| v.to_string()).collect();
+        if value.starts_with('[') {
+            values.push(format!("[\"/{}\"]", "a".repeat(64 * 1024)));
+            values.push(format!("[{}]", vec!["\"/srv/x\""; 10_001].join(",")));
+        }
+        for value in &values {
+            let mut mutated = lines.clone();
+            let replaced = format!("{} = {}", key, value);
+            mutated[i] = &replaced;
+            out.push(mutated.join("\n"));
+        }
+    }
+    out
+}
+
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("fuzzing runtime")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// The seed corpus, and the example policy
+    fn corpus() -> Vec<(String, Vec<u8>)> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let dir = root.join("fuzz/corpus/policy_parser");
+        let mut entries: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap_or_else(|e| panic!("{}: {}", dir.display(), e))
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+        entries.push(root.join("examples/policy.toml"));
+        entries
+            .into_iter()
+            .map(|path| (path.display().to_string(), std::fs::read(&path).unwrap()))
+            .collect()
+    }
+
+    /// Runs every corpus file, and the mutations of those that are valid
+    /// policies, without libFuzzer; `cargo fuzz run policy_parser` goes
+    /// further
+    #[test]
+    fn test_policy_corpus_does_not_panic() {
+        let corpus = corpus();
+        assert!(!corpus.is_empty());
+        let mut panicked = Vec::new();
+        for (name, data) in &corpus {
+            if std::panic::catch_unwind(|| check(data)).is_err() {
+                panicked.push(name.clone());
+            }
+            let Ok(content) = std::str::from_utf8(data) else {
+                continue;
+            };
+            for (i, mutated) in mutations(content).iter().enumerate() {
+                if std::panic::catch_unwind(|| check(mutated.as_bytes())).is_err() {
+                    panicked.push(format!("mutation {} of {}", i, name));
+                }
+            }
+        }
+        assert!(panicked.is_empty(), "panicked: {:#?}", panicked);
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tonic::transport::{Channel, Endpoint};
+
+use crate::network::Denial;
+use crate::policy::{EscalationMode, EscalationPolicy};
+use crate::proto::openclaw::enforce::approval_service_client::ApprovalServiceClient;
+use crate::proto::openclaw::enforce::{ApprovalDecision, ApprovalRequest};
+
+/// Operations whose denials can be escalated
+pub const ESCALATABLE_OPERATIONS: &[&str] = &["read_file", "write_file", "edit_file", "delete_file", "execute_command"];
+
+/// Violations that no approval overrides
+const FINAL_VIOLATIONS: &[&str] = &["malformed_path", "toctou_detected", "internal_error", "self_protection"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Deny,
+    AllowOnce,
+    AllowSession(Duration),
+}
+
+/// The answer to an escalation, with who gave it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Approval {
+    pub verdict: Verdict,
+    pub approver: String,
+    /// Why there was no usable answer, e.g. a timeout; the verdict is then `Deny`
+    pub failure: Option<String>,
+}
+
+impl Approval {
+    fn failed(failure: impl Into<String>) -> Self {
+        Self {
+            verdict: Verdict::Deny,
+            approver: String::new(),
+            failure: Some(failure.into()),
+        }
+    }
+
+    pub fn allowed(&self) -> bool {
+        self.verdict != Verdict::Deny
+    }
+
+    /// Audit metadata value for the outcome
+    pub fn outcome(&self) -> &'static str {
+        match (self.verdict, &self.failure) {
+            (Verdict::Deny, Some(_)) => "no_answer",
+            (Verdict::Deny, None) => "deny",
+            (Verdict::AllowOnce, _) => "allow_once",
+            (Verdict::AllowSession(_), _) => "allow_session",
+        }
+    }
+}
+
+/// Offers denials to the embedder's `ApprovalService` (`[escalation]`).
+///
+/// Anything but a clear allow within the timeout is a denial: an
+/// unreachable service, an error status, an unknown decision and an
+/// `allow_session` without a TTL all deny.
+pub struct Approver {
+    endpoint: Endpoint,
+    /// Connected on first use, so building the policy state needs no runtime
+    channel: OnceLock<Channel>,
+    operations: HashSet<String>,
+    timeout: Duration,
+    max_grant: Duration,
+}
+
+impl Approver {
+    /// `None` unless the policy turns escalation on
+    pub fn from_policy(policy: &EscalationPolicy) -> Result<Option<Self>> {
+        if policy.mode == EscalationMode::Off {
+            return Ok(None);
+        }
+        let address = policy
+            .address
+            .as_deref()
+            .context("escalation.address is required when mode = \"callback\"")?;
+        let timeout = Duration::from_secs(policy.timeout_secs);
+        let endpoint = Endpoint::from_shared(address.to_string())
+            .with_context(|| format!("Invalid escalation.address {:?}", address))?
+            .connect_timeout(timeout);
+        Ok(Some(Self {
+            endpoint,
+            channel: OnceLock::new(),
+            operations: policy.operations.iter().cloned().collect(),
+            timeout,
+            max_grant: Duration::from_secs(policy.max_grant_secs),
+        }))
+    }
+
+    pub fn escalates(&self, operation: &str, denial: &Denial) -> bool {
+        self.operations.contains(operation) && !FINAL_VIOLATIONS.contains(&denial.violation)
+    }
+
+    pub async fn request(&self, request: ApprovalRequest) -> Approval {
+        let channel = self.channel.get_or_init(|| self.endpoint.connect_lazy()).clone();
+        let mut client = ApprovalServiceClient::new(channel);
+        let response = match tokio::time::timeout(self.timeout, client.request_approval(request)).await {
+            Err(_) => return Approval::failed(format!("no answer within {}s", self.timeout.as_secs())),
+            Ok(Err(status)) => return Approval::failed(format!("approval service error: {}", status.message())),
+            Ok(Ok(response)) => response.into_inner(),
+        };
+
+        let verdict = match ApprovalDecision::try_from(response.decision) {
+            Ok(ApprovalDecision::Deny) => Verdict::Deny,
+            Ok(ApprovalDecision::AllowOnce) => Verdict::AllowOnce,
+            Ok(ApprovalDecision::AllowSession) if response.ttl_secs > 0 => {
+                Verdict::AllowSession(Duration::from_secs(response.ttl_secs).min(self.max_grant))
+            }
+            Ok(ApprovalDecision::AllowSession) => return Approval::failed("allow_session without ttl_secs"),
+            Err(_) => return Approval::failed(format!("unknown decision {}", response.decision)),
+        };
+        Approval {
+            verdict,
+            approver: response.approver,
+            failure: None,
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! The daemon as a client sees it, over a real connection
+
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::HealthCheckRequest;
+
+use super::test_env::TestEnv;
+use crate::proto::openclaw::enforce::*;
+
+fn read(path: &str) -> ReadFileRequest {
+    ReadFileRequest {
+        path: path.to_string(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_read_file() {
+    let mut env = TestEnv::with_policy(|policy| policy.writable()).await;
+    let path = env.write("notes/today.txt", "hello");
+
+    let response = env.client.read_file(read(&path)).await.unwrap().into_inner();
+    let status = response.status.unwrap();
+    assert!(status.allowed, "{}", status.reason);
+    assert_eq!(status.reason_code(), ReasonCode::AccessGranted);
+    assert_eq!(response.data, b"hello");
+    assert_eq!(response.encoding_guess, "utf-8");
+
+    let binary = env.write("notes/logo.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+    let response = env.client.read_file(read(&binary)).await.unwrap().into_inner();
+    assert_eq!(response.encoding_guess, "binary");
+    // Parts are only guessed at when asked
+    let part = ReadFileRequest { length: 4, ..read(&binary) };
+    assert!(env.client.read_file(part.clone()).await.unwrap().into_inner().encoding_guess.is_empty());
+    let part = ReadFileRequest { detect_encoding: true, offset: 8, ..part };
+    assert_eq!(env.client.read_file(part).await.unwrap().into_inner().encoding_guess, "binary");
+
+    // What a write leaves behind is what the next read returns
+    let write = WriteFileRequest {
+        path: path.clone(),
+        data: b"updated".to_vec(),
+        ..Default::default()
+    };
+    assert!(env.client.write_file(write).await.unwrap().into_inner().status.unwrap().allowed);
+    let response = env.client.read_file(read(&path)).await.unwrap().into_inner();
+    assert_eq!(response.data, b"updated");
+}
+
+#[tokio::test]
+async fn test_snapshot_sessions_flag_or_deny_changed_files() {
+    let mut env = TestEnv::with_policy(|policy| policy.writable()).await;
+    let path = env.write("inputs/data.csv", "a,b\n1,2\n");
+    let mut sessions = Vec::new();
+    for snapshot in [SnapshotMode::Flag, SnapshotMode::Strict] {
+        let request = CreateSessionRequest {
+            snapshot: snapshot as i32,
+            ..Default::default()
+        };
+        sessions.push(env.client.create_session(request).await.unwrap().into_inner());
+    }
+    let session_read = |session: &SessionInfo| ReadFileRequest {
+        capability: Some(Capability {
+            session_id: session.session_id.clone(),
+            ..Default::default()
+        }),
+        ..read(&path)
+    };
+    for session in &sessions {
+        let response = env.client.read_file(session_read(session)).await.unwrap().into_inner();
+        assert!(response.status.unwrap().allowed && !response.changed_since_first_read);
+    }
+
+    env.write("inputs/data.csv", "a,b\n1,3\n");
+    let flagged = env.client.read_file(session_read(&sessions[0])).await.unwrap().into_inner();
+    assert!(flagged.status.unwrap().allowed && flagged.changed_since_first_read);
+    assert_eq!(flagged.data, b"a,b\n1,3\n");
+    let strict = env.client.read_file(session_read(&sessions[1])).await.unwrap().into_inner();
+    assert_eq!(strict.status.unwrap().reason_code(), ReasonCode::SnapshotViolation);
+    assert!(strict.data.is_empty());
+    // Outside a snapshot session the change goes unremarked
+    assert!(!env.client.read_file(read(&path)).await.unwrap().into_inner().changed_since_first_read);
+
+    let request = GetSessionRequest {
+        session_id: sessions[1].session_id.clone(),
+    };
+    let info = env.client.get_session(request).await.unwrap().into_inner();
+    assert_eq!((info.snapshot(), info.snapshot_paths), (SnapshotMode::Strict, 1));
+}
+
+#[tokio::test]
+async fn test_denied_reads() {
+    let mut env = TestEnv::with_policy(|policy| policy.deny("*.key")).await;
+    let key = env.write("server.key", "secret");
+
+    let cases = [
+        ("/etc/passwd".to_string(), "path_not_allowed"),
+        (key, "path_not_allowed"),
+        (env.path("../outside.txt"), "path_not_allowed"),
+    ];
+    for (path, violation) in cases {
+        let response = env.client.read_file(read(&path)).await.unwrap().into_inner();
+        let status = response.status.unwrap();
+        assert!(!status.allowed, "{}", path);
+        assert_eq!(status.violations, vec![violation], "{}", path);
+        assert_eq!(status.reason_code(), ReasonCode::PathNotAllowed, "{}", path);
+        assert!(response.data.is_empty(), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_denials_suggest_allowed_entries() {
+    let mut env = TestEnv::with_policy(|policy| policy.domain("api.github.com").command("sh")).await;
+
+    let connect = |host: &str, suggest| ConnectRequest {
+        host: host.to_string(),
+        port: 443,
+        protocol: ConnectProtocol::Tls as i32,
+        suggest,
+        ..Default::default()
+    };
+    let response = env.client.check_connect(connect("api.githb.com", true)).await.unwrap().into_inner();
+    assert_eq!(response.status.unwrap().violations, vec!["domain_not_allowed"]);
+    assert_eq!(response.suggestions, vec!["api.github.com"]);
+    let response = env.client.check_connect(connect("api.githb.com", false)).await.unwrap().into_inner();
+    assert!(response.suggestions.is_empty());
+
+    let execute = |command: &str| ExecuteCommandRequest {
+        command: command.to_string(),
+        suggest: true,
+        ..Default::default()
+    };
+    let response = env.client.execute_command(execute("ssh")).await.unwrap().into_inner();
+    assert!(!response.status.unwrap().allowed);
+    assert_eq!(response.suggestions, vec!["sh"]);
+    let response = env.client.execute_command(execute("kubectl")).await.unwrap().into_inner();
+    assert!(response.suggestions.is_empty());
+}
+
+#[tokio::test]
+async fn test_get_status() {
+    let mut env = TestEnv::with_policy(|policy| policy.toml("[sessions]\nmax_read_mb = 1")).await;
+    let session = env
+        .client
+        .create_session(CreateSessionRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(session.limits.unwrap().bytes_read, 1024 * 1024);
+
+    let status = env.client.get_status(StatusRequest {}).await.unwrap().into_inner();
+    assert!(status.healthy);
+    assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+    assert!(status.active_policy.unwrap().loaded_at > 0);
+    assert_eq!(status.resources.unwrap().active_sessions, 1);
+    assert!(status.proxy.is_none());
+    let create = status.rpc_latency.iter().find(|rpc| rpc.method.ends_with("/CreateSession")).unwrap();
+    assert_eq!(create.calls, 1);
+    #[cfg(target_os = "linux")]
+    assert_eq!(status.identity.unwrap().uid, nix::unistd::geteuid().as_raw());
+    assert_eq!(status.denials.unwrap(), DenialDigest::default());
+
+    for path in ["/etc/passwd", "/etc/shadow", "/etc/passwd"] {
+        assert!(!env.client.read_file(read(path)).await.unwrap().into_inner().status.unwrap().allowed);
+    }
+    let denials = env.client.get_status(StatusRequest {}).await.unwrap().into_inner().denials.unwrap();
+    let reads = &denials.operations[0];
+    assert_eq!((reads.operation.as_str(), reads.last_1m, reads.last_60m), ("read_file", 3, 3));
+    let top: Vec<_> = denials.top_targets.iter().map(|t| (t.target.as_str(), t.denials)).collect();
+    assert_eq!(top, [("/etc/passwd", 2), ("/etc/shadow", 1)]);
+    assert!(denials.last_denied_at > 0);
+}
+
+#[tokio::test]
+async fn test_audit_starts_with_the_policy_in_force() {
+    let mut env = TestEnv::with_policy(|policy| policy.domain("api.github.com")).await;
+    let request = AuditLogRequest {
+        event_types: vec!["policy_load".to_string()],
+        ..Default::default()
+    };
+    let mut stream = env.client.get_audit_logs(request).await.unwrap().into_inner();
+    let load = stream.message().await.unwrap().unwrap();
+    assert_eq!(load.event_type, "policy_load");
+    assert_eq!(load.metadata["allowed_domains"], "1");
+    assert_eq!(load.metadata["policy_hash"].len(), 64);
+    assert!(stream.message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_health_check() {
+    let env = TestEnv::start().await;
+    let mut health = env.health().await;
+    for service in ["", "grpc.health.v1.Health", "openclaw.enforce.EnforcementService"] {
+        let request = HealthCheckRequest {
+            service: service.to_string(),
+        };
+        let response = health.check(request).await.unwrap().into_inner();
+        assert_eq!(response.status(), ServingStatus::Serving, "{:?}", service);
+    }
+
+    let unknown = HealthCheckRequest {
+        service: "no.such.Service".to_string(),
+    };
+    assert_eq!(health.check(unknown).await.unwrap_err().code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_health_checks_are_answered_while_calls_are_shed() {
+    let mut env = TestEnv::with_policy(|policy| policy.toml("[server]\nmax_concurrent_requests = 1")).await;
+    let follow = AuditLogRequest {
+        follow: true,
+        ..Default::default()
+    };
+    // An open stream holds the only place
+    let stream = env.client.get_audit_logs(follow).await.unwrap().into_inner();
+    let shed = env.client.read_file(read("/tmp/anything")).await.unwrap_err();
+    assert_eq!(shed.code(), tonic::Code::ResourceExhausted);
+    assert!(shed.message().contains("max_concurrent_requests"), "{}", shed.message());
+
+    let mut health = env.health().await;
+    let check = HealthCheckRequest {
+        service: "openclaw.enforce.EnforcementService".to_string(),
+    };
+    assert_eq!(health.check(check).await.unwrap().into_inner().status(), ServingStatus::Serving);
+
+    // Cancelling the stream gives the place back
+    drop(stream);
+    for _ in 0..100 {
+        if env.client.read_file(read("/tmp/anything")).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    panic!("the cancelled stream kept its place");
+}
+
+#[tokio::test]
+async fn test_request_ids_and_oversized_targets() {
+    let mut env = TestEnv::start().await;
+    let mut request = tonic::Request::new(read("/tmp/anything"));
+    request.metadata_mut().insert("x-request-id", "trace-42".parse().unwrap());
+    let response = env.client.read_file(request).await.unwrap();
+    assert_eq!(response.metadata().get("x-request-id").unwrap(), "trace-42");
+
+    // Refused before it is decoded, and still correlated
+    let err = env.client.read_file(read(&format!("/{}", "a".repeat(1024 * 1024)))).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    assert!(err.message().starts_with("target_too_long: path is 1048577 bytes"), "{}", err.message());
+    assert_eq!(err.metadata().get("x-request-id").unwrap().len(), 36);
+}
+
+#[cfg(feature = "reflection")]
+#[tokio::test]
+async fn test_reflection_lists_services() {
+    use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+    let env = TestEnv::start().await;
+    let mut reflection = ServerReflectionClient::new(env.channel().await);
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut responses = reflection
+        .server_reflection_info(tokio_stream::iter([request]))
+        .await
+        .unwrap()
+        .into_inner();
+    let response = responses.message().await.unwrap().unwrap();
+    let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+        panic!("unexpected response: {:?}", response);
+    };
+    let names: Vec<String> = list.service.into_iter().map(|service| service.name).collect();
+    for service in ["openclaw.enforce.EnforcementService", "grpc.health.v1.Health"] {
+        assert!(names.iter().any(|name| name == service), "{:?}", names);
+    }
+}
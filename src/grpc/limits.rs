@@ -0,0 +1,500 @@
+use bytes::{Buf, Bytes};
+use http_body_util::{BodyExt, Full, Limited};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::Semaphore;
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::warn;
+
+use crate::policy::ServerPolicy;
+
+const SERVICE_PREFIX: &str = "/openclaw.enforce.EnforcementService/";
+
+/// Which bound a target field is held to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Paths, hostnames, commands and policy entries
+    Target,
+    Url,
+}
+
+/// Top-level string fields naming what a call acts on, by method: (field
+/// number, name, kind). Fields nested in a message, and content such as
+/// `data` or `body`, are not targets.
+fn targets(method: &str) -> &'static [(u32, &'static str, Kind)] {
+    use Kind::*;
+    match method {
+        "ReadFile" | "WriteFile" | "EditFile" | "ListDirectory" | "StatFile" | "DeleteFile" | "WatchFile" => {
+            &[(1, "path", Target)]
+        }
+        "RestoreFile" => &[(1, "trash_id", Target)],
+        "SearchFiles" => &[(1, "root", Target)],
+        "HttpRequest" => &[(1, "url", Url)],
+        "DnsLookup" => &[(1, "hostname", Target)],
+        "CheckConnect" => &[(1, "host", Target), (6, "url", Url)],
+        "ExecuteCommand" => &[(1, "command", Target), (4, "working_dir", Target)],
+        "AddPolicyEntry" | "RemovePolicyEntry" => &[(2, "entry", Target)],
+        _ => &[],
+    }
+}
+
+/// `[server] max_target_bytes` and `max_url_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetLimits {
+    pub max_target_bytes: usize,
+    pub max_url_bytes: usize,
+    /// The body buffered to find the targets, as `max_message_bytes` caps
+    /// the message
+    pub max_message_bytes: usize,
+}
+
+impl TargetLimits {
+    pub fn of(server: &ServerPolicy) -> Self {
+        Self {
+            max_target_bytes: server.max_target_bytes,
+            max_url_bytes: server.max_url_bytes,
+            max_message_bytes: server.max_message_bytes,
+        }
+    }
+
+    fn max(&self, kind: Kind) -> (usize, &'static str) {
+        match kind {
+            Kind::Target => (self.max_target_bytes, "max_target_bytes"),
+            Kind::Url => (self.max_url_bytes, "max_url_bytes"),
+        }
+    }
+
+    /// The error for the first target in `message` over its bound
+    fn check(&self, method: &str, message: &[u8]) -> Result<(), Box<Status>> {
+        let fields = targets(method);
+        for (number, len) in string_fields(message) {
+            let Some((_, name, kind)) = fields.iter().find(|(target, _, _)| *target == number) else {
+                continue;
+            };
+            let (max, setting) = self.max(*kind);
+            if len > max {
+                return Err(Box::new(Status::invalid_argument(format!(
+                    "target_too_long: {} is {} bytes, over [server] {} of {}",
+                    name, len, setting, max
+                ))));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Refuses a call whose path, URL or other target is longer than
+/// [`TargetLimits`] allow, with INVALID_ARGUMENT, before it is decoded or
+/// reaches a handler.
+///
+/// Calls that carry targets are unary, so their body is buffered and handed
+/// on unchanged once the targets are measured; other calls pass straight
+/// through.
+#[derive(Debug, Clone)]
+pub struct TargetLimitLayer {
+    limits: TargetLimits,
+}
+
+impl TargetLimitLayer {
+    pub fn new(limits: TargetLimits) -> Self {
+        Self { limits }
+    }
+}
+
+impl<S> Layer<S> for TargetLimitLayer {
+    type Service = TargetLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TargetLimitService {
+            inner,
+            limits: self.limits,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TargetLimitService<S> {
+    inner: S,
+    limits: TargetLimits,
+}
+
+impl<S> Service<hyper::Request<BoxBody>> for TargetLimitService<S>
+where
+    S: Service<hyper::Request<BoxBody>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<BoxBody>) -> Self::Future {
+        let method = request.uri().path().strip_prefix(SERVICE_PREFIX).unwrap_or_default().to_string();
+        if targets(&method).is_empty() {
+            return Box::pin(self.inner.call(request));
+        }
+        // The clone is ready only once polled, so the ready one is taken
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limits = self.limits;
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            // Framing and compression can add a little to the message
+            let max_body = limits.max_message_bytes.saturating_mul(2).saturating_add(FRAME_HEADER);
+            let body = match Limited::new(body, max_body).collect().await {
+                Ok(collected) => collected.to_bytes(),
+                Err(e) => {
+                    let status = if e.is::<http_body_util::LengthLimitError>() {
+                        Status::resource_exhausted(format!("Request body over {} bytes", max_body))
+                    } else {
+                        Status::unknown(format!("Failed to read the request body: {}", e))
+                    };
+                    return Ok(status.into_http());
+                }
+            };
+            if let Err(status) = unframe(&body, limits.max_message_bytes).and_then(|message| match message {
+                Some(message) => limits.check(&method, &message),
+                None => Ok(()),
+            }) {
+                warn!("❌ {} refused: {}", method, status.message());
+                return Ok((*status).into_http());
+            }
+            inner
+                .call(hyper::Request::from_parts(parts, tonic::body::boxed(Full::new(body))))
+                .await
+        })
+    }
+}
+
+/// Refuses `EnforcementService` calls with RESOURCE_EXHAUSTED while `[server]
+/// max_concurrent_requests` of them are in flight, rather than queueing
+/// them. A call holds its place until its response body ends, so a stream
+/// counts for as long as it runs. Other services, health checks among
+/// them, are not counted or refused.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimitLayer {
+    permits: Option<Arc<Semaphore>>,
+    max: usize,
+}
+
+impl ConcurrencyLimitLayer {
+    /// No limit when `max` is 0
+    pub fn new(max: usize) -> Self {
+        Self {
+            permits: (max > 0).then(|| Arc::new(Semaphore::new(max))),
+            max,
+        }
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLimitLayer {
+    type Service = ConcurrencyLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConcurrencyLimitService {
+            inner,
+            permits: self.permits.clone(),
+            max: self.max,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimitService<S> {
+    inner: S,
+    permits: Option<Arc<Semaphore>>,
+    max: usize,
+}
+
+impl<S> Service<hyper::Request<BoxBody>> for ConcurrencyLimitService<S>
+where
+    S: Service<hyper::Request<BoxBody>, Response = hyper::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<BoxBody>) -> Self::Future {
+        let Some(permits) = self.permits.as_ref().filter(|_| request.uri().path().starts_with(SERVICE_PREFIX)) else {
+            return Box::pin(self.inner.call(request));
+        };
+        let Ok(permit) = permits.clone().try_acquire_owned() else {
+            let method = request.uri().path().strip_prefix(SERVICE_PREFIX).unwrap_or_default();
+            warn!("❌ {} refused: {} calls already in flight", method, self.max);
+            let status = Status::resource_exhausted(format!(
+                "server_busy: {} calls in flight, the [server] max_concurrent_requests; retry later",
+                self.max
+            ));
+            return Box::pin(async move { Ok(status.into_http()) });
+        };
+        let call = self.inner.call(request);
+        Box::pin(async move {
+            let response = call.await?;
+            // Dropped with the body, once the last of the response is sent
+            Ok(response.map(|body| {
+                tonic::body::boxed(body.map_frame(move |frame| {
+                    let _held = &permit;
+                    frame
+                }))
+            }))
+        })
+    }
+}
+
+/// Compression flag and length ahead of each gRPC message
+const FRAME_HEADER: usize = 5;
+
+/// The first message in a request body, decompressed, which is the one a
+/// unary handler is given; `None` when the body is cut short, which tonic
+/// then refuses on its own
+fn unframe(body: &Bytes, max_message_bytes: usize) -> Result<Option<Bytes>, Box<Status>> {
+    if body.len() < FRAME_HEADER {
+        return Ok(None);
+    }
+    let compressed = body[0] == 1;
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let Some(message) = body.get(FRAME_HEADER..FRAME_HEADER + len) else {
+        return Ok(None);
+    };
+    let message = body.slice_ref(message);
+    if !compressed {
+        return Ok(Some(message));
+    }
+    decompress(&message, max_message_bytes).map(Some)
+}
+
+#[cfg(feature = "gzip")]
+fn decompress(message: &[u8], max_message_bytes: usize) -> Result<Bytes, Box<Status>> {
+    use std::io::Read;
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(message)
+        .take(max_message_bytes as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| Box::new(Status::internal(format!("Failed to decompress the request: {}", e))))?;
+    if decompressed.len() > max_message_bytes {
+        let status = Status::resource_exhausted(format!("Request message over {} bytes", max_message_bytes));
+        return Err(Box::new(status));
+    }
+    Ok(Bytes::from(decompressed))
+}
+
+/// Without gzip, tonic refuses compressed requests itself
+#[cfg(not(feature = "gzip"))]
+fn decompress(_message: &[u8], _max_message_bytes: usize) -> Result<Bytes, Box<Status>> {
+    Err(Box::new(Status::unimplemented("Compressed requests are not supported")))
+}
+
+/// (field number, length) of each top-level length-delimited field, as far
+/// as `message` parses; what does not parse is left for the decoder to
+/// refuse
+fn string_fields(mut message: &[u8]) -> Vec<(u32, usize)> {
+    let mut fields = Vec::new();
+    while message.has_remaining() {
+        let Some(key) = varint(&mut message) else {
+            break;
+        };
+        let number = (key >> 3) as u32;
+        let skip = match key & 7 {
+            0 => match varint(&mut message) {
+                Some(_) => 0,
+                None => break,
+            },
+            1 => 8,
+            2 => {
+                let Some(len) = varint(&mut message).and_then(|len| usize::try_from(len).ok()) else {
+                    break;
+                };
+                fields.push((number, len));
+                len
+            }
+            5 => 4,
+            _ => break,
+        };
+        if skip > message.remaining() {
+            break;
+        }
+        message.advance(skip);
+    }
+    fields
+}
+
+fn varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf.first()?;
+        buf.advance(1);
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::openclaw::enforce::{ExecuteCommandRequest, ReadFileRequest, WriteFileRequest};
+    use prost::Message;
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+
+    const LIMITS: TargetLimits = TargetLimits {
+        max_target_bytes: 64,
+        max_url_bytes: 128,
+        max_message_bytes: 1024 * 1024,
+    };
+
+    /// Answers OK with the request body it was handed
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<hyper::Request<BoxBody>> for Echo {
+        type Response = hyper::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = Ready<Result<hyper::Response<BoxBody>, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: hyper::Request<BoxBody>) -> Self::Future {
+            ready(Ok(hyper::Response::new(request.into_body())))
+        }
+    }
+
+    fn framed(message: &impl Message) -> Bytes {
+        let encoded = message.encode_to_vec();
+        let mut body = vec![0];
+        body.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        body.extend_from_slice(&encoded);
+        body.into()
+    }
+
+    /// The grpc-status the call was refused with, or the body handed on
+    async fn call(method: &str, body: Bytes) -> Result<Bytes, String> {
+        let mut service = TargetLimitLayer::new(LIMITS).layer(Echo);
+        let request = hyper::Request::builder()
+            .uri(format!("{}{}", SERVICE_PREFIX, method))
+            .body(tonic::body::boxed(Full::new(body)))
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        if let Some(status) = response.headers().get("grpc-status") {
+            let message = response.headers().get("grpc-message").map_or("", |m| m.to_str().unwrap());
+            return Err(format!("{} {}", status.to_str().unwrap(), message));
+        }
+        Ok(response.into_body().collect().await.unwrap().to_bytes())
+    }
+
+    #[tokio::test]
+    async fn test_oversized_targets_are_refused() {
+        let read = |path: String| ReadFileRequest {
+            path,
+            ..Default::default()
+        };
+        let ok = framed(&read("/tmp/a.txt".into()));
+        assert_eq!(call("ReadFile", ok.clone()).await.unwrap(), ok);
+
+        let err = call("ReadFile", framed(&read(format!("/{}", "a".repeat(1024 * 1024))))).await.unwrap_err();
+        // 3 is INVALID_ARGUMENT
+        assert!(err.starts_with("3 target_too_long"), "{}", err);
+        assert!(err.contains("max_target_bytes"), "{}", err);
+
+        let command = ExecuteCommandRequest {
+            command: "ls".into(),
+            working_dir: "/".repeat(65),
+            ..Default::default()
+        };
+        assert!(call("ExecuteCommand", framed(&command)).await.unwrap_err().contains("working_dir"));
+
+        // A second message does not hide the first
+        let mut two = framed(&read(format!("/{}", "a".repeat(100)))).to_vec();
+        two.extend_from_slice(&ok);
+        assert!(call("ReadFile", two.into()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_content_and_other_methods_pass() {
+        // Data is content, not a target
+        let write = WriteFileRequest {
+            path: "/tmp/a.txt".into(),
+            data: vec![b'x'; 4096],
+            ..Default::default()
+        };
+        assert!(call("WriteFile", framed(&write)).await.is_ok());
+        // Not parsed at all
+        let long = framed(&read_file_with_path_len(4096));
+        assert_eq!(call("GetStatus", long.clone()).await.unwrap(), long);
+        // Malformed messages are left to the decoder
+        assert!(call("ReadFile", Bytes::from_static(b"\0\0\0\0\x02\xff\xff")).await.is_ok());
+    }
+
+    fn read_file_with_path_len(len: usize) -> ReadFileRequest {
+        ReadFileRequest {
+            path: "a".repeat(len),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_calls_over_the_concurrency_limit_are_shed() {
+        let mut service = ConcurrencyLimitLayer::new(1).layer(Echo);
+        let mut call = |path: &str| {
+            let request = hyper::Request::builder().uri(path).body(tonic::body::empty_body()).unwrap();
+            service.call(request)
+        };
+        let grpc_status = |response: &hyper::Response<BoxBody>| {
+            response.headers().get("grpc-status").map(|s| s.to_str().unwrap().to_string())
+        };
+        let read = format!("{}ReadFile", SERVICE_PREFIX);
+
+        let first = call(&read).await.unwrap();
+        assert_eq!(grpc_status(&first), None);
+        // 8 is RESOURCE_EXHAUSTED
+        let shed = call(&read).await.unwrap();
+        assert_eq!(grpc_status(&shed).as_deref(), Some("8"));
+        assert!(shed.headers()["grpc-message"].to_str().unwrap().starts_with("server_busy"));
+        // Health checks are answered however busy the service is
+        let health = call("/grpc.health.v1.Health/Check").await.unwrap();
+        assert_eq!(grpc_status(&health), None);
+
+        // The place is held until the response body is done with
+        first.into_body().collect().await.unwrap();
+        assert_eq!(grpc_status(&call(&read).await.unwrap()), None);
+
+        let mut unlimited = ConcurrencyLimitLayer::new(0).layer(Echo);
+        let mut held = Vec::new();
+        for _ in 0..3 {
+            let request = hyper::Request::builder().uri(&read).body(tonic::body::empty_body()).unwrap();
+            held.push(unlimited.call(request).await.unwrap());
+        }
+        assert!(held.iter().all(|response| grpc_status(response).is_none()));
+    }
+
+    #[test]
+    fn test_string_fields() {
+        let write = WriteFileRequest {
+            path: "/tmp/a".into(),
+            data: vec![0; 300],
+            sync: true,
+            ..Default::default()
+        };
+        assert_eq!(string_fields(&write.encode_to_vec()), vec![(1, 6), (2, 300)]);
+        // Truncated: the length runs past the end
+        assert_eq!(string_fields(&[0x0a, 0x05, b'a']), vec![(1, 5)]);
+        assert!(string_fields(&[0xff]).is_empty());
+    }
+}
@@ -1 +1,10 @@
+pub mod approval;
+pub mod limits;
+pub mod request_id;
 pub mod server;
+pub mod timing;
+
+#[cfg(test)]
+pub mod test_env;
+#[cfg(test)]
+mod integration_tests;
@@ -0,0 +1,193 @@
+use hyper::header::HeaderValue;
+use rand::Rng;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tonic::Request;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::Instrument;
+
+/// Metadata key carrying the correlation ID of a call
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest caller-supplied ID that is accepted as is
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Gives every call a request ID and runs it inside a span carrying that ID.
+///
+/// A valid `x-request-id` from the caller is kept; otherwise a random UUID
+/// is generated. Either way it is written back into the request metadata
+/// and extensions, so handlers can read it with [`request_id`], and into
+/// the response headers, so the caller can quote it.
+#[derive(Debug, Clone, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdService { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdService<S> {
+    inner: S,
+}
+
+/// The ID [`RequestIdLayer`] assigned, in the request extensions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl<S, B, R> Service<hyper::Request<B>> for RequestIdService<S>
+where
+    S: Service<hyper::Request<B>, Response = hyper::Response<R>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: hyper::Request<B>) -> Self::Future {
+        let id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| is_valid(id))
+            .map(str::to_string)
+            .unwrap_or_else(generate);
+
+        let span = tracing::info_span!("rpc", request_id = %id, method = %request.uri().path());
+        // Valid caller IDs and generated ones are both plain ASCII
+        let value = HeaderValue::from_str(&id).ok();
+        if let Some(value) = &value {
+            request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+        }
+        request.extensions_mut().insert(RequestId(id));
+        let response = self.inner.call(request);
+        Box::pin(
+            async move {
+                let mut response = response.await?;
+                if let Some(value) = value {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// The correlation ID assigned by [`RequestIdLayer`], empty if the layer
+/// was not installed
+pub fn request_id<T>(request: &Request<T>) -> String {
+    if let Some(RequestId(id)) = request.extensions().get::<RequestId>() {
+        return id.clone();
+    }
+    request
+        .metadata()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+/// A random (version 4) UUID
+fn generate() -> String {
+    let mut bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+
+    /// Echoes the request ID the handler would see in its body
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<hyper::Request<()>> for Echo {
+        type Response = hyper::Response<String>;
+        type Error = Infallible;
+        type Future = Ready<Result<hyper::Response<String>, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: hyper::Request<()>) -> Self::Future {
+            let request = Request::from_http(request);
+            ready(Ok(hyper::Response::new(request_id(&request))))
+        }
+    }
+
+    async fn call(header: Option<&str>) -> hyper::Response<String> {
+        let mut builder = hyper::Request::builder().uri("/openclaw.enforce.EnforcementService/ReadFile");
+        if let Some(header) = header {
+            builder = builder.header(REQUEST_ID_HEADER, header);
+        }
+        let mut service = RequestIdLayer.layer(Echo);
+        service.call(builder.body(()).unwrap()).await.unwrap()
+    }
+
+    async fn assigned_id(header: Option<&str>) -> String {
+        call(header).await.into_body()
+    }
+
+    #[tokio::test]
+    async fn test_caller_id_is_kept() {
+        assert_eq!(assigned_id(Some("trace-42")).await, "trace-42");
+    }
+
+    #[tokio::test]
+    async fn test_missing_or_invalid_id_is_generated() {
+        let generated = assigned_id(None).await;
+        assert_eq!(generated.len(), 36);
+        assert_eq!(&generated[14..15], "4");
+
+        let replaced = assigned_id(Some("has spaces")).await;
+        assert_ne!(replaced, "has spaces");
+        assert_eq!(replaced.len(), 36);
+    }
+
+    #[tokio::test]
+    async fn test_id_is_returned_in_response_metadata() {
+        for header in [Some("trace-42"), None] {
+            let response = call(header).await;
+            let returned = response.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string();
+            assert_eq!(&returned, response.body());
+        }
+
+        // Extensions win over metadata, which a caller controls
+        let mut request = Request::new(());
+        request.metadata_mut().insert(REQUEST_ID_HEADER, "forged".parse().unwrap());
+        request.extensions_mut().insert(RequestId("assigned".to_string()));
+        assert_eq!(request_id(&request), "assigned");
+    }
+}
@@ -1,29 +1,892 @@
-use anyhow::Result;
-use std::net::SocketAddr;
-use std::path::Path;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tonic::{transport::Server, Request, Response, Status};
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::fs::validator::PathValidator;
-use crate::policy::Policy;
+use super::approval::{Approver, Verdict};
+use super::limits::{ConcurrencyLimitLayer, TargetLimitLayer, TargetLimits};
+use super::request_id::{request_id, RequestIdLayer};
+use super::timing::{MethodLatency, TimingLayer};
+use crate::capabilities::grants::GrantStore;
+use crate::daemon::clock::SharedClock;
+use crate::daemon::health::{Condition, HealthRegistry, Subsystem};
+use crate::daemon::state::StatePaths;
+use crate::capabilities::sessions::{Budget, Budgets, Debit, Fingerprint, SessionStore, Snapshot};
+use crate::daemon::notify;
+use crate::audit::digest::DigestSnapshot;
+use crate::audit::logger::{AuditEntry, AuditFilter, AuditLogger};
+use crate::fs::edit::{self, Current, EditLocks, Replacement};
+use crate::fs::encoding;
+use crate::fs::protected::{SelfProtection, SharedProtection};
+use crate::fs::redact::Redactor;
+use crate::fs::scratch::ScratchArea;
+use crate::fs::search::{self, search};
+use crate::fs::self_check::SelfCheckReport;
+use crate::fs::trash::Trash;
+use crate::fs::validator::{Access, PathValidator};
+use crate::fs::verified::{self, resolved_target};
+use crate::fs::walk::{self, WalkOptions};
+use crate::fs::watch::{Change, FileWatcher, WatchRegistry, WatchSlot};
+use crate::fs::writer::{FileWriter, WriteMode, WriteOptions};
+use crate::network::address::AddressGuard;
+use crate::network::connect::{parse_url, ConnectGuard, ConnectionLeases, Protocol};
+use crate::network::domain::DomainMatcher;
+use crate::network::http::{self, HttpClient, HttpGuard};
+use crate::network::proxy::ProxyConfig;
+use crate::network::quota::DomainQuotas;
+use crate::network::{check_input_length, Denial};
+use crate::policy::diff::{self, PolicyDiff};
+use crate::policy::edits::{self, EditAction, EditError, PolicyEdits};
+use crate::policy::messages::{Messages, Params};
+use crate::policy::parser::{self, PolicyFormat, PolicySource};
+use crate::policy::schedule::Schedule;
+use crate::policy::signature::PolicyKey;
+use crate::process::command::CommandGuard;
+use crate::process::privileges;
+use crate::policy::{OnInternalError, Operation, OperationsPolicy, Policy, SearchPolicy};
+use crate::resources::latency::{DecisionLatency, Transition};
+use crate::resources::monitor::{self, ResourceMonitor};
 use crate::proto::openclaw::enforce::*;
 
+/// How often usage is compared against the `warn_*` resource thresholds
+const RESOURCE_SAMPLE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often `[audit]` retention trims the audit log file
+const AUDIT_RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+/// How often grants, session budgets and quota usage are saved, when
+/// `[state] dir` or `quota_state_file` is set
+const STATE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often trashed files past `trash_retention_days` are purged
+const TRASH_SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How often expired grants are dropped and their expiry audited
+const GRANT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often a watched file is checked for changes
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Entries one `list_directory` returns at most, however many it asks for
+const MAX_LIST_ENTRIES: u32 = 10_000;
+
+/// Everything derived from the policy. `reload_policy` replaces it as a
+/// whole; leases, watches, sessions and the audit log hold runtime state
+/// and keep the limits they were started with.
+struct PolicyState {
+    /// What the rest was built from, for edits to start from
+    policy: Policy,
+    validator: Arc<PathValidator>,
+    redactor: Arc<Redactor>,
+    writer: FileWriter,
+    trash: Option<Arc<Trash>>,
+    http_guard: HttpGuard,
+    http_client: HttpClient,
+    allowed_domains: DomainMatcher,
+    address_guard: Arc<AddressGuard>,
+    connect_guard: ConnectGuard,
+    require_proxy: bool,
+    commands: Arc<CommandGuard>,
+    approver: Option<Approver>,
+    self_check: SelfCheckReport,
+    watch_heartbeat: Duration,
+    search: SearchPolicy,
+    /// `[performance] walk_threads`, with 0 resolved to the core count
+    walk_threads: usize,
+    admin_token: Option<String>,
+    latency_budget: Option<Duration>,
+    on_internal_error: OnInternalError,
+    schedule: Schedule,
+    messages: Messages,
+    operations: OperationsPolicy,
+    stats: PolicyStats,
+    /// [`diff::digest`] of `policy`, which audit events name it by
+    digest: String,
+    loaded_at: i64,
+}
+
+impl PolicyState {
+    /// `loaded_at` is `now`, in Unix seconds. Writes are verified against
+    /// `protected` as well as the policy once the file is open.
+    fn new(policy: &Policy, protected: &SharedProtection, now: i64) -> Result<Self> {
+        let address_guard = Arc::new(AddressGuard::new(&policy.network)?);
+        let limits = &policy.enforcement;
+        let http_guard =
+            HttpGuard::new(&policy.network, address_guard.clone()).with_max_domain_length(limits.max_domain_length);
+        let http_client = HttpClient::new(
+            ProxyConfig::from_policy(&policy.network)?,
+            address_guard.clone(),
+        );
+        let performance = &policy.performance;
+        let validator = Arc::new(
+            PathValidator::new(policy.filesystem.clone())
+                .with_state_dir(policy.state.dir.as_deref())
+                .with_input_limits(limits)
+                .with_decision_cache(
+                    performance.decision_cache_size as usize,
+                    Duration::from_secs(performance.decision_cache_ttl_secs),
+                ),
+        );
+        let (write_check, protected) = (validator.clone(), protected.clone());
+        Ok(Self {
+            policy: policy.clone(),
+            writer: FileWriter::new(&policy.filesystem)
+                .with_check(Arc::new(move |path| write_check.can_write(path).unwrap_or(false)))
+                .with_guard(Arc::new(move |path| protected.check(path).is_ok())),
+            validator,
+            redactor: Arc::new(Redactor::new(&policy.filesystem.redaction)?),
+            trash: policy.filesystem.trash_dir.as_ref().map(|dir| {
+                Arc::new(Trash::new(dir.into(), policy.filesystem.trash_retention_days))
+            }),
+            http_guard,
+            http_client,
+            allowed_domains: DomainMatcher::new("network.allowed_domains", &policy.network.allowed_domains),
+            connect_guard: ConnectGuard::new(&policy.network, address_guard.clone())
+                .with_max_domain_length(limits.max_domain_length),
+            address_guard,
+            require_proxy: policy.network.require_proxy,
+            commands: Arc::new(CommandGuard::new(&policy.process).with_max_command_length(limits.max_command_length)),
+            approver: Approver::from_policy(&policy.escalation)?,
+            self_check: SelfCheckReport::run(&policy.filesystem),
+            watch_heartbeat: Duration::from_secs(policy.filesystem.watch_heartbeat_secs),
+            search: policy.filesystem.search.clone(),
+            walk_threads: match performance.walk_threads {
+                0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                threads => threads,
+            },
+            admin_token: policy.server.admin_token.clone(),
+            latency_budget: (policy.server.latency_budget_ms > 0)
+                .then(|| Duration::from_millis(policy.server.latency_budget_ms)),
+            on_internal_error: policy.enforcement.on_internal_error,
+            schedule: Schedule::new(&policy.schedule).map_err(anyhow::Error::msg)?,
+            messages: Messages::new(&policy.messages).map_err(anyhow::Error::msg)?,
+            operations: policy.operations.clone(),
+            stats: PolicyStats {
+                allowed_read: policy.filesystem.allowed_read.len() as u32,
+                allowed_write: policy.filesystem.allowed_write.len() as u32,
+                denied_patterns: policy.filesystem.denied_patterns.len() as u32,
+                rules: policy.filesystem.rules.len() as u32,
+                allowed_domains: policy.network.allowed_domains.len() as u32,
+                allowed_commands: policy.process.allowed_commands.len() as u32,
+            },
+            digest: diff::digest(policy),
+            loaded_at: now,
+        })
+    }
+
+    /// A check that could not be completed. Logged, then denied with
+    /// `internal_error`; under `on_internal_error = "error"` the error is
+    /// passed on to fail the RPC instead.
+    fn internal_error(&self, resource: &str, err: anyhow::Error) -> Result<Denial> {
+        error!("Internal error while checking {}: {:#}", resource, err);
+        match self.on_internal_error {
+            OnInternalError::Deny => Ok(Denial::new(
+                "internal_error",
+                format!("Internal error while checking {}", resource),
+            )),
+            OnInternalError::Error => Err(err),
+        }
+    }
+
+    /// Runs the filesystem checks for a request path. The outer error is
+    /// a check that could not be completed under `on_internal_error =
+    /// "error"`, the inner one a policy denial.
+    fn authorize_path(&self, path: &Path, access: Access) -> Result<Result<(), Denial>> {
+        if let Err(denial) = self.validator.precheck(path) {
+            return Ok(Err(denial));
+        }
+        if access == Access::Write {
+            if let Err(denial) = self.validator.check_write_extension(path) {
+                return Ok(Err(denial));
+            }
+        }
+
+        match self.validator.can_access(path, access) {
+            Ok(true) if access == Access::Read => return Ok(self.validator.check_exists(path)),
+            Ok(true) => return Ok(Ok(())),
+            Ok(false) => {}
+            Err(e) => return self.internal_error(&path.to_string_lossy(), e).map(Err),
+        }
+        let list = match access {
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+        Ok(Err(Denial::new(
+            "path_not_allowed",
+            format!("Path not in allowed {} list", list),
+        )))
+    }
+
+    /// [`authorize_path`](Self::authorize_path) for `stat_file`, which
+    /// `allowed_stat` permits as well as `allowed_read`
+    fn authorize_stat(&self, path: &Path) -> Result<Result<(), Denial>> {
+        if let Err(denial) = self.validator.precheck(path) {
+            return Ok(Err(denial));
+        }
+
+        match self.validator.can_stat(path) {
+            Ok(true) => Ok(Ok(())),
+            Ok(false) => Ok(Err(Denial::new(
+                "path_not_allowed",
+                "Path not in allowed stat or read list".to_string(),
+            ))),
+            Err(e) => self.internal_error(&path.to_string_lossy(), e).map(Err),
+        }
+    }
+
+    fn log_self_check_problems(&self) {
+        for (entry, problem) in self.self_check.problems() {
+            warn!("Self-check: {} entry {:?}: {}", entry.access.list_name(), entry.entry, problem);
+        }
+    }
+}
+
 /// Enforcement service implementation
 pub struct EnforcementServiceImpl {
-    validator: PathValidator,
+    state: Arc<RwLock<Arc<PolicyState>>>,
+    /// Where `reload_policy` re-reads the policy; `None` for stdin
+    origin: Option<(PolicySource, Option<PolicyFormat>)>,
+    /// `--policy-pubkey`, which a reloaded policy must be signed with too
+    policy_key: Option<PolicyKey>,
+    reload_lock: tokio::sync::Mutex<()>,
+    leases: ConnectionLeases,
+    quotas: Arc<DomainQuotas>,
+    watches: Arc<WatchRegistry>,
+    edit_locks: Arc<EditLocks>,
+    /// Set from the policy at startup; a reload does not move it
+    scratch: Option<ScratchArea>,
+    /// The daemon's own files, which no write may touch. Built again with
+    /// each policy swapped in, always keeping `opened`.
+    protected: SharedProtection,
+    /// The audit log and state stores opened at startup
+    opened: SelfProtection,
+    /// `allow_session` approvals
+    grants: Arc<GrantStore>,
+    sessions: Arc<SessionStore>,
+    latency: DecisionLatency,
+    audit: Arc<AuditLogger>,
+    health: Arc<HealthRegistry>,
+    /// Filled by [`TimingLayer`]
+    rpc_latency: Arc<MethodLatency>,
+    /// `--read-only` or `read_only_mode`: writes, commands and network
+    /// access are denied while set
+    read_only: AtomicBool,
+    /// What expiries, quotas and `[schedule]` windows are decided by
+    clock: SharedClock,
 }
 
 impl EnforcementServiceImpl {
-    pub fn new(policy: Policy) -> Self {
-        let validator = PathValidator::new(policy.filesystem.clone());
-        Self { validator }
+    pub fn new(policy: Policy) -> Result<Self> {
+        Self::new_with_clock(policy, SharedClock::default())
+    }
+
+    /// [`new`](Self::new), reading the time from `clock` instead of the
+    /// system
+    pub fn new_with_clock(policy: Policy, clock: SharedClock) -> Result<Self> {
+        let health = Arc::new(HealthRegistry::default());
+        let mut audit = AuditLogger::default()
+            .with_dedupe_window(policy.audit.dedupe_window_secs)
+            .with_sample_rate(policy.audit.sample_rate)
+            .with_retention(policy.audit.max_entries, policy.audit.max_age_secs)
+            .with_health(health.clone());
+        if let Some(file) = &policy.audit.file {
+            audit = audit
+                .with_file(Path::new(file))
+                .with_context(|| format!("Failed to open audit log {}", file))?;
+        }
+        let saved = StatePaths::of(&policy);
+        let quotas = DomainQuotas::new(&policy.network, saved.quotas, clock.unix_now());
+        let protected = SharedProtection::new(SelfProtection::of(&policy, None));
+        Ok(Self {
+            state: Arc::new(RwLock::new(Arc::new(PolicyState::new(&policy, &protected, clock.unix_now())?))),
+            origin: None,
+            policy_key: None,
+            reload_lock: tokio::sync::Mutex::new(()),
+            leases: ConnectionLeases::new(
+                policy.network.max_connections,
+                Duration::from_secs(policy.network.connect.lease_ttl_secs),
+            )
+            .with_domain_limits(&policy.network.max_connections_per_domain)
+            .with_quotas(quotas.clone())
+            .with_clock(clock.clone()),
+            quotas,
+            watches: WatchRegistry::new(policy.filesystem.max_watches_per_client),
+            edit_locks: EditLocks::new(),
+            scratch: policy.filesystem.scratch_dir.as_ref().map(|dir| ScratchArea::new(Path::new(dir))),
+            protected,
+            opened: SelfProtection::opened(&policy),
+            grants: Arc::new(GrantStore::default().with_clock(clock.clone()).with_state_file(saved.grants)),
+            sessions: Arc::new(
+                SessionStore::new(&policy.sessions)
+                    .with_clock(clock.clone())
+                    .with_state_file(saved.sessions),
+            ),
+            latency: DecisionLatency::default(),
+            audit: Arc::new(audit),
+            health,
+            rpc_latency: Arc::default(),
+            read_only: AtomicBool::new(false),
+            clock,
+        })
+    }
+
+    /// Starts in read-only mode, as `--read-only` does
+    pub fn with_read_only(self, read_only: bool) -> Self {
+        self.read_only.store(read_only, Ordering::Relaxed);
+        self
+    }
+
+    /// Lets `reload_policy` read the policy again from `source`, and
+    /// protects it from writes when it is a file
+    pub fn with_origin(mut self, source: PolicySource, format: Option<PolicyFormat>) -> Self {
+        self.origin = (source != PolicySource::Stdin).then_some((source, format));
+        self.protected.replace(self.protection(&self.state().policy));
+        self
+    }
+
+    /// What [`protected`](Self::protected) holds while `policy` is in force
+    fn protection(&self, policy: &Policy) -> SelfProtection {
+        let policy_file = match &self.origin {
+            Some((PolicySource::File(file), _)) => Some(file.as_path()),
+            _ => None,
+        };
+        SelfProtection::of(policy, policy_file).and(&self.opened)
+    }
+
+    /// Puts `new_state` in force, with the daemon's files as it names them
+    /// protected from then on, and returns the state it replaces
+    fn swap_state(&self, new_state: Arc<PolicyState>) -> Arc<PolicyState> {
+        let protection = self.protection(&new_state.policy);
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        self.protected.replace(protection);
+        std::mem::replace(&mut *state, new_state)
+    }
+
+    /// Saves the stores `[state] dir` keeps that changed since the last
+    /// call, reporting [`Subsystem::StateStore`] failed while any cannot be
+    fn state_flusher(&self) -> impl Fn() -> Result<()> + Send + Sync + 'static {
+        let (grants, sessions, quotas) = (self.grants.clone(), self.sessions.clone(), self.quotas.clone());
+        let health = self.health.clone();
+        move || {
+            // One store failing does not keep the others from being saved
+            let errors: Vec<String> = [grants.flush(), sessions.flush(), quotas.flush()]
+                .into_iter()
+                .filter_map(|flushed| flushed.err().map(|e| format!("{:#}", e)))
+                .collect();
+            let flushed = if errors.is_empty() { Ok(()) } else { Err(anyhow::anyhow!(errors.join("; "))) };
+            health.report_result(Subsystem::StateStore, &flushed, Condition::Failed);
+            flushed
+        }
+    }
+
+    /// Audits the policy the daemon starts with, so the entries after it
+    /// can be read against it
+    fn record_policy_load(&self) {
+        let state = self.state();
+        let PolicyStats { allowed_read, allowed_write, denied_patterns, rules, allowed_domains, allowed_commands } =
+            state.stats;
+        let counts = [
+            ("allowed_read", allowed_read),
+            ("allowed_write", allowed_write),
+            ("denied_patterns", denied_patterns),
+            ("rules", rules),
+            ("allowed_domains", allowed_domains),
+            ("allowed_commands", allowed_commands),
+        ];
+        let mut entry = AuditEntry::note("policy_load", "load_policy", &self.policy_info(&state).path, "Policy loaded")
+            .with_metadata("policy_hash", state.digest.clone())
+            .with_metadata("read_only", self.read_only.load(Ordering::Relaxed).to_string());
+        for (key, count) in counts {
+            entry = entry.with_metadata(key, count.to_string());
+        }
+        self.audit.record(entry);
+    }
+
+    /// Drops expired grants, auditing each
+    fn grant_sweeper(&self) -> impl Fn() + Send + Sync + 'static {
+        let (grants, audit) = (self.grants.clone(), self.audit.clone());
+        move || {
+            for (session, operation, resource, grant) in grants.expire() {
+                audit.record(
+                    AuditEntry::note("grant_expired", &operation, &resource, "Grant expired")
+                        .with_session(&session)
+                        .with_metadata("approver", grant.approver),
+                );
+            }
+        }
+    }
+
+    /// Makes `reload_policy` refuse a policy file `key` did not sign
+    pub fn with_policy_key(mut self, key: Option<PolicyKey>) -> Self {
+        self.policy_key = key;
+        self
+    }
+
+    /// The policy in force; a request keeps the snapshot it started with
+    fn state(&self) -> Arc<PolicyState> {
+        current_state(&self.state)
+    }
+
+    fn policy_info(&self, state: &PolicyState) -> PolicyInfo {
+        PolicyInfo {
+            path: self
+                .origin
+                .as_ref()
+                .map_or_else(|| PolicySource::Stdin.to_string(), |(source, _)| source.to_string()),
+            loaded_at: state.loaded_at,
+        }
+    }
+
+    /// Admin RPCs need `authorization: Bearer <admin_token>`, and are
+    /// refused outright when no token is configured
+    fn is_admin(&self, metadata: &tonic::metadata::MetadataMap) -> bool {
+        let state = self.state();
+        let Some(token) = state.admin_token.as_deref() else {
+            return false;
+        };
+        constant_time_eq(bearer_token(metadata).as_bytes(), token.as_bytes())
+    }
+
+    /// The `[server.policy_editors]` name whose token the request carries
+    fn policy_editor(&self, metadata: &tonic::metadata::MetadataMap) -> Option<String> {
+        let presented = bearer_token(metadata);
+        if presented.is_empty() {
+            return None;
+        }
+        let state = self.state();
+        // Every token is compared, so the time taken does not tell which matched
+        let mut editor = None;
+        for (name, token) in &state.policy.server.policy_editors {
+            if constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+                editor = Some(name.clone());
+            }
+        }
+        editor
+    }
+
+    /// `add_policy_entry` or `remove_policy_entry` by `editor`. The edited
+    /// policy is normalized, validated and swapped in whole, like a
+    /// reload; with `persist`, the edit is recorded in `policy_edits_file`
+    /// before it takes effect.
+    async fn edit_policy(
+        &self,
+        editor: &str,
+        action: EditAction,
+        req: &PolicyEntryRequest,
+    ) -> Result<(String, Arc<PolicyState>), Status> {
+        let edit_status = |e: EditError| match e {
+            EditError::UnknownList(_) => Status::invalid_argument(e.to_string()),
+            EditError::AlreadyPresent { .. } => Status::already_exists(e.to_string()),
+            EditError::NotFound { .. } => Status::not_found(e.to_string()),
+        };
+        // Serialized with reloads, so neither undoes the other
+        let _editing = self.reload_lock.lock().await;
+        let mut policy = self.state().policy.clone();
+        let before = edits::entries(&policy, &req.list).map_err(edit_status)?.len();
+        edits::apply(&mut policy, action, &req.list, &req.entry).map_err(edit_status)?;
+        let policy = parser::revalidate(policy)
+            .map_err(|e| Status::invalid_argument(format!("The edited policy is invalid: {:#}", e)))?;
+        let entry = match action {
+            EditAction::Add => {
+                let after = edits::entries(&policy, &req.list).map_err(edit_status)?;
+                // Dropped as a repeat once normalized, e.g. a relative form
+                // of an absolute entry
+                match after.last().filter(|_| after.len() > before) {
+                    Some(entry) => entry.clone(),
+                    None => {
+                        return Err(edit_status(EditError::AlreadyPresent {
+                            list: req.list.clone(),
+                            entry: req.entry.clone(),
+                        }))
+                    }
+                }
+            }
+            EditAction::Remove => req.entry.clone(),
+        };
+        let new_state = PolicyState::new(&policy, &self.protected, self.clock.unix_now())
+            .map_err(|e| Status::invalid_argument(format!("The edited policy is invalid: {:#}", e)))?;
+
+        if req.persist {
+            let Some(file) = &policy.server.policy_edits_file else {
+                return Err(Status::failed_precondition("No policy_edits_file is configured"));
+            };
+            let path = Path::new(file);
+            PolicyEdits::load(path)
+                .and_then(|mut recorded| {
+                    recorded.record(action, &req.list, &entry);
+                    recorded.save(path)
+                })
+                .map_err(|e| Status::internal(format!("Failed to persist the edit: {:#}", e)))?;
+        }
+        let new_state = Arc::new(new_state);
+        self.swap_state(new_state.clone());
+        info!("✅ Policy edited by {}: {} {} {:?}", editor, action, req.list, entry);
+        Ok((entry, new_state))
+    }
+
+    async fn policy_entry(
+        &self,
+        request: Request<PolicyEntryRequest>,
+        action: EditAction,
+    ) -> Result<Response<PolicyEntryResponse>, Status> {
+        let request_id = request_id(&request);
+        let operation = match action {
+            EditAction::Add => "add_policy_entry",
+            EditAction::Remove => "remove_policy_entry",
+        };
+        let editor = self.policy_editor(request.metadata());
+        let req = request.into_inner();
+        info!("{} request: {} {:?}", operation, req.list, req.entry);
+        let resource = format!("{} {}", req.list, req.entry);
+
+        let Some(editor) = editor else {
+            if self.audit.record(
+                AuditEntry::decision(operation, &resource, false, "Missing or invalid policy editor token")
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", "policy_editor_required"),
+            ) {
+                warn!("❌ Policy edit denied: missing or invalid policy editor token");
+            }
+            return Err(Status::permission_denied(format!(
+                "policy_editor_required: {} needs a [server.policy_editors] token",
+                operation
+            )));
+        };
+        match self.edit_policy(&editor, action, &req).await {
+            Ok((entry, state)) => {
+                let reason = match action {
+                    EditAction::Add => format!("Added {:?} to {}", entry, req.list),
+                    EditAction::Remove => format!("Removed {:?} from {}", entry, req.list),
+                };
+                self.audit.record(
+                    AuditEntry::note("policy_edit", operation, &resource, &reason)
+                        .with_request_id(&request_id)
+                        .with_metadata("editor", editor)
+                        .with_metadata("persisted", req.persist.to_string())
+                        .with_metadata("policy_hash", state.digest.clone()),
+                );
+                Ok(Response::new(PolicyEntryResponse {
+                    entry,
+                    persisted: req.persist,
+                    stats: Some(state.stats),
+                    policy: Some(self.policy_info(&state)),
+                }))
+            }
+            Err(status) => {
+                self.audit.record(
+                    AuditEntry::note("policy_edit_failed", operation, &resource, status.message())
+                        .with_request_id(&request_id)
+                        .with_metadata("editor", editor),
+                );
+                Err(status)
+            }
+        }
+    }
+
+    /// The resolved `path` when it is a temp file `session_id` created,
+    /// which it may read and write whatever the policy says
+    fn temp_file_grant(&self, session_id: &str, path: &Path) -> Option<PathBuf> {
+        let scratch = self.scratch.as_ref()?;
+        resolved_target(path).filter(|resolved| scratch.owns(session_id, resolved))
+    }
+
+    fn quota_usage(&self) -> Vec<QuotaUsage> {
+        self.quotas
+            .usage(self.clock.unix_now())
+            .into_iter()
+            .map(|usage| QuotaUsage {
+                domain: usage.domain,
+                max_concurrent: usage.max_concurrent,
+                in_flight: usage.in_flight,
+                max_bytes_per_day: usage.max_bytes_per_day,
+                bytes_used: usage.bytes_used,
+                resets_at: usage.resets_at,
+            })
+            .collect()
+    }
+
+    fn session_id(capability: &Option<Capability>) -> &str {
+        capability.as_ref().map(|c| c.session_id.as_str()).unwrap_or_default()
+    }
+
+    /// [`PolicyState::authorize_path`], timed against `[server]
+    /// latency_budget_ms`. Writes to the daemon's own files are refused
+    /// first, so no allow entry, rule or approval reaches them.
+    fn authorize_path(&self, state: &PolicyState, path: &Path, access: Access) -> Result<Result<(), Denial>> {
+        let started = Instant::now();
+        let protected = match access {
+            Access::Write if state.validator.precheck(path).is_ok() => self.protected.check(path),
+            _ => Ok(()),
+        };
+        let authorized = match protected {
+            Ok(()) => state.authorize_path(path, access),
+            Err(denial) => Ok(Err(denial)),
+        };
+        self.record_latency(state, started.elapsed());
+        authorized
+    }
+
+    /// [`authorize_path`](Self::authorize_path) for writing `path`, and
+    /// then `resolved`, the path it leads to, when that differs
+    fn authorize_resolved(&self, state: &PolicyState, path: &Path, resolved: &Path) -> Result<Result<(), Denial>> {
+        let authorized = self.authorize_path(state, path, Access::Write)?;
+        if authorized.is_err() || resolved == path {
+            return Ok(authorized);
+        }
+        self.authorize_path(state, resolved, Access::Write)
+    }
+
+    /// Logs and audits entering and leaving degraded mode
+    fn record_latency(&self, state: &PolicyState, elapsed: Duration) {
+        if let Some(transition) = self.latency.record(elapsed, state.latency_budget) {
+            let budget_ms = state.latency_budget.map_or(0, |b| b.as_millis()).to_string();
+            let (resource, p99, message) = match transition {
+                Transition::Degraded { p99 } => {
+                    ("degraded", p99, "Decision latency over budget; shedding escalations and searches")
+                }
+                Transition::Recovered { p99 } => ("recovered", p99, "Decision latency back under budget"),
+            };
+            warn!("{}: p99 {:?}, budget {}ms", message, p99, budget_ms);
+            self.audit.record(
+                AuditEntry::note("degraded_mode", "decision_latency", resource, message)
+                    .with_metadata("p99_us", p99.as_micros().to_string())
+                    .with_metadata("budget_ms", budget_ms),
+            );
+        }
+    }
+
+    /// Offers a denied request to the approver, after looking for an
+    /// `allow_session` grant covering it. Returns whether the request may
+    /// go ahead; the outcome is audited here either way.
+    async fn escalate(
+        &self,
+        state: &PolicyState,
+        operation: &'static str,
+        resource: &str,
+        session_id: &str,
+        request_id: &str,
+        denial: &Denial,
+    ) -> bool {
+        let Some(approver) = state.approver.as_ref().filter(|a| a.escalates(operation, denial)) else {
+            return false;
+        };
+        // Waiting on a human holds the request open; the denial stands
+        if self.latency.is_degraded() {
+            info!("Not escalating {} on {} while degraded", operation, resource);
+            return false;
+        }
+        if !session_id.is_empty() {
+            if let Some(grant) = self.grants.find(session_id, operation, resource) {
+                self.audit.record(
+                    AuditEntry::decision(operation, resource, true, "Allowed by session grant")
+                        .with_session(session_id)
+                        .with_request_id(request_id)
+                        .with_metadata("violation", denial.violation)
+                        .with_metadata("approval", "session_grant")
+                        .with_metadata("approver", grant.approver),
+                );
+                return true;
+            }
+        }
+
+        info!("Escalating {} on {} for approval", operation, resource);
+        let mut approval = approver
+            .request(ApprovalRequest {
+                request_id: request_id.to_string(),
+                session_id: session_id.to_string(),
+                operation: operation.to_string(),
+                resource: resource.to_string(),
+                reason: denial.reason.clone(),
+                violation: denial.violation.to_string(),
+                reason_code: reason_code(denial.violation) as i32,
+            })
+            .await;
+        // A grant needs a session to belong to
+        if matches!(approval.verdict, Verdict::AllowSession(_)) && session_id.is_empty() {
+            approval.verdict = Verdict::AllowOnce;
+        }
+
+        let reason = match (&approval.failure, approval.verdict) {
+            (Some(failure), _) => format!("{}; escalation failed: {}", denial.reason, failure),
+            (None, Verdict::Deny) => format!("{}; denied by {}", denial.reason, approval.approver),
+            (None, Verdict::AllowOnce) => format!("Approved once by {}", approval.approver),
+            (None, Verdict::AllowSession(ttl)) => {
+                format!("Approved for the session by {} for {}s", approval.approver, ttl.as_secs())
+            }
+        };
+        let mut entry = AuditEntry::decision(operation, resource, approval.allowed(), &reason)
+            .with_session(session_id)
+            .with_request_id(request_id)
+            .with_metadata("violation", denial.violation)
+            .with_metadata("approval", approval.outcome())
+            .with_metadata("approver", approval.approver.clone());
+        if let Verdict::AllowSession(ttl) = approval.verdict {
+            entry = entry.with_metadata("grant_secs", ttl.as_secs().to_string());
+        }
+        self.audit.record(entry);
+        if let Verdict::AllowSession(ttl) = approval.verdict {
+            let state = self.state();
+            let conflicts = &state.policy.escalation.conflicts;
+            let revoked = self.grants.insert(session_id, operation, resource, ttl, &approval.approver, conflicts);
+            state.validator.clear_cache();
+            let reason = format!("Granted for {}s by {}", ttl.as_secs(), approval.approver);
+            self.audit.record(
+                AuditEntry::note("grant_created", operation, resource, &reason)
+                    .with_session(session_id)
+                    .with_request_id(request_id)
+                    .with_metadata("approver", approval.approver.clone())
+                    .with_metadata("grant_secs", ttl.as_secs().to_string()),
+            );
+            for (revoked_operation, revoked_resource, grant) in revoked {
+                let reason = format!("Revoked by a conflicting grant for {} on {}", operation, resource);
+                self.audit.record(
+                    AuditEntry::note("grant_revoked", &revoked_operation, &revoked_resource, &reason)
+                        .with_session(session_id)
+                        .with_request_id(request_id)
+                        .with_metadata("approver", grant.approver)
+                        .with_metadata("conflicts_with", operation),
+                );
+            }
+        }
+        if approval.allowed() {
+            info!("✅ {}: {} {}", reason, operation, resource);
+        } else {
+            warn!("❌ Escalation of {} on {} denied: {}", operation, resource, reason);
+        }
+        approval.allowed()
+    }
+
+    /// For a snapshot session, whether `path` has changed since the session
+    /// first read it; a strict one is refused the read instead. A file
+    /// that cannot be fingerprinted is let through unflagged.
+    fn check_snapshot(&self, session_id: &str, path: &Path) -> Result<bool, Denial> {
+        if self.sessions.snapshot(session_id) == Snapshot::Off {
+            return Ok(false);
+        }
+        let Ok(fingerprint) = Fingerprint::read(path) else {
+            return Ok(false);
+        };
+        let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.sessions.check_read(session_id, &canonical, fingerprint)
+    }
+
+    /// Spends from the budget of the request's session, if it names one
+    /// created by `create_session`. A refusal is audited here.
+    fn debit(
+        &self,
+        operation: &str,
+        resource: &str,
+        session_id: &str,
+        request_id: &str,
+        budget: Budget,
+        amount: u64,
+    ) -> Result<Option<Debit>, Denial> {
+        self.sessions.debit(session_id, budget, amount).inspect_err(|denial| {
+            if self.audit.record(
+                AuditEntry::decision(operation, resource, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(request_id)
+                    .with_metadata("violation", denial.violation)
+                    .with_metadata("budget", budget.to_string()),
+            ) {
+                warn!("❌ {} denied: {} ({})", operation, resource, denial.reason);
+            }
+        })
+    }
+
+    /// The denial for an operation ruled out whatever it acts on: by
+    /// read-only mode, for any but a read, by `[operations]` switching it
+    /// off or by a `[schedule]` window that is not open. Audited here, and
+    /// asked before the operation does anything else, escalating and
+    /// debiting a session included.
+    fn check_operation(
+        &self,
+        class: Operation,
+        operation: &str,
+        resource: &str,
+        session_id: &str,
+        request_id: &str,
+    ) -> Result<(), Denial> {
+        let checked = if class != Operation::Read && self.read_only.load(Ordering::Relaxed) {
+            Err(Denial::new(
+                "read_only_mode",
+                "The daemon is in read-only mode; writes, commands and network access are denied".to_string(),
+            ))
+        } else {
+            let state = self.state();
+            state
+                .operations
+                .check(class)
+                .and_then(|()| state.schedule.check(class, self.clock.unix_now()))
+        };
+        let Err(denial) = checked else {
+            return Ok(());
+        };
+        if self.audit.record(
+            AuditEntry::decision(operation, resource, false, &denial.reason)
+                .with_session(session_id)
+                .with_request_id(request_id)
+                .with_metadata("violation", denial.violation),
+        ) {
+            warn!("❌ {} denied: {} ({})", operation, resource, denial.reason);
+        }
+        Err(denial)
+    }
+
+    /// A path that passed its check but resolved elsewhere once opened
+    fn record_swap(&self, operation: &str, path: &str, session_id: &str, request_id: &str, denial: &Denial) {
+        self.audit.record(
+            AuditEntry::decision(operation, path, false, &denial.reason)
+                .with_session(session_id)
+                .with_request_id(request_id)
+                .with_metadata("violation", denial.violation),
+        );
+        warn!("❌ Possible TOCTOU attack on {}: {}", path, denial.reason);
+    }
+
+    /// The RPC error for a check that could not be completed, under
+    /// `on_internal_error = "error"`
+    fn internal_status(resource: &str, err: anyhow::Error) -> Status {
+        Status::internal(format!("Internal error while checking {}: {:#}", resource, err))
+    }
+
+    /// The status for a denial of `operation` on `target`, with the reason
+    /// worded by the `[messages]` catalog
+    fn denial_status(&self, operation: &str, target: &str, denial: Denial) -> SecurityStatus {
+        let params = Params {
+            operation,
+            target,
+            detail: &denial.reason,
+        };
+        let reason = self.state().messages.render(denial.violation, &params);
+        self.create_security_status(false, reason, vec![denial.violation.to_string()])
     }
 
     fn create_security_status(&self, allowed: bool, reason: String, violations: Vec<String>) -> SecurityStatus {
+        let reason_code = match violations.first() {
+            _ if allowed => ReasonCode::AccessGranted,
+            Some(violation) => reason_code(violation),
+            None => ReasonCode::Unspecified,
+        };
+        // Allowed requests have no violation; their wording is access_granted's
+        let reason = if allowed {
+            let params = Params {
+                operation: "",
+                target: "",
+                detail: &reason,
+            };
+            self.state().messages.render("access_granted", &params)
+        } else {
+            reason
+        };
         SecurityStatus {
             allowed,
             reason,
             violations,
+            reason_code: reason_code as i32,
         }
     }
 }
@@ -34,167 +897,4082 @@ impl enforcement_service_server::EnforcementService for EnforcementServiceImpl {
         &self,
         request: Request<ReadFileRequest>,
     ) -> Result<Response<ReadFileResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
         let req = request.into_inner();
         info!("ReadFile request: path={}", req.path);
 
         let path = Path::new(&req.path);
+        let session_id = Self::session_id(&req.capability);
+        let respond = |denial: Denial| {
+            Response::new(ReadFileResponse {
+                status: Some(self.denial_status("read_file", &req.path, denial)),
+                ..Default::default()
+            })
+        };
+        let deny = |denial: Denial| {
+            if self.audit.record(
+                AuditEntry::decision("read_file", &req.path, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Access denied: {} ({})", req.path, denial.reason);
+            }
+            respond(denial)
+        };
+        if let Err(denial) = self.check_operation(Operation::Read, "read_file", &req.path, session_id, &request_id) {
+            return Ok(respond(denial));
+        }
 
         // Validate path against policy
-        match self.validator.can_read(path) {
-            Ok(true) => {
-                // Attempt to read the file
-                match std::fs::read(&req.path) {
-                    Ok(data) => {
-                        info!("✅ File read successful: {} ({} bytes)", req.path, data.len());
-                        Ok(Response::new(ReadFileResponse {
-                            data,
-                            status: Some(self.create_security_status(
-                                true,
-                                "Access granted".to_string(),
-                                vec![],
-                            )),
-                        }))
-                    }
-                    Err(e) => {
-                        error!("File system error reading {}: {}", req.path, e);
-                        Err(Status::not_found(format!("File not found: {}", e)))
-                    }
-                }
+        let authorized = self
+            .authorize_path(&state, path, Access::Read)
+            .map_err(|e| Self::internal_status(&req.path, e))?;
+        let mut approved = None;
+        if let Err(denial) = &authorized {
+            approved = self.temp_file_grant(session_id, path);
+            if approved.is_none() && self.escalate(&state, "read_file", &req.path, session_id, &request_id, denial).await {
+                approved = resolved_target(path);
             }
-            Ok(false) => {
-                warn!("❌ Access denied: {} (not in allowed paths)", req.path);
+        }
+        if let (Err(denial), None) = (authorized, &approved) {
+            return Ok(deny(denial));
+        }
+
+        // The path may have changed since it was checked, so what was
+        // opened is checked again
+        let can_read = |resolved: &Path| {
+            approved.as_deref() == Some(resolved) || state.validator.can_read(resolved).unwrap_or(false)
+        };
+        // Redaction needs the whole file, so a part of a covered one is
+        // taken from what redaction made of it
+        let ranged = req.offset > 0 || req.length > 0;
+        let covered = state.redactor.covers(path);
+        let read = if ranged && !covered {
+            verified::read_range(path, req.offset, req.length, &can_read)
+        } else {
+            verified::read(path, &can_read).map(|data| (data, true))
+        };
+        match read {
+            Ok((data, mut eof)) => {
+                let (mut data, redactions) = match state.redactor.redact(path, &data) {
+                    Ok(Some(redacted)) => (redacted.data, Some(redacted.replacements)),
+                    Ok(None) => (data, None),
+                    Err(denial) => return Ok(deny(denial)),
+                };
+                if ranged && covered {
+                    (data, eof) = verified::slice_range(data, req.offset, req.length);
+                }
+                let changed_since_first_read = match self.check_snapshot(session_id, path) {
+                    Ok(changed) => changed,
+                    Err(denial) => return Ok(deny(denial)),
+                };
+                let debit = match self.debit("read_file", &req.path, session_id, &request_id, Budget::BytesRead, data.len() as u64) {
+                    Ok(debit) => debit,
+                    Err(denial) => return Ok(respond(denial)),
+                };
+                info!("✅ File read successful: {} ({} bytes)", req.path, data.len());
+                let mut entry = AuditEntry::decision("read_file", &req.path, true, "Access granted")
+                    .with_session(session_id)
+                    .with_request_id(&request_id);
+                // Only the count; the masked values never reach the log
+                if let Some(count) = redactions {
+                    entry = entry.with_metadata("redactions", count.to_string());
+                }
+                if changed_since_first_read {
+                    entry = entry.with_metadata("changed_since_first_read", "true");
+                }
+                self.audit.record(with_debit(entry, debit));
+                // A digest of the real content would say something about
+                // what was masked
+                let version = if redactions.is_some() || ranged { String::new() } else { edit::version(&data) };
+                let detect = req.detect_encoding || (!ranged && data.len() <= encoding::DEFAULT_MAX_BYTES);
+                let encoding_guess = if detect { encoding::guess(&data).to_string() } else { String::new() };
                 Ok(Response::new(ReadFileResponse {
-                    data: vec![],
+                    data,
                     status: Some(self.create_security_status(
-                        false,
-                        "Path not in allowed read list".to_string(),
-                        vec!["path_not_allowed".to_string()],
+                        true,
+                        "Access granted".to_string(),
+                        vec![],
                     )),
+                    redacted: redactions.is_some(),
+                    redactions: redactions.unwrap_or(0),
+                    version,
+                    eof,
+                    encoding_guess,
+                    changed_since_first_read,
                 }))
             }
             Err(e) => {
-                error!("Path validation error for {}: {}", req.path, e);
-                Err(Status::invalid_argument(format!(
-                    "Path validation failed: {}",
-                    e
-                )))
+                if let Some(swapped) = verified::swapped(&e) {
+                    let denial = swapped.denial();
+                    self.record_swap("read_file", &req.path, session_id, &request_id, &denial);
+                    return Ok(respond(denial));
+                }
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    return Err(Status::not_found(format!("File not found: {}", e)));
+                }
+                let denial = state
+                    .internal_error(&req.path, e.into())
+                    .map_err(|e| Self::internal_status(&req.path, e))?;
+                Ok(deny(denial))
             }
         }
     }
 
     async fn write_file(
         &self,
-        _request: Request<WriteFileRequest>,
+        request: Request<WriteFileRequest>,
     ) -> Result<Response<WriteFileResponse>, Status> {
-        Err(Status::unimplemented("write_file not yet implemented"))
-    }
-
-    async fn list_directory(
-        &self,
-        _request: Request<ListDirectoryRequest>,
-    ) -> Result<Response<ListDirectoryResponse>, Status> {
-        Err(Status::unimplemented("list_directory not yet implemented"))
-    }
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("WriteFile request: path={} ({} bytes)", req.path, req.data.len());
 
-    async fn delete_file(
-        &self,
-        _request: Request<DeleteFileRequest>,
-    ) -> Result<Response<DeleteFileResponse>, Status> {
-        Err(Status::unimplemented("delete_file not yet implemented"))
-    }
+        let mode = match WriteFileMode::try_from(req.mode) {
+            Ok(WriteFileMode::Overwrite) if req.append => WriteMode::Append,
+            Ok(WriteFileMode::Overwrite) => WriteMode::Overwrite,
+            Ok(WriteFileMode::Append) => WriteMode::Append,
+            Ok(WriteFileMode::CreateNew) => WriteMode::CreateNew,
+            Err(_) => {
+                return Err(Status::invalid_argument(format!("Unknown write mode: {}", req.mode)))
+            }
+        };
+        let path = Path::new(&req.path);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) = self.check_operation(Operation::Write, "write_file", &req.path, session_id, &request_id) {
+            return Ok(Response::new(WriteFileResponse {
+                status: Some(self.denial_status("write_file", &req.path, denial)),
+            }));
+        }
 
-    async fn http_request(
-        &self,
-        _request: Request<HttpRequestData>,
-    ) -> Result<Response<HttpResponseData>, Status> {
-        Err(Status::unimplemented("http_request not yet implemented"))
-    }
+        let authorized = self
+            .authorize_path(&state, path, Access::Write)
+            .map_err(|e| Self::internal_status(&req.path, e))?;
+        let mut approved = None;
+        if let Err(denial) = &authorized {
+            approved = self.temp_file_grant(session_id, path);
+            if approved.is_none() && self.escalate(&state, "write_file", &req.path, session_id, &request_id, denial).await {
+                approved = resolved_target(path);
+            }
+        }
+        if let (Err(denial), None) = (authorized, &approved) {
+            if self.audit.record(
+                AuditEntry::decision("write_file", &req.path, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Write denied: {} ({})", req.path, denial.reason);
+            }
+            return Ok(Response::new(WriteFileResponse {
+                status: Some(self.denial_status("write_file", &req.path, denial)),
+            }));
+        }
 
-    async fn dns_lookup(
-        &self,
-        _request: Request<DnsLookupRequest>,
-    ) -> Result<Response<DnsLookupResponse>, Status> {
-        Err(Status::unimplemented("dns_lookup not yet implemented"))
+        let debit = match self.debit("write_file", &req.path, session_id, &request_id, Budget::FilesWritten, 1) {
+            Ok(debit) => debit,
+            Err(denial) => {
+                return Ok(Response::new(WriteFileResponse {
+                    status: Some(self.denial_status("write_file", &req.path, denial)),
+                }));
+            }
+        };
+        let options = WriteOptions {
+            mode,
+            sync: req.sync,
+        };
+        let written = match &approved {
+            Some(target) => state.writer.write_approved(path, &req.data, options, target),
+            None => state.writer.write(path, &req.data, options),
+        };
+        if written.is_err() && debit.is_some() {
+            self.sessions.refund(session_id, Budget::FilesWritten, 1);
+        }
+        match written {
+            Ok(()) => {
+                info!("✅ File write successful: {} ({} bytes)", req.path, req.data.len());
+                self.audit.record(with_debit(
+                    AuditEntry::decision("write_file", &req.path, true, "Access granted")
+                        .with_session(session_id)
+                        .with_request_id(&request_id),
+                    debit,
+                ));
+                Ok(Response::new(WriteFileResponse {
+                    status: Some(self.create_security_status(
+                        true,
+                        "Access granted".to_string(),
+                        vec![],
+                    )),
+                }))
+            }
+            Err(e) => {
+                if let Some(swapped) = verified::swapped(&e) {
+                    let denial = swapped.denial();
+                    self.record_swap("write_file", &req.path, session_id, &request_id, &denial);
+                    return Ok(Response::new(WriteFileResponse {
+                        status: Some(self.denial_status("write_file", &req.path, denial)),
+                    }));
+                }
+                if e.kind() == std::io::ErrorKind::AlreadyExists && mode == WriteMode::CreateNew {
+                    let denial = Denial::new("file_exists", format!("{} already exists", req.path));
+                    if self.audit.record(
+                        AuditEntry::decision("write_file", &req.path, false, &denial.reason)
+                            .with_session(session_id)
+                            .with_request_id(&request_id)
+                            .with_metadata("violation", denial.violation),
+                    ) {
+                        warn!("❌ Write denied: {} ({})", req.path, denial.reason);
+                    }
+                    return Ok(Response::new(WriteFileResponse {
+                        status: Some(self.denial_status("write_file", &req.path, denial)),
+                    }));
+                }
+                let denial = state
+                    .internal_error(&req.path, e.into())
+                    .map_err(|e| Self::internal_status(&req.path, e))?;
+                self.audit.record(
+                    AuditEntry::decision("write_file", &req.path, false, &denial.reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                );
+                Ok(Response::new(WriteFileResponse {
+                    status: Some(self.denial_status("write_file", &req.path, denial)),
+                }))
+            }
+        }
     }
 
-    async fn execute_command(
+    async fn write_file_stream(
         &self,
-        _request: Request<ExecuteCommandRequest>,
-    ) -> Result<Response<ExecuteCommandResponse>, Status> {
-        Err(Status::unimplemented("execute_command not yet implemented"))
-    }
+        request: Request<tonic::Streaming<WriteFileChunk>>,
+    ) -> Result<Response<WriteFileResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let mut stream = request.into_inner();
+        let Some(mut first) = stream.message().await? else {
+            return Err(Status::invalid_argument("The first chunk must name the file"));
+        };
+        info!("WriteFileStream request: path={}", first.path);
+        let path = Path::new(&first.path);
+        let session_id = Self::session_id(&first.capability);
 
-    async fn request_capability(
-        &self,
-        _request: Request<CapabilityRequest>,
-    ) -> Result<Response<CapabilityResponse>, Status> {
-        Err(Status::unimplemented("request_capability not yet implemented"))
-    }
+        let respond = |denial: Denial| {
+            Response::new(WriteFileResponse {
+                status: Some(self.denial_status("write_file", &first.path, denial)),
+            })
+        };
+        let deny = |denial: Denial| {
+            if self.audit.record(
+                AuditEntry::decision("write_file", &first.path, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Write denied: {} ({})", first.path, denial.reason);
+            }
+            respond(denial)
+        };
+        // The outer error fails the RPC under `on_internal_error = "error"`
+        let io_denied = |e: std::io::Error| {
+            if let Some(swapped) = verified::swapped(&e) {
+                let denial = swapped.denial();
+                self.record_swap("write_file", &first.path, session_id, &request_id, &denial);
+                return Ok(respond(denial));
+            }
+            state.internal_error(&first.path, e.into()).map(deny)
+        };
 
-    async fn revoke_capability(
-        &self,
-        _request: Request<RevokeRequest>,
-    ) -> Result<Response<RevokeResponse>, Status> {
-        Err(Status::unimplemented("revoke_capability not yet implemented"))
-    }
+        let checked = self.check_operation(Operation::Write, "write_file", &first.path, session_id, &request_id);
+        if let Err(denial) = checked {
+            return Ok(respond(denial));
+        }
+        let authorized = self
+            .authorize_path(&state, path, Access::Write)
+            .map_err(|e| Self::internal_status(&first.path, e))?;
+        let mut approved = None;
+        if let Err(denial) = &authorized {
+            approved = self.temp_file_grant(session_id, path);
+            if approved.is_none()
+                && self.escalate(&state, "write_file", &first.path, session_id, &request_id, denial).await
+            {
+                approved = resolved_target(path);
+            }
+        }
+        if let (Err(denial), None) = (authorized, &approved) {
+            return Ok(deny(denial));
+        }
+        let debit = match self.debit("write_file", &first.path, session_id, &request_id, Budget::FilesWritten, 1) {
+            Ok(debit) => debit,
+            Err(denial) => return Ok(respond(denial)),
+        };
 
-    async fn get_status(
-        &self,
-        _request: Request<StatusRequest>,
-    ) -> Result<Response<StatusResponse>, Status> {
-        info!("Status request received");
-        
-        Ok(Response::new(StatusResponse {
-            version: env!("CARGO_PKG_VERSION").to_string(),
-            healthy: true,
-            active_policy: Some(PolicyInfo {
-                path: "policy.toml".to_string(),
-                loaded_at: 0,
-            }),
-            resources: Some(ResourceUsage {
-                memory_bytes: 0,
-                cpu_percent: 0.0,
-                active_connections: 0,
-            }),
+        // Chunks go straight to the temporary file, renamed into place only
+        // once a chunk marked done arrives. A stream ending without one may
+        // have been cancelled, which reads as its end, so it writes nothing.
+        let max_bytes = state.policy.resources.max_file_size_mb.saturating_mul(1024 * 1024);
+        let written = match state.writer.begin_replace(path, approved.as_deref()) {
+            Err(e) => Err(UploadFailed::Io(e)),
+            Ok(mut pending) => {
+                let mut total = 0u64;
+                let (mut data, mut done) = (std::mem::take(&mut first.data), first.done);
+                loop {
+                    total += data.len() as u64;
+                    if total > max_bytes {
+                        break Err(UploadFailed::Denied(Denial::new(
+                            "file_too_large",
+                            format!(
+                                "{} would be over [resources] max_file_size_mb of {}",
+                                first.path, state.policy.resources.max_file_size_mb
+                            ),
+                        )));
+                    }
+                    if let Err(e) = pending.write(&data) {
+                        break Err(UploadFailed::Io(e));
+                    }
+                    if done {
+                        break pending.commit(first.sync).map(|()| total).map_err(UploadFailed::Io);
+                    }
+                    match stream.message().await {
+                        Ok(Some(chunk)) if !chunk.path.is_empty() => {
+                            let refused = Status::invalid_argument("Only the first chunk may name the file");
+                            break Err(UploadFailed::Stream(refused));
+                        }
+                        Ok(Some(chunk)) => (data, done) = (chunk.data, chunk.done),
+                        Ok(None) => {
+                            warn!("WriteFileStream for {} ended before its last chunk", first.path);
+                            let unfinished = Status::invalid_argument("The stream ended without a chunk marked done");
+                            break Err(UploadFailed::Stream(unfinished));
+                        }
+                        Err(status) => {
+                            warn!("WriteFileStream for {} cut off: {}", first.path, status.message());
+                            break Err(UploadFailed::Stream(status));
+                        }
+                    }
+                }
+            }
+        };
+        let total = match written {
+            Ok(total) => total,
+            Err(failed) => {
+                if debit.is_some() {
+                    self.sessions.refund(session_id, Budget::FilesWritten, 1);
+                }
+                return match failed {
+                    UploadFailed::Denied(denial) => Ok(deny(denial)),
+                    UploadFailed::Io(e) => io_denied(e).map_err(|e| Self::internal_status(&first.path, e)),
+                    UploadFailed::Stream(status) => Err(status),
+                };
+            }
+        };
+        info!("✅ File write successful: {} ({} bytes)", first.path, total);
+        self.audit.record(with_debit(
+            AuditEntry::decision("write_file", &first.path, true, "Access granted")
+                .with_session(session_id)
+                .with_request_id(&request_id)
+                .with_metadata("bytes", total.to_string()),
+            debit,
+        ));
+        Ok(Response::new(WriteFileResponse {
+            status: Some(self.create_security_status(true, "Access granted".to_string(), vec![])),
         }))
     }
 
-    type GetAuditLogsStream = tokio_stream::wrappers::ReceiverStream<Result<AuditLogEntry, Status>>;
-
-    async fn get_audit_logs(
+    async fn edit_file(
         &self,
-        _request: Request<AuditLogRequest>,
-    ) -> Result<Response<Self::GetAuditLogsStream>, Status> {
-        Err(Status::unimplemented("get_audit_logs not yet implemented"))
-    }
-}
+        request: Request<EditFileRequest>,
+    ) -> Result<Response<EditFileResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("EditFile request: path={} ({} replacements)", req.path, req.replacements.len());
 
-pub async fn serve(addr: SocketAddr, policy: Policy) -> Result<()> {
-    info!("Starting OpenClaw Enforce gRPC server");
+        if !req.replacements.is_empty() && !req.data.is_empty() {
+            return Err(Status::invalid_argument("Set either replacements or data, not both"));
+        }
+        if req.replacements.is_empty() && req.expected_version.is_empty() && req.expected_mtime_ns == 0 {
+            return Err(Status::invalid_argument(
+                "Replacing a whole file needs expected_version or expected_mtime_ns; use WriteFile otherwise",
+            ));
+        }
+        let path = Path::new(&req.path);
+        let session_id = Self::session_id(&req.capability);
 
-    // Health service
-    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
-    health_reporter
-        .set_serving::<tonic_health::pb::health_server::HealthServer<
-            tonic_health::server::HealthReporter,
-        >>()
-        .await;
+        let respond = |denial: Denial, current: Option<&Current>| {
+            Response::new(EditFileResponse {
+                status: Some(self.denial_status("edit_file", &req.path, denial)),
+                version: current.map(|c| c.version.clone()).unwrap_or_default(),
+                mtime_ns: current.map_or(0, |c| c.mtime_ns),
+            })
+        };
+        let deny = |denial: Denial, current: Option<&Current>| {
+            if self.audit.record(
+                AuditEntry::decision("edit_file", &req.path, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Edit denied: {} ({})", req.path, denial.reason);
+            }
+            respond(denial, current)
+        };
+        // The outer error fails the RPC under `on_internal_error = "error"`
+        let io_denied = |e: std::io::Error, current: Option<&Current>| {
+            if let Some(swapped) = verified::swapped(&e) {
+                let denial = swapped.denial();
+                self.record_swap("edit_file", &req.path, session_id, &request_id, &denial);
+                return Ok(respond(denial, current));
+            }
+            state.internal_error(&req.path, e.into()).map(|denial| deny(denial, current))
+        };
 
-    // Enforcement service
-    let enforcement_service = EnforcementServiceImpl::new(policy);
-    
-    info!("Services registered:");
-    info!("  - grpc.health.v1.Health");
-    info!("  - openclaw.enforce.EnforcementService");
-    info!("gRPC server listening on {}", addr);
+        if let Err(denial) = self.check_operation(Operation::Write, "edit_file", &req.path, session_id, &request_id) {
+            return Ok(respond(denial, None));
+        }
 
-    Server::builder()
-        .add_service(health_service)
-        .add_service(enforcement_service_server::EnforcementServiceServer::new(
-            enforcement_service,
-        ))
-        .serve(addr)
-        .await?;
+        // The file is read and written back, so both lists must allow it
+        let mut authorized = self
+            .authorize_path(&state, path, Access::Read)
+            .map_err(|e| Self::internal_status(&req.path, e))?;
+        if authorized.is_ok() {
+            authorized = self
+                .authorize_path(&state, path, Access::Write)
+                .map_err(|e| Self::internal_status(&req.path, e))?;
+        }
+        let mut approved = None;
+        if let Err(denial) = &authorized {
+            approved = self.temp_file_grant(session_id, path);
+            if approved.is_none() && self.escalate(&state, "edit_file", &req.path, session_id, &request_id, denial).await {
+                approved = resolved_target(path);
+            }
+        }
+        if let (Err(denial), None) = (authorized, &approved) {
+            return Ok(deny(denial, None));
+        }
+        // Whether a replacement applies would reveal the masked content
+        if state.redactor.covers(path) {
+            return Ok(deny(
+                Denial::new(
+                    "redacted_file",
+                    format!("{} is covered by a redaction rule and cannot be edited", req.path),
+                ),
+                None,
+            ));
+        }
 
-    Ok(())
+        // Held until the edit returns, however it returns
+        let _lock = self
+            .edit_locks
+            .lock(&resolved_target(path).unwrap_or_else(|| path.to_path_buf()))
+            .await;
+        let can_read = |resolved: &Path| {
+            approved.as_deref() == Some(resolved) || state.validator.can_read(resolved).unwrap_or(false)
+        };
+        let current = match Current::read(path, &can_read) {
+            Ok(current) => current,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Status::not_found(format!("File not found: {}", e)));
+            }
+            Err(e) => return io_denied(e, None).map_err(|e| Self::internal_status(&req.path, e)),
+        };
+        if let Err(denial) = current.check_expected(&req.path, &req.expected_version, req.expected_mtime_ns) {
+            return Ok(deny(denial, Some(&current)));
+        }
+        let edited;
+        let data = if req.replacements.is_empty() {
+            &req.data
+        } else {
+            let replacements: Vec<Replacement> = req
+                .replacements
+                .iter()
+                .map(|r| Replacement {
+                    old_text: r.old_text.clone(),
+                    new_text: r.new_text.clone(),
+                    replace_all: r.replace_all,
+                })
+                .collect();
+            edited = match current.apply(&req.path, &replacements) {
+                Ok(edited) => edited,
+                Err(denial) => return Ok(deny(denial, Some(&current))),
+            };
+            &edited
+        };
+
+        let debit = match self.debit("edit_file", &req.path, session_id, &request_id, Budget::FilesWritten, 1) {
+            Ok(debit) => debit,
+            Err(denial) => return Ok(respond(denial, Some(&current))),
+        };
+        let options = WriteOptions {
+            mode: WriteMode::Overwrite,
+            sync: req.sync,
+        };
+        let written = match &approved {
+            Some(target) => state.writer.write_approved(path, data, options, target),
+            None => state.writer.write(path, data, options),
+        };
+        if let Err(e) = written {
+            if debit.is_some() {
+                self.sessions.refund(session_id, Budget::FilesWritten, 1);
+            }
+            return io_denied(e, Some(&current)).map_err(|e| Self::internal_status(&req.path, e));
+        }
+
+        let version = edit::version(data);
+        info!("✅ File edit successful: {} ({} -> {} bytes)", req.path, current.data.len(), data.len());
+        self.audit.record(with_debit(
+            AuditEntry::decision("edit_file", &req.path, true, "Access granted")
+                .with_session(session_id)
+                .with_request_id(&request_id)
+                .with_metadata("version", version.clone()),
+            debit,
+        ));
+        Ok(Response::new(EditFileResponse {
+            status: Some(self.create_security_status(true, "Access granted".to_string(), vec![])),
+            version,
+            mtime_ns: std::fs::metadata(path).map_or(0, |metadata| edit::mtime_ns(&metadata)),
+        }))
+    }
+
+    async fn list_directory(
+        &self,
+        request: Request<ListDirectoryRequest>,
+    ) -> Result<Response<ListDirectoryResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("ListDirectory request: path={} recursive={}", req.path, req.recursive);
+
+        let path = PathBuf::from(&req.path);
+        let session_id = Self::session_id(&req.capability);
+        let deny = |denial: Denial| {
+            Response::new(ListDirectoryResponse {
+                status: Some(self.denial_status("list_directory", &req.path, denial)),
+                ..Default::default()
+            })
+        };
+        if let Err(denial) =
+            self.check_operation(Operation::Read, "list_directory", &req.path, session_id, &request_id)
+        {
+            return Ok(deny(denial));
+        }
+
+        let mut authorized = self
+            .authorize_path(&state, &path, Access::Read)
+            .map_err(|e| Self::internal_status(&req.path, e))?;
+        // A recursive walk costs as much as a search, so it is shed the same way
+        if authorized.is_ok() && req.recursive && self.latency.is_degraded() {
+            authorized = Err(Denial::new(
+                "degraded",
+                "Recursive listings are refused while decisions are over the latency budget".to_string(),
+            ));
+        }
+        if let Err(denial) = authorized {
+            if self.audit.record(
+                AuditEntry::decision("list_directory", &req.path, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ List denied: {} ({})", req.path, denial.reason);
+            }
+            return Ok(deny(denial));
+        }
+        if !std::fs::metadata(&path).map_err(io_status)?.is_dir() {
+            return Err(Status::failed_precondition(format!("{} is not a directory", req.path)));
+        }
+        self.audit.record(
+            AuditEntry::decision("list_directory", &req.path, true, "Access granted")
+                .with_session(session_id)
+                .with_request_id(&request_id),
+        );
+
+        let max_entries = match req.max_entries {
+            0 => MAX_LIST_ENTRIES,
+            requested => requested.min(MAX_LIST_ENTRIES),
+        } as usize;
+        let options = WalkOptions {
+            threads: if req.recursive { state.walk_threads } else { 1 },
+            max_depth: if req.recursive { usize::MAX } else { 1 },
+        };
+        let validator = state.validator.clone();
+        // Dropped with this future when the client goes away, stopping the walk
+        let cancel = CancelOnDrop::default();
+        let cancelled = cancel.0.clone();
+        let (entries, truncated) = tokio::task::spawn_blocking(move || {
+            let entries = Mutex::new(Vec::new());
+            let truncated = AtomicBool::new(false);
+            let stop = || cancelled.load(Ordering::Relaxed) || truncated.load(Ordering::Relaxed);
+            walk::walk(&path, options, &validator, &stop, &|entry, metadata| {
+                let mut entries = entries.lock().unwrap_or_else(|e| e.into_inner());
+                if entries.len() >= max_entries {
+                    truncated.store(true, Ordering::Relaxed);
+                    return;
+                }
+                entries.push(FileEntry {
+                    name: entry.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                    path: entry.to_string_lossy().into_owned(),
+                    is_directory: metadata.is_dir(),
+                    size: if metadata.is_dir() { 0 } else { metadata.len() },
+                });
+            });
+            let mut entries = entries.into_inner().unwrap_or_else(|e| e.into_inner());
+            entries.sort_by(|a, b| a.path.cmp(&b.path));
+            (entries, truncated.into_inner())
+        })
+        .await
+        .map_err(|e| Status::internal(format!("Listing failed: {}", e)))?;
+
+        Ok(Response::new(ListDirectoryResponse {
+            entries,
+            status: Some(self.create_security_status(true, "Access granted".to_string(), vec![])),
+            truncated,
+        }))
+    }
+
+    async fn stat_file(
+        &self,
+        request: Request<StatFileRequest>,
+    ) -> Result<Response<StatFileResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("StatFile request: path={}", req.path);
+
+        let path = Path::new(&req.path);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) = self.check_operation(Operation::Read, "stat_file", &req.path, session_id, &request_id) {
+            return Ok(Response::new(StatFileResponse {
+                status: Some(self.denial_status("stat_file", &req.path, denial)),
+                ..Default::default()
+            }));
+        }
+
+        // Symlinks in the directories along the way are followed, so where
+        // they lead must be allowed too; the last component is not followed
+        let started = Instant::now();
+        let resolved = resolved_target(path);
+        let mut authorized = state.authorize_stat(path);
+        if let (Ok(Ok(())), Some(resolved)) = (&authorized, &resolved) {
+            authorized = state.authorize_stat(resolved);
+        }
+        self.record_latency(&state, started.elapsed());
+        let mut authorized = authorized.map_err(|e| Self::internal_status(&req.path, e))?;
+        if authorized.is_err() && self.temp_file_grant(session_id, path).is_some() {
+            authorized = Ok(());
+        }
+        if let Err(denial) = authorized {
+            if self.audit.record(
+                AuditEntry::decision("stat_file", &req.path, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Stat denied: {} ({})", req.path, denial.reason);
+            }
+            return Ok(Response::new(StatFileResponse {
+                status: Some(self.denial_status("stat_file", &req.path, denial)),
+                ..Default::default()
+            }));
+        }
+
+        let metadata = std::fs::symlink_metadata(resolved.as_deref().unwrap_or(path)).map_err(io_status)?;
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Directory
+        } else if file_type.is_file() {
+            FileKind::Regular
+        } else {
+            FileKind::Other
+        };
+        self.audit.record(
+            AuditEntry::decision("stat_file", &req.path, true, "Access granted")
+                .with_session(session_id)
+                .with_request_id(&request_id),
+        );
+        Ok(Response::new(StatFileResponse {
+            status: Some(self.create_security_status(true, "Access granted".to_string(), vec![])),
+            size: metadata.len(),
+            mtime_ns: edit::mtime_ns(&metadata),
+            kind: kind.into(),
+        }))
+    }
+
+    async fn delete_file(
+        &self,
+        request: Request<DeleteFileRequest>,
+    ) -> Result<Response<DeleteFileResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("DeleteFile request: path={}", req.path);
+
+        let path = Path::new(&req.path);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) =
+            self.check_operation(Operation::Delete, "delete_file", &req.path, session_id, &request_id)
+        {
+            return Ok(Response::new(DeleteFileResponse {
+                status: Some(self.denial_status("delete_file", &req.path, denial)),
+                ..Default::default()
+            }));
+        }
+
+        // The directories along the way are resolved, so a symlinked one
+        // cannot lead outside allowed_write; a symlink as the file itself
+        // is deleted as the link
+        let target = resolved_target(path).unwrap_or_else(|| path.to_path_buf());
+        let authorized = self
+            .authorize_resolved(&state, path, &target)
+            .map_err(|e| Self::internal_status(&req.path, e))?;
+        let mut approved = false;
+        if let Err(denial) = &authorized {
+            approved = self.escalate(&state, "delete_file", &req.path, session_id, &request_id, denial).await;
+        }
+        if let (Err(denial), false) = (authorized, approved) {
+            if self.audit.record(
+                AuditEntry::decision("delete_file", &req.path, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Delete denied: {} ({})", req.path, denial.reason);
+            }
+            return Ok(Response::new(DeleteFileResponse {
+                status: Some(self.denial_status("delete_file", &req.path, denial)),
+                ..Default::default()
+            }));
+        }
+
+        if std::fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.is_dir()) {
+            return Err(Status::failed_precondition(format!(
+                "{} is a directory; delete_file only removes files",
+                req.path
+            )));
+        }
+
+        let result = match &state.trash {
+            Some(trash) => trash.move_to_trash(&target).map(|item| Some(item.id)),
+            None => std::fs::remove_file(&target).map(|_| None),
+        };
+        match result {
+            Ok(trash_id) => {
+                info!("✅ File deleted: {} (trash id: {:?})", req.path, trash_id);
+                let mut entry = AuditEntry::decision("delete_file", &req.path, true, "Access granted")
+                    .with_session(session_id)
+                    .with_request_id(&request_id);
+                if let Some(trash_id) = &trash_id {
+                    entry = entry.with_metadata("trash_id", trash_id.clone());
+                }
+                self.audit.record(entry);
+                Ok(Response::new(DeleteFileResponse {
+                    status: Some(self.create_security_status(
+                        true,
+                        "Access granted".to_string(),
+                        vec![],
+                    )),
+                    trash_id: trash_id.unwrap_or_default(),
+                }))
+            }
+            Err(e) => {
+                error!("File system error deleting {}: {}", req.path, e);
+                Err(io_status(e))
+            }
+        }
+    }
+
+    async fn restore_file(
+        &self,
+        request: Request<RestoreFileRequest>,
+    ) -> Result<Response<RestoreFileResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("RestoreFile request: trash_id={}", req.trash_id);
+
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) =
+            self.check_operation(Operation::Write, "restore_file", &req.trash_id, session_id, &request_id)
+        {
+            return Ok(Response::new(RestoreFileResponse {
+                status: Some(self.denial_status("restore_file", &req.trash_id, denial)),
+                ..Default::default()
+            }));
+        }
+        let Some(trash) = &state.trash else {
+            return Err(Status::failed_precondition("No trash_dir is configured"));
+        };
+        let item = trash.lookup(&req.trash_id).map_err(io_status)?;
+        let original = item.original_path.to_string_lossy().into_owned();
+
+        // The policy may have changed since the file was deleted, and a
+        // directory on the way may have become a symlink
+        let target = resolved_target(&item.original_path).unwrap_or_else(|| item.original_path.clone());
+        let authorized = self
+            .authorize_resolved(&state, &item.original_path, &target)
+            .map_err(|e| Self::internal_status(&original, e))?;
+        if let Err(denial) = authorized {
+            if self.audit.record(
+                AuditEntry::decision("restore_file", &original, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("trash_id", req.trash_id.clone())
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Restore denied: {} ({})", original, denial.reason);
+            }
+            return Ok(Response::new(RestoreFileResponse {
+                status: Some(self.denial_status("restore_file", &original, denial)),
+                path: original,
+            }));
+        }
+
+        match trash.restore(&item, &target) {
+            Ok(()) => {
+                info!("✅ File restored: {} from {}", original, req.trash_id);
+                self.audit.record(
+                    AuditEntry::decision("restore_file", &original, true, "Access granted")
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("trash_id", req.trash_id),
+                );
+                Ok(Response::new(RestoreFileResponse {
+                    path: original,
+                    status: Some(self.create_security_status(
+                        true,
+                        "Access granted".to_string(),
+                        vec![],
+                    )),
+                }))
+            }
+            Err(e) => {
+                error!("File system error restoring {}: {}", original, e);
+                Err(io_status(e))
+            }
+        }
+    }
+
+    async fn create_temp_file(
+        &self,
+        request: Request<CreateTempFileRequest>,
+    ) -> Result<Response<CreateTempFileResponse>, Status> {
+        let request_id = request_id(&request);
+        let req = request.into_inner();
+        info!("CreateTempFile request: prefix={:?} suffix={:?}", req.prefix, req.suffix);
+
+        let session_id = Self::session_id(&req.capability);
+        let resource = format!("{}*{}", req.prefix, req.suffix);
+        if let Err(denial) =
+            self.check_operation(Operation::Write, "create_temp_file", &resource, session_id, &request_id)
+        {
+            return Ok(Response::new(CreateTempFileResponse {
+                status: Some(self.denial_status("create_temp_file", &resource, denial)),
+                ..Default::default()
+            }));
+        }
+        let Some(scratch) = &self.scratch else {
+            return Err(Status::failed_precondition("No scratch_dir is configured"));
+        };
+        let created = scratch.create(session_id, &req.prefix, &req.suffix).map_err(|e| {
+            error!("File system error creating temp file {}: {}", resource, e);
+            io_status(e)
+        })?;
+        match created {
+            Ok(file) => {
+                self.state().validator.clear_cache();
+                let path = file.path.to_string_lossy().into_owned();
+                info!("✅ Temp file created: {}", path);
+                self.audit.record(
+                    AuditEntry::decision("create_temp_file", &path, true, "Access granted")
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("handle_id", file.handle_id.clone()),
+                );
+                Ok(Response::new(CreateTempFileResponse {
+                    status: Some(self.create_security_status(true, "Access granted".to_string(), vec![])),
+                    path,
+                    handle_id: file.handle_id,
+                }))
+            }
+            Err(denial) => {
+                if self.audit.record(
+                    AuditEntry::decision("create_temp_file", &resource, false, &denial.reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ Temp file denied: {} ({})", resource, denial.reason);
+                }
+                Ok(Response::new(CreateTempFileResponse {
+                    status: Some(self.denial_status("create_temp_file", &resource, denial)),
+                    ..Default::default()
+                }))
+            }
+        }
+    }
+
+    async fn cleanup_temp_files(
+        &self,
+        request: Request<CleanupTempFilesRequest>,
+    ) -> Result<Response<CleanupTempFilesResponse>, Status> {
+        let request_id = request_id(&request);
+        let req = request.into_inner();
+        let session_id = Self::session_id(&req.capability);
+        // The response has no status to carry a denial
+        if let Err(denial) =
+            self.check_operation(Operation::Delete, "cleanup_temp_files", "scratch_dir", session_id, &request_id)
+        {
+            return Err(denied_status(&denial));
+        }
+        let Some(scratch) = &self.scratch else {
+            return Err(Status::failed_precondition("No scratch_dir is configured"));
+        };
+        let removed = scratch.cleanup(session_id);
+        if removed > 0 {
+            self.state().validator.clear_cache();
+        }
+        info!("Removed {} temp files of session {:?}", removed, session_id);
+        Ok(Response::new(CleanupTempFilesResponse { removed: removed as u32 }))
+    }
+
+    type SearchFilesStream = tokio_stream::wrappers::ReceiverStream<Result<SearchFilesEvent, Status>>;
+
+    async fn search_files(
+        &self,
+        request: Request<SearchFilesRequest>,
+    ) -> Result<Response<Self::SearchFilesStream>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("SearchFiles request: root={} pattern={:?}", req.root, req.pattern);
+
+        let root = PathBuf::from(&req.root);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) = self.check_operation(Operation::Read, "search_files", &req.root, session_id, &request_id) {
+            return Err(denied_status(&denial));
+        }
+
+        let authorized = self
+            .authorize_path(&state, &root, Access::Read)
+            .map_err(|e| Self::internal_status(&req.root, e))?;
+        if let Err(denial) = authorized {
+            if self.audit.record(
+                AuditEntry::decision("search_files", &req.root, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ Search denied: {} ({})", req.root, denial.reason);
+            }
+            return Err(denied_status(&denial));
+        }
+        if self.latency.is_degraded() {
+            let denial = Denial::new(
+                "degraded",
+                "Searches are refused while decisions are over the latency budget".to_string(),
+            );
+            self.audit.record(
+                AuditEntry::decision("search_files", &req.root, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            );
+            return Err(denied_status(&denial));
+        }
+
+        self.audit.record(
+            AuditEntry::decision("search_files", &req.root, true, "Access granted")
+                .with_session(session_id)
+                .with_request_id(&request_id)
+                .with_metadata("pattern", req.pattern.as_str()),
+        );
+
+        let limit = state.search.max_results;
+        let max_results = match req.max_results {
+            0 => limit,
+            requested => requested.min(limit),
+        };
+        let options = search::SearchOptions {
+            pattern: req.pattern,
+            literal: req.literal,
+            include: req.include,
+            exclude: req.exclude,
+            context_lines: req.context_lines as usize,
+            max_results: max_results as u64,
+            max_file_bytes: state.search.max_file_kb * 1024,
+            time_budget: Duration::from_secs(state.search.time_budget_secs),
+            threads: state.walk_threads,
+        };
+
+        // The walk runs on blocking threads; a client that goes away cancels it
+        let (tx, rx) = mpsc::channel(64);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        {
+            let tx = tx.clone();
+            let cancel = cancel.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = tx.closed() => cancel.store(true, Ordering::Relaxed),
+                    _ = done_rx => {}
+                }
+            });
+        }
+        let (validator, redactor) = (state.validator.clone(), state.redactor.clone());
+        tokio::task::spawn_blocking(move || {
+            let result = search(&root, &options, &validator, &redactor, &cancel, &|hit| {
+                let event = SearchFilesEvent {
+                    r#match: Some(to_proto_match(hit)),
+                    summary: None,
+                };
+                tx.blocking_send(Ok(event)).is_ok()
+            });
+            drop(done_tx);
+            let last = match result {
+                Ok(summary) => Ok(SearchFilesEvent {
+                    r#match: None,
+                    summary: Some(SearchSummary {
+                        files_searched: summary.files_searched,
+                        matches: summary.matches,
+                        truncated: summary.truncated,
+                        timed_out: summary.timed_out,
+                    }),
+                }),
+                Err(e) => Err(Status::invalid_argument(format!("{:#}", e))),
+            };
+            let _ = tx.blocking_send(last);
+        });
+
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    type WatchFileStream = tokio_stream::wrappers::ReceiverStream<Result<WatchFileEvent, Status>>;
+
+    async fn watch_file(
+        &self,
+        request: Request<WatchFileRequest>,
+    ) -> Result<Response<Self::WatchFileStream>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let peer = request.remote_addr();
+        let req = request.into_inner();
+        info!("WatchFile request: path={} from_start={}", req.path, req.from_start);
+
+        let path = PathBuf::from(&req.path);
+        let session_id = Self::session_id(&req.capability).to_string();
+        // Watches are counted per session, or per peer for anonymous callers
+        let client = match (session_id.as_str(), peer) {
+            ("", Some(peer)) => peer.to_string(),
+            (session, _) => session.to_string(),
+        };
+        if let Err(denial) = self.check_operation(Operation::Read, "watch_file", &req.path, &session_id, &request_id) {
+            return Err(denied_status(&denial));
+        }
+
+        let authorized = self
+            .authorize_path(&state, &path, Access::Read)
+            .map_err(|e| Self::internal_status(&req.path, e))?;
+        // A watch streams raw appends, which redaction rules cannot cover
+        let authorized = authorized.and_then(|()| {
+            if state.redactor.covers(&path) {
+                return Err(Denial::new(
+                    "redacted_file",
+                    format!("{} is covered by a redaction rule and cannot be watched", req.path),
+                ));
+            }
+            Ok(())
+        });
+        let slot = match authorized.and_then(|()| self.watches.acquire(&client)) {
+            Ok(slot) => slot,
+            Err(denial) => {
+                if self.audit.record(
+                    AuditEntry::decision("watch_file", &req.path, false, &denial.reason)
+                        .with_session(&session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ Watch denied: {} ({})", req.path, denial.reason);
+                }
+                return Err(denied_status(&denial));
+            }
+        };
+
+        let validator = state.validator.clone();
+        let can_read = Box::new(move |resolved: &Path| validator.can_read(resolved).unwrap_or(false));
+        let watcher = match FileWatcher::open(&path, req.from_start, can_read) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                if let Some(swapped) = verified::swapped(&e) {
+                    let denial = swapped.denial();
+                    self.record_swap("watch_file", &req.path, &session_id, &request_id, &denial);
+                    return Err(denied_status(&denial));
+                }
+                error!("File system error watching {}: {}", req.path, e);
+                return Err(io_status(e));
+            }
+        };
+        self.audit.record(
+            AuditEntry::decision("watch_file", &req.path, true, "Access granted")
+                .with_session(&session_id)
+                .with_request_id(&request_id),
+        );
+
+        let (tx, rx) = mpsc::channel(64);
+        let task = WatchTask {
+            watcher,
+            _slot: slot,
+            validator: state.validator.clone(),
+            audit: self.audit.clone(),
+            session_id,
+            request_id,
+            heartbeat: state.watch_heartbeat,
+            health: self.health.clone(),
+        };
+        tokio::spawn(task.run(tx));
+
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    async fn http_request(
+        &self,
+        request: Request<HttpRequestData>,
+    ) -> Result<Response<HttpResponseData>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("HttpRequest request: method={} url={}", req.method, req.url);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) = self.check_operation(Operation::Http, "http_request", &req.url, session_id, &request_id) {
+            return Ok(Response::new(HttpResponseData {
+                security_status: Some(self.denial_status("http_request", &req.url, denial)),
+                ..Default::default()
+            }));
+        }
+
+        let permit = match state
+            .http_guard
+            .check(&req.url, &req.method, &req.headers, req.body.len())
+        {
+            Ok(permit) => permit,
+            Err(denial) => {
+                if self.audit.record(
+                    AuditEntry::decision("http_request", &req.url, false, &denial.reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ HTTP request denied: {} ({})", req.url, denial.reason);
+                }
+                return Ok(Response::new(HttpResponseData {
+                    security_status: Some(self.denial_status("http_request", &req.url, denial)),
+                    ..Default::default()
+                }));
+            }
+        };
+
+        if let Err(denial) = state.http_guard.vet_destination(&permit).await {
+            if self.audit.record(
+                AuditEntry::decision("http_request", &req.url, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", denial.violation),
+            ) {
+                warn!("❌ HTTP request denied: {} ({})", req.url, denial.reason);
+            }
+            return Ok(Response::new(HttpResponseData {
+                security_status: Some(self.denial_status("http_request", &req.url, denial)),
+                ..Default::default()
+            }));
+        }
+
+        let host = permit.uri.host().unwrap_or_default();
+        let quota = match self.quotas.acquire(host, self.clock.unix_now()) {
+            Ok(quota) => quota,
+            Err(denial) => {
+                if self.audit.record(
+                    AuditEntry::decision("http_request", &req.url, false, &denial.reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ HTTP request denied: {} ({})", req.url, denial.reason);
+                }
+                return Ok(Response::new(HttpResponseData {
+                    security_status: Some(self.denial_status("http_request", &req.url, denial)),
+                    ..Default::default()
+                }));
+            }
+        };
+
+        self.audit.record(
+            AuditEntry::decision("http_request", &req.url, true, "Request allowed")
+                .with_session(session_id)
+                .with_request_id(&request_id)
+                .with_metadata("method", permit.method.as_str()),
+        );
+
+        let request_bytes = header_bytes(&req.headers) + req.body.len() as u64;
+        let sent = state.http_client.send(permit, &req.headers, req.body).await;
+        if let Some(quota) = &quota {
+            let response_bytes = sent
+                .as_ref()
+                .map_or(0, |response| header_bytes(&response.headers) + response.body.len() as u64);
+            quota.add_bytes(request_bytes + response_bytes, self.clock.unix_now());
+        }
+        match sent {
+            Ok(response) => {
+                let reason = if response.truncated {
+                    warn!("HTTP response from {} truncated at {} bytes", req.url, response.body.len());
+                    "Request allowed; response body truncated".to_string()
+                } else {
+                    "Request allowed".to_string()
+                };
+                info!("✅ HTTP request completed: {} ({})", req.url, response.status_code);
+                Ok(Response::new(HttpResponseData {
+                    status_code: response.status_code as i32,
+                    headers: response.headers,
+                    body: response.body,
+                    security_status: Some(self.create_security_status(true, reason, vec![])),
+                    truncated: response.truncated,
+                }))
+            }
+            Err(e) => {
+                let code = http::error_code(&e);
+                error!("HTTP request to {} failed ({}): {:#}", req.url, code, e);
+                let mut status = Status::unavailable(format!("{}: {:#}", code, e));
+                if let Ok(value) = code.parse() {
+                    status.metadata_mut().insert("x-error-code", value);
+                }
+                Err(status)
+            }
+        }
+    }
+
+    async fn dns_lookup(
+        &self,
+        request: Request<DnsLookupRequest>,
+    ) -> Result<Response<DnsLookupResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("DnsLookup request: hostname={}", req.hostname);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) =
+            self.check_operation(Operation::Dns, "dns_lookup", &req.hostname, session_id, &request_id)
+        {
+            return Ok(Response::new(DnsLookupResponse {
+                ip_addresses: vec![],
+                status: Some(self.denial_status("dns_lookup", &req.hostname, denial)),
+            }));
+        }
+
+        let max_length = state.policy.enforcement.max_domain_length;
+        let checked = check_input_length("Hostname", &req.hostname, "max_domain_length", max_length).and_then(|()| {
+            if state.allowed_domains.matches(&req.hostname) {
+                Ok(())
+            } else {
+                Err(Denial::new("domain_not_allowed", format!("Domain not in allowed list: {}", req.hostname)))
+            }
+        });
+        if let Err(denial) = checked {
+            if self.audit.record(
+                AuditEntry::decision("dns_lookup", &req.hostname, false, &denial.reason)
+                    .with_session(session_id)
+                    .with_request_id(&request_id),
+            ) {
+                warn!("❌ DNS lookup denied: {}", denial.reason);
+            }
+            return Ok(Response::new(DnsLookupResponse {
+                ip_addresses: vec![],
+                status: Some(self.denial_status("dns_lookup", &req.hostname, denial)),
+            }));
+        }
+
+        match state.address_guard.resolve(&req.hostname).await {
+            Ok(Ok(ips)) => {
+                self.audit.record(
+                    AuditEntry::decision("dns_lookup", &req.hostname, true, "Lookup allowed")
+                        .with_session(session_id)
+                        .with_request_id(&request_id),
+                );
+                Ok(Response::new(DnsLookupResponse {
+                    ip_addresses: ips.iter().map(|ip| ip.to_string()).collect(),
+                    status: Some(self.create_security_status(
+                        true,
+                        "Lookup allowed".to_string(),
+                        vec![],
+                    )),
+                }))
+            }
+            Ok(Err(denial)) => {
+                if self.audit.record(
+                    AuditEntry::decision("dns_lookup", &req.hostname, false, &denial.reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ DNS lookup denied: {} ({})", req.hostname, denial.reason);
+                }
+                Ok(Response::new(DnsLookupResponse {
+                    ip_addresses: vec![],
+                    status: Some(self.denial_status("dns_lookup", &req.hostname, denial)),
+                }))
+            }
+            Err(e) => {
+                error!("DNS lookup for {} failed: {}", req.hostname, e);
+                Err(Status::not_found(format!("DNS lookup failed: {}", e)))
+            }
+        }
+    }
+
+    async fn check_connect(
+        &self,
+        request: Request<ConnectRequest>,
+    ) -> Result<Response<ConnectResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        let parsed = if req.url.is_empty() {
+            let protocol = match ConnectProtocol::try_from(req.protocol) {
+                Ok(ConnectProtocol::Tcp) => Protocol::Tcp,
+                Ok(ConnectProtocol::Tls) => Protocol::Tls,
+                Ok(ConnectProtocol::Ws) => Protocol::Ws,
+                Ok(ConnectProtocol::Wss) => Protocol::Wss,
+                Err(_) => {
+                    return Err(Status::invalid_argument(format!(
+                        "Unknown protocol: {}",
+                        req.protocol
+                    )))
+                }
+            };
+            let Ok(port) = u16::try_from(req.port) else {
+                return Err(Status::invalid_argument(format!("Invalid port: {}", req.port)));
+            };
+            Ok((protocol, req.host.clone(), port))
+        } else if !req.host.is_empty() || req.port != 0 {
+            return Err(Status::invalid_argument("Set either url, or host, port and protocol"));
+        } else {
+            parse_url(&req.url)
+        };
+        let (target, host) = match &parsed {
+            Ok((protocol, host, port)) => (format!("{}://{}:{}", protocol, host, port), host.clone()),
+            Err(_) => (req.url.clone(), String::new()),
+        };
+        info!("CheckConnect request: {}", target);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) =
+            self.check_operation(Operation::Connect, "check_connect", &target, session_id, &request_id)
+        {
+            return Ok(Response::new(ConnectResponse {
+                status: Some(self.denial_status("check_connect", &target, denial)),
+                ..Default::default()
+            }));
+        }
+
+        let granted = match parsed {
+            Ok((protocol, _, port)) => match state.connect_guard.check(&host, port, protocol).await {
+                Ok(()) => self.leases.grant(&host, target.clone()),
+                Err(denial) => Err(denial),
+            },
+            Err(denial) => Err(denial),
+        };
+
+        match granted {
+            Ok(lease) => {
+                self.audit.record(
+                    AuditEntry::decision("check_connect", &target, true, "Connection allowed")
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("lease_id", lease.id.clone()),
+                );
+                Ok(Response::new(ConnectResponse {
+                    lease_id: lease.id,
+                    expires_at: lease.expires_at,
+                    status: Some(self.create_security_status(
+                        true,
+                        "Connection allowed".to_string(),
+                        vec![],
+                    )),
+                    suggestions: vec![],
+                }))
+            }
+            Err(denial) => {
+                if self.audit.record(
+                    AuditEntry::decision("check_connect", &target, false, &denial.reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ Connection denied: {} ({})", target, denial.reason);
+                }
+                let suggestions = if req.suggest && denial.violation == "domain_not_allowed" {
+                    state.connect_guard.suggest(&host)
+                } else {
+                    Vec::new()
+                };
+                Ok(Response::new(ConnectResponse {
+                    status: Some(self.denial_status("check_connect", &target, denial)),
+                    suggestions,
+                    ..Default::default()
+                }))
+            }
+        }
+    }
+
+    async fn report_connection(
+        &self,
+        request: Request<ConnectionReport>,
+    ) -> Result<Response<ConnectionReportResponse>, Status> {
+        let request_id = request_id(&request);
+        let req = request.into_inner();
+        let (operation, result) = match ConnectionState::try_from(req.state) {
+            Ok(ConnectionState::Opened) => ("connection_opened", self.leases.opened(&req.lease_id)),
+            Ok(ConnectionState::Closed) => ("connection_closed", self.leases.closed(&req.lease_id, req.bytes)),
+            Err(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown connection state: {}",
+                    req.state
+                )))
+            }
+        };
+        debug!("ReportConnection: {} {}", operation, req.lease_id);
+
+        match result {
+            Ok((target, active)) => {
+                self.audit.record(
+                    AuditEntry::decision(operation, &target, true, "Lease updated")
+                        .with_request_id(&request_id)
+                        .with_metadata("lease_id", req.lease_id)
+                        .with_metadata("active_connections", active.to_string()),
+                );
+                Ok(Response::new(ConnectionReportResponse {
+                    active_connections: active as u32,
+                    status: Some(self.create_security_status(
+                        true,
+                        "Lease updated".to_string(),
+                        vec![],
+                    )),
+                }))
+            }
+            Err(denial) => {
+                if self.audit.record(
+                    AuditEntry::decision(operation, &req.lease_id, false, &denial.reason)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ Connection report rejected: {} ({})", req.lease_id, denial.reason);
+                }
+                Ok(Response::new(ConnectionReportResponse {
+                    active_connections: self.leases.active() as u32,
+                    status: Some(self.denial_status(operation, &req.lease_id, denial)),
+                }))
+            }
+        }
+    }
+
+    async fn execute_command(
+        &self,
+        request: Request<ExecuteCommandRequest>,
+    ) -> Result<Response<ExecuteCommandResponse>, Status> {
+        let request_id = request_id(&request);
+        let state = self.state();
+        let req = request.into_inner();
+        info!("ExecuteCommand request: {}", req.command);
+        let session_id = Self::session_id(&req.capability);
+        if let Err(denial) =
+            self.check_operation(Operation::Execute, "execute_command", &req.command, session_id, &request_id)
+        {
+            return Ok(Response::new(ExecuteCommandResponse {
+                status: Some(self.denial_status("execute_command", &req.command, denial)),
+                ..Default::default()
+            }));
+        }
+
+        // Matching may probe the binary's version
+        let commands = state.commands.clone();
+        let command = req.command.clone();
+        let checked = tokio::task::spawn_blocking(move || commands.check(&command))
+            .await
+            .map_err(|e| Status::internal(format!("Command check failed: {}", e)))?;
+        let approved = match &checked {
+            Ok(_) => true,
+            Err(denial) => {
+                self.escalate(&state, "execute_command", &req.command, session_id, &request_id, denial)
+                    .await
+            }
+        };
+        let debit = if approved {
+            self.debit("execute_command", &req.command, session_id, &request_id, Budget::CommandsRun, 1)
+        } else {
+            Ok(None)
+        };
+        let debit = match debit {
+            Ok(debit) => debit,
+            Err(denial) => {
+                return Ok(Response::new(ExecuteCommandResponse {
+                    status: Some(self.denial_status("execute_command", &req.command, denial)),
+                    ..Default::default()
+                }));
+            }
+        };
+        match checked {
+            Ok(allowed) => {
+                let reason = allowed.describe();
+                self.audit.record(with_debit(
+                    AuditEntry::decision("execute_command", &req.command, true, &reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("match", allowed.form.to_string()),
+                    debit,
+                ));
+                Err(Status::unimplemented(format!(
+                    "{}, but execute_command is not yet implemented",
+                    reason
+                )))
+            }
+            Err(_) if approved => {
+                Err(Status::unimplemented(
+                    "Command approved, but execute_command is not yet implemented",
+                ))
+            }
+            Err(denial) => {
+                if self.audit.record(
+                    AuditEntry::decision("execute_command", &req.command, false, &denial.reason)
+                        .with_session(session_id)
+                        .with_request_id(&request_id)
+                        .with_metadata("violation", denial.violation),
+                ) {
+                    warn!("❌ Command denied: {} ({})", req.command, denial.reason);
+                }
+                let suggestions = if req.suggest && ["command_not_allowed", "command_not_found"].contains(&denial.violation) {
+                    // Suggestions are checked like commands, so this may probe too
+                    let command = req.command.clone();
+                    tokio::task::spawn_blocking(move || state.commands.suggest(&command))
+                        .await
+                        .unwrap_or_default()
+                } else {
+                    Vec::new()
+                };
+                Ok(Response::new(ExecuteCommandResponse {
+                    status: Some(self.denial_status("execute_command", &req.command, denial)),
+                    suggestions,
+                    ..Default::default()
+                }))
+            }
+        }
+    }
+
+    async fn request_capability(
+        &self,
+        _request: Request<CapabilityRequest>,
+    ) -> Result<Response<CapabilityResponse>, Status> {
+        Err(Status::unimplemented("request_capability not yet implemented"))
+    }
+
+    async fn revoke_capability(
+        &self,
+        _request: Request<RevokeRequest>,
+    ) -> Result<Response<RevokeResponse>, Status> {
+        Err(Status::unimplemented("revoke_capability not yet implemented"))
+    }
+
+    async fn create_session(
+        &self,
+        request: Request<CreateSessionRequest>,
+    ) -> Result<Response<SessionInfo>, Status> {
+        let request_id = request_id(&request);
+        let req = request.into_inner();
+        let budgets = req.budgets.unwrap_or_default();
+        let limits = Budgets {
+            bytes_read: budgets.bytes_read,
+            files_written: budgets.files_written,
+            commands_run: budgets.commands_run,
+        };
+        let ttl = (req.ttl_secs > 0).then(|| Duration::from_secs(req.ttl_secs));
+        let snapshot = match SnapshotMode::try_from(req.snapshot) {
+            Ok(SnapshotMode::Off) => Snapshot::Off,
+            Ok(SnapshotMode::Flag) => Snapshot::Flag,
+            Ok(SnapshotMode::Strict) => Snapshot::Strict,
+            Err(_) => return Err(Status::invalid_argument(format!("Unknown snapshot mode: {}", req.snapshot))),
+        };
+
+        let session = self
+            .sessions
+            .create(limits, ttl, snapshot)
+            .map_err(|denial| Status::resource_exhausted(format!("{}: {}", denial.violation, denial.reason)))?;
+        info!("Session {} created", session.id);
+        self.audit.record(
+            AuditEntry::note("session_created", "create_session", &session.id, "Session created")
+                .with_session(&session.id)
+                .with_request_id(&request_id)
+                .with_metadata("expires_at", session.expires_at.to_string())
+                .with_metadata("bytes_read", session.limits.bytes_read.to_string())
+                .with_metadata("files_written", session.limits.files_written.to_string())
+                .with_metadata("commands_run", session.limits.commands_run.to_string())
+                .with_metadata("snapshot", format!("{:?}", session.snapshot).to_ascii_lowercase()),
+        );
+        Ok(Response::new(session_info(session, 0)))
+    }
+
+    async fn get_session(
+        &self,
+        request: Request<GetSessionRequest>,
+    ) -> Result<Response<SessionInfo>, Status> {
+        let req = request.into_inner();
+        match self.sessions.get(&req.session_id) {
+            Some(session) => {
+                let snapshot_paths = self.sessions.snapshot_paths(&session.id);
+                Ok(Response::new(session_info(session, snapshot_paths)))
+            }
+            None => Err(Status::not_found(format!("No live session {:?}", req.session_id))),
+        }
+    }
+
+    async fn reload_policy(
+        &self,
+        request: Request<ReloadPolicyRequest>,
+    ) -> Result<Response<ReloadPolicyResponse>, Status> {
+        let request_id = request_id(&request);
+        info!("ReloadPolicy request");
+
+        if !self.is_admin(request.metadata()) {
+            if self.audit.record(
+                AuditEntry::decision("reload_policy", "policy", false, "Missing or invalid admin token")
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", "admin_token_required"),
+            ) {
+                warn!("❌ Policy reload denied: missing or invalid admin token");
+            }
+            return Err(Status::permission_denied("admin_token_required: reload_policy needs a valid admin token"));
+        }
+        let Some((source, format)) = &self.origin else {
+            return Err(Status::failed_precondition("Policy was read from stdin and cannot be reloaded"));
+        };
+
+        // Reloads are serialized, so the last one to start is the one in force
+        let _reloading = self.reload_lock.lock().await;
+        let loaded = Policy::load_normalized(source, *format, self.policy_key.as_ref())
+            .and_then(|(policy, _)| PolicyState::new(&policy, &self.protected, self.clock.unix_now()));
+        match loaded {
+            Ok(new_state) => {
+                new_state.log_self_check_problems();
+                let new_state = Arc::new(new_state);
+                let old_state = self.swap_state(new_state.clone());
+                info!("✅ Policy reloaded from {}", source);
+                self.health.report(Subsystem::PolicyLoader, Condition::Ok, "");
+                let diff = PolicyDiff::between(&old_state.policy, &new_state.policy);
+                let reason = if diff.is_empty() { "Policy reloaded, unchanged" } else { "Policy reloaded" };
+                let mut entry = AuditEntry::note("policy_reload", "reload_policy", &source.to_string(), reason)
+                    .with_request_id(&request_id)
+                    .with_metadata("old_hash", old_state.digest.clone())
+                    .with_metadata("new_hash", new_state.digest.clone());
+                for (key, entries) in [("added", &diff.added), ("removed", &diff.removed)] {
+                    if !entries.is_empty() {
+                        entry = entry.with_metadata(key, PolicyDiff::describe(entries));
+                    }
+                }
+                if !diff.changed.is_empty() {
+                    entry = entry.with_metadata("changed", diff.changed.join(", "));
+                }
+                self.audit.record(entry);
+                Ok(Response::new(ReloadPolicyResponse {
+                    reloaded: true,
+                    error: String::new(),
+                    stats: Some(new_state.stats),
+                    policy: Some(self.policy_info(&new_state)),
+                }))
+            }
+            Err(e) => {
+                let error = format!("{:#}", e);
+                warn!("Policy reload from {} failed, keeping the current policy: {}", source, error);
+                // The last good policy is still enforced, so this alone does not stop serving
+                self.health.report(Subsystem::PolicyLoader, Condition::Degraded, &error);
+                self.audit.record(
+                    AuditEntry::note("policy_reload_failed", "reload_policy", &source.to_string(), &error)
+                        .with_request_id(&request_id),
+                );
+                let state = self.state();
+                Ok(Response::new(ReloadPolicyResponse {
+                    reloaded: false,
+                    error,
+                    stats: Some(state.stats),
+                    policy: Some(self.policy_info(&state)),
+                }))
+            }
+        }
+    }
+
+    async fn read_only_mode(
+        &self,
+        request: Request<ReadOnlyModeRequest>,
+    ) -> Result<Response<ReadOnlyModeResponse>, Status> {
+        let request_id = request_id(&request);
+        info!("ReadOnlyMode request");
+
+        if !self.is_admin(request.metadata()) {
+            if self.audit.record(
+                AuditEntry::decision("read_only_mode", "read_only", false, "Missing or invalid admin token")
+                    .with_request_id(&request_id)
+                    .with_metadata("violation", "admin_token_required"),
+            ) {
+                warn!("❌ Read-only mode change denied: missing or invalid admin token");
+            }
+            return Err(Status::permission_denied("admin_token_required: read_only_mode needs a valid admin token"));
+        }
+        let req = request.into_inner();
+        let changed = self.read_only.swap(req.enabled, Ordering::Relaxed) != req.enabled;
+        if changed {
+            let (resource, message) = if req.enabled {
+                ("enabled", "Read-only mode on; writes, commands and network access are denied")
+            } else {
+                ("disabled", "Read-only mode off")
+            };
+            warn!("{} (reason: {:?})", message, req.reason);
+            let mut entry = AuditEntry::note("read_only_mode", "read_only_mode", resource, message)
+                .with_request_id(&request_id);
+            if !req.reason.is_empty() {
+                entry = entry.with_metadata("reason", req.reason);
+            }
+            self.audit.record(entry);
+        }
+        Ok(Response::new(ReadOnlyModeResponse {
+            enabled: req.enabled,
+            changed,
+        }))
+    }
+
+    async fn add_policy_entry(
+        &self,
+        request: Request<PolicyEntryRequest>,
+    ) -> Result<Response<PolicyEntryResponse>, Status> {
+        self.policy_entry(request, EditAction::Add).await
+    }
+
+    async fn remove_policy_entry(
+        &self,
+        request: Request<PolicyEntryRequest>,
+    ) -> Result<Response<PolicyEntryResponse>, Status> {
+        self.policy_entry(request, EditAction::Remove).await
+    }
+
+    async fn get_status(
+        &self,
+        _request: Request<StatusRequest>,
+    ) -> Result<Response<StatusResponse>, Status> {
+        info!("Status request received");
+        let state = self.state();
+
+        let usage = monitor::ResourceUsage::sample();
+        let proxy = state.http_client.proxy_health().await.map(|health| ProxyStatus {
+            url: health.url,
+            required: state.require_proxy,
+            reachable: health.reachable,
+            error: health.error.unwrap_or_default(),
+        });
+        // Without a reachable proxy every request would be refused
+        let healthy =
+            self.health.is_serving() && (!state.require_proxy || proxy.as_ref().is_some_and(|p| p.reachable));
+
+        Ok(Response::new(StatusResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            healthy,
+            active_policy: Some(self.policy_info(&state)),
+            resources: Some(ResourceUsage {
+                memory_bytes: usage.memory_bytes.unwrap_or(0),
+                cpu_percent: 0.0,
+                active_connections: self.leases.active() as u32,
+                active_watches: self.watches.active() as u32,
+                active_sessions: self.sessions.active() as u32,
+            }),
+            proxy,
+            self_check: Some(to_proto_self_check(&state.self_check)),
+            identity: privileges::current().map(|identity| ProcessIdentity {
+                uid: identity.uid,
+                gid: identity.gid,
+            }),
+            degraded: self.latency.is_degraded(),
+            decision_p99_us: self.latency.p99().as_micros() as u64,
+            quotas: self.quota_usage(),
+            decision_cache: state.validator.cache_stats().map(|stats| DecisionCacheStats {
+                hits: stats.hits,
+                misses: stats.misses,
+                entries: stats.entries,
+                capacity: stats.capacity,
+            }),
+            subsystems: self.health.snapshot().into_iter().map(to_proto_subsystem_health).collect(),
+            rpc_latency: self
+                .rpc_latency
+                .snapshot()
+                .into_iter()
+                .map(|(method, stats)| RpcLatency {
+                    method,
+                    calls: stats.calls,
+                    mean_us: (stats.total.as_micros() / u128::from(stats.calls.max(1))) as u64,
+                    max_us: stats.max.as_micros() as u64,
+                })
+                .collect(),
+            read_only: self.read_only.load(Ordering::Relaxed),
+            disabled_operations: state.operations.disabled().iter().map(|operation| operation.to_string()).collect(),
+            build: Some(build_info()),
+            denials: Some(to_proto_denial_digest(self.audit.denial_digest())),
+        }))
+    }
+
+    async fn get_quotas(
+        &self,
+        _request: Request<GetQuotasRequest>,
+    ) -> Result<Response<GetQuotasResponse>, Status> {
+        Ok(Response::new(GetQuotasResponse {
+            quotas: self.quota_usage(),
+        }))
+    }
+
+    type GetAuditLogsStream = tokio_stream::wrappers::ReceiverStream<Result<AuditLogEntry, Status>>;
+
+    async fn get_audit_logs(
+        &self,
+        request: Request<AuditLogRequest>,
+    ) -> Result<Response<Self::GetAuditLogsStream>, Status> {
+        let req = request.into_inner();
+        info!("GetAuditLogs request: since={} follow={}", req.since_timestamp, req.follow);
+
+        let filter = AuditFilter {
+            since_timestamp: req.since_timestamp,
+            session_id: (!req.session_id.is_empty()).then_some(req.session_id),
+            event_types: req.event_types,
+        };
+
+        // Subscribe before replaying so no entry falls between the two
+        let mut live = req.follow.then(|| self.audit.subscribe());
+        let history = self.audit.query(&filter);
+        let (tx, rx) = mpsc::channel(64);
+
+        tokio::spawn(async move {
+            for entry in history {
+                if tx.send(Ok(to_proto_entry(entry))).await.is_err() {
+                    return;
+                }
+            }
+
+            let Some(live) = live.as_mut() else {
+                return;
+            };
+            loop {
+                match live.recv().await {
+                    Ok(entry) if filter.matches(&entry) => {
+                        // The client went away; dropping the receiver detaches it
+                        if tx.send(Ok(to_proto_entry(entry))).await.is_err() {
+                            debug!("Audit log subscriber disconnected");
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Audit log subscriber lagging, skipped {} entries", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+}
+
+/// Follows one watched file until the client goes away or the stream ends.
+/// Holding the slot keeps the watch counted against the client's limit.
+struct WatchTask {
+    watcher: FileWatcher,
+    _slot: WatchSlot,
+    validator: Arc<PathValidator>,
+    audit: Arc<AuditLogger>,
+    session_id: String,
+    request_id: String,
+    heartbeat: Duration,
+    /// Told of each poll, as [`Subsystem::FileWatches`]
+    health: Arc<HealthRegistry>,
+}
+
+impl WatchTask {
+    async fn run(mut self, tx: mpsc::Sender<Result<WatchFileEvent, Status>>) {
+        let mut poll = tokio::time::interval(WATCH_POLL_INTERVAL);
+        let mut heartbeat = tokio::time::interval(self.heartbeat);
+        heartbeat.reset();
+
+        loop {
+            tokio::select! {
+                _ = tx.closed() => {
+                    debug!("Watch on {} cancelled", self.watcher.path().display());
+                    return;
+                }
+                _ = heartbeat.tick() => {
+                    if tx.send(Ok(watch_event(WatchEventType::Heartbeat))).await.is_err() {
+                        return;
+                    }
+                }
+                _ = poll.tick() => {
+                    let changes = match self.watcher.poll() {
+                        Ok(changes) => {
+                            self.health.report(Subsystem::FileWatches, Condition::Ok, "");
+                            changes
+                        }
+                        Err(e) => {
+                            // The file was replaced by one the watch may not read
+                            if let Some(swapped) = verified::swapped(&e) {
+                                let denial = swapped.denial();
+                                let reason = self.revoke(denial.reason, denial.violation);
+                                let revoked = WatchFileEvent {
+                                    reason,
+                                    ..watch_event(WatchEventType::Revoked)
+                                };
+                                let _ = tx.send(Ok(revoked)).await;
+                                return;
+                            }
+                            error!("File system error watching {}: {}", self.watcher.path().display(), e);
+                            let detail = format!("Watching {} failed: {}", self.watcher.path().display(), e);
+                            self.health.report(Subsystem::FileWatches, Condition::Degraded, detail);
+                            let _ = tx.send(Err(io_status(e))).await;
+                            return;
+                        }
+                    };
+                    for change in changes {
+                        let (event, last) = self.to_event(change);
+                        if tx.send(Ok(event)).await.is_err() || last {
+                            return;
+                        }
+                        heartbeat.reset();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Converts a change into an event, and whether it ends the stream
+    fn to_event(&self, change: Change) -> (WatchFileEvent, bool) {
+        match change {
+            Change::Appended { offset, data } => (
+                WatchFileEvent {
+                    data,
+                    offset,
+                    ..watch_event(WatchEventType::Appended)
+                },
+                false,
+            ),
+            Change::Truncated => (watch_event(WatchEventType::Truncated), false),
+            Change::Deleted => (watch_event(WatchEventType::Deleted), true),
+            Change::Replaced => match self.recheck() {
+                Ok(()) => (watch_event(WatchEventType::Replaced), false),
+                Err(reason) => (
+                    WatchFileEvent {
+                        reason,
+                        ..watch_event(WatchEventType::Revoked)
+                    },
+                    true,
+                ),
+            },
+        }
+    }
+
+    /// A replacement may be a symlink or a file the policy no longer
+    /// allows, so the new target is validated before any of it is sent
+    fn recheck(&self) -> std::result::Result<(), String> {
+        let path = self.watcher.path();
+        let reason = match self.validator.can_access(path, Access::Read) {
+            Ok(true) => return Ok(()),
+            Ok(false) => "Replacement not in allowed read list".to_string(),
+            Err(e) => format!("Replacement could not be validated: {}", e),
+        };
+        Err(self.revoke(reason, "path_not_allowed"))
+    }
+
+    /// Audits the end of the watch, returning the reason sent to the client
+    fn revoke(&self, reason: String, violation: &'static str) -> String {
+        let resource = self.watcher.path().to_string_lossy();
+        if self.audit.record(
+            AuditEntry::decision("watch_file", &resource, false, &reason)
+                .with_session(&self.session_id)
+                .with_request_id(&self.request_id)
+                .with_metadata("violation", violation),
+        ) {
+            warn!("❌ Watch revoked: {} ({})", resource, reason);
+        }
+        reason
+    }
+}
+
+fn to_proto_match(hit: search::SearchMatch) -> SearchMatch {
+    SearchMatch {
+        path: hit.path.display().to_string(),
+        line: hit.line,
+        column: hit.column,
+        snippet: hit.snippet,
+        before: hit.before,
+        after: hit.after,
+    }
+}
+
+/// Set by build.rs
+fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: env!("OPENCLAW_GIT_SHA").to_string(),
+        target: env!("OPENCLAW_TARGET").to_string(),
+        features: env!("OPENCLAW_FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(String::from)
+            .collect(),
+    }
+}
+
+fn watch_event(event_type: WatchEventType) -> WatchFileEvent {
+    WatchFileEvent {
+        r#type: event_type as i32,
+        timestamp: crate::audit::logger::unix_now(),
+        ..Default::default()
+    }
+}
+
+/// The code for a violation string; they are named alike
+fn reason_code(violation: &str) -> ReasonCode {
+    ReasonCode::from_str_name(&format!("REASON_CODE_{}", violation.to_ascii_uppercase()))
+        .unwrap_or(ReasonCode::Unspecified)
+}
+
+/// How a WriteFileStream upload stopped short of replacing the file
+enum UploadFailed {
+    Denied(Denial),
+    Io(std::io::Error),
+    /// The stream broke off, ended unfinished or was malformed
+    Stream(Status),
+}
+
+/// Streaming RPCs have no response to carry a `SecurityStatus`, so denials
+/// become `permission_denied` with the violation in `x-error-code`
+fn denied_status(denial: &Denial) -> Status {
+    let mut status = Status::permission_denied(format!("{}: {}", denial.violation, denial.reason));
+    if let Ok(value) = denial.violation.parse() {
+        status.metadata_mut().insert("x-error-code", value);
+    }
+    status
+}
+
+fn current_state(state: &RwLock<Arc<PolicyState>>) -> Arc<PolicyState> {
+    state.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// The token of an `authorization: Bearer <token>` header, or "" without one
+fn bearer_token(metadata: &tonic::metadata::MetadataMap) -> &str {
+    metadata
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default()
+}
+
+/// Compares without an early exit, so a token cannot be guessed from timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Header names and values as they count against a quota
+fn header_bytes(headers: &std::collections::HashMap<String, String>) -> u64 {
+    headers.iter().map(|(name, value)| (name.len() + value.len()) as u64).sum()
+}
+
+/// Records what a request spent of its session budget
+fn with_debit(entry: AuditEntry, debit: Option<Debit>) -> AuditEntry {
+    match debit {
+        Some(debit) => entry.with_metadata(&debit.budget.to_string(), debit.to_string()),
+        None => entry,
+    }
+}
+
+fn session_budgets(budgets: Budgets) -> SessionBudgets {
+    SessionBudgets {
+        bytes_read: budgets.bytes_read,
+        files_written: budgets.files_written,
+        commands_run: budgets.commands_run,
+    }
+}
+
+fn session_info(session: crate::capabilities::sessions::Session, snapshot_paths: usize) -> SessionInfo {
+    let snapshot = match session.snapshot {
+        Snapshot::Off => SnapshotMode::Off,
+        Snapshot::Flag => SnapshotMode::Flag,
+        Snapshot::Strict => SnapshotMode::Strict,
+    };
+    SessionInfo {
+        session_id: session.id,
+        expires_at: session.expires_at,
+        limits: Some(session_budgets(session.limits)),
+        used: Some(session_budgets(session.used)),
+        snapshot: snapshot as i32,
+        snapshot_paths: snapshot_paths as u64,
+    }
+}
+
+/// Sets its flag when dropped, as a request's future is when the client
+/// goes away
+#[derive(Default)]
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+fn io_status(err: std::io::Error) -> Status {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => Status::not_found(err.to_string()),
+        std::io::ErrorKind::AlreadyExists => Status::already_exists(err.to_string()),
+        std::io::ErrorKind::InvalidInput => Status::invalid_argument(err.to_string()),
+        _ => Status::internal(err.to_string()),
+    }
+}
+
+fn to_proto_subsystem_health(health: crate::daemon::health::SubsystemHealth) -> SubsystemHealth {
+    SubsystemHealth {
+        name: health.subsystem.name().to_string(),
+        condition: match health.condition {
+            Condition::Ok => SubsystemCondition::Ok,
+            Condition::Degraded => SubsystemCondition::Degraded,
+            Condition::Failed => SubsystemCondition::Failed,
+        } as i32,
+        detail: health.detail,
+        since: health.since,
+        transitions: health.transitions,
+        critical: health.subsystem.is_critical(),
+    }
+}
+
+fn to_proto_denial_digest(digest: DigestSnapshot) -> DenialDigest {
+    DenialDigest {
+        operations: digest
+            .operations
+            .into_iter()
+            .map(|denials| OperationDenials {
+                operation: denials.operation,
+                last_1m: denials.last_1m,
+                last_5m: denials.last_5m,
+                last_60m: denials.last_60m,
+            })
+            .collect(),
+        top_targets: digest
+            .top_targets
+            .into_iter()
+            .map(|(target, denials)| DeniedTarget { target, denials })
+            .collect(),
+        last_denied_at: digest.last_denied_at,
+    }
+}
+
+fn to_proto_self_check(report: &SelfCheckReport) -> crate::proto::openclaw::enforce::SelfCheckReport {
+    crate::proto::openclaw::enforce::SelfCheckReport {
+        checked_at: report.checked_at,
+        entries: report
+            .entries
+            .iter()
+            .map(|e| SelfCheckEntry {
+                list: e.access.list_name().to_string(),
+                entry: e.entry.clone(),
+                exists: e.exists,
+                resolved: e
+                    .resolved
+                    .as_ref()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default(),
+                accessible: e.accessible,
+                escapes: e.escapes,
+                is_glob: e.is_glob(),
+                glob_matches: e.glob_matches.unwrap_or(0) as u64,
+                problem: e.problem().unwrap_or_default(),
+            })
+            .collect(),
+    }
+}
+
+fn to_proto_entry(entry: AuditEntry) -> AuditLogEntry {
+    let reason_code = match entry.metadata.get("violation") {
+        Some(violation) => reason_code(violation),
+        None if entry.allowed && entry.event_type == "decision" => ReasonCode::AccessGranted,
+        None => ReasonCode::Unspecified,
+    };
+    AuditLogEntry {
+        timestamp: entry.timestamp,
+        event_type: entry.event_type,
+        session_id: entry.session_id,
+        request_id: entry.request_id,
+        operation: entry.operation,
+        resource: entry.resource,
+        allowed: entry.allowed,
+        reason: entry.reason,
+        metadata: entry.metadata,
+        prev_hash: entry.prev_hash,
+        entry_hash: entry.entry_hash,
+        reason_code: reason_code as i32,
+    }
+}
+
+/// Serves on a socket bound before the runtime started, so privileges
+/// could be dropped in between
+pub async fn serve(
+    listener: std::net::TcpListener,
+    policy: Policy,
+    source: PolicySource,
+    format: Option<PolicyFormat>,
+    policy_key: Option<PolicyKey>,
+    read_only: bool,
+) -> Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    serve_listener(listener, policy, source, format, policy_key, read_only, shutdown_signal()).await
+}
+
+/// Sets the server as a whole, the health service and the enforcement
+/// service to SERVING or NOT_SERVING together
+async fn set_health(reporter: &mut tonic_health::server::HealthReporter, serving: bool) {
+    use tonic::server::NamedService;
+    use tonic_health::ServingStatus;
+    let status = if serving { ServingStatus::Serving } else { ServingStatus::NotServing };
+    let services = [
+        "",
+        <tonic_health::pb::health_server::HealthServer<tonic_health::server::HealthReporter> as NamedService>::NAME,
+        <enforcement_service_server::EnforcementServiceServer<EnforcementServiceImpl> as NamedService>::NAME,
+    ];
+    for service in services {
+        reporter.set_service_status(service, status).await;
+    }
+}
+
+/// SIGTERM, as systemd sends it, or Ctrl-C
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(e) => {
+            warn!("Cannot handle SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// [`serve`] on a listener that is already bound, e.g. to port 0, until
+/// `shutdown` completes and the requests in flight have finished
+pub async fn serve_listener(
+    listener: tokio::net::TcpListener,
+    policy: Policy,
+    source: PolicySource,
+    format: Option<PolicyFormat>,
+    policy_key: Option<PolicyKey>,
+    read_only: bool,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<()> {
+    info!("Starting OpenClaw Enforce gRPC server");
+    let addr = listener.local_addr()?;
+
+    // Health service
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    set_health(&mut health_reporter, true).await;
+
+    // Enforcement service
+    let dedupe_window = policy.audit.dedupe_window_secs;
+    let audit_retention = policy.audit.max_entries > 0 || policy.audit.max_age_secs > 0;
+    let compression = policy.server.compression;
+    let max_message_bytes = policy.server.max_message_bytes;
+    let target_limits = TargetLimits::of(&policy.server);
+    let max_concurrent_requests = policy.server.max_concurrent_requests;
+    let default_deadline = Duration::from_secs(policy.server.default_deadline_secs);
+    let resource_monitor = ResourceMonitor::new(&policy);
+    let persisted = StatePaths::of(&policy) != StatePaths::default();
+    let enforcement_service = EnforcementServiceImpl::new(policy)?
+        .with_origin(source, format)
+        .with_policy_key(policy_key)
+        .with_read_only(read_only);
+    if read_only {
+        warn!("Starting in read-only mode; writes, commands and network access are denied");
+    }
+    enforcement_service.state().log_self_check_problems();
+    enforcement_service.record_policy_load();
+    let rpc_latency = enforcement_service.rpc_latency.clone();
+    let health_watcher = {
+        // Follows the subsystems from here until shutdown
+        let mut reporter = health_reporter.clone();
+        let mut serving = enforcement_service.health.subscribe();
+        tokio::spawn(async move {
+            while serving.changed().await.is_ok() {
+                let serving = *serving.borrow_and_update();
+                set_health(&mut reporter, serving).await;
+            }
+        })
+    };
+    let flush_state = Arc::new(enforcement_service.state_flusher());
+    if persisted {
+        // Saved off the request path, so no decision waits on a write
+        let flush_state = flush_state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STATE_FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let flush_state = flush_state.clone();
+                let _ = tokio::task::spawn_blocking(move || flush_state()).await;
+            }
+        });
+    }
+    if dedupe_window > 0 {
+        let audit = enforcement_service.audit.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(dedupe_window));
+            loop {
+                interval.tick().await;
+                audit.flush_expired();
+            }
+        });
+    }
+    if audit_retention {
+        let audit = enforcement_service.audit.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(AUDIT_RETENTION_INTERVAL);
+            loop {
+                interval.tick().await;
+                let swept = tokio::task::spawn_blocking({
+                    let audit = audit.clone();
+                    move || audit.enforce_retention()
+                });
+                match swept.await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(evicted)) => info!("Evicted {} entries from the audit log file", evicted),
+                    Ok(Err(e)) => warn!("Audit log retention failed: {}", e),
+                    Err(e) => warn!("Audit log retention failed: {}", e),
+                }
+            }
+        });
+    }
+    {
+        // The trash is looked up on every sweep since a reload may move it
+        let (state, clock) = (enforcement_service.state.clone(), enforcement_service.clock.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TRASH_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(trash) = current_state(&state).trash.clone() else {
+                    continue;
+                };
+                match trash.purge_expired(clock.unix_now()) {
+                    Ok(0) => {}
+                    Ok(purged) => info!("Purged {} expired entries from {}", purged, trash.dir().display()),
+                    Err(e) => warn!("Trash sweep of {} failed: {}", trash.dir().display(), e),
+                }
+            }
+        });
+    }
+    {
+        let sweep_grants = enforcement_service.grant_sweeper();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(GRANT_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                sweep_grants();
+            }
+        });
+    }
+    if let Some(interval) = notify::watchdog_interval() {
+        // A wedged policy lock stops the pings, and systemd restarts us
+        let state = enforcement_service.state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                current_state(&state);
+                notify::notify(notify::State::Watchdog);
+            }
+        });
+    }
+    if resource_monitor.is_enabled() {
+        let audit = enforcement_service.audit.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RESOURCE_SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+                for warning in resource_monitor.check(&monitor::ResourceUsage::sample()) {
+                    let message = warning.message();
+                    warn!("⚠️ Resource soft limit: {}", message);
+                    audit.record(
+                        AuditEntry::note("resource_warning", "resource_usage", warning.resource, &message)
+                            .with_metadata("value", warning.value.to_string())
+                            .with_metadata("threshold", warning.threshold.to_string())
+                            .with_metadata("limit", warning.limit.to_string()),
+                    );
+                }
+            }
+        });
+    }
+    
+    info!("Services registered:");
+    info!("  - grpc.health.v1.Health");
+    info!("  - openclaw.enforce.EnforcementService");
+    // Both versions, since older grpcurl and other tools only try v1alpha
+    #[cfg(feature = "reflection")]
+    let (reflection_v1, reflection_v1alpha) = {
+        let builder = || {
+            tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(crate::proto::FILE_DESCRIPTOR_SET)
+                .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        };
+        info!("  - grpc.reflection.v1.ServerReflection");
+        (builder().build_v1()?, builder().build_v1alpha()?)
+    };
+    info!("gRPC server listening on {}", addr);
+
+    // Oversized requests are refused before they are decoded into memory
+    let enforcement_server =
+        enforcement_service_server::EnforcementServiceServer::new(enforcement_service)
+            .max_decoding_message_size(max_message_bytes);
+    // Only clients advertising gzip in grpc-accept-encoding get compressed replies
+    #[cfg(feature = "gzip")]
+    let (enforcement_server, health_service) = if compression == crate::policy::Compression::Gzip {
+        use tonic::codec::CompressionEncoding;
+        info!("gzip compression enabled");
+        (
+            enforcement_server
+                .send_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Gzip),
+            health_service
+                .send_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Gzip),
+        )
+    } else {
+        (enforcement_server, health_service)
+    };
+    #[cfg(not(feature = "gzip"))]
+    let _ = compression;
+
+    let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    // Outermost first: the request ID span covers the others
+    let router = Server::builder()
+        .layer(RequestIdLayer)
+        .layer(TimingLayer::new(rpc_latency, default_deadline))
+        .layer(ConcurrencyLimitLayer::new(max_concurrent_requests))
+        .layer(TargetLimitLayer::new(target_limits))
+        .add_service(health_service)
+        .add_service(enforcement_server);
+    #[cfg(feature = "reflection")]
+    let router = router.add_service(reflection_v1).add_service(reflection_v1alpha);
+    let drain = async move {
+        shutdown.await;
+        info!("Shutdown requested; draining in-flight requests");
+        notify::notify(notify::State::Stopping);
+        health_watcher.abort();
+        set_health(&mut health_reporter, false).await;
+    };
+    notify::notify(notify::State::Ready);
+    router.serve_with_incoming_shutdown(incoming, drain).await?;
+    if let Err(e) = flush_state() {
+        warn!("{:#}", e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::enforcement_service_server::EnforcementService;
+    use super::*;
+    use crate::daemon::clock::MockClock;
+    use tempfile::TempDir;
+
+    fn policy_toml(allowed_read: &str) -> String {
+        format!(
+            r#"
+[filesystem]
+allowed_read = [{:?}]
+allowed_write = []
+denied_patterns = []
+
+[network]
+allowed_domains = []
+blocked_ips = []
+max_connections = 10
+
+[process]
+allowed_commands = []
+max_cpu_percent = 50
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = 100
+max_open_files = 1000
+
+[server]
+admin_token = "s3cret"
+"#,
+            allowed_read
+        )
+    }
+
+    fn reload_request(token: &str) -> Request<ReloadPolicyRequest> {
+        let mut request = Request::new(ReloadPolicyRequest {});
+        if let Ok(value) = format!("Bearer {}", token).parse() {
+            request.metadata_mut().insert("authorization", value);
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_reload_policy_swaps_or_keeps_state() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("policy.toml");
+        std::fs::write(&path, policy_toml("/srv/one")).unwrap();
+        let source = PolicySource::File(path.clone());
+        let service = EnforcementServiceImpl::new(Policy::load(&source, None).unwrap())
+            .unwrap()
+            .with_origin(source, None);
+
+        let err = service.reload_policy(reload_request("wrong")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+        std::fs::write(&path, policy_toml("/srv/two")).unwrap();
+        let response = service.reload_policy(reload_request("s3cret")).await.unwrap().into_inner();
+        assert!(response.reloaded);
+        assert_eq!(response.stats.unwrap().allowed_read, 1);
+        assert!(service.state().validator.can_read(Path::new("/srv/two/a")).unwrap());
+        assert!(!service.state().validator.can_read(Path::new("/srv/one/a")).unwrap());
+
+        // One event, naming the policies by hash and saying what changed
+        let reloads = || {
+            let filter = AuditFilter { event_types: vec!["policy_reload".into()], ..Default::default() };
+            service.audit.query(&filter)
+        };
+        let [reload] = reloads().try_into().unwrap();
+        assert_ne!(reload.metadata["old_hash"], reload.metadata["new_hash"]);
+        assert_eq!(reload.metadata["new_hash"], service.state().digest);
+        assert_eq!(reload.metadata["added"], "filesystem.allowed_read \"/srv/two\"");
+        assert_eq!(reload.metadata["removed"], "filesystem.allowed_read \"/srv/one\"");
+        assert!(!reload.metadata.contains_key("changed"));
+
+        // An invalid policy is reported and the current one stays in force
+        std::fs::write(&path, "[filesystem]\nallowed_read = [").unwrap();
+        let response = service.reload_policy(reload_request("s3cret")).await.unwrap().into_inner();
+        assert!(!response.reloaded);
+        assert!(response.error.contains("Failed to parse policy"));
+        assert!(service.state().validator.can_read(Path::new("/srv/two/a")).unwrap());
+        assert_eq!(reloads().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_subsystem_health() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("policy.toml");
+        // A file where the state dir should be, so no snapshot can be saved
+        let state_dir = dir.path().join("state");
+        std::fs::write(&state_dir, "").unwrap();
+        let toml = format!("{}\n[state]\ndir = {:?}\n", policy_toml("/srv/one"), state_dir.display().to_string());
+        std::fs::write(&path, toml).unwrap();
+        let source = PolicySource::File(path.clone());
+        let service = EnforcementServiceImpl::new(Policy::load(&source, None).unwrap())
+            .unwrap()
+            .with_origin(source, None);
+        let status = || async { service.get_status(Request::new(StatusRequest {})).await.unwrap().into_inner() };
+        let subsystem = |status: &StatusResponse, name: &str| {
+            status.subsystems.iter().find(|health| health.name == name).cloned().unwrap()
+        };
+
+        let initial = status().await;
+        assert!(initial.healthy);
+        assert_eq!(initial.subsystems.len(), 4);
+        assert!(initial.subsystems.iter().all(|health| health.condition == SubsystemCondition::Ok as i32));
+        let build = initial.build.clone().unwrap();
+        assert_eq!(build.version, initial.version);
+        assert!(!build.git_sha.is_empty() && !build.target.is_empty());
+        assert_eq!(build.features.contains(&"gzip".to_string()), cfg!(feature = "gzip"));
+
+        std::fs::write(&path, "[filesystem]\nallowed_read = [").unwrap();
+        service.reload_policy(reload_request("s3cret")).await.unwrap();
+        service.sessions.create(Budgets::default(), None, Snapshot::Off).unwrap();
+        assert!(service.state_flusher()().is_err());
+        let degraded = status().await;
+        // The old policy is still enforced and snapshots are retried
+        assert!(degraded.healthy);
+        let loader = subsystem(&degraded, "policy_loader");
+        assert_eq!((loader.condition, loader.transitions), (SubsystemCondition::Degraded as i32, 1));
+        assert!(loader.detail.contains("Failed to parse policy"));
+        let state = subsystem(&degraded, "state_store");
+        assert_eq!(state.condition, SubsystemCondition::Failed as i32);
+        assert!(!state.critical && state.detail.contains("sessions"));
+
+        service.health.report(Subsystem::AuditSink, Condition::Failed, "disk full");
+        let failed = status().await;
+        assert!(!failed.healthy);
+        assert!(subsystem(&failed, "audit_sink").critical);
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_failed_watches() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let path = root.join("app.log");
+        std::fs::write(&path, "started\n").unwrap();
+        let toml = policy_toml(&root.display().to_string());
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let watch = WatchFileRequest {
+            path: path.display().to_string(),
+            ..Default::default()
+        };
+        let mut events = service.watch_file(Request::new(watch)).await.unwrap().into_inner().into_inner();
+
+        // A directory in its place opens, but cannot be read
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        let ended = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap();
+        assert!(ended.unwrap().is_err());
+        let status = service.get_status(Request::new(StatusRequest {})).await.unwrap().into_inner();
+        let watches = status.subsystems.iter().find(|health| health.name == "file_watches").unwrap();
+        assert_eq!(watches.condition, SubsystemCondition::Degraded as i32);
+        assert!(!watches.critical && watches.detail.contains("app.log"));
+        assert!(status.healthy);
+    }
+
+    fn entry_request(token: &str, list: &str, entry: &str, persist: bool) -> Request<PolicyEntryRequest> {
+        let mut request = Request::new(PolicyEntryRequest {
+            list: list.into(),
+            entry: entry.into(),
+            persist,
+        });
+        if let Ok(value) = format!("Bearer {}", token).parse() {
+            request.metadata_mut().insert("authorization", value);
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn test_policy_entries_are_edited_and_persisted() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("policy.toml");
+        let edits_file = dir.path().join("policy.d/runtime.toml");
+        let toml = policy_toml("/srv/one").replace(
+            "admin_token = \"s3cret\"",
+            &format!(
+                "admin_token = \"s3cret\"\npolicy_edits_file = {:?}\n[server.policy_editors]\nalice = \"edit-token\"",
+                edits_file.display().to_string()
+            ),
+        );
+        std::fs::write(&path, toml).unwrap();
+        let source = PolicySource::File(path);
+        let service = EnforcementServiceImpl::new(Policy::load(&source, None).unwrap())
+            .unwrap()
+            .with_origin(source.clone(), None);
+        let read = "filesystem.allowed_read";
+
+        // The admin token does not authorize edits
+        let err = service.add_policy_entry(entry_request("s3cret", read, "/srv/two", false)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+        let response = service
+            .add_policy_entry(entry_request("edit-token", read, "/srv/two", true))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.entry, "/srv/two");
+        assert_eq!(response.stats.unwrap().allowed_read, 2);
+        assert!(service.state().validator.can_read(Path::new("/srv/two/a")).unwrap());
+
+        let service_add = |entry: &'static str| service.add_policy_entry(entry_request("edit-token", read, entry, false));
+        assert_eq!(service_add("/srv/two").await.unwrap_err().code(), tonic::Code::AlreadyExists);
+        assert_eq!(service_add("").await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let err = service
+            .remove_policy_entry(entry_request("edit-token", read, "/srv/three", false))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let err = service
+            .add_policy_entry(entry_request("edit-token", "filesystem.denied_patterns", "*", false))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        service
+            .remove_policy_entry(entry_request("edit-token", read, "/srv/one", true))
+            .await
+            .unwrap();
+        assert!(!service.state().validator.can_read(Path::new("/srv/one/a")).unwrap());
+
+        // The persisted edits apply when the policy loads again
+        let reloaded = Policy::load(&source, None).unwrap();
+        assert_eq!(reloaded.filesystem.allowed_read, vec!["/srv/two"]);
+
+        let edits: Vec<AuditEntry> = service
+            .audit
+            .query(&AuditFilter::default())
+            .into_iter()
+            .filter(|entry| entry.event_type == "policy_edit")
+            .collect();
+        assert_eq!(edits.len(), 2);
+        assert_eq!(edits[0].metadata.get("editor").map(String::as_str), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_reload_disabled_without_token_or_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("policy.toml");
+        std::fs::write(&path, policy_toml("/srv").replace("admin_token = \"s3cret\"", "")).unwrap();
+        let source = PolicySource::File(path);
+        let service = EnforcementServiceImpl::new(Policy::load(&source, None).unwrap())
+            .unwrap()
+            .with_origin(source.clone(), None);
+        let err = service.reload_policy(reload_request("")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+        let from_stdin = EnforcementServiceImpl::new(
+            Policy::load(&PolicySource::Inline(policy_toml("/srv")), None).unwrap(),
+        )
+        .unwrap()
+        .with_origin(PolicySource::Stdin, None);
+        let err = from_stdin.reload_policy(reload_request("s3cret")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_write_modes() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let toml = policy_toml("/srv").replace(
+            "allowed_write = []",
+            &format!("allowed_write = [{:?}]", root.display().to_string()),
+        );
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let path = root.join("agent.log");
+        let write = |data: &str, mode: WriteFileMode| {
+            service.write_file(Request::new(WriteFileRequest {
+                path: path.display().to_string(),
+                data: data.into(),
+                mode: mode as i32,
+                ..Default::default()
+            }))
+        };
+        let status = |response: Response<WriteFileResponse>| response.into_inner().status.unwrap();
+
+        assert!(status(write("one\n", WriteFileMode::CreateNew).await.unwrap()).allowed);
+        assert!(status(write("two\n", WriteFileMode::Append).await.unwrap()).allowed);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+
+        let denied = status(write("clobbered", WriteFileMode::CreateNew).await.unwrap());
+        assert!(!denied.allowed);
+        assert_eq!(denied.violations, vec!["file_exists"]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+
+        assert!(status(write("fresh", WriteFileMode::Overwrite).await.unwrap()).allowed);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fresh");
+    }
+
+    #[tokio::test]
+    async fn test_temp_file_is_writable_by_its_session() {
+        let dir = TempDir::new().unwrap();
+        let toml = policy_toml("/srv").replace(
+            "denied_patterns = []",
+            &format!("denied_patterns = []\nscratch_dir = {:?}", dir.path().display().to_string()),
+        );
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let capability = |session: &str| {
+            Some(Capability {
+                session_id: session.to_string(),
+                ..Default::default()
+            })
+        };
+        let created = service
+            .create_temp_file(Request::new(CreateTempFileRequest {
+                prefix: "notes-".to_string(),
+                suffix: ".md".to_string(),
+                capability: capability("alice"),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(created.status.unwrap().allowed);
+        assert!(created.handle_id.starts_with("tmp-"));
+
+        let write = |session: &str| {
+            service.write_file(Request::new(WriteFileRequest {
+                path: created.path.clone(),
+                data: "draft".into(),
+                capability: capability(session),
+                ..Default::default()
+            }))
+        };
+        assert!(write("alice").await.unwrap().into_inner().status.unwrap().allowed);
+        assert_eq!(std::fs::read_to_string(&created.path).unwrap(), "draft");
+        assert!(!write("bob").await.unwrap().into_inner().status.unwrap().allowed);
+
+        let escape = service
+            .create_temp_file(Request::new(CreateTempFileRequest {
+                prefix: "../../".to_string(),
+                capability: capability("alice"),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(escape.status.unwrap().violations, vec!["malformed_path"]);
+
+        let cleanup = |session: &str| {
+            service.cleanup_temp_files(Request::new(CleanupTempFilesRequest {
+                capability: capability(session),
+            }))
+        };
+        assert_eq!(cleanup("alice").await.unwrap().into_inner().removed, 1);
+        assert!(!Path::new(&created.path).exists());
+        assert!(!write("alice").await.unwrap().into_inner().status.unwrap().allowed);
+
+        let unconfigured = EnforcementServiceImpl::new(
+            Policy::load(&PolicySource::Inline(policy_toml("/srv")), None).unwrap(),
+        )
+        .unwrap();
+        let err = unconfigured
+            .cleanup_temp_files(Request::new(CleanupTempFilesRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+    }
+
+    fn edit_service(root: &Path) -> Arc<EnforcementServiceImpl> {
+        let toml = policy_toml(&root.display().to_string()).replace(
+            "allowed_write = []",
+            &format!("allowed_write = [{:?}]", root.display().to_string()),
+        );
+        Arc::new(EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap())
+    }
+
+    fn replacement(old_text: &str, new_text: &str) -> TextReplacement {
+        TextReplacement {
+            old_text: old_text.to_string(),
+            new_text: new_text.to_string(),
+            replace_all: false,
+        }
+    }
+
+    // Edits only interleave with more than one worker
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_edits_are_not_lost() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let path = root.join("config.toml");
+        std::fs::write(&path, "[servers]\n").unwrap();
+        let service = edit_service(&root);
+
+        let edits = (0..16).map(|i| {
+            let service = service.clone();
+            let path = path.display().to_string();
+            tokio::spawn(async move {
+                let request = EditFileRequest {
+                    path,
+                    replacements: vec![replacement("[servers]\n", &format!("[servers]\nhost{} = true\n", i))],
+                    ..Default::default()
+                };
+                service.edit_file(Request::new(request)).await.unwrap().into_inner()
+            })
+        });
+        for edit in edits.collect::<Vec<_>>() {
+            assert!(edit.await.unwrap().status.unwrap().allowed);
+        }
+        let content = std::fs::read_to_string(&path).unwrap();
+        for i in 0..16 {
+            assert!(content.contains(&format!("host{} = true\n", i)), "{}", content);
+        }
+        assert_eq!(service.edit_locks.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_stale_version_is_a_conflict() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let path = root.join("config.toml");
+        std::fs::write(&path, "v1").unwrap();
+        let service = edit_service(&root);
+
+        let read = service
+            .read_file(Request::new(ReadFileRequest {
+                path: path.display().to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let replace = |data: &str| {
+            service.edit_file(Request::new(EditFileRequest {
+                path: path.display().to_string(),
+                data: data.into(),
+                expected_version: read.version.clone(),
+                ..Default::default()
+            }))
+        };
+
+        // Both callers start from the same version; only the first wins
+        let first = replace("v2 from a").await.unwrap().into_inner();
+        assert!(first.status.unwrap().allowed);
+        let second = replace("v2 from b").await.unwrap().into_inner();
+        let status = second.status.unwrap();
+        assert!(!status.allowed);
+        assert_eq!(status.violations, vec!["conflict"]);
+        assert_eq!(second.version, first.version);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "v2 from a");
+
+        let stale_mtime = service
+            .edit_file(Request::new(EditFileRequest {
+                path: path.display().to_string(),
+                data: "v3".into(),
+                expected_mtime_ns: first.mtime_ns - 1,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stale_mtime.status.unwrap().violations, vec!["conflict"]);
+
+        // A whole-file replacement must say what it replaces
+        let ungated = service
+            .edit_file(Request::new(EditFileRequest {
+                path: path.display().to_string(),
+                data: "v3".into(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(ungated.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_failed_edits_release_the_lock() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let path = root.join("config.toml");
+        std::fs::write(&path, "port = 80\n").unwrap();
+        let service = edit_service(&root);
+        let edit = |path: &Path, old_text: &str| {
+            service.edit_file(Request::new(EditFileRequest {
+                path: path.display().to_string(),
+                replacements: vec![replacement(old_text, "port = 8080")],
+                ..Default::default()
+            }))
+        };
+
+        let status = edit(&path, "port = 443").await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.violations, vec!["edit_not_applied"]);
+        let err = edit(&root.join("missing.toml"), "port = 80").await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        assert_eq!(service.edit_locks.active(), 0);
+
+        // Nothing was left holding the path
+        let edited = tokio::time::timeout(Duration::from_secs(5), edit(&path, "port = 80"))
+            .await
+            .unwrap()
+            .unwrap()
+            .into_inner();
+        assert!(edited.status.unwrap().allowed);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "port = 8080\n");
+    }
+
+    struct MockApprover {
+        response: ApprovalResponse,
+        delay: Duration,
+        calls: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[tonic::async_trait]
+    impl approval_service_server::ApprovalService for MockApprover {
+        async fn request_approval(
+            &self,
+            _request: Request<ApprovalRequest>,
+        ) -> Result<Response<ApprovalResponse>, Status> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            Ok(Response::new(self.response.clone()))
+        }
+    }
+
+    /// Serves `approver` on a free port and returns its address
+    async fn spawn_approver(approver: MockApprover) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(approval_service_server::ApprovalServiceServer::new(approver))
+                .serve_with_incoming(incoming),
+        );
+        format!("http://{}", addr)
+    }
+
+    fn escalating_service(address: &str, timeout_secs: u64) -> EnforcementServiceImpl {
+        let toml = format!(
+            "{}\n[escalation]\nmode = \"callback\"\naddress = {:?}\noperations = [\"write_file\"]\ntimeout_secs = {}\nmax_grant_secs = 600\n",
+            policy_toml("/srv"),
+            address,
+            timeout_secs
+        );
+        EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap()
+    }
+
+    fn session_write(path: &Path, session_id: &str) -> Request<WriteFileRequest> {
+        Request::new(WriteFileRequest {
+            path: path.display().to_string(),
+            data: b"approved".to_vec(),
+            capability: Some(Capability {
+                session_id: session_id.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_allow_session_approval_grants_repeat_requests() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let address = spawn_approver(MockApprover {
+            response: ApprovalResponse {
+                decision: ApprovalDecision::AllowSession as i32,
+                ttl_secs: 86_400,
+                approver: "alice".into(),
+            },
+            delay: Duration::ZERO,
+            calls: calls.clone(),
+        })
+        .await;
+        let service = escalating_service(&address, 5);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.txt");
+
+        for _ in 0..2 {
+            let response = service.write_file(session_write(&path, "session-a")).await.unwrap();
+            assert!(response.into_inner().status.unwrap().allowed);
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "approved");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let approvals: Vec<_> = service
+            .audit
+            .query(&AuditFilter::default())
+            .into_iter()
+            .filter_map(|e| e.metadata.get("approval").cloned().map(|a| (a, e.metadata)))
+            .collect();
+        assert_eq!(approvals[0].0, "allow_session");
+        assert_eq!(approvals[0].1["approver"], "alice");
+        // Capped by max_grant_secs
+        assert_eq!(approvals[0].1["grant_secs"], "600");
+        assert_eq!(approvals[1].0, "session_grant");
+        let events = |event_type: &str| {
+            service.audit.query(&AuditFilter { event_types: vec![event_type.into()], ..Default::default() })
+        };
+        let [created] = events("grant_created").try_into().unwrap();
+        assert_eq!((created.session_id.as_str(), created.operation.as_str()), ("session-a", "write_file"));
+        assert_eq!(created.metadata["grant_secs"], "600");
+        // Until it expires, a sweep leaves the grant alone
+        service.grant_sweeper()();
+        assert!(events("grant_expired").is_empty());
+
+        // The grant belongs to the session that was approved
+        let other = dir.path().join("other.txt");
+        service.write_file(session_write(&other, "session-a")).await.unwrap();
+        service.write_file(session_write(&path, "session-b")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_escalation_denies_without_an_answer() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("out.txt");
+        let slow = spawn_approver(MockApprover {
+            response: ApprovalResponse {
+                decision: ApprovalDecision::AllowOnce as i32,
+                ttl_secs: 0,
+                approver: "bob".into(),
+            },
+            delay: Duration::from_secs(10),
+            calls: Default::default(),
+        })
+        .await;
+
+        for service in [escalating_service(&slow, 1), escalating_service("http://127.0.0.1:1", 1)] {
+            let status = service.write_file(session_write(&path, "session-a")).await.unwrap();
+            let status = status.into_inner().status.unwrap();
+            assert!(!status.allowed);
+            assert_eq!(status.violations, vec!["path_not_allowed"]);
+            let entries = service.audit.query(&AuditFilter::default());
+            assert!(entries.iter().any(|e| e.metadata.get("approval").map(String::as_str) == Some("no_answer")));
+        }
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_read_file_follows_a_symlink_to_an_allowed_file() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("conf")).unwrap();
+        std::fs::write(root.join("conf/real.toml"), "real").unwrap();
+        std::os::unix::fs::symlink(root.join("conf/real.toml"), root.join("config.toml")).unwrap();
+        let toml = policy_toml(&root.display().to_string());
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let read = ReadFileRequest {
+            path: root.join("config.toml").display().to_string(),
+            ..Default::default()
+        };
+        let response = service.read_file(Request::new(read)).await.unwrap().into_inner();
+        assert!(response.status.unwrap().allowed);
+        assert_eq!(response.data, b"real");
+    }
+
+    #[tokio::test]
+    async fn test_read_file_redacts_covered_files() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("app.env"), "PORT=80\nAPI_KEY=hunter2\n").unwrap();
+        std::fs::write(root.join("app.bin.env"), b"API_KEY=\0\x01").unwrap();
+        let toml = policy_toml(&root.display().to_string())
+            + "\n[[filesystem.redaction]]\npattern = \"*.env\"\nrules = [{ regex = '(?m)^(API_KEY)=.*$', replacement = '$1=***' }]\n";
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let read = |name: &str| {
+            service.read_file(Request::new(ReadFileRequest {
+                path: root.join(name).display().to_string(),
+                ..Default::default()
+            }))
+        };
+
+        let response = read("app.env").await.unwrap().into_inner();
+        assert_eq!(response.data, b"PORT=80\nAPI_KEY=***\n");
+        assert!(response.redacted);
+        assert_eq!(response.redactions, 1);
+        let entries = service.audit.query(&AuditFilter::default());
+        assert_eq!(entries[0].metadata["redactions"], "1");
+        assert!(!format!("{:?}", entries).contains("hunter2"));
+
+        let binary = read("app.bin.env").await.unwrap().into_inner();
+        assert!(binary.data.is_empty());
+        assert_eq!(binary.status.unwrap().violations, vec!["binary_not_redactable"]);
+
+        // Parts are taken from the redacted content
+        let part = |offset, length| {
+            service.read_file(Request::new(ReadFileRequest {
+                path: root.join("app.env").display().to_string(),
+                offset,
+                length,
+                ..Default::default()
+            }))
+        };
+        let response = part(8, 9).await.unwrap().into_inner();
+        assert_eq!((response.data.as_slice(), response.eof), (&b"API_KEY=*"[..], false));
+        assert!(response.redacted && response.version.is_empty());
+        let response = part(17, 0).await.unwrap().into_inner();
+        assert_eq!((response.data.as_slice(), response.eof), (&b"**\n"[..], true));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_list_directory_flat_recursive_and_capped() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/nested/lib.rs"), "").unwrap();
+        std::fs::write(root.join("server.key"), "key").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("src/nested/loop")).unwrap();
+        let toml =
+            policy_toml(&root.display().to_string()).replace("denied_patterns = []", "denied_patterns = [\"*.key\"]");
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let list = |path: PathBuf, recursive: bool, max_entries: u32| {
+            service.list_directory(Request::new(ListDirectoryRequest {
+                path: path.display().to_string(),
+                capability: None,
+                recursive,
+                max_entries,
+            }))
+        };
+        let relative = |response: &ListDirectoryResponse| -> Vec<String> {
+            let paths = response.entries.iter().map(|e| Path::new(&e.path).strip_prefix(&root).unwrap());
+            paths.map(|path| path.display().to_string()).collect()
+        };
+
+        let flat = list(root.clone(), false, 0).await.unwrap().into_inner();
+        assert!(flat.status.as_ref().unwrap().allowed);
+        assert_eq!(relative(&flat), ["src"]);
+        assert!(flat.entries[0].is_directory && !flat.truncated);
+
+        // The loop back to the root is listed, but not entered again
+        let tree = list(root.clone(), true, 0).await.unwrap().into_inner();
+        assert_eq!(relative(&tree), ["src", "src/main.rs", "src/nested", "src/nested/lib.rs", "src/nested/loop"]);
+        assert_eq!(tree.entries[1].size, 12);
+        let capped = list(root.clone(), true, 2).await.unwrap().into_inner();
+        assert_eq!((capped.entries.len(), capped.truncated), (2, true));
+
+        let outside = TempDir::new().unwrap();
+        let denied = list(outside.path().to_path_buf(), true, 0).await.unwrap().into_inner();
+        assert_eq!(denied.status.unwrap().violations, ["path_not_allowed"]);
+        assert!(denied.entries.is_empty());
+        let status = list(root.join("src/main.rs"), false, 0).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_stat_is_separate_from_read() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let outside = TempDir::new().unwrap();
+        std::fs::create_dir_all(root.join("public")).unwrap();
+        std::fs::create_dir_all(root.join("private")).unwrap();
+        std::fs::write(root.join("public/readme.md"), "hello").unwrap();
+        std::fs::write(root.join("private/notes.txt"), "not for the agent").unwrap();
+        std::fs::write(root.join("private/server.key"), "key").unwrap();
+        std::fs::write(outside.path().join("elsewhere.txt"), "x").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(outside.path(), root.join("private/out")).unwrap();
+        let toml = policy_toml(&root.join("public").display().to_string())
+            .replace("allowed_write = []", &format!("allowed_write = []\nallowed_stat = [{:?}]", root.display().to_string()))
+            .replace("denied_patterns = []", "denied_patterns = [\"*.key\"]");
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let stat = |path: PathBuf| {
+            service.stat_file(Request::new(StatFileRequest {
+                path: path.display().to_string(),
+                capability: None,
+            }))
+        };
+        let read = |path: PathBuf| {
+            service.read_file(Request::new(ReadFileRequest {
+                path: path.display().to_string(),
+                ..Default::default()
+            }))
+        };
+
+        // Metadata only: the stat succeeds where the read is denied
+        let notes = stat(root.join("private/notes.txt")).await.unwrap().into_inner();
+        assert!(notes.status.as_ref().unwrap().allowed);
+        assert_eq!(notes.size, 17);
+        assert_eq!(notes.kind(), FileKind::Regular);
+        assert!(notes.mtime_ns > 0);
+        let denied = read(root.join("private/notes.txt")).await.unwrap().into_inner();
+        assert!(denied.data.is_empty());
+        assert_eq!(denied.status.unwrap().violations, vec!["path_not_allowed"]);
+
+        // What may be read may be stated
+        let readme = stat(root.join("public/readme.md")).await.unwrap().into_inner();
+        assert_eq!((readme.status.unwrap().allowed, readme.size), (true, 5));
+        let private = stat(root.join("private")).await.unwrap().into_inner();
+        assert_eq!(private.kind(), FileKind::Directory);
+
+        for path in [root.join("private/server.key"), outside.path().join("elsewhere.txt")] {
+            let response = stat(path.clone()).await.unwrap().into_inner();
+            assert_eq!(response.status.unwrap().violations, vec!["path_not_allowed"], "{:?}", path);
+            assert_eq!(response.size, 0);
+        }
+        #[cfg(unix)]
+        {
+            // The link itself is under allowed_stat; what it leads to is not
+            let link = stat(root.join("private/out")).await.unwrap().into_inner();
+            assert_eq!(link.kind(), FileKind::Symlink);
+            let through = stat(root.join("private/out/elsewhere.txt")).await.unwrap().into_inner();
+            assert!(!through.status.unwrap().allowed);
+        }
+        let missing = stat(root.join("private/missing.txt")).await.unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let entries = service.audit.query(&AuditFilter::default());
+        assert!(entries.iter().any(|e| e.operation == "stat_file" && !e.allowed));
+    }
+
+    #[tokio::test]
+    async fn test_require_exists_denies_missing_paths() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let missing = root.join("missing.txt").display().to_string();
+        let service = |require_exists: bool| {
+            let toml = policy_toml(&root.display().to_string())
+                .replace("allowed_write = []", &format!("allowed_write = []\nrequire_exists = {}", require_exists));
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap()
+        };
+        let read = |path: &str| {
+            Request::new(ReadFileRequest {
+                path: path.to_string(),
+                ..Default::default()
+            })
+        };
+
+        // Allowed, and then the read itself fails
+        let err = service(false).read_file(read(&missing)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+
+        let strict = service(true);
+        let status = strict.read_file(read(&missing)).await.unwrap().into_inner().status.unwrap();
+        assert!(!status.allowed);
+        assert_eq!(status.violations, vec!["path_not_found"]);
+        assert_eq!(status.reason_code(), ReasonCode::PathNotFound);
+        // Outside the policy, a missing path is denied like any other
+        let status = strict.read_file(read("/nonexistent-openclaw/a.txt")).await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.violations, vec!["path_not_allowed"]);
+    }
+
+    #[tokio::test]
+    async fn test_denied_write_extensions() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root_str = root.display().to_string();
+        let toml = policy_toml(&root_str).replace(
+            "allowed_write = []",
+            &format!("allowed_write = [{:?}]\ndenied_write_extensions = [\".sh\", \"exe\"]", root_str),
+        );
+        let service = EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let write = |name: &str| {
+            Request::new(WriteFileRequest {
+                path: root.join(name).display().to_string(),
+                data: "echo hi\n".into(),
+                ..Default::default()
+            })
+        };
+
+        let status = service.write_file(write("run.sh")).await.unwrap().into_inner().status.unwrap();
+        assert!(!status.allowed);
+        assert_eq!(status.violations, vec!["write_extension_denied"]);
+        assert_eq!(status.reason_code(), ReasonCode::WriteExtensionDenied);
+        assert!(!root.join("run.sh").exists());
+
+        let status = service.write_file(write("notes.txt")).await.unwrap().into_inner().status.unwrap();
+        assert!(status.allowed, "{:?}", status);
+        assert!(root.join("notes.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_read_only_mode_denies_side_effects() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root_str = root.display().to_string();
+        std::fs::write(root.join("notes.txt"), "kept").unwrap();
+        let toml = policy_toml(&root_str)
+            .replace("allowed_write = []", &format!("allowed_write = [{:?}]", root_str))
+            .replace("allowed_domains = []", "allowed_domains = [\"example.com\"]")
+            .replace("allowed_commands = []", "allowed_commands = [\"ls\"]");
+        let service = EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap())
+            .unwrap()
+            .with_read_only(true);
+        let path = root.join("notes.txt").display().to_string();
+        let write = || {
+            service.write_file(Request::new(WriteFileRequest {
+                path: path.clone(),
+                data: "changed".into(),
+                ..Default::default()
+            }))
+        };
+        let toggle = |token: &str, enabled: bool| {
+            let mut request = Request::new(ReadOnlyModeRequest {
+                enabled,
+                reason: "incident 42".to_string(),
+            });
+            request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+            service.read_only_mode(request)
+        };
+
+        let status = write().await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.violations, vec!["read_only_mode"]);
+        assert_eq!(status.reason_code(), ReasonCode::ReadOnlyMode);
+        let http = HttpRequestData {
+            url: "https://example.com/".to_string(),
+            method: "GET".to_string(),
+            ..Default::default()
+        };
+        let response = service.http_request(Request::new(http)).await.unwrap().into_inner();
+        assert_eq!(response.security_status.unwrap().violations, vec!["read_only_mode"]);
+        let command = ExecuteCommandRequest {
+            command: "ls".to_string(),
+            ..Default::default()
+        };
+        let response = service.execute_command(Request::new(command)).await.unwrap().into_inner();
+        assert_eq!(response.status.unwrap().violations, vec!["read_only_mode"]);
+        // Reads go on
+        let read = ReadFileRequest {
+            path: path.clone(),
+            ..Default::default()
+        };
+        assert_eq!(service.read_file(Request::new(read)).await.unwrap().into_inner().data, b"kept");
+        let status = service.get_status(Request::new(StatusRequest {})).await.unwrap().into_inner();
+        assert!(status.read_only);
+
+        let err = toggle("wrong", false).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        let response = toggle("s3cret", false).await.unwrap().into_inner();
+        assert!(!response.enabled && response.changed);
+        assert!(write().await.unwrap().into_inner().status.unwrap().allowed);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "changed");
+        assert!(!toggle("s3cret", false).await.unwrap().into_inner().changed);
+
+        let entries = service.audit.query(&AuditFilter::default());
+        let change = entries.iter().find(|e| e.event_type == "read_only_mode").unwrap();
+        assert_eq!(change.resource, "disabled");
+        assert_eq!(change.metadata.get("reason").map(String::as_str), Some("incident 42"));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_operations_are_denied_and_advertised() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root_str = root.display().to_string();
+        std::fs::write(root.join("notes.txt"), "kept").unwrap();
+        let toml = format!(
+            "{}\n[operations]\nwrite = false\nexecute = false\n",
+            policy_toml(&root_str)
+                .replace("allowed_write = []", &format!("allowed_write = [{:?}]", root_str))
+                .replace("allowed_commands = []", "allowed_commands = [\"ls\"]")
+        );
+        let service = EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let path = root.join("notes.txt").display().to_string();
+
+        let write = WriteFileRequest {
+            path: path.clone(),
+            data: "changed".into(),
+            ..Default::default()
+        };
+        let status = service.write_file(Request::new(write)).await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.violations, vec!["operation_disabled"]);
+        assert_eq!(status.reason_code(), ReasonCode::OperationDisabled);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "kept");
+        let command = ExecuteCommandRequest {
+            command: "ls".to_string(),
+            ..Default::default()
+        };
+        let response = service.execute_command(Request::new(command)).await.unwrap().into_inner();
+        assert_eq!(response.status.unwrap().violations, vec!["operation_disabled"]);
+        // Deletes are switched separately from writes
+        let delete = DeleteFileRequest {
+            path: path.clone(),
+            ..Default::default()
+        };
+        assert!(service.delete_file(Request::new(delete)).await.unwrap().into_inner().status.unwrap().allowed);
+
+        let status = service.get_status(Request::new(StatusRequest {})).await.unwrap().into_inner();
+        assert_eq!(status.disabled_operations, vec!["write", "execute"]);
+    }
+
+    #[tokio::test]
+    async fn test_files_read_with_the_policy_cannot_be_changed() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root_str = root.display().to_string();
+        let (edits, blocked) = (root.join("edits.toml"), root.join("blocked.txt"));
+        std::fs::write(&blocked, "10.0.0.0/8\n").unwrap();
+        let toml = policy_toml(&root_str)
+            .replace("allowed_write = []", &format!("allowed_write = [{:?}]", root_str))
+            .replace("blocked_ips = []", &format!("blocked_ips = []\nblocked_ip_lists = [{:?}]", blocked))
+            .replace("[server]", &format!("[server]\npolicy_edits_file = {:?}", edits));
+        let policy = Policy::load(&PolicySource::Inline(toml), None).unwrap();
+        let service = EnforcementServiceImpl::new(policy).unwrap();
+
+        for path in [&edits, &blocked] {
+            let write = WriteFileRequest {
+                path: path.display().to_string(),
+                data: "".into(),
+                ..Default::default()
+            };
+            let status = service.write_file(Request::new(write)).await.unwrap().into_inner().status.unwrap();
+            assert_eq!(status.violations, vec!["self_protection"], "{}", path.display());
+        }
+        assert!(!edits.exists());
+        assert_eq!(std::fs::read_to_string(&blocked).unwrap(), "10.0.0.0/8\n");
+
+        // Also refused once open, for a path swapped after the check
+        let writer = &service.state().writer;
+        assert!(writer.write(&blocked, b"", WriteOptions::default()).is_err());
+        assert_eq!(std::fs::read_to_string(&blocked).unwrap(), "10.0.0.0/8\n");
+    }
+
+    #[tokio::test]
+    async fn test_protected_files_follow_a_reload() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root_str = root.display().to_string();
+        let file = root.join("policy.toml");
+        let toml = |edits: &str, audit: &str| {
+            policy_toml(&root_str)
+                .replace("allowed_write = []", &format!("allowed_write = [{:?}]", root_str))
+                .replace("[server]", &format!("[audit]\nfile = {:?}\n[server]", root.join(audit)))
+                .replace("[server]", &format!("[server]\npolicy_edits_file = {:?}", root.join(edits)))
+        };
+        std::fs::write(&file, toml("edits.toml", "audit.jsonl")).unwrap();
+        let source = PolicySource::File(file.clone());
+        let service = EnforcementServiceImpl::new(Policy::load(&source, None).unwrap())
+            .unwrap()
+            .with_origin(source, None);
+        let violations = |name: &str| {
+            let write = WriteFileRequest {
+                path: root.join(name).display().to_string(),
+                data: "".into(),
+                ..Default::default()
+            };
+            let service = &service;
+            async move {
+                let response = service.write_file(Request::new(write)).await.unwrap().into_inner();
+                response.status.unwrap().violations
+            }
+        };
+        assert_eq!(violations("edits.toml").await, vec!["self_protection"]);
+        assert!(violations("moved.toml").await.is_empty());
+
+        std::fs::write(&file, toml("moved.toml", "moved.jsonl")).unwrap();
+        assert!(service.reload_policy(reload_request("s3cret")).await.unwrap().into_inner().reloaded);
+        for name in ["policy.toml", "moved.toml", "moved.jsonl"] {
+            assert_eq!(violations(name).await, vec!["self_protection"], "{}", name);
+        }
+        // The log opened at startup is written to until a restart
+        assert_eq!(violations("audit.jsonl").await, vec!["self_protection"]);
+        assert!(violations("edits.toml").await.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_deletes_and_restores_resolve_symlinked_directories() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let (work, outside, trash) = (root.join("work"), root.join("outside"), root.join(".trash"));
+        std::fs::create_dir_all(work.join("sub")).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        symlink(&outside, work.join("escape")).unwrap();
+        symlink(outside.join("secret.txt"), work.join("link.txt")).unwrap();
+        let toml = policy_toml(&root.display().to_string())
+            .replace("allowed_write = []", &format!("allowed_write = [{:?}]", work))
+            .replace("denied_patterns = []", &format!("denied_patterns = []\ntrash_dir = {:?}", trash));
+        let service = EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let delete = |path: PathBuf| {
+            service.delete_file(Request::new(DeleteFileRequest {
+                path: path.display().to_string(),
+                ..Default::default()
+            }))
+        };
+        let restore = |trash_id: String| {
+            service.restore_file(Request::new(RestoreFileRequest {
+                trash_id,
+                ..Default::default()
+            }))
+        };
+
+        // A symlinked directory leads outside allowed_write
+        let response = delete(work.join("escape/secret.txt")).await.unwrap().into_inner();
+        assert!(!response.status.unwrap().allowed);
+        assert_eq!(std::fs::read_to_string(outside.join("secret.txt")).unwrap(), "secret");
+
+        // A symlinked file is trashed as the link
+        let response = delete(work.join("link.txt")).await.unwrap().into_inner();
+        assert!(response.status.unwrap().allowed);
+        assert!(std::fs::symlink_metadata(work.join("link.txt")).is_err());
+        let trashed = trash.join(&response.trash_id).join("data");
+        assert_eq!(std::fs::read_link(trashed).unwrap(), outside.join("secret.txt"));
+        assert_eq!(std::fs::read_to_string(outside.join("secret.txt")).unwrap(), "secret");
+        assert!(restore(response.trash_id).await.unwrap().into_inner().status.unwrap().allowed);
+        assert_eq!(std::fs::read_link(work.join("link.txt")).unwrap(), outside.join("secret.txt"));
+
+        // Not restored through a directory swapped for a symlink since
+        std::fs::write(work.join("sub/a.txt"), "a").unwrap();
+        let trash_id = delete(work.join("sub/a.txt")).await.unwrap().into_inner().trash_id;
+        std::fs::remove_dir(work.join("sub")).unwrap();
+        symlink(&outside, work.join("sub")).unwrap();
+        assert!(!restore(trash_id).await.unwrap().into_inner().status.unwrap().allowed);
+        assert!(!outside.join("a.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_the_daemons_own_files_cannot_be_changed() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root_str = root.display().to_string();
+        let base = policy_toml(&root_str)
+            .replace("allowed_write = []", &format!("allowed_write = [{:?}]", root_str))
+            .replace("denied_patterns = []", &format!("denied_patterns = []\ntrash_dir = {:?}", root.join(".trash")));
+        // Deleted before the files are the daemon's, then restored after
+        std::fs::create_dir(root.join("state")).unwrap();
+        std::fs::write(root.join("state/grants.json"), "{}").unwrap();
+        let policy = Policy::load(&PolicySource::Inline(base.clone()), None).unwrap();
+        let before = EnforcementServiceImpl::new(policy).unwrap();
+        let delete = DeleteFileRequest {
+            path: root.join("state/grants.json").display().to_string(),
+            ..Default::default()
+        };
+        let trash_id = before.delete_file(Request::new(delete)).await.unwrap().into_inner().trash_id;
+
+        let file = root.join("policy.toml");
+        let toml = format!(
+            "{}\n[audit]\nfile = {:?}\n[state]\ndir = {:?}\n",
+            base,
+            root.join("audit.jsonl").display().to_string(),
+            root.join("state").display().to_string()
+        );
+        std::fs::write(&file, &toml).unwrap();
+        let source = PolicySource::File(file.clone());
+        let policy = Policy::load(&source, None).unwrap();
+        let service = EnforcementServiceImpl::new(policy).unwrap().with_origin(source, None);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&file, root.join("link.toml")).unwrap();
+            std::os::unix::fs::symlink(&root, root.join("alias")).unwrap();
+        }
+
+        let write = |path: PathBuf| {
+            service.write_file(Request::new(WriteFileRequest {
+                path: path.display().to_string(),
+                data: "[filesystem]\nallowed_write = [\"/\"]\n".into(),
+                ..Default::default()
+            }))
+        };
+        let mut targets = vec![file.clone(), root.join("audit.jsonl"), root.join("state/sessions.json")];
+        if cfg!(unix) {
+            targets.extend([root.join("link.toml"), root.join("alias/policy.toml")]);
+        }
+        for path in targets {
+            let status = write(path.clone()).await.unwrap().into_inner().status.unwrap();
+            assert_eq!(status.violations, vec!["self_protection"], "{}", path.display());
+            assert_eq!(status.reason_code(), ReasonCode::SelfProtection);
+        }
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), toml);
+        assert!(!root.join("state/sessions.json").exists());
+
+        let delete = DeleteFileRequest {
+            path: file.display().to_string(),
+            ..Default::default()
+        };
+        let status = service.delete_file(Request::new(delete)).await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.violations, vec!["self_protection"]);
+        assert!(file.exists());
+        let restore = RestoreFileRequest {
+            trash_id,
+            ..Default::default()
+        };
+        let status = service.restore_file(Request::new(restore)).await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.violations, vec!["self_protection"]);
+        assert!(!root.join("state/grants.json").exists());
+
+        // Reads, and writes beside the protected files, are up to the policy
+        assert!(write(root.join("notes.toml")).await.unwrap().into_inner().status.unwrap().allowed);
+        let read = ReadFileRequest {
+            path: file.display().to_string(),
+            ..Default::default()
+        };
+        assert!(service.read_file(Request::new(read)).await.unwrap().into_inner().status.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_windows_follow_the_clock() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let root_str = root.display().to_string();
+        let toml = format!(
+            "{}\n[schedule]\n[[schedule.windows]]\noperations = [\"write\"]\nstart = \"09:00\"\nend = \"17:00\"\n",
+            policy_toml(&root_str).replace("allowed_write = []", &format!("allowed_write = [{:?}]", root_str))
+        );
+        // 2024-03-01 16:59 UTC
+        let clock = MockClock::new(1_709_312_340);
+        let policy = Policy::load(&PolicySource::Inline(toml), None).unwrap();
+        let service = EnforcementServiceImpl::new_with_clock(policy, SharedClock::new(clock.clone())).unwrap();
+        let write = || {
+            service.write_file(Request::new(WriteFileRequest {
+                path: root.join("out.txt").display().to_string(),
+                data: "report".into(),
+                ..Default::default()
+            }))
+        };
+
+        assert!(write().await.unwrap().into_inner().status.unwrap().allowed);
+        clock.advance(Duration::from_secs(60));
+        let status = write().await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.violations, vec!["outside_schedule"]);
+        assert_eq!(status.reason_code(), ReasonCode::OutsideSchedule);
+        assert!(status.reason.ends_with("it is 17:00 on fri"), "{}", status.reason);
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Fault {
+        Canonicalize,
+        Io,
+    }
+
+    async fn faulty_call(
+        service: &EnforcementServiceImpl,
+        operation: &str,
+        path: &Path,
+        fault: Fault,
+    ) -> Result<SecurityStatus, Status> {
+        if let Fault::Canonicalize = fault {
+            crate::fs::validator::hooks::fail_next_canonicalize();
+        }
+        let path = path.display().to_string();
+        let status = match operation {
+            "read_file" => service
+                .read_file(Request::new(ReadFileRequest { path, ..Default::default() }))
+                .await?
+                .into_inner()
+                .status,
+            "write_file" => service
+                .write_file(Request::new(WriteFileRequest {
+                    path,
+                    data: b"data".to_vec(),
+                    ..Default::default()
+                }))
+                .await?
+                .into_inner()
+                .status,
+            "delete_file" => service
+                .delete_file(Request::new(DeleteFileRequest { path, capability: None }))
+                .await?
+                .into_inner()
+                .status,
+            other => unreachable!("{}", other),
+        };
+        Ok(status.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_internal_errors_fail_closed() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("file.txt"), "data").unwrap();
+        std::fs::create_dir(root.join("dir")).unwrap();
+        std::fs::write(root.join("dir/inner.txt"), "data").unwrap();
+        let base = policy_toml(&root.display().to_string()).replace(
+            "allowed_write = []",
+            &format!("allowed_write = [{:?}]", root.display().to_string()),
+        );
+
+        // A directory where a file is expected makes the read or write fail
+        let cases = [
+            ("read_file", Fault::Canonicalize, "file.txt"),
+            ("write_file", Fault::Canonicalize, "file.txt"),
+            ("delete_file", Fault::Canonicalize, "file.txt"),
+            ("read_file", Fault::Io, "dir"),
+            ("write_file", Fault::Io, "dir"),
+        ];
+        for mode in ["deny", "error"] {
+            let toml = format!("{}\n[enforcement]\non_internal_error = {:?}\n", base, mode);
+            let service =
+                EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+            for (operation, fault, name) in cases {
+                let result = faulty_call(&service, operation, &root.join(name), fault).await;
+                let case = format!("{} {:?} on_internal_error={}", operation, fault, mode);
+                match mode {
+                    "deny" => {
+                        let status = result.expect(&case);
+                        assert!(!status.allowed, "{}", case);
+                        assert_eq!(status.violations, vec!["internal_error"], "{}", case);
+                    }
+                    _ => assert_eq!(result.map(|_| ()).unwrap_err().code(), tonic::Code::Internal, "{}", case),
+                }
+            }
+        }
+        assert_eq!(std::fs::read_to_string(root.join("file.txt")).unwrap(), "data");
+
+        // A deny pattern that cannot be compiled stops the policy loading
+        let bad_glob = base.replace("denied_patterns = []", "denied_patterns = [\"/srv/[unclosed\"]");
+        assert_ne!(bad_glob, base);
+        assert!(Policy::load(&PolicySource::Inline(bad_glob), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_degraded_mode_sheds_searches_until_recovered() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("notes.txt"), "needle").unwrap();
+        let toml = policy_toml(&root.display().to_string()).replace(
+            "admin_token = \"s3cret\"",
+            "admin_token = \"s3cret\"\nlatency_budget_ms = 5",
+        );
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let search = || SearchFilesRequest {
+            root: root.display().to_string(),
+            pattern: "needle".to_string(),
+            ..Default::default()
+        };
+        let state = service.state();
+        assert_eq!(state.latency_budget, Some(Duration::from_millis(5)));
+        let feed = |elapsed| {
+            for _ in 0..1024 {
+                service.record_latency(&state, elapsed);
+            }
+        };
+
+        feed(Duration::from_millis(50));
+        let status = service.get_status(Request::new(StatusRequest {})).await.unwrap().into_inner();
+        assert!(status.degraded);
+        assert_eq!(status.decision_p99_us, 50_000);
+        let err = service.search_files(Request::new(search())).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::PermissionDenied);
+        assert_eq!(err.metadata().get("x-error-code").unwrap(), "degraded");
+        // Plain reads never take a shortcut
+        let read = ReadFileRequest {
+            path: root.join("notes.txt").display().to_string(),
+            ..Default::default()
+        };
+        let response = service.read_file(Request::new(read)).await.unwrap().into_inner();
+        assert_eq!(response.data, b"needle");
+
+        feed(Duration::from_millis(1));
+        let status = service.get_status(Request::new(StatusRequest {})).await.unwrap().into_inner();
+        assert!(!status.degraded);
+        assert!(service.search_files(Request::new(search())).await.is_ok());
+        let transitions: Vec<String> = service
+            .audit
+            .query(&AuditFilter {
+                event_types: vec!["degraded_mode".to_string()],
+                ..Default::default()
+            })
+            .into_iter()
+            .map(|entry| entry.resource)
+            .collect();
+        assert_eq!(transitions, vec!["degraded", "recovered"]);
+    }
+
+    #[tokio::test]
+    async fn test_session_budgets() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let toml = policy_toml("/srv").replace(
+            "allowed_write = []",
+            &format!("allowed_write = [{:?}]", root.display().to_string()),
+        ) + "\n[sessions]\nmax_files_written = 50\n";
+        let service =
+            EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let create = |files_written| {
+            service.create_session(Request::new(CreateSessionRequest {
+                budgets: Some(SessionBudgets {
+                    files_written,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+        };
+        let limited = create(1).await.unwrap().into_inner();
+        let defaulted = create(0).await.unwrap().into_inner();
+        assert_eq!(defaulted.limits.unwrap().files_written, 50);
+
+        let path = root.join("out.txt");
+        let first = service.write_file(session_write(&path, &limited.session_id)).await.unwrap();
+        assert!(first.into_inner().status.unwrap().allowed);
+        let second = service.write_file(session_write(&path, &limited.session_id)).await.unwrap();
+        assert_eq!(second.into_inner().status.unwrap().violations, vec!["budget_exhausted"]);
+
+        // Other sessions keep their own budgets
+        let other = service.write_file(session_write(&path, &defaulted.session_id)).await.unwrap();
+        assert!(other.into_inner().status.unwrap().allowed);
+
+        let info = service
+            .get_session(Request::new(GetSessionRequest {
+                session_id: limited.session_id.clone(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(info.used.unwrap().files_written, 1);
+        let entries = service.audit.query(&AuditFilter::default());
+        assert!(entries
+            .iter()
+            .any(|e| e.session_id == limited.session_id && e.metadata.get("files_written").map(String::as_str) == Some("1/1")));
+
+        let missing = service
+            .get_session(Request::new(GetSessionRequest {
+                session_id: "sess-unknown".into(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_write_file_stream_replaces_whole_files_only() {
+        let env = crate::grpc::test_env::TestEnv::with_policy(|policy| policy.writable().max_file_size_mb(1)).await;
+        let path = env.write("upload.bin", "original");
+        let chunks = |path: &str, sizes: &[usize]| -> Vec<WriteFileChunk> {
+            sizes
+                .iter()
+                .enumerate()
+                .map(|(index, size)| WriteFileChunk {
+                    path: if index == 0 { path.to_string() } else { String::new() },
+                    data: vec![b'x'; *size],
+                    done: index + 1 == sizes.len(),
+                    ..Default::default()
+                })
+                .collect()
+        };
+        let send = |chunks: Vec<WriteFileChunk>| {
+            let mut client = env.client.clone();
+            async move { client.write_file_stream(tokio_stream::iter(chunks)).await }
+        };
+        let leftovers = || std::fs::read_dir(&env.root).unwrap().count();
+
+        let status = |response: Response<WriteFileResponse>| response.into_inner().status.unwrap();
+        let written = status(send(chunks(&path, &[0, 3, 512 * 1024])).await.unwrap());
+        assert!(written.allowed, "{}", written.reason);
+        assert_eq!(std::fs::read(&path).unwrap().len(), 3 + 512 * 1024);
+
+        // Over 1 MiB in all, though no chunk is
+        let denied = status(send(chunks(&path, &[600 * 1024, 600 * 1024])).await.unwrap());
+        assert_eq!(denied.violations, vec!["file_too_large"]);
+        assert_eq!(denied.reason_code, ReasonCode::FileTooLarge as i32);
+        assert_eq!(std::fs::read(&path).unwrap().len(), 3 + 512 * 1024);
+        assert_eq!(leftovers(), 1);
+
+        let mut named_twice = chunks(&path, &[1, 1]);
+        named_twice[1].path = path.clone();
+        assert_eq!(send(named_twice).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert_eq!(send(Vec::new()).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        let outside = status(send(chunks("/etc/upload.bin", &[1])).await.unwrap());
+        assert_eq!(outside.violations, vec!["path_not_allowed"]);
+
+        // A stream that ends before its last chunk writes nothing
+        let mut unfinished = chunks(&path, &[1, 1]);
+        unfinished[1].done = false;
+        assert_eq!(send(unfinished).await.unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert_eq!(std::fs::read(&path).unwrap().len(), 3 + 512 * 1024);
+
+        // Nor does a stream the client cancels midway
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        sender.send(chunks(&path, &[3, 3]).remove(0)).await.unwrap();
+        let mut client = env.client.clone();
+        let call = tokio::spawn(async move {
+            client.write_file_stream(tokio_stream::wrappers::ReceiverStream::new(receiver)).await
+        });
+        for _ in 0..100 {
+            if leftovers() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(leftovers(), 2, "the temporary file should exist while the stream is open");
+        call.abort();
+        assert!(call.await.unwrap_err().is_cancelled());
+        drop(sender);
+        for _ in 0..100 {
+            if leftovers() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(leftovers(), 1);
+        assert_eq!(std::fs::read(&path).unwrap().len(), 3 + 512 * 1024);
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_client_round_trip() {
+        use openclaw_enforce::proto::openclaw::enforce as client_proto;
+        use openclaw_enforce::{ClientError, EnforceClient};
+
+        let env = crate::grpc::test_env::TestEnv::with_policy(|policy| policy.writable()).await;
+        let mut client = EnforceClient::connect(env.url()).await.unwrap();
+        let path = env.path("notes.txt");
+        client.write_file(&path, "hello", client_proto::WriteFileMode::CreateNew).await.unwrap();
+        assert_eq!(client.read_file(&path).await.unwrap(), b"hello");
+        assert_eq!(client.read_file_range(&path, 1, 3).await.unwrap(), (b"ell".to_vec(), false));
+        client.write_file_chunks(&path, [b"hel".to_vec(), b"lo again".to_vec()]).await.unwrap();
+        assert_eq!(client.read_file(&path).await.unwrap(), b"hello again");
+        assert!(client.can_read(&path).await.unwrap());
+        assert!(!client.can_read("/etc/passwd").await.unwrap());
+        match client.read_file("/etc/passwd").await {
+            Err(ClientError::Denied { code, violations, .. }) => {
+                assert_eq!(code, client_proto::ReasonCode::PathNotAllowed);
+                assert_eq!(violations, vec!["path_not_allowed"]);
+            }
+            other => panic!("expected a denial, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_every_violation_has_a_reason_code() {
+        fn sources(dir: &Path, found: &mut Vec<PathBuf>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    sources(&path, found);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    found.push(path);
+                }
+            }
+        }
+        let mut files = Vec::new();
+        sources(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut files);
+
+        let mut violations = std::collections::BTreeSet::new();
+        for file in files {
+            let text = std::fs::read_to_string(&file).unwrap();
+            for call in ["Denial::new(", "with_metadata(\"violation\","] {
+                for (at, _) in text.match_indices(call) {
+                    let rest = text[at + call.len()..].trim_start();
+                    let literal = rest.strip_prefix('"').and_then(|rest| rest.split('"').next());
+                    // Skips this test's own search strings
+                    let is_name = |l: &&str| !l.is_empty() && l.bytes().all(|b| b.is_ascii_lowercase() || b == b'_');
+                    if let Some(literal) = literal.filter(is_name) {
+                        violations.insert(literal.to_string());
+                    }
+                }
+            }
+        }
+        assert!(violations.len() > 30, "{:?}", violations);
+        for violation in violations {
+            assert_ne!(reason_code(&violation), ReasonCode::Unspecified, "{}", violation);
+        }
+        assert_eq!(reason_code("path_not_allowed"), ReasonCode::PathNotAllowed);
+        assert_eq!(reason_code("no_such_violation"), ReasonCode::Unspecified);
+    }
+
+    #[test]
+    fn test_every_reason_code_has_a_message() {
+        let codes: Vec<String> = (1..1000)
+            .filter_map(|code| ReasonCode::try_from(code).ok())
+            .map(|code| code.as_str_name().trim_start_matches("REASON_CODE_").to_ascii_lowercase())
+            .collect();
+        assert!(codes.len() > 40, "{:?}", codes);
+        for code in codes {
+            assert!(crate::policy::messages::has_message(&code), "{} has no [messages] entry", code);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reasons_are_worded_by_the_catalog() {
+        let toml = format!(
+            "{}
+[messages]
+locale = \"fr\"
+[messages.catalogs.fr]
+path_not_allowed = \"Accès refusé à {{path}}\"\n",
+            policy_toml("/srv")
+        );
+        let service = EnforcementServiceImpl::new(Policy::load(&PolicySource::Inline(toml), None).unwrap()).unwrap();
+        let read = ReadFileRequest {
+            path: "/etc/hostname".into(),
+            ..Default::default()
+        };
+        let status = service.read_file(Request::new(read)).await.unwrap().into_inner().status.unwrap();
+        assert_eq!(status.reason, "Accès refusé à /etc/hostname");
+        assert_eq!(status.reason_code(), ReasonCode::PathNotAllowed);
+        // The audit log keeps the daemon's own explanation
+        let [entry] = service.audit.query(&AuditFilter::default()).try_into().unwrap();
+        assert_eq!(entry.reason, "Path not in allowed read list");
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"token", b"token"));
+        assert!(!constant_time_eq(b"token", b"tokeN"));
+        assert!(!constant_time_eq(b"token", b"token2"));
+    }
 }
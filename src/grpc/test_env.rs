@@ -0,0 +1,184 @@
+//! In-process daemon for end-to-end tests: a tempdir, a policy built
+//! around it, [`serve_listener`] on an ephemeral port and a client for it
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+use tonic::transport::{Channel, Endpoint};
+use tonic_health::pb::health_client::HealthClient;
+
+use super::server::serve_listener;
+use crate::policy::parser::PolicySource;
+use crate::policy::Policy;
+use crate::proto::openclaw::enforce::enforcement_service_client::EnforcementServiceClient;
+
+/// The policy a [`TestEnv`] starts with. The tempdir is readable and
+/// nothing else is allowed until added.
+pub struct PolicyBuilder {
+    root: PathBuf,
+    allowed_read: Vec<String>,
+    allowed_write: Vec<String>,
+    denied_patterns: Vec<String>,
+    allowed_domains: Vec<String>,
+    allowed_commands: Vec<String>,
+    max_file_size_mb: u64,
+    extra: String,
+}
+
+impl PolicyBuilder {
+    fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            allowed_read: vec![root.display().to_string()],
+            allowed_write: Vec::new(),
+            denied_patterns: Vec::new(),
+            allowed_domains: Vec::new(),
+            allowed_commands: Vec::new(),
+            max_file_size_mb: 100,
+            extra: String::new(),
+        }
+    }
+
+    /// Makes the whole tempdir writable
+    pub fn writable(mut self) -> Self {
+        self.allowed_write.push(self.root.display().to_string());
+        self
+    }
+
+    pub fn deny(mut self, pattern: impl Into<String>) -> Self {
+        self.denied_patterns.push(pattern.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.allowed_domains.push(domain.into());
+        self
+    }
+
+    /// A bare name is looked up on the test's `PATH`
+    pub fn command(mut self, command: impl Into<String>) -> Self {
+        self.allowed_commands.push(command.into());
+        self
+    }
+
+    pub fn max_file_size_mb(mut self, mb: u64) -> Self {
+        self.max_file_size_mb = mb;
+        self
+    }
+
+    /// Appends raw TOML, for tables the builder has no method for
+    pub fn toml(mut self, toml: &str) -> Self {
+        self.extra.push('\n');
+        self.extra.push_str(toml);
+        self
+    }
+
+    pub fn to_toml(&self) -> String {
+        format!(
+            r#"
+[filesystem]
+allowed_read = {:?}
+allowed_write = {:?}
+denied_patterns = {:?}
+
+[network]
+allowed_domains = {:?}
+blocked_ips = []
+max_connections = 10
+
+[process]
+allowed_commands = {:?}
+max_cpu_percent = 50
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = {}
+max_open_files = 1000
+{}
+"#,
+            self.allowed_read,
+            self.allowed_write,
+            self.denied_patterns,
+            self.allowed_domains,
+            self.allowed_commands,
+            self.max_file_size_mb,
+            self.extra
+        )
+    }
+}
+
+/// A daemon serving a policy that covers a fresh tempdir. The server is
+/// stopped when the env is dropped.
+pub struct TestEnv {
+    pub root: PathBuf,
+    pub client: EnforcementServiceClient<Channel>,
+    addr: SocketAddr,
+    server: JoinHandle<anyhow::Result<()>>,
+    _dir: TempDir,
+}
+
+impl TestEnv {
+    pub async fn start() -> Self {
+        Self::with_policy(|policy| policy).await
+    }
+
+    /// Starts the daemon with the default policy as changed by `build`
+    pub async fn with_policy(build: impl FnOnce(PolicyBuilder) -> PolicyBuilder) -> Self {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let source = PolicySource::Inline(build(PolicyBuilder::new(&root)).to_toml());
+        let policy = Policy::load(&source, None).expect("test policy should load");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve_listener(listener, policy, source, None, None, false, std::future::pending()));
+        let channel = Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        Self {
+            root,
+            client: EnforcementServiceClient::new(channel),
+            addr,
+            server,
+            _dir: dir,
+        }
+    }
+
+    /// Where the daemon listens, for clients other than [`TestEnv::client`]
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// `name` within the tempdir, as a request path
+    pub fn path(&self, name: &str) -> String {
+        self.root.join(name).display().to_string()
+    }
+
+    /// Creates `name` within the tempdir and returns its request path
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> String {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(&path, contents).unwrap();
+        path.display().to_string()
+    }
+
+    /// A new connection to the daemon
+    pub async fn channel(&self) -> Channel {
+        Endpoint::from_shared(self.url()).unwrap().connect().await.unwrap()
+    }
+
+    pub async fn health(&self) -> HealthClient<Channel> {
+        HealthClient::new(self.channel().await)
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Header a client sets its deadline with
+const TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Methods tracked at most, so made-up method names cannot grow the table
+const MAX_METHODS: usize = 64;
+
+/// Calls and time taken per method, until the response headers are sent.
+/// For a streaming call that is the time to the start of the stream.
+#[derive(Debug, Default)]
+pub struct MethodLatency {
+    methods: Mutex<BTreeMap<String, MethodStats>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MethodStats {
+    pub calls: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl MethodLatency {
+    pub fn record(&self, method: &str, elapsed: Duration) {
+        let mut methods = self.methods.lock().unwrap_or_else(|e| e.into_inner());
+        if !methods.contains_key(method) && methods.len() >= MAX_METHODS {
+            return;
+        }
+        let stats = methods.entry(method.to_string()).or_default();
+        stats.calls += 1;
+        stats.total += elapsed;
+        stats.max = stats.max.max(elapsed);
+    }
+
+    /// Every method called so far, by path
+    pub fn snapshot(&self) -> Vec<(String, MethodStats)> {
+        let methods = self.methods.lock().unwrap_or_else(|e| e.into_inner());
+        methods.iter().map(|(method, stats)| (method.clone(), *stats)).collect()
+    }
+}
+
+/// Times every call into a [`MethodLatency`], and ends a call the client
+/// gave no deadline with DEADLINE_EXCEEDED after `default_deadline`. A
+/// deadline the client did set is left to tonic.
+#[derive(Debug, Clone)]
+pub struct TimingLayer {
+    latency: Arc<MethodLatency>,
+    default_deadline: Option<Duration>,
+}
+
+impl TimingLayer {
+    /// `default_deadline` of zero sets none
+    pub fn new(latency: Arc<MethodLatency>, default_deadline: Duration) -> Self {
+        Self {
+            latency,
+            default_deadline: (!default_deadline.is_zero()).then_some(default_deadline),
+        }
+    }
+}
+
+impl<S> Layer<S> for TimingLayer {
+    type Service = TimingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimingService {
+            inner,
+            latency: self.latency.clone(),
+            default_deadline: self.default_deadline,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimingService<S> {
+    inner: S,
+    latency: Arc<MethodLatency>,
+    default_deadline: Option<Duration>,
+}
+
+impl<S, B> Service<hyper::Request<B>> for TimingService<S>
+where
+    S: Service<hyper::Request<B>, Response = hyper::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<B>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let deadline = self.default_deadline.filter(|_| !request.headers().contains_key(TIMEOUT_HEADER));
+        let latency = self.latency.clone();
+        let started = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = match deadline {
+                None => response.await,
+                Some(deadline) => match tokio::time::timeout(deadline, response).await {
+                    Ok(response) => response,
+                    Err(_) => Ok(Status::deadline_exceeded(format!(
+                        "No deadline was set and the call ran past the default of {:?}",
+                        deadline
+                    ))
+                    .into_http()),
+                },
+            };
+            latency.record(&method, started.elapsed());
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    /// Answers OK after `delay`
+    #[derive(Clone)]
+    struct Slow {
+        delay: Duration,
+    }
+
+    impl Service<hyper::Request<()>> for Slow {
+        type Response = hyper::Response<BoxBody>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _request: hyper::Request<()>) -> Self::Future {
+            let delay = self.delay;
+            Box::pin(async move {
+                tokio::time::sleep(delay).await;
+                Ok(hyper::Response::new(tonic::body::empty_body()))
+            })
+        }
+    }
+
+    async fn grpc_status(layer: &TimingLayer, delay_ms: u64, timeout: Option<&str>) -> Option<String> {
+        let mut service = layer.layer(Slow {
+            delay: Duration::from_millis(delay_ms),
+        });
+        let mut request = hyper::Request::builder().uri("/openclaw.enforce.EnforcementService/ReadFile");
+        if let Some(timeout) = timeout {
+            request = request.header(TIMEOUT_HEADER, timeout);
+        }
+        let response = service.call(request.body(()).unwrap()).await.unwrap();
+        response.headers().get("grpc-status").map(|status| status.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_default_deadline_applies_only_without_one() {
+        let latency = Arc::new(MethodLatency::default());
+        let layer = TimingLayer::new(latency.clone(), Duration::from_millis(50));
+        assert_eq!(grpc_status(&layer, 1, None).await, None);
+        // 4 is DEADLINE_EXCEEDED
+        assert_eq!(grpc_status(&layer, 500, None).await.as_deref(), Some("4"));
+        // The client's own deadline is tonic's to enforce
+        assert_eq!(grpc_status(&layer, 100, Some("5S")).await, None);
+
+        let unlimited = TimingLayer::new(latency.clone(), Duration::ZERO);
+        assert_eq!(grpc_status(&unlimited, 100, None).await, None);
+
+        let [(method, stats)] = latency.snapshot().try_into().unwrap();
+        assert_eq!(method, "/openclaw.enforce.EnforcementService/ReadFile");
+        assert_eq!(stats.calls, 4);
+        // The timed out call counts until it was ended
+        assert!(stats.max >= Duration::from_millis(100) && stats.max < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_method_table_is_bounded() {
+        let latency = MethodLatency::default();
+        for i in 0..MAX_METHODS + 10 {
+            latency.record(&format!("/made.up/Method{}", i), Duration::from_millis(1));
+        }
+        latency.record("/made.up/Method0", Duration::from_millis(3));
+        let snapshot = latency.snapshot();
+        assert_eq!(snapshot.len(), MAX_METHODS);
+        let first = snapshot.iter().find(|(method, _)| method == "/made.up/Method0").unwrap().1;
+        assert_eq!((first.calls, first.max), (2, Duration::from_millis(3)));
+    }
+}
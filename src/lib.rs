@@ -0,0 +1,45 @@
+//! Rust client for the `openclaw-enforce` daemon, enabled with the
+//! `client` feature. The daemon itself is the binary target.
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod proto;
+
+#[cfg(feature = "client")]
+pub use client::{ClientError, EnforceClient};
+
+// The `fuzzing` and `bench` features build the daemon's modules into the
+// library too, for the targets under `fuzz/` and `benches/`; their tests
+// run from the binary
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod audit;
+#[cfg(all(feature = "bench", not(test)))]
+pub mod benching;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod capabilities;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod daemon;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod fs;
+#[cfg(all(feature = "fuzzing", not(test)))]
+pub mod fuzzing;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod grpc;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod network;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod policy;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod process;
+#[cfg(all(any(feature = "fuzzing", feature = "bench"), not(test)))]
+#[allow(dead_code)]
+mod resources;
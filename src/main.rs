@@ -1,29 +1,58 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
 mod audit;
 mod capabilities;
+mod daemon;
 mod fs;
+#[cfg(test)]
+mod fuzzing;
 mod grpc;
 mod network;
 mod policy;
 mod process;
 mod proto;
+mod resources;
 
+use crate::audit::chain::verify_chain;
+use crate::daemon::pid_file::PidFile;
+use crate::daemon::state::StatePaths;
+use crate::fs::self_check::SelfCheckReport;
+use crate::fs::validator::{Access, PathValidator};
 use crate::grpc::server::serve;
+use crate::network::domain::DomainMatcher;
+use crate::policy::merge::MergeStrategy;
+use crate::policy::parser::{self, PolicyErrors, PolicyFormat, PolicySource, POLICY_ENV_VAR};
+use crate::policy::rules::Decision;
+use crate::policy::signature::PolicyKey;
 use crate::policy::Policy;
+use crate::process::command::CommandGuard;
+use crate::process::privileges;
 
 #[derive(Parser, Debug)]
 #[command(name = "openclaw-enforce")]
 #[command(version, author = "OpenClaw Security Team")]
 #[command(about = "Security enforcement layer for OpenClaw AI Assistant", long_about = None)]
 struct Args {
-    /// Path to policy configuration file
-    #[arg(short, long, default_value = "policy.toml")]
-    config: PathBuf,
+    /// Path to policy configuration file, or `-` to read it from stdin.
+    /// Defaults to $OPENCLAW_POLICY when set, otherwise policy.toml
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Policy format (toml, json). Detected from the file extension;
+    /// stdin and $OPENCLAW_POLICY default to toml
+    #[arg(long)]
+    config_format: Option<PolicyFormat>,
+
+    /// Only load a policy file signed by this Ed25519 public key (PEM, or
+    /// the base64 of the raw key), with the signature in `<config>.sig`.
+    /// Reloads are checked the same way
+    #[arg(long, value_name = "FILE")]
+    policy_pubkey: Option<PathBuf>,
 
     /// gRPC server address
     #[arg(short = 'a', long, default_value = "127.0.0.1:50051")]
@@ -37,15 +66,95 @@ struct Args {
     #[arg(long)]
     show_policy: bool,
 
+    /// Check that allowed paths exist and are accessible, then exit
+    #[arg(long)]
+    self_check: bool,
+
+    /// Show which policy entries decide a request and which they outrank,
+    /// then exit (exits 1 if it is denied). KIND is read, write, domain or
+    /// command, e.g. `write:/srv/app/run.sh`
+    #[arg(long, value_name = "KIND:TARGET")]
+    explain: Option<String>,
+
+    /// Verify the hash chain of an audit log written with `[audit] file`,
+    /// then exit (exits 1 if it was tampered with)
+    #[arg(long, value_name = "FILE")]
+    verify_audit_log: Option<PathBuf>,
+
+    /// Write the daemon's pid here once it is listening; removed on a
+    /// clean shutdown
+    #[arg(long, value_name = "FILE")]
+    pid_file: Option<PathBuf>,
+
+    /// Keep running as root when no `[server] run_as_user` is set, or it
+    /// is root itself
+    #[arg(long)]
+    allow_root: bool,
+
+    /// Start in read-only mode: writes, commands and network access are
+    /// denied with read_only_mode until the ReadOnlyMode RPC turns it off
+    #[arg(long)]
+    read_only: bool,
+
+    /// Discard the grants, session budgets and quota usage saved in
+    /// `[state] dir` (and `quota_state_file`) before starting
+    #[arg(long)]
+    reset_state: bool,
+
+    /// Print an example policy (development, strict) to start from, then
+    /// exit
+    #[arg(long, value_name = "PROFILE", num_args = 0..=1, default_missing_value = "development")]
+    example: Option<policy::examples::Profile>,
+
+    /// Merge this policy onto the one --config loads, print the result as
+    /// TOML, then exit
+    #[arg(long, value_name = "FILE")]
+    merge: Option<PathBuf>,
+
+    /// How --merge combines them: narrow keeps what both allow, widen
+    /// what either allows
+    #[arg(long, value_name = "STRATEGY", default_value = "narrow", requires = "merge")]
+    merge_strategy: MergeStrategy,
+
+    /// Print the policy's JSON Schema (json-schema) or a reference for it
+    /// (markdown), then exit
+    #[cfg(feature = "schema")]
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "json-schema")]
+    schema: Option<policy::schema::SchemaFormat>,
+
     /// Log level (trace, debug, info, warn, error)
     #[arg(short, long, default_value = "info")]
     log_level: String,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let args = Args::parse();
 
+    // Before logging starts, so the output can be redirected to a file
+    if let Some(profile) = args.example {
+        // Not something to hand out if this build would reject it
+        Policy::example(profile).with_context(|| format!("The {} example policy does not load in this build", profile))?;
+        print!("{}", profile.source());
+        return Ok(());
+    }
+    if let Some(overlay) = &args.merge {
+        let source = PolicySource::resolve(args.config.clone(), std::env::var(POLICY_ENV_VAR).ok());
+        let base = Policy::load(&source, args.config_format)?;
+        let overlay_policy = Policy::load(&PolicySource::File(overlay.clone()), None)?;
+        let merged = base.merge(&overlay_policy, args.merge_strategy)?;
+        println!("# {} merged onto {}", overlay.display(), source);
+        for origin in merged.added_by_overlay() {
+            println!("#   from the overlay: {} {}", origin.key, origin.value);
+        }
+        print!("{}", toml::to_string(&merged.policy).context("Failed to serialize the merged policy")?);
+        return Ok(());
+    }
+    #[cfg(feature = "schema")]
+    if let Some(format) = args.schema {
+        println!("{}", policy::schema::render(format));
+        return Ok(());
+    }
+
     // Initialize logging
     let log_level = match args.log_level.to_lowercase().as_str() {
         "trace" => Level::TRACE,
@@ -67,15 +176,47 @@ async fn main() -> Result<()> {
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to set tracing subscriber");
 
-    info!("OpenClaw Enforce v{}", env!("CARGO_PKG_VERSION"));
+    info!(
+        "OpenClaw Enforce v{} ({}, {})",
+        env!("CARGO_PKG_VERSION"),
+        env!("OPENCLAW_GIT_SHA"),
+        env!("OPENCLAW_TARGET")
+    );
     info!("Security enforcement layer for OpenClaw AI Assistant");
 
+    if let Some(path) = &args.verify_audit_log {
+        match verify_chain(path) {
+            Ok(count) => println!("✅ Audit log intact: {} entries", count),
+            Err(e) => {
+                eprintln!("❌ Audit log verification failed: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
     // Load and validate policy
-    info!("Loading policy from: {:?}", args.config);
-    let policy = match Policy::from_file(&args.config) {
-        Ok(p) => p,
+    let source = PolicySource::resolve(args.config, std::env::var(POLICY_ENV_VAR).ok());
+    info!("Loading policy from: {}", source);
+    let policy_key = match args.policy_pubkey.as_deref().map(PolicyKey::load).transpose() {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("❌ {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let (policy, normalizations) = match Policy::load_normalized(&source, args.config_format, policy_key.as_ref()) {
+        Ok(loaded) => loaded,
         Err(e) => {
-            eprintln!("❌ Failed to load policy: {}", e);
+            match e.downcast_ref::<PolicyErrors>() {
+                Some(PolicyErrors(problems)) => {
+                    eprintln!("❌ Failed to load policy, {} problem(s):", problems.len());
+                    for (i, problem) in problems.iter().enumerate() {
+                        eprintln!("  {}. {}", i + 1, problem);
+                    }
+                }
+                None => eprintln!("❌ Failed to load policy: {}", e),
+            }
             std::process::exit(1);
         }
     };
@@ -90,7 +231,37 @@ async fn main() -> Result<()> {
     // If validate-only mode, exit here
     if args.validate {
         println!("✅ Policy validation successful");
-        println!("Configuration: {:?}", args.config);
+        println!("Configuration: {}", source);
+        if !normalizations.is_empty() {
+            println!("Normalized {} entries:", normalizations.len());
+            for normalization in &normalizations {
+                println!("  {}", normalization);
+            }
+        }
+        for warning in parser::lint(&policy, &source) {
+            println!("⚠️ {}", warning);
+        }
+        return Ok(());
+    }
+
+    if args.self_check {
+        let report = SelfCheckReport::run(&policy.filesystem);
+        print!("{}", report.render_table());
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(query) = &args.explain {
+        let decision = explain(&policy, query)?;
+        println!("{}: {}", if decision.allowed() { "allowed" } else { "denied" }, decision.describe());
+        for loser in &decision.losers {
+            println!("  over {}", loser);
+        }
+        if !decision.allowed() {
+            std::process::exit(1);
+        }
         return Ok(());
     }
 
@@ -98,9 +269,22 @@ async fn main() -> Result<()> {
     if args.show_policy {
         println!("\n=== Policy Summary ===\n");
         println!("Filesystem:");
+        if let Some(root) = &policy.workspace_root {
+            println!("  Workspace root: {}", root);
+        }
+        // Entries are shown as enforced, with relative ones already resolved
         println!("  Allowed read: {} paths", policy.filesystem.allowed_read.len());
+        for entry in &policy.filesystem.allowed_read {
+            println!("    {}", entry);
+        }
         println!("  Allowed write: {} paths", policy.filesystem.allowed_write.len());
+        for entry in &policy.filesystem.allowed_write {
+            println!("    {}", entry);
+        }
         println!("  Denied patterns: {} patterns", policy.filesystem.denied_patterns.len());
+        for entry in &policy.filesystem.denied_patterns {
+            println!("    {}", entry);
+        }
         println!("\nNetwork:");
         println!("  Allowed domains: {} domains", policy.network.allowed_domains.len());
         println!("  Max connections: {}", policy.network.max_connections);
@@ -111,16 +295,76 @@ async fn main() -> Result<()> {
         println!("\nResources:");
         println!("  Max file size: {} MB", policy.resources.max_file_size_mb);
         println!("  Max open files: {}", policy.resources.max_open_files);
+        let overrides: Vec<_> = normalizations.iter().filter(|n| n.is_override()).collect();
+        if !overrides.is_empty() {
+            println!("\nOverridden by the environment:");
+            for normalization in overrides {
+                println!("  {}", normalization);
+            }
+        }
         println!();
         return Ok(());
     }
 
     // Start gRPC server
-    let addr = args.address.parse()?;
+    let addr: SocketAddr = args.address.parse()?;
     info!("🚀 Starting gRPC server on {}", addr);
-    
-    serve(addr, policy).await?;
+    // Bound while still privileged, so a low port works, and privileges
+    // are dropped before the runtime starts a thread that would keep them
+    let listener =
+        std::net::TcpListener::bind(addr).with_context(|| format!("Failed to listen on {}", addr))?;
+    match privileges::drop_privileges(&policy.server, args.allow_root) {
+        Ok(Some(identity)) => info!("Running as uid {} gid {}", identity.uid, identity.gid),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("❌ Failed to drop privileges: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+    let _pid_file = match args.pid_file.as_deref().map(PidFile::create).transpose() {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            eprintln!("❌ {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if args.reset_state {
+        match StatePaths::of(&policy).reset() {
+            Ok(removed) => {
+                info!("Reset saved state: removed {} files", removed.len());
+                for path in &removed {
+                    info!("  {}", path.display());
+                }
+            }
+            Err(e) => {
+                eprintln!("❌ Failed to reset state: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let serving = serve(listener, policy, source, args.config_format, policy_key, args.read_only);
+    tokio::runtime::Runtime::new()?.block_on(serving)?;
 
     info!("Shutting down");
     Ok(())
 }
+
+/// The decision for an --explain query, made as the daemon would
+fn explain(policy: &Policy, query: &str) -> Result<Decision> {
+    let (kind, target) = query
+        .split_once(':')
+        .context("--explain takes KIND:TARGET, e.g. read:/srv/app/main.rs")?;
+    match kind {
+        "read" | "write" => {
+            let access = if kind == "read" { Access::Read } else { Access::Write };
+            PathValidator::new(policy.filesystem.clone())
+                .with_state_dir(policy.state.dir.as_deref())
+                .explain(Path::new(target), access)
+        }
+        "domain" => Ok(DomainMatcher::new("network.allowed_domains", &policy.network.allowed_domains).explain(target)),
+        "command" => Ok(CommandGuard::new(&policy.process).explain(target)),
+        _ => anyhow::bail!("Unknown --explain kind {:?}, expected read, write, domain or command", kind),
+    }
+}
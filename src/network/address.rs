@@ -0,0 +1,467 @@
+use anyhow::{Context, Result};
+use hyper_util::client::legacy::connect::dns::Name;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use super::Denial;
+use crate::policy::NetworkPolicy;
+
+/// Ranges rejected by `block_private_addresses`, with a label for violations
+const PRIVATE_RANGES: &[(&str, &str)] = &[
+    ("0.0.0.0/8", "unspecified"),
+    ("127.0.0.0/8", "loopback"),
+    ("10.0.0.0/8", "RFC1918 private"),
+    ("172.16.0.0/12", "RFC1918 private"),
+    ("192.168.0.0/16", "RFC1918 private"),
+    ("169.254.0.0/16", "link-local"),
+    ("100.64.0.0/10", "CGNAT shared"),
+    ("::/128", "unspecified"),
+    ("::1/128", "loopback"),
+    ("fe80::/10", "link-local"),
+    ("fc00::/7", "unique-local"),
+];
+
+/// Cloud instance metadata endpoints. These are denied even when
+/// `block_private_addresses` is off or a range covering them is allowed.
+pub const METADATA_ADDRESSES: &[&str] = &[
+    "169.254.169.254",
+    "169.254.170.2",
+    "100.100.100.200",
+    "fd00:ec2::254",
+];
+
+/// An IPv4 or IPv6 network in CIDR notation. A bare address is a /32 or /128.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Whether `ip`, or the IPv4 address it carries, is in the network
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.contains_exactly(ip) || self.contains_exactly(&normalize(*ip))
+    }
+
+    fn contains_exactly(&self, ip: &IpAddr) -> bool {
+        match (self.network, *ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                mask_v4(u32::from(net), self.prefix) == mask_v4(u32::from(ip), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                mask_v6(u128::from(net), self.prefix) == mask_v6(u128::from(ip), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let network: IpAddr = addr
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid IP address in {:?}", s))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max)
+                .with_context(|| format!("Invalid prefix length in {:?}", s))?,
+            None => max,
+        };
+
+        Ok(Self { network, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn mask_v4(value: u32, prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        value & (!0u32 << (32 - prefix as u32))
+    }
+}
+
+fn mask_v6(value: u128, prefix: u8) -> u128 {
+    if prefix == 0 {
+        0
+    } else {
+        value & (!0u128 << (128 - prefix as u32))
+    }
+}
+
+/// Unwraps the IPv4 address an IPv6 one carries, so that each of these is
+/// checked as `10.0.0.1`: IPv4-mapped `::ffff:10.0.0.1`, IPv4-compatible
+/// `::10.0.0.1`, NAT64 `64:ff9b::10.0.0.1` and 6to4 `2002:a00:1::`. On a
+/// dual-stack or NAT64 network each reaches the IPv4 host. `::` and `::1`
+/// stay the IPv6 unspecified and loopback addresses.
+fn normalize(ip: IpAddr) -> IpAddr {
+    let IpAddr::V6(v6) = ip else {
+        return ip;
+    };
+    if let Some(v4) = v6.to_ipv4_mapped() {
+        return IpAddr::V4(v4);
+    }
+    let last = Ipv4Addr::from(u128::from(v6) as u32);
+    match v6.segments() {
+        [0, 0, 0, 0, 0, 0, _, _] if u128::from(v6) > 1 => IpAddr::V4(last),
+        [0x64, 0xff9b, 0, 0, 0, 0, _, _] => IpAddr::V4(last),
+        [0x2002, high, low, ..] => IpAddr::V4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low))),
+        _ => ip,
+    }
+}
+
+/// Decides whether a resolved address may be contacted
+#[derive(Debug, Clone)]
+pub struct AddressGuard {
+    blocked: Vec<Cidr>,
+    private_ranges: Vec<(Cidr, &'static str)>,
+    allowed_private: Vec<Cidr>,
+    metadata: Vec<IpAddr>,
+}
+
+impl AddressGuard {
+    pub fn new(policy: &NetworkPolicy) -> Result<Self> {
+        let mut blocked = policy
+            .blocked_ips
+            .iter()
+            .map(|entry| entry.parse())
+            .collect::<Result<Vec<Cidr>>>()
+            .context("Invalid entry in blocked_ips")?;
+        for file in &policy.blocked_ip_lists {
+            blocked.extend(load_ip_list(Path::new(file))?);
+        }
+
+        let allowed_private = policy
+            .allowed_private_cidrs
+            .iter()
+            .map(|entry| entry.parse())
+            .collect::<Result<Vec<Cidr>>>()
+            .context("Invalid entry in allowed_private_cidrs")?;
+
+        let private_ranges = if policy.block_private_addresses {
+            PRIVATE_RANGES
+                .iter()
+                .map(|(cidr, label)| (cidr.parse().expect("valid built-in range"), *label))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let metadata = if policy.dangerously_allow_metadata_endpoints {
+            vec![]
+        } else {
+            METADATA_ADDRESSES
+                .iter()
+                .map(|ip| ip.parse().expect("valid built-in address"))
+                .collect()
+        };
+
+        Ok(Self {
+            blocked,
+            private_ranges,
+            allowed_private,
+            metadata,
+        })
+    }
+
+    pub fn check(&self, ip: IpAddr) -> Result<(), Denial> {
+        // Ranges are matched against both forms, so a blocked IPv6 range
+        // such as 2002::/16 still covers the addresses in it
+        let (raw, ip) = (ip, normalize(ip));
+
+        if self.metadata.contains(&ip) {
+            return Err(Denial::new(
+                "metadata_endpoint",
+                format!("Address {} is a cloud metadata endpoint", ip),
+            ));
+        }
+
+        if let Some(cidr) = self.blocked.iter().find(|c| c.contains(&raw)) {
+            return Err(Denial::new(
+                "ip_blocked",
+                format!("Address {} is in blocked range {}", ip, cidr),
+            ));
+        }
+
+        if let Some((cidr, label)) = self.private_ranges.iter().find(|(c, _)| c.contains(&raw)) {
+            if !self.allowed_private.iter().any(|c| c.contains(&raw)) {
+                return Err(Denial::new(
+                    "private_address",
+                    format!("Address {} is in {} range {}", ip, label, cidr),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks every address; one bad record fails the whole set so a
+    /// mixed DNS answer cannot be used to reach an internal host.
+    pub fn check_all<'a, I: IntoIterator<Item = &'a IpAddr>>(&self, ips: I) -> Result<(), Denial> {
+        ips.into_iter().try_for_each(|ip| self.check(*ip))
+    }
+
+    /// Resolves `host` and checks every returned address
+    pub async fn resolve(&self, host: &str) -> io::Result<Result<Vec<IpAddr>, Denial>> {
+        let ips = resolve_host(host).await?;
+        Ok(self.check_all(&ips).map(|_| ips))
+    }
+}
+
+/// The ranges in a `blocked_ip_lists` file. Blank lines and `#` comments
+/// are skipped; any other line that is not an address or CIDR fails the
+/// whole file.
+fn load_ip_list(path: &Path) -> Result<Vec<Cidr>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read blocked_ip_lists file {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            (!entry.is_empty()).then_some((i + 1, entry))
+        })
+        .map(|(line, entry)| {
+            entry
+                .parse()
+                .with_context(|| format!("Invalid entry on line {} of blocked_ip_lists file {}", line, path.display()))
+        })
+        .collect()
+}
+
+pub async fn resolve_host(host: &str) -> io::Result<Vec<IpAddr>> {
+    // IPv6 literals from URIs keep their brackets
+    let bare = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok(vec![ip]);
+    }
+
+    let mut ips: Vec<IpAddr> = tokio::net::lookup_host((bare, 0))
+        .await?
+        .map(|addr| addr.ip())
+        .collect();
+    ips.dedup();
+    Ok(ips)
+}
+
+/// Raised by [`GuardedResolver`] when a lookup returns a forbidden address,
+/// e.g. because the record changed after the request was checked.
+#[derive(Debug, thiserror::Error)]
+#[error("{}", .0.reason)]
+pub struct AddressBlocked(pub Denial);
+
+/// DNS resolver for the HTTP client that only hands out vetted addresses,
+/// so the connection goes to the same addresses the policy approved.
+#[derive(Clone)]
+pub struct GuardedResolver {
+    guard: Arc<AddressGuard>,
+}
+
+impl GuardedResolver {
+    pub fn new(guard: Arc<AddressGuard>) -> Self {
+        Self { guard }
+    }
+}
+
+impl tower_service::Service<Name> for GuardedResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let guard = self.guard.clone();
+        Box::pin(async move {
+            let ips = guard.resolve(name.as_str()).await?.map_err(AddressBlocked)?;
+            let addrs: Vec<SocketAddr> = ips.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn guard(policy: NetworkPolicy) -> AddressGuard {
+        AddressGuard::new(&policy).unwrap()
+    }
+
+    #[test]
+    fn test_cidr_parsing_and_contains() {
+        let cidr: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains(&ip("10.255.1.2")));
+        assert!(!cidr.contains(&ip("11.0.0.1")));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&ip("8.8.8.8")));
+
+        let single: Cidr = "1.2.3.4".parse().unwrap();
+        assert!(single.contains(&ip("1.2.3.4")));
+        assert!(!single.contains(&ip("1.2.3.5")));
+
+        assert!("10.0.0.999".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fc00::/7".parse::<Cidr>().unwrap().contains(&ip("fd12::1")));
+    }
+
+    #[test]
+    fn test_private_ranges_blocked_by_default() {
+        let guard = guard(NetworkPolicy::default());
+        for addr in ["10.1.2.3", "127.0.0.1", "192.168.1.1", "172.20.0.1", "100.64.0.1", "::1", "fd00::1"] {
+            let denial = guard.check(ip(addr)).unwrap_err();
+            assert_eq!(denial.violation, "private_address", "{}", addr);
+            assert!(denial.reason.contains(addr));
+        }
+        assert!(guard.check(ip("93.184.216.34")).is_ok());
+    }
+
+    #[test]
+    fn test_ipv4_mapped_addresses_are_unwrapped() {
+        let guard = guard(NetworkPolicy::default());
+        let denial = guard.check(ip("::ffff:10.0.0.1")).unwrap_err();
+        assert_eq!(denial.violation, "private_address");
+        assert!(denial.reason.contains("10.0.0.0/8"));
+    }
+
+    #[test]
+    fn test_embedded_ipv4_addresses_are_unwrapped() {
+        let strict = guard(NetworkPolicy {
+            block_private_addresses: false,
+            ..Default::default()
+        });
+        let private = guard(NetworkPolicy::default());
+        // IPv4-mapped, IPv4-compatible, NAT64 and 6to4
+        for (metadata, internal) in [
+            ("::ffff:169.254.169.254", "::ffff:10.0.0.1"),
+            ("::a9fe:a9fe", "::a00:1"),
+            ("64:ff9b::a9fe:a9fe", "64:ff9b::a00:1"),
+            ("2002:a9fe:a9fe::", "2002:a00:1::"),
+        ] {
+            assert_eq!(strict.check(ip(metadata)).unwrap_err().violation, "metadata_endpoint", "{}", metadata);
+            let denial = private.check(ip(internal)).unwrap_err();
+            assert_eq!(denial.violation, "private_address", "{}", internal);
+            assert!(denial.reason.contains("10.0.0.1 is in RFC1918 private range 10.0.0.0/8"), "{}", denial.reason);
+        }
+        assert!(private.check(ip("64:ff9b::5db8:d822")).is_ok());
+        assert!(private.check(ip("2002:5db8:d822::1")).is_ok());
+        assert!(private.check(ip("::1")).unwrap_err().reason.contains("loopback"));
+        assert!(private.check(ip("::")).unwrap_err().reason.contains("unspecified"));
+
+        // IPv6 ranges still match the addresses they hold
+        let blocked = guard(NetworkPolicy {
+            blocked_ips: vec!["2002::/16".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(blocked.check(ip("2002:5db8:d822::1")).unwrap_err().violation, "ip_blocked");
+    }
+
+    #[test]
+    fn test_allowed_private_cidrs() {
+        let guard = guard(NetworkPolicy {
+            allowed_private_cidrs: vec!["10.20.0.0/16".to_string()],
+            ..Default::default()
+        });
+        assert!(guard.check(ip("10.20.3.4")).is_ok());
+        assert!(guard.check(ip("10.21.3.4")).is_err());
+    }
+
+    #[test]
+    fn test_metadata_endpoints_always_denied() {
+        let strict = guard(NetworkPolicy {
+            block_private_addresses: false,
+            allowed_private_cidrs: vec!["169.254.0.0/16".to_string()],
+            ..Default::default()
+        });
+        let denial = strict.check(ip("169.254.169.254")).unwrap_err();
+        assert_eq!(denial.violation, "metadata_endpoint");
+        assert!(strict.check(ip("10.0.0.1")).is_ok());
+
+        let permissive = guard(NetworkPolicy {
+            block_private_addresses: false,
+            dangerously_allow_metadata_endpoints: true,
+            ..Default::default()
+        });
+        assert!(permissive.check(ip("169.254.169.254")).is_ok());
+    }
+
+    #[test]
+    fn test_blocked_ips() {
+        let guard = guard(NetworkPolicy {
+            blocked_ips: vec!["203.0.113.0/24".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(guard.check(ip("203.0.113.9")).unwrap_err().violation, "ip_blocked");
+        assert!(AddressGuard::new(&NetworkPolicy {
+            blocked_ips: vec!["not-an-ip".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_blocked_ip_lists() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let list = dir.path().join("cloud.txt");
+        fs::write(&list, "# Provider ranges\n198.51.100.0/24\n\n2001:db8::/32  # v6\n192.0.2.1\n").unwrap();
+        let policy = |list: &Path| NetworkPolicy {
+            blocked_ips: vec!["203.0.113.0/24".to_string()],
+            blocked_ip_lists: vec![list.display().to_string()],
+            ..Default::default()
+        };
+        let guard = guard(policy(&list));
+        for blocked in ["198.51.100.7", "2001:db8::1", "192.0.2.1", "203.0.113.9"] {
+            assert_eq!(guard.check(ip(blocked)).unwrap_err().violation, "ip_blocked", "{}", blocked);
+        }
+        assert!(guard.check(ip("192.0.2.2")).is_ok());
+
+        fs::write(&list, "198.51.100.0/24\n198.51.100.0/33\n").unwrap();
+        let err = format!("{:#}", AddressGuard::new(&policy(&list)).unwrap_err());
+        assert!(err.contains("line 2 of blocked_ip_lists file"), "{}", err);
+        assert!(AddressGuard::new(&policy(&dir.path().join("missing.txt"))).is_err());
+    }
+
+    #[test]
+    fn test_mixed_answers_fail_closed() {
+        let guard = guard(NetworkPolicy::default());
+        let answers = [ip("93.184.216.34"), ip("10.0.0.5")];
+        assert!(guard.check_all(&answers).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_literal() {
+        let guard = guard(NetworkPolicy::default());
+        let result = guard.resolve("[::1]").await.unwrap();
+        assert_eq!(result.unwrap_err().violation, "private_address");
+    }
+}
@@ -0,0 +1,128 @@
+use crate::policy::rules::{Candidate, Decision, Effect, RuleSet, Specificity};
+
+/// Matches hostnames against domain entries from the policy.
+///
+/// Entries are either exact hostnames (`api.github.com`) or wildcards
+/// (`*.github.com`) that match any subdomain but not the apex itself.
+/// Comparison is case-insensitive and ignores a trailing dot.
+#[derive(Debug, Clone, Default)]
+pub struct DomainMatcher {
+    rules: RuleSet<Entry>,
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+    Exact(String),
+    // Stored with the leading dot, e.g. ".github.com"
+    Subdomain(String),
+}
+
+impl Entry {
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            Entry::Exact(name) => name == host,
+            Entry::Subdomain(suffix) => host.ends_with(suffix.as_str()),
+        }
+    }
+}
+
+impl DomainMatcher {
+    /// `entries` of the policy list `list`, named in [`explain`](Self::explain)
+    pub fn new<S: AsRef<str>>(list: &'static str, entries: &[S]) -> Self {
+        let mut rules = RuleSet::default();
+        for entry in entries {
+            let normalized = normalize(entry.as_ref());
+            let specificity = Specificity::domain(&normalized);
+            let matcher = match normalized.strip_prefix("*.") {
+                Some(suffix) => Entry::Subdomain(format!(".{}", suffix)),
+                None => Entry::Exact(normalized),
+            };
+            rules.push(Candidate::new(list, entry.as_ref(), Effect::Allow, specificity), matcher);
+        }
+
+        Self { rules }
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        self.find(host).is_some()
+    }
+
+    /// Returns the position of the matching entry in the original list.
+    /// The entry with the most labels wins, so an exact name outranks a
+    /// wildcard covering it and `*.api.example.com` outranks `*.example.com`.
+    pub fn find(&self, host: &str) -> Option<usize> {
+        let host = normalize(host);
+        if host.is_empty() {
+            return None;
+        }
+        self.rules.winner(|entry| entry.matches(&host)).map(|(index, _, _)| index)
+    }
+
+    /// Every entry matching `host`, the deciding one first
+    pub fn explain(&self, host: &str) -> Decision {
+        let host = normalize(host);
+        if host.is_empty() {
+            return Decision::default();
+        }
+        self.rules.evaluate(|entry| entry.matches(&host))
+    }
+}
+
+/// Lowercased, without surrounding space or a trailing dot
+pub fn normalize(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exact_and_wildcard_entries() {
+        let matcher = DomainMatcher::new("network.allowed_domains", &["api.anthropic.com", "*.github.com"]);
+        assert!(matcher.matches("api.anthropic.com"));
+        assert!(matcher.matches("API.Anthropic.com."));
+        assert!(matcher.matches("api.github.com"));
+        assert!(!matcher.matches("github.com"));
+        assert!(!matcher.matches("evilgithub.com"));
+        assert!(!matcher.matches("anthropic.com"));
+        assert!(!matcher.matches(""));
+    }
+
+    #[test]
+    fn test_find_prefers_exact_entries() {
+        let matcher = DomainMatcher::new("network.allowed_domains", &["*.example.com", "api.example.com"]);
+        assert_eq!(matcher.find("api.example.com"), Some(1));
+        assert_eq!(matcher.find("www.example.com"), Some(0));
+        assert_eq!(matcher.find("example.org"), None);
+    }
+
+    /// Locks in [`RuleSet`]'s precedence for domains
+    #[test]
+    fn test_precedence_golden() {
+        let matcher = DomainMatcher::new(
+            "network.allowed_domains",
+            &["*.example.com", "*.api.example.com", "v1.api.example.com", "*.api.example.com"],
+        );
+        let cases: [(&str, Option<&str>, &[&str]); 5] = [
+            (
+                "v1.api.example.com",
+                Some("v1.api.example.com"),
+                &["*.api.example.com", "*.api.example.com", "*.example.com"],
+            ),
+            ("v2.api.example.com", Some("*.api.example.com"), &["*.api.example.com", "*.example.com"]),
+            ("api.example.com", Some("*.example.com"), &[]),
+            ("www.example.com", Some("*.example.com"), &[]),
+            ("example.com", None, &[]),
+        ];
+        for (host, winner, losers) in cases {
+            let decision = matcher.explain(host);
+            assert_eq!(decision.winner.as_ref().map(|c| c.entry.as_str()), winner, "{}", host);
+            let lost: Vec<&str> = decision.losers.iter().map(|c| c.entry.as_str()).collect();
+            assert_eq!(lost, losers, "{}", host);
+            assert_eq!(decision.allowed(), matcher.matches(host), "{}", host);
+        }
+        // The first of two equal entries decides
+        assert_eq!(matcher.find("v2.api.example.com"), Some(1));
+    }
+}
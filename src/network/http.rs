@@ -0,0 +1,614 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, Uri};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::address::{AddressBlocked, AddressGuard, GuardedResolver};
+use super::domain::DomainMatcher;
+use super::proxy::{ProxyConfig, ProxyConnector, ProxyError, ProxyRouter, ProxyScheme};
+use super::{check_input_length, Denial};
+use crate::policy::{EnforcementPolicy, HttpPolicy, NetworkPolicy};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Headers a caller may not set: the daemon derives them from the URL and
+/// body, or they are meant for the proxy or a single hop rather than the
+/// server, so forwarding them could smuggle a second request or a proxy
+/// credential
+const RESERVED_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+type DirectClient = Client<HttpConnector<GuardedResolver>, Full<Bytes>>;
+type ProxiedClient = Client<ProxyConnector, Full<Bytes>>;
+
+/// A request that passed all HTTP policy checks
+#[derive(Debug)]
+pub struct HttpPermit {
+    pub uri: Uri,
+    pub method: Method,
+    pub max_response_bytes: usize,
+    pub via_proxy: bool,
+}
+
+/// Effective limits for a single domain after applying overrides
+struct DomainLimits<'a> {
+    allowed_methods: &'a [String],
+    blocked_request_headers: &'a [String],
+    max_request_body_kb: u64,
+    max_response_body_mb: u64,
+}
+
+/// Checks outbound HTTP requests against `[network]` and `[network.http]`
+pub struct HttpGuard {
+    allowed_domains: DomainMatcher,
+    trusted_domains: DomainMatcher,
+    override_domains: DomainMatcher,
+    proxy_router: ProxyRouter,
+    address_guard: Arc<AddressGuard>,
+    policy: HttpPolicy,
+    max_domain_length: usize,
+}
+
+impl HttpGuard {
+    pub fn new(policy: &NetworkPolicy, address_guard: Arc<AddressGuard>) -> Self {
+        let override_domains: Vec<&str> = policy
+            .http
+            .overrides
+            .iter()
+            .map(|o| o.domain.as_str())
+            .collect();
+
+        Self {
+            allowed_domains: DomainMatcher::new("network.allowed_domains", &policy.allowed_domains),
+            trusted_domains: DomainMatcher::new("network.http.trusted_domains", &policy.http.trusted_domains),
+            override_domains: DomainMatcher::new("network.http.overrides", &override_domains),
+            proxy_router: ProxyRouter::new(policy),
+            address_guard,
+            policy: policy.http.clone(),
+            max_domain_length: EnforcementPolicy::default().max_domain_length,
+        }
+    }
+
+    /// Denies hosts over `[enforcement] max_domain_length`, instead of the
+    /// default
+    pub fn with_max_domain_length(mut self, max: usize) -> Self {
+        self.max_domain_length = max;
+        self
+    }
+
+    pub fn check(
+        &self,
+        url: &str,
+        method: &str,
+        headers: &HashMap<String, String>,
+        body_len: usize,
+    ) -> Result<HttpPermit, Denial> {
+        let uri: Uri = url
+            .parse()
+            .map_err(|e| Denial::new("invalid_url", format!("Invalid URL {:?}: {}", url, e)))?;
+
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            _ => {
+                return Err(Denial::new(
+                    "invalid_url",
+                    format!("Only http and https URLs are supported: {}", url),
+                ))
+            }
+        }
+
+        let host = uri
+            .host()
+            .ok_or_else(|| Denial::new("invalid_url", format!("URL has no host: {}", url)))?
+            .to_string();
+        check_input_length("Host", &host, "max_domain_length", self.max_domain_length)?;
+
+        if !self.allowed_domains.matches(&host) {
+            return Err(Denial::new(
+                "domain_not_allowed",
+                format!("Domain not in allowed list: {}", host),
+            ));
+        }
+
+        if let Some(name) = headers.keys().find(|name| RESERVED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name))) {
+            return Err(Denial::new(
+                "reserved_header",
+                format!("Header {} may not be set on a request", name),
+            ));
+        }
+
+        let limits = self.limits_for(&host);
+
+        let method = if method.is_empty() { "GET" } else { method };
+        let method_allowed = limits
+            .allowed_methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method));
+        if !method_allowed {
+            return Err(Denial::new(
+                "method_not_allowed",
+                format!("HTTP method {} not allowed for {}", method, host),
+            ));
+        }
+
+        if !self.trusted_domains.matches(&host) {
+            for name in headers.keys() {
+                let blocked = limits
+                    .blocked_request_headers
+                    .iter()
+                    .any(|h| h.eq_ignore_ascii_case(name));
+                if blocked {
+                    return Err(Denial::new(
+                        "header_not_allowed",
+                        format!("Header {} may only be sent to trusted domains", name),
+                    ));
+                }
+            }
+        }
+
+        let max_request_bytes = limits.max_request_body_kb.saturating_mul(1024);
+        if body_len as u64 > max_request_bytes {
+            return Err(Denial::new(
+                "request_body_too_large",
+                format!(
+                    "Request body is {} bytes, limit is {} KB",
+                    body_len, limits.max_request_body_kb
+                ),
+            ));
+        }
+
+        let via_proxy = self.proxy_router.use_proxy(&host)?;
+
+        let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| Denial::new("method_not_allowed", format!("Invalid HTTP method: {}", method)))?;
+        let max_response_bytes = limits
+            .max_response_body_mb
+            .saturating_mul(1024 * 1024)
+            .try_into()
+            .unwrap_or(usize::MAX);
+
+        Ok(HttpPermit {
+            uri,
+            method,
+            max_response_bytes,
+            via_proxy,
+        })
+    }
+
+    /// Resolves the destination and rejects private or blocked addresses.
+    ///
+    /// Direct requests must resolve. Proxied requests are resolved on a
+    /// best-effort basis since the proxy performs its own lookup; literal
+    /// IP destinations are always checked.
+    pub async fn vet_destination(&self, permit: &HttpPermit) -> Result<(), Denial> {
+        let host = permit.uri.host().unwrap_or_default();
+        match self.address_guard.resolve(host).await {
+            Ok(result) => result.map(|_| ()),
+            Err(_) if permit.via_proxy => Ok(()),
+            Err(e) => Err(Denial::new(
+                "dns_resolution_failed",
+                format!("Could not resolve {}: {}", host, e),
+            )),
+        }
+    }
+
+    fn limits_for(&self, host: &str) -> DomainLimits<'_> {
+        let mut limits = DomainLimits {
+            allowed_methods: &self.policy.allowed_methods,
+            blocked_request_headers: &self.policy.blocked_request_headers,
+            max_request_body_kb: self.policy.max_request_body_kb,
+            max_response_body_mb: self.policy.max_response_body_mb,
+        };
+
+        if let Some(index) = self.override_domains.find(host) {
+            let entry = &self.policy.overrides[index];
+            if let Some(methods) = &entry.allowed_methods {
+                limits.allowed_methods = methods;
+            }
+            if let Some(headers) = &entry.blocked_request_headers {
+                limits.blocked_request_headers = headers;
+            }
+            if let Some(kb) = entry.max_request_body_kb {
+                limits.max_request_body_kb = kb;
+            }
+            if let Some(mb) = entry.max_response_body_mb {
+                limits.max_response_body_mb = mb;
+            }
+        }
+
+        limits
+    }
+}
+
+/// Response returned to the caller, possibly truncated
+#[derive(Debug)]
+pub struct FetchedResponse {
+    pub status_code: u16,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    pub truncated: bool,
+}
+
+/// Classifies a transport failure from [`HttpClient::send`] so callers can
+/// tell proxy problems apart from upstream errors.
+pub fn error_code(err: &anyhow::Error) -> &'static str {
+    for cause in err.chain() {
+        if let Some(proxy) = cause.downcast_ref::<ProxyError>() {
+            return proxy.code();
+        }
+        if let Some(blocked) = cause.downcast_ref::<AddressBlocked>() {
+            return blocked.0.violation;
+        }
+    }
+    "upstream_error"
+}
+
+/// Reachability of the configured proxy, reported by `get_status`
+#[derive(Debug, Clone)]
+pub struct ProxyHealth {
+    pub url: String,
+    pub reachable: bool,
+    pub error: Option<String>,
+}
+
+/// Outbound HTTP client used by the `http_request` RPC
+pub struct HttpClient {
+    direct: DirectClient,
+    proxied: Option<(Arc<ProxyConfig>, ProxiedClient)>,
+}
+
+impl HttpClient {
+    pub fn new(proxy: Option<ProxyConfig>, address_guard: Arc<AddressGuard>) -> Self {
+        let connector = HttpConnector::new_with_resolver(GuardedResolver::new(address_guard));
+        let direct = Client::builder(TokioExecutor::new()).build(connector);
+        let proxied = proxy.map(|config| {
+            let config = Arc::new(config);
+            let client = Client::builder(TokioExecutor::new())
+                .build(ProxyConnector::new(config.clone()));
+            (config, client)
+        });
+        Self { direct, proxied }
+    }
+
+    pub async fn proxy_health(&self) -> Option<ProxyHealth> {
+        let (config, _) = self.proxied.as_ref()?;
+        let result = config.probe().await;
+        Some(ProxyHealth {
+            url: config.display_url(),
+            reachable: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        })
+    }
+
+    pub async fn send(
+        &self,
+        permit: HttpPermit,
+        headers: &HashMap<String, String>,
+        body: Vec<u8>,
+    ) -> Result<FetchedResponse> {
+        if permit.uri.scheme_str() == Some("https") {
+            anyhow::bail!("HTTPS requests are not supported by this build");
+        }
+
+        let mut builder = Request::builder().method(permit.method).uri(permit.uri);
+        for (name, value) in headers {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        let proxy = if permit.via_proxy {
+            let (config, client) = self
+                .proxied
+                .as_ref()
+                .context("Request routed to a proxy but none is configured")?;
+            if config.scheme == ProxyScheme::Http {
+                if let Some(auth) = config.authorization() {
+                    builder = builder.header("proxy-authorization", auth);
+                }
+            }
+            Some((config, client))
+        } else {
+            None
+        };
+
+        let request = builder
+            .body(Full::new(Bytes::from(body)))
+            .context("Failed to build HTTP request")?;
+
+        let pending = async {
+            match proxy {
+                Some((_, client)) => client.request(request).await,
+                None => self.direct.request(request).await,
+            }
+        };
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, pending)
+            .await
+            .context("HTTP request timed out")?
+            .context("HTTP request failed")?;
+
+        if let Some((config, _)) = proxy {
+            if config.scheme == ProxyScheme::Http
+                && response.status() == hyper::StatusCode::PROXY_AUTHENTICATION_REQUIRED
+            {
+                return Err(ProxyError::AuthFailed.into());
+            }
+        }
+
+        let status_code = response.status().as_u16();
+        let mut response_headers: HashMap<String, String> = HashMap::new();
+        for (name, value) in response.headers() {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            response_headers
+                .entry(name.as_str().to_string())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                })
+                .or_insert(value);
+        }
+
+        // Stream the body so an oversized response is never fully buffered
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        let mut truncated = false;
+        while let Some(frame) = body.frame().await {
+            let frame = frame.context("Failed to read HTTP response body")?;
+            if let Some(chunk) = frame.data_ref() {
+                let remaining = permit.max_response_bytes - data.len();
+                if chunk.len() > remaining {
+                    data.extend_from_slice(&chunk[..remaining]);
+                    truncated = true;
+                    break;
+                }
+                data.extend_from_slice(chunk);
+            }
+        }
+
+        Ok(FetchedResponse {
+            status_code,
+            headers: response_headers,
+            body: data,
+            truncated,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::HttpOverride;
+
+    fn create_test_policy() -> NetworkPolicy {
+        NetworkPolicy {
+            allowed_domains: vec!["api.example.com".to_string(), "*.trusted.dev".to_string()],
+            http: HttpPolicy {
+                trusted_domains: vec!["*.trusted.dev".to_string()],
+                max_request_body_kb: 1,
+                overrides: vec![HttpOverride {
+                    domain: "api.example.com".to_string(),
+                    allowed_methods: Some(vec!["GET".to_string(), "PUT".to_string()]),
+                    blocked_request_headers: None,
+                    max_request_body_kb: None,
+                    max_response_body_mb: Some(1),
+                }],
+                ..HttpPolicy::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn test_address_guard() -> Arc<AddressGuard> {
+        Arc::new(AddressGuard::new(&NetworkPolicy::default()).unwrap())
+    }
+
+    fn test_guard() -> HttpGuard {
+        HttpGuard::new(&create_test_policy(), test_address_guard())
+    }
+
+    fn violation(result: Result<HttpPermit, Denial>) -> &'static str {
+        result.unwrap_err().violation
+    }
+
+    #[test]
+    fn test_allowed_request() {
+        let guard = test_guard();
+        let permit = guard
+            .check("http://api.example.com/v1", "get", &HashMap::new(), 0)
+            .unwrap();
+        assert_eq!(permit.method, Method::GET);
+        assert_eq!(permit.max_response_bytes, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_domain_not_allowed() {
+        let guard = test_guard();
+        let result = guard.check("http://evil.com/", "GET", &HashMap::new(), 0);
+        assert_eq!(violation(result), "domain_not_allowed");
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        let guard = test_guard();
+        assert_eq!(violation(guard.check("not a url", "GET", &HashMap::new(), 0)), "invalid_url");
+        assert_eq!(
+            violation(guard.check("ftp://api.example.com/", "GET", &HashMap::new(), 0)),
+            "invalid_url"
+        );
+    }
+
+    #[test]
+    fn test_method_override() {
+        let guard = test_guard();
+        assert!(guard
+            .check("http://api.example.com/", "PUT", &HashMap::new(), 0)
+            .is_ok());
+        let result = guard.check("http://api.example.com/", "POST", &HashMap::new(), 0);
+        assert_eq!(violation(result), "method_not_allowed");
+        let result = guard.check("http://a.trusted.dev/", "DELETE", &HashMap::new(), 0);
+        assert_eq!(violation(result), "method_not_allowed");
+    }
+
+    #[test]
+    fn test_blocked_header_unless_trusted() {
+        let guard = test_guard();
+        let mut headers = HashMap::new();
+        headers.insert("authorization".to_string(), "Bearer secret".to_string());
+
+        let result = guard.check("http://api.example.com/", "GET", &headers, 0);
+        assert_eq!(violation(result), "header_not_allowed");
+        assert!(guard.check("http://a.trusted.dev/", "GET", &headers, 0).is_ok());
+    }
+
+    #[test]
+    fn test_reserved_headers_are_refused_for_any_domain() {
+        let guard = test_guard();
+        for name in ["Host", "proxy-authorization", "Transfer-Encoding", "connection"] {
+            let headers = HashMap::from([(name.to_string(), "x".to_string())]);
+            for url in ["http://api.example.com/", "http://a.trusted.dev/"] {
+                let result = guard.check(url, "GET", &headers, 0);
+                assert_eq!(violation(result), "reserved_header", "{} to {}", name, url);
+            }
+        }
+        let headers = HashMap::from([("accept".to_string(), "*/*".to_string())]);
+        assert!(guard.check("http://api.example.com/", "GET", &headers, 0).is_ok());
+    }
+
+    #[test]
+    fn test_request_body_limit() {
+        let guard = test_guard();
+        assert!(guard
+            .check("http://api.example.com/", "GET", &HashMap::new(), 1024)
+            .is_ok());
+        let result = guard.check("http://api.example.com/", "GET", &HashMap::new(), 1025);
+        assert_eq!(violation(result), "request_body_too_large");
+    }
+
+    #[tokio::test]
+    async fn test_response_truncated_at_limit() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await;
+            let body = vec![b'x'; 4096];
+            let head = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", body.len());
+            let _ = socket.write_all(head.as_bytes()).await;
+            let _ = socket.write_all(&body).await;
+        });
+
+        let permit = HttpPermit {
+            uri: format!("http://{}/big", addr).parse().unwrap(),
+            method: Method::GET,
+            max_response_bytes: 100,
+            via_proxy: false,
+        };
+        let response = HttpClient::new(None, test_address_guard())
+            .send(permit, &HashMap::new(), vec![])
+            .await
+            .unwrap();
+
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body.len(), 100);
+        assert!(response.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_request_through_http_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            let _ = socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .await;
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+
+        std::env::set_var("OPENCLAW_TEST_HTTP_PROXY_AUTH", "agent:pw");
+        let config = ProxyConfig::from_policy(&NetworkPolicy {
+            proxy_url: Some(format!("http://127.0.0.1:{}", port)),
+            proxy_auth_env: Some("OPENCLAW_TEST_HTTP_PROXY_AUTH".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let permit = HttpPermit {
+            uri: "http://api.example.com/v1".parse().unwrap(),
+            method: Method::GET,
+            max_response_bytes: 1024,
+            via_proxy: true,
+        };
+        let response = HttpClient::new(config, test_address_guard())
+            .send(permit, &HashMap::new(), vec![])
+            .await
+            .unwrap();
+        assert_eq!(response.body, b"ok");
+
+        let seen = proxy.await.unwrap();
+        assert!(seen.starts_with("GET http://api.example.com/v1 HTTP/1.1"));
+        assert!(seen.to_ascii_lowercase().contains("proxy-authorization: basic"));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_proxy_error_code() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let config = ProxyConfig::parse(&format!("socks5://127.0.0.1:{}", port)).unwrap();
+        let permit = HttpPermit {
+            uri: "http://api.example.com/".parse().unwrap(),
+            method: Method::GET,
+            max_response_bytes: 1024,
+            via_proxy: true,
+        };
+        let err = HttpClient::new(Some(config), test_address_guard())
+            .send(permit, &HashMap::new(), vec![])
+            .await
+            .unwrap_err();
+        assert_eq!(error_code(&err), "proxy_unreachable");
+    }
+
+    #[tokio::test]
+    async fn test_vet_destination_rejects_private_literals() {
+        let guard = test_guard();
+        let permit = HttpPermit {
+            uri: "http://169.254.169.254/latest/meta-data".parse().unwrap(),
+            method: Method::GET,
+            max_response_bytes: 1024,
+            via_proxy: false,
+        };
+        let denial = guard.vet_destination(&permit).await.unwrap_err();
+        assert_eq!(denial.violation, "metadata_endpoint");
+
+        let permit = HttpPermit {
+            uri: "http://10.0.0.8/".parse().unwrap(),
+            via_proxy: true,
+            ..permit
+        };
+        let denial = guard.vet_destination(&permit).await.unwrap_err();
+        assert_eq!(denial.violation, "private_address");
+    }
+}
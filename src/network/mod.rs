@@ -1 +1,33 @@
 // Network security implementation
+pub mod address;
+pub mod connect;
+pub mod domain;
+pub mod http;
+pub mod proxy;
+pub mod quota;
+
+/// A policy denial carrying a machine-readable violation code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denial {
+    pub violation: &'static str,
+    pub reason: String,
+}
+
+impl Denial {
+    pub fn new(violation: &'static str, reason: String) -> Self {
+        Self { violation, reason }
+    }
+}
+
+/// Refuses `value` when it is over `max` bytes, the `[enforcement]`
+/// `setting`. Checked before `value` is matched against anything, so an
+/// oversized input costs no more than reading its length.
+pub fn check_input_length(what: &str, value: &str, setting: &str, max: usize) -> Result<(), Denial> {
+    if value.len() > max {
+        return Err(Denial::new(
+            "input_too_large",
+            format!("{} is {} bytes, over [enforcement] {} of {}", what, value.len(), setting, max),
+        ));
+    }
+    Ok(())
+}
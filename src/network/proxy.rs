@@ -0,0 +1,407 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use hyper::Uri;
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::rt::TokioIo;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+use super::domain::DomainMatcher;
+use super::Denial;
+use crate::policy::NetworkPolicy;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Failures talking to the proxy itself, as opposed to policy denials or
+/// errors from the upstream server.
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyError {
+    #[error("proxy unreachable: {0}")]
+    Unreachable(#[source] io::Error),
+    #[error("proxy authentication failed")]
+    AuthFailed,
+    #[error("proxy refused connection: {0}")]
+    Refused(String),
+    #[error("proxy protocol error: {0}")]
+    Protocol(String),
+}
+
+impl ProxyError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProxyError::Unreachable(_) => "proxy_unreachable",
+            ProxyError::AuthFailed => "proxy_auth_failed",
+            ProxyError::Refused(_) => "proxy_refused",
+            ProxyError::Protocol(_) => "proxy_protocol_error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyScheme {
+    Http,
+    Socks5,
+}
+
+/// Parsed `proxy_url` with credentials resolved from the environment
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    pub scheme: ProxyScheme,
+    pub host: String,
+    pub port: u16,
+    credentials: Option<(String, String)>,
+}
+
+impl ProxyConfig {
+    /// Parses a proxy URL without resolving credentials. Used both at policy
+    /// validation time and when building the client.
+    pub fn parse(url: &str) -> Result<Self> {
+        let uri: Uri = url
+            .parse()
+            .with_context(|| format!("Invalid proxy_url: {}", url))?;
+
+        let scheme = match uri.scheme_str() {
+            Some("http") => ProxyScheme::Http,
+            Some("socks5") | Some("socks5h") => ProxyScheme::Socks5,
+            Some("https") => anyhow::bail!("https proxies are not supported by this build; use http or socks5"),
+            _ => anyhow::bail!("proxy_url must use http or socks5: {}", url),
+        };
+
+        let authority = uri
+            .authority()
+            .with_context(|| format!("proxy_url has no host: {}", url))?;
+        if authority.as_str().contains('@') {
+            anyhow::bail!("proxy_url must not embed credentials; use proxy_auth_env");
+        }
+
+        let default_port = match scheme {
+            ProxyScheme::Http => 80,
+            ProxyScheme::Socks5 => 1080,
+        };
+
+        Ok(Self {
+            scheme,
+            host: authority.host().to_string(),
+            port: authority.port_u16().unwrap_or(default_port),
+            credentials: None,
+        })
+    }
+
+    /// Builds the proxy configuration from the policy, reading credentials
+    /// from the environment variable named by `proxy_auth_env`.
+    pub fn from_policy(policy: &NetworkPolicy) -> Result<Option<Self>> {
+        let Some(url) = &policy.proxy_url else {
+            return Ok(None);
+        };
+        let mut config = Self::parse(url)?;
+
+        if let Some(var) = &policy.proxy_auth_env {
+            let value = std::env::var(var)
+                .with_context(|| format!("Proxy credentials variable {} is not set", var))?;
+            let (user, password) = value
+                .split_once(':')
+                .with_context(|| format!("{} must have the form user:password", var))?;
+            config.credentials = Some((user.to_string(), password.to_string()));
+        }
+
+        Ok(Some(config))
+    }
+
+    /// Display form without credentials
+    pub fn display_url(&self) -> String {
+        let scheme = match self.scheme {
+            ProxyScheme::Http => "http",
+            ProxyScheme::Socks5 => "socks5",
+        };
+        format!("{}://{}:{}", scheme, self.host, self.port)
+    }
+
+    /// Value for the `Proxy-Authorization` header, for HTTP proxies
+    pub fn authorization(&self) -> Option<String> {
+        let (user, password) = self.credentials.as_ref()?;
+        let encoded =
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+        Some(format!("Basic {}", encoded))
+    }
+
+    /// Checks that the proxy accepts TCP connections
+    pub async fn probe(&self) -> Result<(), ProxyError> {
+        self.connect_tcp(PROBE_TIMEOUT).await.map(|_| ())
+    }
+
+    async fn connect_tcp(&self, timeout: Duration) -> Result<TcpStream, ProxyError> {
+        let addr = (self.host.as_str(), self.port);
+        match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
+            Ok(Ok(stream)) => Ok(stream),
+            Ok(Err(e)) => Err(ProxyError::Unreachable(e)),
+            Err(_) => Err(ProxyError::Unreachable(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection timed out",
+            ))),
+        }
+    }
+
+    async fn connect(&self, dst: &Uri) -> Result<ProxyStream, ProxyError> {
+        let mut stream = self.connect_tcp(CONNECT_TIMEOUT).await?;
+
+        match self.scheme {
+            // The client sends absolute-form requests to the proxy
+            ProxyScheme::Http => Ok(ProxyStream {
+                inner: stream,
+                absolute_form: true,
+            }),
+            ProxyScheme::Socks5 => {
+                let host = dst
+                    .host()
+                    .ok_or_else(|| ProxyError::Protocol("destination has no host".to_string()))?;
+                let port = dst.port_u16().unwrap_or(80);
+                self.socks5_handshake(&mut stream, host, port).await?;
+                Ok(ProxyStream {
+                    inner: stream,
+                    absolute_form: false,
+                })
+            }
+        }
+    }
+
+    /// SOCKS5 CONNECT (RFC 1928) with optional username/password auth (RFC 1929).
+    /// The hostname is sent unresolved so DNS happens at the proxy.
+    async fn socks5_handshake(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+    ) -> Result<(), ProxyError> {
+        let io_err = |e: io::Error| ProxyError::Protocol(e.to_string());
+
+        let method = if self.credentials.is_some() { 0x02 } else { 0x00 };
+        stream.write_all(&[0x05, 0x01, method]).await.map_err(io_err)?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await.map_err(io_err)?;
+        if reply[0] != 0x05 || reply[1] != method {
+            return Err(ProxyError::AuthFailed);
+        }
+
+        if let Some((user, password)) = &self.credentials {
+            if user.len() > 255 || password.len() > 255 {
+                return Err(ProxyError::Protocol("credentials too long".to_string()));
+            }
+            let mut auth = vec![0x01, user.len() as u8];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth).await.map_err(io_err)?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await.map_err(io_err)?;
+            if status[1] != 0x00 {
+                return Err(ProxyError::AuthFailed);
+            }
+        }
+
+        if host.len() > 255 {
+            return Err(ProxyError::Protocol("hostname too long".to_string()));
+        }
+        let mut request = vec![0x05, 0x01, 0x00, 0x03, host.len() as u8];
+        request.extend_from_slice(host.as_bytes());
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await.map_err(io_err)?;
+
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await.map_err(io_err)?;
+        if head[1] != 0x00 {
+            return Err(ProxyError::Refused(format!("SOCKS5 reply code {}", head[1])));
+        }
+
+        // Skip the bound address in the reply
+        let addr_len = match head[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await.map_err(io_err)?;
+                len[0] as usize
+            }
+            other => return Err(ProxyError::Protocol(format!("unknown address type {}", other))),
+        };
+        let mut rest = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut rest).await.map_err(io_err)?;
+
+        Ok(())
+    }
+}
+
+/// Decides whether a request goes through the proxy or directly
+pub struct ProxyRouter {
+    configured: bool,
+    require_proxy: bool,
+    exempt_domains: DomainMatcher,
+}
+
+impl ProxyRouter {
+    pub fn new(policy: &NetworkPolicy) -> Self {
+        Self {
+            configured: policy.proxy_url.is_some(),
+            require_proxy: policy.require_proxy,
+            exempt_domains: DomainMatcher::new("network.exempt_domains", &policy.exempt_domains),
+        }
+    }
+
+    /// Returns true if the request should be sent through the proxy
+    pub fn use_proxy(&self, host: &str) -> Result<bool, Denial> {
+        let direct = !self.configured || self.exempt_domains.matches(host);
+
+        if direct && self.require_proxy {
+            return Err(Denial::new(
+                "proxy_required",
+                format!("Direct connection to {} refused: require_proxy is set", host),
+            ));
+        }
+
+        Ok(!direct)
+    }
+}
+
+/// Connector that routes every connection through the configured proxy
+#[derive(Clone)]
+pub struct ProxyConnector {
+    config: Arc<ProxyConfig>,
+}
+
+impl ProxyConnector {
+    pub fn new(config: Arc<ProxyConfig>) -> Self {
+        Self { config }
+    }
+}
+
+impl tower_service::Service<Uri> for ProxyConnector {
+    type Response = TokioIo<ProxyStream>;
+    type Error = ProxyError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let config = self.config.clone();
+        Box::pin(async move { config.connect(&dst).await.map(TokioIo::new) })
+    }
+}
+
+/// TCP stream to the proxy, flagged so the client knows which request form to use
+pub struct ProxyStream {
+    inner: TcpStream,
+    absolute_form: bool,
+}
+
+impl Connection for ProxyStream {
+    fn connected(&self) -> Connected {
+        Connected::new().proxy(self.absolute_form)
+    }
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_policy() -> NetworkPolicy {
+        NetworkPolicy {
+            allowed_domains: vec!["api.example.com".to_string(), "internal.corp".to_string()],
+            blocked_ips: vec![],
+            max_connections: 10,
+            proxy_url: Some("http://proxy.corp:3128".to_string()),
+            exempt_domains: vec!["internal.corp".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_proxy_url() {
+        let config = ProxyConfig::parse("socks5://127.0.0.1").unwrap();
+        assert_eq!(config.scheme, ProxyScheme::Socks5);
+        assert_eq!(config.port, 1080);
+        assert_eq!(config.display_url(), "socks5://127.0.0.1:1080");
+
+        assert!(ProxyConfig::parse("http://user:pw@proxy:3128").is_err());
+        assert!(ProxyConfig::parse("ftp://proxy:21").is_err());
+    }
+
+    #[test]
+    fn test_exempt_domains_bypass_proxy() {
+        let router = ProxyRouter::new(&create_test_policy());
+        assert!(router.use_proxy("api.example.com").unwrap());
+        assert!(!router.use_proxy("internal.corp").unwrap());
+    }
+
+    #[test]
+    fn test_require_proxy_refuses_direct() {
+        let router = ProxyRouter::new(&NetworkPolicy {
+            require_proxy: true,
+            ..create_test_policy()
+        });
+        assert!(router.use_proxy("api.example.com").unwrap());
+        let denial = router.use_proxy("internal.corp").unwrap_err();
+        assert_eq!(denial.violation, "proxy_required");
+    }
+
+    #[test]
+    fn test_credentials_from_env() {
+        std::env::set_var("OPENCLAW_TEST_PROXY_AUTH", "agent:s3cret");
+        let config = ProxyConfig::from_policy(&NetworkPolicy {
+            proxy_auth_env: Some("OPENCLAW_TEST_PROXY_AUTH".to_string()),
+            ..create_test_policy()
+        })
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.authorization().unwrap(), "Basic YWdlbnQ6czNjcmV0");
+        assert!(!config.display_url().contains("s3cret"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_unreachable_proxy() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let config = ProxyConfig::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+        let err = config.probe().await.unwrap_err();
+        assert_eq!(err.code(), "proxy_unreachable");
+    }
+}
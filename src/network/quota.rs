@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::domain::DomainMatcher;
+use super::Denial;
+use crate::daemon::state;
+use crate::policy::{DomainQuota, NetworkPolicy};
+
+const DAY_SECS: i64 = 86_400;
+
+/// Usage of one quota entry, as `get_quotas` reports it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub domain: String,
+    pub max_concurrent: u32,
+    pub in_flight: u32,
+    pub max_bytes_per_day: u64,
+    pub bytes_used: u64,
+    /// Unix timestamp at which `bytes_used` starts over
+    pub resets_at: i64,
+}
+
+/// What the state file holds: the day's byte counts by entry domain
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SavedUsage {
+    period_start: i64,
+    bytes: BTreeMap<String, u64>,
+}
+
+struct Usage {
+    period_start: i64,
+    /// In the order of the entries
+    in_flight: Vec<u32>,
+    bytes: Vec<u64>,
+}
+
+/// Holds `[[network.quotas]]` entries against HTTP requests and connection
+/// leases.
+///
+/// A request or lease takes a [`QuotaPermit`] before it starts, which
+/// counts towards `max_concurrent` until dropped; its bytes are added when
+/// it ends. A day's budget is checked before a request, so the request
+/// that crosses it still completes. Byte counts are saved to `state_file`
+/// on [`flush`](Self::flush), so a restart keeps them.
+pub struct DomainQuotas {
+    domains: DomainMatcher,
+    entries: Vec<DomainQuota>,
+    /// Seconds after midnight UTC at which a period starts
+    reset_offset: i64,
+    state_file: Option<PathBuf>,
+    changed: AtomicBool,
+    usage: Mutex<Usage>,
+}
+
+impl DomainQuotas {
+    pub fn new(policy: &NetworkPolicy, state_file: Option<PathBuf>, now: i64) -> Arc<Self> {
+        let domains: Vec<&str> = policy.quotas.iter().map(|quota| quota.domain.as_str()).collect();
+        let reset_offset = policy.quota_reset_hour_utc as i64 * 3600;
+        let period_start = period_start(now, reset_offset);
+        let mut bytes = vec![0; policy.quotas.len()];
+        let saved = state_file.as_deref().and_then(|path| state::load_json::<SavedUsage>(path, "quota usage"));
+        if let Some(saved) = saved {
+            if saved.period_start == period_start {
+                for (quota, used) in policy.quotas.iter().zip(&mut bytes) {
+                    *used = saved.bytes.get(&quota.domain).copied().unwrap_or(0);
+                }
+            }
+        }
+        Arc::new(Self {
+            domains: DomainMatcher::new("network.quotas", &domains),
+            entries: policy.quotas.clone(),
+            reset_offset,
+            state_file,
+            changed: AtomicBool::new(false),
+            usage: Mutex::new(Usage {
+                period_start,
+                in_flight: vec![0; policy.quotas.len()],
+                bytes,
+            }),
+        })
+    }
+
+    /// Admits one request or connection to `host`. `None` means no entry
+    /// covers the host.
+    pub fn acquire(self: &Arc<Self>, host: &str, now: i64) -> Result<Option<QuotaPermit>, Denial> {
+        let Some(index) = self.domains.find(host) else {
+            return Ok(None);
+        };
+        let quota = &self.entries[index];
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        self.roll(&mut usage, now);
+        if quota.max_concurrent > 0 && usage.in_flight[index] >= quota.max_concurrent {
+            return Err(Denial::new(
+                "quota_exceeded",
+                format!(
+                    "Quota for {}: {} requests and connections already in flight",
+                    quota.domain, quota.max_concurrent
+                ),
+            ));
+        }
+        if quota.max_bytes_per_day > 0 && usage.bytes[index] >= quota.max_bytes_per_day {
+            return Err(Denial::new(
+                "quota_exceeded",
+                format!(
+                    "Quota for {}: {} of {} bytes used today; resets at {}",
+                    quota.domain,
+                    usage.bytes[index],
+                    quota.max_bytes_per_day,
+                    UtcTime(usage.period_start + DAY_SECS)
+                ),
+            ));
+        }
+        usage.in_flight[index] += 1;
+        Ok(Some(QuotaPermit {
+            quotas: Arc::clone(self),
+            index,
+        }))
+    }
+
+    pub fn usage(&self, now: i64) -> Vec<QuotaUsage> {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        self.roll(&mut usage, now);
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, quota)| QuotaUsage {
+                domain: quota.domain.clone(),
+                max_concurrent: quota.max_concurrent,
+                in_flight: usage.in_flight[i],
+                max_bytes_per_day: quota.max_bytes_per_day,
+                bytes_used: usage.bytes[i],
+                resets_at: usage.period_start + DAY_SECS,
+            })
+            .collect()
+    }
+
+    fn add_bytes(&self, index: usize, bytes: u64, now: i64) {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        self.roll(&mut usage, now);
+        usage.bytes[index] = usage.bytes[index].saturating_add(bytes);
+        self.changed.store(true, Ordering::Relaxed);
+    }
+
+    fn release(&self, index: usize) {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        usage.in_flight[index] = usage.in_flight[index].saturating_sub(1);
+    }
+
+    fn roll(&self, usage: &mut Usage, now: i64) {
+        let start = period_start(now, self.reset_offset);
+        if start != usage.period_start {
+            usage.period_start = start;
+            usage.bytes.iter_mut().for_each(|bytes| *bytes = 0);
+        }
+    }
+
+    /// Saves the byte counts if traffic was added since the last save
+    pub fn flush(&self) -> Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        if !self.changed.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let saved = {
+            let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+            SavedUsage {
+                period_start: usage.period_start,
+                bytes: self.entries.iter().map(|quota| quota.domain.clone()).zip(usage.bytes.iter().copied()).collect(),
+            }
+        };
+        let saved = state::save_json(path, &saved);
+        if saved.is_err() {
+            self.changed.store(true, Ordering::Relaxed);
+        }
+        saved.with_context(|| format!("Failed to save quota usage to {}", path.display()))
+    }
+}
+
+/// A request or connection counted against a quota entry; dropping it
+/// ends the count
+pub struct QuotaPermit {
+    quotas: Arc<DomainQuotas>,
+    index: usize,
+}
+
+impl QuotaPermit {
+    /// Adds traffic to the entry's count for the day
+    pub fn add_bytes(&self, bytes: u64, now: i64) {
+        self.quotas.add_bytes(self.index, bytes, now);
+    }
+}
+
+impl Drop for QuotaPermit {
+    fn drop(&mut self) {
+        self.quotas.release(self.index);
+    }
+}
+
+impl fmt::Debug for QuotaPermit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("QuotaPermit").field(&self.quotas.entries[self.index].domain).finish()
+    }
+}
+
+/// The latest period boundary at or before `now`
+fn period_start(now: i64, reset_offset: i64) -> i64 {
+    now - (now - reset_offset).rem_euclid(DAY_SECS)
+}
+
+/// A Unix timestamp shown as `2024-03-01 00:00 UTC`
+struct UtcTime(i64);
+
+impl fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (days, secs) = (self.0.div_euclid(DAY_SECS), self.0.rem_euclid(DAY_SECS));
+        // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, secs / 3600, secs % 3600 / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // 2024-03-01 12:00 UTC
+    const NOON: i64 = 1_709_294_400;
+
+    fn quotas(reset_hour: u32, state_file: Option<&Path>) -> Arc<DomainQuotas> {
+        let policy = NetworkPolicy {
+            quotas: vec![
+                DomainQuota {
+                    domain: "api.openai.com".to_string(),
+                    max_concurrent: 2,
+                    max_bytes_per_day: 100,
+                },
+                DomainQuota {
+                    domain: "*.cdn.net".to_string(),
+                    max_concurrent: 0,
+                    max_bytes_per_day: 10,
+                },
+            ],
+            quota_reset_hour_utc: reset_hour,
+            ..Default::default()
+        };
+        DomainQuotas::new(&policy, state_file.map(Path::to_path_buf), NOON)
+    }
+
+    #[test]
+    fn test_concurrency_is_released_on_drop() {
+        let quotas = quotas(0, None);
+        let first = quotas.acquire("api.openai.com", NOON).unwrap().unwrap();
+        let _second = quotas.acquire("API.openai.com", NOON).unwrap().unwrap();
+        let denial = quotas.acquire("api.openai.com", NOON).unwrap_err();
+        assert_eq!(denial.violation, "quota_exceeded");
+        assert!(denial.reason.contains("2 requests and connections"));
+
+        drop(first);
+        assert!(quotas.acquire("api.openai.com", NOON).is_ok());
+        assert!(quotas.acquire("example.com", NOON).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_daily_bytes_reset_at_the_boundary() {
+        let quotas = quotas(6, None);
+        let permit = quotas.acquire("a.cdn.net", NOON).unwrap().unwrap();
+        permit.add_bytes(12, NOON);
+        drop(permit);
+
+        // Hosts under one wildcard share its budget
+        let denial = quotas.acquire("b.cdn.net", NOON).unwrap_err();
+        assert_eq!(denial.reason, "Quota for *.cdn.net: 12 of 10 bytes used today; resets at 2024-03-02 06:00 UTC");
+        let usage = &quotas.usage(NOON)[1];
+        assert_eq!((usage.bytes_used, usage.resets_at), (12, NOON + 18 * 3600));
+
+        assert!(quotas.acquire("b.cdn.net", NOON + 18 * 3600 - 1).is_err());
+        assert!(quotas.acquire("b.cdn.net", NOON + 18 * 3600).is_ok());
+        assert_eq!(quotas.usage(NOON + 18 * 3600)[1].bytes_used, 0);
+    }
+
+    #[test]
+    fn test_usage_survives_a_restart_within_the_day() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let state = dir.path().join("quotas.json");
+        let before = quotas(0, Some(&state));
+        before.acquire("api.openai.com", NOON).unwrap().unwrap().add_bytes(40, NOON);
+        before.flush().unwrap();
+
+        assert_eq!(quotas(0, Some(&state)).usage(NOON)[0].bytes_used, 40);
+        // Saved under a different reset hour, so from another period
+        assert_eq!(quotas(13, Some(&state)).usage(NOON)[0].bytes_used, 0);
+    }
+
+    #[test]
+    fn test_utc_time() {
+        assert_eq!(UtcTime(0).to_string(), "1970-01-01 00:00 UTC");
+        assert_eq!(UtcTime(NOON).to_string(), "2024-03-01 12:00 UTC");
+        assert_eq!(UtcTime(951_782_400).to_string(), "2000-02-29 00:00 UTC");
+    }
+}
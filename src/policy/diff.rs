@@ -0,0 +1,104 @@
+//! What changed between two policies, for the audit events of a reload
+//! or an edit
+
+use serde_json::Value;
+
+use super::parser::string_lists;
+use super::Policy;
+use crate::audit::sha256;
+
+/// SHA-256 of the policy as enforced, in hex; the same for two policies
+/// that decide everything the same way
+pub fn digest(policy: &Policy) -> String {
+    // Maps in the policy are ordered, so the serialization is stable
+    sha256::hex_digest(&serde_json::to_vec(policy).unwrap_or_default())
+}
+
+/// Entries added to or removed from each list, and the other settings
+/// whose value changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyDiff {
+    /// `(list, entry)`, e.g. `("network.allowed_domains", "a.com")`
+    pub added: Vec<(&'static str, String)>,
+    pub removed: Vec<(&'static str, String)>,
+    /// Keys like `process.max_memory_mb`
+    pub changed: Vec<String>,
+}
+
+impl PolicyDiff {
+    pub fn between(old: &Policy, new: &Policy) -> Self {
+        let mut diff = Self::default();
+        let (old_lists, new_lists) = (string_lists(old), string_lists(new));
+        for ((list, old), (_, new)) in old_lists.iter().zip(&new_lists) {
+            let entries = |from: &[&str], not_in: &[&str]| -> Vec<(&'static str, String)> {
+                from.iter().filter(|e| !not_in.contains(e)).map(|e| (*list, e.to_string())).collect()
+            };
+            diff.added.extend(entries(new, old));
+            diff.removed.extend(entries(old, new));
+        }
+        let lists: Vec<&str> = new_lists.iter().map(|(list, _)| *list).collect();
+        let (old, new) = (serde_json::to_value(old), serde_json::to_value(new));
+        if let (Ok(old), Ok(new)) = (old, new) {
+            changed_settings("", &old, &new, &lists, &mut diff.changed);
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// `list "entry"` for each of `entries`, comma-separated
+    pub fn describe(entries: &[(&'static str, String)]) -> String {
+        let entries: Vec<String> = entries.iter().map(|(list, entry)| format!("{} {:?}", list, entry)).collect();
+        entries.join(", ")
+    }
+}
+
+/// Keys under `prefix` whose value differs, other than `lists`, whose
+/// entries are compared one at a time instead
+fn changed_settings(prefix: &str, old: &Value, new: &Value, lists: &[&str], changed: &mut Vec<String>) {
+    if lists.contains(&prefix) || old == new {
+        return;
+    }
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        changed.push(prefix.to_string());
+        return;
+    };
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let key_prefix = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+        let (old, new) = (old.get(key).unwrap_or(&Value::Null), new.get(key).unwrap_or(&Value::Null));
+        changed_settings(&key_prefix, old, new, lists, changed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::examples::Profile;
+
+    #[test]
+    fn test_diff_lists_entries_and_settings() {
+        let old = Policy::example(Profile::Strict).unwrap();
+        let mut new = old.clone();
+        assert!(PolicyDiff::between(&old, &new).is_empty());
+        assert_eq!(digest(&old), digest(&new));
+
+        new.network.allowed_domains.push("a.example.com".into());
+        let removed = new.filesystem.allowed_read.remove(0);
+        new.process.max_memory_mb += 1;
+        new.audit.dedupe_window_secs += 1;
+        let diff = PolicyDiff::between(&old, &new);
+        assert_eq!(diff.added, [("network.allowed_domains", "a.example.com".to_string())]);
+        assert_eq!(diff.removed, [("filesystem.allowed_read", removed)]);
+        assert_eq!(diff.changed, ["audit.dedupe_window_secs", "process.max_memory_mb"]);
+        assert_ne!(digest(&old), digest(&new));
+        assert_eq!(
+            PolicyDiff::describe(&diff.added),
+            "network.allowed_domains \"a.example.com\""
+        );
+    }
+}
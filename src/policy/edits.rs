@@ -0,0 +1,258 @@
+//! Allow-list entries added or removed on a running daemon
+//!
+//! `AddPolicyEntry` and `RemovePolicyEntry` change the policy in memory.
+//! Those that ask to persist are also recorded in `[server]
+//! policy_edits_file`, which is applied on top of the policy each time it
+//! loads, so the change survives a restart or reload. The file holds the
+//! entries as enforced, with relative paths already resolved:
+//!
+//! ```toml
+//! [added]
+//! "filesystem.allowed_read" = ["/srv/data/new-project"]
+//!
+//! [removed]
+//! "network.allowed_domains" = ["old.example.com"]
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{CommandEntry, Policy};
+
+/// Lists the edit RPCs may change
+pub const EDITABLE_LISTS: &[&str] = &[
+    "filesystem.allowed_read",
+    "filesystem.allowed_write",
+    "network.allowed_domains",
+    "process.allowed_commands",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditAction {
+    Add,
+    Remove,
+}
+
+impl fmt::Display for EditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EditAction::Add => "add",
+            EditAction::Remove => "remove",
+        })
+    }
+}
+
+/// Why an edit was not made
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EditError {
+    #[error("{0:?} is not an editable list; expected one of {lists}", lists = EDITABLE_LISTS.join(", "))]
+    UnknownList(String),
+    #[error("{list} already has {entry:?}")]
+    AlreadyPresent { list: String, entry: String },
+    #[error("{list} has no entry {entry:?}; entries are matched as enforced, with relative paths resolved")]
+    NotFound { list: String, entry: String },
+}
+
+/// An editable list of `policy`
+enum List<'a> {
+    Strings(&'a mut Vec<String>),
+    /// Plain entries only; structured ones are left to the policy file
+    Commands(&'a mut Vec<CommandEntry>),
+}
+
+impl<'a> List<'a> {
+    fn of(policy: &'a mut Policy, name: &str) -> Result<Self, EditError> {
+        Ok(match name {
+            "filesystem.allowed_read" => List::Strings(&mut policy.filesystem.allowed_read),
+            "filesystem.allowed_write" => List::Strings(&mut policy.filesystem.allowed_write),
+            "network.allowed_domains" => List::Strings(&mut policy.network.allowed_domains),
+            "process.allowed_commands" => List::Commands(&mut policy.process.allowed_commands),
+            other => return Err(EditError::UnknownList(other.to_string())),
+        })
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            List::Strings(list) => list.len(),
+            List::Commands(list) => list.len(),
+        }
+    }
+
+    fn contains(&self, entry: &str) -> bool {
+        match self {
+            List::Strings(list) => list.iter().any(|e| e == entry),
+            List::Commands(list) => list.iter().any(|e| matches!(e, CommandEntry::Plain(c) if c == entry)),
+        }
+    }
+
+    fn push(&mut self, entry: &str) {
+        match self {
+            List::Strings(list) => list.push(entry.to_string()),
+            List::Commands(list) => list.push(CommandEntry::Plain(entry.to_string())),
+        }
+    }
+
+    /// Whether there was such an entry
+    fn remove(&mut self, entry: &str) -> bool {
+        let before = self.len();
+        match self {
+            List::Strings(list) => list.retain(|e| e != entry),
+            List::Commands(list) => list.retain(|e| !matches!(e, CommandEntry::Plain(c) if c == entry)),
+        }
+        self.len() < before
+    }
+}
+
+/// Adds `entry` to the end of `list` or removes it, failing if it is
+/// already there or, for a removal, missing
+pub fn apply(policy: &mut Policy, action: EditAction, list: &str, entry: &str) -> Result<(), EditError> {
+    let mut entries = List::of(policy, list)?;
+    let error = |list: &str| (list.to_string(), entry.to_string());
+    match action {
+        EditAction::Add if entries.contains(entry) => {
+            let (list, entry) = error(list);
+            Err(EditError::AlreadyPresent { list, entry })
+        }
+        EditAction::Add => {
+            entries.push(entry);
+            Ok(())
+        }
+        EditAction::Remove if entries.remove(entry) => Ok(()),
+        EditAction::Remove => {
+            let (list, entry) = error(list);
+            Err(EditError::NotFound { list, entry })
+        }
+    }
+}
+
+/// The entries of `list` as strings: for `allowed_commands`, the plain
+/// ones, since only those can be edited
+pub fn entries(policy: &Policy, list: &str) -> Result<Vec<String>, EditError> {
+    Ok(match list {
+        "filesystem.allowed_read" => policy.filesystem.allowed_read.clone(),
+        "filesystem.allowed_write" => policy.filesystem.allowed_write.clone(),
+        "network.allowed_domains" => policy.network.allowed_domains.clone(),
+        "process.allowed_commands" => policy
+            .process
+            .allowed_commands
+            .iter()
+            .filter_map(|entry| match entry {
+                CommandEntry::Plain(command) => Some(command.clone()),
+                CommandEntry::Spec(_) => None,
+            })
+            .collect(),
+        other => return Err(EditError::UnknownList(other.to_string())),
+    })
+}
+
+/// The content of `policy_edits_file`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyEdits {
+    #[serde(default)]
+    added: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    removed: BTreeMap<String, Vec<String>>,
+}
+
+impl PolicyEdits {
+    /// The edits in `path`; none while it does not exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read policy edits {}", path.display())),
+        };
+        toml::from_str(&content).with_context(|| format!("Failed to parse policy edits {}", path.display()))
+    }
+
+    /// Written beside the file and renamed over it, so a crash leaves the
+    /// old edits or the new ones
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string(self).context("Failed to serialize policy edits")?;
+        let mut tmp = path.to_path_buf().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        if let Some(dir) = path.parent() {
+            crate::fs::writer::create_dirs(dir)?;
+        }
+        fs::write(&tmp, content)
+            .and_then(|()| fs::rename(&tmp, path))
+            .with_context(|| format!("Failed to write policy edits {}", path.display()))
+    }
+
+    /// Adds the edit, cancelling an opposite one to the same entry
+    pub fn record(&mut self, action: EditAction, list: &str, entry: &str) {
+        let (to, from) = match action {
+            EditAction::Add => (&mut self.added, &mut self.removed),
+            EditAction::Remove => (&mut self.removed, &mut self.added),
+        };
+        if let Some(entries) = from.get_mut(list) {
+            entries.retain(|e| e != entry);
+            if entries.is_empty() {
+                from.remove(list);
+            }
+        }
+        let entries = to.entry(list.to_string()).or_default();
+        if !entries.iter().any(|e| e == entry) {
+            entries.push(entry.to_string());
+        }
+    }
+
+    /// Removals, then additions, returning those that changed `policy`.
+    /// The policy file may have changed since an edit was made, so entries
+    /// already gone or already present are skipped instead of failing the
+    /// load.
+    pub fn apply_to(&self, policy: &mut Policy) -> Result<Vec<(EditAction, &str, &str)>> {
+        let mut applied = Vec::new();
+        for (action, edits) in [(EditAction::Remove, &self.removed), (EditAction::Add, &self.added)] {
+            for (list, entries) in edits {
+                for entry in entries {
+                    match apply(policy, action, list, entry) {
+                        Ok(()) => applied.push((action, list.as_str(), entry.as_str())),
+                        Err(EditError::UnknownList(list)) => anyhow::bail!("Policy edits name an unknown list {:?}", list),
+                        Err(_) => {}
+                    }
+                }
+            }
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_edits_cancel_and_survive_a_round_trip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("policy.d/runtime.toml");
+        let mut edits = PolicyEdits::load(&path).unwrap();
+        edits.record(EditAction::Add, "filesystem.allowed_read", "/srv/new");
+        edits.record(EditAction::Add, "filesystem.allowed_read", "/srv/new");
+        edits.record(EditAction::Remove, "network.allowed_domains", "old.example.com");
+        edits.record(EditAction::Add, "process.allowed_commands", "jq");
+        edits.record(EditAction::Remove, "process.allowed_commands", "jq");
+        edits.save(&path).unwrap();
+
+        let loaded = PolicyEdits::load(&path).unwrap();
+        assert_eq!(loaded, edits);
+        assert_eq!(loaded.added["filesystem.allowed_read"], ["/srv/new"]);
+        assert!(!loaded.added.contains_key("process.allowed_commands"));
+        assert_eq!(loaded.removed["process.allowed_commands"], ["jq"]);
+
+        let mut policy = Policy::example(crate::policy::examples::Profile::Strict).unwrap();
+        policy.network.allowed_domains = vec!["old.example.com".into(), "api.example.com".into()];
+        let applied = loaded.apply_to(&mut policy).unwrap();
+        assert_eq!(applied.len(), 2, "{:?}", applied);
+        assert!(policy.filesystem.allowed_read.iter().any(|e| e == "/srv/new"));
+        assert_eq!(policy.network.allowed_domains, ["api.example.com"]);
+        // Applying again changes nothing
+        assert!(loaded.apply_to(&mut policy).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+//! Example policies shipped with the binary, as `--example` prints them.
+//!
+//! The text is that of the files under `examples/`, so the copy a user
+//! generates and the one documented there cannot drift apart.
+
+use std::fmt;
+use std::str::FromStr;
+
+const DEVELOPMENT: &str = include_str!("../../examples/policy.toml");
+const STRICT: &str = include_str!("../../examples/strict.toml");
+
+/// A starting point for a policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Every section, commented: a workspace, a few API domains and
+    /// common developer commands
+    Development,
+    /// Read one workspace, write its output directory, no network and no
+    /// commands
+    Strict,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 2] = [Profile::Development, Profile::Strict];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Development => "development",
+            Profile::Strict => "strict",
+        }
+    }
+
+    /// The policy as TOML, comments included
+    pub fn source(self) -> &'static str {
+        match self {
+            Profile::Development => DEVELOPMENT,
+            Profile::Strict => STRICT,
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Profile::ALL
+            .into_iter()
+            .find(|profile| profile.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Profile::ALL.iter().map(|profile| profile.name()).collect();
+                format!("unknown profile {:?}; expected one of {}", s, names.join(", "))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+
+    #[test]
+    fn test_examples_load() {
+        for profile in Profile::ALL {
+            assert_eq!(profile.to_string().parse::<Profile>(), Ok(profile));
+            let loaded = Policy::example(profile);
+            if profile == Profile::Development && !cfg!(feature = "gzip") {
+                // It sets compression = "gzip"
+                assert!(loaded.is_err());
+            } else if let Err(e) = loaded {
+                panic!("{}: {:#}", profile, e);
+            }
+        }
+        let strict = Policy::example(Profile::Strict).unwrap();
+        assert!(strict.network.allowed_domains.is_empty());
+        assert!(strict.process.allowed_commands.is_empty());
+        assert!("permissive".parse::<Profile>().unwrap_err().contains("development, strict"));
+    }
+}
@@ -0,0 +1,570 @@
+//! Composing one policy from a base and an overlay, e.g. a vendor baseline
+//! and the approvals a user granted on top of it.
+//!
+//! Both inputs are loaded policies, so relative entries are already
+//! anchored to their own workspace. What is merged, and how:
+//!
+//! | | narrow | widen |
+//! |---|---|---|
+//! | allow lists, `!` exemptions | in both | in either |
+//! | deny lists, redaction, grant conflicts | in either | in either |
+//! | `allow_*` switches | both set | either set |
+//! | protective switches | either set | either set |
+//! | limits | lower | higher |
+//!
+//! A session budget of 0, which is unlimited, counts as the highest. Every
+//! other setting, like `[server]`, `[audit]` or the escalation address, is
+//! the base's.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+
+use super::{parser, CommandEntry, DomainQuota, EmptyAllowMeans, PathRule, Policy, RedactionRule, RuleAction};
+
+/// How the overlay combines with the base
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Only what both allow is allowed
+    #[default]
+    Narrow,
+    /// What either allows is allowed; denials still add up
+    Widen,
+}
+
+impl FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "narrow" => Ok(MergeStrategy::Narrow),
+            "widen" => Ok(MergeStrategy::Widen),
+            other => Err(format!("unknown merge strategy {:?} (expected narrow or widen)", other)),
+        }
+    }
+}
+
+/// Which input a merged entry or value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Base,
+    Overlay,
+    Both,
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Input::Base => "base",
+            Input::Overlay => "overlay",
+            Input::Both => "both",
+        })
+    }
+}
+
+/// One entry of a merged list, or a merged limit or switch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// Dotted policy key, e.g. `network.allowed_domains`
+    pub key: &'static str,
+    /// The entry or value as written in the policy
+    pub value: String,
+    pub from: Input,
+}
+
+/// A merged policy and where each merged entry came from
+#[derive(Debug, Clone)]
+pub struct Merged {
+    pub policy: Policy,
+    /// Every merged list entry, in policy order, and each limit or switch
+    /// the overlay set. Settings that are the base's alone are not listed.
+    pub origins: Vec<Origin>,
+}
+
+impl Merged {
+    /// Origins of entries that are in the result because of the overlay
+    pub fn added_by_overlay(&self) -> impl Iterator<Item = &Origin> {
+        self.origins.iter().filter(|origin| origin.from == Input::Overlay)
+    }
+}
+
+/// Merges `overlay` into `base`; the result passes the same validation as
+/// a loaded policy, or this fails
+pub fn merge(base: &Policy, overlay: &Policy, strategy: MergeStrategy) -> Result<Merged> {
+    let mut m = Merger {
+        strategy,
+        origins: Vec::new(),
+    };
+    // An empty list that allows every path has no entries to intersect or
+    // join, and the one setting could not say which merged list it meant
+    for (input, filesystem) in [("base", &base.filesystem), ("overlay", &overlay.filesystem)] {
+        let empty = filesystem.allowed_read.is_empty() || filesystem.allowed_write.is_empty();
+        if empty && filesystem.empty_allow_means == EmptyAllowMeans::All {
+            anyhow::bail!(
+                "The {} leaves an allow list empty with empty_allow_means = \"all\"; list its paths to merge it",
+                input
+            );
+        }
+    }
+    let mut policy = base.clone();
+
+    let (fs, o) = (&mut policy.filesystem, &overlay.filesystem);
+    // No input needs it, and an intersection can come out empty
+    fs.empty_allow_means = EmptyAllowMeans::None;
+    m.allows("filesystem.allowed_read", &mut fs.allowed_read, &o.allowed_read);
+    m.allows("filesystem.allowed_write", &mut fs.allowed_write, &o.allowed_write);
+    m.allows("filesystem.allowed_stat", &mut fs.allowed_stat, &o.allowed_stat);
+    m.denied_patterns(&mut fs.denied_patterns, &o.denied_patterns);
+    m.protects("filesystem.use_default_denylist", &mut fs.use_default_denylist, o.use_default_denylist);
+    m.protects("filesystem.check_inodes", &mut fs.check_inodes, o.check_inodes);
+    m.rules(&mut fs.rules, &o.rules);
+    m.denies("filesystem.denied_write_extensions", &mut fs.denied_write_extensions, &o.denied_write_extensions);
+    m.permits("filesystem.allow_relative_paths", &mut fs.allow_relative_paths, o.allow_relative_paths);
+    m.limit("filesystem.max_watches_per_client", &mut fs.max_watches_per_client, o.max_watches_per_client);
+    m.limit("filesystem.search.max_results", &mut fs.search.max_results, o.search.max_results);
+    m.limit("filesystem.search.time_budget_secs", &mut fs.search.time_budget_secs, o.search.time_budget_secs);
+    m.limit("filesystem.search.max_file_kb", &mut fs.search.max_file_kb, o.search.max_file_kb);
+    m.denies("filesystem.redaction", &mut fs.redaction, &o.redaction);
+
+    let (net, o) = (&mut policy.network, &overlay.network);
+    m.allows("network.allowed_domains", &mut net.allowed_domains, &o.allowed_domains);
+    m.denies("network.blocked_ips", &mut net.blocked_ips, &o.blocked_ips);
+    m.denies("network.blocked_ip_lists", &mut net.blocked_ip_lists, &o.blocked_ip_lists);
+    m.limit("network.max_connections", &mut net.max_connections, o.max_connections);
+    m.per_domain_limits(&mut net.max_connections_per_domain, &o.max_connections_per_domain);
+    m.quotas(&mut net.quotas, &o.quotas);
+    m.protects("network.require_proxy", &mut net.require_proxy, o.require_proxy);
+    m.allows("network.exempt_domains", &mut net.exempt_domains, &o.exempt_domains);
+    m.protects("network.block_private_addresses", &mut net.block_private_addresses, o.block_private_addresses);
+    m.allows("network.allowed_private_cidrs", &mut net.allowed_private_cidrs, &o.allowed_private_cidrs);
+    m.permits(
+        "network.dangerously_allow_metadata_endpoints",
+        &mut net.dangerously_allow_metadata_endpoints,
+        o.dangerously_allow_metadata_endpoints,
+    );
+
+    let (http, o) = (&mut net.http, &overlay.network.http);
+    m.allows("network.http.allowed_methods", &mut http.allowed_methods, &o.allowed_methods);
+    m.denies("network.http.blocked_request_headers", &mut http.blocked_request_headers, &o.blocked_request_headers);
+    m.allows("network.http.trusted_domains", &mut http.trusted_domains, &o.trusted_domains);
+    m.limit("network.http.max_request_body_kb", &mut http.max_request_body_kb, o.max_request_body_kb);
+    m.limit("network.http.max_response_body_mb", &mut http.max_response_body_mb, o.max_response_body_mb);
+
+    let (connect, o) = (&mut net.connect, &overlay.network.connect);
+    m.permits("network.connect.allow_tcp", &mut connect.allow_tcp, o.allow_tcp);
+    m.permits("network.connect.allow_tls", &mut connect.allow_tls, o.allow_tls);
+    m.permits("network.connect.allow_ws", &mut connect.allow_ws, o.allow_ws);
+    m.permits("network.connect.allow_wss", &mut connect.allow_wss, o.allow_wss);
+    m.allows("network.connect.allowed_ports", &mut connect.allowed_ports, &o.allowed_ports);
+
+    let (process, o) = (&mut policy.process, &overlay.process);
+    m.allows("process.allowed_commands", &mut process.allowed_commands, &o.allowed_commands);
+    m.allows("process.allowed_env", &mut process.allowed_env, &o.allowed_env);
+    m.limit("process.max_cpu_percent", &mut process.max_cpu_percent, o.max_cpu_percent);
+    m.limit("process.max_memory_mb", &mut process.max_memory_mb, o.max_memory_mb);
+
+    let (resources, o) = (&mut policy.resources, &overlay.resources);
+    m.limit("resources.max_file_size_mb", &mut resources.max_file_size_mb, o.max_file_size_mb);
+    m.limit("resources.max_open_files", &mut resources.max_open_files, o.max_open_files);
+
+    let (sessions, o) = (&mut policy.sessions, &overlay.sessions);
+    m.limit("sessions.ttl_secs", &mut sessions.ttl_secs, o.ttl_secs);
+    m.limit("sessions.max_sessions", &mut sessions.max_sessions, o.max_sessions);
+    // Budgets of 0 are unlimited
+    m.budget("sessions.max_read_mb", &mut sessions.max_read_mb, o.max_read_mb);
+    m.budget("sessions.max_files_written", &mut sessions.max_files_written, o.max_files_written);
+    m.budget("sessions.max_commands_run", &mut sessions.max_commands_run, o.max_commands_run);
+    m.denies("escalation.conflicts", &mut policy.escalation.conflicts, &overlay.escalation.conflicts);
+    m.limit(
+        "escalation.max_grant_secs",
+        &mut policy.escalation.max_grant_secs,
+        overlay.escalation.max_grant_secs,
+    );
+
+    parser::validate_policy(&policy).context("The merged policy is invalid")?;
+    Ok(Merged {
+        policy,
+        origins: m.origins,
+    })
+}
+
+/// How a list entry is shown in an [`Origin`]
+pub(super) trait Label {
+    fn label(&self) -> String;
+}
+
+impl Label for String {
+    fn label(&self) -> String {
+        self.clone()
+    }
+}
+
+impl Label for [String; 2] {
+    fn label(&self) -> String {
+        format!("{} and {}", self[0], self[1])
+    }
+}
+
+impl Label for u16 {
+    fn label(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Label for CommandEntry {
+    fn label(&self) -> String {
+        match self {
+            CommandEntry::Plain(command) => command.clone(),
+            CommandEntry::Spec(spec) => match (&spec.path, &spec.min_version) {
+                (Some(path), _) => format!("{} ({})", spec.name, path),
+                (None, Some(version)) => format!("{} >= {}", spec.name, version),
+                (None, None) => spec.name.clone(),
+            },
+        }
+    }
+}
+
+impl Label for PathRule {
+    fn label(&self) -> String {
+        format!("{:?} {:?} {}", self.action, self.access, self.path).to_lowercase()
+    }
+}
+
+impl Label for RedactionRule {
+    fn label(&self) -> String {
+        self.pattern.clone()
+    }
+}
+
+/// Merges overlay values into a copy of the base, recording origins
+struct Merger {
+    strategy: MergeStrategy,
+    origins: Vec<Origin>,
+}
+
+impl Merger {
+    /// Entries in both lists under narrow, in either under widen
+    fn allows<T: Clone + PartialEq + Label>(&mut self, key: &'static str, merged: &mut Vec<T>, overlay: &[T]) {
+        self.list(key, merged, overlay, self.strategy == MergeStrategy::Widen);
+    }
+
+    /// Entries in either list
+    fn denies<T: Clone + PartialEq + Label>(&mut self, key: &'static str, merged: &mut Vec<T>, overlay: &[T]) {
+        self.list(key, merged, overlay, true);
+    }
+
+    /// Base entries first, in order, then those only the overlay has
+    fn list<T: Clone + PartialEq + Label>(&mut self, key: &'static str, merged: &mut Vec<T>, overlay: &[T], union: bool) {
+        let base = std::mem::take(merged);
+        for entry in &base {
+            let from = if overlay.contains(entry) { Input::Both } else { Input::Base };
+            if union || from == Input::Both {
+                self.record(key, entry.label(), from);
+                merged.push(entry.clone());
+            }
+        }
+        if union {
+            for entry in overlay.iter().filter(|entry| !base.contains(entry)) {
+                self.record(key, entry.label(), Input::Overlay);
+                merged.push(entry.clone());
+            }
+        }
+    }
+
+    /// Patterns are denials; `!` exemptions from them are allows
+    fn denied_patterns(&mut self, merged: &mut Vec<String>, overlay: &[String]) {
+        let key = "filesystem.denied_patterns";
+        let (mut exempt, mut denied): (Vec<String>, Vec<String>) =
+            std::mem::take(merged).into_iter().partition(|p| p.starts_with('!'));
+        let (overlay_exempt, overlay_denied): (Vec<String>, Vec<String>) =
+            overlay.iter().cloned().partition(|p| p.starts_with('!'));
+        self.denies(key, &mut denied, &overlay_denied);
+        self.allows(key, &mut exempt, &overlay_exempt);
+        *merged = denied;
+        merged.append(&mut exempt);
+    }
+
+    /// Under narrow deny rules come first, from either input, followed by
+    /// the allow rules in both, so no allow rule can get ahead of a denial.
+    /// Under widen the overlay's new rules follow the base's.
+    fn rules(&mut self, merged: &mut Vec<PathRule>, overlay: &[PathRule]) {
+        let key = "filesystem.rules";
+        if self.strategy == MergeStrategy::Widen {
+            return self.denies(key, merged, overlay);
+        }
+        let (mut denied, mut allowed): (Vec<PathRule>, Vec<PathRule>) =
+            std::mem::take(merged).into_iter().partition(|rule| rule.action == RuleAction::Deny);
+        let (overlay_denied, overlay_allowed): (Vec<PathRule>, Vec<PathRule>) =
+            overlay.iter().cloned().partition(|rule| rule.action == RuleAction::Deny);
+        self.denies(key, &mut denied, &overlay_denied);
+        self.allows(key, &mut allowed, &overlay_allowed);
+        *merged = denied;
+        merged.append(&mut allowed);
+    }
+
+    /// A domain without an entry is only held to `max_connections`, so an
+    /// entry in one input alone is kept under narrow and dropped under widen
+    fn per_domain_limits(&mut self, merged: &mut BTreeMap<String, u32>, overlay: &BTreeMap<String, u32>) {
+        let narrow = self.strategy == MergeStrategy::Narrow;
+        let base = std::mem::take(merged);
+        for domain in base.keys().chain(overlay.keys()) {
+            let picked = match (base.get(domain), overlay.get(domain)) {
+                (Some(&b), Some(&o)) => self.pick(b, o, false),
+                (Some(&b), None) if narrow => (b, Input::Base),
+                (None, Some(&o)) if narrow => (o, Input::Overlay),
+                _ => continue,
+            };
+            if merged.insert(domain.clone(), picked.0).is_none() {
+                let value = format!("{} = {}", domain, picked.0);
+                self.record("network.max_connections_per_domain", value, picked.1);
+            }
+        }
+    }
+
+    /// Like [`Self::per_domain_limits`], each budget picked as by
+    /// [`Self::budget`]
+    fn quotas(&mut self, merged: &mut Vec<DomainQuota>, overlay: &[DomainQuota]) {
+        let narrow = self.strategy == MergeStrategy::Narrow;
+        let base = std::mem::take(merged);
+        let find = |quotas: &[DomainQuota], domain: &str| quotas.iter().find(|q| q.domain == domain).cloned();
+        for domain in base.iter().chain(overlay).map(|quota| quota.domain.as_str()) {
+            if merged.iter().any(|quota| quota.domain == domain) {
+                continue;
+            }
+            let (quota, from) = match (find(&base, domain), find(overlay, domain)) {
+                (Some(b), Some(o)) => {
+                    let concurrent = self.pick(b.max_concurrent, o.max_concurrent, true);
+                    let bytes = self.pick(b.max_bytes_per_day, o.max_bytes_per_day, true);
+                    if concurrent.0 == 0 && bytes.0 == 0 {
+                        // Widened to no limit at all
+                        continue;
+                    }
+                    let from = match (concurrent.1, bytes.1) {
+                        (Input::Overlay, _) | (_, Input::Overlay) => Input::Overlay,
+                        (Input::Base, _) | (_, Input::Base) => Input::Base,
+                        _ => Input::Both,
+                    };
+                    let quota = DomainQuota {
+                        domain: domain.to_string(),
+                        max_concurrent: concurrent.0,
+                        max_bytes_per_day: bytes.0,
+                    };
+                    (quota, from)
+                }
+                (Some(b), None) if narrow => (b, Input::Base),
+                (None, Some(o)) if narrow => (o, Input::Overlay),
+                _ => continue,
+            };
+            let value = format!(
+                "{}: max_concurrent = {}, max_bytes_per_day = {}",
+                domain, quota.max_concurrent, quota.max_bytes_per_day
+            );
+            self.record("network.quotas", value, from);
+            merged.push(quota);
+        }
+    }
+
+    /// The lower value under narrow and the higher under widen
+    fn limit<T: Ord + Copy + Default + fmt::Display>(&mut self, key: &'static str, merged: &mut T, overlay: T) {
+        self.set(key, merged, overlay, false);
+    }
+
+    /// Like [`Self::limit`], with 0 meaning unlimited
+    fn budget<T: Ord + Copy + Default + fmt::Display>(&mut self, key: &'static str, merged: &mut T, overlay: T) {
+        self.set(key, merged, overlay, true);
+    }
+
+    fn set<T: Ord + Copy + Default + fmt::Display>(&mut self, key: &'static str, merged: &mut T, overlay: T, zero_unlimited: bool) {
+        let (value, from) = self.pick(*merged, overlay, zero_unlimited);
+        if from == Input::Overlay {
+            self.record(key, value.to_string(), from);
+        }
+        *merged = value;
+    }
+
+    fn pick<T: Ord + Copy + Default>(&self, base: T, overlay: T, zero_unlimited: bool) -> (T, Input) {
+        if base == overlay {
+            return (base, Input::Both);
+        }
+        let rank = |value: T| (zero_unlimited && value == T::default(), value);
+        let overlay_higher = rank(overlay) > rank(base);
+        match (self.strategy, overlay_higher) {
+            (MergeStrategy::Widen, true) | (MergeStrategy::Narrow, false) => (overlay, Input::Overlay),
+            _ => (base, Input::Base),
+        }
+    }
+
+    /// A switch that grants access: on under narrow only if both set it
+    fn permits(&mut self, key: &'static str, merged: &mut bool, overlay: bool) {
+        match self.strategy {
+            MergeStrategy::Narrow => *merged &= overlay,
+            MergeStrategy::Widen => self.protects(key, merged, overlay),
+        }
+    }
+
+    /// A switch that protects: on if either input sets it
+    fn protects(&mut self, key: &'static str, merged: &mut bool, overlay: bool) {
+        if overlay && !*merged {
+            self.record(key, "true".to_string(), Input::Overlay);
+            *merged = true;
+        }
+    }
+
+    fn record(&mut self, key: &'static str, value: String, from: Input) {
+        self.origins.push(Origin { key, value, from });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::parser::{parse_policy, PolicyFormat, PolicySource};
+
+    fn policy(toml: &str) -> Policy {
+        parse_policy(toml, PolicyFormat::Toml, &PolicySource::Inline(String::new())).unwrap()
+    }
+
+    const BASE: &str = r#"
+[filesystem]
+allowed_read = ["/srv/app", "/srv/docs"]
+allowed_write = ["/srv/app/out"]
+denied_patterns = ["*.key", "!/srv/app/public.key"]
+
+[network]
+allowed_domains = ["api.github.com"]
+blocked_ips = []
+max_connections = 10
+
+[process]
+allowed_commands = ["git"]
+max_cpu_percent = 50
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = 100
+max_open_files = 100
+
+[sessions]
+max_read_mb = 200
+"#;
+
+    const OVERLAY: &str = r#"
+[filesystem]
+allowed_read = ["/srv/app", "/home/user/notes"]
+allowed_write = ["/srv/app/out"]
+denied_patterns = ["*.pem"]
+allow_relative_paths = true
+
+[network]
+allowed_domains = ["api.github.com", "api.example.com"]
+blocked_ips = ["203.0.113.7"]
+max_connections = 20
+
+[process]
+allowed_commands = ["git", "npm"]
+max_cpu_percent = 25
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = 100
+max_open_files = 100
+"#;
+
+    #[test]
+    fn test_narrow_keeps_common_allows_and_every_denial() {
+        let merged = merge(&policy(BASE), &policy(OVERLAY), MergeStrategy::Narrow).unwrap();
+        let p = &merged.policy;
+        assert_eq!(p.filesystem.allowed_read, vec!["/srv/app"]);
+        assert_eq!(p.filesystem.denied_patterns, vec!["*.key", "*.pem"]);
+        assert!(!p.filesystem.allow_relative_paths);
+        assert_eq!(p.network.allowed_domains, vec!["api.github.com"]);
+        assert_eq!(p.network.blocked_ips, vec!["203.0.113.7"]);
+        assert_eq!(p.network.max_connections, 10);
+        assert_eq!(p.process.max_cpu_percent, 25);
+        // The overlay sets no read budget, which is unlimited
+        assert_eq!(p.sessions.max_read_mb, 200);
+
+        let added: Vec<(&str, &str)> =
+            merged.added_by_overlay().map(|origin| (origin.key, origin.value.as_str())).collect();
+        assert_eq!(
+            added,
+            vec![
+                ("filesystem.denied_patterns", "*.pem"),
+                ("network.blocked_ips", "203.0.113.7"),
+                ("process.max_cpu_percent", "25"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_widen_unions_allows() {
+        let merged = merge(&policy(BASE), &policy(OVERLAY), MergeStrategy::Widen).unwrap();
+        let p = &merged.policy;
+        assert_eq!(p.filesystem.allowed_read, vec!["/srv/app", "/srv/docs", "/home/user/notes"]);
+        assert_eq!(p.filesystem.denied_patterns, vec!["*.key", "*.pem", "!/srv/app/public.key"]);
+        assert!(p.filesystem.allow_relative_paths);
+        assert_eq!(p.network.max_connections, 20);
+        assert_eq!(p.process.max_cpu_percent, 50);
+        assert_eq!(p.sessions.max_read_mb, 0);
+        assert!(merged.origins.contains(&Origin {
+            key: "process.allowed_commands",
+            value: "npm".to_string(),
+            from: Input::Overlay,
+        }));
+        assert!(merged.origins.contains(&Origin {
+            key: "filesystem.allowed_read",
+            value: "/srv/app".to_string(),
+            from: Input::Both,
+        }));
+    }
+
+    #[test]
+    fn test_quotas_merge_per_budget() {
+        let with_quotas = |quotas: &[(&str, u32, u64)]| {
+            let mut p = policy(BASE);
+            p.network.quotas = quotas
+                .iter()
+                .map(|&(domain, max_concurrent, max_bytes_per_day)| DomainQuota {
+                    domain: domain.to_string(),
+                    max_concurrent,
+                    max_bytes_per_day,
+                })
+                .collect();
+            p
+        };
+        let limits = |p: Policy| -> Vec<(String, u32, u64)> {
+            p.network.quotas.into_iter().map(|q| (q.domain, q.max_concurrent, q.max_bytes_per_day)).collect()
+        };
+        let base = with_quotas(&[("api.openai.com", 5, 0), ("a.com", 0, 10)]);
+        let overlay = with_quotas(&[("api.openai.com", 8, 100)]);
+
+        let narrow = merge(&base, &overlay, MergeStrategy::Narrow).unwrap().policy;
+        assert_eq!(limits(narrow), [("api.openai.com".to_string(), 5, 100), ("a.com".to_string(), 0, 10)]);
+        // 0 bytes is unlimited, so widening keeps only the higher connection cap
+        let widen = merge(&base, &overlay, MergeStrategy::Widen).unwrap().policy;
+        assert_eq!(limits(widen), [("api.openai.com".to_string(), 8, 0)]);
+    }
+
+    #[test]
+    fn test_merged_policy_is_validated() {
+        // Nothing left to read or write
+        let overlay = OVERLAY.replace("[\"/srv/app\", \"/home/user/notes\"]", "[\"/home/user/notes\"]").replace(
+            "allowed_write = [\"/srv/app/out\"]",
+            "allowed_write = []",
+        );
+        let err = merge(&policy(BASE), &policy(&overlay), MergeStrategy::Narrow).unwrap_err();
+        assert!(format!("{:#}", err).contains("at least one allowed read or write path"), "{:#}", err);
+        // Nor is there a list to merge an empty one that allows every path with
+        let overlay =
+            OVERLAY.replace("allowed_write = [\"/srv/app/out\"]", "allowed_write = []\nempty_allow_means = \"all\"");
+        let err = merge(&policy(BASE), &policy(&overlay), MergeStrategy::Narrow).unwrap_err();
+        assert!(err.to_string().contains("overlay leaves an allow list empty"), "{:#}", err);
+
+        // What comes out loads again
+        let merged = merge(&policy(BASE), &policy(OVERLAY), MergeStrategy::Widen).unwrap();
+        let reloaded = policy(&toml::to_string(&merged.policy).unwrap());
+        assert_eq!(reloaded.filesystem.allowed_read, merged.policy.filesystem.allowed_read);
+    }
+}
@@ -0,0 +1,302 @@
+//! The reasons a client is shown, by violation code (`[messages]`)
+//!
+//! `SecurityStatus.reason` is rendered from a template for the violation
+//! rather than taken from the check that denied, so it can be reworded or
+//! translated without touching what is decided: clients branch on the
+//! reason code, and the audit log keeps the daemon's own explanation.
+//! Templates name their parameters in braces:
+//!
+//! - `{operation}`, e.g. `read_file`, and `{target}`, what it was asked of
+//! - `{path}`, `{domain}` or `{command}`, the target of a filesystem,
+//!   network or process operation; empty for the others
+//! - `{rule}`, the policy section the violation is about, e.g. `[network]`
+//! - `{detail}`, the explanation that was audited
+//!
+//! `{{` and `}}` are literal braces.
+
+use std::collections::BTreeMap;
+
+use super::MessagesPolicy;
+
+/// The locale whose catalog is built in, and what any other falls back to
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// What a template may name
+const PARAMS: &[&str] = &["operation", "target", "path", "domain", "command", "rule", "detail"];
+
+/// `(code, rule, template)` for every reason code, `access_granted` first
+const CATALOG: &[(&str, &str, &str)] = &[
+    ("access_granted", "", "{detail}"),
+    // Paths
+    ("path_not_allowed", "[filesystem]", "Access to {path} is not allowed"),
+    ("relative_path_not_allowed", "[filesystem]", "{path} is a relative path; give an absolute one"),
+    ("malformed_path", "[filesystem]", "{path} is not a valid path: {detail}"),
+    ("toctou_detected", "[filesystem]", "{path} changed while it was being checked"),
+    ("redacted_file", "[filesystem.redaction]", "{path} is covered by a redaction rule"),
+    ("binary_not_redactable", "[filesystem.redaction]", "{path} is covered by a redaction rule but is not text"),
+    ("file_exists", "", "{path} already exists"),
+    ("conflict", "", "{path} has changed since it was read"),
+    ("edit_not_applied", "", "The edit to {path} was not applied: {detail}"),
+    ("watch_limit_reached", "", "Too many files are being watched"),
+    ("temp_file_limit_reached", "", "Too many temporary files exist; remove some first"),
+    ("path_not_found", "[filesystem]", "{path} does not exist"),
+    ("write_extension_denied", "[filesystem]", "Files like {path} may not be written"),
+    ("file_too_large", "[resources]", "{path} would be too large"),
+    ("self_protection", "", "{path} belongs to the daemon and cannot be changed"),
+    // Network
+    ("domain_not_allowed", "[network]", "Access to {domain} is not allowed"),
+    ("invalid_url", "[network]", "{target} is not a valid URL"),
+    ("method_not_allowed", "[network.http]", "That request method is not allowed for {domain}"),
+    ("header_not_allowed", "[network.http]", "A header of the request may not be sent to {domain}"),
+    ("request_body_too_large", "[network.http]", "The request body is too large"),
+    ("dns_resolution_failed", "[network]", "{domain} could not be resolved"),
+    ("ip_blocked", "[network]", "{domain} is at a blocked address"),
+    ("private_address", "[network]", "{domain} is at a private address"),
+    ("metadata_endpoint", "[network]", "{domain} is a cloud metadata endpoint"),
+    ("proxy_required", "[network.proxy]", "Connections to {domain} must go through the proxy"),
+    ("protocol_not_allowed", "[network.connect]", "That protocol is not allowed for {domain}"),
+    ("port_not_allowed", "[network.connect]", "That port is not allowed for {domain}"),
+    ("connection_limit_reached", "[network.connect]", "Too many connections are open"),
+    ("lease_expired", "[network.connect]", "The connection lease has expired"),
+    ("unknown_lease", "[network.connect]", "There is no such connection lease"),
+    ("quota_exceeded", "[network.quotas]", "The network quota for {domain} is used up: {detail}"),
+    ("reserved_header", "[network.http]", "The daemon sets that header itself: {detail}"),
+    // Commands
+    ("command_not_allowed", "[process]", "{command} may not be run"),
+    ("command_not_found", "[process]", "{command} was not found"),
+    ("command_version_unmet", "[process]", "The installed {command} is too old: {detail}"),
+    // Sessions and the daemon itself
+    ("session_limit_reached", "[sessions]", "Too many sessions are active"),
+    ("budget_exhausted", "[sessions]", "The session's budget is used up: {detail}"),
+    ("snapshot_violation", "", "{path} has changed since this session first read it"),
+    ("degraded", "[performance]", "The daemon is busy; try again shortly"),
+    ("internal_error", "[enforcement]", "{target} could not be checked"),
+    ("admin_token_required", "[server]", "This needs the admin token"),
+    ("policy_editor_required", "[server]", "This needs a policy editor's token"),
+    ("read_only_mode", "", "The daemon is read-only; {operation} is not available"),
+    ("malformed_url", "[network.connect]", "{target} is not a valid URL"),
+    ("input_too_large", "[enforcement]", "The request is too large to check: {detail}"),
+    ("outside_schedule", "[schedule]", "{operation} is not allowed right now: {detail}"),
+    ("operation_disabled", "[operations]", "{operation} is turned off"),
+    ("suspicious_path", "[filesystem]", "{path} looks like an attempt to leave the allowed paths"),
+];
+
+/// What a decision was about, for its template
+#[derive(Debug, Clone, Copy)]
+pub struct Params<'a> {
+    pub operation: &'a str,
+    pub target: &'a str,
+    pub detail: &'a str,
+}
+
+/// The templates of the selected locale, over the built-in catalog
+#[derive(Debug, Clone, Default)]
+pub struct Messages {
+    templates: BTreeMap<String, String>,
+}
+
+impl Messages {
+    /// Checks every catalog in `policy`, so a template with a typo fails
+    /// the load rather than the request that needs it
+    pub fn new(policy: &MessagesPolicy) -> Result<Self, String> {
+        validate_locale(&policy.locale)?;
+        if policy.locale != DEFAULT_LOCALE && !policy.catalogs.contains_key(&policy.locale) {
+            return Err(format!(
+                "messages.locale {:?} has no [messages.catalogs.{}]; only {:?} is built in",
+                policy.locale, policy.locale, DEFAULT_LOCALE
+            ));
+        }
+        for (locale, catalog) in &policy.catalogs {
+            validate_locale(locale)?;
+            for (code, template) in catalog {
+                if !has_message(code) {
+                    return Err(format!("messages.catalogs.{}: {:?} is not a violation code", locale, code));
+                }
+                parse(template).map_err(|e| format!("messages.catalogs.{}.{}: {}", locale, code, e))?;
+            }
+        }
+        Ok(Self {
+            templates: policy.catalogs.get(&policy.locale).cloned().unwrap_or_default(),
+        })
+    }
+
+    /// The reason for `code`, from the selected locale or else the built-in
+    /// catalog; a code neither has is shown by its detail
+    pub fn render(&self, code: &str, params: &Params) -> String {
+        let Some((_, rule, builtin)) = entry(code) else {
+            return params.detail.to_string();
+        };
+        let template = self.templates.get(code).map_or(*builtin, String::as_str);
+        let Ok(parts) = parse(template) else {
+            return params.detail.to_string();
+        };
+        let (path, domain, command) = match kind(params.operation) {
+            Kind::Path => (params.target, "", ""),
+            Kind::Domain => ("", host(params.target), ""),
+            Kind::Command => ("", "", params.target),
+            Kind::Other => ("", "", ""),
+        };
+        parts
+            .into_iter()
+            .map(|part| match part {
+                Part::Text(text) => text,
+                Part::Param("operation") => params.operation,
+                Part::Param("target") => params.target,
+                Part::Param("path") => path,
+                Part::Param("domain") => domain,
+                Part::Param("command") => command,
+                Part::Param("rule") => rule,
+                Part::Param("detail") => params.detail,
+                Part::Param(_) => "",
+            })
+            .collect()
+    }
+}
+
+/// Whether the built-in catalog has a message for `code`
+pub fn has_message(code: &str) -> bool {
+    entry(code).is_some()
+}
+
+fn entry(code: &str) -> Option<&'static (&'static str, &'static str, &'static str)> {
+    CATALOG.iter().find(|(c, _, _)| *c == code)
+}
+
+/// Tags like `en`, `pt-BR` or `zh_Hant`
+fn validate_locale(locale: &str) -> Result<(), String> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if locale.is_empty() || !locale.chars().all(valid) {
+        return Err(format!("messages locale {:?} should be a language tag like \"en\" or \"pt-BR\"", locale));
+    }
+    Ok(())
+}
+
+enum Kind {
+    Path,
+    Domain,
+    Command,
+    Other,
+}
+
+fn kind(operation: &str) -> Kind {
+    match operation {
+        "http_request" | "dns_lookup" | "check_connect" => Kind::Domain,
+        "execute_command" => Kind::Command,
+        "report_connection" | "" => Kind::Other,
+        _ => Kind::Path,
+    }
+}
+
+/// The host of a URL, or `target` itself when it is a bare name
+fn host(target: &str) -> &str {
+    let rest = target.split_once("://").map_or(target, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or(bracketed),
+        None => authority.split(':').next().unwrap_or(authority),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part<'a> {
+    Text(&'a str),
+    Param(&'a str),
+}
+
+fn parse(template: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(at) = rest.find(['{', '}']) {
+        if at > 0 {
+            parts.push(Part::Text(&rest[..at]));
+        }
+        let brace = &rest[at..at + 1];
+        rest = &rest[at + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            parts.push(Part::Text(brace));
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            return Err("unmatched \"}\"; write \"}}\" for a brace".to_string());
+        }
+        let (name, after) = rest.split_once('}').ok_or("unclosed \"{\"; write \"{{\" for a brace")?;
+        if !PARAMS.contains(&name) {
+            return Err(format!("{{{}}} is not one of {{{}}}", name, PARAMS.join("}, {")));
+        }
+        parts.push(Part::Param(name));
+        rest = after;
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest));
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(locale: &str, catalogs: &[(&str, &str, &str)]) -> MessagesPolicy {
+        let mut policy = MessagesPolicy {
+            locale: locale.to_string(),
+            ..Default::default()
+        };
+        for (locale, code, template) in catalogs {
+            let catalog = policy.catalogs.entry(locale.to_string()).or_default();
+            catalog.insert(code.to_string(), template.to_string());
+        }
+        policy
+    }
+
+    #[test]
+    fn test_reasons_render_from_the_selected_catalog() {
+        let read = Params {
+            operation: "read_file",
+            target: "/etc/shadow",
+            detail: "Path not in allowed read list",
+        };
+        let http = Params {
+            operation: "http_request",
+            target: "https://user@evil.example.com:8443/x?y",
+            detail: "Domain not in allowed list: evil.example.com",
+        };
+        let builtin = Messages::new(&MessagesPolicy::default()).unwrap();
+        assert_eq!(builtin.render("path_not_allowed", &read), "Access to /etc/shadow is not allowed");
+        assert_eq!(builtin.render("domain_not_allowed", &http), "Access to evil.example.com is not allowed");
+        assert_eq!(builtin.render("no_such_code", &read), read.detail);
+        assert_eq!(host("[::1]:80"), "::1");
+        assert_eq!(host("api.example.com"), "api.example.com");
+
+        let messages = Messages::new(&policy(
+            "de",
+            &[
+                ("de", "path_not_allowed", "Kein Zugriff auf {path} ({rule}, {{{operation}}})"),
+                ("fr", "path_not_allowed", "Accès refusé"),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(messages.render("path_not_allowed", &read), "Kein Zugriff auf /etc/shadow ([filesystem], {read_file})");
+        // Codes the locale leaves out fall back to the built-in catalog
+        assert_eq!(messages.render("domain_not_allowed", &http), "Access to evil.example.com is not allowed");
+    }
+
+    #[test]
+    fn test_invalid_catalogs_fail_the_load() {
+        let invalid = [
+            policy("de", &[]),
+            policy("e n", &[]),
+            policy("en", &[("en", "path_not_alowed", "Nope")]),
+            policy("en", &[("en", "path_not_allowed", "Nope {file}")]),
+            policy("en", &[("en", "path_not_allowed", "Nope {path")]),
+            policy("en", &[("en", "path_not_allowed", "Nope }")]),
+        ];
+        for policy in invalid {
+            assert!(Messages::new(&policy).is_err(), "{:?}", policy);
+        }
+        for (_, _, template) in CATALOG {
+            assert!(parse(template).is_ok(), "{}", template);
+        }
+    }
+}
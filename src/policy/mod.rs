@@ -1,46 +1,956 @@
+pub mod diff;
+pub mod edits;
+pub mod examples;
+pub mod merge;
+pub mod messages;
+pub mod overrides;
 pub mod parser;
+pub mod rules;
+pub mod schedule;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod signature;
+pub mod suggest;
 
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
 use anyhow::Result;
+use parser::{PolicyFormat, PolicySource};
 
+/// An OpenClaw Enforce policy, read from TOML or JSON
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Policy {
+    /// Directory that relative filesystem entries are resolved against.
+    /// Defaults to the directory of the policy file.
+    #[serde(default)]
+    pub workspace_root: Option<String>,
+    /// Values that path and domain entries reference as `$name` or
+    /// `${name}`, expanded when the policy loads (`[vars]`)
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
     pub filesystem: FilesystemPolicy,
     pub network: NetworkPolicy,
     pub process: ProcessPolicy,
     pub resources: ResourcePolicy,
+    #[serde(default)]
+    pub audit: AuditPolicy,
+    #[serde(default)]
+    pub server: ServerPolicy,
+    #[serde(default)]
+    pub escalation: EscalationPolicy,
+    #[serde(default)]
+    pub sessions: SessionsPolicy,
+    #[serde(default)]
+    pub enforcement: EnforcementPolicy,
+    #[serde(default)]
+    pub performance: PerformancePolicy,
+    #[serde(default)]
+    pub state: StatePolicy,
+    #[serde(default)]
+    pub schedule: SchedulePolicy,
+    #[serde(default)]
+    pub operations: OperationsPolicy,
+    #[serde(default)]
+    pub messages: MessagesPolicy,
 }
 
+/// Paths the agent may read and write (`[filesystem]`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FilesystemPolicy {
+    /// Paths the agent may read: absolute prefixes, or globs. A leading
+    /// `<tmp>` stands for the system temp directory
     pub allowed_read: Vec<String>,
+    /// Paths the agent may write, matched like `allowed_read`
     pub allowed_write: Vec<String>,
+    /// Paths whose size, mtime and type `stat_file` returns without their
+    /// content, matched like `allowed_read`. Readable paths may be stated
+    /// too, so this only needs what is broader.
+    #[serde(default)]
+    pub allowed_stat: Vec<String>,
+    /// Glob patterns that are always denied; entries starting with `!`
+    /// exempt matching paths from the deny list
     pub denied_patterns: Vec<String>,
+    /// Merge the built-in list of sensitive locations into `denied_patterns`
+    #[serde(default = "default_true")]
+    pub use_default_denylist: bool,
+    /// Ordered allow/deny rules consulted after the deny list and before
+    /// `allowed_read`/`allowed_write`
+    #[serde(default)]
+    pub rules: Vec<PathRule>,
+    /// Which matching rule decides when several match
+    #[serde(default)]
+    pub match_strategy: MatchStrategy,
+    /// How `allowed_read`, `allowed_write` and `allowed_stat` entries
+    /// match a request path
+    #[serde(default)]
+    pub path_match_mode: PathMatchMode,
+    /// What an empty `allowed_read` or `allowed_write` allows: nothing,
+    /// the default, or every path the deny list and rules leave
+    #[serde(default)]
+    pub empty_allow_means: EmptyAllowMeans,
+    /// Resolve relative request paths against the daemon's working
+    /// directory instead of rejecting them
+    #[serde(default)]
+    pub allow_relative_paths: bool,
+    /// Answer reads of readable paths that do not exist with a
+    /// `path_not_found` denial, rather than allowing them and failing the read
+    #[serde(default)]
+    pub require_exists: bool,
+    /// File extensions, without the dot, that are never writable whatever
+    /// the allow lists and rules say, e.g. `["sh", "exe", "so"]`. Matched
+    /// against the last extension, ignoring ASCII case.
+    #[serde(default)]
+    pub denied_write_extensions: Vec<String>,
+    /// Deny every name of the files the deny list matches when the policy
+    /// loads, so a hardlink to one is denied too (Unix only)
+    #[serde(default)]
+    pub check_inodes: bool,
+    /// Move deleted files here instead of unlinking them. Paths under the
+    /// trash are never readable or writable through the normal API.
+    #[serde(default)]
+    pub trash_dir: Option<String>,
+    /// Trashed files older than this are purged; 0 keeps them indefinitely
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+    /// Where `create_temp_file` makes its files, each writable by the
+    /// session that created it whether or not this is in `allowed_write`
+    #[serde(default)]
+    pub scratch_dir: Option<String>,
+    /// Permissions for files created by `write_file` (Unix only)
+    #[serde(default = "default_write_mode")]
+    pub write_mode: u32,
+    /// Concurrent `watch_file` streams allowed per session
+    #[serde(default = "default_max_watches_per_client")]
+    pub max_watches_per_client: u32,
+    /// Seconds between heartbeat events on an idle watch stream
+    #[serde(default = "default_watch_heartbeat_secs")]
+    pub watch_heartbeat_secs: u64,
+    #[serde(default)]
+    pub search: SearchPolicy,
+    /// Contents of matching files are masked before `read_file` returns them
+    #[serde(default)]
+    pub redaction: Vec<RedactionRule>,
+    /// Form request paths and policy entries are normalized to before
+    /// matching, so composed and decomposed spellings of a name match alike
+    #[serde(default)]
+    pub unicode_normalization: UnicodeNormalization,
+    /// Compare request paths and policy entries ignoring case, for volumes
+    /// that do, as APFS and NTFS do by default. Unset, it is on when an
+    /// allow list entry is on such a volume.
+    #[serde(default)]
+    pub case_insensitive_fs: Option<bool>,
 }
 
+impl Default for FilesystemPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_read: vec![],
+            allowed_write: vec![],
+            allowed_stat: vec![],
+            denied_patterns: vec![],
+            use_default_denylist: true,
+            rules: vec![],
+            match_strategy: MatchStrategy::default(),
+            path_match_mode: PathMatchMode::default(),
+            empty_allow_means: EmptyAllowMeans::default(),
+            allow_relative_paths: false,
+            require_exists: false,
+            denied_write_extensions: vec![],
+            check_inodes: false,
+            trash_dir: None,
+            trash_retention_days: default_trash_retention_days(),
+            scratch_dir: None,
+            write_mode: default_write_mode(),
+            max_watches_per_client: default_max_watches_per_client(),
+            watch_heartbeat_secs: default_watch_heartbeat_secs(),
+            search: SearchPolicy::default(),
+            redaction: vec![],
+            unicode_normalization: UnicodeNormalization::default(),
+            case_insensitive_fs: None,
+        }
+    }
+}
+
+/// Bounds on `search_files` (`[filesystem.search]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SearchPolicy {
+    /// Results per search; requests asking for more are capped
+    pub max_results: u32,
+    /// Wall-clock seconds a search may run before results are cut off
+    pub time_budget_secs: u64,
+    /// Larger files are skipped
+    pub max_file_kb: u64,
+}
+
+impl Default for SearchPolicy {
+    fn default() -> Self {
+        Self {
+            max_results: 1000,
+            time_budget_secs: 10,
+            max_file_kb: 1024,
+        }
+    }
+}
+
+/// An entry of `[[filesystem.redaction]]`: `rules` are applied in order
+/// to the contents of files whose absolute path matches the `pattern` glob
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct RedactionRule {
+    pub pattern: String,
+    pub rules: Vec<RedactionPattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct RedactionPattern {
+    pub regex: String,
+    /// May refer to capture groups, e.g. `"$1=***"`
+    pub replacement: String,
+}
+
+/// An entry of `[[filesystem.rules]]`. `path` is matched like the allow
+/// lists: as a prefix, or as a glob when it contains glob characters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PathRule {
+    pub path: String,
+    pub action: RuleAction,
+    #[serde(default)]
+    pub access: RuleAccess,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAccess {
+    Read,
+    Write,
+    #[default]
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MatchStrategy {
+    #[default]
+    FirstMatch,
+    LastMatch,
+}
+
+/// `[filesystem] path_match_mode`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum PathMatchMode {
+    /// A literal prefix, or a glob as well when the entry has glob
+    /// characters
+    #[default]
+    Hybrid,
+    /// A literal prefix; `*`, `?` and `[` are plain characters
+    Prefix,
+    /// A glob over the whole path, so a tree is `/srv/*`
+    Glob,
+    /// A regular expression anchored at the start of the path
+    Regex,
+}
+
+/// `[filesystem] empty_allow_means`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyAllowMeans {
+    /// An empty list allows no path
+    #[default]
+    None,
+    /// An empty list puts no restriction on paths; `denied_patterns`, the
+    /// default denylist and `rules` still apply
+    All,
+}
+
+/// `[filesystem] unicode_normalization`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeNormalization {
+    /// Match names byte for byte
+    #[default]
+    None,
+    /// Canonical composition, as most input methods produce
+    Nfc,
+    /// Canonical decomposition, as HFS+ stores names
+    Nfd,
+}
+
+/// Outbound network access (`[network]`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NetworkPolicy {
+    /// Domains requests may go to; `*.example.com` covers subdomains
     pub allowed_domains: Vec<String>,
+    /// Addresses and CIDR ranges never connected to
     pub blocked_ips: Vec<String>,
+    /// Files of more ranges to block, one address or CIDR per line, e.g. a
+    /// cloud provider's published ranges. Read again on each reload.
+    #[serde(default)]
+    pub blocked_ip_lists: Vec<String>,
+    /// Connections open at once across all domains
     pub max_connections: u32,
+    /// Caps on open connections per domain (exact or `*.` wildcard), on
+    /// top of `max_connections`
+    #[serde(default)]
+    pub max_connections_per_domain: BTreeMap<String, u32>,
+    /// Concurrency and daily traffic budgets per domain (`[[network.quotas]]`).
+    /// Read at startup only.
+    #[serde(default)]
+    pub quotas: Vec<DomainQuota>,
+    /// Keeps the day's quota usage across restarts; defaults to
+    /// `quotas.json` in `[state] dir`
+    #[serde(default)]
+    pub quota_state_file: Option<String>,
+    /// Hour of the day, 0 to 23 UTC, at which quota usage starts over
+    #[serde(default)]
+    pub quota_reset_hour_utc: u32,
+    #[serde(default)]
+    pub http: HttpPolicy,
+    #[serde(default)]
+    pub connect: ConnectPolicy,
+    /// Egress proxy for `http_request` (`http://` or `socks5://`)
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    /// Environment variable holding proxy credentials as `user:password`
+    #[serde(default)]
+    pub proxy_auth_env: Option<String>,
+    /// Refuse any request that would not go through the proxy
+    #[serde(default)]
+    pub require_proxy: bool,
+    /// Domains that bypass the proxy, matched like `allowed_domains`
+    #[serde(default)]
+    pub exempt_domains: Vec<String>,
+    /// Reject resolved addresses in loopback, link-local, RFC1918, CGNAT
+    /// and unique-local ranges
+    #[serde(default = "default_true")]
+    pub block_private_addresses: bool,
+    /// Private ranges that remain reachable when `block_private_addresses` is on
+    #[serde(default)]
+    pub allowed_private_cidrs: Vec<String>,
+    /// Permit cloud instance metadata endpoints such as 169.254.169.254.
+    /// Doing so lets anything that can reach an allowed domain steal
+    /// instance credentials.
+    #[serde(default)]
+    pub dangerously_allow_metadata_endpoints: bool,
+}
+
+impl Default for NetworkPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_domains: vec![],
+            blocked_ips: vec![],
+            blocked_ip_lists: vec![],
+            max_connections: 10,
+            max_connections_per_domain: BTreeMap::new(),
+            quotas: vec![],
+            quota_state_file: None,
+            quota_reset_hour_utc: 0,
+            http: HttpPolicy::default(),
+            connect: ConnectPolicy::default(),
+            proxy_url: None,
+            proxy_auth_env: None,
+            require_proxy: false,
+            exempt_domains: vec![],
+            block_private_addresses: true,
+            allowed_private_cidrs: vec![],
+            dangerously_allow_metadata_endpoints: false,
+        }
+    }
+}
+
+/// Restrictions applied to outbound HTTP requests (`[network.http]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct HttpPolicy {
+    pub allowed_methods: Vec<String>,
+    /// A request carrying one of these headers is denied unless the
+    /// domain is trusted
+    pub blocked_request_headers: Vec<String>,
+    /// Domains allowed to receive `blocked_request_headers`
+    pub trusted_domains: Vec<String>,
+    pub max_request_body_kb: u64,
+    /// Responses larger than this are truncated, not rejected
+    pub max_response_body_mb: u64,
+    /// Per-domain overrides, matched like `allowed_domains`
+    pub overrides: Vec<HttpOverride>,
+}
+
+impl Default for HttpPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            blocked_request_headers: vec!["Authorization".to_string(), "Cookie".to_string()],
+            trusted_domains: vec![],
+            max_request_body_kb: 1024,
+            max_response_body_mb: 10,
+            overrides: vec![],
+        }
+    }
+}
+
+/// Budgets for one domain (exact or `*.` wildcard); all hosts matching an
+/// entry share it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DomainQuota {
+    pub domain: String,
+    /// HTTP requests in flight plus connection leases at once; 0 is
+    /// unlimited
+    #[serde(default)]
+    pub max_concurrent: u32,
+    /// Request and response bytes per day; 0 is unlimited
+    #[serde(default)]
+    pub max_bytes_per_day: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HttpOverride {
+    pub domain: String,
+    pub allowed_methods: Option<Vec<String>>,
+    pub blocked_request_headers: Option<Vec<String>>,
+    pub max_request_body_kb: Option<u64>,
+    pub max_response_body_mb: Option<u64>,
+}
+
+/// Restrictions applied to `check_connect` (`[network.connect]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ConnectPolicy {
+    pub allow_tcp: bool,
+    pub allow_tls: bool,
+    pub allow_ws: bool,
+    pub allow_wss: bool,
+    pub allowed_ports: Vec<u16>,
+    /// Seconds a granted lease stays valid before it is reported opened
+    pub lease_ttl_secs: u64,
 }
 
+impl Default for ConnectPolicy {
+    fn default() -> Self {
+        Self {
+            allow_tcp: false,
+            allow_tls: true,
+            allow_ws: true,
+            allow_wss: true,
+            allowed_ports: vec![80, 443],
+            lease_ttl_secs: 30,
+        }
+    }
+}
+
+/// Commands the agent may run (`[process]`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ProcessPolicy {
-    pub allowed_commands: Vec<String>,
+    pub allowed_commands: Vec<CommandEntry>,
+    /// CPU share a command may use, in percent
     pub max_cpu_percent: u32,
+    /// Memory a command may use
     pub max_memory_mb: u64,
+    /// Directories searched for bare command names, in order. Empty uses
+    /// the daemon's `PATH`.
+    #[serde(default)]
+    pub search_path: Vec<String>,
+    /// Variables of the daemon's environment that commands inherit; all
+    /// others are stripped. Empty starts commands with no environment
+    /// beyond what the request sets.
+    #[serde(default)]
+    pub allowed_env: Vec<String>,
+}
+
+/// An entry of `allowed_commands`: an absolute path allowing exactly that
+/// binary, a bare name allowing whatever it resolves to on `search_path`,
+/// or a table with extra conditions
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(untagged)]
+pub enum CommandEntry {
+    Plain(String),
+    Spec(CommandSpec),
+}
+
+/// `{ name = "node", path = "/usr/local/bin/node", min_version = "20" }`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(deny_unknown_fields)]
+pub struct CommandSpec {
+    /// Name the command is invoked by
+    pub name: String,
+    /// Binary the name must resolve to; otherwise it is looked up on `search_path`
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Lowest accepted version, compared against the first version number
+    /// printed by `<binary> --version`
+    #[serde(default)]
+    pub min_version: Option<String>,
 }
 
+/// Limits on the daemon's own resource use (`[resources]`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ResourcePolicy {
+    /// Largest file read or written
     pub max_file_size_mb: u64,
+    /// Files the daemon may hold open
     pub max_open_files: u32,
+    /// Warn (without denying anything) once the daemon holds this many files
+    #[serde(default)]
+    pub warn_open_files: Option<u32>,
+    /// Warn once the daemon's resident memory reaches this size
+    #[serde(default)]
+    pub warn_memory_mb: Option<u64>,
+    /// Minimum seconds between repeated warnings for the same resource
+    #[serde(default = "default_warn_interval_secs")]
+    pub warn_interval_secs: u64,
+}
+
+/// gRPC transport settings (`[server]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ServerPolicy {
+    /// Compress responses for clients that advertise support
+    pub compression: Compression,
+    /// Bearer token required by admin RPCs such as `reload_policy`; they
+    /// are disabled while unset
+    pub admin_token: Option<String>,
+    /// Bearer tokens for `add_policy_entry` and `remove_policy_entry`, by
+    /// the name edits made with them are audited under. `admin_token` does
+    /// not authorize edits; they are disabled while this is empty.
+    pub policy_editors: BTreeMap<String, String>,
+    /// Where edits made with `persist` are recorded. The file is applied
+    /// on top of the policy whenever it loads.
+    pub policy_edits_file: Option<String>,
+    /// Largest request message accepted, after decompression. Larger
+    /// requests are rejected by the transport before any policy check.
+    /// Read at startup only.
+    pub max_message_bytes: usize,
+    /// Longest path, hostname, command or policy entry a request may name,
+    /// in bytes. Longer ones are refused with INVALID_ARGUMENT before the
+    /// request is decoded. Read at startup only, like the two below.
+    pub max_target_bytes: usize,
+    /// Longest URL an `http_request` may name
+    pub max_url_bytes: usize,
+    /// Seconds a call may run when the client set no deadline; 0 lets it
+    /// run until it finishes
+    pub default_deadline_secs: u64,
+    /// Most `EnforcementService` calls served at once; more are refused
+    /// with RESOURCE_EXHAUSTED until one ends. A stream counts until it
+    /// ends. Health checks are never refused. 0 sets no limit; read at
+    /// startup only.
+    pub max_concurrent_requests: usize,
+    /// p99 path-decision time, in milliseconds, above which the daemon
+    /// sheds optional work until it recovers; 0 disables the check
+    pub latency_budget_ms: u64,
+    /// User, by name or uid, to switch to once the socket is bound. Read
+    /// at startup only, like the two below.
+    pub run_as_user: Option<String>,
+    /// Group to switch to; defaults to the primary group of `run_as_user`
+    pub run_as_group: Option<String>,
+    /// Linux: set PR_SET_NO_NEW_PRIVS and clear ambient capabilities
+    pub no_new_privs: bool,
+}
+
+impl Default for ServerPolicy {
+    fn default() -> Self {
+        Self {
+            compression: Compression::None,
+            admin_token: None,
+            policy_editors: BTreeMap::new(),
+            policy_edits_file: None,
+            max_message_bytes: 16 * 1024 * 1024,
+            max_target_bytes: 4096,
+            max_url_bytes: 8192,
+            default_deadline_secs: 60,
+            max_concurrent_requests: 0,
+            latency_budget_ms: 0,
+            run_as_user: None,
+            run_as_group: None,
+            no_new_privs: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+}
+
+/// Asking a human about denied requests (`[escalation]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct EscalationPolicy {
+    pub mode: EscalationMode,
+    /// `ApprovalService` endpoint the daemon calls, e.g. `http://127.0.0.1:50052`
+    pub address: Option<String>,
+    /// Operations whose denials are escalated, e.g. `["write_file"]`
+    pub operations: Vec<String>,
+    /// Seconds to wait for an answer before denying
+    pub timeout_secs: u64,
+    /// Upper bound on the TTL of an `allow_session` approval
+    pub max_grant_secs: u64,
+    /// Pairs of operations a session cannot hold grants for at once, e.g.
+    /// `[["write_file", "execute_command"]]`; a grant for either revokes the
+    /// session's grants for the other. A pair naming one operation twice
+    /// leaves a session one grant of it at a time.
+    pub conflicts: Vec<[String; 2]>,
+}
+
+impl Default for EscalationPolicy {
+    fn default() -> Self {
+        Self {
+            mode: EscalationMode::Off,
+            address: None,
+            operations: vec![],
+            timeout_secs: 30,
+            max_grant_secs: 3600,
+            conflicts: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum EscalationMode {
+    /// Denials are final
+    #[default]
+    Off,
+    /// Denials are offered to the approval service at `address`
+    Callback,
+}
+
+/// Defaults for sessions created with `CreateSession` (`[sessions]`).
+/// A budget of 0 is unlimited.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SessionsPolicy {
+    /// Seconds a session lives unless `CreateSession` asks otherwise
+    pub ttl_secs: u64,
+    /// Sessions that may be live at once
+    pub max_sessions: u32,
+    pub max_read_mb: u64,
+    pub max_files_written: u64,
+    pub max_commands_run: u64,
+}
+
+impl Default for SessionsPolicy {
+    fn default() -> Self {
+        Self {
+            ttl_secs: 3600,
+            max_sessions: 1000,
+            max_read_mb: 0,
+            max_files_written: 0,
+            max_commands_run: 0,
+        }
+    }
+}
+
+/// Runtime state kept across restarts (`[state]`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct StatePolicy {
+    /// Directory temporary grants, session budgets and quota usage are
+    /// saved in. Never readable or writable through the API. Unset, they
+    /// start over with each restart.
+    pub dir: Option<String>,
+}
+
+/// A class of request, as `[schedule]` windows and `[operations]` name
+/// them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// ReadFile, StatFile, SearchFiles and WatchFile
+    Read,
+    /// WriteFile, WriteFileStream, EditFile, RestoreFile and CreateTempFile
+    Write,
+    /// DeleteFile and CleanupTempFiles
+    Delete,
+    /// ExecuteCommand
+    Execute,
+    /// HttpRequest
+    Http,
+    /// DnsLookup
+    Dns,
+    /// CheckConnect
+    Connect,
+}
+
+/// Times of day operations are confined to (`[schedule]`). An operation
+/// that no window names may run at any time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SchedulePolicy {
+    /// Offset from UTC the windows are in, e.g. "+02:00". It is fixed, so
+    /// a change to daylight saving time needs the policy changed.
+    pub utc_offset: String,
+    pub windows: Vec<ScheduleWindow>,
+}
+
+impl Default for SchedulePolicy {
+    fn default() -> Self {
+        Self {
+            utc_offset: "+00:00".to_string(),
+            windows: Vec::new(),
+        }
+    }
+}
+
+/// A window the named operations may run in; with several naming one
+/// operation, any of them will do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ScheduleWindow {
+    pub operations: Vec<Operation>,
+    /// Start, as "09:00"
+    pub start: String,
+    /// End, not included; one before `start` runs past midnight
+    pub end: String,
+    /// Days the window starts on; every day when empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+/// Operations switched off outright, whatever the lists allow
+/// (`[operations]`). All are on by default.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct OperationsPolicy {
+    pub read: bool,
+    pub write: bool,
+    pub delete: bool,
+    pub execute: bool,
+    pub http: bool,
+    pub dns: bool,
+    pub connect: bool,
+}
+
+impl Default for OperationsPolicy {
+    fn default() -> Self {
+        Self {
+            read: true,
+            write: true,
+            delete: true,
+            execute: true,
+            http: true,
+            dns: true,
+            connect: true,
+        }
+    }
+}
+
+/// The wording of the reasons clients are shown (`[messages]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct MessagesPolicy {
+    /// Catalog reasons are taken from; "en" is built in, any other needs
+    /// an entry in `catalogs`
+    pub locale: String,
+    /// Templates by locale, then by violation code, e.g.
+    /// `[messages.catalogs.en] path_not_allowed = "{path} is off limits"`.
+    /// Codes a catalog leaves out are worded as the built-in one has them.
+    pub catalogs: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Default for MessagesPolicy {
+    fn default() -> Self {
+        Self {
+            locale: messages::DEFAULT_LOCALE.to_string(),
+            catalogs: BTreeMap::new(),
+        }
+    }
+}
+
+/// Caching of decisions, and walking trees (`[performance]`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct PerformancePolicy {
+    /// Path decisions remembered, least recently used first out; 0 turns
+    /// the cache off. It is emptied whenever the policy is reloaded.
+    pub decision_cache_size: u32,
+    /// Seconds a cached decision is reused for; 0 until it is evicted
+    pub decision_cache_ttl_secs: u64,
+    /// Threads walking the tree of a `search_files` or recursive
+    /// `list_directory`; 0 uses the available parallelism
+    pub walk_threads: usize,
+}
+
+/// How decisions fail, and the inputs too large to decide on
+/// (`[enforcement]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct EnforcementPolicy {
+    pub on_internal_error: OnInternalError,
+    /// Longest path checked, in bytes; longer ones are denied with
+    /// `input_too_large` before they are resolved or matched
+    pub max_path_length: usize,
+    /// Most components a path checked may have
+    pub max_path_components: usize,
+    /// Longest hostname checked, in bytes
+    pub max_domain_length: usize,
+    /// Longest command checked, in bytes
+    pub max_command_length: usize,
+}
+
+impl Default for EnforcementPolicy {
+    fn default() -> Self {
+        // PATH_MAX, and the most a DNS name can be
+        Self {
+            on_internal_error: OnInternalError::default(),
+            max_path_length: 4096,
+            max_path_components: 1024,
+            max_domain_length: 253,
+            max_command_length: 4096,
+        }
+    }
+}
+
+/// What a request gets when its check cannot be completed, e.g. because a
+/// path could not be resolved or a file could not be read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum OnInternalError {
+    /// A normal denial with the `internal_error` violation
+    #[default]
+    Deny,
+    /// An `INTERNAL` RPC error
+    Error,
+}
+
+/// Audit trail settings (`[audit]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct AuditPolicy {
+    /// Repeats of the same denial within this many seconds are folded into
+    /// one summary entry; 0 records every denial
+    pub dedupe_window_secs: u64,
+    /// Append every entry to this file as a hash-chained JSON line. Read
+    /// at startup only.
+    pub file: Option<String>,
+    /// Fraction of allowed decisions recorded, from 0.0 to 1.0; denials
+    /// are always recorded
+    pub sample_rate: f64,
+    /// Entries kept, in memory and in `file`, before the oldest are
+    /// evicted; 0 keeps 10,000 in memory and the whole file. Read at
+    /// startup only.
+    pub max_entries: usize,
+    /// Entries older than this are evicted; 0 keeps them. Read at startup
+    /// only.
+    pub max_age_secs: u64,
+}
+
+impl Default for AuditPolicy {
+    fn default() -> Self {
+        Self {
+            dedupe_window_secs: 60,
+            file: None,
+            sample_rate: 1.0,
+            max_entries: 0,
+            max_age_secs: 0,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_warn_interval_secs() -> u64 {
+    300
+}
+
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
+fn default_write_mode() -> u32 {
+    0o600
+}
+
+fn default_max_watches_per_client() -> u32 {
+    4
+}
+
+fn default_watch_heartbeat_secs() -> u64 {
+    15
 }
 
 impl Policy {
-    pub fn from_file(path: &PathBuf) -> Result<Self> {
-        parser::load_policy(path)
+    pub fn load(source: &PolicySource, format: Option<PolicyFormat>) -> Result<Self> {
+        parser::load_policy(source, format)
+    }
+
+    /// [`load`](Self::load), with the changes made to entries as it
+    /// loaded; with `key`, only if the file is signed by it
+    pub fn load_normalized(
+        source: &PolicySource,
+        format: Option<PolicyFormat>,
+        key: Option<&signature::PolicyKey>,
+    ) -> Result<(Self, Vec<parser::Normalization>)> {
+        parser::load_policy_normalized(source, format, key)
+    }
+
+    /// `overlay` composed onto this policy; see [`merge`](mod@merge)
+    pub fn merge(&self, overlay: &Policy, strategy: merge::MergeStrategy) -> Result<merge::Merged> {
+        merge::merge(self, overlay, strategy)
+    }
+
+    /// One of the example policies, parsed and validated like any other
+    pub fn example(profile: examples::Profile) -> Result<Self> {
+        Self::load(&PolicySource::Inline(profile.source().to_string()), Some(PolicyFormat::Toml))
     }
 }
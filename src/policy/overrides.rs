@@ -0,0 +1,238 @@
+//! Policy settings overridden from the environment
+//!
+//! A variable named `OPENCLAW_ENFORCE__<SECTION>__<KEY>` replaces the
+//! setting `section.key` of the policy that was read, before it is
+//! normalized and validated:
+//!
+//! ```sh
+//! OPENCLAW_ENFORCE__PROCESS__MAX_MEMORY_MB=2048
+//! OPENCLAW_ENFORCE__NETWORK__ALLOWED_DOMAINS="a.com,b.com"
+//! OPENCLAW_ENFORCE__FILESYSTEM__ALLOWED_READ__APPEND="/srv/data"
+//! ```
+//!
+//! Nested tables add a segment each, e.g.
+//! `OPENCLAW_ENFORCE__FILESYSTEM__SEARCH__TIME_BUDGET_SECS`. Lists are
+//! comma-separated; the `__APPEND` suffix adds to a list instead of
+//! replacing it. The value is read as the type of the setting it replaces,
+//! so a number that does not parse fails the load, naming the variable.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use super::parser::Normalization;
+use super::Policy;
+
+/// What every override variable starts with
+pub const OVERRIDE_PREFIX: &str = "OPENCLAW_ENFORCE__";
+
+const APPEND_SUFFIX: &str = "__APPEND";
+
+/// A policy setting given by an environment variable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    /// The variable, e.g. `OPENCLAW_ENFORCE__PROCESS__MAX_MEMORY_MB`
+    pub var: String,
+    /// The setting it names, e.g. `process.max_memory_mb`
+    pub setting: String,
+    pub value: String,
+    pub append: bool,
+}
+
+impl Override {
+    /// None for variables without [`OVERRIDE_PREFIX`]
+    fn parse(var: &str, value: &str) -> Option<Result<Self>> {
+        let name = var.strip_prefix(OVERRIDE_PREFIX)?;
+        let (name, append) = match name.strip_suffix(APPEND_SUFFIX) {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        if name.is_empty() || name.split("__").any(str::is_empty) {
+            return Some(Err(anyhow::anyhow!(
+                "{}: expected a name like {}PROCESS__MAX_MEMORY_MB",
+                var,
+                OVERRIDE_PREFIX
+            )));
+        }
+        Some(Ok(Self {
+            var: var.to_string(),
+            setting: name.split("__").map(str::to_ascii_lowercase).collect::<Vec<_>>().join("."),
+            value: value.to_string(),
+            append,
+        }))
+    }
+
+    /// The note listed by `--validate` and `--show-policy`
+    fn note(&self) -> Normalization {
+        let change = if self.append {
+            format!("{:?} appended by ${}", self.value, self.var)
+        } else {
+            format!("set to {:?} by ${}", self.value, self.var)
+        };
+        Normalization {
+            entry: self.setting.clone(),
+            change,
+        }
+    }
+
+    /// Replaces or extends the setting in `policy`, as its value
+    fn apply_to(&self, policy: &mut Value) -> Result<()> {
+        let mut path = self.setting.split('.');
+        let mut setting = path.next().and_then(|key| policy.get_mut(key));
+        for key in path {
+            setting = setting.filter(|value| value.is_object()).and_then(|value| value.get_mut(key));
+        }
+        let setting = setting.with_context(|| format!("{}: {} is not a policy setting", self.var, self.setting))?;
+        match setting {
+            Value::Array(entries) => {
+                let values = self.value.split(',').map(str::trim).filter(|v| !v.is_empty());
+                let values = values.map(|v| Value::String(v.to_string()));
+                if !self.append {
+                    entries.clear();
+                }
+                entries.extend(values);
+                return Ok(());
+            }
+            _ if self.append => {
+                anyhow::bail!("{}: {} is not a list, so it cannot be appended to", self.var, self.setting)
+            }
+            Value::Object(_) => {
+                anyhow::bail!("{}: {} is a table; set its keys one at a time", self.var, self.setting)
+            }
+            Value::Bool(_) => {
+                let value = self.value.parse().ok();
+                *setting = Value::Bool(value.with_context(|| self.expected("true or false"))?);
+            }
+            Value::Number(_) => *setting = self.number().with_context(|| self.expected("a number"))?,
+            Value::String(_) => *setting = Value::String(self.value.clone()),
+            // Unset optional settings take a number if it reads as one
+            Value::Null => *setting = self.number().unwrap_or_else(|| Value::String(self.value.clone())),
+        }
+        Ok(())
+    }
+
+    fn number(&self) -> Option<Value> {
+        let value = self.value.trim();
+        value
+            .parse::<u64>()
+            .map(Value::from)
+            .or_else(|_| value.parse::<i64>().map(Value::from))
+            .ok()
+            .or_else(|| value.parse::<f64>().ok().and_then(|v| serde_json::Number::from_f64(v).map(Value::Number)))
+    }
+
+    fn expected(&self, what: &str) -> String {
+        format!("{}: {} must be {}, got {:?}", self.var, self.setting, what, self.value)
+    }
+}
+
+/// The overrides among `vars`, sorted by variable so they apply in the
+/// same order wherever the environment lists them
+pub fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<Vec<Override>> {
+    let mut overrides = vars
+        .into_iter()
+        .filter_map(|(var, value)| Override::parse(&var, &value))
+        .collect::<Result<Vec<_>>>()?;
+    overrides.sort_by(|a, b| a.var.cmp(&b.var));
+    Ok(overrides)
+}
+
+/// `policy` with `overrides` applied, and a note for each. Each is checked
+/// as it is applied, so a value of the wrong type is reported with the
+/// variable that gave it.
+pub fn apply(policy: Policy, overrides: &[Override]) -> Result<(Policy, Vec<Normalization>)> {
+    if overrides.is_empty() {
+        return Ok((policy, Vec::new()));
+    }
+    let mut value = serde_json::to_value(&policy).context("Failed to apply policy overrides")?;
+    let mut policy = policy;
+    for over in overrides {
+        over.apply_to(&mut value)?;
+        policy = serde_json::from_value(value.clone())
+            .with_context(|| format!("{}: invalid value {:?} for {}", over.var, over.value, over.setting))?;
+    }
+    Ok((policy, overrides.iter().map(Override::note).collect()))
+}
+
+/// Problems found in the overridden policy, each that names an overridden
+/// setting followed by the variable that set it
+pub fn annotate(problems: Vec<String>, overrides: &[Override]) -> Vec<String> {
+    problems
+        .into_iter()
+        .map(|problem| {
+            let vars: Vec<&str> = overrides
+                .iter()
+                .filter(|over| problem.contains(&over.setting))
+                .map(|over| over.var.as_str())
+                .collect();
+            if vars.is_empty() {
+                problem
+            } else {
+                format!("{} (set by ${})", problem, vars.join(", $"))
+            }
+        })
+        .collect()
+}
+
+impl Normalization {
+    /// Whether the setting was given by an environment override
+    pub fn is_override(&self) -> bool {
+        self.change.contains(&format!(" by ${}", OVERRIDE_PREFIX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::examples::Profile;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(var, value)| (var.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_overrides_replace_or_extend_settings() {
+        let policy = Policy::example(Profile::Strict).unwrap();
+        let read = policy.filesystem.allowed_read.clone();
+        let overrides = from_vars(vars(&[
+            ("OPENCLAW_ENFORCE__PROCESS__MAX_MEMORY_MB", "2048"),
+            ("OPENCLAW_ENFORCE__NETWORK__ALLOWED_DOMAINS", "a.com, b.com"),
+            ("OPENCLAW_ENFORCE__FILESYSTEM__ALLOWED_READ__APPEND", "/srv/data"),
+            ("OPENCLAW_ENFORCE__FILESYSTEM__SEARCH__TIME_BUDGET_SECS", "3"),
+            ("OPENCLAW_ENFORCE__OPERATIONS__EXECUTE", "false"),
+            ("OPENCLAW_POLICY", "ignored"),
+        ]))
+        .unwrap();
+        assert_eq!(overrides.len(), 5);
+        let (policy, notes) = apply(policy, &overrides).unwrap();
+        assert_eq!(policy.process.max_memory_mb, 2048);
+        assert_eq!(policy.network.allowed_domains, ["a.com", "b.com"]);
+        assert_eq!(policy.filesystem.allowed_read.len(), read.len() + 1);
+        assert_eq!(policy.filesystem.allowed_read.last().unwrap(), "/srv/data");
+        assert_eq!(policy.filesystem.search.time_budget_secs, 3);
+        assert!(!policy.operations.execute);
+
+        assert!(notes.iter().all(Normalization::is_override));
+        let note = notes.iter().find(|n| n.entry == "process.max_memory_mb").unwrap();
+        assert_eq!(note.to_string(), "process.max_memory_mb: set to \"2048\" by $OPENCLAW_ENFORCE__PROCESS__MAX_MEMORY_MB");
+    }
+
+    #[test]
+    fn test_invalid_overrides_name_the_variable() {
+        let invalid = [
+            ("OPENCLAW_ENFORCE__PROCESS__MAX_MEMORY_MB", "lots"),
+            ("OPENCLAW_ENFORCE__PROCESS__MAX_MEMORY_MB", "-1"),
+            ("OPENCLAW_ENFORCE__PROCESS__MAX_MEMORY_MB__APPEND", "1"),
+            ("OPENCLAW_ENFORCE__PROCESS__NO_SUCH_KEY", "1"),
+            ("OPENCLAW_ENFORCE__PROCESS", "1"),
+            ("OPENCLAW_ENFORCE__OPERATIONS__EXECUTE", "no"),
+            ("OPENCLAW_ENFORCE__FILESYSTEM__PATH_MATCH_MODE", "fuzzy"),
+            ("OPENCLAW_ENFORCE__NETWORK____ALLOWED_DOMAINS", "a.com"),
+        ];
+        for (var, value) in invalid {
+            let policy = Policy::example(Profile::Strict).unwrap();
+            let error = from_vars(vars(&[(var, value)])).and_then(|overrides| apply(policy, &overrides));
+            let error = format!("{:#}", error.unwrap_err());
+            assert!(error.contains(var), "{}", error);
+        }
+    }
+}
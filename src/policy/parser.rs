@@ -1,43 +1,1130 @@
 use anyhow::{Context, Result};
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::Hash;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::warn;
 
-use super::Policy;
+use path_absolutize::Absolutize;
 
-pub fn load_policy(path: &PathBuf) -> Result<Policy> {
-    let content = fs::read_to_string(path)
-        .with_context(|| format!("Failed to read policy file: {:?}", path))?;
-    
-    let policy: Policy = toml::from_str(&content)
-        .with_context(|| format!("Failed to parse policy file: {:?}", path))?;
-    
+use super::edits::{EditAction, PolicyEdits};
+use super::merge::Label;
+use super::messages::Messages;
+use super::overrides;
+use super::schedule::Schedule;
+use super::signature::PolicyKey;
+use super::{CommandEntry, Compression, EmptyAllowMeans, EscalationMode, Operation, PathMatchMode, Policy};
+use crate::fs::matcher::has_glob_chars;
+use crate::fs::protected::SelfProtection;
+use crate::fs::validator::PathValidator;
+use crate::fs::redact::Redactor;
+use crate::grpc::approval::{Approver, ESCALATABLE_OPERATIONS};
+use crate::network::address::{AddressGuard, Cidr};
+use crate::network::proxy::ProxyConfig;
+use crate::process::command::Version;
+
+/// Environment variable holding inline policy content, read when no
+/// `--config` is given
+pub const POLICY_ENV_VAR: &str = "OPENCLAW_POLICY";
+
+/// Default policy file when neither `--config` nor the env var is set
+pub const DEFAULT_POLICY_PATH: &str = "policy.toml";
+
+/// Filesystem entries starting with this are under the system temp
+/// directory, as `std::env::temp_dir` finds it when the policy loads
+pub const TMP_TOKEN: &str = "<tmp>";
+
+/// Longest path, pattern, domain or command entry accepted
+const MAX_ENTRY_LEN: usize = 4096;
+/// Most entries accepted in one list
+const MAX_LIST_LEN: usize = 10_000;
+/// Longest interval or lifetime a setting may hold: ten years, so it can
+/// be added to the current time
+const MAX_SECS: u64 = 10 * 365 * 86_400;
+/// Largest size limit in MB (1 PiB), so it can be converted to bytes
+const MAX_MB: u64 = 1 << 30;
+const MAX_WALK_THREADS: usize = 256;
+/// Most decisions `[performance] decision_cache_size` may keep
+const MAX_DECISION_CACHE: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyFormat {
+    Toml,
+    Json,
+}
+
+impl PolicyFormat {
+    /// `.json` files are JSON; everything else is TOML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => PolicyFormat::Json,
+            _ => PolicyFormat::Toml,
+        }
+    }
+}
+
+impl FromStr for PolicyFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(PolicyFormat::Toml),
+            "json" => Ok(PolicyFormat::Json),
+            other => Err(format!("unknown policy format {:?} (expected toml or json)", other)),
+        }
+    }
+}
+
+/// Where the policy is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicySource {
+    File(PathBuf),
+    Stdin,
+    /// Content of [`POLICY_ENV_VAR`]
+    Inline(String),
+}
+
+impl PolicySource {
+    /// `--config -` reads stdin and any other `--config` is a file. Without
+    /// one, non-empty inline content wins over the default file.
+    pub fn resolve(config: Option<PathBuf>, inline: Option<String>) -> Self {
+        match config {
+            Some(path) if path.as_os_str() == "-" => PolicySource::Stdin,
+            Some(path) => PolicySource::File(path),
+            None => match inline {
+                Some(content) if !content.trim().is_empty() => PolicySource::Inline(content),
+                _ => PolicySource::File(PathBuf::from(DEFAULT_POLICY_PATH)),
+            },
+        }
+    }
+
+    /// Where relative entries are anchored when `workspace_root` is unset
+    fn default_workspace_root(&self) -> Option<PathBuf> {
+        let PolicySource::File(path) = self else {
+            return None;
+        };
+        let path = path.absolutize().ok()?;
+        path.parent().map(Path::to_path_buf)
+    }
+
+    /// Files are detected by extension; stdin and inline content are TOML
+    pub fn default_format(&self) -> PolicyFormat {
+        match self {
+            PolicySource::File(path) => PolicyFormat::from_path(path),
+            PolicySource::Stdin | PolicySource::Inline(_) => PolicyFormat::Toml,
+        }
+    }
+}
+
+impl fmt::Display for PolicySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicySource::File(path) => write!(f, "{}", path.display()),
+            PolicySource::Stdin => f.write_str("stdin"),
+            PolicySource::Inline(_) => write!(f, "${}", POLICY_ENV_VAR),
+        }
+    }
+}
+
+/// Reads and validates a policy; `format` overrides the source's default
+pub fn load_policy(source: &PolicySource, format: Option<PolicyFormat>) -> Result<Policy> {
+    parse_policy(&read_source(source)?, format.unwrap_or_else(|| source.default_format()), source)
+}
+
+/// [`load_policy`], with the changes made to entries on the way. With
+/// `key`, the policy must be a file signed by it; see
+/// [`signature`](super::signature).
+pub fn load_policy_normalized(
+    source: &PolicySource,
+    format: Option<PolicyFormat>,
+    key: Option<&PolicyKey>,
+) -> Result<(Policy, Vec<Normalization>)> {
+    let content = read_source(source)?;
+    if let Some(key) = key {
+        let PolicySource::File(path) = source else {
+            anyhow::bail!("A signed policy must be read from a file, with its signature beside it, not from {}", source);
+        };
+        key.verify(path, content.as_bytes())?;
+    }
+    parse_normalized(&content, format.unwrap_or_else(|| source.default_format()), source, key.is_some())
+}
+
+fn read_source(source: &PolicySource) -> Result<String> {
+    Ok(match source {
+        PolicySource::File(path) => fs::read_to_string(path)
+            .with_context(|| format!("Failed to read policy file: {:?}", path))?,
+        PolicySource::Stdin => {
+            let mut content = String::new();
+            std::io::stdin()
+                .read_to_string(&mut content)
+                .context("Failed to read policy from stdin")?;
+            content
+        }
+        PolicySource::Inline(content) => content.clone(),
+    })
+}
+
+/// Parses and validates policy `content`; `source` anchors relative
+/// entries and names the policy in errors
+pub fn parse_policy(content: &str, format: PolicyFormat, source: &PolicySource) -> Result<Policy> {
+    parse_normalized(content, format, source, false).map(|(policy, _)| policy)
+}
+
+/// Settings given by [`overrides`] variables replace those read first.
+/// Entries are checked and rewritten before the policy as a whole is
+/// validated: relative paths are anchored, URLs in domain lists reduced to
+/// their host and repeated entries dropped. Mistakes that would make an
+/// entry silently match nothing are errors naming the setting and index.
+/// Edits persisted to `policy_edits_file` are applied last, unless the
+/// policy is `signed`: those edits are not, so the file is refused, as are
+/// overrides.
+fn parse_normalized(
+    content: &str,
+    format: PolicyFormat,
+    source: &PolicySource,
+    signed: bool,
+) -> Result<(Policy, Vec<Normalization>)> {
+    let policy: Policy = match format {
+        PolicyFormat::Toml => toml::from_str(content).map_err(anyhow::Error::from),
+        PolicyFormat::Json => serde_json::from_str(content).map_err(anyhow::Error::from),
+    }
+    .with_context(|| format!("Failed to parse policy from {}", source))?;
+
+    if signed && policy.server.policy_edits_file.is_some() {
+        anyhow::bail!("[server] policy_edits_file cannot be used with a signed policy, since the edits in it are not signed");
+    }
+    let env = overrides::from_vars(std::env::vars())?;
+    if signed && !env.is_empty() {
+        anyhow::bail!("${} cannot override a signed policy, since the override is not signed", env[0].var);
+    }
+    let (policy, mut notes) = overrides::apply(policy, &env)?;
+    let annotated = |e: anyhow::Error| match e.downcast::<PolicyErrors>() {
+        Ok(PolicyErrors(problems)) => PolicyErrors(overrides::annotate(problems, &env)).into(),
+        Err(e) => e,
+    };
+    let (mut policy, normalized) = normalize(policy, source.default_workspace_root()).map_err(annotated)?;
+    notes.extend(normalized);
+    if let Some(file) = policy.server.policy_edits_file.clone().filter(|f| Path::new(f).is_absolute()) {
+        let edits = PolicyEdits::load(Path::new(&file))?;
+        for (action, list, entry) in edits.apply_to(&mut policy)? {
+            let done = match action {
+                EditAction::Add => "added",
+                EditAction::Remove => "removed",
+            };
+            notes.push(Normalization {
+                entry: list.to_string(),
+                change: format!("{:?} {} by {}", entry, done, file),
+            });
+        }
+    }
+    validate_policy(&policy).map_err(annotated)?;
+
+    Ok((policy, notes))
+}
+
+/// A policy changed in memory, such as by `AddPolicyEntry`, normalized and
+/// validated like one that was read. Its `workspace_root` was set when it
+/// was first loaded, so relative entries anchor the same way.
+pub fn revalidate(policy: Policy) -> Result<Policy> {
+    let (policy, _) = normalize(policy, None)?;
     validate_policy(&policy)?;
-    
     Ok(policy)
 }
 
-fn validate_policy(policy: &Policy) -> Result<()> {
+fn normalize(mut policy: Policy, default_root: Option<PathBuf>) -> Result<(Policy, Vec<Normalization>)> {
+    let mut notes = Vec::new();
+    // Before entries are worked through, however many there are
+    let mut problems = Problems::default();
+    validate_bounds(&policy, &mut problems);
+    problems.finish()?;
+    expand_vars(&mut policy, &mut notes)?;
+    check_empty_entries(&policy)?;
+    anchor_relative_entries(&mut policy, default_root, &mut notes)?;
+    normalize_entries(&mut policy, &mut notes)?;
+    Ok((policy, notes))
+}
+
+/// A `[vars]` name: a letter or `_`, then letters, digits and `_`
+fn is_var_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The entry with its `$name` and `${name}` references replaced, or None
+/// without any. Every `$` must start a reference to a defined variable.
+fn expand_entry(entry: &str, vars: &BTreeMap<String, String>) -> Result<Option<String>> {
+    if !entry.contains('$') {
+        return Ok(None);
+    }
+    let mut expanded = String::with_capacity(entry.len());
+    let mut rest = entry;
+    while let Some(at) = rest.find('$') {
+        expanded.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        let (name, tail) = match after.strip_prefix('{') {
+            Some(braced) => braced
+                .split_once('}')
+                .with_context(|| format!("{:?} has a \"${{\" that is not closed", entry))?,
+            None => {
+                let end = after.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'));
+                after.split_at(end.unwrap_or(after.len()))
+            }
+        };
+        if !is_var_name(name) {
+            anyhow::bail!("{:?}: '$' must start a variable reference like $name or ${{name}}", entry);
+        }
+        let value = vars
+            .get(name)
+            .with_context(|| format!("{:?} references ${}, which [vars] does not define", entry, name))?;
+        expanded.push_str(value);
+        rest = tail;
+    }
+    expanded.push_str(rest);
+    Ok(Some(expanded))
+}
+
+/// Expands `[vars]` references in `workspace_root` and in the path and
+/// domain entries, before anything else looks at them. Values cannot
+/// reference other variables, so an expanded entry has no `$` left and a
+/// policy revalidated later expands to the same.
+fn expand_vars(policy: &mut Policy, notes: &mut Vec<Normalization>) -> Result<()> {
+    for (name, value) in &policy.vars {
+        if !is_var_name(name) {
+            anyhow::bail!(
+                "vars.{:?} is not a valid name; use letters, digits and '_', not starting with a digit",
+                name
+            );
+        }
+        if value.contains('$') {
+            anyhow::bail!("vars.{} = {:?} must not reference another variable", name, value);
+        }
+    }
+    let vars = &policy.vars;
+    let (fs, net) = (&mut policy.filesystem, &mut policy.network);
+    let singles: [(&str, Option<&mut String>); 4] = [
+        ("workspace_root", policy.workspace_root.as_mut()),
+        ("filesystem.scratch_dir", fs.scratch_dir.as_mut()),
+        ("filesystem.trash_dir", fs.trash_dir.as_mut()),
+        ("state.dir", policy.state.dir.as_mut()),
+    ];
+    for (name, value) in singles {
+        let Some(value) = value else {
+            continue;
+        };
+        if let Some(expanded) = expand_entry(value, vars).context(name)? {
+            notes.push(Normalization {
+                entry: name.to_string(),
+                change: format!("{:?} expanded to {:?}", value, expanded),
+            });
+            *value = expanded;
+        }
+    }
+    let lists: [(&str, Vec<&mut String>); 11] = [
+        ("filesystem.allowed_read", fs.allowed_read.iter_mut().collect()),
+        ("filesystem.allowed_write", fs.allowed_write.iter_mut().collect()),
+        ("filesystem.allowed_stat", fs.allowed_stat.iter_mut().collect()),
+        ("filesystem.denied_patterns", fs.denied_patterns.iter_mut().collect()),
+        ("filesystem.rules", fs.rules.iter_mut().map(|rule| &mut rule.path).collect()),
+        ("network.allowed_domains", net.allowed_domains.iter_mut().collect()),
+        ("network.exempt_domains", net.exempt_domains.iter_mut().collect()),
+        ("network.http.trusted_domains", net.http.trusted_domains.iter_mut().collect()),
+        ("network.quotas", net.quotas.iter_mut().map(|q| &mut q.domain).collect()),
+        ("network.http.overrides", net.http.overrides.iter_mut().map(|o| &mut o.domain).collect()),
+        ("process.search_path", policy.process.search_path.iter_mut().collect()),
+    ];
+    for (name, entries) in lists {
+        for (index, entry) in entries.into_iter().enumerate() {
+            if let Some(expanded) = expand_entry(entry, vars).with_context(|| format!("{}[{}]", name, index))? {
+                notes.push(Normalization::new(name, index, format!("{:?} expanded to {:?}", entry, expanded)));
+                *entry = expanded;
+            }
+        }
+    }
+    if net.max_connections_per_domain.keys().any(|domain| domain.contains('$')) {
+        let limits = std::mem::take(&mut net.max_connections_per_domain);
+        for (domain, limit) in limits {
+            let context = || format!("network.max_connections_per_domain.{:?}", domain);
+            let domain = match expand_entry(&domain, vars).with_context(context)? {
+                Some(expanded) => {
+                    notes.push(Normalization {
+                        entry: context(),
+                        change: format!("{:?} expanded to {:?}", domain, expanded),
+                    });
+                    expanded
+                }
+                None => domain,
+            };
+            net.max_connections_per_domain.insert(domain, limit);
+        }
+    }
+    Ok(())
+}
+
+/// Paths and patterns that start with neither `/` nor a glob character
+/// are relative to the workspace; `*.key` keeps matching anywhere
+fn is_relative_entry(entry: &str) -> bool {
+    !entry.is_empty() && !entry.starts_with(['/', '*', '?', '['])
+}
+
+/// The entry with a leading [`TMP_TOKEN`] replaced, or None without one
+fn expand_tmp_token(path: &str) -> Result<Option<String>> {
+    let Some(rest) = path.strip_prefix(TMP_TOKEN) else {
+        return Ok(None);
+    };
+    if !(rest.is_empty() || rest.starts_with('/')) {
+        anyhow::bail!("filesystem entry {:?}: {} must be followed by '/'", path, TMP_TOKEN);
+    }
+    // Without the trailing slash macOS puts on TMPDIR
+    let tmp: PathBuf = std::env::temp_dir().components().collect();
+    if !tmp.is_absolute() {
+        anyhow::bail!("{} in {:?} needs an absolute temp directory, got {:?}; check TMPDIR", TMP_TOKEN, path, tmp);
+    }
+    Ok(Some(format!("{}{}", tmp.display(), rest)))
+}
+
+/// Rewrites relative allow, deny and rule entries to absolute ones under
+/// `workspace_root`, or `default_root` when that is unset, and expands
+/// [`TMP_TOKEN`] in them and in `scratch_dir`. Entries with a `..` component
+/// are rejected, since they could point outside the root, and so are
+/// relative entries when there is no root to resolve them against.
+fn anchor_relative_entries(
+    policy: &mut Policy,
+    default_root: Option<PathBuf>,
+    notes: &mut Vec<Normalization>,
+) -> Result<()> {
+    let root = match &policy.workspace_root {
+        Some(root) if Path::new(root).is_absolute() => Some(PathBuf::from(root)),
+        Some(root) => anyhow::bail!("workspace_root must be an absolute path, got {:?}", root),
+        None => default_root,
+    };
+
+    let fs = &mut policy.filesystem;
+    // A regex that does not start with `/` need not be relative: `.*` may
+    // be meant to match anywhere
+    let regex = fs.path_match_mode == PathMatchMode::Regex;
+    // (key, entries, whether entries may be negated with `!`, whether
+    // relative entries are resolved)
+    let lists: [(&str, Vec<&mut String>, bool, bool); 5] = [
+        ("filesystem.allowed_read", fs.allowed_read.iter_mut().collect(), false, !regex),
+        ("filesystem.allowed_write", fs.allowed_write.iter_mut().collect(), false, !regex),
+        ("filesystem.allowed_stat", fs.allowed_stat.iter_mut().collect(), false, !regex),
+        ("filesystem.rules", fs.rules.iter_mut().map(|rule| &mut rule.path).collect(), false, true),
+        ("filesystem.denied_patterns", fs.denied_patterns.iter_mut().collect(), true, true),
+    ];
+    for (name, entries, negatable, resolved) in lists {
+        for (index, entry) in entries.into_iter().enumerate() {
+            let (prefix, path) = match entry.strip_prefix('!') {
+                Some(rest) if negatable => ("!", rest.to_string()),
+                _ => ("", entry.clone()),
+            };
+            if Path::new(&path).components().any(|c| c == std::path::Component::ParentDir) {
+                anyhow::bail!("{}[{}] {:?} must not contain '..'", name, index, entry);
+            }
+            let anchored = if let Some(expanded) =
+                expand_tmp_token(&path).with_context(|| format!("{}[{}]", name, index))?
+            {
+                expanded
+            } else if resolved && is_relative_entry(&path) {
+                let Some(root) = &root else {
+                    anyhow::bail!(
+                        "{}[{}] {:?} is relative, but there is no workspace_root to resolve it against; \
+                         set workspace_root or make the entry absolute",
+                        name,
+                        index,
+                        entry
+                    );
+                };
+                match path.trim_start_matches("./") {
+                    "" | "." => root.display().to_string(),
+                    relative => root.join(relative).display().to_string(),
+                }
+            } else {
+                continue;
+            };
+            let anchored = format!("{}{}", prefix, anchored);
+            notes.push(Normalization::new(name, index, format!("{:?} resolved to {:?}", entry, anchored)));
+            *entry = anchored;
+        }
+    }
+    if let Some(dir) = &mut fs.scratch_dir {
+        if let Some(expanded) = expand_tmp_token(dir).context("filesystem.scratch_dir")? {
+            *dir = expanded;
+        }
+    }
+
+    policy.workspace_root = root.map(|root| root.display().to_string());
+    Ok(())
+}
+
+/// Everything [`validate_policy`] found wrong with a policy, in the order
+/// the settings are checked
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub struct PolicyErrors(pub Vec<String>);
+
+impl fmt::Display for PolicyErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.as_slice() {
+            [problem] => f.write_str(problem),
+            problems => write!(f, "{} problems: {}", problems.len(), problems.join("; ")),
+        }
+    }
+}
+
+/// Collects problems instead of stopping at the first
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn add(&mut self, problem: impl Into<String>) {
+        self.0.push(problem.into());
+    }
+
+    fn check<T, E: Into<anyhow::Error>>(&mut self, result: std::result::Result<T, E>) {
+        if let Err(e) = result {
+            self.add(format!("{:#}", e.into()));
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(PolicyErrors(self.0).into())
+        }
+    }
+}
+
+/// A change made to a policy entry while it loaded, listed by `--validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalization {
+    /// The setting and index the entry has in the source, e.g.
+    /// `network.allowed_domains[2]`
+    pub entry: String,
+    pub change: String,
+}
+
+impl Normalization {
+    fn new(name: &str, index: usize, change: String) -> Self {
+        Self {
+            entry: format!("{}[{}]", name, index),
+            change,
+        }
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.entry, self.change)
+    }
+}
+
+/// Every list of strings in the policy, by key, for checks that apply to
+/// all of them; table lists contribute the field that names the entry
+pub(super) fn string_lists(policy: &Policy) -> [(&'static str, Vec<&str>); 20] {
+    let fs = &policy.filesystem;
+    let net = &policy.network;
+    fn strs(list: &[String]) -> Vec<&str> {
+        list.iter().map(String::as_str).collect()
+    }
+    [
+        ("filesystem.allowed_read", strs(&fs.allowed_read)),
+        ("filesystem.allowed_write", strs(&fs.allowed_write)),
+        ("filesystem.allowed_stat", strs(&fs.allowed_stat)),
+        ("filesystem.denied_patterns", strs(&fs.denied_patterns)),
+        ("filesystem.denied_write_extensions", strs(&fs.denied_write_extensions)),
+        ("filesystem.rules", fs.rules.iter().map(|rule| rule.path.as_str()).collect()),
+        ("filesystem.redaction", fs.redaction.iter().map(|r| r.pattern.as_str()).collect()),
+        ("network.allowed_domains", strs(&net.allowed_domains)),
+        ("network.blocked_ips", strs(&net.blocked_ips)),
+        ("network.blocked_ip_lists", strs(&net.blocked_ip_lists)),
+        ("network.exempt_domains", strs(&net.exempt_domains)),
+        ("network.allowed_private_cidrs", strs(&net.allowed_private_cidrs)),
+        ("network.max_connections_per_domain", net.max_connections_per_domain.keys().map(String::as_str).collect()),
+        ("network.quotas", net.quotas.iter().map(|q| q.domain.as_str()).collect()),
+        ("network.http.trusted_domains", strs(&net.http.trusted_domains)),
+        ("network.http.blocked_request_headers", strs(&net.http.blocked_request_headers)),
+        ("network.http.overrides", net.http.overrides.iter().map(|o| o.domain.as_str()).collect()),
+        (
+            "process.allowed_commands",
+            policy
+                .process
+                .allowed_commands
+                .iter()
+                .map(|entry| match entry {
+                    CommandEntry::Plain(command) => command.as_str(),
+                    CommandEntry::Spec(spec) => spec.name.as_str(),
+                })
+                .collect(),
+        ),
+        ("process.search_path", strs(&policy.process.search_path)),
+        ("process.allowed_env", strs(&policy.process.allowed_env)),
+    ]
+}
+
+/// An empty entry either matches nothing or, as a path prefix, everything
+fn check_empty_entries(policy: &Policy) -> Result<()> {
+    for (name, entries) in string_lists(policy) {
+        if let Some(index) = entries.iter().position(|entry| entry.trim().is_empty()) {
+            anyhow::bail!("{}[{}] is empty", name, index);
+        }
+    }
+    Ok(())
+}
+
+/// `https://example.com/path` as the `example.com` it was meant to be, or
+/// None if `entry` is a domain already
+fn strip_url(entry: &str) -> Option<&str> {
+    let host = entry.split_once("://").map_or(entry, |(_, rest)| rest);
+    let host = host.split(['/', '?', '#']).next().unwrap_or(host);
+    (host != entry).then_some(host)
+}
+
+/// Rewrites URLs in domain lists to their host, rejects addresses that do
+/// not parse and drops repeated entries, noting each change
+fn normalize_entries(policy: &mut Policy, notes: &mut Vec<Normalization>) -> Result<()> {
+    let net = &mut policy.network;
+    let domains: [(&str, Vec<&mut String>); 5] = [
+        ("network.allowed_domains", net.allowed_domains.iter_mut().collect()),
+        ("network.exempt_domains", net.exempt_domains.iter_mut().collect()),
+        ("network.http.trusted_domains", net.http.trusted_domains.iter_mut().collect()),
+        ("network.quotas", net.quotas.iter_mut().map(|q| &mut q.domain).collect()),
+        ("network.http.overrides", net.http.overrides.iter_mut().map(|o| &mut o.domain).collect()),
+    ];
+    for (name, entries) in domains {
+        for (index, entry) in entries.into_iter().enumerate() {
+            let Some(host) = strip_url(entry) else {
+                continue;
+            };
+            if host.is_empty() {
+                anyhow::bail!("{}[{}] {:?} has no domain", name, index, entry);
+            }
+            let change = format!("{:?} rewritten to {:?}, a domain list holds no scheme or path", entry, host);
+            let note = Normalization::new(name, index, change);
+            warn!("{}", note);
+            *entry = host.to_string();
+            notes.push(note);
+        }
+    }
+
+    for (name, entries) in [
+        ("network.blocked_ips", &net.blocked_ips),
+        ("network.allowed_private_cidrs", &net.allowed_private_cidrs),
+    ] {
+        for (index, entry) in entries.iter().enumerate() {
+            if let Err(e) = entry.parse::<Cidr>() {
+                anyhow::bail!("{}[{}] {:?} is not an IP address or CIDR: {}", name, index, entry, e);
+            }
+        }
+    }
+
+    let fs = &mut policy.filesystem;
+    for (index, extension) in fs.denied_write_extensions.iter_mut().enumerate() {
+        if let Some(bare) = extension.strip_prefix('.') {
+            let change = format!("{:?} rewritten to {:?}, extensions are listed without the dot", extension, bare);
+            let note = Normalization::new("filesystem.denied_write_extensions", index, change);
+            warn!("{}", note);
+            *extension = bare.to_string();
+            notes.push(note);
+        }
+    }
+    dedupe("filesystem.allowed_read", &mut fs.allowed_read, notes);
+    dedupe("filesystem.allowed_write", &mut fs.allowed_write, notes);
+    dedupe("filesystem.allowed_stat", &mut fs.allowed_stat, notes);
+    dedupe("filesystem.denied_patterns", &mut fs.denied_patterns, notes);
+    dedupe("filesystem.denied_write_extensions", &mut fs.denied_write_extensions, notes);
+    dedupe("network.allowed_domains", &mut net.allowed_domains, notes);
+    dedupe("network.blocked_ips", &mut net.blocked_ips, notes);
+    dedupe("network.blocked_ip_lists", &mut net.blocked_ip_lists, notes);
+    dedupe("network.exempt_domains", &mut net.exempt_domains, notes);
+    dedupe("network.allowed_private_cidrs", &mut net.allowed_private_cidrs, notes);
+    dedupe("network.http.trusted_domains", &mut net.http.trusted_domains, notes);
+    dedupe("network.http.blocked_request_headers", &mut net.http.blocked_request_headers, notes);
+    let process = &mut policy.process;
+    dedupe("process.allowed_commands", &mut process.allowed_commands, notes);
+    dedupe("process.search_path", &mut process.search_path, notes);
+    dedupe("process.allowed_env", &mut process.allowed_env, notes);
+    dedupe("escalation.operations", &mut policy.escalation.operations, notes);
+    Ok(())
+}
+
+/// Keeps the first of equal entries; indices in the notes are the ones
+/// entries had before any were dropped
+fn dedupe<T: Eq + Hash + Label>(name: &str, list: &mut Vec<T>, notes: &mut Vec<Normalization>) {
+    let mut first = HashMap::new();
+    let keep: Vec<bool> = list
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| match first.entry(entry) {
+            Entry::Occupied(original) => {
+                let change = format!("{:?} dropped, it repeats [{}]", entry.label(), original.get());
+                notes.push(Normalization::new(name, index, change));
+                false
+            }
+            Entry::Vacant(slot) => {
+                slot.insert(index);
+                true
+            }
+        })
+        .collect();
+    let mut keep = keep.into_iter();
+    list.retain(|_| keep.next().unwrap_or(true));
+}
+
+fn validate_command_entry(entry: &CommandEntry) -> Result<()> {
+    let bare_name = |name: &str| {
+        if name.trim().is_empty() || name.contains('/') {
+            anyhow::bail!(
+                "allowed_commands entries must be absolute paths or bare names, got {:?}",
+                name
+            );
+        }
+        Ok(())
+    };
+    match entry {
+        CommandEntry::Plain(command) if Path::new(command).is_absolute() => Ok(()),
+        CommandEntry::Plain(command) => bare_name(command),
+        CommandEntry::Spec(spec) => {
+            bare_name(&spec.name)?;
+            if let Some(path) = &spec.path {
+                if !Path::new(path).is_absolute() {
+                    anyhow::bail!("allowed_commands path must be absolute, got {:?}", path);
+                }
+            }
+            if let Some(min_version) = &spec.min_version {
+                if Version::parse(min_version).is_none() {
+                    anyhow::bail!(
+                        "allowed_commands min_version must be a dotted number like \"20\" or \"3.11\", got {:?}",
+                        min_version
+                    );
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Rejects lists, and entries, far larger than any real policy needs
+fn check_list<'a>(name: &str, entries: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    for (count, entry) in entries.into_iter().enumerate() {
+        if count >= MAX_LIST_LEN {
+            anyhow::bail!("{} has more than {} entries", name, MAX_LIST_LEN);
+        }
+        if entry.len() > MAX_ENTRY_LEN {
+            anyhow::bail!(
+                "{} entry {:?}... is {} bytes long, more than {}",
+                name,
+                entry.chars().take(32).collect::<String>(),
+                entry.len(),
+                MAX_ENTRY_LEN
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check_at_most(name: &str, value: u64, max: u64) -> Result<()> {
+    if value > max {
+        anyhow::bail!("{} is {}, more than the maximum of {}", name, value, max);
+    }
+    Ok(())
+}
+
+/// Bounds on list sizes and numeric limits, so no setting can overflow
+/// where it is converted to bytes or added to a time
+fn validate_bounds(policy: &Policy, problems: &mut Problems) {
+    let fs = &policy.filesystem;
+    let net = &policy.network;
+    for (name, entries) in string_lists(policy) {
+        problems.check(check_list(name, entries));
+    }
+    if let Some(root) = &policy.workspace_root {
+        problems.check(check_list("workspace_root", [root.as_str()]));
+    }
+
+    let secs = [
+        ("filesystem.watch_heartbeat_secs", fs.watch_heartbeat_secs),
+        ("filesystem.search.time_budget_secs", fs.search.time_budget_secs),
+        ("network.connect.lease_ttl_secs", net.connect.lease_ttl_secs),
+        ("resources.warn_interval_secs", policy.resources.warn_interval_secs),
+        ("audit.dedupe_window_secs", policy.audit.dedupe_window_secs),
+        ("audit.max_age_secs", policy.audit.max_age_secs),
+        ("escalation.timeout_secs", policy.escalation.timeout_secs),
+        ("escalation.max_grant_secs", policy.escalation.max_grant_secs),
+        ("sessions.ttl_secs", policy.sessions.ttl_secs),
+        ("performance.decision_cache_ttl_secs", policy.performance.decision_cache_ttl_secs),
+        ("server.default_deadline_secs", policy.server.default_deadline_secs),
+    ];
+    for (name, value) in secs {
+        problems.check(check_at_most(name, value, MAX_SECS));
+    }
+    problems.check(check_at_most("filesystem.trash_retention_days", fs.trash_retention_days, MAX_SECS / 86_400));
+    problems.check(check_at_most("server.latency_budget_ms", policy.server.latency_budget_ms, MAX_SECS * 1000));
+
+    // (name, value, maximum)
+    let mut sizes = vec![
+        ("process.max_memory_mb", policy.process.max_memory_mb, MAX_MB),
+        ("resources.max_file_size_mb", policy.resources.max_file_size_mb, MAX_MB),
+        ("resources.warn_memory_mb", policy.resources.warn_memory_mb.unwrap_or(0), MAX_MB),
+        ("network.http.max_response_body_mb", net.http.max_response_body_mb, MAX_MB),
+        ("network.http.max_request_body_kb", net.http.max_request_body_kb, MAX_MB * 1024),
+        ("filesystem.search.max_file_kb", fs.search.max_file_kb, MAX_MB * 1024),
+        ("sessions.max_read_mb", policy.sessions.max_read_mb, MAX_MB),
+    ];
+    for entry in &net.http.overrides {
+        let mb = entry.max_response_body_mb.unwrap_or(0);
+        let kb = entry.max_request_body_kb.unwrap_or(0);
+        sizes.push(("network.http.overrides max_response_body_mb", mb, MAX_MB));
+        sizes.push(("network.http.overrides max_request_body_kb", kb, MAX_MB * 1024));
+    }
+    for (name, value, max) in sizes {
+        problems.check(check_at_most(name, value, max));
+    }
+    problems.check(check_at_most(
+        "performance.walk_threads",
+        policy.performance.walk_threads as u64,
+        MAX_WALK_THREADS as u64,
+    ));
+    problems.check(check_at_most(
+        "performance.decision_cache_size",
+        policy.performance.decision_cache_size as u64,
+        MAX_DECISION_CACHE as u64,
+    ));
+    problems.check(check_at_most("schedule.windows", policy.schedule.windows.len() as u64, MAX_LIST_LEN as u64));
+}
+
+/// Checks the policy as a whole. Every problem found is reported, as a
+/// [`PolicyErrors`], rather than only the first.
+pub fn validate_policy(policy: &Policy) -> Result<()> {
+    let mut problems = Problems::default();
+    validate_bounds(policy, &mut problems);
+
     // Validate filesystem paths
-    if policy.filesystem.allowed_read.is_empty() 
-        && policy.filesystem.allowed_write.is_empty() {
-        anyhow::bail!("Policy must specify at least one allowed read or write path");
+    if policy.filesystem.allowed_read.is_empty()
+        && policy.filesystem.allowed_write.is_empty()
+        && policy.filesystem.empty_allow_means == EmptyAllowMeans::None
+    {
+        problems.add("Policy must specify at least one allowed read or write path");
     }
     
+    for pattern in &policy.filesystem.denied_patterns {
+        if pattern.trim_start_matches('!').is_empty() {
+            problems.add("denied_patterns entries must not be empty");
+        }
+        // A deny pattern that cannot compile would never match. Allow lists
+        // and rules also match as literal prefixes, so they are not checked.
+        let glob = pattern.strip_prefix('!').unwrap_or(pattern);
+        if has_glob_chars(glob) {
+            problems.check(
+                glob::Pattern::new(glob).with_context(|| format!("Invalid glob in denied_patterns: {:?}", pattern)),
+            );
+        }
+    }
+
+    for rule in &policy.filesystem.rules {
+        if rule.path.trim().is_empty() {
+            problems.add("filesystem.rules entries must specify a path");
+        }
+    }
+
+    // Outside the hybrid and prefix modes an entry only matches as a
+    // pattern, so one that does not compile would match nothing
+    let fs = &policy.filesystem;
+    let allow_lists = [
+        ("filesystem.allowed_read", &fs.allowed_read),
+        ("filesystem.allowed_write", &fs.allowed_write),
+        ("filesystem.allowed_stat", &fs.allowed_stat),
+    ];
+    for (name, entries) in allow_lists {
+        for (index, entry) in entries.iter().enumerate() {
+            let context = |kind| format!("Invalid {} in {}[{}]: {:?}", kind, name, index, entry);
+            match fs.path_match_mode {
+                PathMatchMode::Glob => problems.check(glob::Pattern::new(entry).with_context(|| context("glob"))),
+                PathMatchMode::Regex => problems.check(regex::Regex::new(entry).with_context(|| context("regex"))),
+                PathMatchMode::Hybrid | PathMatchMode::Prefix => {}
+            }
+        }
+    }
+
+    if policy.filesystem.write_mode > 0o777 {
+        problems.add(format!(
+            "write_mode must be a permission mode no greater than 0o777, got {:#o}",
+            policy.filesystem.write_mode
+        ));
+    }
+
+    if let Some(trash_dir) = &policy.filesystem.trash_dir {
+        if !std::path::Path::new(trash_dir).is_absolute() {
+            problems.add(format!("trash_dir must be an absolute path, got {:?}", trash_dir));
+        }
+    }
+
+    if let Some(scratch_dir) = &policy.filesystem.scratch_dir {
+        if !std::path::Path::new(scratch_dir).is_absolute() {
+            problems.add(format!("scratch_dir must be an absolute path, got {:?}", scratch_dir));
+        }
+    }
+
+    if policy.filesystem.watch_heartbeat_secs == 0 {
+        problems.add("watch_heartbeat_secs must be greater than 0");
+    }
+
+    if policy.filesystem.search.max_results == 0 || policy.filesystem.search.time_budget_secs == 0 {
+        problems.add("filesystem.search max_results and time_budget_secs must be greater than 0");
+    }
+
     // Validate network settings
     if policy.network.max_connections == 0 {
-        anyhow::bail!("max_connections must be greater than 0");
+        problems.add("max_connections must be greater than 0");
+    }
+
+    for (domain, max) in &policy.network.max_connections_per_domain {
+        if domain.trim().is_empty() || *max == 0 {
+            problems.add(format!(
+                "max_connections_per_domain entries need a domain and a limit greater than 0, got {:?} = {}",
+                domain,
+                max
+            ));
+        }
+    }
+
+    for (i, quota) in policy.network.quotas.iter().enumerate() {
+        if quota.domain.trim().is_empty() || (quota.max_concurrent == 0 && quota.max_bytes_per_day == 0) {
+            problems.add(format!(
+                "network.quotas entries need a domain and max_concurrent or max_bytes_per_day, got {:?}",
+                quota.domain
+            ));
+        }
+        if policy.network.quotas[..i].iter().any(|other| other.domain == quota.domain) {
+            problems.add(format!("network.quotas has more than one entry for {:?}", quota.domain));
+        }
+    }
+    if policy.network.quota_reset_hour_utc > 23 {
+        problems.add(format!("quota_reset_hour_utc must be 0 to 23, got {}", policy.network.quota_reset_hour_utc));
+    }
+    for extension in &policy.filesystem.denied_write_extensions {
+        if extension.is_empty() || extension.contains(['.', '/', '\\']) {
+            problems.add(format!(
+                "denied_write_extensions entries must be bare extensions like \"sh\", got {:?}",
+                extension
+            ));
+        }
+    }
+    for file in &policy.network.blocked_ip_lists {
+        if !std::path::Path::new(file).is_absolute() {
+            problems.add(format!("blocked_ip_lists entries must be absolute paths, got {:?}", file));
+        }
+    }
+    if let Some(file) = &policy.network.quota_state_file {
+        if !std::path::Path::new(file).is_absolute() {
+            problems.add(format!("quota_state_file must be an absolute path, got {:?}", file));
+        }
+    }
+    if let Some(dir) = &policy.state.dir {
+        if !std::path::Path::new(dir).is_absolute() {
+            problems.add(format!("state.dir must be an absolute path, got {:?}", dir));
+        }
+    }
+
+    problems.check(AddressGuard::new(&policy.network));
+
+    if let Some(url) = &policy.network.proxy_url {
+        problems.check(ProxyConfig::parse(url));
+    } else if policy.network.require_proxy {
+        problems.add("require_proxy is set but no proxy_url is configured");
+    }
+
+    if policy.network.connect.lease_ttl_secs == 0 {
+        problems.add("network.connect.lease_ttl_secs must be greater than 0");
+    }
+
+    if policy.network.http.allowed_methods.is_empty() {
+        problems.add("network.http.allowed_methods must not be empty");
+    }
+
+    for entry in &policy.network.http.overrides {
+        if entry.domain.trim().is_empty() {
+            problems.add("network.http.overrides entries must specify a domain");
+        }
     }
     
     // Validate process limits
+    for entry in &policy.process.allowed_commands {
+        problems.check(validate_command_entry(entry));
+    }
+    for name in &policy.process.allowed_env {
+        if name.is_empty() || name.contains(['=', '\0']) {
+            problems.add(format!("process.allowed_env entry {:?} is not a variable name", name));
+        }
+    }
+
     if policy.process.max_cpu_percent > 100 {
-        anyhow::bail!("max_cpu_percent cannot exceed 100");
+        problems.add("max_cpu_percent cannot exceed 100");
     }
     
     if policy.process.max_memory_mb == 0 {
-        anyhow::bail!("max_memory_mb must be greater than 0");
+        problems.add("max_memory_mb must be greater than 0");
     }
     
-    Ok(())
+    if let Some(warn) = policy.resources.warn_open_files {
+        if warn >= policy.resources.max_open_files {
+            problems.add("warn_open_files must be below max_open_files");
+        }
+    }
+
+    if let Some(warn) = policy.resources.warn_memory_mb {
+        if warn >= policy.process.max_memory_mb {
+            problems.add("warn_memory_mb must be below max_memory_mb");
+        }
+    }
+
+    problems.check(Redactor::new(&policy.filesystem.redaction));
+
+    if policy.escalation.mode == EscalationMode::Callback {
+        problems.check(Approver::from_policy(&policy.escalation));
+        if policy.escalation.operations.is_empty() {
+            problems.add("escalation.operations must list the operations to escalate");
+        }
+        for operation in &policy.escalation.operations {
+            if !ESCALATABLE_OPERATIONS.contains(&operation.as_str()) {
+                problems.add(format!(
+                    "escalation.operations entry {:?} is not one of {}",
+                    operation,
+                    ESCALATABLE_OPERATIONS.join(", ")
+                ));
+            }
+        }
+        for operation in policy.escalation.conflicts.iter().flatten() {
+            if !ESCALATABLE_OPERATIONS.contains(&operation.as_str()) {
+                problems.add(format!(
+                    "escalation.conflicts entry {:?} is not one of {}",
+                    operation,
+                    ESCALATABLE_OPERATIONS.join(", ")
+                ));
+            }
+        }
+        if policy.escalation.timeout_secs == 0 || policy.escalation.max_grant_secs == 0 {
+            problems.add("escalation.timeout_secs and max_grant_secs must be greater than 0");
+        }
+    }
+
+    // Also rejects NaN
+    if !(0.0..=1.0).contains(&policy.audit.sample_rate) {
+        problems.add(format!("audit.sample_rate must be between 0.0 and 1.0, got {}", policy.audit.sample_rate));
+    }
+    if let Some(file) = &policy.audit.file {
+        if !std::path::Path::new(file).is_absolute() {
+            problems.add(format!("audit.file must be an absolute path, got {:?}", file));
+        }
+    }
+
+    if policy.sessions.ttl_secs == 0 || policy.sessions.max_sessions == 0 {
+        problems.add("sessions.ttl_secs and max_sessions must be greater than 0");
+    }
+
+    if let Err(problem) = Schedule::new(&policy.schedule) {
+        problems.add(problem);
+    }
+    if let Err(problem) = Messages::new(&policy.messages) {
+        problems.add(problem);
+    }
+
+    let sizes = [
+        ("server.max_message_bytes", policy.server.max_message_bytes),
+        ("server.max_target_bytes", policy.server.max_target_bytes),
+        ("server.max_url_bytes", policy.server.max_url_bytes),
+        ("enforcement.max_path_length", policy.enforcement.max_path_length),
+        ("enforcement.max_path_components", policy.enforcement.max_path_components),
+        ("enforcement.max_domain_length", policy.enforcement.max_domain_length),
+        ("enforcement.max_command_length", policy.enforcement.max_command_length),
+    ];
+    for (name, value) in sizes {
+        if value == 0 {
+            problems.add(format!("{} must be greater than 0", name));
+        }
+    }
+
+    let server = &policy.server;
+    for (i, (name, token)) in server.policy_editors.iter().enumerate() {
+        if name.trim().is_empty() || token.trim().is_empty() {
+            problems.add(format!("server.policy_editors entries need a name and a token, got {:?}", name));
+        } else if server.admin_token.as_ref() == Some(token) {
+            problems.add(format!("server.policy_editors token for {:?} must differ from admin_token", name));
+        } else if server.policy_editors.values().take(i).any(|other| other == token) {
+            problems.add(format!("server.policy_editors token for {:?} is also another editor's", name));
+        }
+    }
+    if let Some(file) = &server.policy_edits_file {
+        if !std::path::Path::new(file).is_absolute() {
+            problems.add(format!("policy_edits_file must be an absolute path, got {:?}", file));
+        }
+    }
+
+    for (name, value) in [
+        ("run_as_user", &policy.server.run_as_user),
+        ("run_as_group", &policy.server.run_as_group),
+    ] {
+        if value.as_deref().is_some_and(|v| v.trim().is_empty()) {
+            problems.add(format!("server.{} must not be empty", name));
+        }
+    }
+
+    if policy.filesystem.check_inodes && !cfg!(unix) {
+        problems.add("filesystem.check_inodes is only supported on Unix");
+    }
+
+    if policy.server.compression == Compression::Gzip && !cfg!(feature = "gzip") {
+        problems.add("server.compression = \"gzip\" requires building with the gzip feature");
+    }
+
+    problems.finish()
+}
+
+/// Settings that load but can have no effect, for `--validate` to warn
+/// about: lists whose every operation `[operations]` switches off, and
+/// entries that would make the daemon's own files, read from `source`,
+/// writable
+pub fn lint(policy: &Policy, source: &PolicySource) -> Vec<String> {
+    let lists: [(&str, bool, &[Operation]); 4] = [
+        ("filesystem.allowed_read", policy.filesystem.allowed_read.is_empty(), &[Operation::Read]),
+        (
+            "filesystem.allowed_write",
+            policy.filesystem.allowed_write.is_empty(),
+            &[Operation::Write, Operation::Delete],
+        ),
+        ("process.allowed_commands", policy.process.allowed_commands.is_empty(), &[Operation::Execute]),
+        (
+            "network.allowed_domains",
+            policy.network.allowed_domains.is_empty(),
+            &[Operation::Http, Operation::Dns, Operation::Connect],
+        ),
+    ];
+    let mut warnings = Vec::new();
+    for (name, empty, operations) in lists {
+        if !empty && !operations.iter().any(|operation| policy.operations.enabled(*operation)) {
+            let operations: Vec<&str> = operations.iter().map(|operation| operation.as_str()).collect();
+            warnings.push(format!("{} is not empty, but [operations] disables {}", name, operations.join(", ")));
+        }
+    }
+
+    let policy_file = match source {
+        PolicySource::File(file) => Some(file.as_path()),
+        _ => None,
+    };
+    let validator = PathValidator::new(policy.filesystem.clone());
+    for (path, kind) in SelfProtection::of(policy, policy_file).files() {
+        if validator.can_write(path).unwrap_or(false) {
+            warnings.push(format!(
+                "[filesystem] allows writing {}, the {}, but the daemon protects it",
+                path.display(),
+                kind
+            ));
+        }
+    }
+    warnings
 }
 
 #[cfg(test)]
@@ -72,7 +1159,7 @@ max_open_files = 1000
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(policy_content.as_bytes()).unwrap();
         
-        let policy = load_policy(&file.path().to_path_buf());
+        let policy = load_policy(&PolicySource::File(file.path().to_path_buf()), None);
         assert!(policy.is_ok());
         
         let policy = policy.unwrap();
@@ -106,7 +1193,532 @@ max_open_files = 1000
         let mut file = NamedTempFile::new().unwrap();
         file.write_all(policy_content.as_bytes()).unwrap();
         
-        let policy = load_policy(&file.path().to_path_buf());
+        let policy = load_policy(&PolicySource::File(file.path().to_path_buf()), None);
         assert!(policy.is_err());
     }
+
+    #[test]
+    fn test_server_compression_setting() {
+        let base = r#"
+[filesystem]
+allowed_read = ["/tmp"]
+allowed_write = []
+denied_patterns = []
+
+[network]
+allowed_domains = []
+blocked_ips = []
+max_connections = 10
+
+[process]
+allowed_commands = []
+max_cpu_percent = 50
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = 100
+max_open_files = 1000
+        "#;
+
+        let policy: Policy = toml::from_str(base).unwrap();
+        assert_eq!(policy.server.compression, Compression::None);
+        assert_eq!(policy.server.max_message_bytes, 16 * 1024 * 1024);
+
+        let policy: Policy =
+            toml::from_str(&format!("{}\n[server]\nmax_message_bytes = 1024\n", base)).unwrap();
+        assert_eq!(policy.server.max_message_bytes, 1024);
+        let policy: Policy =
+            toml::from_str(&format!("{}\n[server]\nmax_message_bytes = 0\n", base)).unwrap();
+        assert!(validate_policy(&policy).is_err());
+
+        let policy: Policy =
+            toml::from_str(&format!("{}\n[server]\ncompression = \"gzip\"\n", base)).unwrap();
+        assert_eq!(policy.server.compression, Compression::Gzip);
+        assert_eq!(validate_policy(&policy).is_ok(), cfg!(feature = "gzip"));
+
+        let invalid = format!("{}\n[server]\ncompression = \"brotli\"\n", base);
+        assert!(toml::from_str::<Policy>(&invalid).is_err());
+    }
+
+    const MINIMAL_SECTIONS: &str = r#"
+[network]
+allowed_domains = []
+blocked_ips = []
+max_connections = 10
+
+[process]
+allowed_commands = []
+max_cpu_percent = 50
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = 100
+max_open_files = 1000
+"#;
+
+    #[test]
+    fn test_bounds_reject_oversized_settings() {
+        let inline = |filesystem: &str, sections: &str| {
+            PolicySource::Inline(format!(
+                "[filesystem]\nallowed_write = []\ndenied_patterns = []\n{}\n{}",
+                filesystem, sections
+            ))
+        };
+        let read = "allowed_read = [\"/srv\"]";
+        assert!(load_policy(&inline(read, MINIMAL_SECTIONS), None).is_ok());
+
+        let long_entry = format!("allowed_read = [\"/{}\"]", "a".repeat(MAX_ENTRY_LEN));
+        let huge_list = format!("allowed_read = [{}]", vec!["\"/srv\""; MAX_LIST_LEN + 1].join(","));
+        let cases = [
+            (inline(&long_entry, MINIMAL_SECTIONS), "bytes long"),
+            (inline(&huge_list, MINIMAL_SECTIONS), "more than 10000 entries"),
+            (
+                inline(read, &MINIMAL_SECTIONS.replace("max_memory_mb = 1024", "max_memory_mb = 9223372036854775807")),
+                "process.max_memory_mb",
+            ),
+            (
+                inline(&format!("{}\ntrash_retention_days = 9223372036854775807", read), MINIMAL_SECTIONS),
+                "filesystem.trash_retention_days",
+            ),
+            (
+                inline(read, &format!("{}\n[sessions]\nttl_secs = 9223372036854775807", MINIMAL_SECTIONS)),
+                "sessions.ttl_secs",
+            ),
+            (
+                inline(read, &format!("{}\n[audit]\nsample_rate = 1.5", MINIMAL_SECTIONS)),
+                "audit.sample_rate",
+            ),
+            (
+                inline(read, &format!("{}\n[audit]\nfile = \"audit.jsonl\"", MINIMAL_SECTIONS)),
+                "audit.file must be an absolute path",
+            ),
+        ];
+        for (source, expected) in cases {
+            let err = load_policy(&source, None).unwrap_err();
+            assert!(format!("{:#}", err).contains(expected), "{}: {:#}", expected, err);
+        }
+    }
+
+    #[test]
+    fn test_validation_reports_every_problem() {
+        let sections = MINIMAL_SECTIONS
+            .replace("max_cpu_percent = 50", "max_cpu_percent = 150")
+            .replace("max_connections = 10", "max_connections = 0");
+        let source = PolicySource::Inline(format!(
+            "[filesystem]\nallowed_read = [\"/srv\"]\nallowed_write = []\ndenied_patterns = [\"[a-\"]\n{}",
+            sections
+        ));
+        let err = load_policy(&source, None).unwrap_err();
+        let errors = err.downcast_ref::<PolicyErrors>().unwrap();
+        assert_eq!(errors.0.len(), 3, "{:?}", errors);
+        assert!(errors.0[0].starts_with("Invalid glob in denied_patterns"));
+        assert_eq!(errors.0[1], "max_connections must be greater than 0");
+        assert_eq!(errors.0[2], "max_cpu_percent cannot exceed 100");
+        assert!(err.to_string().starts_with("3 problems: Invalid glob"));
+    }
+
+    #[test]
+    fn test_allow_entries_compile_in_their_match_mode() {
+        let inline = |mode: &str, entry: &str| {
+            PolicySource::Inline(format!(
+                "[filesystem]\npath_match_mode = {:?}\nallowed_read = [{:?}]\nallowed_write = []\ndenied_patterns = []\n{}",
+                mode, entry, MINIMAL_SECTIONS
+            ))
+        };
+        let err = load_policy(&inline("regex", "/srv/(logs"), None).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Invalid regex in filesystem.allowed_read[0]"), "{:#}", err);
+        let err = load_policy(&inline("glob", "/srv/[a-"), None).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Invalid glob in filesystem.allowed_read[0]"), "{:#}", err);
+        // Literal prefixes in the other modes
+        assert!(load_policy(&inline("prefix", "/srv/[a-"), None).is_ok());
+        assert!(load_policy(&inline("hybrid", "/srv/(logs"), None).is_ok());
+
+        // A regex is not resolved against the workspace like a relative path
+        let policy = load_policy(&inline("regex", r".*/notes\.md\z"), None).unwrap();
+        assert_eq!(policy.filesystem.allowed_read, vec![r".*/notes\.md\z"]);
+        assert_eq!(policy.filesystem.path_match_mode, PathMatchMode::Regex);
+    }
+
+    #[test]
+    fn test_quota_entries_are_validated() {
+        let inline = |network: &str| {
+            let sections = MINIMAL_SECTIONS.replace("max_connections = 10", &format!("max_connections = 10\n{}", network));
+            PolicySource::Inline(format!(
+                "[filesystem]\nallowed_read = [\"/srv\"]\nallowed_write = []\ndenied_patterns = []\n{}",
+                sections
+            ))
+        };
+        let quota = "[[network.quotas]]\ndomain = \"api.openai.com\"\nmax_bytes_per_day = 1000";
+        let policy = load_policy(&inline(&format!("quota_reset_hour_utc = 6\n{}", quota)), None).unwrap();
+        assert_eq!(policy.network.quotas[0].max_concurrent, 0);
+
+        let cases = [
+            (inline("[[network.quotas]]\ndomain = \"api.openai.com\""), "network.quotas entries need"),
+            (inline(&format!("{}\n{}", quota, quota)), "more than one entry"),
+            (inline(&format!("quota_reset_hour_utc = 24\n{}", quota)), "0 to 23"),
+            (inline("quota_state_file = \"quotas.json\""), "absolute path"),
+        ];
+        for (source, expected) in cases {
+            let err = format!("{:#}", load_policy(&source, None).unwrap_err());
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_signed_policy_is_verified_before_loading() {
+        use base64::Engine;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("policy.toml");
+        let sig_path = crate::policy::signature::signature_path(&path);
+        let content = format!(
+            "[filesystem]\nallowed_read = [\"/srv\"]\nallowed_write = []\ndenied_patterns = []\n{}",
+            MINIMAL_SECTIONS
+        );
+        fs::write(&path, &content).unwrap();
+        // Made with the Ed25519 key whose seed is the bytes 0 to 31
+        let key = dir.path().join("policy.pub");
+        fs::write(&key, "A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=\n").unwrap();
+        let key = PolicyKey::load(&key).unwrap();
+        let signature = "qXSR632S9ujtwz7HfPs+/RDbHvo3z2pyJfB/bctquqbq4FT2FMyt91+NI+5IhYGU6lPcwDI+UhTIl9WeEV8LBA==";
+        let source = PolicySource::File(path.clone());
+
+        let err = load_policy_normalized(&source, None, Some(&key)).unwrap_err().to_string();
+        assert!(err.contains("Failed to read policy signature"), "{}", err);
+        // Opt-in: without a key the signature is not looked for
+        assert!(load_policy_normalized(&source, None, None).is_ok());
+
+        fs::write(&sig_path, signature).unwrap();
+        let (policy, _) = load_policy_normalized(&source, None, Some(&key)).unwrap();
+        assert_eq!(policy.filesystem.allowed_read, ["/srv"]);
+        // Raw, as OpenSSL writes it
+        let raw = base64::engine::general_purpose::STANDARD.decode(signature).unwrap();
+        fs::write(&sig_path, raw).unwrap();
+        assert!(load_policy_normalized(&source, None, Some(&key)).is_ok());
+
+        // The same signature over a widened policy
+        fs::write(&path, content.replace(r#"["/srv"]"#, r#"["/srv", "/"]"#)).unwrap();
+        let err = load_policy_normalized(&source, None, Some(&key)).unwrap_err().to_string();
+        assert!(err.contains("does not match"), "{}", err);
+
+        let err = load_policy_normalized(&PolicySource::Inline(content), None, Some(&key)).unwrap_err();
+        assert!(err.to_string().contains("must be read from a file"), "{}", err);
+    }
+
+    #[test]
+    fn test_entries_are_normalized_or_rejected() {
+        let inline = |read: &str, network: &str| {
+            let sections = MINIMAL_SECTIONS
+                .replace("allowed_domains = []\nblocked_ips = []\n", "")
+                .replace("[network]", &format!("[network]\n{}", network));
+            PolicySource::Inline(format!(
+                "[filesystem]\nallowed_read = {}\nallowed_write = []\ndenied_patterns = []\n{}",
+                read, sections
+            ))
+        };
+        let source = inline(
+            r#"["/srv", "/srv/app", "/srv"]"#,
+            r#"allowed_domains = ["https://example.com/api?v=1", "example.com", "api.github.com"]
+blocked_ips = ["10.0.0.0/8"]"#,
+        );
+        let (policy, notes) = load_policy_normalized(&source, None, None).unwrap();
+        assert_eq!(policy.filesystem.allowed_read, vec!["/srv", "/srv/app"]);
+        assert_eq!(policy.network.allowed_domains, vec!["example.com", "api.github.com"]);
+        let notes: Vec<String> = notes.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            notes,
+            vec![
+                r#"network.allowed_domains[0]: "https://example.com/api?v=1" rewritten to "example.com", a domain list holds no scheme or path"#,
+                r#"filesystem.allowed_read[2]: "/srv" dropped, it repeats [0]"#,
+                r#"network.allowed_domains[1]: "example.com" dropped, it repeats [0]"#,
+            ]
+        );
+
+        let cases = [
+            (
+                inline(r#"["/srv", ""]"#, "allowed_domains = []\nblocked_ips = []"),
+                "filesystem.allowed_read[1] is empty",
+            ),
+            (
+                inline(r#"["/srv"]"#, "allowed_domains = []\nblocked_ips = [\"10.0.0.1\", \"10.0.0.999\"]"),
+                r#"network.blocked_ips[1] "10.0.0.999" is not an IP address or CIDR"#,
+            ),
+            (
+                inline(r#"["/srv"]"#, "allowed_domains = []\nblocked_ips = []\nallowed_private_cidrs = [\"10.0.0.0/33\"]"),
+                "network.allowed_private_cidrs[0]",
+            ),
+            (inline(r#"["/srv"]"#, "allowed_domains = [\"https://\"]\nblocked_ips = []"), "has no domain"),
+        ];
+        for (source, expected) in cases {
+            let err = format!("{:#}", load_policy(&source, None).unwrap_err());
+            assert!(err.contains(expected), "{}: {}", expected, err);
+        }
+    }
+
+    #[test]
+    fn test_relative_entries_anchor_to_policy_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("policy.toml");
+        let content = format!(
+            "[filesystem]\nallowed_read = [\"./src\", \".\", \"/etc/hosts\"]\nallowed_write = [\"build/\"]\ndenied_patterns = [\"*.key\", \"secrets/*\", \"!./secrets/ok\"]\n{}",
+            MINIMAL_SECTIONS
+        );
+        fs::write(&path, content).unwrap();
+
+        let policy = load_policy(&PolicySource::File(path), None).unwrap();
+        let root = dir.path().display().to_string();
+        assert_eq!(policy.workspace_root.as_deref(), Some(root.as_str()));
+        assert_eq!(
+            policy.filesystem.allowed_read,
+            vec![format!("{}/src", root), root.clone(), "/etc/hosts".to_string()]
+        );
+        assert_eq!(policy.filesystem.allowed_write, vec![format!("{}/build/", root)]);
+        assert_eq!(
+            policy.filesystem.denied_patterns,
+            vec!["*.key".to_string(), format!("{}/secrets/*", root), format!("!{}/secrets/ok", root)]
+        );
+    }
+
+    #[test]
+    fn test_workspace_root_and_parent_dir_entries() {
+        let inline = |filesystem: &str| {
+            PolicySource::Inline(format!(
+                "[filesystem]\ndenied_patterns = []\n{}\n{}",
+                filesystem, MINIMAL_SECTIONS
+            ))
+        };
+
+        let source = inline("allowed_read = [\"/srv\", \"src\"]\nallowed_write = []");
+        // No root for inline content, so the entry could never match
+        let err = load_policy(&source, None).unwrap_err().to_string();
+        assert!(err.contains("filesystem.allowed_read[1] \"src\" is relative"), "{}", err);
+
+        let rooted = PolicySource::Inline(format!(
+            "workspace_root = \"/srv/project\"\n[filesystem]\nallowed_read = [\"src\"]\nallowed_write = []\ndenied_patterns = []\n{}",
+            MINIMAL_SECTIONS
+        ));
+        let policy = load_policy(&rooted, None).unwrap();
+        assert_eq!(policy.filesystem.allowed_read, vec!["/srv/project/src"]);
+
+        for entry in ["../outside", "src/../../etc", "/srv/project/../etc"] {
+            let source = inline(&format!("allowed_read = [{:?}]\nallowed_write = []", entry));
+            let err = load_policy(&source, None).unwrap_err();
+            assert!(err.to_string().contains(".."), "{}: {}", entry, err);
+        }
+
+        let relative_root = PolicySource::Inline(format!(
+            "workspace_root = \"project\"\n[filesystem]\nallowed_read = [\"/tmp\"]\nallowed_write = []\ndenied_patterns = []\n{}",
+            MINIMAL_SECTIONS
+        ));
+        assert!(load_policy(&relative_root, None).is_err());
+    }
+
+    #[test]
+    fn test_tmp_token_expands_to_temp_dir() {
+        let tmp = std::env::temp_dir().components().collect::<PathBuf>().display().to_string();
+        let source = PolicySource::Inline(format!(
+            "[filesystem]\nallowed_read = [\"<tmp>\"]\nallowed_write = [\"<tmp>/openclaw\"]\ndenied_patterns = [\"<tmp>/*.key\", \"!<tmp>/ok.key\"]\nscratch_dir = \"<tmp>/scratch\"\n{}",
+            MINIMAL_SECTIONS
+        ));
+        let policy = load_policy(&source, None).unwrap();
+        assert_eq!(policy.filesystem.allowed_read, vec![tmp.clone()]);
+        assert_eq!(policy.filesystem.allowed_write, vec![format!("{}/openclaw", tmp)]);
+        assert_eq!(
+            policy.filesystem.denied_patterns,
+            vec![format!("{}/*.key", tmp), format!("!{}/ok.key", tmp)]
+        );
+        assert_eq!(policy.filesystem.scratch_dir, Some(format!("{}/scratch", tmp)));
+
+        for entry in ["<tmp>openclaw", "<tmp>/../etc"] {
+            let source = PolicySource::Inline(format!(
+                "[filesystem]\nallowed_read = [{:?}]\nallowed_write = []\ndenied_patterns = []\n{}",
+                entry, MINIMAL_SECTIONS
+            ));
+            assert!(load_policy(&source, None).is_err(), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn test_vars_expand_in_path_and_domain_entries() {
+        let source = PolicySource::Inline(format!(
+            "[vars]\nbase = \"/home/agent\"\ncorp = \"corp.example.com\"\n\
+             [filesystem]\nallowed_read = [\"$base/docs\", \"${{base}}_shared\"]\nallowed_write = [\"$base/out\"]\n\
+             denied_patterns = [\"!$base/docs/ok.key\"]\n{}",
+            MINIMAL_SECTIONS.replace("allowed_domains = []", "allowed_domains = [\"api.$corp\"]")
+        ));
+        let (policy, notes) = load_policy_normalized(&source, None, None).unwrap();
+        assert_eq!(policy.filesystem.allowed_read, vec!["/home/agent/docs", "/home/agent_shared"]);
+        assert_eq!(policy.filesystem.allowed_write, vec!["/home/agent/out"]);
+        assert_eq!(policy.filesystem.denied_patterns, vec!["!/home/agent/docs/ok.key"]);
+        assert_eq!(policy.network.allowed_domains, vec!["api.corp.example.com"]);
+        let note = r#"filesystem.allowed_read[0]: "$base/docs" expanded to "/home/agent/docs""#;
+        assert!(notes.iter().any(|n| n.to_string() == note));
+        // Expanded entries have nothing left to expand
+        assert_eq!(revalidate(policy.clone()).unwrap().filesystem.allowed_read, policy.filesystem.allowed_read);
+
+        for (vars, entry, error) in [
+            ("", "$base/docs", "references $base, which [vars] does not define"),
+            ("base = \"/srv\"", "$bse/docs", "references $bse"),
+            ("base = \"/srv\"", "${base/docs", "is not closed"),
+            ("base = \"/srv\"", "/srv/$/docs", "must start a variable reference"),
+            ("base = \"$root/srv\"", "$base", "must not reference another variable"),
+        ] {
+            let source = PolicySource::Inline(format!(
+                "[vars]\n{}\n[filesystem]\nallowed_read = [{:?}]\nallowed_write = []\ndenied_patterns = []\n{}",
+                vars, entry, MINIMAL_SECTIONS
+            ));
+            let error_text = format!("{:#}", load_policy(&source, None).unwrap_err());
+            assert!(error_text.contains(error), "{}: {}", entry, error_text);
+        }
+    }
+
+    #[test]
+    fn test_lint_warns_of_lists_for_disabled_operations() {
+        let source = PolicySource::Inline(format!(
+            "[filesystem]\nallowed_read = [\"/srv\"]\nallowed_write = [\"/srv/out\"]\ndenied_patterns = []\n\
+             [operations]\nwrite = false\nexecute = false\nhttp = false\ndns = false\nconnect = false\n{}",
+            MINIMAL_SECTIONS
+                .replace("allowed_domains = []", "allowed_domains = [\"example.com\"]")
+                .replace("allowed_commands = []", "allowed_commands = [\"ls\"]")
+        ));
+        let policy = load_policy(&source, None).unwrap();
+        // Deletes still use allowed_write
+        assert_eq!(
+            lint(&policy, &source),
+            vec![
+                "process.allowed_commands is not empty, but [operations] disables execute",
+                "network.allowed_domains is not empty, but [operations] disables http, dns, connect",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_warns_of_writable_daemon_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let file = root.join("policy.toml");
+        fs::write(
+            &file,
+            format!(
+                "[filesystem]\nallowed_read = [{root:?}]\nallowed_write = [{root:?}]\ndenied_patterns = []\n\
+                 [state]\ndir = {state:?}\n{}",
+                MINIMAL_SECTIONS,
+                root = root.display().to_string(),
+                state = root.join("state").display().to_string(),
+            ),
+        )
+        .unwrap();
+        let source = PolicySource::File(file.clone());
+        let warnings = lint(&load_policy(&source, None).unwrap(), &source);
+        assert_eq!(
+            warnings[0],
+            format!("[filesystem] allows writing {}, the policy file, but the daemon protects it", file.display())
+        );
+        // The signature, then each state file
+        assert_eq!(warnings.len(), 5, "{:?}", warnings);
+        assert_eq!(lint(&load_policy(&source, None).unwrap(), &PolicySource::Stdin).len(), 3);
+    }
+
+    #[test]
+    fn test_command_entry_forms() {
+        let inline = |commands: &str| {
+            let sections = MINIMAL_SECTIONS.replace("allowed_commands = []", commands);
+            PolicySource::Inline(format!(
+                "[filesystem]\nallowed_read = [\"/srv\"]\nallowed_write = []\ndenied_patterns = []\n{}",
+                sections
+            ))
+        };
+
+        let policy = load_policy(
+            &inline(
+                r#"allowed_commands = ["git", "/usr/bin/python3", { name = "node", path = "/usr/local/bin/node", min_version = "20" }]"#,
+            ),
+            None,
+        )
+        .unwrap();
+        let commands = &policy.process.allowed_commands;
+        assert_eq!(commands[0], CommandEntry::Plain("git".into()));
+        assert_eq!(commands[1], CommandEntry::Plain("/usr/bin/python3".into()));
+        let CommandEntry::Spec(spec) = &commands[2] else {
+            panic!("expected a structured entry");
+        };
+        assert_eq!(spec.path.as_deref(), Some("/usr/local/bin/node"));
+        assert_eq!(spec.min_version.as_deref(), Some("20"));
+
+        for invalid in [
+            r#"allowed_commands = ["bin/tool"]"#,
+            r#"allowed_commands = [{ name = "node", path = "node" }]"#,
+            r#"allowed_commands = [{ name = "node", min_version = "latest" }]"#,
+            r#"allowed_commands = [{ name = "node", version = "20" }]"#,
+        ] {
+            assert!(load_policy(&inline(invalid), None).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_policy_source_resolution() {
+        let inline = Some("[filesystem]\n".to_string());
+        assert_eq!(
+            PolicySource::resolve(Some(PathBuf::from("-")), inline.clone()),
+            PolicySource::Stdin
+        );
+        assert_eq!(
+            PolicySource::resolve(Some(PathBuf::from("/etc/openclaw.json")), inline.clone()),
+            PolicySource::File(PathBuf::from("/etc/openclaw.json"))
+        );
+        assert_eq!(
+            PolicySource::resolve(None, inline.clone()),
+            PolicySource::Inline("[filesystem]\n".to_string())
+        );
+        assert_eq!(
+            PolicySource::resolve(None, Some("  ".to_string())),
+            PolicySource::File(PathBuf::from(DEFAULT_POLICY_PATH))
+        );
+
+        assert_eq!(
+            PolicySource::File(PathBuf::from("policy.JSON")).default_format(),
+            PolicyFormat::Json
+        );
+        assert_eq!(PolicySource::Inline(String::new()).default_format(), PolicyFormat::Toml);
+        assert_eq!("Json".parse::<PolicyFormat>(), Ok(PolicyFormat::Json));
+        assert!("yaml".parse::<PolicyFormat>().is_err());
+    }
+
+    #[test]
+    fn test_inline_policy_formats() {
+        let toml_policy = r#"
+[filesystem]
+allowed_read = ["/tmp"]
+allowed_write = []
+denied_patterns = []
+
+[network]
+allowed_domains = []
+blocked_ips = []
+max_connections = 10
+
+[process]
+allowed_commands = []
+max_cpu_percent = 50
+max_memory_mb = 1024
+
+[resources]
+max_file_size_mb = 100
+max_open_files = 1000
+        "#;
+        let policy = load_policy(&PolicySource::Inline(toml_policy.to_string()), None).unwrap();
+        assert_eq!(policy.filesystem.allowed_read, vec!["/tmp"]);
+
+        let json_policy = serde_json::json!({
+            "filesystem": { "allowed_read": ["/tmp"], "allowed_write": [], "denied_patterns": [] },
+            "network": { "allowed_domains": [], "blocked_ips": [], "max_connections": 10 },
+            "process": { "allowed_commands": [], "max_cpu_percent": 50, "max_memory_mb": 1024 },
+            "resources": { "max_file_size_mb": 100, "max_open_files": 1000 }
+        })
+        .to_string();
+        let source = PolicySource::Inline(json_policy);
+        assert!(load_policy(&source, None).is_err());
+        let policy = load_policy(&source, Some(PolicyFormat::Json)).unwrap();
+        assert_eq!(policy.network.max_connections, 10);
+    }
 }
@@ -0,0 +1,222 @@
+use std::cmp::Reverse;
+use std::fmt;
+
+/// What a matching entry says about a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Effect {
+    Deny,
+    Allow,
+}
+
+/// How narrowly an entry picks out what it matches. Higher is narrower:
+///
+/// - a path entry scores the components before its first glob character,
+///   so `/srv/app/src` outranks `/srv/app` and `/srv/*/src` scores 1
+/// - a domain scores its labels, the `*` of a wildcard not counted, so
+///   `api.example.com` outranks `*.example.com`
+/// - a command scores 2 when the entry is an absolute path, 1 when it is a
+///   name looked up on `search_path`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Specificity(pub u32);
+
+impl Specificity {
+    pub fn path(entry: &str) -> Self {
+        let literal = entry.find(['*', '?', '[']).map_or(entry, |glob| &entry[..glob]);
+        // A partial name before the glob is not a component
+        let complete = if literal.len() == entry.len() {
+            literal
+        } else {
+            literal.rsplit_once('/').map_or("", |(dir, _)| dir)
+        };
+        Self(complete.split('/').filter(|c| !c.is_empty()).count() as u32)
+    }
+
+    pub fn domain(entry: &str) -> Self {
+        let name = entry.strip_prefix("*.").unwrap_or(entry);
+        Self(name.split('.').filter(|label| !label.is_empty()).count() as u32)
+    }
+
+    pub fn command(is_path: bool) -> Self {
+        Self(if is_path { 2 } else { 1 })
+    }
+}
+
+/// A policy entry a request was weighed against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The list the entry is from, e.g. `filesystem.allowed_read`
+    pub list: &'static str,
+    /// The entry as written in the policy
+    pub entry: String,
+    pub effect: Effect,
+    pub specificity: Specificity,
+}
+
+impl Candidate {
+    pub fn new(list: &'static str, entry: impl Into<String>, effect: Effect, specificity: Specificity) -> Self {
+        Self {
+            list,
+            entry: entry.into(),
+            effect,
+            specificity,
+        }
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let effect = match self.effect {
+            Effect::Deny => "deny",
+            Effect::Allow => "allow",
+        };
+        write!(f, "{} {:?} ({}, specificity {})", effect, self.entry, self.list, self.specificity.0)
+    }
+}
+
+/// The entry that decided a request and the others that matched it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Decision {
+    /// `None` when nothing matched, which denies
+    pub winner: Option<Candidate>,
+    /// Matching entries outranked by the winner, best first
+    pub losers: Vec<Candidate>,
+}
+
+impl Decision {
+    pub fn allowed(&self) -> bool {
+        self.winner.as_ref().is_some_and(|winner| winner.effect == Effect::Allow)
+    }
+
+    /// The winner, or "no entry matched"
+    pub fn describe(&self) -> String {
+        self.winner.as_ref().map_or_else(|| "no entry matched".to_string(), Candidate::to_string)
+    }
+}
+
+/// Policy entries in the one order of precedence the filesystem, network
+/// and process checks share:
+///
+/// 1. an explicit deny
+/// 2. the most specific allow (see [`Specificity`])
+/// 3. a more general allow
+/// 4. nothing matched: deny
+///
+/// Entries of equal effect and specificity are taken in list order. Each
+/// entry carries a matcher `M` of its checker's choosing.
+#[derive(Debug, Clone)]
+pub struct RuleSet<M> {
+    rules: Vec<(Candidate, M)>,
+}
+
+impl<M> Default for RuleSet<M> {
+    fn default() -> Self {
+        Self { rules: Vec::new() }
+    }
+}
+
+impl<M> RuleSet<M> {
+    pub fn push(&mut self, candidate: Candidate, matcher: M) {
+        self.rules.push((candidate, matcher));
+    }
+
+    /// Position and matcher of the deciding entry, when it is found without
+    /// the others being needed
+    pub fn winner(&self, mut matches: impl FnMut(&M) -> bool) -> Option<(usize, &Candidate, &M)> {
+        let mut best: Option<(usize, &Candidate, &M)> = None;
+        for (index, (candidate, matcher)) in self.rules.iter().enumerate() {
+            if best.is_some_and(|(_, b, _)| rank(b) <= rank(candidate)) || !matches(matcher) {
+                continue;
+            }
+            best = Some((index, candidate, matcher));
+        }
+        best
+    }
+
+    /// Every matching entry, ranked
+    pub fn evaluate(&self, mut matches: impl FnMut(&M) -> bool) -> Decision {
+        let mut matching: Vec<&Candidate> = self
+            .rules
+            .iter()
+            .filter(|(_, matcher)| matches(matcher))
+            .map(|(candidate, _)| candidate)
+            .collect();
+        // Stable, so list order breaks ties
+        matching.sort_by_key(|candidate| rank(candidate));
+        let mut matching = matching.into_iter().cloned();
+        Decision {
+            winner: matching.next(),
+            losers: matching.collect(),
+        }
+    }
+}
+
+/// Lower ranks first
+fn rank(candidate: &Candidate) -> (Effect, Reverse<Specificity>) {
+    (candidate.effect, Reverse(candidate.specificity))
+}
+
+/// Ranks candidates the checker collected itself, as [`RuleSet::evaluate`]
+/// would
+pub fn decide(mut candidates: Vec<Candidate>) -> Decision {
+    candidates.sort_by_key(rank);
+    let mut candidates = candidates.into_iter();
+    Decision {
+        winner: candidates.next(),
+        losers: candidates.collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_specificity() {
+        assert_eq!(Specificity::path("/"), Specificity(0));
+        assert_eq!(Specificity::path("/srv/app"), Specificity(2));
+        assert_eq!(Specificity::path("/srv/app/"), Specificity(2));
+        assert_eq!(Specificity::path("/srv/*/src"), Specificity(1));
+        assert_eq!(Specificity::path("/srv/app/*.log"), Specificity(2));
+        assert_eq!(Specificity::path("*.key"), Specificity(0));
+        assert_eq!(Specificity::domain("api.example.com"), Specificity(3));
+        assert_eq!(Specificity::domain("*.example.com"), Specificity(2));
+        assert!(Specificity::command(true) > Specificity::command(false));
+    }
+
+    #[test]
+    fn test_deny_then_most_specific_allow_then_list_order() {
+        let mut rules = RuleSet::default();
+        let entries = [
+            ("general", Effect::Allow, 1),
+            ("specific", Effect::Allow, 3),
+            ("also specific", Effect::Allow, 3),
+            ("deny", Effect::Deny, 0),
+        ];
+        for (name, effect, specificity) in entries {
+            rules.push(Candidate::new("list", name, effect, Specificity(specificity)), name);
+        }
+        let names = |decision: &Decision| -> Vec<String> {
+            decision.winner.iter().chain(&decision.losers).map(|c| c.entry.clone()).collect()
+        };
+
+        let decision = rules.evaluate(|_| true);
+        assert!(!decision.allowed());
+        assert_eq!(names(&decision), vec!["deny", "specific", "also specific", "general"]);
+
+        let decision = rules.evaluate(|name| *name != "deny");
+        assert!(decision.allowed());
+        assert_eq!(names(&decision), vec!["specific", "also specific", "general"]);
+        assert_eq!(rules.winner(|name| *name != "deny").map(|(index, _, _)| index), Some(1));
+        let collected = vec![
+            Candidate::new("list", "general", Effect::Allow, Specificity(1)),
+            Candidate::new("list", "specific", Effect::Allow, Specificity(3)),
+            Candidate::new("list", "also specific", Effect::Allow, Specificity(3)),
+        ];
+        assert_eq!(decide(collected), decision);
+
+        let decision = rules.evaluate(|_| false);
+        assert!(!decision.allowed());
+        assert_eq!(decision.describe(), "no entry matched");
+        assert!(rules.winner(|_| false).is_none());
+    }
+}
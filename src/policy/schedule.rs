@@ -0,0 +1,315 @@
+use std::fmt;
+
+use super::{Operation, OperationsPolicy, SchedulePolicy, Weekday};
+use crate::network::Denial;
+
+const DAY_SECS: i64 = 86_400;
+
+/// Largest offset from UTC in use anywhere, in hours
+const MAX_OFFSET_HOURS: i64 = 14;
+
+impl Operation {
+    pub const ALL: [Operation; 7] = [
+        Operation::Read,
+        Operation::Write,
+        Operation::Delete,
+        Operation::Execute,
+        Operation::Http,
+        Operation::Dns,
+        Operation::Connect,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operation::Read => "read",
+            Operation::Write => "write",
+            Operation::Delete => "delete",
+            Operation::Execute => "execute",
+            Operation::Http => "http",
+            Operation::Dns => "dns",
+            Operation::Connect => "connect",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl OperationsPolicy {
+    pub fn enabled(&self, operation: Operation) -> bool {
+        match operation {
+            Operation::Read => self.read,
+            Operation::Write => self.write,
+            Operation::Delete => self.delete,
+            Operation::Execute => self.execute,
+            Operation::Http => self.http,
+            Operation::Dns => self.dns,
+            Operation::Connect => self.connect,
+        }
+    }
+
+    /// The operations switched off, in [`Operation::ALL`] order
+    pub fn disabled(&self) -> Vec<Operation> {
+        Operation::ALL.into_iter().filter(|operation| !self.enabled(*operation)).collect()
+    }
+
+    /// The `operation_disabled` denial for an operation switched off
+    pub fn check(&self, operation: Operation) -> Result<(), Denial> {
+        if self.enabled(operation) {
+            return Ok(());
+        }
+        Err(Denial::new(
+            "operation_disabled",
+            format!("{} is disabled by the policy's [operations] section", operation),
+        ))
+    }
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Weekday::Mon => "mon",
+            Weekday::Tue => "tue",
+            Weekday::Wed => "wed",
+            Weekday::Thu => "thu",
+            Weekday::Fri => "fri",
+            Weekday::Sat => "sat",
+            Weekday::Sun => "sun",
+        }
+    }
+}
+
+/// `[schedule]` with its times parsed, asked as each request is decided
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    offset_secs: i64,
+    /// The offset as written, for denials
+    offset: String,
+    windows: Vec<Window>,
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+    operations: Vec<Operation>,
+    /// Seconds after local midnight
+    start: i64,
+    end: i64,
+    /// Days the window starts on; every day when empty
+    days: Vec<Weekday>,
+    /// As written, e.g. `09:00-17:00 on mon, tue`
+    label: String,
+}
+
+impl Window {
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn is_open(&self, day: Weekday, secs: i64) -> bool {
+        if self.start < self.end {
+            return self.starts_on(day) && (self.start..self.end).contains(&secs);
+        }
+        // Past midnight: the evening of a day it starts on, or the morning after
+        let yesterday = Weekday::ALL[(day as usize + 6) % 7];
+        (self.starts_on(day) && secs >= self.start) || (self.starts_on(yesterday) && secs < self.end)
+    }
+}
+
+impl Schedule {
+    /// Fails with a message naming the setting, for policy validation
+    pub fn new(policy: &SchedulePolicy) -> Result<Self, String> {
+        let offset_secs = parse_offset(&policy.utc_offset).ok_or_else(|| {
+            format!(
+                "schedule.utc_offset must be like \"+02:00\", at most {} hours from UTC, got {:?}",
+                MAX_OFFSET_HOURS, policy.utc_offset
+            )
+        })?;
+        let mut windows = Vec::with_capacity(policy.windows.len());
+        for (index, window) in policy.windows.iter().enumerate() {
+            let time = |value: &str| {
+                parse_time(value).ok_or_else(|| {
+                    format!("schedule.windows[{}] {:?} is not a time like \"09:00\"", index, value)
+                })
+            };
+            let (start, end) = (time(&window.start)?, time(&window.end)?);
+            if window.operations.is_empty() {
+                return Err(format!("schedule.windows[{}] names no operations", index));
+            }
+            if start == end || start == DAY_SECS {
+                return Err(format!(
+                    "schedule.windows[{}] {}-{} is empty; a window for the whole day is no window",
+                    index, window.start, window.end
+                ));
+            }
+            let mut label = format!("{}-{}", window.start, window.end);
+            if !window.days.is_empty() {
+                let days: Vec<&str> = window.days.iter().map(|day| day.as_str()).collect();
+                label = format!("{} on {}", label, days.join(", "));
+            }
+            windows.push(Window {
+                operations: window.operations.clone(),
+                start,
+                end,
+                days: window.days.clone(),
+                label,
+            });
+        }
+        Ok(Self {
+            offset_secs,
+            offset: policy.utc_offset.clone(),
+            windows,
+        })
+    }
+
+    /// The `outside_schedule` denial for `operation` at `now`, in Unix
+    /// seconds, when windows name it and none of them is open
+    pub fn check(&self, operation: Operation, now: i64) -> Result<(), Denial> {
+        let local = now + self.offset_secs;
+        // 1970-01-01 was a Thursday
+        let day = Weekday::ALL[(local.div_euclid(DAY_SECS) + 3).rem_euclid(7) as usize];
+        let secs = local.rem_euclid(DAY_SECS);
+        let mut windows = Vec::new();
+        for window in self.windows.iter().filter(|window| window.operations.contains(&operation)) {
+            if window.is_open(day, secs) {
+                return Ok(());
+            }
+            windows.push(window.label.as_str());
+        }
+        if windows.is_empty() {
+            return Ok(());
+        }
+        Err(Denial::new(
+            "outside_schedule",
+            format!(
+                "{} is only allowed {} (UTC{}); it is {:02}:{:02} on {}",
+                operation,
+                windows.join(" or "),
+                self.offset,
+                secs / 3600,
+                secs % 3600 / 60,
+                day.as_str()
+            ),
+        ))
+    }
+}
+
+/// `HH:MM` as seconds after midnight; `24:00` is the end of the day
+fn parse_time(value: &str) -> Option<i64> {
+    let (hours, minutes) = value.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    match (hours, minutes) {
+        (24, 0) => Some(DAY_SECS),
+        (0..=23, 0..=59) => Some(hours * 3600 + minutes * 60),
+        _ => None,
+    }
+}
+
+/// `+HH:MM` or `-HH:MM` as seconds east of UTC
+fn parse_offset(value: &str) -> Option<i64> {
+    let (sign, rest) = match value.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let secs = parse_time(rest).filter(|secs| *secs <= MAX_OFFSET_HOURS * 3600)?;
+    Some(sign * secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::ScheduleWindow;
+
+    /// 2024-03-01, a Friday, at `hh:mm` UTC
+    fn friday_at(hh: i64, mm: i64) -> i64 {
+        1_709_251_200 + hh * 3600 + mm * 60
+    }
+
+    fn window(operations: &[Operation], start: &str, end: &str, days: &[Weekday]) -> ScheduleWindow {
+        ScheduleWindow {
+            operations: operations.to_vec(),
+            start: start.to_string(),
+            end: end.to_string(),
+            days: days.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_decisions_inside_and_outside_the_window() {
+        let weekdays = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+        let schedule = Schedule::new(&SchedulePolicy {
+            utc_offset: "+02:00".to_string(),
+            windows: vec![
+                window(&[Operation::Execute], "09:00", "17:00", &weekdays),
+                window(&[Operation::Http], "22:00", "02:00", &[Weekday::Thu]),
+            ],
+        })
+        .unwrap();
+
+        // 09:30 and 16:59 in UTC+2
+        assert!(schedule.check(Operation::Execute, friday_at(7, 30)).is_ok());
+        assert!(schedule.check(Operation::Execute, friday_at(14, 59)).is_ok());
+        let denial = schedule.check(Operation::Execute, friday_at(15, 0)).unwrap_err();
+        assert_eq!(denial.violation, "outside_schedule");
+        assert_eq!(
+            denial.reason,
+            "execute is only allowed 09:00-17:00 on mon, tue, wed, thu, fri (UTC+02:00); it is 17:00 on fri"
+        );
+        // Saturday morning
+        assert!(schedule.check(Operation::Execute, friday_at(31, 0)).is_err());
+        // Operations no window names run at any time
+        assert!(schedule.check(Operation::Read, friday_at(15, 0)).is_ok());
+
+        // Thursday's window runs into Friday, until 02:00
+        assert!(schedule.check(Operation::Http, friday_at(-1, 0)).is_ok());
+        assert!(schedule.check(Operation::Http, friday_at(-1, 59)).is_ok());
+        assert!(schedule.check(Operation::Http, friday_at(0, 0)).is_err());
+        assert!(schedule.check(Operation::Http, friday_at(20, 0)).is_err());
+
+        assert!(Schedule::default().check(Operation::Execute, friday_at(3, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_schedules_are_rejected() {
+        let invalid = [
+            ("+2", window(&[Operation::Write], "09:00", "17:00", &[])),
+            ("+15:00", window(&[Operation::Write], "09:00", "17:00", &[])),
+            ("+00:00", window(&[Operation::Write], "9:00", "17:00", &[])),
+            ("+00:00", window(&[Operation::Write], "09:00", "17:60", &[])),
+            ("+00:00", window(&[Operation::Write], "09:00", "09:00", &[])),
+            ("+00:00", window(&[], "09:00", "17:00", &[])),
+        ];
+        for (utc_offset, window) in invalid {
+            let policy = SchedulePolicy {
+                utc_offset: utc_offset.to_string(),
+                windows: vec![window.clone()],
+            };
+            assert!(Schedule::new(&policy).is_err(), "{} {:?}", utc_offset, window);
+        }
+        let until_midnight = window(&[Operation::Write], "18:00", "24:00", &[]);
+        let schedule = Schedule::new(&SchedulePolicy {
+            utc_offset: "-05:30".to_string(),
+            windows: vec![until_midnight],
+        })
+        .unwrap();
+        // 23:59 in UTC-5:30
+        assert!(schedule.check(Operation::Write, friday_at(29, 29)).is_ok());
+        assert!(schedule.check(Operation::Write, friday_at(29, 30)).is_err());
+    }
+}
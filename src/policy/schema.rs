@@ -0,0 +1,362 @@
+//! JSON Schema for the policy document, derived from the types in
+//! [`crate::policy`] and their doc comments, and a markdown reference
+//! rendered from it.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use super::Policy;
+
+/// What `--schema` prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaFormat {
+    JsonSchema,
+    Markdown,
+}
+
+impl FromStr for SchemaFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json-schema" => Ok(SchemaFormat::JsonSchema),
+            "markdown" => Ok(SchemaFormat::Markdown),
+            other => Err(format!("unknown schema format {:?} (expected json-schema or markdown)", other)),
+        }
+    }
+}
+
+/// The schema as JSON (draft-07)
+pub fn json_schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(Policy)).expect("schema serializes")
+}
+
+pub fn render(format: SchemaFormat) -> String {
+    let schema = json_schema();
+    match format {
+        SchemaFormat::JsonSchema => serde_json::to_string_pretty(&schema).expect("schema serializes"),
+        SchemaFormat::Markdown => markdown(&schema),
+    }
+}
+
+/// One section per table of the policy, with a row per key
+pub fn markdown(root: &Value) -> String {
+    let mut out = String::from("# Policy reference\n\n");
+    if let Some(description) = root.get("description").and_then(Value::as_str) {
+        let _ = writeln!(out, "{}\n", one_line(description));
+    }
+    section(root, resolve(root, root), "", false, &mut out);
+    out
+}
+
+/// Writes the table for `schema` at `path`, then the tables nested in it
+fn section(root: &Value, schema: &Value, path: &str, array: bool, out: &mut String) {
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let required = required(schema);
+    let mut rows = Vec::new();
+    let mut nested = Vec::new();
+    for (key, property) in properties.into_iter().flatten() {
+        let full = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+        let resolved = resolve(root, property);
+        if is_table(resolved) {
+            nested.push((full, resolved, false));
+            continue;
+        }
+        if let Some(items) = resolved.get("items").map(|items| resolve(root, items)) {
+            if is_table(items) {
+                nested.push((full, items, true));
+                continue;
+            }
+        }
+        let default = if required.contains(&key.as_str()) {
+            "required".to_string()
+        } else {
+            property
+                .get("default")
+                .filter(|default| !default.is_null())
+                .map_or_else(String::new, |default| format!("`{}`", default))
+        };
+        rows.push(format!(
+            "| `{}` | {} | {} | {} |",
+            key,
+            type_name(root, property),
+            default,
+            cell(description(property, resolved)),
+        ));
+    }
+
+    if !path.is_empty() {
+        let heading = if array { format!("[[{}]]", path) } else { format!("[{}]", path) };
+        let _ = writeln!(out, "## `{}`\n", heading);
+        if let Some(text) = schema.get("description").and_then(Value::as_str) {
+            let _ = writeln!(out, "{}\n", one_line(text));
+        }
+    }
+    if !rows.is_empty() {
+        out.push_str("| Key | Type | Default | Description |\n|---|---|---|---|\n");
+        for row in rows {
+            out.push_str(&row);
+            out.push('\n');
+        }
+        out.push('\n');
+    }
+    for (path, schema, array) in nested {
+        section(root, schema, &path, array, out);
+    }
+}
+
+/// Follows a `$ref`, or the single `allOf` schemars wraps one in when the
+/// field has a description or default of its own
+fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    if let Some(target) = schema.get("$ref").and_then(Value::as_str) {
+        let name = target.rsplit('/').next().unwrap_or_default();
+        return root
+            .pointer(&format!("/definitions/{}", name))
+            .map_or(schema, |definition| resolve(root, definition));
+    }
+    match schema.get("allOf").and_then(Value::as_array).map(Vec::as_slice) {
+        Some([only]) => resolve(root, only),
+        _ => schema,
+    }
+}
+
+fn is_table(schema: &Value) -> bool {
+    schema.get("properties").is_some()
+}
+
+fn required(schema: &Value) -> Vec<&str> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|keys| keys.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+fn type_name(root: &Value, schema: &Value) -> String {
+    let schema = resolve(root, schema);
+    if let Some(values) = enum_values(schema) {
+        return values.iter().map(|v| format!("`{}`", v)).collect::<Vec<_>>().join(" \\| ");
+    }
+    if let Some(alternatives) = schema.get("anyOf").and_then(Value::as_array) {
+        let names: Vec<String> = alternatives
+            .iter()
+            .filter(|alternative| alternative.get("type") != Some(&Value::from("null")))
+            .map(|alternative| type_name(root, alternative))
+            .collect();
+        return names.join(" or ");
+    }
+    if is_table(schema) {
+        return "table".to_string();
+    }
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(kind)) => vec![kind.as_str()],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).filter(|k| *k != "null").collect(),
+        _ => vec![],
+    };
+    match types.as_slice() {
+        ["array"] => match schema.get("items") {
+            Some(items) => format!("array of {}", type_name(root, items)),
+            None => "array".to_string(),
+        },
+        ["object"] => match schema.get("additionalProperties") {
+            Some(values) if values.is_object() => format!("table of {}", type_name(root, values)),
+            _ => "table".to_string(),
+        },
+        [kind] => kind.to_string(),
+        _ => "any".to_string(),
+    }
+}
+
+/// The values of a string enum, documented variants included
+fn enum_values(schema: &Value) -> Option<Vec<String>> {
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        return Some(values.iter().map(|v| v.as_str().map_or_else(|| v.to_string(), String::from)).collect());
+    }
+    let variants = schema.get("oneOf")?.as_array()?;
+    let mut values = Vec::new();
+    for variant in variants {
+        values.extend(enum_values(variant)?);
+    }
+    Some(values)
+}
+
+/// The field's own description, or else its type's
+fn description<'a>(property: &'a Value, resolved: &'a Value) -> &'a str {
+    property
+        .get("description")
+        .or_else(|| resolved.get("description"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn cell(text: &str) -> String {
+    one_line(text).replace('|', "\\|")
+}
+
+/// Problems found checking `value` against `schema`, covering the parts of
+/// JSON Schema schemars emits
+#[cfg(test)]
+fn validate(root: &Value, schema: &Value, value: &Value, at: &str, errors: &mut Vec<String>) {
+    let schema = resolve(root, schema);
+    if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+        for part in all {
+            validate(root, part, value, at, errors);
+        }
+    }
+    for (keyword, exactly_one) in [("anyOf", false), ("oneOf", true)] {
+        if let Some(alternatives) = schema.get(keyword).and_then(Value::as_array) {
+            let matching = alternatives
+                .iter()
+                .filter(|alternative| {
+                    let mut found = Vec::new();
+                    validate(root, alternative, value, at, &mut found);
+                    found.is_empty()
+                })
+                .count();
+            if matching == 0 || (exactly_one && matching > 1) {
+                errors.push(format!("{}: {} matches {} of the {} alternatives", at, value, matching, keyword));
+            }
+        }
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            errors.push(format!("{}: {} is not one of {}", at, value, Value::from(values.clone())));
+        }
+    }
+    if let Some(kinds) = schema.get("type") {
+        let kinds: Vec<&str> = match kinds {
+            Value::String(kind) => vec![kind.as_str()],
+            Value::Array(kinds) => kinds.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !kinds.iter().any(|kind| has_type(value, kind)) {
+            errors.push(format!("{}: {} is not {}", at, value, kinds.join(" or ")));
+            return;
+        }
+    }
+    if let (Some(minimum), Some(number)) = (schema.get("minimum").and_then(Value::as_f64), value.as_f64()) {
+        if number < minimum {
+            errors.push(format!("{}: {} is below {}", at, number, minimum));
+        }
+    }
+    if let Some(object) = value.as_object() {
+        validate_object(root, schema, object, at, errors);
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            validate(root, items, item, &format!("{}[{}]", at, i), errors);
+        }
+    }
+}
+
+#[cfg(test)]
+fn validate_object(
+    root: &Value,
+    schema: &Value,
+    object: &serde_json::Map<String, Value>,
+    at: &str,
+    errors: &mut Vec<String>,
+) {
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for key in required(schema) {
+        if !object.contains_key(key) {
+            errors.push(format!("{}: missing {}", at, key));
+        }
+    }
+    for (key, value) in object {
+        let here = if at.is_empty() { key.clone() } else { format!("{}.{}", at, key) };
+        match (properties.and_then(|p| p.get(key)), schema.get("additionalProperties")) {
+            (Some(property), _) => validate(root, property, value, &here, errors),
+            (None, Some(Value::Bool(false))) => errors.push(format!("{}: unknown key", here)),
+            (None, Some(values)) if values.is_object() => validate(root, values, value, &here, errors),
+            // serde would ignore it, but in an example it is a key that
+            // was renamed or never existed
+            (None, _) if properties.is_some() => errors.push(format!("{}: unknown key", here)),
+            (None, _) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+fn has_type(value: &Value, kind: &str) -> bool {
+    match kind {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// The example policies and the valid policies of the fuzz corpus
+    fn example_policies() -> Vec<(String, Value)> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut paths = vec![root.join("examples/policy.toml"), root.join("examples/strict.toml")];
+        for name in ["everything.toml", "minimal.toml", "minimal.json"] {
+            paths.push(root.join("fuzz/corpus/policy_parser").join(name));
+        }
+        paths
+            .into_iter()
+            .map(|path| {
+                let content = std::fs::read_to_string(&path).unwrap();
+                let value = if path.extension().is_some_and(|ext| ext == "json") {
+                    serde_json::from_str(&content).unwrap()
+                } else {
+                    serde_json::to_value(toml::from_str::<toml::Value>(&content).unwrap()).unwrap()
+                };
+                (path.display().to_string(), value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_example_policies_match_the_schema() {
+        let schema = json_schema();
+        for (name, policy) in example_policies() {
+            let mut errors = Vec::new();
+            validate(&schema, &schema, &policy, "", &mut errors);
+            assert!(errors.is_empty(), "{}: {:#?}", name, errors);
+        }
+
+        // And the check is not vacuous
+        let (_, mut policy) = example_policies().remove(0);
+        policy["filesystem"]["allowed_reed"] = Value::from(vec!["/srv"]);
+        policy["network"]["max_connections"] = Value::from("ten");
+        policy["filesystem"]["match_strategy"] = Value::from("best_match");
+        let mut errors = Vec::new();
+        validate(&schema, &schema, &policy, "", &mut errors);
+        assert_eq!(errors.len(), 3, "{:#?}", errors);
+    }
+
+    #[test]
+    fn test_markdown_covers_every_section() {
+        let markdown = markdown(&json_schema());
+        for heading in [
+            "## `[filesystem]`",
+            "## `[filesystem.search]`",
+            "## `[[filesystem.redaction]]`",
+            "## `[[filesystem.rules]]`",
+            "## `[network.http]`",
+            "## `[server]`",
+            "## `[audit]`",
+        ] {
+            assert!(markdown.contains(heading), "{} missing from:\n{}", heading, markdown);
+        }
+        assert!(markdown.contains("| `match_strategy` | `first_match` \\| `last_match` | `\"first_match\"` |"));
+        assert!(markdown.contains("| `allowed_read` | array of string | required |"));
+    }
+}
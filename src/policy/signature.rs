@@ -0,0 +1,161 @@
+//! Detached signatures of the policy file
+//!
+//! With `--policy-pubkey`, a policy file is only loaded when the file
+//! beside it with `.sig` appended holds an Ed25519 signature of its exact
+//! bytes by that key, so a policy swapped in on the host is refused however
+//! well-formed it is. OpenSSL makes both the key and the signature:
+//!
+//! ```sh
+//! openssl genpkey -algorithm ed25519 -out policy-signing.pem
+//! openssl pkey -in policy-signing.pem -pubout -out policy-signing.pub
+//! openssl pkeyutl -sign -rawin -inkey policy-signing.pem -in policy.toml -out policy.toml.sig
+//! ```
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH as PUBLIC_KEY_LEN, SIGNATURE_LENGTH as SIGNATURE_LEN};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The DER of an Ed25519 SubjectPublicKeyInfo up to the key itself
+const SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+const PEM_BEGIN: &str = "-----BEGIN PUBLIC KEY-----";
+const PEM_END: &str = "-----END PUBLIC KEY-----";
+
+/// The Ed25519 key the policy must be signed with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyKey(VerifyingKey);
+
+impl PolicyKey {
+    /// A PEM public key, as `openssl pkey -pubout` writes it, or the
+    /// base64 of the raw 32-byte key
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read policy public key {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid policy public key {}", path.display()))
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let content = content.trim();
+        let key = match content.strip_prefix(PEM_BEGIN) {
+            Some(rest) => {
+                let body = rest.strip_suffix(PEM_END).context("PEM public key has no END line")?;
+                let der = decode(&body.split_whitespace().collect::<String>())?;
+                match der.strip_prefix(&SPKI_PREFIX[..]) {
+                    Some(key) if der.len() == SPKI_PREFIX.len() + PUBLIC_KEY_LEN => key.to_vec(),
+                    _ => anyhow::bail!("PEM public key is not an Ed25519 key"),
+                }
+            }
+            None => decode(content)?,
+        };
+        let key: [u8; PUBLIC_KEY_LEN] = key
+            .try_into()
+            .map_err(|key: Vec<u8>| anyhow::anyhow!("Ed25519 public key must be {} bytes, got {}", PUBLIC_KEY_LEN, key.len()))?;
+        let key = VerifyingKey::from_bytes(&key).context("Ed25519 public key is not a point on the curve")?;
+        Ok(Self(key))
+    }
+
+    /// Fails unless [`signature_path`] of `policy` holds this key's
+    /// signature of `content`, raw as OpenSSL writes it or in base64
+    pub fn verify(&self, policy: &Path, content: &[u8]) -> Result<()> {
+        let path = signature_path(policy);
+        let signature = fs::read(&path).with_context(|| format!("Failed to read policy signature {}", path.display()))?;
+        let signature = match signature.len() {
+            SIGNATURE_LEN => signature,
+            _ => String::from_utf8(signature)
+                .ok()
+                .and_then(|text| decode(text.trim()).ok())
+                .with_context(|| format!("Policy signature {} is neither {} raw bytes nor base64", path.display(), SIGNATURE_LEN))?,
+        };
+        let Ok(signature) = <[u8; SIGNATURE_LEN]>::try_from(signature.as_slice()) else {
+            anyhow::bail!("Policy signature {} must be {} bytes, got {}", path.display(), SIGNATURE_LEN, signature.len());
+        };
+        if !self.signed(content, &signature) {
+            anyhow::bail!(
+                "Policy signature {} does not match {} for the configured public key; refusing to load it",
+                path.display(),
+                policy.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether `signature` is this key's signature of `content`. Strict:
+    /// an unreduced S, or a weak key or R, is refused rather than accepted
+    /// as some verifiers do.
+    fn signed(&self, content: &[u8], signature: &[u8; SIGNATURE_LEN]) -> bool {
+        self.0.verify_strict(content, &Signature::from_bytes(signature)).is_ok()
+    }
+}
+
+/// `policy.toml.sig` for `policy.toml`
+pub fn signature_path(policy: &Path) -> PathBuf {
+    let mut path = policy.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+fn decode(text: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD.decode(text).context("Invalid base64")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_formats() {
+        let pem = "-----BEGIN PUBLIC KEY-----\nMCowBQYDK2VwAyEAA6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=\n-----END PUBLIC KEY-----\n";
+        let raw = "A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=\n";
+        assert_eq!(PolicyKey::parse(pem).unwrap(), PolicyKey::parse(raw).unwrap());
+
+        // An RSA key, and a raw key of the wrong length
+        let rsa = "-----BEGIN PUBLIC KEY-----\nMFwwDQYJKoZIhvcNAQEBBQADSwAwSAJBAMm3\n-----END PUBLIC KEY-----";
+        assert!(PolicyKey::parse(rsa).is_err());
+        let err = PolicyKey::parse("AAEC").unwrap_err().to_string();
+        assert!(err.contains("must be 32 bytes, got 3"), "{}", err);
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_rfc8032_vectors() {
+        // The order of the base point, little-endian
+        const L: [u8; 32] = [
+            0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+        ];
+        for (key, message, signature) in [
+            (
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ] {
+            let key = PolicyKey::parse(&base64::engine::general_purpose::STANDARD.encode(hex(key))).unwrap();
+            let message = hex(message);
+            let mut signature: [u8; 64] = hex(signature).try_into().unwrap();
+            assert!(key.signed(&message, &signature));
+            assert!(!key.signed(b"x", &signature));
+
+            signature[0] ^= 1;
+            assert!(!key.signed(&message, &signature));
+            signature[0] ^= 1;
+            // S + L encodes the same scalar but is not reduced
+            let mut carry = 0;
+            for (byte, l) in signature[32..].iter_mut().zip(L) {
+                let total = u16::from(*byte) + u16::from(l) + carry;
+                *byte = total as u8;
+                carry = total >> 8;
+            }
+            assert!(!key.signed(&message, &signature));
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::cmp::Reverse;
+
+/// Suggestions returned for one denial
+pub const MAX_SUGGESTIONS: usize = 3;
+/// Ranked candidates tried against the caller's filter before giving up
+const MAX_CANDIDATES: usize = 10;
+/// Characters of a name that are compared; longer ones are cut
+const MAX_KEY_LEN: usize = 64;
+
+/// Allow-list entries indexed for "did you mean" suggestions.
+///
+/// Built once per policy load. Entries are compared by a normalized key,
+/// e.g. a command's file name or a domain without its wildcard; the entry
+/// is returned as written in the policy. A lookup is bounded by the key
+/// length and stops comparing an entry once it is further than the
+/// closest useful distance.
+#[derive(Debug, Clone, Default)]
+pub struct SuggestionIndex {
+    entries: Vec<(Vec<char>, String)>,
+}
+
+impl SuggestionIndex {
+    /// `entries` as (key, entry) pairs
+    pub fn new(entries: impl IntoIterator<Item = (String, String)>) -> Self {
+        let mut entries: Vec<(Vec<char>, String)> = entries
+            .into_iter()
+            .map(|(key, entry)| (key.chars().take(MAX_KEY_LEN).collect::<Vec<_>>(), entry))
+            .filter(|(key, _)| !key.is_empty())
+            .collect();
+        entries.sort();
+        entries.dedup_by(|a, b| a.1 == b.1);
+        Self { entries }
+    }
+
+    /// Up to [`MAX_SUGGESTIONS`] entries closest to `key`, by edit distance
+    /// and then by shared prefix, skipping those `allowed` rejects
+    pub fn nearest(&self, key: &str, allowed: impl Fn(&str) -> bool) -> Vec<String> {
+        let key: Vec<char> = key.chars().take(MAX_KEY_LEN).collect();
+        if key.is_empty() {
+            return Vec::new();
+        }
+        // Anything further than this shares too little to be useful
+        let cutoff = (key.len() / 3).max(1);
+        let mut ranked: Vec<(usize, Reverse<usize>, &str)> = self
+            .entries
+            .iter()
+            .filter_map(|(candidate, entry)| {
+                let prefix = key.iter().zip(candidate).take_while(|(a, b)| a == b).count();
+                let distance = match bounded_distance(&key, candidate, cutoff) {
+                    Some(distance) => distance,
+                    // One name extending the other, like `py` and `python3`
+                    None if prefix >= 2 && prefix == key.len().min(candidate.len()) => cutoff + 1,
+                    None => return None,
+                };
+                Some((distance, Reverse(prefix), entry.as_str()))
+            })
+            .collect();
+        ranked.sort_unstable();
+        ranked
+            .into_iter()
+            .take(MAX_CANDIDATES)
+            .map(|(_, _, entry)| entry)
+            .filter(|entry| allowed(entry))
+            .take(MAX_SUGGESTIONS)
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Edit distance between `a` and `b`, counting a swap of neighbouring
+/// characters as one edit, or None once it exceeds `max`
+fn bounded_distance(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            let mut best = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+            if i > 0 && j > 0 && *ca == b[j - 1] && a[i - 1] == *cb {
+                best = best.min(before[j - 1] + 1);
+            }
+            current[j + 1] = best;
+        }
+        // A swap reaches back two rows, so both have to be past `max`
+        if current.iter().chain(&previous).all(|&distance| distance > max) {
+            return None;
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(names: &[&str]) -> SuggestionIndex {
+        SuggestionIndex::new(names.iter().map(|name| (name.to_string(), name.to_string())))
+    }
+
+    #[test]
+    fn test_nearest_entries_rank_by_distance_then_prefix() {
+        let index = index(&["git", "gitk", "grep", "python3", "node", "npm"]);
+        assert_eq!(index.nearest("gi", |_| true), ["git", "gitk"]);
+        assert_eq!(index.nearest("gti", |_| true), ["git"]);
+        assert_eq!(index.nearest("py", |_| true), ["python3"]);
+        assert_eq!(index.nearest("nodejs", |_| true), ["node"]);
+        assert!(index.nearest("kubectl", |_| true).is_empty());
+        assert!(index.nearest("", |_| true).is_empty());
+
+        // Entries the caller rejects are never suggested
+        assert_eq!(index.nearest("gi", |entry| entry != "git"), ["gitk"]);
+    }
+
+    #[test]
+    fn test_bounded_distance() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert_eq!(bounded_distance(&chars("kitten"), &chars("sitting"), 3), Some(3));
+        assert_eq!(bounded_distance(&chars("kitten"), &chars("sitting"), 2), None);
+        assert_eq!(bounded_distance(&chars("a"), &chars("abcdef"), 2), None);
+        assert_eq!(bounded_distance(&chars(""), &chars("ab"), 2), Some(2));
+        assert_eq!(bounded_distance(&chars("gti"), &chars("git"), 1), Some(1));
+    }
+}
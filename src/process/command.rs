@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::network::{check_input_length, Denial};
+use crate::policy::rules::{Candidate, Decision, Effect, RuleSet, Specificity};
+use crate::policy::suggest::SuggestionIndex;
+use crate::policy::{CommandEntry, EnforcementPolicy, ProcessPolicy};
+
+/// How long `<binary> --version` may run before it is killed
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Output of the version probe that is looked at
+const MAX_VERSION_OUTPUT: u64 = 4096;
+
+/// The kind of `allowed_commands` entry that allowed a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchForm {
+    Path,
+    Name,
+    Spec,
+}
+
+impl fmt::Display for MatchForm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MatchForm::Path => "path entry",
+            MatchForm::Name => "name entry",
+            MatchForm::Spec => "structured entry",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandMatch {
+    pub form: MatchForm,
+    /// The entry's name or path as written in the policy
+    pub entry: String,
+    /// The binary that would run
+    pub binary: PathBuf,
+    /// Version reported by the binary, for entries with `min_version`
+    pub version: Option<Version>,
+}
+
+impl CommandMatch {
+    pub fn describe(&self) -> String {
+        let mut reason = format!("Allowed by {} {:?} ({})", self.form, self.entry, self.binary.display());
+        if let Some(version) = &self.version {
+            reason.push_str(&format!(", version {}", version));
+        }
+        reason
+    }
+}
+
+/// A dotted version number; missing components compare as 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version(Vec<u64>);
+
+impl Version {
+    /// The first version number in `text`, e.g. `20.11.1` in `node v20.11.1`
+    pub fn find(text: &str) -> Option<Self> {
+        let start = text.find(|c: char| c.is_ascii_digit())?;
+        let number = &text[start..];
+        let end = number
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(number.len());
+        Self::parse(number[..end].trim_end_matches('.'))
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let parts: Option<Vec<u64>> = text.split('.').map(|p| p.parse().ok()).collect();
+        parts.filter(|p| !p.is_empty()).map(Self)
+    }
+
+    pub fn at_least(&self, min: &Version) -> bool {
+        let len = self.0.len().max(min.0.len());
+        let component = |v: &Version, i: usize| v.0.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| component(self, i).cmp(&component(min, i)))
+            .find(|o| o.is_ne())
+            .is_none_or(|o| o.is_gt())
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        f.write_str(&parts.join("."))
+    }
+}
+
+/// Decides whether a command may run under `allowed_commands`.
+///
+/// Commands are resolved to a binary first: absolute paths as given, bare
+/// names through `search_path`. An entry matches when it resolves to the
+/// same binary under the same file name; the name has to agree because
+/// multi-call binaries such as busybox behave according to it.
+pub struct CommandGuard {
+    rules: RuleSet<CommandEntry>,
+    search_path: Vec<PathBuf>,
+    /// The `allowed_env` variables with the values the daemon started with
+    inherited_env: Vec<(String, OsString)>,
+    /// Entries by the name they are invoked as
+    suggestions: SuggestionIndex,
+    /// Probed versions by binary, valid while its mtime is unchanged
+    versions: Mutex<HashMap<PathBuf, (SystemTime, Option<Version>)>>,
+    max_command_length: usize,
+}
+
+impl CommandGuard {
+    pub fn new(policy: &ProcessPolicy) -> Self {
+        let search_path = if policy.search_path.is_empty() {
+            env::var_os("PATH")
+                .map(|path| env::split_paths(&path).collect())
+                .unwrap_or_default()
+        } else {
+            policy.search_path.iter().map(PathBuf::from).collect()
+        };
+        let suggestions = SuggestionIndex::new(policy.allowed_commands.iter().map(|entry| {
+            // A pinned binary is suggested by path, since that is what passes
+            let (name, suggestion) = match entry {
+                CommandEntry::Plain(command) => (command, command),
+                CommandEntry::Spec(spec) => (&spec.name, spec.path.as_ref().unwrap_or(&spec.name)),
+            };
+            (file_name(name).to_string(), suggestion.clone())
+        }));
+        let inherited_env = policy
+            .allowed_env
+            .iter()
+            .filter_map(|name| Some((name.clone(), env::var_os(name)?)))
+            .collect();
+        Self {
+            rules: command_rules(&policy.allowed_commands),
+            search_path,
+            inherited_env,
+            suggestions,
+            versions: Mutex::new(HashMap::new()),
+            max_command_length: EnforcementPolicy::default().max_command_length,
+        }
+    }
+
+    /// Denies commands over `[enforcement] max_command_length`, instead of
+    /// the default
+    pub fn with_max_command_length(mut self, max: usize) -> Self {
+        self.max_command_length = max;
+        self
+    }
+
+    /// May run `--version` on the binary, so call it off the async runtime.
+    /// Of the entries that allow the command, the most specific decides
+    /// and is reported, a path entry before a name.
+    pub fn check(&self, command: &str) -> Result<CommandMatch, Denial> {
+        let (binary, name) = self.target(command)?;
+        let mut version_denial = None;
+        let winner = self.rules.winner(|entry| match self.entry_allows(entry, &binary, name) {
+            Ok(_) => true,
+            Err(denial) => {
+                if denial.is_some() {
+                    version_denial = denial;
+                }
+                false
+            }
+        });
+        let Some((_, candidate, entry)) = winner else {
+            return Err(version_denial.unwrap_or_else(|| {
+                Denial::new(
+                    "command_not_allowed",
+                    format!("{} ({}) is not in allowed_commands", command, binary.display()),
+                )
+            }));
+        };
+        // Versions are cached, so asking the winner again probes nothing
+        let version = self.entry_allows(entry, &binary, name).ok().flatten();
+        Ok(CommandMatch {
+            form: form_of(entry),
+            entry: candidate.entry.clone(),
+            binary,
+            version,
+        })
+    }
+
+    /// Every `allowed_commands` entry allowing `command`, the deciding one
+    /// first. Denied when the command does not resolve at all.
+    pub fn explain(&self, command: &str) -> Decision {
+        let Ok((binary, name)) = self.target(command) else {
+            return Decision::default();
+        };
+        self.rules.evaluate(|entry| self.entry_allows(entry, &binary, name).is_ok())
+    }
+
+    /// The binary `command` runs, and the name it runs under
+    fn target<'a>(&self, command: &'a str) -> Result<(PathBuf, &'a str), Denial> {
+        check_input_length("Command", command, "max_command_length", self.max_command_length)?;
+        let Some(binary) = self.resolve(command) else {
+            return Err(if command.contains('/') && !Path::new(command).is_absolute() {
+                Denial::new(
+                    "command_not_allowed",
+                    format!("{} is a relative path; use a bare name or an absolute path", command),
+                )
+            } else {
+                Denial::new("command_not_found", format!("{} is not an executable", command))
+            });
+        };
+        Ok((binary, file_name(command)))
+    }
+
+    /// Whether `entry` allows running `binary` as `name`, with the version
+    /// found when it sets `min_version`. The error holds the denial for a
+    /// version that is too old, or `None` when the entry is for another
+    /// binary.
+    fn entry_allows(&self, entry: &CommandEntry, binary: &Path, name: &str) -> Result<Option<Version>, Option<Denial>> {
+        let (entry_name, expected, min_version) = match entry {
+            CommandEntry::Plain(path) if Path::new(path).is_absolute() => {
+                (path, canonical_binary(Path::new(path)), None)
+            }
+            CommandEntry::Plain(bare) => (bare, self.lookup(bare), None),
+            CommandEntry::Spec(spec) => {
+                let expected = match &spec.path {
+                    Some(path) => canonical_binary(Path::new(path)),
+                    None => self.lookup(&spec.name),
+                };
+                (&spec.name, expected, spec.min_version.as_deref())
+            }
+        };
+        if file_name(entry_name) != name || expected.as_deref() != Some(binary) {
+            return Err(None);
+        }
+        let Some(min) = min_version.and_then(Version::parse) else {
+            return Ok(None);
+        };
+        match self.version_of(binary) {
+            Some(version) if version.at_least(&min) => Ok(Some(version)),
+            found => {
+                let found = found.map_or("no version".to_string(), |v| format!("version {}", v));
+                Err(Some(Denial::new(
+                    "command_version_unmet",
+                    format!("{} reports {}, {} or later is required", binary.display(), found, min),
+                )))
+            }
+        }
+    }
+
+    /// Allowed commands named like `command`, for a denial. Only entries
+    /// that would pass [`check`](Self::check) themselves are suggested, so
+    /// this may probe versions too.
+    pub fn suggest(&self, command: &str) -> Vec<String> {
+        self.suggestions
+            .nearest(file_name(command), |entry| entry != command && self.check(entry).is_ok())
+    }
+
+    fn resolve(&self, command: &str) -> Option<PathBuf> {
+        let path = Path::new(command);
+        if path.is_absolute() {
+            canonical_binary(path)
+        } else if command.contains('/') {
+            None
+        } else {
+            self.lookup(command)
+        }
+    }
+
+    /// The first executable called `name` on the search path
+    fn lookup(&self, name: &str) -> Option<PathBuf> {
+        self.search_path
+            .iter()
+            .find_map(|dir| canonical_binary(&dir.join(name)))
+    }
+
+    /// Replaces the environment `command` would inherit with the
+    /// `allowed_env` variables, then sets `overrides` on top. Anything
+    /// else in the daemon's environment, such as credentials it was
+    /// started with, never reaches the child.
+    pub fn set_child_env(&self, command: &mut Command, overrides: &HashMap<String, String>) {
+        command.env_clear();
+        command.envs(self.inherited_env.iter().map(|(name, value)| (name, value)));
+        command.envs(overrides);
+    }
+
+    fn version_of(&self, binary: &Path) -> Option<Version> {
+        let modified = fs::metadata(binary).and_then(|m| m.modified()).ok()?;
+        let mut versions = self.versions.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((mtime, version)) = versions.get(binary) {
+            if *mtime == modified {
+                return version.clone();
+            }
+        }
+        let mut probe = Command::new(binary);
+        self.set_child_env(&mut probe, &HashMap::new());
+        let version = probe_version(probe);
+        versions.insert(binary.to_path_buf(), (modified, version.clone()));
+        version
+    }
+}
+
+fn form_of(entry: &CommandEntry) -> MatchForm {
+    match entry {
+        CommandEntry::Plain(path) if Path::new(path).is_absolute() => MatchForm::Path,
+        CommandEntry::Plain(_) => MatchForm::Name,
+        CommandEntry::Spec(_) => MatchForm::Spec,
+    }
+}
+
+fn file_name(command: &str) -> &str {
+    command.rsplit('/').next().unwrap_or(command)
+}
+
+/// `path` with symlinks resolved, if it is an executable file
+/// The entries as rules, a path pinning one binary more specific than a name
+fn command_rules(entries: &[CommandEntry]) -> RuleSet<CommandEntry> {
+    let mut rules = RuleSet::default();
+    for entry in entries {
+        let (written, is_path) = match entry {
+            CommandEntry::Plain(command) => (command, Path::new(command).is_absolute()),
+            CommandEntry::Spec(spec) => (&spec.name, spec.path.is_some()),
+        };
+        let candidate = Candidate::new(
+            "process.allowed_commands",
+            written.as_str(),
+            Effect::Allow,
+            Specificity::command(is_path),
+        );
+        rules.push(candidate, entry.clone());
+    }
+    rules
+}
+
+fn canonical_binary(path: &Path) -> Option<PathBuf> {
+    let binary = fs::canonicalize(path).ok()?;
+    let metadata = fs::metadata(&binary).ok()?;
+    is_executable(&metadata).then_some(binary)
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    metadata.is_file()
+}
+
+/// Runs `<binary> --version` directly, without a shell, and reads the first
+/// version number from its stdout or stderr. A probe that fails or runs past
+/// [`VERSION_PROBE_TIMEOUT`] reports no version. Output beyond the pipe
+/// buffer would stall the probe until the timeout, which is plenty for a
+/// version banner.
+fn probe_version(mut command: Command) -> Option<Version> {
+    let mut child = command
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .ok()?;
+
+    let deadline = Instant::now() + VERSION_PROBE_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+        }
+    }
+
+    let mut output = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        let _ = stdout.take(MAX_VERSION_OUTPUT).read_to_string(&mut output);
+    }
+    Version::find(&output).or_else(|| {
+        let mut errors = String::new();
+        let _ = child.stderr.take()?.take(MAX_VERSION_OUTPUT).read_to_string(&mut errors);
+        Version::find(&errors)
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::policy::CommandSpec;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.canonicalize().unwrap()
+    }
+
+    fn guard(entries: Vec<CommandEntry>, search_path: &[&Path]) -> CommandGuard {
+        CommandGuard::new(&ProcessPolicy {
+            allowed_commands: entries,
+            max_cpu_percent: 50,
+            max_memory_mb: 1024,
+            search_path: search_path.iter().map(|p| p.display().to_string()).collect(),
+            allowed_env: vec![],
+        })
+    }
+
+    #[test]
+    fn test_path_entries_pin_one_binary() {
+        let dir = TempDir::new().unwrap();
+        let (system, local) = (dir.path().join("usr/bin"), dir.path().join("home/bin"));
+        let python = script(&system, "python3", "true");
+        script(&local, "python3", "true");
+
+        let entries = vec![CommandEntry::Plain(python.display().to_string())];
+        let guard = guard(entries, &[local.as_path(), system.as_path()]);
+
+        let allowed = guard.check(&python.display().to_string()).unwrap();
+        assert_eq!(allowed.form, MatchForm::Path);
+        assert_eq!(allowed.binary, python);
+        // The bare name resolves to the other python3 first
+        assert_eq!(guard.check("python3").unwrap_err().violation, "command_not_allowed");
+    }
+
+    #[test]
+    fn test_bare_names_follow_search_path() {
+        let dir = TempDir::new().unwrap();
+        let (bin, other) = (dir.path().join("bin"), dir.path().join("other"));
+        let tool = script(&bin, "tool", "true");
+        let copy = script(&other, "tool", "true");
+        std::os::unix::fs::symlink(&tool, bin.join("alias")).unwrap();
+
+        let guard = guard(vec![CommandEntry::Plain("tool".into())], &[bin.as_path()]);
+        let allowed = guard.check("tool").unwrap();
+        assert_eq!((allowed.form, allowed.binary), (MatchForm::Name, tool.clone()));
+        assert!(guard.check(&tool.display().to_string()).is_ok());
+
+        assert_eq!(guard.check(&copy.display().to_string()).unwrap_err().violation, "command_not_allowed");
+        // Same binary under another name, as with busybox applets
+        assert_eq!(guard.check("alias").unwrap_err().violation, "command_not_allowed");
+        assert_eq!(guard.check("missing").unwrap_err().violation, "command_not_found");
+        assert_eq!(guard.check("bin/tool").unwrap_err().violation, "command_not_allowed");
+    }
+
+    /// Locks in [`RuleSet`]'s precedence for commands
+    #[test]
+    fn test_precedence_golden() {
+        let dir = TempDir::new().unwrap();
+        let bin = dir.path().join("bin");
+        let tool = script(&bin, "tool", "echo 1.0");
+        let pinned = CommandEntry::Spec(CommandSpec {
+            name: "tool".into(),
+            path: Some(tool.display().to_string()),
+            min_version: None,
+        });
+        let too_new = CommandEntry::Spec(CommandSpec {
+            name: "tool".into(),
+            path: Some(tool.display().to_string()),
+            min_version: Some("2".into()),
+        });
+        let path = tool.display().to_string();
+        let cases: [(Vec<CommandEntry>, Option<MatchForm>, Vec<&str>); 4] = [
+            // Named first, but the path entry is more specific
+            (
+                vec![CommandEntry::Plain("tool".into()), CommandEntry::Plain(path.clone())],
+                Some(MatchForm::Path),
+                vec![path.as_str(), "tool"],
+            ),
+            (
+                vec![CommandEntry::Plain("tool".into()), pinned],
+                Some(MatchForm::Spec),
+                vec!["tool", "tool"],
+            ),
+            // An entry whose condition fails does not match at all
+            (vec![CommandEntry::Plain("tool".into()), too_new.clone()], Some(MatchForm::Name), vec!["tool"]),
+            (vec![too_new], None, vec![]),
+        ];
+        for (entries, form, matching) in cases {
+            let guard = guard(entries, &[bin.as_path()]);
+            let decision = guard.explain("tool");
+            let entries: Vec<&str> = decision.winner.iter().chain(&decision.losers).map(|c| c.entry.as_str()).collect();
+            assert_eq!(entries, matching);
+            assert_eq!(guard.check("tool").ok().map(|allowed| allowed.form), form);
+            assert_eq!(decision.allowed(), form.is_some());
+        }
+    }
+
+    #[test]
+    fn test_min_version_is_probed_once_per_mtime() {
+        let dir = TempDir::new().unwrap();
+        let bin = dir.path().join("bin");
+        let probes = dir.path().join("probes");
+        let node = script(&bin, "node", &format!("echo probe >> {}\necho v20.11.1", probes.display()));
+
+        let spec = |min: &str| {
+            CommandEntry::Spec(CommandSpec {
+                name: "node".into(),
+                path: Some(node.display().to_string()),
+                min_version: Some(min.into()),
+            })
+        };
+        let guard = guard(vec![spec("21"), spec("20")], &[]);
+
+        let allowed = guard.check(&node.display().to_string()).unwrap();
+        assert_eq!(allowed.form, MatchForm::Spec);
+        assert_eq!(allowed.version, Version::parse("20.11.1"));
+        assert!(allowed.describe().contains("version 20.11.1"));
+        guard.check(&node.display().to_string()).unwrap();
+        assert_eq!(fs::read_to_string(&probes).unwrap().lines().count(), 1);
+
+        let only_21 = super::CommandGuard {
+            rules: super::command_rules(&[spec("21")]),
+            ..guard
+        };
+        let denial = only_21.check(&node.display().to_string()).unwrap_err();
+        assert_eq!(denial.violation, "command_version_unmet");
+        assert_eq!(fs::read_to_string(&probes).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_suggestions_only_name_commands_that_pass() {
+        let dir = TempDir::new().unwrap();
+        let bin = dir.path().join("bin");
+        script(&bin, "python3", "true");
+        script(&bin, "pip3", "true");
+        let node = script(&dir.path().join("opt"), "node", "true");
+
+        let entries = vec![
+            CommandEntry::Plain("python3".into()),
+            // Not installed, so it would be denied too
+            CommandEntry::Plain("python2".into()),
+            CommandEntry::Plain("pip3".into()),
+            CommandEntry::Spec(CommandSpec {
+                name: "node".into(),
+                path: Some(node.display().to_string()),
+                min_version: None,
+            }),
+        ];
+        let guard = guard(entries, &[bin.as_path()]);
+        assert_eq!(guard.suggest("python"), ["python3"]);
+        assert_eq!(guard.suggest("/usr/bin/pip"), ["pip3"]);
+        assert_eq!(guard.suggest("nodejs"), [node.display().to_string()]);
+        assert!(guard.suggest("rm").is_empty());
+    }
+
+    #[test]
+    fn test_version_parsing_and_comparison() {
+        assert_eq!(Version::find("Python 3.11.4\n"), Version::parse("3.11.4"));
+        assert_eq!(Version::find("git version 2.43.0."), Version::parse("2.43.0"));
+        assert_eq!(Version::find("no digits"), None);
+
+        let v = |s| Version::parse(s).unwrap();
+        assert!(v("20.11.1").at_least(&v("20")));
+        assert!(v("20").at_least(&v("20.0")));
+        assert!(!v("19.9").at_least(&v("20")));
+        assert!(v("3.10").at_least(&v("3.9")));
+        assert!(Version::parse("20.x").is_none());
+    }
+
+    #[test]
+    fn test_child_env_strips_unlisted_variables() {
+        // Names no other test uses, since the environment is process-wide
+        env::set_var("OPENCLAW_TEST_SECRET", "hunter2");
+        env::set_var("OPENCLAW_TEST_LOCALE", "C.UTF-8");
+        let run = |allowed_env: &[&str], overrides: &[(&str, &str)]| {
+            let guard = CommandGuard::new(&ProcessPolicy {
+                allowed_commands: vec![],
+                max_cpu_percent: 50,
+                max_memory_mb: 1024,
+                search_path: vec![],
+                allowed_env: allowed_env.iter().map(|name| name.to_string()).collect(),
+            });
+            let mut command = Command::new("/bin/sh");
+            command.args(["-c", "echo \"$OPENCLAW_TEST_SECRET:$OPENCLAW_TEST_LOCALE:$GREETING\""]);
+            let overrides = overrides.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            guard.set_child_env(&mut command, &overrides);
+            String::from_utf8(command.output().unwrap().stdout).unwrap()
+        };
+
+        assert_eq!(run(&["OPENCLAW_TEST_LOCALE"], &[]), ":C.UTF-8:\n");
+        assert_eq!(run(&[], &[("GREETING", "hi")]), "::hi\n");
+        assert_eq!(run(&["OPENCLAW_TEST_SECRET"], &[]), "hunter2::\n");
+        // Request values win over inherited ones
+        assert_eq!(run(&["OPENCLAW_TEST_SECRET"], &[("OPENCLAW_TEST_SECRET", "x")]), "x::\n");
+    }
+}
@@ -1 +1,3 @@
 // Process execution security
+pub mod command;
+pub mod privileges;
@@ -0,0 +1,178 @@
+//! Dropping root once the daemon's socket is bound.
+//!
+//! Every path the policy allows is opened with the daemon's own rights, so
+//! a daemon left running as root turns each allowed_read entry into the
+//! whole of what root can read beneath it.
+
+use anyhow::Result;
+
+use crate::policy::ServerPolicy;
+
+/// Effective user and group of the daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Switches to `[server] run_as_user` and `run_as_group`, applies
+/// `no_new_privs`, and refuses to carry on as root unless `allow_root`.
+///
+/// Call it before any other thread is started: `no_new_privs` only
+/// applies to the calling thread and those it starts afterwards. Any
+/// error is fatal; a daemon that meant to drop privileges and could not
+/// must not serve.
+#[cfg(target_os = "linux")]
+pub fn drop_privileges(server: &ServerPolicy, allow_root: bool) -> Result<Option<Identity>> {
+    use anyhow::Context;
+    use nix::unistd::{self, Uid};
+
+    let target = resolve(server)?;
+    let was_root = unistd::geteuid().is_root();
+    if let Some(gid) = target.gid {
+        // Supplementary groups are dropped as well, root's included
+        if was_root {
+            unistd::setgroups(&[gid]).context("setgroups failed")?;
+        }
+        unistd::setgid(gid).with_context(|| format!("setgid({}) failed", gid))?;
+    }
+    if let Some(uid) = target.uid {
+        unistd::setuid(uid).with_context(|| format!("setuid({}) failed", uid))?;
+        if was_root && !uid.is_root() && unistd::setuid(Uid::from_raw(0)).is_ok() {
+            anyhow::bail!("root could be regained after setuid({})", uid);
+        }
+    }
+    if server.no_new_privs {
+        nix::sys::prctl::set_no_new_privs().context("PR_SET_NO_NEW_PRIVS failed")?;
+        caps::clear(None, caps::CapSet::Ambient).context("Clearing ambient capabilities failed")?;
+    }
+
+    let identity = current().expect("identity is known on Linux");
+    if identity.uid == 0 && !allow_root {
+        anyhow::bail!("refusing to run as root; set [server] run_as_user, or pass --allow-root");
+    }
+    Ok(Some(identity))
+}
+
+/// Only Linux can switch users here; elsewhere setting any of the options
+/// is an error and the root check is skipped
+#[cfg(not(target_os = "linux"))]
+pub fn drop_privileges(server: &ServerPolicy, _allow_root: bool) -> Result<Option<Identity>> {
+    if server.run_as_user.is_some() || server.run_as_group.is_some() || server.no_new_privs {
+        anyhow::bail!("run_as_user, run_as_group and no_new_privs are only supported on Linux");
+    }
+    Ok(None)
+}
+
+#[cfg(target_os = "linux")]
+pub fn current() -> Option<Identity> {
+    Some(Identity {
+        uid: nix::unistd::geteuid().as_raw(),
+        gid: nix::unistd::getegid().as_raw(),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current() -> Option<Identity> {
+    None
+}
+
+/// The ids `[server]` names; the group defaults to the user's primary one
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq)]
+struct Target {
+    uid: Option<nix::unistd::Uid>,
+    gid: Option<nix::unistd::Gid>,
+}
+
+#[cfg(target_os = "linux")]
+fn resolve(server: &ServerPolicy) -> Result<Target> {
+    use anyhow::Context;
+    use nix::unistd::{Gid, Group, Uid, User};
+
+    let user = match server.run_as_user.as_deref() {
+        None => None,
+        Some(spec) => {
+            let found = match spec.parse::<u32>() {
+                Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+                Err(_) => User::from_name(spec),
+            }
+            .with_context(|| format!("Looking up run_as_user {:?} failed", spec))?;
+            match (found, spec.parse::<u32>()) {
+                (Some(user), _) => Some((user.uid, Some(user.gid))),
+                // A bare uid need not have a passwd entry
+                (None, Ok(uid)) => Some((Uid::from_raw(uid), None)),
+                (None, Err(_)) => anyhow::bail!("run_as_user {:?} does not exist", spec),
+            }
+        }
+    };
+    let group = match server.run_as_group.as_deref() {
+        None => None,
+        Some(spec) => {
+            let found = match spec.parse::<u32>() {
+                Ok(gid) => Group::from_gid(Gid::from_raw(gid)),
+                Err(_) => Group::from_name(spec),
+            }
+            .with_context(|| format!("Looking up run_as_group {:?} failed", spec))?;
+            match (found, spec.parse::<u32>()) {
+                (Some(group), _) => Some(group.gid),
+                (None, Ok(gid)) => Some(Gid::from_raw(gid)),
+                (None, Err(_)) => anyhow::bail!("run_as_group {:?} does not exist", spec),
+            }
+        }
+    };
+
+    let gid = match (group, user) {
+        (Some(gid), _) => Some(gid),
+        (None, Some((_, Some(primary)))) => Some(primary),
+        (None, Some((uid, None))) => {
+            anyhow::bail!("run_as_user {} has no passwd entry; set run_as_group", uid)
+        }
+        (None, None) => None,
+    };
+    Ok(Target {
+        uid: user.map(|(uid, _)| uid),
+        gid,
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use nix::unistd::{Gid, Uid};
+
+    fn server(user: Option<&str>, group: Option<&str>) -> ServerPolicy {
+        ServerPolicy {
+            run_as_user: user.map(String::from),
+            run_as_group: group.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_users_and_groups() {
+        let root = Target {
+            uid: Some(Uid::from_raw(0)),
+            gid: Some(Gid::from_raw(0)),
+        };
+        assert_eq!(resolve(&server(Some("root"), None)).unwrap(), root);
+        assert_eq!(resolve(&server(Some("0"), Some("0"))).unwrap(), root);
+        assert_eq!(
+            resolve(&server(None, Some("4242424"))).unwrap(),
+            Target {
+                uid: None,
+                gid: Some(Gid::from_raw(4242424)),
+            }
+        );
+        assert_eq!(resolve(&server(None, None)).unwrap(), Target { uid: None, gid: None });
+
+        for (user, group) in [
+            (Some("no-such-user-openclaw"), None),
+            (None, Some("no-such-group-openclaw")),
+            // Nothing to take the group from
+            (Some("4242424"), None),
+        ] {
+            assert!(resolve(&server(user, group)).is_err(), "{:?} {:?}", user, group);
+        }
+    }
+}
@@ -4,3 +4,7 @@ pub mod openclaw {
         tonic::include_proto!("openclaw.enforce");
     }
 }
+
+/// Encoded descriptors of `proto/enforce.proto`, for server reflection
+#[cfg(feature = "reflection")]
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("enforce_descriptor");
@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Decisions kept for the percentile
+const WINDOW: usize = 1024;
+/// The percentile is recomputed every this many decisions
+const CHECK_EVERY: u64 = 64;
+/// Fewer decisions than this say nothing about load
+const MIN_SAMPLES: usize = 128;
+
+/// A change between normal and degraded operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Degraded { p99: Duration },
+    Recovered { p99: Duration },
+}
+
+struct Samples {
+    window: Vec<Duration>,
+    next: usize,
+    recorded: u64,
+    p99: Duration,
+}
+
+/// Tracks how long path decisions take against `[server] latency_budget_ms`.
+///
+/// The daemon degrades once the p99 over the last decisions exceeds the
+/// budget, and recovers once it falls to half the budget, so it does not
+/// flap around the limit.
+pub struct DecisionLatency {
+    samples: Mutex<Samples>,
+    degraded: AtomicBool,
+}
+
+impl Default for DecisionLatency {
+    fn default() -> Self {
+        Self {
+            samples: Mutex::new(Samples {
+                window: Vec::with_capacity(WINDOW),
+                next: 0,
+                recorded: 0,
+                p99: Duration::ZERO,
+            }),
+            degraded: AtomicBool::new(false),
+        }
+    }
+}
+
+impl DecisionLatency {
+    /// Adds one decision. Returns the transition it caused, if any; with
+    /// no budget a degraded daemon recovers at once.
+    pub fn record(&self, elapsed: Duration, budget: Option<Duration>) -> Option<Transition> {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        if samples.window.len() < WINDOW {
+            samples.window.push(elapsed);
+        } else {
+            let next = samples.next;
+            samples.window[next] = elapsed;
+        }
+        samples.next = (samples.next + 1) % WINDOW;
+        samples.recorded += 1;
+
+        let Some(budget) = budget else {
+            return self.set_degraded(false).then_some(Transition::Recovered { p99: samples.p99 });
+        };
+        if !samples.recorded.is_multiple_of(CHECK_EVERY) || samples.window.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut sorted = samples.window.clone();
+        let rank = sorted.len() * 99 / 100;
+        let p99 = *sorted.select_nth_unstable(rank).1;
+        samples.p99 = p99;
+
+        if p99 > budget {
+            self.set_degraded(true).then_some(Transition::Degraded { p99 })
+        } else if p99 <= budget / 2 {
+            self.set_degraded(false).then_some(Transition::Recovered { p99 })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// As of the last check
+    pub fn p99(&self) -> Duration {
+        self.samples.lock().unwrap_or_else(|e| e.into_inner()).p99
+    }
+
+    /// Whether this changed the state
+    fn set_degraded(&self, degraded: bool) -> bool {
+        self.degraded.swap(degraded, Ordering::Relaxed) != degraded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(latency: &DecisionLatency, n: usize, elapsed: Duration, budget: Option<Duration>) -> Vec<Transition> {
+        (0..n).filter_map(|_| latency.record(elapsed, budget)).collect()
+    }
+
+    #[test]
+    fn test_degrades_over_budget_and_recovers_under_half() {
+        let latency = DecisionLatency::default();
+        let budget = Some(Duration::from_millis(10));
+        assert!(feed(&latency, WINDOW, Duration::from_millis(1), budget).is_empty());
+
+        let slow = feed(&latency, WINDOW, Duration::from_millis(20), budget);
+        assert_eq!(slow, vec![Transition::Degraded { p99: Duration::from_millis(20) }]);
+        assert!(latency.is_degraded());
+
+        // Back under the budget but above half of it: still degraded
+        assert!(feed(&latency, WINDOW, Duration::from_millis(8), budget).is_empty());
+        assert!(latency.is_degraded());
+
+        let fast = feed(&latency, WINDOW, Duration::from_millis(2), budget);
+        assert_eq!(fast, vec![Transition::Recovered { p99: Duration::from_millis(2) }]);
+        assert!(!latency.is_degraded());
+    }
+
+    #[test]
+    fn test_removing_the_budget_recovers() {
+        let latency = DecisionLatency::default();
+        feed(&latency, WINDOW, Duration::from_secs(1), Some(Duration::from_millis(1)));
+        assert!(latency.is_degraded());
+        assert!(matches!(
+            latency.record(Duration::from_secs(1), None),
+            Some(Transition::Recovered { .. })
+        ));
+        assert!(feed(&latency, MIN_SAMPLES, Duration::ZERO, None).is_empty());
+    }
+}
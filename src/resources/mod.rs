@@ -0,0 +1,3 @@
+// Resource usage monitoring
+pub mod latency;
+pub mod monitor;
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::policy::Policy;
+
+/// Usage of the daemon process. Fields are `None` where the platform does
+/// not expose them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub open_files: Option<u64>,
+    pub memory_bytes: Option<u64>,
+}
+
+impl ResourceUsage {
+    #[cfg(target_os = "linux")]
+    pub fn sample() -> Self {
+        let open_files = std::fs::read_dir("/proc/self/fd")
+            .ok()
+            .map(|entries| entries.count() as u64);
+        let memory_bytes = std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| {
+                let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+                line.split_whitespace().nth(1)?.parse::<u64>().ok()
+            })
+            .map(|kb| kb * 1024);
+        Self {
+            open_files,
+            memory_bytes,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn sample() -> Self {
+        Self::default()
+    }
+}
+
+/// A soft limit that usage has reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoftLimitWarning {
+    pub resource: &'static str,
+    pub value: u64,
+    pub threshold: u64,
+    pub limit: u64,
+}
+
+impl SoftLimitWarning {
+    pub fn message(&self) -> String {
+        format!(
+            "{} at {} crossed warning threshold {} (limit {})",
+            self.resource, self.value, self.threshold, self.limit
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Threshold {
+    resource: &'static str,
+    warn_at: u64,
+    limit: u64,
+}
+
+/// Compares usage against the `warn_*` thresholds of the policy.
+///
+/// Each resource warns at most once per `warn_interval_secs` while it stays
+/// above its threshold, so hovering around the line does not flood the log.
+pub struct ResourceMonitor {
+    open_files: Option<Threshold>,
+    memory: Option<Threshold>,
+    interval: Duration,
+    last_warned: Mutex<HashMap<&'static str, Instant>>,
+}
+
+impl ResourceMonitor {
+    pub fn new(policy: &Policy) -> Self {
+        let resources = &policy.resources;
+        const MB: u64 = 1024 * 1024;
+        Self {
+            open_files: resources.warn_open_files.map(|warn_at| Threshold {
+                resource: "open_files",
+                warn_at: warn_at as u64,
+                limit: resources.max_open_files as u64,
+            }),
+            memory: resources.warn_memory_mb.map(|warn_at| Threshold {
+                resource: "memory_bytes",
+                warn_at: warn_at * MB,
+                limit: policy.process.max_memory_mb * MB,
+            }),
+            interval: Duration::from_secs(resources.warn_interval_secs),
+            last_warned: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.open_files.is_some() || self.memory.is_some()
+    }
+
+    /// Returns the thresholds crossed by `usage` that are due a warning
+    pub fn check(&self, usage: &ResourceUsage) -> Vec<SoftLimitWarning> {
+        let readings = [
+            (self.open_files, usage.open_files),
+            (self.memory, usage.memory_bytes),
+        ];
+
+        let now = Instant::now();
+        let mut last_warned = self.last_warned.lock().unwrap_or_else(|e| e.into_inner());
+        readings
+            .into_iter()
+            .filter_map(|(threshold, value)| {
+                let (threshold, value) = (threshold?, value?);
+                if value < threshold.warn_at {
+                    return None;
+                }
+                let due = last_warned
+                    .get(threshold.resource)
+                    .is_none_or(|at| now.duration_since(*at) >= self.interval);
+                if !due {
+                    return None;
+                }
+                last_warned.insert(threshold.resource, now);
+                Some(SoftLimitWarning {
+                    resource: threshold.resource,
+                    value,
+                    threshold: threshold.warn_at,
+                    limit: threshold.limit,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{
+        AuditPolicy, EnforcementPolicy, EscalationPolicy, FilesystemPolicy, NetworkPolicy, PerformancePolicy,
+        ProcessPolicy, ResourcePolicy, ServerPolicy, SessionsPolicy, StatePolicy,
+    };
+
+    fn create_test_policy(warn_interval_secs: u64) -> Policy {
+        Policy {
+            workspace_root: None,
+            vars: Default::default(),
+            filesystem: FilesystemPolicy::default(),
+            network: NetworkPolicy::default(),
+            process: ProcessPolicy {
+                allowed_commands: vec![],
+                max_cpu_percent: 50,
+                max_memory_mb: 2048,
+                search_path: vec![],
+                allowed_env: vec![],
+            },
+            resources: ResourcePolicy {
+                max_file_size_mb: 100,
+                max_open_files: 1000,
+                warn_open_files: Some(800),
+                warn_memory_mb: None,
+                warn_interval_secs,
+            },
+            audit: AuditPolicy::default(),
+            server: ServerPolicy::default(),
+            escalation: EscalationPolicy::default(),
+            sessions: SessionsPolicy::default(),
+            enforcement: EnforcementPolicy::default(),
+            performance: PerformancePolicy::default(),
+            state: StatePolicy::default(),
+            schedule: Default::default(),
+            operations: Default::default(),
+            messages: Default::default(),
+        }
+    }
+
+    fn usage(open_files: u64) -> ResourceUsage {
+        ResourceUsage {
+            open_files: Some(open_files),
+            memory_bytes: Some(u64::MAX),
+        }
+    }
+
+    #[test]
+    fn test_warns_above_threshold_only() {
+        let monitor = ResourceMonitor::new(&create_test_policy(0));
+        assert!(monitor.check(&usage(799)).is_empty());
+
+        let warnings = monitor.check(&usage(850));
+        assert_eq!(
+            warnings,
+            vec![SoftLimitWarning {
+                resource: "open_files",
+                value: 850,
+                threshold: 800,
+                limit: 1000,
+            }]
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sample_reads_proc() {
+        let usage = ResourceUsage::sample();
+        assert!(usage.open_files.is_some_and(|n| n > 0));
+        assert!(usage.memory_bytes.is_some_and(|n| n > 0));
+    }
+
+    #[test]
+    fn test_warnings_are_rate_limited() {
+        let monitor = ResourceMonitor::new(&create_test_policy(300));
+        assert_eq!(monitor.check(&usage(850)).len(), 1);
+        assert!(monitor.check(&usage(900)).is_empty());
+        assert!(monitor.check(&usage(100)).is_empty());
+        assert!(monitor.check(&usage(950)).is_empty());
+    }
+}